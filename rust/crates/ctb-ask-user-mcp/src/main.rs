@@ -3,21 +3,31 @@
 //! This mirrors `ask_user_mcp/server.ts`:
 //! - JSON-RPC over stdio (newline-delimited)
 //! - Exposes a single tool: `ask_user`
-//! - Writes request files to `/tmp/ask-user-<id>.json` for the Telegram bot to pick up
+//! - Writes request files to `$ASK_USER_DIR/ask-user-<id>.json` (default `/tmp`) for the
+//!   Telegram bot to pick up; the bot sets `ASK_USER_DIR` to its `TEMP_DIR`.
+//! - With `ASK_USER_SYNC=1` the call stays open until the bot records the answer in that file
+//!   (or `ASK_USER_SYNC_TIMEOUT_SECS` pass) and the choice is returned as the tool result.
+//!   Once answered or expired, the file moves to `processed/` next to it.
 
 use std::{
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
 
 use anyhow::Context;
+use ctb_core::ask_user::{archive_request, expire_request, read_answer, SyncAnswer};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::io::{AsyncBufReadExt, BufReader};
 
 static COUNTER: AtomicUsize = AtomicUsize::new(1);
 
+/// How often a sync call re-reads its request file.
+const ANSWER_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const DEFAULT_SYNC_TIMEOUT: Duration = Duration::from_secs(600);
+
 #[derive(Debug, Deserialize)]
 struct RpcRequest {
     #[allow(dead_code)]
@@ -75,16 +85,51 @@ struct AskUserFile {
     options: Vec<String>,
     status: String,
     chat_id: String,
+    /// Forum topic the question belongs to (absent outside topics).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_id: Option<String>,
     created_at: String,
+    /// This server waits for the answer in the file (sync mode).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    sync: bool,
+}
+
+fn ask_user_dir() -> PathBuf {
+    std::env::var("ASK_USER_DIR")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+}
+
+/// How long to wait for the answer when `ASK_USER_SYNC` is on; `None` in the default mode.
+fn sync_timeout() -> Option<Duration> {
+    let on = std::env::var("ASK_USER_SYNC").is_ok_and(|v| {
+        matches!(
+            v.trim().to_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        )
+    });
+    on.then(|| {
+        std::env::var("ASK_USER_SYNC_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|n| *n > 0)
+            .map_or(DEFAULT_SYNC_TIMEOUT, Duration::from_secs)
+    })
 }
 
 fn write_request_file(
+    dir: &Path,
     chat_id: &str,
+    thread_id: Option<&str>,
     question: &str,
     options: Vec<String>,
+    sync: bool,
 ) -> anyhow::Result<String> {
     let request_id = next_request_id();
-    let path = PathBuf::from(format!("/tmp/ask-user-{request_id}.json"));
+    std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    let path = dir.join(format!("ask-user-{request_id}.json"));
 
     let data = AskUserFile {
         request_id: request_id.clone(),
@@ -92,7 +137,9 @@ fn write_request_file(
         options,
         status: "pending".to_string(),
         chat_id: chat_id.to_string(),
+        thread_id: thread_id.map(|t| t.to_string()),
         created_at: ctb_core::utils::iso_timestamp_utc(),
+        sync,
     };
 
     let txt = serde_json::to_string_pretty(&data)?;
@@ -100,8 +147,45 @@ fn write_request_file(
     Ok(request_id)
 }
 
-fn handle_rpc(req: RpcRequest) -> Option<RpcResponse<'static>> {
+/// Poll the request at `path` until it is answered; on timeout it is marked expired so a late
+/// tap is rejected instead of being lost.
+async fn wait_for_answer(path: &Path, timeout: Duration, poll: Duration) -> Option<String> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match read_answer(path) {
+            SyncAnswer::Answered(answer) => return Some(answer),
+            SyncAnswer::Gone => return None,
+            SyncAnswer::Pending => {}
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return expire_request(path).unwrap_or_else(|e| {
+                eprintln!("failed to expire {}: {e}", path.display());
+                None
+            });
+        }
+        tokio::time::sleep(poll).await;
+    }
+}
+
+fn tool_text(text: &str) -> serde_json::Value {
+    json!({ "content": [{ "type": "text", "text": text }] })
+}
+
+fn tool_description(sync: Option<Duration>) -> String {
+    match sync {
+        Some(timeout) => format!(
+            "Present options to the user as tappable inline buttons in Telegram and wait for \
+             their choice, which is returned as the tool result. If nobody answers within {} \
+             seconds the result says so; do not assume a choice in that case.",
+            timeout.as_secs()
+        ),
+        None => "Present options to the user as tappable inline buttons in Telegram. IMPORTANT: After calling this tool, STOP and wait. Do NOT add any text after calling this tool - the user will tap a button and their choice becomes their next message. Just call the tool and end your turn.".to_string(),
+    }
+}
+
+async fn handle_rpc(req: RpcRequest) -> Option<RpcResponse<'static>> {
     let id = req.id?;
+    let sync = sync_timeout();
 
     match req.method.as_str() {
         "initialize" => {
@@ -128,7 +212,7 @@ fn handle_rpc(req: RpcRequest) -> Option<RpcResponse<'static>> {
               "tools": [
                 {
                   "name": "ask_user",
-                  "description": "Present options to the user as tappable inline buttons in Telegram. IMPORTANT: After calling this tool, STOP and wait. Do NOT add any text after calling this tool - the user will tap a button and their choice becomes their next message. Just call the tool and end your turn.",
+                  "description": tool_description(sync),
                   "inputSchema": {
                     "type": "object",
                     "properties": {
@@ -194,24 +278,51 @@ fn handle_rpc(req: RpcRequest) -> Option<RpcResponse<'static>> {
                 ));
             }
 
-            match write_request_file(&chat_id, &question, options) {
-                Ok(_request_id) => Some(respond_ok(
-                    id,
-                    json!({
-                      "content": [
-                        {
-                          "type": "text",
-                          "text": "[Buttons sent to user. STOP HERE - do not output any more text. Wait for user to tap a button.]"
-                        }
-                      ]
-                    }),
-                )),
-                Err(e) => Some(respond_err(
+            let thread_id = std::env::var("TELEGRAM_THREAD_ID")
+                .ok()
+                .filter(|t| !t.trim().is_empty());
+            let dir = ask_user_dir();
+            let request_id = match write_request_file(
+                &dir,
+                &chat_id,
+                thread_id.as_deref(),
+                &question,
+                options,
+                sync.is_some(),
+            ) {
+                Ok(request_id) => request_id,
+                Err(e) => {
+                    return Some(respond_err(
+                        id,
+                        -32000,
+                        &format!("failed to write request file: {e}"),
+                    ))
+                }
+            };
+            let Some(timeout) = sync else {
+                return Some(respond_ok(
                     id,
-                    -32000,
-                    &format!("failed to write request file: {e}"),
-                )),
+                    tool_text("[Buttons sent to user. STOP HERE - do not output any more text. Wait for user to tap a button.]"),
+                ));
+            };
+
+            let path = dir.join(format!("ask-user-{request_id}.json"));
+            let answer = wait_for_answer(&path, timeout, ANSWER_POLL_INTERVAL).await;
+            // Answered or expired: either way the bot is done with it. Late taps still find the
+            // file in `processed/`.
+            if path.exists() {
+                if let Err(e) = archive_request(&path) {
+                    eprintln!("failed to archive {}: {e}", path.display());
+                }
             }
+            let text = match answer {
+                Some(answer) => format!("The user chose: {answer}"),
+                None => format!(
+                    "[No answer: the user did not choose an option within {} seconds. Do not assume a choice.]",
+                    timeout.as_secs()
+                ),
+            };
+            Some(respond_ok(id, tool_text(&text)))
         }
 
         _ => Some(respond_err(id, -32601, "Method not found")),
@@ -238,7 +349,7 @@ async fn main() -> anyhow::Result<()> {
         };
 
         // Notifications have no id => no response.
-        let Some(resp) = handle_rpc(req) else {
+        let Some(resp) = handle_rpc(req).await else {
             continue;
         };
 
@@ -254,6 +365,7 @@ async fn main() -> anyhow::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ctb_core::domain::ChatId;
 
     #[test]
     fn request_id_is_8_chars() {
@@ -261,15 +373,15 @@ mod tests {
         assert_eq!(id.len(), 8);
     }
 
-    #[test]
-    fn tools_list_contains_ask_user() {
+    #[tokio::test]
+    async fn tools_list_contains_ask_user() {
         let req = RpcRequest {
             jsonrpc: Some("2.0".to_string()),
             id: Some(json!(1)),
             method: "tools/list".to_string(),
             params: None,
         };
-        let resp = handle_rpc(req).unwrap();
+        let resp = handle_rpc(req).await.unwrap();
         let tools = resp
             .result
             .unwrap()
@@ -285,8 +397,17 @@ mod tests {
 
     #[test]
     fn writes_ask_user_file_schema() {
-        let id = write_request_file("123", "Q?", vec!["a".to_string(), "b".to_string()]).unwrap();
-        let path = format!("/tmp/ask-user-{id}.json");
+        let dir = PathBuf::from(format!("/tmp/ctb-ask-user-{}", std::process::id()));
+        let id = write_request_file(
+            &dir,
+            "123",
+            None,
+            "Q?",
+            vec!["a".to_string(), "b".to_string()],
+            false,
+        )
+        .unwrap();
+        let path = dir.join(format!("ask-user-{id}.json"));
         let txt = std::fs::read_to_string(&path).unwrap();
         let v: serde_json::Value = serde_json::from_str(&txt).unwrap();
         assert_eq!(
@@ -296,7 +417,64 @@ mod tests {
         assert_eq!(v.get("question").and_then(|x| x.as_str()), Some("Q?"));
         assert_eq!(v.get("status").and_then(|x| x.as_str()), Some("pending"));
         assert_eq!(v.get("chat_id").and_then(|x| x.as_str()), Some("123"));
+        assert!(v.get("thread_id").is_none());
         assert!(v.get("options").and_then(|x| x.as_array()).unwrap().len() == 2);
         let _ = std::fs::remove_file(&path);
+
+        let id = write_request_file(
+            &dir,
+            "123",
+            Some("42"),
+            "Q?",
+            vec!["a".to_string(), "b".to_string()],
+            false,
+        )
+        .unwrap();
+        let path = dir.join(format!("ask-user-{id}.json"));
+        let v: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(v.get("thread_id").and_then(|x| x.as_str()), Some("42"));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_dir(&dir);
+    }
+
+    #[tokio::test]
+    async fn sync_call_waits_for_the_recorded_answer() {
+        let dir = PathBuf::from(format!("/tmp/ctb-ask-user-sync-{}", std::process::id()));
+        let id = write_request_file(
+            &dir,
+            "7",
+            None,
+            "Deploy?",
+            vec!["yes".to_string(), "no".to_string()],
+            true,
+        )
+        .unwrap();
+        let path = dir.join(format!("ask-user-{id}.json"));
+        let v: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(v.get("sync").and_then(|x| x.as_bool()), Some(true));
+
+        let tap = path.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            ctb_core::ask_user::record_answer(&tap, ChatId(7).into(), 1, 30, "t").unwrap();
+        });
+        let answer =
+            wait_for_answer(&path, Duration::from_secs(5), Duration::from_millis(10)).await;
+        assert_eq!(answer.as_deref(), Some("no"));
+
+        let id =
+            write_request_file(&dir, "7", None, "Q?", vec!["a".into(), "b".into()], true).unwrap();
+        let path = dir.join(format!("ask-user-{id}.json"));
+        let answer =
+            wait_for_answer(&path, Duration::from_millis(30), Duration::from_millis(10)).await;
+        assert_eq!(answer, None);
+        assert_eq!(
+            read_answer(&path),
+            SyncAnswer::Gone,
+            "expired after the timeout"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,18 @@
+//! `ctb_core::mcp_config::probe_stdio_server` against the real ask-user server binary.
+
+use std::collections::HashMap;
+
+use ctb_core::mcp_config::{probe_stdio_server, MCP_PROBE_TIMEOUT};
+
+#[tokio::test]
+async fn probe_lists_ask_user_tool() {
+    let tools = probe_stdio_server(
+        env!("CARGO_BIN_EXE_ctb-ask-user-mcp"),
+        &[],
+        &HashMap::from([("TELEGRAM_CHAT_ID".to_string(), "1".to_string())]),
+        MCP_PROBE_TIMEOUT,
+    )
+    .await
+    .unwrap();
+    assert_eq!(tools, vec!["ask_user".to_string()]);
+}
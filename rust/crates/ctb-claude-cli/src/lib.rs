@@ -5,36 +5,117 @@
 use async_trait::async_trait;
 
 use std::process::Stdio;
+use std::time::{Duration, Instant};
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, OnceLock};
 
 use ctb_core::{
-    errors::Error,
+    errors::{CancelReason, Error},
+    log_line,
+    metrics::{Counter, Histogram, MetricsSink},
     model::{
-        client::{ClaudeCliPromptAdapter, ModelClient},
+        client::{ClaudeCliPromptAdapter, EventSink, ModelClient},
         types::{
-            ClaudeCliConfig, ModelCapabilities, ModelEvent, ProviderKind, RunRequest, RunResult,
-            SessionRef, TokenUsage,
+            ClaudeCliConfig, MergedResult, ModelCapabilities, ModelEvent, ProviderKind, RunId,
+            RunRequest, RunResult, SessionRef,
         },
     },
+    processes::{ProcessTracker, TrackedProcess},
     Result,
 };
 
+#[cfg(unix)]
+use ctb_core::processes::{is_alive, signal_group};
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::Command,
-    sync::Mutex,
+    sync::{mpsc, Mutex},
 };
 use tokio_util::sync::CancellationToken;
 
 const STDERR_TAIL_MAX_BYTES: usize = 16 * 1024;
 const STDERR_TAIL_MAX_LINES: usize = 200;
-
+/// Longest stderr notice forwarded to the chat.
+const STDERR_NOTICE_MAX_CHARS: usize = 300;
+/// How long a finished run waits for stderr to be read to its end.
+const STDERR_DRAIN_GRACE: Duration = Duration::from_millis(200);
+/// Stop draining stderr once it has been quiet this long after the process exited.
+const STDERR_DRAIN_IDLE: Duration = Duration::from_millis(20);
+
+/// Claude CLI model client.
+///
+/// Every `run()` spawns and owns its own `claude` child; in-flight runs are tracked by `RunId`
+/// so cancelling one chat's query never touches another chat's process.
 #[derive(Clone, Debug)]
 pub struct ClaudeCliClient {
     cfg: ClaudeCliConfig,
-    child: std::sync::Arc<Mutex<Option<tokio::process::Child>>>,
-    cancel: std::sync::Arc<Mutex<Option<CancellationToken>>>,
+    runs: Arc<StdMutex<HashMap<RunId, RunHandle>>>,
+    processes: Option<Arc<ProcessTracker>>,
+}
+
+/// Cancellation handle of one in-flight run; the first reason given wins.
+#[derive(Clone, Debug, Default)]
+struct RunHandle {
+    token: CancellationToken,
+    reason: Arc<OnceLock<CancelReason>>,
+}
+
+impl RunHandle {
+    fn cancel(&self, reason: CancelReason) {
+        let _ = self.reason.set(reason);
+        self.token.cancel();
+    }
+
+    fn cancelled_error(&self) -> Error {
+        Error::Cancelled(self.reason.get().copied().unwrap_or(CancelReason::UserStop))
+    }
+}
+
+/// Removes a run's cancellation handle once `run()` returns (on every exit path).
+struct RunRegistration {
+    runs: Arc<StdMutex<HashMap<RunId, RunHandle>>>,
+    run_id: RunId,
+}
+
+impl Drop for RunRegistration {
+    fn drop(&mut self) {
+        if let Ok(mut runs) = self.runs.lock() {
+            runs.remove(&self.run_id);
+        }
+    }
+}
+
+/// Drops a child's entry from the process tracker once `run()` returns (on every exit path;
+/// `kill_on_drop` has stopped the child by then).
+struct TrackedChild {
+    processes: Arc<ProcessTracker>,
+    pid: u32,
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        if let Err(e) = self.processes.untrack(self.pid) {
+            log_line!("[CLI] Failed to untrack claude process {}: {e}", self.pid);
+        }
+    }
+}
+
+/// Records the CLI's wall time and parsed stdout volume once `run()` returns (on every exit path).
+struct RunMeter {
+    metrics: Option<Arc<dyn MetricsSink>>,
+    started: Instant,
+    stdout_bytes: u64,
+}
+
+impl Drop for RunMeter {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.observe(Histogram::CliWallTime, self.started.elapsed());
+            metrics.incr(Counter::StdoutBytes, self.stdout_bytes);
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -67,15 +148,47 @@ impl ClaudeCliClient {
     pub fn new(cfg: ClaudeCliConfig) -> Self {
         Self {
             cfg,
-            child: std::sync::Arc::new(Mutex::new(None)),
-            cancel: std::sync::Arc::new(Mutex::new(None)),
+            runs: Arc::new(StdMutex::new(HashMap::new())),
+            processes: None,
         }
     }
-}
 
-async fn clear_cancel_token(cancel: &std::sync::Arc<Mutex<Option<CancellationToken>>>) {
-    let mut guard = cancel.lock().await;
-    *guard = None;
+    /// Record spawned children in `processes`, so a restart can stop the ones a crash left.
+    pub fn with_process_tracker(mut self, processes: Arc<ProcessTracker>) -> Self {
+        self.processes = Some(processes);
+        self
+    }
+
+    fn track_child(&self, pid: Option<u32>, req: &RunRequest) -> Option<TrackedChild> {
+        let (processes, pid) = (self.processes.clone()?, pid?);
+        let entry = TrackedProcess::spawned(
+            pid,
+            &self.cfg.claude_path,
+            req.run_id.0,
+            req.resume.as_ref().map(|s| s.id.clone()),
+            &req.cwd,
+        );
+        if let Err(e) = processes.track(entry) {
+            log_line!("[CLI] Failed to track claude process {pid}: {e}");
+        }
+        Some(TrackedChild { processes, pid })
+    }
+
+    fn register_run(&self, run_id: RunId) -> (RunHandle, RunRegistration) {
+        let handle = RunHandle::default();
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        // A reused id would orphan the previous run's token; cancel it rather than leak it.
+        if let Some(previous) = runs.insert(run_id, handle.clone()) {
+            previous.cancel(CancelReason::Interrupt);
+        }
+        (
+            handle,
+            RunRegistration {
+                runs: self.runs.clone(),
+                run_id,
+            },
+        )
+    }
 }
 
 #[async_trait]
@@ -94,20 +207,9 @@ impl ModelClient for ClaudeCliClient {
         }
     }
 
-    async fn run(
-        &self,
-        req: RunRequest,
-        on_event: &mut (dyn FnMut(ModelEvent) -> Result<()> + Send),
-    ) -> Result<RunResult> {
-        // Cancel any existing run first. If we can't kill/reap it, fail fast rather than
-        // spawning a second long-running CLI process.
-        self.cancel().await?;
-
-        let token = CancellationToken::new();
-        {
-            let mut guard = self.cancel.lock().await;
-            *guard = Some(token.clone());
-        }
+    async fn run(&self, req: RunRequest, on_event: &mut dyn EventSink) -> Result<RunResult> {
+        let (handle, _registration) = self.register_run(req.run_id);
+        let token = handle.token.clone();
 
         let adapter = ClaudeCliPromptAdapter {
             cfg: self.cfg.clone(),
@@ -119,57 +221,79 @@ impl ModelClient for ClaudeCliClient {
             .current_dir(&inv.cwd)
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            .stderr(Stdio::piped())
+            // Never leave an orphaned CLI behind if this future is dropped mid-run.
+            .kill_on_drop(true);
+        // Own process group, so cancellation also reaches the CLI's MCP stdio servers.
+        #[cfg(unix)]
+        cmd.process_group(0);
         for (k, v) in &inv.env {
             cmd.env(k, v);
         }
 
         let mut child = cmd.spawn()?;
+        let _tracked = self.track_child(child.id(), &req);
+        let mut meter = RunMeter {
+            metrics: req.metrics.clone(),
+            started: Instant::now(),
+            stdout_bytes: 0,
+        };
+        let grace = self.cfg.kill_grace;
 
         let stdout = child
             .stdout
             .take()
             .ok_or_else(|| Error::External("claude stdout was not captured".to_string()))?;
         let stderr = child.stderr.take();
-        let stderr_tail: std::sync::Arc<Mutex<StderrTail>> =
-            std::sync::Arc::new(Mutex::new(StderrTail::default()));
+        let stderr_tail: Arc<Mutex<StderrTail>> = Arc::new(Mutex::new(StderrTail::default()));
 
-        // Store child so `cancel()` can kill it.
-        {
-            let mut guard = self.child.lock().await;
-            *guard = Some(child);
-        }
-
-        // Drain stderr in background to avoid blocking on a full pipe.
-        if let Some(stderr) = stderr {
+        // Drain stderr in background to avoid blocking on a full pipe. Warning-class lines are
+        // also handed to the run loop, once each, to become `Notice` events.
+        let (notice_tx, mut notices) = mpsc::unbounded_channel::<String>();
+        let stderr_lines = Arc::new(AtomicUsize::new(0));
+        let stderr_task = stderr.map(|stderr| {
             let tail = stderr_tail.clone();
+            let lines = stderr_lines.clone();
+            let patterns = self.cfg.stderr_notices.clone();
             tokio::spawn(async move {
+                let mut seen = HashSet::new();
                 let mut r = BufReader::new(stderr).lines();
                 while let Ok(Some(line)) = r.next_line().await {
+                    let notice = line.trim();
+                    if patterns.matches(notice) && seen.insert(notice.to_string()) {
+                        let _ = notice_tx.send(truncate_text(notice, STDERR_NOTICE_MAX_CHARS));
+                    }
                     tail.lock().await.push_line(line);
+                    lines.fetch_add(1, Ordering::Relaxed);
                 }
-            });
-        }
+            })
+        });
 
         let mut session: Option<SessionRef> = None;
-        let mut final_text: Option<String> = None;
-        let mut final_is_error: Option<bool> = None;
-        let mut final_usage: Option<TokenUsage> = None;
+        let mut results = MergedResult::default();
 
         let mut reader = BufReader::new(stdout).lines();
         loop {
             tokio::select! {
               _ = token.cancelled() => {
-                if let Err(e) = self.kill_child().await {
-                  return Err(Error::External(format!("Cancelled (failed to kill claude process: {e})")));
+                if let Err(e) = kill_child(&mut child, grace).await {
+                  log_line!("[CLI] Failed to kill cancelled claude process: {e}");
+                }
+                return Err(handle.cancelled_error());
+              }
+              Some(text) = notices.recv() => {
+                if let Err(e) = on_event.emit(stderr_notice(&text)).await {
+                  if let Err(kill_e) = kill_child(&mut child, grace).await {
+                    return Err(Error::External(format!("{e} (also failed to kill claude process: {kill_e})")));
+                  }
+                  return Err(e);
                 }
-                return Err(Error::External("Cancelled".to_string()));
               }
               line = reader.next_line() => {
                 let line = match line {
                   Ok(v) => v,
                   Err(e) => {
-                    let kill = self.kill_child().await;
+                    let kill = kill_child(&mut child, grace).await;
                     if let Err(kill_e) = kill {
                       return Err(Error::External(format!("claude stdout read failed: {e} (also failed to kill claude process: {kill_e})")));
                     }
@@ -177,13 +301,14 @@ impl ModelClient for ClaudeCliClient {
                   }
                 };
                 let Some(line) = line else { break; };
+                meter.stdout_bytes += line.len() as u64 + 1;
 
                 let value: serde_json::Value = match serde_json::from_str(&line) {
                   Ok(v) => v,
                   Err(e) => {
                     let stderr = stderr_tail.lock().await.snapshot();
                     let line_preview = truncate_text(&line, 500);
-                    let kill = self.kill_child().await;
+                    let kill = kill_child(&mut child, grace).await;
                     let mut msg = format!(
                       "claude stream-json parse failed: {e}\nstdout line: {line_preview}"
                     );
@@ -205,22 +330,14 @@ impl ModelClient for ClaudeCliClient {
                   }
                 }
 
-                // Track final result fields.
+                // Merge every result line (see `MergedResult` for the semantics).
                 if value.get("type").and_then(|v| v.as_str()) == Some("result") {
-                  if let Some(text) = value.get("result").and_then(|v| v.as_str()) {
-                    final_text = Some(text.to_string());
-                  }
-                  if let Some(is_error) = value.get("is_error").and_then(|v| v.as_bool()) {
-                    final_is_error = Some(is_error);
-                  }
-                  if let Some(usage) = value.get("usage") {
-                    final_usage = parse_usage(usage);
-                  }
+                  results.push(&value);
                 }
 
-                let ev = classify_event(value);
-                if let Err(e) = on_event(ev) {
-                  if let Err(kill_e) = self.kill_child().await {
+                let ev = ModelEvent::from_stream_json(value);
+                if let Err(e) = on_event.emit(ev).await {
+                  if let Err(kill_e) = kill_child(&mut child, grace).await {
                     return Err(Error::External(format!("{e} (also failed to kill claude process: {kill_e})")));
                   }
                   return Err(e);
@@ -229,110 +346,120 @@ impl ModelClient for ClaudeCliClient {
             }
         }
 
-        // Wait for the process to exit.
-        let status = {
-            let mut guard = self.child.lock().await;
-            if let Some(mut child) = guard.take() {
-                child.wait().await?
-            } else {
-                // Process already removed (cancelled).
-                // Avoid returning a confusing error if the caller requested cancellation.
-                if token.is_cancelled() {
-                    return Err(Error::External("Cancelled".to_string()));
-                }
-                return Err(Error::External("claude process missing".to_string()));
+        // Wait for the process to exit (or for a cancellation that lands after stdout closed).
+        let status = tokio::select! {
+            status = child.wait() => status?,
+            _ = token.cancelled() => {
+                kill_child(&mut child, grace).await?;
+                return Err(handle.cancelled_error());
             }
         };
 
-        // Clear cancellation token.
-        clear_cancel_token(&self.cancel).await;
+        // Warnings printed just before exit; MCP servers may keep stderr open, so don't wait long.
+        if let Some(task) = stderr_task {
+            drain_stderr(task, &stderr_lines).await;
+        }
+        while let Ok(text) = notices.try_recv() {
+            on_event.emit(stderr_notice(&text)).await?;
+        }
 
-        if !status.success() && final_text.is_none() {
+        if !status.success() && results.text().is_none() {
             let stderr = stderr_tail.lock().await.snapshot();
             if !stderr.trim().is_empty() {
-                return Err(Error::External(format!(
+                return Err(Error::ModelCrashed(format!(
                     "claude exited with status {status}\nstderr (tail):\n{stderr}"
                 )));
             }
-            return Err(Error::External(format!(
+            return Err(Error::ModelCrashed(format!(
                 "claude exited with status {status}"
             )));
         }
 
         Ok(RunResult {
             session,
-            is_error: final_is_error.unwrap_or(!status.success()),
-            text: final_text.unwrap_or_default(),
-            usage: final_usage,
+            is_error: results.is_error().unwrap_or(!status.success()),
+            text: results.text().unwrap_or_default().to_string(),
+            usage: results.usage().cloned(),
         })
     }
 
-    async fn cancel(&self) -> Result<()> {
-        // Signal cancellation first (for cooperative shutdown paths).
-        if let Some(token) = self.cancel.lock().await.as_ref() {
-            token.cancel();
+    async fn cancel(&self, run_id: RunId, reason: CancelReason) -> Result<()> {
+        // The owning `run()` observes the token, kills and reaps its child, and returns.
+        if let Some(handle) = self
+            .runs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&run_id)
+        {
+            handle.cancel(reason);
         }
-        self.kill_child().await?;
         Ok(())
     }
-}
-
-impl ClaudeCliClient {
-    async fn kill_child(&self) -> Result<()> {
-        clear_cancel_token(&self.cancel).await;
 
-        let child = {
-            let mut guard = self.child.lock().await;
-            guard.take()
-        };
-
-        let Some(mut child) = child else {
-            return Ok(());
-        };
-
-        // If it's already exited, `try_wait` reaps it.
-        if child.try_wait()?.is_some() {
-            return Ok(());
+    async fn cancel_all(&self) -> Result<()> {
+        for handle in self.runs.lock().unwrap_or_else(|e| e.into_inner()).values() {
+            handle.cancel(CancelReason::Shutdown);
         }
+        Ok(())
+    }
+}
 
-        // Best-effort kill + reap. If kill fails and the process is still alive, keep
-        // the handle so callers can retry instead of losing track of the child.
-        match child.kill().await {
-            Ok(()) => {
-                let _ = child.wait().await?;
-            }
-            Err(e) => {
-                // If it exited between `try_wait` and `kill`, `wait` will reap it.
-                if child.try_wait()?.is_none() {
-                    let mut guard = self.child.lock().await;
-                    *guard = Some(child);
-                    return Err(Error::Io(e));
-                }
-            }
+/// Stop + reap a run's child. A child that already exited is just reaped.
+///
+/// On Unix the child's process group gets SIGINT first so the CLI can flush and shut its MCP
+/// servers down; whatever is still running after `grace` is SIGKILLed. The child is only reaped
+/// after that, so its pgid cannot have been handed to another group yet.
+async fn kill_child(child: &mut tokio::process::Child, grace: Duration) -> Result<()> {
+    if child.try_wait()?.is_some() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        signal_group(pid, libc::SIGINT);
+        let deadline = tokio::time::Instant::now() + grace;
+        while is_alive(pid) && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let exited = !is_alive(pid);
+        // Also sweeps group members that outlived a gracefully exiting CLI.
+        signal_group(pid, libc::SIGKILL);
+        if exited {
+            child.wait().await?;
+            return Ok(());
         }
-
-        Ok(())
+    }
+    match child.kill().await {
+        Ok(()) => Ok(()),
+        // If it exited between `try_wait` and `kill`, `try_wait` reaps it.
+        Err(e) if child.try_wait()?.is_none() => Err(Error::Io(e)),
+        Err(_) => Ok(()),
     }
 }
 
-fn classify_event(raw: serde_json::Value) -> ModelEvent {
-    match raw.get("type").and_then(|v| v.as_str()) {
-        Some("system") => ModelEvent::SystemInit { raw },
-        Some("assistant") => ModelEvent::Assistant { raw },
-        Some("result") => ModelEvent::Result { raw },
-        Some("tool_progress") | Some("tool_use_summary") => ModelEvent::Tool { raw },
-        _ => ModelEvent::Unknown { raw },
+/// Waits for the stderr reader after the process exited: until the pipe closes, or until it has
+/// been quiet for [`STDERR_DRAIN_IDLE`] when something else still holds it open, at most
+/// [`STDERR_DRAIN_GRACE`].
+async fn drain_stderr(mut task: tokio::task::JoinHandle<()>, lines: &AtomicUsize) {
+    let deadline = Instant::now() + STDERR_DRAIN_GRACE;
+    loop {
+        let seen = lines.load(Ordering::Relaxed);
+        if tokio::time::timeout(STDERR_DRAIN_IDLE, &mut task)
+            .await
+            .is_ok()
+        {
+            return;
+        }
+        if lines.load(Ordering::Relaxed) == seen || Instant::now() >= deadline {
+            return;
+        }
     }
 }
 
-fn parse_usage(v: &serde_json::Value) -> Option<TokenUsage> {
-    let get = |k: &str| v.get(k).and_then(|x| x.as_u64()).unwrap_or(0);
-    Some(TokenUsage {
-        input_tokens: get("input_tokens"),
-        output_tokens: get("output_tokens"),
-        cache_read_input_tokens: get("cache_read_input_tokens"),
-        cache_creation_input_tokens: get("cache_creation_input_tokens"),
-    })
+/// A warning-class stderr line, shown as an informational status.
+fn stderr_notice(line: &str) -> ModelEvent {
+    ModelEvent::Notice {
+        text: format!("ℹ️ {line}"),
+    }
 }
 
 fn truncate_text(s: &str, max_len: usize) -> String {
@@ -343,3 +470,373 @@ fn truncate_text(s: &str, max_len: usize) -> String {
     out.push_str("...");
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ctb_core::model::types::{NoticePatterns, PermissionMode};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn tmp_path(name: &str, ext: &str) -> PathBuf {
+        PathBuf::from(format!(
+            "/tmp/ctb-fake-claude-{name}-{}.{ext}",
+            std::process::id()
+        ))
+    }
+
+    fn write_script(name: &str, script: &str) -> PathBuf {
+        let path = tmp_path(name, "sh");
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// Fake `claude` that echoes its prompt (the last argument) after a delay.
+    /// The prompt `slow` sleeps long enough to be cancelled mid-run.
+    fn fake_claude(name: &str) -> PathBuf {
+        write_script(
+            name,
+            r#"#!/bin/sh
+for prompt; do :; done
+if [ "$prompt" = "slow" ]; then sleep 5; else sleep 1; fi
+echo "{\"type\":\"system\",\"subtype\":\"init\",\"session_id\":\"s-$prompt\"}"
+echo "{\"type\":\"result\",\"result\":\"echo $prompt\",\"is_error\":false,\"session_id\":\"s-$prompt\"}"
+"#,
+        )
+    }
+
+    /// Fake `claude` with a background child standing in for an MCP server. `on_int` is the
+    /// shell's SIGINT trap action; the child's pid is written to `<name>.pid`.
+    fn trapping_claude(name: &str, on_int: &str) -> PathBuf {
+        let pid_file = tmp_path(name, "pid");
+        write_script(
+            name,
+            &format!(
+                r#"#!/bin/sh
+trap '{on_int}' INT
+sleep 30 &
+echo $! > {pid_file}
+echo '{{"type":"system","subtype":"init","session_id":"s-trap"}}'
+wait
+"#,
+                pid_file = pid_file.display()
+            ),
+        )
+    }
+
+    /// Alive and not a zombie (orphans may linger unreaped in containers).
+    fn is_alive(pid: &str) -> bool {
+        std::fs::read_to_string(format!("/proc/{pid}/stat"))
+            .ok()
+            .and_then(|stat| {
+                stat.rsplit_once(") ")
+                    .map(|(_, rest)| !rest.starts_with('Z'))
+            })
+            .unwrap_or(false)
+    }
+
+    async fn wait_until_dead(pid: &str) -> bool {
+        for _ in 0..50 {
+            if !is_alive(pid) {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    fn client(claude_path: PathBuf) -> ClaudeCliClient {
+        client_with_grace(claude_path, Duration::from_secs(3))
+    }
+
+    fn client_with_grace(claude_path: PathBuf, kill_grace: Duration) -> ClaudeCliClient {
+        ClaudeCliClient::new(ClaudeCliConfig {
+            claude_path,
+            model: None,
+            permission_mode: PermissionMode::Default,
+            dangerously_skip_permissions: false,
+            include_partial_messages: false,
+            kill_grace,
+            allowed_tools: None,
+            disallowed_tools: None,
+            stderr_notices: Default::default(),
+        })
+    }
+
+    /// Start a run, cancel it once the fake CLI is up, and time the cancellation.
+    async fn cancel_after_start(client: &ClaudeCliClient) -> (Result<RunResult>, Duration) {
+        let run_id = RunId::next();
+        let canceller = client.clone();
+        let mut on_event = |_| Ok(());
+        let (result, cancelled_at) = tokio::join!(
+            client.run(request(run_id, "hi"), &mut on_event),
+            async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                let started = Instant::now();
+                canceller
+                    .cancel(run_id, CancelReason::UserStop)
+                    .await
+                    .unwrap();
+                started
+            },
+        );
+        (result, cancelled_at.elapsed())
+    }
+
+    fn request(run_id: RunId, prompt: &str) -> RunRequest {
+        RunRequest {
+            run_id,
+            prompt: prompt.to_string(),
+            cwd: PathBuf::from("/tmp"),
+            add_dirs: Vec::new(),
+            mcp_config_path: None,
+            system_prompt: None,
+            append_system_prompt: None,
+            bash_wrapper: None,
+            allowed_tools: None,
+            disallowed_tools: None,
+            resume: None,
+            fork_session: false,
+            max_thinking_tokens: None,
+            model: None,
+            permission_mode: None,
+            max_turns: None,
+            max_budget_usd: None,
+            metrics: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn stderr_warnings_become_one_notice_each() {
+        let script = write_script(
+            "notices",
+            r#"#!/bin/sh
+echo "Warning: the --foo flag is deprecated" >&2
+echo "loading config" >&2
+echo "Warning: the --foo flag is deprecated" >&2
+echo '{"type":"result","result":"done","is_error":false,"session_id":"s-n"}'
+"#,
+        );
+        let client = ClaudeCliClient::new(ClaudeCliConfig {
+            stderr_notices: NoticePatterns::defaults(),
+            ..client(script.clone()).cfg
+        });
+
+        let mut notices = Vec::new();
+        let mut on_event = |ev: ModelEvent| {
+            if let ModelEvent::Notice { text } = ev {
+                notices.push(text);
+            }
+            Ok(())
+        };
+        let result = client
+            .run(request(RunId::next(), "hi"), &mut on_event)
+            .await;
+        let _ = std::fs::remove_file(&script);
+
+        assert_eq!(result.unwrap().text, "done");
+        assert_eq!(notices, ["ℹ️ Warning: the --foo flag is deprecated"]);
+    }
+
+    #[tokio::test]
+    async fn parallel_runs_do_not_kill_each_other() {
+        let script = fake_claude("parallel");
+        let client = client(script.clone());
+
+        let (mut on_a, mut on_b) = (|_| Ok(()), |_| Ok(()));
+        let (a, b) = tokio::join!(
+            client.run(request(RunId::next(), "a"), &mut on_a),
+            client.run(request(RunId::next(), "b"), &mut on_b),
+        );
+        let _ = std::fs::remove_file(&script);
+
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.text, "echo a");
+        assert_eq!(a.session.unwrap().id, "s-a");
+        assert_eq!(b.text, "echo b");
+        assert_eq!(b.session.unwrap().id, "s-b");
+    }
+
+    #[tokio::test]
+    async fn run_reports_wall_time_and_stdout_bytes() {
+        use ctb_core::metrics::ProcessMetrics;
+
+        let script = fake_claude("metered");
+        let client = client(script.clone());
+        let metrics = ProcessMetrics::new();
+        let mut req = request(RunId::next(), "m");
+        req.metrics = Some(metrics.clone());
+
+        let result = client.run(req, &mut |_| Ok(())).await;
+        let _ = std::fs::remove_file(&script);
+
+        assert_eq!(result.unwrap().text, "echo m");
+        let expected = r#"{"type":"system","subtype":"init","session_id":"s-m"}"#.len()
+            + r#"{"type":"result","result":"echo m","is_error":false,"session_id":"s-m"}"#.len()
+            + 2;
+        assert_eq!(metrics.counter(Counter::StdoutBytes), expected as u64);
+        let wall = metrics.histogram(Histogram::CliWallTime);
+        assert_eq!(wall.count, 1);
+        assert!(wall.max >= Duration::from_secs(1), "{:?}", wall.max);
+    }
+
+    #[tokio::test]
+    async fn spawned_children_are_tracked_until_reaped() {
+        let script = fake_claude("tracked");
+        let processes = Arc::new(ProcessTracker::new(tmp_path("tracked", "json")));
+        let client = client(script.clone()).with_process_tracker(processes.clone());
+
+        let mut req = request(RunId::next(), "t");
+        req.resume = Some(SessionRef {
+            provider: ProviderKind::ClaudeCli,
+            id: "s-prev".to_string(),
+        });
+        let mut seen = Vec::new();
+        let mut on_event = |_| {
+            seen.extend(processes.entries());
+            Ok(())
+        };
+        let result = client.run(req, &mut on_event).await;
+        let _ = std::fs::remove_file(&script);
+
+        assert_eq!(result.unwrap().text, "echo t");
+        let entry = seen.first().expect("child tracked while running");
+        assert_eq!(entry.session_id.as_deref(), Some("s-prev"));
+        assert_eq!(entry.pgid, entry.pid);
+        assert!(processes.entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancel_targets_a_single_run() {
+        let script = fake_claude("cancel");
+        let client = client(script.clone());
+        let (slow_id, fast_id) = (RunId::next(), RunId::next());
+
+        let canceller = client.clone();
+        let (mut on_slow, mut on_fast) = (|_| Ok(()), |_| Ok(()));
+        let (slow, fast, _) = tokio::join!(
+            client.run(request(slow_id, "slow"), &mut on_slow),
+            client.run(request(fast_id, "fast"), &mut on_fast),
+            async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                canceller
+                    .cancel(slow_id, CancelReason::Interrupt)
+                    .await
+                    .unwrap();
+            },
+        );
+        let _ = std::fs::remove_file(&script);
+
+        let err = slow.expect_err("slow run should be cancelled");
+        assert!(
+            matches!(err, Error::Cancelled(CancelReason::Interrupt)),
+            "got: {err}"
+        );
+        assert_eq!(fast.unwrap().text, "echo fast");
+        assert!(client.runs.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancel_interrupts_before_killing() {
+        let marker = tmp_path("graceful", "done");
+        let script = trapping_claude(
+            "graceful",
+            &format!("echo interrupted > {}; exit 130", marker.display()),
+        );
+        let client = client_with_grace(script.clone(), Duration::from_secs(3));
+
+        let (result, elapsed) = cancel_after_start(&client).await;
+        let child = std::fs::read_to_string(tmp_path("graceful", "pid")).unwrap();
+        let interrupted = std::fs::read_to_string(&marker).unwrap_or_default();
+        for ext in ["sh", "pid", "done"] {
+            let _ = std::fs::remove_file(tmp_path("graceful", ext));
+        }
+
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::Cancelled(CancelReason::UserStop)
+        ));
+        assert_eq!(interrupted.trim(), "interrupted", "SIGINT trap should run");
+        assert!(
+            elapsed < Duration::from_secs(2),
+            "exited without waiting out the grace period ({elapsed:?})"
+        );
+        assert!(
+            wait_until_dead(child.trim()).await,
+            "process group member survived"
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_escalates_to_kill_when_interrupt_is_ignored() {
+        let script = trapping_claude("stubborn", "");
+        let grace = Duration::from_millis(300);
+        let client = client_with_grace(script.clone(), grace);
+
+        let (result, elapsed) = cancel_after_start(&client).await;
+        let child = std::fs::read_to_string(tmp_path("stubborn", "pid")).unwrap();
+        for ext in ["sh", "pid"] {
+            let _ = std::fs::remove_file(tmp_path("stubborn", ext));
+        }
+
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::Cancelled(CancelReason::UserStop)
+        ));
+        assert!(
+            elapsed >= grace,
+            "killed before the grace period ({elapsed:?})"
+        );
+        assert!(elapsed < Duration::from_secs(3), "took {elapsed:?}");
+        assert!(
+            wait_until_dead(child.trim()).await,
+            "process group member survived"
+        );
+    }
+
+    #[tokio::test]
+    async fn multiple_results_are_merged() {
+        let fixture = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(
+            "../../../docs/rust-port/fixtures/claude-stream-json.synthetic-multi-result.jsonl",
+        );
+        let script = write_script(
+            "multi-result",
+            &format!("#!/bin/sh\ncat '{}'\n", fixture.display()),
+        );
+        let client = client(script.clone());
+
+        let mut results = 0;
+        let mut on_event = |ev| {
+            if matches!(ev, ModelEvent::Result { .. }) {
+                results += 1;
+            }
+            Ok(())
+        };
+        let result = client
+            .run(request(RunId::next(), "hi"), &mut on_event)
+            .await;
+        let _ = std::fs::remove_file(&script);
+
+        let result = result.unwrap();
+        assert_eq!(results, 2);
+        assert!(!result.is_error);
+        assert_eq!(result.text, "Retried: all good.");
+        let usage = result.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (220, 9));
+    }
+
+    #[test]
+    fn compaction_boundary_is_classified_separately() {
+        let fixture = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../../docs/rust-port/fixtures/claude-stream-json.synthetic-compaction.jsonl");
+        let events = std::fs::read_to_string(fixture)
+            .unwrap()
+            .lines()
+            .map(|l| ModelEvent::from_stream_json(serde_json::from_str(l).unwrap()))
+            .collect::<Vec<_>>();
+        assert!(matches!(events[0], ModelEvent::SystemInit { .. }));
+        assert!(matches!(events[1], ModelEvent::ContextCompacted { .. }));
+        assert!(matches!(events[2], ModelEvent::Assistant { .. }));
+    }
+}
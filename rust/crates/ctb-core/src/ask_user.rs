@@ -0,0 +1,694 @@
+//! `ask_user` request files (`<temp_dir>/ask-user-<id>.json`).
+//!
+//! The ask-user MCP server writes a request as `pending`; the session marks it `sent` once the
+//! inline keyboard is posted; the callback handler records the tap here as `answered`. Answered
+//! files are kept so that a second tap on the same keyboard can be recognised and ignored.
+//!
+//! Options whose button label had to be truncated take two taps: the first shows the full text
+//! (recorded under `previewed`), the second selects it.
+//!
+//! Once an answer has been consumed (resumed by the bot, or read by a waiting sync server) the
+//! file moves to `processed/` (see [`archive_request`]), where double taps and replayed requests
+//! still find it until it ages out. Startup sweeps both directories with [`sweep_request_files`].
+//!
+//! Messengers without inline keyboards get the options as a numbered list instead (the file is
+//! marked with `answer_mode: "text"`), and the user answers by replying with a number.
+//!
+//! In sync mode (`ASK_USER_SYNC`) the server writes `sync: true` and keeps the tool call open,
+//! polling the file until it is `answered` (see [`read_answer`]); the choice becomes the tool
+//! result and the run carries on. A server that gives up marks the file `expired`. A typed answer
+//! to such a request must not wait behind the prompt that asked, so those requests are tracked
+//! in memory ([`awaits_sync_text_answer`]) for the dispatcher to route it separately.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use serde_json::Value;
+
+use crate::{
+    config::{Config, GroupMode},
+    domain::ChatTarget,
+    formatting::escape_html,
+    messaging::types::truncate_label,
+    theme::StatusTheme,
+    Result,
+};
+
+pub const STATUS_ANSWERED: &str = "answered";
+/// A pending request that repeats one still awaiting its answer (see
+/// [`find_unanswered_duplicate`]).
+pub const STATUS_DUPLICATE: &str = "duplicate";
+/// A sync request whose server stopped waiting; later taps are rejected.
+pub const STATUS_EXPIRED: &str = "expired";
+
+/// `answer_mode` of a request shown as a numbered list rather than a keyboard.
+pub const ANSWER_MODE_TEXT: &str = "text";
+
+/// How recently an unanswered request must have been sent to suppress a repeat of it.
+pub const DUPLICATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// How long a suspended turn waits for its answer; after that the answer starts a fresh set of
+/// streamed messages.
+pub const ASK_USER_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Subdirectory of the request directory holding consumed requests.
+pub const PROCESSED_DIR: &str = "processed";
+
+/// Serialises read-modify-write of request files across concurrent callbacks.
+static ANSWER_LOCK: Mutex<()> = Mutex::new(());
+
+/// Sync requests shown as numbered lists, with their chat, until they are no longer `sent`.
+static SYNC_TEXT_REQUESTS: Mutex<Vec<(i64, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Path of the request file for `request_id`, or `None` if the id could escape `dir`.
+pub fn request_file_path(dir: &Path, request_id: &str) -> Option<PathBuf> {
+    let valid = !request_id.is_empty()
+        && request_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| dir.join(format!("ask-user-{request_id}.json")))
+}
+
+/// Request file for `request_id`, whether still active or already in `processed/`.
+pub fn locate_request_file(dir: &Path, request_id: &str) -> Option<PathBuf> {
+    let path = request_file_path(dir, request_id)?;
+    if path.exists() {
+        return Some(path);
+    }
+    let processed = request_file_path(&dir.join(PROCESSED_DIR), request_id)?;
+    Some(if processed.exists() { processed } else { path })
+}
+
+fn is_request_file_name(name: &str) -> bool {
+    name.starts_with("ask-user-") && name.ends_with(".json")
+}
+
+/// Request files in `dir` and `dir/processed`, with their modification times.
+fn request_files(dir: &Path) -> impl Iterator<Item = (PathBuf, SystemTime)> {
+    [dir.to_path_buf(), dir.join(PROCESSED_DIR)]
+        .into_iter()
+        .filter_map(|d| std::fs::read_dir(d).ok())
+        .flat_map(|rd| rd.flatten())
+        .filter(|ent| is_request_file_name(&ent.file_name().to_string_lossy()))
+        .filter_map(|ent| Some((ent.path(), ent.metadata().ok()?.modified().ok()?)))
+}
+
+/// Move a consumed request to `processed/` and drop processed requests older than
+/// [`ASK_USER_TTL`].
+pub fn archive_request(path: &Path) -> Result<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(());
+    };
+    let processed = dir.join(PROCESSED_DIR);
+    std::fs::create_dir_all(&processed)?;
+    std::fs::rename(path, processed.join(name))?;
+    prune_older_than(&processed, ASK_USER_TTL);
+    Ok(())
+}
+
+fn prune_older_than(dir: &Path, max_age: Duration) -> usize {
+    let now = SystemTime::now();
+    let Ok(rd) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    rd.flatten()
+        .filter(|ent| is_request_file_name(&ent.file_name().to_string_lossy()))
+        .filter(|ent| {
+            ent.metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|t| now.duration_since(t).unwrap_or_default() > max_age)
+        })
+        .filter(|ent| std::fs::remove_file(ent.path()).is_ok())
+        .count()
+}
+
+/// Delete request files (active or processed) not touched for `max_age`; returns how many.
+///
+/// Run at startup: whatever was pending then belongs to a run that no longer exists.
+pub fn sweep_request_files(dir: &Path, max_age: Duration) -> usize {
+    prune_older_than(dir, max_age) + prune_older_than(&dir.join(PROCESSED_DIR), max_age)
+}
+
+/// Whether a request file may target `chat_id`: an allowed user's private chat, or a group
+/// while groups are enabled. Request files are plain files in a shared directory, so another
+/// local process could plant one for an arbitrary chat.
+pub fn is_allowed_request_chat(chat_id: i64, cfg: &Config) -> bool {
+    if chat_id < 0 {
+        return cfg.group_mode != GroupMode::Off;
+    }
+    cfg.telegram_allowed_users.contains(&chat_id)
+}
+
+/// Numeric id stored either as a JSON number or a string.
+pub fn parse_id(v: &Value) -> Option<i64> {
+    v.as_i64()
+        .or_else(|| v.as_str().and_then(|s| s.parse::<i64>().ok()))
+}
+
+/// The parts of a request needed to (re)render its keyboard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AskUserRequest {
+    pub question: String,
+    pub options: Vec<String>,
+    pub answered: bool,
+}
+
+/// Parsed request file, if it exists and belongs to `target`.
+fn read_for_target(path: &Path, target: ChatTarget) -> Option<Value> {
+    let v: Value = serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+    let chat = v.get("chat_id").and_then(parse_id);
+    let thread = v.get("thread_id").and_then(parse_id).map(|t| t as i32);
+    if chat.is_some_and(|c| c != target.chat_id.0) || thread != target.thread_id {
+        return None;
+    }
+    Some(v)
+}
+
+fn question_of(v: &Value) -> String {
+    v.get("question")
+        .and_then(Value::as_str)
+        .unwrap_or("Please choose:")
+        .to_string()
+}
+
+pub fn load_request(path: &Path, target: ChatTarget) -> Option<AskUserRequest> {
+    let v = read_for_target(path, target)?;
+    let options = v
+        .get("options")
+        .and_then(Value::as_array)
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|x| x.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(AskUserRequest {
+        question: question_of(&v),
+        options,
+        answered: v.get("status").and_then(Value::as_str) == Some(STATUS_ANSWERED),
+    })
+}
+
+/// A request in `dir` for `target` whose keyboard was sent within [`DUPLICATE_WINDOW`], is still
+/// unanswered and asks the same question with the same options.
+///
+/// A run resumed after `ask_user` replays its last tool call, so the MCP server writes the same
+/// request again; showing it would put a second, identical keyboard in the chat. Once the first
+/// one is answered, the same question asked again is a new question and gets its own keyboard.
+pub fn find_unanswered_duplicate(
+    dir: &Path,
+    target: ChatTarget,
+    question: &str,
+    options: &[String],
+) -> Option<PathBuf> {
+    let now = SystemTime::now();
+    request_files(dir).find_map(|(path, modified)| {
+        if now.duration_since(modified).unwrap_or_default() > DUPLICATE_WINDOW {
+            return None;
+        }
+        let v = read_for_target(&path, target)?;
+        if v.get("status").and_then(Value::as_str) != Some("sent") {
+            return None;
+        }
+        let request = load_request(&path, target)?;
+        (request.question == question && request.options == options).then_some(path)
+    })
+}
+
+/// HTML for a request on a messenger without inline keyboards: the question and its options
+/// numbered from 1.
+pub fn render_text_options(theme: &StatusTheme, question: &str, options: &[String]) -> String {
+    let mut html = format!("{} {}\n", theme.question, escape_html(question));
+    for (i, option) in options.iter().enumerate() {
+        html.push_str(&format!("\n{}. {}", i + 1, escape_html(option)));
+    }
+    html.push_str("\n\n<i>Reply with the number of your choice.</i>");
+    html
+}
+
+/// `reply` as a 1-based option number, if it is just a number.
+pub fn parse_option_number(reply: &str) -> Option<usize> {
+    reply.trim().trim_end_matches('.').parse().ok()
+}
+
+/// Remember the sync request at `path`, shown in `chat_id` as a numbered list.
+pub fn track_sync_text_request(chat_id: i64, path: &Path) {
+    let mut requests = SYNC_TEXT_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+    requests.push((chat_id, path.to_path_buf()));
+}
+
+/// Whether a sync request shown in `chat_id` as a numbered list still waits for its typed
+/// answer. Requests answered, expired or removed meanwhile are forgotten.
+pub fn awaits_sync_text_answer(chat_id: i64) -> bool {
+    let mut requests = SYNC_TEXT_REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+    requests.retain(|(_, path)| {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|txt| serde_json::from_str::<Value>(&txt).ok())
+            .is_some_and(|v| v.get("status").and_then(Value::as_str) == Some("sent"))
+    });
+    requests.iter().any(|(chat, _)| *chat == chat_id)
+}
+
+/// The newest unanswered text-mode request in `dir` for `target` (written within
+/// [`ASK_USER_TTL`]) and the option index `reply` picks, if `reply` is just a valid number.
+pub fn find_text_answer(dir: &Path, target: ChatTarget, reply: &str) -> Option<(PathBuf, usize)> {
+    let number = parse_option_number(reply)?;
+    let now = SystemTime::now();
+    let (_, path, options) = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter_map(|ent| {
+            if !is_request_file_name(&ent.file_name().to_string_lossy()) {
+                return None;
+            }
+            let modified = ent.metadata().ok()?.modified().ok()?;
+            if now.duration_since(modified).unwrap_or_default() > ASK_USER_TTL {
+                return None;
+            }
+            let path = ent.path();
+            let v = read_for_target(&path, target)?;
+            let text_mode = v.get("answer_mode").and_then(Value::as_str) == Some(ANSWER_MODE_TEXT);
+            if !text_mode || v.get("status").and_then(Value::as_str) != Some("sent") {
+                return None;
+            }
+            let options = v.get("options").and_then(Value::as_array)?.len();
+            Some((modified, path, options))
+        })
+        .max_by_key(|(modified, _, _)| *modified)?;
+    (1..=options).contains(&number).then(|| (path, number - 1))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnswerOutcome {
+    /// First valid tap; the file is now `answered`. With `sync`, the MCP server is still
+    /// waiting for the answer inside the run, so nothing needs to be resumed.
+    Accepted {
+        question: String,
+        selected: String,
+        sync: bool,
+    },
+    /// Truncated option tapped for the first time: show `full_text`, record nothing else.
+    Preview { full_text: String },
+    /// The request was answered before (`selected` is the recorded choice, if any).
+    AlreadyAnswered { selected: Option<String> },
+    /// Missing file, wrong chat/topic or bad option; the message is user-facing.
+    Invalid(&'static str),
+}
+
+/// Record a keyboard tap on the request at `path`, at most once.
+///
+/// `max_label_len` must match the keyboard's label budget so truncated options are detected.
+pub fn record_answer(
+    path: &Path,
+    target: ChatTarget,
+    option_index: usize,
+    max_label_len: usize,
+    answered_at: &str,
+) -> Result<AnswerOutcome> {
+    let _guard = ANSWER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let Some(mut v) = read_for_target(path, target) else {
+        return Ok(AnswerOutcome::Invalid("Request expired or invalid"));
+    };
+
+    match v.get("status").and_then(Value::as_str) {
+        Some(STATUS_ANSWERED) => {
+            let selected = v.get("answer").and_then(Value::as_str).map(str::to_string);
+            return Ok(AnswerOutcome::AlreadyAnswered { selected });
+        }
+        Some(STATUS_EXPIRED) => return Ok(AnswerOutcome::Invalid("This question timed out")),
+        _ => {}
+    }
+
+    let selected = v
+        .get("options")
+        .and_then(Value::as_array)
+        .and_then(|opts| opts.get(option_index))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let Some(selected) = selected else {
+        return Ok(AnswerOutcome::Invalid("Invalid option"));
+    };
+
+    let (_, truncated) = truncate_label(&selected, max_label_len);
+    let previewed = v
+        .get("previewed")
+        .and_then(Value::as_array)
+        .is_some_and(|p| p.iter().any(|i| i.as_u64() == Some(option_index as u64)));
+    if truncated && !previewed {
+        match v.get_mut("previewed").and_then(Value::as_array_mut) {
+            Some(p) => p.push(Value::from(option_index)),
+            None => v["previewed"] = Value::from(vec![option_index]),
+        }
+        std::fs::write(path, serde_json::to_string(&v)?)?;
+        return Ok(AnswerOutcome::Preview {
+            full_text: selected,
+        });
+    }
+
+    let question = question_of(&v);
+    let sync = v.get("sync").and_then(Value::as_bool) == Some(true);
+
+    v["status"] = Value::from(STATUS_ANSWERED);
+    v["answer_index"] = Value::from(option_index);
+    v["answer"] = Value::from(selected.as_str());
+    v["answered_at"] = Value::from(answered_at);
+    std::fs::write(path, serde_json::to_string(&v)?)?;
+
+    Ok(AnswerOutcome::Accepted {
+        question,
+        selected,
+        sync,
+    })
+}
+
+/// Where a sync request stands, as seen by the waiting MCP server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncAnswer {
+    Pending,
+    Answered(String),
+    /// Deleted or expired.
+    Gone,
+}
+
+/// Current answer of the request at `path`.
+pub fn read_answer(path: &Path) -> SyncAnswer {
+    let Ok(txt) = std::fs::read_to_string(path) else {
+        return SyncAnswer::Gone;
+    };
+    // Unparsable: most likely caught mid-write by the bot.
+    let Ok(v) = serde_json::from_str::<Value>(&txt) else {
+        return SyncAnswer::Pending;
+    };
+    match v.get("status").and_then(Value::as_str) {
+        Some(STATUS_ANSWERED) => SyncAnswer::Answered(
+            v.get("answer")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        Some(STATUS_EXPIRED) => SyncAnswer::Gone,
+        _ => SyncAnswer::Pending,
+    }
+}
+
+/// Mark an unanswered request `expired`; returns the answer instead if one arrived meanwhile.
+pub fn expire_request(path: &Path) -> Result<Option<String>> {
+    let _guard = ANSWER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut v: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    if v.get("status").and_then(Value::as_str) == Some(STATUS_ANSWERED) {
+        return Ok(v.get("answer").and_then(Value::as_str).map(str::to_string));
+    }
+    v["status"] = Value::from(STATUS_EXPIRED);
+    std::fs::write(path, serde_json::to_string(&v)?)?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ChatId;
+    use serde_json::json;
+
+    fn write_request(name: &str, body: Value) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ctb-ask-answer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = request_file_path(&dir, name).unwrap();
+        std::fs::write(&path, body.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn double_tap_is_recorded_once() {
+        let path = write_request(
+            "dbl1",
+            json!({
+              "request_id": "dbl1",
+              "question": "Which?",
+              "options": ["A", "B"],
+              "status": "sent",
+              "chat_id": "42"
+            }),
+        );
+        let target = ChatTarget::from(ChatId(42));
+
+        let first = record_answer(&path, target, 1, 30, "2026-01-01T00:00:00Z").unwrap();
+        assert_eq!(
+            first,
+            AnswerOutcome::Accepted {
+                question: "Which?".to_string(),
+                selected: "B".to_string(),
+                sync: false
+            }
+        );
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["status"], "answered");
+        assert_eq!(saved["answer_index"], 1);
+        assert_eq!(saved["answered_at"], "2026-01-01T00:00:00Z");
+
+        let second = record_answer(&path, target, 0, 30, "2026-01-01T00:00:05Z").unwrap();
+        assert_eq!(
+            second,
+            AnswerOutcome::AlreadyAnswered {
+                selected: Some("B".to_string())
+            }
+        );
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["answer_index"], 1, "second tap must not overwrite");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn numbered_reply_answers_the_latest_text_mode_request() {
+        let dir = std::env::temp_dir().join(format!("ctb-ask-text-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = ChatTarget::from(ChatId(43));
+        let request = |mode: Option<&str>| {
+            let mut v = json!({"options": ["A", "B"], "status": "sent", "chat_id": 43});
+            if let Some(mode) = mode {
+                v["answer_mode"] = json!(mode);
+            }
+            v.to_string()
+        };
+        std::fs::write(dir.join("ask-user-kbd.json"), request(None)).unwrap();
+        assert_eq!(
+            find_text_answer(&dir, target, "1"),
+            None,
+            "keyboard requests"
+        );
+
+        let listed = dir.join("ask-user-txt.json");
+        std::fs::write(&listed, request(Some(ANSWER_MODE_TEXT))).unwrap();
+        assert_eq!(
+            find_text_answer(&dir, target, " 2 "),
+            Some((listed.clone(), 1))
+        );
+        for reply in ["0", "3", "two", "2 please"] {
+            assert_eq!(find_text_answer(&dir, target, reply), None, "{reply}");
+        }
+        assert_eq!(find_text_answer(&dir, ChatId(44).into(), "1"), None);
+
+        record_answer(&listed, target, 1, usize::MAX, "t").unwrap();
+        assert_eq!(
+            find_text_answer(&dir, target, "1"),
+            None,
+            "already answered"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn text_options_are_numbered_and_escaped() {
+        let html = render_text_options(
+            &crate::theme::UNICODE,
+            "Pick <one>",
+            &["a & b".to_string(), "c".to_string()],
+        );
+        assert_eq!(
+            html,
+            "❓ Pick &lt;one&gt;\n\n1. a &amp; b\n2. c\n\n<i>Reply with the number of your choice.</i>"
+        );
+    }
+
+    #[test]
+    fn rejects_foreign_chat_bad_option_and_unsafe_ids() {
+        let path = write_request(
+            "bad1",
+            json!({"options": ["A"], "status": "sent", "chat_id": 42, "thread_id": 7}),
+        );
+        let topic = ChatTarget {
+            chat_id: ChatId(42),
+            thread_id: Some(7),
+        };
+        assert!(matches!(
+            record_answer(&path, ChatId(42).into(), 0, 30, "t").unwrap(),
+            AnswerOutcome::Invalid(_)
+        ));
+        assert_eq!(
+            record_answer(&path, topic, 3, 30, "t").unwrap(),
+            AnswerOutcome::Invalid("Invalid option")
+        );
+        assert!(matches!(
+            record_answer(&path, topic, 0, 30, "t").unwrap(),
+            AnswerOutcome::Accepted { .. }
+        ));
+        assert!(request_file_path(Path::new("/tmp"), "../etc/x").is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn truncated_option_needs_a_second_tap() {
+        let long = "A very long option that does not fit on a button";
+        let path = write_request(
+            "long1",
+            json!({"question": "Q", "options": ["Short", long], "status": "sent", "chat_id": 5}),
+        );
+        let target = ChatTarget::from(ChatId(5));
+
+        assert_eq!(
+            record_answer(&path, target, 1, 20, "t").unwrap(),
+            AnswerOutcome::Preview {
+                full_text: long.to_string()
+            }
+        );
+        assert!(!load_request(&path, target).unwrap().answered);
+        assert_eq!(
+            record_answer(&path, target, 1, 20, "t").unwrap(),
+            AnswerOutcome::Accepted {
+                question: "Q".to_string(),
+                selected: long.to_string(),
+                sync: false
+            }
+        );
+        assert!(load_request(&path, target).unwrap().answered);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn sync_request_is_answered_through_the_file() {
+        let path = write_request(
+            "sync1",
+            json!({"question": "Q", "options": ["A", "B"], "status": "sent", "chat_id": 6, "sync": true}),
+        );
+        let target = ChatTarget::from(ChatId(6));
+        assert_eq!(read_answer(&path), SyncAnswer::Pending);
+
+        assert_eq!(
+            record_answer(&path, target, 0, 30, "t").unwrap(),
+            AnswerOutcome::Accepted {
+                question: "Q".to_string(),
+                selected: "A".to_string(),
+                sync: true
+            }
+        );
+        assert_eq!(read_answer(&path), SyncAnswer::Answered("A".to_string()));
+        assert_eq!(
+            expire_request(&path).unwrap(),
+            Some("A".to_string()),
+            "a late timeout keeps the answer"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn expired_sync_request_rejects_taps() {
+        let path = write_request(
+            "sync2",
+            json!({"options": ["A", "B"], "status": "sent", "chat_id": 6, "sync": true}),
+        );
+        assert_eq!(expire_request(&path).unwrap(), None);
+        assert_eq!(read_answer(&path), SyncAnswer::Gone);
+        assert_eq!(
+            record_answer(&path, ChatId(6).into(), 1, 30, "t").unwrap(),
+            AnswerOutcome::Invalid("This question timed out")
+        );
+        assert_eq!(
+            read_answer(Path::new("/tmp/ask-user-missing.json")),
+            SyncAnswer::Gone
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn sync_list_requests_are_tracked_until_answered() {
+        let path = write_request(
+            "sync3",
+            json!({"options": ["A", "B"], "status": "sent", "chat_id": 77, "sync": true}),
+        );
+        track_sync_text_request(77, &path);
+        assert!(awaits_sync_text_answer(77));
+        assert!(!awaits_sync_text_answer(78));
+        assert_eq!(parse_option_number(" 2. "), Some(2));
+        assert_eq!(parse_option_number("two"), None);
+
+        record_answer(&path, ChatId(77).into(), 0, usize::MAX, "t").unwrap();
+        assert!(!awaits_sync_text_answer(77));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn age(path: &Path, by: Duration) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - by).unwrap();
+    }
+
+    #[test]
+    fn archived_requests_are_still_found_and_sweep_drops_old_files() {
+        let dir = std::env::temp_dir().join(format!("ctb-ask-sweep-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |id: &str, status: &str| {
+            let path = request_file_path(&dir, id).unwrap();
+            let body = json!({
+              "request_id": id,
+              "question": "Deploy?",
+              "options": ["yes", "no"],
+              "status": status,
+              "chat_id": "9"
+            });
+            std::fs::write(&path, body.to_string()).unwrap();
+            path
+        };
+        let target = ChatTarget::from(ChatId(9));
+
+        // Answered and archived: the second tap and a replayed request still see it.
+        let answered = write("done1", STATUS_ANSWERED);
+        archive_request(&answered).unwrap();
+        assert!(!answered.exists());
+        let archived = locate_request_file(&dir, "done1").unwrap();
+        assert_eq!(
+            archived,
+            dir.join(PROCESSED_DIR).join("ask-user-done1.json")
+        );
+        assert!(load_request(&archived, target).unwrap().answered);
+        let options = ["yes".to_string(), "no".to_string()];
+        assert_eq!(
+            find_unanswered_duplicate(&dir, target, "Deploy?", &options),
+            None,
+            "an answered question may be asked again"
+        );
+        assert_eq!(
+            locate_request_file(&dir, "missing1"),
+            Some(dir.join("ask-user-missing1.json"))
+        );
+
+        let stale_pending = write("old1", "pending");
+        let fresh_pending = write("new1", "pending");
+        let stray = dir.join("notes.json");
+        std::fs::write(&stray, "{}").unwrap();
+        age(&stale_pending, ASK_USER_TTL * 2);
+        age(&archived, ASK_USER_TTL * 2);
+        age(&stray, ASK_USER_TTL * 2);
+
+        assert_eq!(sweep_request_files(&dir, ASK_USER_TTL), 2);
+        assert!(!stale_pending.exists());
+        assert!(!archived.exists());
+        assert!(fresh_pending.exists());
+        assert!(stray.exists(), "only request files are swept");
+        assert_eq!(sweep_request_files(&dir, ASK_USER_TTL), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,162 @@
+//! Owner `/broadcast`: one message to every allowed user.
+//!
+//! Sends are spaced by [`BROADCAST_SPACING`] to stay well under Telegram's bulk limit. Users who
+//! blocked the bot or never started it ([`Error::Unreachable`]) are reported, not retried.
+
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    domain::{ChatId, ChatTarget},
+    errors::Error,
+    formatting::escape_html,
+    messaging::port::MessagingPort,
+    theme::StatusTheme,
+    utils::truncate_text,
+};
+
+/// Gap between two broadcast sends (Telegram allows about 30 messages per second overall).
+pub const BROADCAST_SPACING: Duration = Duration::from_millis(100);
+
+/// Characters of a send error quoted in the summary.
+const REASON_MAX_CHARS: usize = 120;
+
+/// Who gets a broadcast: every allowed user (a private chat id equals the user id), without
+/// `sender` unless `include_sender`.
+pub fn broadcast_recipients(cfg: &Config, sender: i64, include_sender: bool) -> Vec<i64> {
+    let mut out: Vec<i64> = Vec::new();
+    for &user in &cfg.telegram_allowed_users {
+        if (include_sender || user != sender) && !out.contains(&user) {
+            out.push(user);
+        }
+    }
+    out
+}
+
+/// What happened to one recipient.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Sent,
+    /// Blocked the bot, never started it, or deleted their account.
+    Unreachable(String),
+    Failed(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BroadcastReport {
+    pub deliveries: Vec<(i64, Delivery)>,
+}
+
+impl BroadcastReport {
+    pub fn sent(&self) -> usize {
+        self.deliveries
+            .iter()
+            .filter(|(_, d)| *d == Delivery::Sent)
+            .count()
+    }
+
+    /// HTML summary for the owner, e.g. `📣 Sent 3/4, failed for 123456: blocked by user`.
+    pub fn summary(&self, theme: &StatusTheme) -> String {
+        let mut out = format!(
+            "{}Sent {}/{}",
+            theme.deco("📣"),
+            self.sent(),
+            self.deliveries.len()
+        );
+        let failures: Vec<String> = self
+            .deliveries
+            .iter()
+            .filter_map(|(user, d)| match d {
+                Delivery::Sent => None,
+                Delivery::Unreachable(reason) | Delivery::Failed(reason) => {
+                    Some(format!("{user}: {}", escape_html(reason)))
+                }
+            })
+            .collect();
+        if !failures.is_empty() {
+            out.push_str(", failed for ");
+            out.push_str(&failures.join("; "));
+        }
+        out
+    }
+}
+
+/// Send `text` (plain, escaped here) to each of `recipients`, `spacing` apart.
+pub async fn broadcast(
+    messenger: &dyn MessagingPort,
+    theme: &StatusTheme,
+    recipients: &[i64],
+    text: &str,
+    spacing: Duration,
+) -> BroadcastReport {
+    let html = format!("{}{}", theme.deco("📣"), escape_html(text));
+    let mut report = BroadcastReport::default();
+    for (i, &user) in recipients.iter().enumerate() {
+        if i > 0 && !spacing.is_zero() {
+            tokio::time::sleep(spacing).await;
+        }
+        let delivery = match messenger
+            .send_html(ChatTarget::from(ChatId(user)), &html)
+            .await
+        {
+            Ok(_) => Delivery::Sent,
+            Err(Error::Unreachable(reason)) => Delivery::Unreachable(reason),
+            Err(e) => Delivery::Failed(truncate_text(&e.to_string(), REASON_MAX_CHARS)),
+        };
+        report.deliveries.push((user, delivery));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::test_support::{Call, Failure, Op, RecordingMessenger};
+    use crate::theme::UNICODE;
+
+    #[tokio::test]
+    async fn fans_out_and_summarizes_failures() {
+        let messenger = RecordingMessenger::new();
+        messenger.fail_nth(Op::Send, 2, Failure::Unreachable("blocked by user"));
+        messenger.fail_nth(Op::Send, 4, Failure::Error("Bad <gateway>".to_string()));
+        let spacing = Duration::from_millis(20);
+
+        let report = broadcast(
+            &messenger,
+            &UNICODE,
+            &[1, 123456, 3, 4],
+            "Down at 22:00 <UTC>",
+            spacing,
+        )
+        .await;
+
+        let sends: Vec<_> = messenger
+            .calls()
+            .into_iter()
+            .filter_map(|c| match c.call {
+                Call::Send { target, html, .. } => Some((target.chat_id.0, html, c.at)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sends.iter().map(|(id, ..)| *id).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(sends[0].1, "📣 Down at 22:00 &lt;UTC&gt;");
+        assert!(sends[1].2 - sends[0].2 >= spacing * 2);
+
+        assert_eq!(report.sent(), 2);
+        assert_eq!(
+            report.deliveries[1],
+            (123456, Delivery::Unreachable("blocked by user".to_string()))
+        );
+        assert_eq!(
+            report.summary(&UNICODE),
+            "📣 Sent 2/4, failed for 123456: blocked by user; 4: external error: Bad &lt;gateway&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn all_sent_summary_has_no_failure_list() {
+        let messenger = RecordingMessenger::new();
+        let report = broadcast(&messenger, &UNICODE, &[7], "hi", Duration::ZERO).await;
+        assert_eq!(report.summary(&UNICODE), "📣 Sent 1/1");
+    }
+}
@@ -0,0 +1,514 @@
+//! Per-chat setting overrides (`/settings`).
+//!
+//! A few display settings can differ per chat, e.g. keeping tool messages in a debug chat while
+//! deleting them in a group. `Config` itself never changes: each turn resolves an
+//! [`EffectiveConfig`] from the global values and the chat's [`ChatOverrides`], and only the keys
+//! listed in [`ChatSetting`] can be overridden, including the chat's language. Overrides live in
+//! `<state_dir>/chat-settings.json`.
+
+use std::{collections::BTreeMap, fs, ops::Deref, path::PathBuf, sync::Mutex, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    i18n::{Locale, Messages},
+    Error, Result,
+};
+
+/// Accepted per-chat streaming throttle, in milliseconds.
+pub const STREAMING_THROTTLE_MIN_MS: u64 = 100;
+pub const STREAMING_THROTTLE_MAX_MS: u64 = 10_000;
+
+/// The settings a chat may override.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatSetting {
+    DeleteThinkingMessages,
+    DeleteToolMessages,
+    ShowToolOutputs,
+    StreamingThrottle,
+    Locale,
+    AllowedTools,
+    DisallowedTools,
+}
+
+impl ChatSetting {
+    pub const ALL: [Self; 7] = [
+        Self::DeleteThinkingMessages,
+        Self::DeleteToolMessages,
+        Self::ShowToolOutputs,
+        Self::StreamingThrottle,
+        Self::Locale,
+        Self::AllowedTools,
+        Self::DisallowedTools,
+    ];
+
+    /// Stable name, used in callback data.
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::DeleteThinkingMessages => "delete_thinking_messages",
+            Self::DeleteToolMessages => "delete_tool_messages",
+            Self::ShowToolOutputs => "show_tool_outputs",
+            Self::StreamingThrottle => "streaming_throttle",
+            Self::Locale => "locale",
+            Self::AllowedTools => "allowed_tools",
+            Self::DisallowedTools => "disallowed_tools",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.key() == key)
+    }
+
+    /// On/off settings, toggled from the `/settings` keyboard.
+    pub fn is_toggle(self) -> bool {
+        !matches!(
+            self,
+            Self::StreamingThrottle | Self::Locale | Self::AllowedTools | Self::DisallowedTools
+        )
+    }
+}
+
+/// One chat's overridden values; `None` falls back to the global config.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_thinking_messages: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_tool_messages: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_tool_outputs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub streaming_throttle_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+    /// Tool lists for the CLI; an empty list runs the chat without the global one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_tools: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disallowed_tools: Option<Vec<String>>,
+    /// `/prompt` profile appended to the system prompt; not a `/settings` value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_profile: Option<String>,
+}
+
+impl ChatOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn is_overridden(&self, setting: ChatSetting) -> bool {
+        match setting {
+            ChatSetting::DeleteThinkingMessages => self.delete_thinking_messages.is_some(),
+            ChatSetting::DeleteToolMessages => self.delete_tool_messages.is_some(),
+            ChatSetting::ShowToolOutputs => self.show_tool_outputs.is_some(),
+            ChatSetting::StreamingThrottle => self.streaming_throttle_ms.is_some(),
+            ChatSetting::Locale => self.locale.is_some(),
+            ChatSetting::AllowedTools => self.allowed_tools.is_some(),
+            ChatSetting::DisallowedTools => self.disallowed_tools.is_some(),
+        }
+    }
+
+    fn flag_mut(&mut self, setting: ChatSetting) -> Option<&mut Option<bool>> {
+        match setting {
+            ChatSetting::DeleteThinkingMessages => Some(&mut self.delete_thinking_messages),
+            ChatSetting::DeleteToolMessages => Some(&mut self.delete_tool_messages),
+            ChatSetting::ShowToolOutputs => Some(&mut self.show_tool_outputs),
+            _ => None,
+        }
+    }
+}
+
+/// The config one chat runs with: the global values plus that chat's overrides.
+#[derive(Clone, Debug)]
+pub struct EffectiveConfig(Config);
+
+impl EffectiveConfig {
+    pub fn for_chat(cfg: &Config, overrides: &ChatOverrides) -> Self {
+        let mut cfg = cfg.clone();
+        if let Some(v) = overrides.delete_thinking_messages {
+            cfg.delete_thinking_messages = v;
+        }
+        if let Some(v) = overrides.delete_tool_messages {
+            cfg.delete_tool_messages = v;
+        }
+        if let Some(v) = overrides.show_tool_outputs {
+            cfg.show_tool_outputs = v;
+        }
+        if let Some(ms) = overrides.streaming_throttle_ms {
+            cfg.streaming_throttle = Duration::from_millis(ms);
+        }
+        if let Some(locale) = overrides.locale {
+            cfg.bot_locale = locale;
+        }
+        if let Some(tools) = &overrides.allowed_tools {
+            cfg.claude_allowed_tools = (!tools.is_empty()).then(|| tools.clone());
+        }
+        if let Some(tools) = &overrides.disallowed_tools {
+            cfg.claude_disallowed_tools = (!tools.is_empty()).then(|| tools.clone());
+        }
+        Self(cfg)
+    }
+
+    /// Value of an on/off setting (`None` for the throttle, language and tool lists).
+    pub fn flag(&self, setting: ChatSetting) -> Option<bool> {
+        config_flag(&self.0, setting)
+    }
+
+    /// Human-readable value in the chat's language, e.g. `on`, `500 ms`, `Italiano` or
+    /// `Read, Grep`.
+    pub fn display(&self, setting: ChatSetting) -> String {
+        let msgs = self.messages();
+        let tools = |tools: &Option<Vec<String>>, unset: &str| {
+            tools
+                .as_ref()
+                .map_or_else(|| unset.to_string(), |t| t.join(", "))
+        };
+        match setting {
+            ChatSetting::StreamingThrottle => {
+                format!("{} ms", self.0.streaming_throttle.as_millis())
+            }
+            ChatSetting::Locale => self.0.bot_locale.name().to_string(),
+            ChatSetting::AllowedTools => tools(&self.0.claude_allowed_tools, msgs.all_tools()),
+            ChatSetting::DisallowedTools => tools(&self.0.claude_disallowed_tools, msgs.no_tools()),
+            _ => msgs
+                .on_off(self.flag(setting).unwrap_or_default())
+                .to_string(),
+        }
+    }
+
+    pub fn messages(&self) -> &'static Messages {
+        self.0.bot_locale.messages().themed(self.0.status_theme)
+    }
+
+    pub fn into_config(self) -> Config {
+        self.0
+    }
+}
+
+impl Deref for EffectiveConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.0
+    }
+}
+
+fn config_flag(cfg: &Config, setting: ChatSetting) -> Option<bool> {
+    match setting {
+        ChatSetting::DeleteThinkingMessages => Some(cfg.delete_thinking_messages),
+        ChatSetting::DeleteToolMessages => Some(cfg.delete_tool_messages),
+        ChatSetting::ShowToolOutputs => Some(cfg.show_tool_outputs),
+        _ => None,
+    }
+}
+
+/// Overrides keyed by chat id, persisted to `file` on every change.
+#[derive(Debug)]
+pub struct ChatSettings {
+    file: PathBuf,
+    chats: Mutex<BTreeMap<i64, ChatOverrides>>,
+}
+
+impl ChatSettings {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self::load(cfg.state_dir.join("chat-settings.json"))
+    }
+
+    /// Load `file`; a missing or unreadable file starts without overrides.
+    pub fn load(file: PathBuf) -> Self {
+        let chats = match fs::read_to_string(&file) {
+            Ok(txt) => serde_json::from_str(&txt).unwrap_or_else(|e| {
+                log_line!("[SETTINGS] Ignoring unreadable {}: {e}", file.display());
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            file,
+            chats: Mutex::new(chats),
+        }
+    }
+
+    pub fn overrides(&self, chat_id: i64) -> ChatOverrides {
+        self.chats().get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    pub fn effective(&self, cfg: &Config, chat_id: i64) -> EffectiveConfig {
+        EffectiveConfig::for_chat(cfg, &self.overrides(chat_id))
+    }
+
+    /// The catalog `chat_id` is answered in: its `/settings language`, else `BOT_LOCALE`.
+    pub fn messages(&self, cfg: &Config, chat_id: i64) -> &'static Messages {
+        self.chats()
+            .get(&chat_id)
+            .and_then(|o| o.locale)
+            .unwrap_or(cfg.bot_locale)
+            .messages()
+            .themed(cfg.status_theme)
+    }
+
+    /// Flip an on/off setting for `chat_id` and return its new value. Landing back on the
+    /// global value drops the override.
+    pub fn toggle(&self, cfg: &Config, chat_id: i64, setting: ChatSetting) -> Result<bool> {
+        let Some(global) = config_flag(cfg, setting) else {
+            return Err(Error::Config(format!(
+                "{} is not an on/off setting",
+                setting.key()
+            )));
+        };
+        self.update(chat_id, |o| {
+            let flag = o.flag_mut(setting).expect("on/off setting");
+            let current = flag.unwrap_or(global);
+            *flag = (current == global).then_some(!global);
+            !current
+        })
+    }
+
+    /// Override the streaming throttle for `chat_id`; `None` restores the global value.
+    pub fn set_streaming_throttle(&self, chat_id: i64, ms: Option<u64>) -> Result<()> {
+        if let Some(ms) = ms {
+            if !(STREAMING_THROTTLE_MIN_MS..=STREAMING_THROTTLE_MAX_MS).contains(&ms) {
+                return Err(Error::Config(format!(
+                    "the streaming throttle must be {STREAMING_THROTTLE_MIN_MS}-{STREAMING_THROTTLE_MAX_MS} ms"
+                )));
+            }
+        }
+        self.update(chat_id, |o| o.streaming_throttle_ms = ms)
+    }
+
+    /// Override a tool list for `chat_id` (an empty list drops the global one); `None` restores
+    /// the global list.
+    pub fn set_tools(
+        &self,
+        chat_id: i64,
+        setting: ChatSetting,
+        tools: Option<Vec<String>>,
+    ) -> Result<()> {
+        match setting {
+            ChatSetting::AllowedTools => self.update(chat_id, |o| o.allowed_tools = tools),
+            ChatSetting::DisallowedTools => self.update(chat_id, |o| o.disallowed_tools = tools),
+            _ => Err(Error::Config(format!(
+                "{} is not a tool list",
+                setting.key()
+            ))),
+        }
+    }
+
+    /// Answer `chat_id` in `locale`; `None` restores `BOT_LOCALE`.
+    pub fn set_locale(&self, chat_id: i64, locale: Option<Locale>) -> Result<()> {
+        self.update(chat_id, |o| o.locale = locale)
+    }
+
+    /// Append the `/prompt` profile `name` to `chat_id`'s system prompt; `None` clears it.
+    pub fn set_prompt_profile(&self, chat_id: i64, name: Option<String>) -> Result<()> {
+        self.update(chat_id, |o| o.prompt_profile = name)
+    }
+
+    /// Drop every override of `chat_id`, or all but its tool lists with `keep_tool_lists` (only
+    /// the owner may change those); `false` if nothing changed.
+    pub fn reset(&self, chat_id: i64, keep_tool_lists: bool) -> Result<bool> {
+        let mut chats = self.chats();
+        let Some(old) = chats.remove(&chat_id) else {
+            return Ok(false);
+        };
+        let kept = ChatOverrides {
+            allowed_tools: old.allowed_tools.clone().filter(|_| keep_tool_lists),
+            disallowed_tools: old.disallowed_tools.clone().filter(|_| keep_tool_lists),
+            prompt_profile: old.prompt_profile.clone(),
+            ..ChatOverrides::default()
+        };
+        if kept == old {
+            chats.insert(chat_id, old);
+            return Ok(false);
+        }
+        if !kept.is_empty() {
+            chats.insert(chat_id, kept);
+        }
+        self.save(&chats)?;
+        Ok(true)
+    }
+
+    fn update<T>(&self, chat_id: i64, f: impl FnOnce(&mut ChatOverrides) -> T) -> Result<T> {
+        let mut chats = self.chats();
+        let overrides = chats.entry(chat_id).or_default();
+        let out = f(overrides);
+        if overrides.is_empty() {
+            chats.remove(&chat_id);
+        }
+        self.save(&chats)?;
+        Ok(out)
+    }
+
+    /// Every chat's overrides (for `/export_state`).
+    pub fn all(&self) -> BTreeMap<i64, ChatOverrides> {
+        self.chats().clone()
+    }
+
+    /// Replace the overrides of each chat in `chats`; other chats keep theirs.
+    pub fn restore(&self, chats: BTreeMap<i64, ChatOverrides>) -> Result<()> {
+        let mut current = self.chats();
+        current.extend(chats);
+        current.retain(|_, o| *o != ChatOverrides::default());
+        self.save(&current)
+    }
+
+    fn chats(&self) -> std::sync::MutexGuard<'_, BTreeMap<i64, ChatOverrides>> {
+        self.chats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn save(&self, chats: &BTreeMap<i64, ChatOverrides>) -> Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.file, serde_json::to_string_pretty(chats)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            telegram_safe_limit: 50,
+            streaming_throttle: Duration::from_millis(500),
+            delete_tool_messages: true,
+            rate_limit_enabled: true,
+            ..Config::for_tests()
+        }
+    }
+
+    fn store(name: &str) -> ChatSettings {
+        let file = std::env::temp_dir()
+            .join(format!("ctb-chat-settings-{name}-{}", std::process::id()))
+            .join("chat-settings.json");
+        let _ = fs::remove_file(&file);
+        ChatSettings::load(file)
+    }
+
+    #[test]
+    fn overrides_win_over_the_global_config_per_key() {
+        let cfg = config();
+        let overrides = ChatOverrides {
+            delete_tool_messages: Some(false),
+            streaming_throttle_ms: Some(2000),
+            ..ChatOverrides::default()
+        };
+        let eff = EffectiveConfig::for_chat(&cfg, &overrides);
+        assert!(!eff.delete_tool_messages);
+        assert_eq!(eff.streaming_throttle, Duration::from_millis(2000));
+        assert!(
+            !eff.delete_thinking_messages,
+            "unset keys keep the global value"
+        );
+        assert_eq!(eff.session_file, cfg.session_file);
+        assert_eq!(eff.display(ChatSetting::StreamingThrottle), "2000 ms");
+        assert_eq!(eff.display(ChatSetting::DeleteToolMessages), "off");
+
+        let italian = ChatOverrides {
+            locale: Some(Locale::It),
+            ..overrides
+        };
+        let eff = EffectiveConfig::for_chat(&cfg, &italian);
+        assert_eq!(eff.display(ChatSetting::DeleteToolMessages), "no");
+        assert_eq!(eff.display(ChatSetting::Locale), "Italiano");
+    }
+
+    #[test]
+    fn tool_lists_override_or_clear_the_global_ones() {
+        let mut cfg = config();
+        cfg.claude_disallowed_tools = Some(vec!["WebFetch".to_string()]);
+        let settings = store("tools");
+        let eff = settings.effective(&cfg, 7);
+        assert_eq!(eff.display(ChatSetting::AllowedTools), "all");
+        assert_eq!(eff.display(ChatSetting::DisallowedTools), "WebFetch");
+
+        let tools = vec!["Read".to_string(), "Grep".to_string()];
+        settings
+            .set_tools(7, ChatSetting::AllowedTools, Some(tools.clone()))
+            .unwrap();
+        settings
+            .set_tools(7, ChatSetting::DisallowedTools, Some(Vec::new()))
+            .unwrap();
+        assert!(settings
+            .set_tools(7, ChatSetting::Locale, Some(Vec::new()))
+            .is_err());
+        let eff = settings.effective(&cfg, 7);
+        assert_eq!(eff.claude_allowed_tools, Some(tools));
+        assert_eq!(
+            eff.claude_disallowed_tools, None,
+            "none drops the global list"
+        );
+        assert_eq!(eff.display(ChatSetting::AllowedTools), "Read, Grep");
+        assert_eq!(eff.display(ChatSetting::DisallowedTools), "none");
+        assert!(!ChatSetting::AllowedTools.is_toggle());
+
+        settings
+            .set_tools(7, ChatSetting::DisallowedTools, None)
+            .unwrap();
+        assert_eq!(
+            settings.effective(&cfg, 7).claude_disallowed_tools,
+            cfg.claude_disallowed_tools
+        );
+
+        // A non-owner's reset keeps the tool lists; the owner's drops them too.
+        settings.set_locale(7, Some(Locale::It)).unwrap();
+        assert!(settings.reset(7, true).unwrap());
+        assert!(!settings.reset(7, true).unwrap());
+        assert_eq!(
+            settings.overrides(7),
+            ChatOverrides {
+                allowed_tools: Some(vec!["Read".to_string(), "Grep".to_string()]),
+                ..ChatOverrides::default()
+            }
+        );
+        assert!(settings.reset(7, false).unwrap());
+        assert!(settings.overrides(7).is_empty());
+        let _ = fs::remove_dir_all(settings.file.parent().unwrap());
+    }
+
+    #[test]
+    fn toggles_persist_and_drop_back_to_the_global_value() {
+        let cfg = config();
+        let settings = store("toggle");
+        assert!(!settings
+            .toggle(&cfg, 7, ChatSetting::DeleteToolMessages)
+            .unwrap());
+        assert!(settings
+            .toggle(&cfg, 7, ChatSetting::ShowToolOutputs)
+            .unwrap());
+        settings.set_streaming_throttle(7, Some(1000)).unwrap();
+        assert!(settings.set_streaming_throttle(7, Some(5)).is_err());
+        assert!(settings
+            .toggle(&cfg, 7, ChatSetting::StreamingThrottle)
+            .is_err());
+
+        let reloaded = ChatSettings::load(settings.file.clone());
+        assert_eq!(
+            reloaded.overrides(7),
+            ChatOverrides {
+                delete_tool_messages: Some(false),
+                show_tool_outputs: Some(true),
+                streaming_throttle_ms: Some(1000),
+                ..ChatOverrides::default()
+            }
+        );
+        assert!(reloaded.overrides(8).is_empty(), "overrides are per chat");
+
+        assert!(reloaded
+            .toggle(&cfg, 7, ChatSetting::DeleteToolMessages)
+            .unwrap());
+        assert!(!reloaded
+            .overrides(7)
+            .is_overridden(ChatSetting::DeleteToolMessages));
+        assert!(reloaded.reset(7, false).unwrap());
+        assert!(!reloaded.reset(7, false).unwrap());
+        assert!(ChatSettings::load(settings.file.clone())
+            .overrides(7)
+            .is_empty());
+        let _ = fs::remove_dir_all(settings.file.parent().unwrap());
+    }
+}
@@ -1,12 +1,124 @@
 use std::{
+    collections::HashMap,
     env,
     ffi::OsString,
     fs,
+    net::SocketAddr,
     path::{Path, PathBuf},
     time::Duration,
 };
 
-use crate::{errors::Error, Result};
+use crate::{
+    errors::Error,
+    health::CliVersion,
+    i18n::Locale,
+    logging::DEFAULT_LOG_BUFFER_LINES,
+    model::types::{parse_tool_list, NoticePatterns, ProviderErrorPatterns},
+    security::{parse_user_roles, Role},
+    theme::{self, StatusTheme},
+    usage::{prefetch::UsagePrefetch, pricing::PricingTable},
+    Result,
+};
+
+/// Upper bound for any thinking budget (env default or `/think N`).
+pub const MAX_THINKING_TOKENS: u32 = 128_000;
+
+/// Between segments of a turn's text unless `TURN_SEGMENT_SEPARATOR` says otherwise.
+pub const DEFAULT_SEGMENT_SEPARATOR: &str = "\n\n---\n\n";
+
+/// Transcription defaults for `TRANSCRIPTION_MODEL`, `OPENAI_BASE_URL` and
+/// `TRANSCRIPTION_TIMEOUT_MS`.
+pub const DEFAULT_TRANSCRIPTION_MODEL: &str = "gpt-4o-transcribe";
+pub const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+pub const DEFAULT_TRANSCRIPTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How the bot treats messages in group and supergroup chats (`GROUP_MODE`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupMode {
+    /// Only @mentions, replies to the bot and `/commands`.
+    #[default]
+    Mention,
+    /// Every message from an allowed user is a prompt.
+    All,
+    /// Group messages are ignored.
+    Off,
+}
+
+impl GroupMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "mention" => Some(Self::Mention),
+            "all" => Some(Self::All),
+            "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// What a prompt does to a session idle for longer than `session_idle_after`
+/// (`SESSION_IDLE_EXPIRY`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdleExpiry {
+    /// Keep continuing the session however old it is.
+    #[default]
+    Off,
+    /// Start a new session and say so.
+    New,
+    /// Ask whether to continue or start fresh before running the prompt.
+    Ask,
+}
+
+impl IdleExpiry {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "new" => Some(Self::New),
+            "ask" => Some(Self::Ask),
+            _ => None,
+        }
+    }
+}
+
+/// Where persistent state lives (`STATE_BACKEND`); see [`crate::storage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StateBackend {
+    /// One JSON file per entry (`SESSION_FILE`, `RESTART_FILE`, ...).
+    #[default]
+    Json,
+    /// `state.db` under `STATE_DIR`; needs the `sqlite` feature.
+    Sqlite,
+}
+
+impl StateBackend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "sqlite" => Some(Self::Sqlite),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a Bash call that doesn't start with `BASH_SANDBOX_WRAPPER`
+/// (`BASH_SANDBOX_ENFORCEMENT`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SandboxEnforcement {
+    /// Let it run and flag it in the tool status.
+    Warn,
+    /// Cancel the run like any other blocked command.
+    #[default]
+    Block,
+}
+
+impl SandboxEnforcement {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "warn" => Some(Self::Warn),
+            "block" => Some(Self::Block),
+            _ => None,
+        }
+    }
+}
 
 /// Typed configuration for the Rust port.
 ///
@@ -16,51 +128,198 @@ pub struct Config {
     // Core
     pub telegram_bot_token: String,
     pub telegram_allowed_users: Vec<i64>,
+    /// `TELEGRAM_USER_ROLES` entries; see `security::role_of` for users without one.
+    pub telegram_user_roles: HashMap<i64, Role>,
     pub claude_working_dir: PathBuf,
     pub openai_api_key: Option<String>,
     pub transcription_prompt: String,
     pub transcription_available: bool,
+    pub transcription_model: String,
+    /// OpenAI-compatible API root used for transcription.
+    pub openai_base_url: String,
+    pub transcription_timeout: Duration,
+    /// ISO-639-1 language hint for transcription (`None` = auto-detect).
+    pub transcription_language: Option<String>,
 
     // Claude CLI
     pub claude_cli_path: PathBuf,
     pub claude_config_dir: Option<PathBuf>,
+    pub claude_model: Option<String>,
+    /// Tools the CLI may use (`CLAUDE_ALLOWED_TOOLS`) and must not use
+    /// (`CLAUDE_DISALLOWED_TOOLS`); `/settings` can override both per chat.
+    pub claude_allowed_tools: Option<Vec<String>>,
+    pub claude_disallowed_tools: Option<Vec<String>>,
+    /// CLI stderr lines forwarded to the chat as notices (`CLAUDE_STDERR_NOTICES`).
+    pub claude_stderr_notices: NoticePatterns,
+    /// Result texts that mean the provider failed the turn (`PROVIDER_ERROR_PATTERNS`).
+    pub provider_error_patterns: ProviderErrorPatterns,
+    /// Retry a turn once when the provider couldn't be reached (`PROVIDER_ERROR_RETRY`).
+    pub provider_error_retry: bool,
+    /// SIGINT -> SIGKILL escalation delay when cancelling a CLI run.
+    pub cli_kill_grace: Duration,
+    /// Second CLI tried when the primary fails before answering; defaults to `claude_cli_path`
+    /// when only the model is set. No fallback when both are `None`.
+    pub claude_fallback_cli_path: Option<PathBuf>,
+    pub claude_fallback_model: Option<String>,
+    /// Accepted `claude --version` range (inclusive): older refuses to start, newer warns.
+    pub claude_cli_min_version: Option<CliVersion>,
+    pub claude_cli_max_version: Option<CliVersion>,
 
     // Security / safety
     pub allowed_paths: Vec<PathBuf>,
     pub temp_paths: Vec<PathBuf>,
+    /// Let Write/Edit/`rm` into `~/.claude` (and `CLAUDE_CONFIG_DIR`) when they are allowed
+    /// paths; by default those are read-only.
+    pub allow_claude_dir_writes: bool,
     pub blocked_patterns: Vec<String>,
+    /// Bash allowlist (`git`, `npm run`, ...); non-empty makes Bash default-deny.
+    pub allowed_command_prefixes: Vec<String>,
+    /// Command every Bash call should run through (`nice -n 19`, `firejail ...`); the model is
+    /// told to prefix its commands with it.
+    pub bash_sandbox_wrapper: Option<String>,
+    pub bash_sandbox_enforcement: SandboxEnforcement,
+    /// WebFetch hosts allowed (with subdomains); non-empty makes all others blocked.
+    pub webfetch_allowed_domains: Vec<String>,
+    /// WebFetch hosts always blocked (with subdomains).
+    pub webfetch_blocked_domains: Vec<String>,
+    /// State file for per-chat `/grant` directories.
+    pub grants_file: PathBuf,
+    /// Lifetime of a `/grant` (`None` = until revoked).
+    pub grant_ttl: Option<Duration>,
     pub safety_prompt: String,
+    pub group_mode: GroupMode,
+    /// Max characters of a replied-to message quoted into the prompt (0 = no reply context).
+    pub reply_context_max_chars: usize,
+    pub session_idle_expiry: IdleExpiry,
+    /// Time since the last completed turn after which `session_idle_expiry` applies.
+    pub session_idle_after: Duration,
 
     // Runtime constants
-    pub query_timeout: Duration,
+    /// Longest a single run may take before it is cancelled (`QUERY_TIMEOUT_MS`; unset or 0 =
+    /// no limit).
+    pub query_timeout: Option<Duration>,
     pub temp_dir: PathBuf,
+    /// Persistent state (`STATE_DIR`, default `~/.ctb/state`): sessions, per-chat settings,
+    /// grants, vocabulary, queues and the usage ledger. Unlike `temp_dir` it survives reboots.
+    pub state_dir: PathBuf,
     pub session_file: PathBuf,
     pub restart_file: PathBuf,
+    pub state_backend: StateBackend,
+    pub cron_queue_file: PathBuf,
+    /// Pending `/remind` reminders, kept across restarts.
+    pub reminders_file: PathBuf,
+    pub ask_user_wait_timeout: Duration,
+    /// `ask_user` keeps the tool call open until the user answers (`ASK_USER_SYNC`) instead of
+    /// ending the run and resuming it with the answer.
+    pub ask_user_sync: bool,
+    /// How long a sync `ask_user` call waits for the answer.
+    pub ask_user_sync_timeout: Duration,
 
     // Telegram limits
+    /// Message size caps; the messenger's `max_message_len` lowers them further.
     pub telegram_message_limit: usize,
     pub telegram_safe_limit: usize,
     pub streaming_throttle: Duration,
+    /// Sends/edits per minute per group chat before spinner ticks are dropped and other calls
+    /// queue (0 = unlimited).
+    pub group_messages_per_minute: u32,
+    /// Model events buffered between the CLI reader and the Telegram pipeline; a full queue
+    /// pauses the reader (`EVENT_QUEUE_DEPTH`, min 1).
+    pub event_queue_depth: usize,
+    /// Fenced code blocks longer than this (chars) are sent as file attachments; 0 disables.
+    pub code_file_threshold: usize,
     pub button_label_max_length: usize,
 
+    // Telegram webhook (unset URL = long polling)
+    /// Public HTTPS URL Telegram posts updates to.
+    pub telegram_webhook_url: Option<String>,
+    /// Local address the webhook listener binds, usually behind a reverse proxy.
+    pub telegram_webhook_listen: SocketAddr,
+    /// `secret_token` Telegram echoes back on every webhook request; required with a URL.
+    pub telegram_webhook_secret: Option<String>,
+
     // Behavior flags
     pub default_thinking_tokens: u32,
     pub thinking_keywords: Vec<String>,
     pub thinking_deep_keywords: Vec<String>,
     pub delete_thinking_messages: bool,
     pub delete_tool_messages: bool,
+    pub show_tool_outputs: bool,
+    /// Consecutive statuses of the same tool action within this window share one message
+    /// (zero disables).
+    pub tool_status_coalesce: Duration,
+    /// Re-send the progress spinner only after this many messages below it (0 = after each).
+    pub progress_resend_after_messages: usize,
+    /// ...or when it is older than this (`None` = no age limit).
+    pub progress_resend_after: Option<Duration>,
+    /// Append per-turn latency numbers to the completion message.
+    pub debug_timings: bool,
+    /// Send a one-line duration / tools / tokens / cost footer after each turn.
+    pub turn_summary: bool,
+    /// A prompt starting with this runs plan-only and waits for approval (empty = off).
+    pub plan_mode_prefix: String,
+    /// Spinner, completion and status glyphs (`unicode`, `ascii`, `minimal`).
+    pub status_theme: &'static StatusTheme,
+    /// Language of the bot's own messages (`en`, `it`); chats may override it in `/settings`.
+    pub bot_locale: Locale,
+
+    // Run limits (unset = unlimited)
+    pub max_turns: Option<u32>,
+    pub max_budget_usd: Option<f64>,
+    /// Per-user tokens per local day; prompts are rejected once reached.
+    pub daily_token_cap: Option<u64>,
+    /// Context size (tokens since the last compaction) that triggers the near-limit warning
+    /// and `needs_save()`.
+    pub context_save_threshold_tokens: u64,
+    /// Bytes of answer text kept for a turn's final output; past it the text is cut and marked.
+    pub max_turn_output_bytes: usize,
+    /// Put between a turn's text segments (split at tool calls) in its final output.
+    pub turn_segment_separator: String,
 
     // Audit
+    /// stderr lines kept in memory for `/logs` (0 = no capture).
+    pub log_buffer_lines: usize,
     pub audit_log_path: PathBuf,
     pub audit_log_json: bool,
+    /// Keep unscrubbed error text in the audit log (chat output is always scrubbed).
+    pub audit_log_raw_errors: bool,
+    /// Audit every message sent, edited or deleted (hash, size and a short preview).
+    pub audit_delivery: bool,
+    /// Tell the owner's chat when someone's run trips a safety block.
+    pub security_alerts: bool,
+    /// JSONL usage ledger behind `/limits` and `daily_token_cap`.
+    pub usage_ledger_path: PathBuf,
+    /// Per-model rates for cost estimates: built-in list prices plus `PRICING_JSON` overrides.
+    pub pricing: PricingTable,
+    /// Provider usage poll interval for threshold warnings (`None` = disabled).
+    pub usage_monitor_interval: Option<Duration>,
+    /// Background warm-up of the provider usage caches (`USAGE_PREFETCH`, off by default).
+    pub usage_prefetch: UsagePrefetch,
 
     // Rate limiting
     pub rate_limit_enabled: bool,
     pub rate_limit_requests: u32,
     pub rate_limit_window: Duration,
+    /// Bucket capacity: how many requests can be sent at once (`RATE_LIMIT_BURST`; default
+    /// `rate_limit_requests`).
+    pub rate_limit_burst: u32,
+    /// Owners are never rate limited.
+    pub rate_limit_owner_bypass: bool,
 
     // Media groups
     pub media_group_timeout: Duration,
+    /// Append `tesseract` OCR text to photo prompts even when the model has vision.
+    pub photo_ocr_force: bool,
+
+    // Undo
+    /// Turns whose file snapshots `/undo` keeps (`UNDO_KEEP_TURNS`; 0 disables the journal).
+    pub undo_keep_turns: usize,
+
+    // Text bursts
+    /// Merge text messages a user sends in quick succession into one prompt (`TEXT_COALESCE`).
+    pub text_coalesce: bool,
+    /// Quiet time that ends a burst (`TEXT_COALESCE_MS`; default 2s).
+    pub text_coalesce_window: Duration,
 }
 
 impl Config {
@@ -70,7 +329,18 @@ impl Config {
 
         // Required env vars
         let telegram_bot_token = env_str("TELEGRAM_BOT_TOKEN").unwrap_or_default();
-        let telegram_allowed_users = parse_csv_i64(env_str("TELEGRAM_ALLOWED_USERS"));
+        let mut telegram_allowed_users = parse_csv_i64(env_str("TELEGRAM_ALLOWED_USERS"));
+        let telegram_user_roles =
+            parse_user_roles(&env_str("TELEGRAM_USER_ROLES").unwrap_or_default())
+                .map_err(|e| Error::Config(format!("TELEGRAM_USER_ROLES: {e}")))?;
+        // Users with a role are allowed even when not listed in TELEGRAM_ALLOWED_USERS.
+        let mut role_users: Vec<i64> = telegram_user_roles
+            .keys()
+            .filter(|u| !telegram_allowed_users.contains(u))
+            .copied()
+            .collect();
+        role_users.sort_unstable();
+        telegram_allowed_users.extend(role_users);
 
         if telegram_bot_token.trim().is_empty() {
             return Err(Error::Config(
@@ -91,12 +361,30 @@ impl Config {
         let openai_api_key = env_str("OPENAI_API_KEY").and_then(non_empty);
         let transcription_prompt = build_transcription_prompt();
         let transcription_available = openai_api_key.is_some();
+        let transcription_model =
+            env_str("TRANSCRIPTION_MODEL").unwrap_or(DEFAULT_TRANSCRIPTION_MODEL.to_string());
+        let openai_base_url =
+            env_str("OPENAI_BASE_URL").unwrap_or(DEFAULT_OPENAI_BASE_URL.to_string());
+        let transcription_timeout = env_u64("TRANSCRIPTION_TIMEOUT_MS")
+            .map_or(DEFAULT_TRANSCRIPTION_TIMEOUT, Duration::from_millis);
+        let transcription_language = env_str("TRANSCRIPTION_LANGUAGE").and_then(non_empty);
 
         // Claude CLI path
         let claude_cli_path = env_path("CLAUDE_CLI_PATH")
             .or_else(|| which_in_path("claude"))
             .unwrap_or_else(|| PathBuf::from("/usr/local/bin/claude"));
         let claude_config_dir = env_path("CLAUDE_CONFIG_DIR");
+        let claude_model = env_str("CLAUDE_MODEL").and_then(non_empty);
+        let claude_allowed_tools = env_tool_list("CLAUDE_ALLOWED_TOOLS")?;
+        let claude_disallowed_tools = env_tool_list("CLAUDE_DISALLOWED_TOOLS")?;
+        let claude_stderr_notices = env_notice_patterns("CLAUDE_STDERR_NOTICES")?;
+        let provider_error_patterns = env_provider_error_patterns("PROVIDER_ERROR_PATTERNS")?;
+        let provider_error_retry = env_bool("PROVIDER_ERROR_RETRY").unwrap_or(false);
+        let cli_kill_grace = Duration::from_millis(env_u64("CLI_KILL_GRACE_MS").unwrap_or(3000));
+        let claude_fallback_cli_path = env_path("CLAUDE_FALLBACK_CLI_PATH");
+        let claude_fallback_model = env_str("CLAUDE_FALLBACK_MODEL").and_then(non_empty);
+        let claude_cli_min_version = env_cli_version("CLAUDE_CLI_MIN_VERSION")?;
+        let claude_cli_max_version = env_cli_version("CLAUDE_CLI_MAX_VERSION")?;
 
         // Allowed paths (ALLOWED_PATHS overrides defaults)
         let default_allowed_paths = vec![
@@ -116,6 +404,8 @@ impl Config {
             PathBuf::from("/var/folders/"),
         ];
 
+        let allow_claude_dir_writes = env_bool("ALLOW_CLAUDE_DIR_WRITES").unwrap_or(false);
+
         let safety_prompt = build_safety_prompt(&allowed_paths);
 
         let blocked_patterns = vec![
@@ -131,30 +421,119 @@ impl Config {
         .into_iter()
         .map(|s| s.to_string())
         .collect();
+        let allowed_command_prefixes = parse_csv(env_str("ALLOWED_COMMAND_PREFIXES"));
+        let bash_sandbox_wrapper = env_str("BASH_SANDBOX_WRAPPER")
+            .and_then(non_empty)
+            .map(|w| w.trim().to_string());
+        let bash_sandbox_enforcement = match env_str("BASH_SANDBOX_ENFORCEMENT").and_then(non_empty)
+        {
+            Some(raw) => SandboxEnforcement::parse(&raw).ok_or_else(|| {
+                Error::Config(format!(
+                    "BASH_SANDBOX_ENFORCEMENT must be one of warn, block (got {raw:?})"
+                ))
+            })?,
+            None => SandboxEnforcement::default(),
+        };
+        let webfetch_allowed_domains = parse_csv_lower(env_str("WEBFETCH_ALLOWED_DOMAINS"));
+        let webfetch_blocked_domains = parse_csv_lower(env_str("WEBFETCH_BLOCKED_DOMAINS"));
+        let group_mode = match env_str("GROUP_MODE").and_then(non_empty) {
+            Some(raw) => GroupMode::parse(&raw).ok_or_else(|| {
+                Error::Config(format!(
+                    "GROUP_MODE must be one of mention, all, off (got {raw:?})"
+                ))
+            })?,
+            None => GroupMode::default(),
+        };
+        let reply_context_max_chars = env_usize("REPLY_CONTEXT_MAX_CHARS").unwrap_or(1500);
+        let session_idle_expiry = match env_str("SESSION_IDLE_EXPIRY").and_then(non_empty) {
+            Some(raw) => IdleExpiry::parse(&raw).ok_or_else(|| {
+                Error::Config(format!(
+                    "SESSION_IDLE_EXPIRY must be one of off, new, ask (got {raw:?})"
+                ))
+            })?,
+            None => IdleExpiry::default(),
+        };
+        let session_idle_after = Duration::from_secs(
+            env_u64("SESSION_IDLE_HOURS")
+                .filter(|h| *h > 0)
+                .unwrap_or(24)
+                * 3600,
+        );
 
         // Timeouts and constants
-        let query_timeout = Duration::from_millis(env_u64("QUERY_TIMEOUT_MS").unwrap_or(180_000));
+        let query_timeout = env_u64("QUERY_TIMEOUT_MS")
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis);
         let temp_dir =
             PathBuf::from(env_str("TEMP_DIR").unwrap_or("/tmp/telegram-bot".to_string()));
-        let session_file = PathBuf::from(
-            env_str("SESSION_FILE").unwrap_or("/tmp/claude-telegram-session.json".to_string()),
-        );
-        let restart_file = PathBuf::from(
-            env_str("RESTART_FILE").unwrap_or("/tmp/claude-telegram-restart.json".to_string()),
+        let state_dir = env_path("STATE_DIR").unwrap_or_else(|| home.join(".ctb").join("state"));
+        let session_file =
+            env_path("SESSION_FILE").unwrap_or_else(|| state_dir.join("session.json"));
+        let restart_file =
+            env_path("RESTART_FILE").unwrap_or_else(|| state_dir.join("restart.json"));
+        let state_backend = match env_str("STATE_BACKEND").and_then(non_empty) {
+            Some(raw) => StateBackend::parse(&raw).ok_or_else(|| {
+                Error::Config(format!(
+                    "STATE_BACKEND must be one of json, sqlite (got {raw:?})"
+                ))
+            })?,
+            None => StateBackend::default(),
+        };
+        if state_backend == StateBackend::Sqlite && !cfg!(feature = "sqlite") {
+            return Err(Error::Config(
+                "STATE_BACKEND=sqlite needs a build with the `sqlite` feature".to_string(),
+            ));
+        }
+        let cron_queue_file =
+            env_path("CRON_QUEUE_FILE").unwrap_or_else(|| state_dir.join("cron-queue.json"));
+        let reminders_file =
+            env_path("REMINDERS_FILE").unwrap_or_else(|| state_dir.join("reminders.json"));
+
+        let ask_user_wait_timeout =
+            Duration::from_millis(env_u64("ASK_USER_WAIT_TIMEOUT_MS").unwrap_or(5000));
+        let ask_user_sync = env_bool("ASK_USER_SYNC").unwrap_or(false);
+        let ask_user_sync_timeout = Duration::from_secs(
+            env_u64("ASK_USER_SYNC_TIMEOUT_SECS")
+                .filter(|n| *n > 0)
+                .unwrap_or(600),
         );
 
+        let grants_file =
+            env_path("GRANTS_FILE").unwrap_or_else(|| state_dir.join("directory-grants.json"));
+        let grant_ttl = env_u64("GRANT_TTL_MINUTES")
+            .filter(|m| *m > 0)
+            .map(|m| Duration::from_secs(m * 60));
+
         // Ensure temp dir exists (parity with TS which writes `.keep`)
         fs::create_dir_all(&temp_dir)?;
+        fs::create_dir_all(&state_dir)?;
 
         // Telegram message limits
         let telegram_message_limit = env_usize("TELEGRAM_MESSAGE_LIMIT").unwrap_or(4096);
         let telegram_safe_limit = env_usize("TELEGRAM_SAFE_LIMIT").unwrap_or(4000);
+        let code_file_threshold = env_usize("CODE_FILE_THRESHOLD").unwrap_or(3000);
         let streaming_throttle =
             Duration::from_millis(env_u64("STREAMING_THROTTLE_MS").unwrap_or(500));
+        let group_messages_per_minute = env_u32("GROUP_MESSAGES_PER_MINUTE").unwrap_or(20);
+        let event_queue_depth = env_usize("EVENT_QUEUE_DEPTH").unwrap_or(256).max(1);
         let button_label_max_length = env_usize("BUTTON_LABEL_MAX_LENGTH").unwrap_or(30);
 
+        // Webhook mode
+        let telegram_webhook_url = env_str("TELEGRAM_WEBHOOK_URL").and_then(non_empty);
+        let telegram_webhook_listen = match env_str("TELEGRAM_WEBHOOK_LISTEN").and_then(non_empty) {
+            Some(raw) => raw.trim().parse().map_err(|_| {
+                Error::Config(format!(
+                    "TELEGRAM_WEBHOOK_LISTEN must be an address like 127.0.0.1:8080 (got {raw:?})"
+                ))
+            })?,
+            None => SocketAddr::from(([127, 0, 0, 1], 8080)),
+        };
+        let telegram_webhook_secret = env_str("TELEGRAM_WEBHOOK_SECRET").and_then(non_empty);
+
         // Thinking config
-        let default_thinking_tokens = env_u32("DEFAULT_THINKING_TOKENS").unwrap_or(0).min(128_000);
+        let default_thinking_tokens = env_u32("DEFAULT_THINKING_TOKENS")
+            .unwrap_or(0)
+            .min(MAX_THINKING_TOKENS);
         let thinking_keywords = parse_csv_lower(
             env_str("THINKING_KEYWORDS").or_else(|| Some("think,pensa,ragiona".to_string())),
         );
@@ -167,58 +546,309 @@ impl Config {
         let delete_thinking_messages =
             env_bool("DEFAULT_DELETE_THINKING_MESSAGES").unwrap_or(false);
         let delete_tool_messages = env_bool("DEFAULT_DELETE_TOOL_MESSAGES").unwrap_or(true);
+        let show_tool_outputs = env_bool("SHOW_TOOL_OUTPUTS").unwrap_or(false);
+        let tool_status_coalesce =
+            Duration::from_millis(env_u64("TOOL_STATUS_COALESCE_MS").unwrap_or(10_000));
+        let progress_resend_after_messages =
+            env_usize("PROGRESS_RESEND_AFTER_MESSAGES").unwrap_or(0);
+        let progress_resend_after = Some(env_u64("PROGRESS_RESEND_AFTER_SECS").unwrap_or(20))
+            .filter(|s| *s > 0)
+            .map(Duration::from_secs);
+        let debug_timings = env_bool("DEBUG_TIMINGS").unwrap_or(false);
+        let turn_summary = env_bool("TURN_SUMMARY").unwrap_or(false);
+        let plan_mode_prefix = env_str("PLAN_MODE_PREFIX")
+            .map(|s| s.trim().to_string())
+            .unwrap_or_else(|| "?".to_string());
+        let status_theme = match env_str("STATUS_THEME").and_then(non_empty) {
+            Some(raw) => StatusTheme::by_name(&raw).ok_or_else(|| {
+                Error::Config(format!(
+                    "STATUS_THEME must be one of unicode, ascii, minimal (got {raw:?})"
+                ))
+            })?,
+            None => &theme::UNICODE,
+        };
+        let bot_locale = match env_str("BOT_LOCALE").and_then(non_empty) {
+            Some(raw) => Locale::parse(&raw).ok_or_else(|| {
+                Error::Config(format!("BOT_LOCALE must be one of en, it (got {raw:?})"))
+            })?,
+            None => Locale::default(),
+        };
+
+        // Per-query guardrails (cron jobs may override per schedule).
+        let max_turns = env_u32("MAX_TURNS").filter(|n| *n > 0);
+        let max_budget_usd = env_f64("MAX_BUDGET_USD").filter(|b| *b > 0.0);
+        let daily_token_cap = env_u64("DAILY_TOKEN_CAP").filter(|n| *n > 0);
+        let context_save_threshold_tokens = env_u64("CONTEXT_SAVE_THRESHOLD_TOKENS")
+            .filter(|n| *n > 0)
+            .unwrap_or(180_000);
+        let max_turn_output_bytes = env_usize("MAX_TURN_OUTPUT_BYTES")
+            .filter(|n| *n > 0)
+            .unwrap_or(1024 * 1024);
+        // `\n` in the env value stands for a newline.
+        let turn_segment_separator = env_str("TURN_SEGMENT_SEPARATOR")
+            .map(|s| s.replace("\\n", "\n"))
+            .unwrap_or_else(|| DEFAULT_SEGMENT_SEPARATOR.to_string());
 
         // Audit logging
+        let log_buffer_lines = env_usize("LOG_BUFFER_LINES").unwrap_or(DEFAULT_LOG_BUFFER_LINES);
         let audit_log_path = PathBuf::from(
             env_str("AUDIT_LOG_PATH").unwrap_or("/tmp/claude-telegram-audit.log".to_string()),
         );
         let audit_log_json = env_bool("AUDIT_LOG_JSON").unwrap_or(false);
+        let audit_log_raw_errors = env_bool("AUDIT_LOG_RAW_ERRORS").unwrap_or(false);
+        let audit_delivery = env_bool("AUDIT_DELIVERY").unwrap_or(false);
+        let security_alerts = env_bool("SECURITY_ALERTS").unwrap_or(false);
+        let pricing = PricingTable::new(load_pricing_json()?.as_deref())?;
+        let usage_monitor_interval = Some(env_u64("USAGE_MONITOR_INTERVAL_MINUTES").unwrap_or(15))
+            .filter(|m| *m > 0)
+            .map(|m| Duration::from_secs(m * 60));
+        let usage_prefetch = match env_str("USAGE_PREFETCH").and_then(non_empty) {
+            Some(raw) => UsagePrefetch::parse(&raw).ok_or_else(|| {
+                Error::Config(format!(
+                    "USAGE_PREFETCH must be one of off, startup, repeat (got {raw:?})"
+                ))
+            })?,
+            None => UsagePrefetch::Off,
+        };
+        let usage_ledger_path =
+            env_path("USAGE_LEDGER_PATH").unwrap_or_else(|| state_dir.join("usage-ledger.jsonl"));
 
         // Rate limiting
         let rate_limit_enabled = env_bool("RATE_LIMIT_ENABLED").unwrap_or(true);
         let rate_limit_requests = env_u32("RATE_LIMIT_REQUESTS").unwrap_or(20);
         let rate_limit_window = Duration::from_secs(env_u64("RATE_LIMIT_WINDOW").unwrap_or(60));
+        let rate_limit_burst = env_u32("RATE_LIMIT_BURST")
+            .filter(|n| *n > 0)
+            .unwrap_or(rate_limit_requests);
+        let rate_limit_owner_bypass = env_bool("RATE_LIMIT_OWNER_BYPASS").unwrap_or(false);
 
         // Media groups
         let media_group_timeout =
             Duration::from_millis(env_u64("MEDIA_GROUP_TIMEOUT").unwrap_or(1000));
+        let photo_ocr_force = env_bool("PHOTO_OCR_FORCE").unwrap_or(false);
+
+        // Text bursts
+        let undo_keep_turns = env_usize("UNDO_KEEP_TURNS").unwrap_or(10);
+        let text_coalesce = env_bool("TEXT_COALESCE").unwrap_or(false);
+        let text_coalesce_window =
+            Duration::from_millis(env_u64("TEXT_COALESCE_MS").unwrap_or(2000));
 
         Ok(Self {
             telegram_bot_token,
             telegram_allowed_users,
+            telegram_user_roles,
             claude_working_dir,
             openai_api_key,
             transcription_prompt,
             transcription_available,
+            transcription_model,
+            openai_base_url,
+            transcription_timeout,
+            transcription_language,
             claude_cli_path,
             claude_config_dir,
+            claude_model,
+            claude_allowed_tools,
+            claude_disallowed_tools,
+            claude_stderr_notices,
+            provider_error_patterns,
+            provider_error_retry,
+            cli_kill_grace,
+            claude_fallback_cli_path,
+            claude_fallback_model,
+            claude_cli_min_version,
+            claude_cli_max_version,
             allowed_paths,
             temp_paths,
+            allow_claude_dir_writes,
             blocked_patterns,
+            allowed_command_prefixes,
+            bash_sandbox_wrapper,
+            bash_sandbox_enforcement,
+            webfetch_allowed_domains,
+            webfetch_blocked_domains,
+            grants_file,
+            grant_ttl,
             safety_prompt,
+            group_mode,
+            reply_context_max_chars,
+            session_idle_expiry,
+            session_idle_after,
             query_timeout,
             temp_dir,
+            state_dir,
             session_file,
             restart_file,
+            state_backend,
+            cron_queue_file,
+            reminders_file,
+            ask_user_wait_timeout,
+            ask_user_sync,
+            ask_user_sync_timeout,
             telegram_message_limit,
             telegram_safe_limit,
             streaming_throttle,
+            group_messages_per_minute,
+            event_queue_depth,
+            code_file_threshold,
             button_label_max_length,
+            telegram_webhook_url,
+            telegram_webhook_listen,
+            telegram_webhook_secret,
             default_thinking_tokens,
             thinking_keywords,
             thinking_deep_keywords,
             delete_thinking_messages,
             delete_tool_messages,
+            show_tool_outputs,
+            tool_status_coalesce,
+            progress_resend_after_messages,
+            progress_resend_after,
+            debug_timings,
+            turn_summary,
+            plan_mode_prefix,
+            status_theme,
+            bot_locale,
+            max_turns,
+            max_budget_usd,
+            daily_token_cap,
+            context_save_threshold_tokens,
+            max_turn_output_bytes,
+            turn_segment_separator,
+            log_buffer_lines,
             audit_log_path,
             audit_log_json,
+            audit_log_raw_errors,
+            audit_delivery,
+            security_alerts,
+            usage_ledger_path,
+            pricing,
+            usage_monitor_interval,
+            usage_prefetch,
             rate_limit_enabled,
             rate_limit_requests,
             rate_limit_window,
+            rate_limit_burst,
+            rate_limit_owner_bypass,
             media_group_timeout,
+            photo_ocr_force,
+            undo_keep_turns,
+            text_coalesce,
+            text_coalesce_window,
         })
     }
 }
 
+#[cfg(test)]
+impl Config {
+    /// Fixed config for unit tests, without [`Config::load`]'s environment dependency.
+    pub(crate) fn for_tests() -> Self {
+        Self {
+            telegram_bot_token: "x".to_string(),
+            telegram_allowed_users: vec![1],
+            telegram_user_roles: Default::default(),
+            claude_working_dir: "/tmp".into(),
+            openai_api_key: None,
+            transcription_prompt: "x".to_string(),
+            transcription_available: false,
+            transcription_model: DEFAULT_TRANSCRIPTION_MODEL.to_string(),
+            openai_base_url: DEFAULT_OPENAI_BASE_URL.to_string(),
+            transcription_timeout: DEFAULT_TRANSCRIPTION_TIMEOUT,
+            transcription_language: None,
+            claude_cli_path: "/usr/bin/claude".into(),
+            claude_config_dir: None,
+            claude_model: None,
+            claude_allowed_tools: None,
+            claude_disallowed_tools: None,
+            claude_stderr_notices: Default::default(),
+            provider_error_patterns: Default::default(),
+            provider_error_retry: false,
+            cli_kill_grace: Duration::from_millis(100),
+            claude_fallback_cli_path: None,
+            claude_fallback_model: None,
+            claude_cli_min_version: None,
+            claude_cli_max_version: None,
+            allowed_paths: vec!["/tmp".into()],
+            temp_paths: vec!["/tmp/".into()],
+            allow_claude_dir_writes: false,
+            blocked_patterns: vec!["rm -rf /".to_string()],
+            allowed_command_prefixes: vec![],
+            bash_sandbox_wrapper: None,
+            bash_sandbox_enforcement: SandboxEnforcement::Block,
+            webfetch_allowed_domains: vec![],
+            webfetch_blocked_domains: vec![],
+            grants_file: "/tmp/ctb-directory-grants.json".into(),
+            grant_ttl: None,
+            safety_prompt: "x".to_string(),
+            group_mode: Default::default(),
+            reply_context_max_chars: 1500,
+            session_idle_expiry: Default::default(),
+            session_idle_after: Duration::from_secs(24 * 3600),
+            query_timeout: None,
+            temp_dir: "/tmp".into(),
+            state_dir: "/tmp".into(),
+            session_file: "/tmp/claude-telegram-session.json".into(),
+            restart_file: "/tmp/claude-telegram-restart.json".into(),
+            state_backend: StateBackend::Json,
+            cron_queue_file: "/tmp/claude-telegram-cron-queue.json".into(),
+            reminders_file: "/tmp/claude-telegram-reminders.json".into(),
+            ask_user_wait_timeout: Duration::from_millis(500),
+            ask_user_sync: false,
+            ask_user_sync_timeout: Duration::from_secs(600),
+            telegram_message_limit: 4096,
+            telegram_safe_limit: 4000,
+            streaming_throttle: Duration::from_millis(0),
+            group_messages_per_minute: 20,
+            event_queue_depth: 256,
+            button_label_max_length: 30,
+            telegram_webhook_url: None,
+            telegram_webhook_listen: ([127, 0, 0, 1], 8080).into(),
+            telegram_webhook_secret: None,
+            default_thinking_tokens: 0,
+            thinking_keywords: vec![],
+            thinking_deep_keywords: vec![],
+            delete_thinking_messages: false,
+            delete_tool_messages: false,
+            show_tool_outputs: false,
+            tool_status_coalesce: Duration::ZERO,
+            progress_resend_after_messages: 0,
+            progress_resend_after: None,
+            debug_timings: false,
+            turn_summary: false,
+            plan_mode_prefix: "?".to_string(),
+            status_theme: &theme::UNICODE,
+            bot_locale: Default::default(),
+            max_turns: None,
+            max_budget_usd: None,
+            daily_token_cap: None,
+            context_save_threshold_tokens: 180_000,
+            max_turn_output_bytes: 1024 * 1024,
+            turn_segment_separator: "\n\n---\n\n".to_string(),
+            log_buffer_lines: 0,
+            audit_log_path: "/tmp/a.log".into(),
+            audit_log_json: false,
+            audit_delivery: false,
+            security_alerts: false,
+            audit_log_raw_errors: false,
+            usage_ledger_path: "/tmp/ctb-usage-ledger.jsonl".into(),
+            pricing: Default::default(),
+            usage_monitor_interval: None,
+            usage_prefetch: Default::default(),
+            rate_limit_enabled: false,
+            rate_limit_requests: 20,
+            rate_limit_window: Duration::from_secs(60),
+            rate_limit_burst: 20,
+            rate_limit_owner_bypass: false,
+            media_group_timeout: Duration::from_millis(1000),
+            photo_ocr_force: false,
+            undo_keep_turns: 10,
+            text_coalesce: false,
+            text_coalesce_window: Duration::from_millis(2000),
+            code_file_threshold: 3000,
+        }
+    }
+}
+
 fn inject_extra_paths() {
     let Some(home) = home_dir() else {
         return;
@@ -350,6 +980,10 @@ fn env_u32(key: &str) -> Option<u32> {
     env_str(key).and_then(|s| s.trim().parse::<u32>().ok())
 }
 
+fn env_f64(key: &str) -> Option<f64> {
+    env_str(key).and_then(|s| s.trim().parse::<f64>().ok())
+}
+
 fn env_usize(key: &str) -> Option<usize> {
     env_str(key).and_then(|s| s.trim().parse::<usize>().ok())
 }
@@ -358,6 +992,48 @@ fn env_path(key: &str) -> Option<PathBuf> {
     env::var_os(key).map(PathBuf::from)
 }
 
+fn env_cli_version(key: &str) -> Result<Option<CliVersion>> {
+    match env_str(key).and_then(non_empty) {
+        Some(raw) => CliVersion::parse(&raw)
+            .map(Some)
+            .ok_or_else(|| Error::Config(format!("{key} must look like 2.0.1 (got {raw:?})"))),
+        None => Ok(None),
+    }
+}
+
+/// A comma-separated tool list; unset or empty means no list.
+fn env_tool_list(key: &str) -> Result<Option<Vec<String>>> {
+    let Some(raw) = env_str(key) else {
+        return Ok(None);
+    };
+    let tools = parse_tool_list(&raw).map_err(|e| Error::Config(format!("{key}: {e}")))?;
+    Ok((!tools.is_empty()).then_some(tools))
+}
+
+/// Comma-separated regexes; unset means [`NoticePatterns::DEFAULTS`], empty or `none` none.
+fn env_notice_patterns(key: &str) -> Result<NoticePatterns> {
+    let Some(raw) = env_str(key) else {
+        return Ok(NoticePatterns::defaults());
+    };
+    if raw.trim().eq_ignore_ascii_case("none") {
+        return Ok(NoticePatterns::default());
+    }
+    NoticePatterns::new(&parse_csv(Some(raw))).map_err(|e| Error::Config(format!("{key}: {e}")))
+}
+
+/// Comma-separated regexes; unset means [`ProviderErrorPatterns::DEFAULTS`], empty or `none`
+/// none (only results flagged `is_error` count).
+fn env_provider_error_patterns(key: &str) -> Result<ProviderErrorPatterns> {
+    let Some(raw) = env_str(key) else {
+        return Ok(ProviderErrorPatterns::defaults());
+    };
+    if raw.trim().eq_ignore_ascii_case("none") {
+        return Ok(ProviderErrorPatterns::default());
+    }
+    ProviderErrorPatterns::new(&parse_csv(Some(raw)))
+        .map_err(|e| Error::Config(format!("{key}: {e}")))
+}
+
 fn parse_csv_i64(v: Option<String>) -> Vec<i64> {
     v.unwrap_or_default()
         .split(',')
@@ -367,6 +1043,14 @@ fn parse_csv_i64(v: Option<String>) -> Vec<i64> {
         .collect()
 }
 
+fn parse_csv(v: Option<String>) -> Vec<String> {
+    v.unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
 fn parse_csv_lower(v: Option<String>) -> Vec<String> {
     v.unwrap_or_default()
         .split(',')
@@ -390,7 +1074,7 @@ fn parse_csv_paths(v: Option<String>) -> Option<Vec<PathBuf>> {
     }
 }
 
-fn which_in_path(binary: &str) -> Option<PathBuf> {
+pub(crate) fn which_in_path(binary: &str) -> Option<PathBuf> {
     let path = env::var_os("PATH")?;
     for dir in env::split_paths(&path) {
         let candidate = dir.join(binary);
@@ -401,7 +1085,7 @@ fn which_in_path(binary: &str) -> Option<PathBuf> {
     None
 }
 
-fn is_executable_file(p: &Path) -> bool {
+pub(crate) fn is_executable_file(p: &Path) -> bool {
     if !p.is_file() {
         return false;
     }
@@ -415,6 +1099,20 @@ fn is_executable_file(p: &Path) -> bool {
     true
 }
 
+/// `PRICING_JSON` holds either inline JSON or a path to a JSON file.
+fn load_pricing_json() -> Result<Option<String>> {
+    let Some(value) = env_str("PRICING_JSON").and_then(non_empty) else {
+        return Ok(None);
+    };
+    let json = if value.trim_start().starts_with('{') {
+        value
+    } else {
+        fs::read_to_string(&value)
+            .map_err(|e| Error::Config(format!("PRICING_JSON: cannot read {value}: {e}")))?
+    };
+    Ok(Some(json))
+}
+
 fn non_empty(s: String) -> Option<String> {
     if s.trim().is_empty() {
         None
@@ -20,3 +20,27 @@ pub struct MessageRef {
 /// Claude/agent session id (string).
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct SessionId(pub String);
+
+/// Where to post in a chat: the chat itself, or one forum topic (`message_thread_id`) inside it.
+///
+/// `thread_id: None` is the plain chat (or the "General" topic of a forum supergroup).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChatTarget {
+    pub chat_id: ChatId,
+    pub thread_id: Option<i32>,
+}
+
+impl ChatTarget {
+    pub fn new(chat_id: ChatId, thread_id: Option<i32>) -> Self {
+        Self { chat_id, thread_id }
+    }
+}
+
+impl From<ChatId> for ChatTarget {
+    fn from(chat_id: ChatId) -> Self {
+        Self {
+            chat_id,
+            thread_id: None,
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Incoming message entities → markdown-ish prompt text.
+//!
+//! Messengers deliver formatting as plain text plus entity ranges, so a pasted message loses the
+//! URLs hidden behind link labels. [`telegram_text_to_prompt`] puts them back: text links become
+//! `[label](url)`, code becomes backticked spans or fenced blocks, bold/italic/strikethrough get
+//! their markdown markers, and a leading bot command is dropped.
+//!
+//! Entity offsets and lengths are UTF-16 code units (Telegram's convention), not bytes.
+
+/// Formatting applied to a range of the message text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextEntityKind {
+    TextLink {
+        url: String,
+    },
+    Code,
+    Pre {
+        language: Option<String>,
+    },
+    Bold,
+    Italic,
+    Strikethrough,
+    BotCommand,
+    /// Anything that needs no markup (mentions, hashtags, plain URLs, ...).
+    Other,
+}
+
+/// One entity: `length` UTF-16 code units starting at `offset`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEntity {
+    pub kind: TextEntityKind,
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl TextEntity {
+    pub fn new(kind: TextEntityKind, offset: usize, length: usize) -> Self {
+        Self {
+            kind,
+            offset,
+            length,
+        }
+    }
+}
+
+/// Byte index of UTF-16 offset `units` in `text`; `None` when it is past the end or splits a
+/// surrogate pair.
+fn utf16_to_byte(text: &str, units: usize) -> Option<usize> {
+    let mut seen = 0;
+    for (byte, ch) in text.char_indices() {
+        if seen == units {
+            return Some(byte);
+        }
+        if seen > units {
+            return None;
+        }
+        seen += ch.len_utf16();
+    }
+    (seen == units).then_some(text.len())
+}
+
+/// Backtick fence longer than any backtick run in `content`.
+fn backtick_fence(content: &str, min: usize) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(min.max(longest + 1))
+}
+
+/// Rebuild `text` with markdown for the entities that carry information the plain text lacks.
+///
+/// Entities with out-of-range offsets are ignored rather than trusted, so malformed input never
+/// panics.
+pub fn telegram_text_to_prompt(text: &str, entities: &[TextEntity]) -> String {
+    // (byte position, closes-before-opens, nesting order, marker)
+    let mut markers: Vec<(usize, u8, (isize, isize), String)> = Vec::new();
+    let range = |entity: &TextEntity| {
+        let start = utf16_to_byte(text, entity.offset)?;
+        let end = utf16_to_byte(text, entity.offset.saturating_add(entity.length))?;
+        (start < end).then_some((start, end))
+    };
+
+    // A leading command (`/ask ...` in a caption) is not part of the prompt.
+    let strip_to = entities
+        .iter()
+        .filter(|e| e.kind == TextEntityKind::BotCommand)
+        .filter_map(range)
+        .find(|(start, _)| *start == 0)
+        .map_or(0, |(_, end)| text.len() - text[end..].trim_start().len());
+
+    for (i, entity) in entities.iter().enumerate() {
+        let Some((start, end)) = range(entity).filter(|(start, _)| *start >= strip_to) else {
+            continue;
+        };
+        let content = &text[start..end];
+
+        // Inline styles hug the words: Telegram often includes surrounding spaces.
+        let trimmed_start = start + (content.len() - content.trim_start().len());
+        let trimmed_end = start + content.trim_end().len();
+
+        let (start, end, open, close) = match &entity.kind {
+            TextEntityKind::BotCommand | TextEntityKind::Other => continue,
+            TextEntityKind::TextLink { url } => (start, end, "[".to_string(), format!("]({url})")),
+            TextEntityKind::Code => {
+                let fence = backtick_fence(content, 1);
+                (start, end, fence.clone(), fence)
+            }
+            TextEntityKind::Pre { language } => {
+                let fence = backtick_fence(content, 3);
+                let lang = language.as_deref().unwrap_or_default();
+                (start, end, format!("{fence}{lang}\n"), format!("\n{fence}"))
+            }
+            TextEntityKind::Bold | TextEntityKind::Italic | TextEntityKind::Strikethrough => {
+                if trimmed_start >= trimmed_end {
+                    continue;
+                }
+                let marker = match entity.kind {
+                    TextEntityKind::Bold => "**",
+                    TextEntityKind::Italic => "_",
+                    _ => "~~",
+                };
+                (
+                    trimmed_start,
+                    trimmed_end,
+                    marker.to_string(),
+                    marker.to_string(),
+                )
+            }
+        };
+        // At one position: closing markers first (innermost first), then opening markers
+        // (outermost first), so nested entities stay nested. Entities with the same range nest
+        // in list order.
+        let i = i as isize;
+        markers.push((start, 1, (-(end as isize), i), open));
+        markers.push((end, 0, (-(start as isize), -i), close));
+    }
+
+    markers.sort_by_key(|(pos, order, nesting, _)| (*pos, *order, *nesting));
+
+    let mut out = String::with_capacity(text.len() + markers.len() * 4);
+    let mut pos = strip_to;
+    for (at, _, _, marker) in markers {
+        out.push_str(&text[pos..at]);
+        out.push_str(&marker);
+        pos = at;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(offset: usize, length: usize, url: &str) -> TextEntity {
+        TextEntity::new(
+            TextEntityKind::TextLink {
+                url: url.to_string(),
+            },
+            offset,
+            length,
+        )
+    }
+
+    #[test]
+    fn plain_text_is_unchanged() {
+        assert_eq!(telegram_text_to_prompt("hello there", &[]), "hello there");
+        let mention = TextEntity::new(TextEntityKind::Other, 0, 6);
+        assert_eq!(
+            telegram_text_to_prompt("@alice hi", &[mention]),
+            "@alice hi"
+        );
+    }
+
+    #[test]
+    fn text_links_expose_their_url() {
+        let text = "see the docs and this";
+        let out = telegram_text_to_prompt(
+            text,
+            &[
+                link(8, 4, "https://docs.rs/"),
+                link(17, 4, "https://example.com/x"),
+            ],
+        );
+        assert_eq!(
+            out,
+            "see the [docs](https://docs.rs/) and [this](https://example.com/x)"
+        );
+    }
+
+    #[test]
+    fn offsets_are_utf16_code_units() {
+        // "🚀" is two UTF-16 units and four bytes; "é" is one unit and two bytes.
+        let text = "🚀 café link";
+        let out = telegram_text_to_prompt(text, &[link(8, 4, "https://a.b/")]);
+        assert_eq!(out, "🚀 café [link](https://a.b/)");
+
+        let text = "👨‍👩‍👧 family `x`";
+        let family = "👨‍👩‍👧".encode_utf16().count();
+        let out =
+            telegram_text_to_prompt(text, &[TextEntity::new(TextEntityKind::Bold, 0, family)]);
+        assert_eq!(out, "**👨‍👩‍👧** family `x`");
+    }
+
+    #[test]
+    fn invalid_offsets_are_ignored() {
+        let text = "🚀 go";
+        // Offset 1 splits the surrogate pair; 10 is past the end.
+        let entities = [
+            TextEntity::new(TextEntityKind::Bold, 1, 2),
+            link(2, 10, "https://a.b/"),
+            TextEntity::new(TextEntityKind::Code, 3, 0),
+        ];
+        assert_eq!(telegram_text_to_prompt(text, &entities), text);
+    }
+
+    #[test]
+    fn code_and_pre_become_backticks() {
+        let text = "run cargo test then\nfn main() {}";
+        let entities = [
+            TextEntity::new(TextEntityKind::Code, 4, 10),
+            TextEntity::new(
+                TextEntityKind::Pre {
+                    language: Some("rust".to_string()),
+                },
+                20,
+                12,
+            ),
+        ];
+        assert_eq!(
+            telegram_text_to_prompt(text, &entities),
+            "run `cargo test` then\n```rust\nfn main() {}\n```"
+        );
+
+        let text = "a `tick` b";
+        let code = TextEntity::new(TextEntityKind::Code, 0, 10);
+        assert_eq!(telegram_text_to_prompt(text, &[code]), "``a `tick` b``");
+    }
+
+    #[test]
+    fn nested_entities_stay_nested() {
+        // Bold link: both entities cover the same range.
+        let text = "read this now";
+        let entities = [
+            TextEntity::new(TextEntityKind::Bold, 5, 4),
+            link(5, 4, "https://x.y/"),
+        ];
+        assert_eq!(
+            telegram_text_to_prompt(text, &entities),
+            "read **[this](https://x.y/)** now"
+        );
+
+        // Italic word inside a longer bold run.
+        let text = "very important note";
+        let entities = [
+            TextEntity::new(TextEntityKind::Bold, 0, 19),
+            TextEntity::new(TextEntityKind::Italic, 5, 9),
+        ];
+        assert_eq!(
+            telegram_text_to_prompt(text, &entities),
+            "**very _important_ note**"
+        );
+    }
+
+    #[test]
+    fn styles_skip_surrounding_whitespace() {
+        let text = "a bold b";
+        let bold = TextEntity::new(TextEntityKind::Bold, 1, 6);
+        assert_eq!(telegram_text_to_prompt(text, &[bold]), "a **bold** b");
+    }
+
+    #[test]
+    fn leading_bot_command_is_stripped() {
+        let text = "/ask what is x?";
+        let entities = [
+            TextEntity::new(TextEntityKind::BotCommand, 0, 4),
+            TextEntity::new(TextEntityKind::Code, 13, 1),
+        ];
+        assert_eq!(telegram_text_to_prompt(text, &entities), "what is `x`?");
+
+        // Only at offset 0.
+        let text = "try /help";
+        let cmd = TextEntity::new(TextEntityKind::BotCommand, 4, 5);
+        assert_eq!(telegram_text_to_prompt(text, &[cmd]), "try /help");
+    }
+}
@@ -23,6 +23,67 @@ pub enum Error {
 
     #[error("external error: {0}")]
     External(String),
+
+    /// The message to edit or delete no longer exists (e.g. the user deleted it).
+    #[error("message gone: {0}")]
+    MessageGone(String),
+
+    /// The recipient can't be messaged: they blocked the bot, never started it, or deleted their
+    /// account. Holds a short reason ("blocked by user").
+    #[error("recipient unreachable: {0}")]
+    Unreachable(String),
+
+    /// The run was cancelled on purpose; see [`CancelReason`].
+    #[error("cancelled: {0}")]
+    Cancelled(CancelReason),
+
+    /// The model process exited without producing a result (a crash, worth one retry).
+    #[error("model crashed: {0}")]
+    ModelCrashed(String),
+
+    /// The provider failed the turn (connection, auth, overload) and the CLI reported that
+    /// failure as its result text; see [`crate::model::types::ProviderErrorPatterns`].
+    #[error("provider error: {0}")]
+    Provider(String),
+}
+
+/// Why a run was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CancelReason {
+    /// `/stop`, `/new`, or a button answer that replaces the running query.
+    UserStop,
+    /// A `!` message or an edited prompt replaced the run.
+    Interrupt,
+    /// The run paused to wait for an `ask_user` answer.
+    AskUser,
+    /// A tool call was blocked by a safety check.
+    SafetyBlock,
+    /// The run crossed its spending limit.
+    Budget,
+    /// The bot is shutting down or restarting.
+    Shutdown,
+    /// The run took longer than `QUERY_TIMEOUT_MS`.
+    Timeout,
+}
+
+impl CancelReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UserStop => "stopped by user",
+            Self::Interrupt => "interrupted",
+            Self::AskUser => "waiting for ask_user answer",
+            Self::SafetyBlock => "blocked by safety check",
+            Self::Budget => "budget exceeded",
+            Self::Shutdown => "shutdown",
+            Self::Timeout => "timed out",
+        }
+    }
+}
+
+impl std::fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -2,6 +2,9 @@
 
 use regex::Regex;
 
+use crate::model::types::TokenUsage;
+use crate::theme::StatusTheme;
+
 /// Escape HTML special characters for Telegram HTML parse mode.
 pub fn escape_html(text: &str) -> String {
     text.replace('&', "&amp;")
@@ -93,6 +96,140 @@ pub fn convert_markdown_to_html(input: &str) -> String {
     text
 }
 
+/// [`convert_markdown_to_html`] for text that only grows, like a streamed segment.
+///
+/// The text is cut into chunks at blank lines where no code fence, inline code span or link is
+/// left open. Converting each chunk on its own and joining the results with the blank line gives
+/// exactly the HTML of converting the whole text, so finished chunks are converted once and
+/// cached and each update only converts what follows the last one. Whenever the pieces would not
+/// join cleanly (e.g. the text currently ends in a blank line) the whole text is converted.
+#[derive(Clone, Debug)]
+pub struct IncrementalHtml {
+    convert: fn(&str) -> String,
+    /// Source and HTML of the finished chunks, in order.
+    chunks: Vec<(String, String)>,
+}
+
+impl Default for IncrementalHtml {
+    fn default() -> Self {
+        Self::with_converter(convert_markdown_to_html)
+    }
+}
+
+impl IncrementalHtml {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert with `convert` instead of [`convert_markdown_to_html`] (tests count calls).
+    pub fn with_converter(convert: fn(&str) -> String) -> Self {
+        Self {
+            convert,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// The HTML of `input`, identical to `convert_markdown_to_html(input)`.
+    pub fn convert(&mut self, input: &str) -> String {
+        // Keep the cached chunks the input still starts with.
+        let mut offset = 0;
+        let mut keep = 0;
+        for (src, _) in &self.chunks {
+            let rest = &input[offset..];
+            if !rest.starts_with(src.as_str()) || chunk_split(rest, src.len()).is_none() {
+                break;
+            }
+            offset += src.len() + 2;
+            keep += 1;
+        }
+        self.chunks.truncate(keep);
+
+        // Cut new chunks from the rest.
+        let mut search = offset;
+        while let Some(rel) = input[search..].find("\n\n") {
+            let end = search + rel;
+            search = end + 1;
+            let Some(chunk) = chunk_split(&input[offset..], end - offset) else {
+                continue;
+            };
+            if !chunk_is_closed(chunk) {
+                continue;
+            }
+            let html = (self.convert)(chunk);
+            if !joins_cleanly(&html) {
+                continue;
+            }
+            self.chunks.push((chunk.to_string(), html));
+            offset = end + 2;
+            search = offset;
+        }
+
+        let tail = (self.convert)(&input[offset..]);
+        if self.chunks.is_empty() {
+            return tail;
+        }
+        if tail.is_empty() || tail.starts_with('\n') || has_numbered_placeholder(&tail) {
+            return (self.convert)(input);
+        }
+        let mut out = String::with_capacity(input.len() + input.len() / 4);
+        for (_, html) in &self.chunks {
+            out.push_str(html);
+            out.push_str("\n\n");
+        }
+        out.push_str(&tail);
+        out
+    }
+}
+
+/// `text[..at]` when `at` is a chunk boundary: exactly one blank line (`\n\n`) follows, the
+/// chunk does not end in a line break and more text follows the blank line.
+fn chunk_split(text: &str, at: usize) -> Option<&str> {
+    let chunk = text.get(..at).filter(|c| !c.is_empty())?;
+    let after = text[at..].strip_prefix("\n\n")?;
+    let ends_clean = !chunk.ends_with(['\n', '\r']);
+    let continues = after.chars().next().is_some_and(|c| c != '\n');
+    (ends_clean && continues).then_some(chunk)
+}
+
+/// No fence, inline code span or `[text](url)` link opened in `chunk` could close after it.
+fn chunk_is_closed(chunk: &str) -> bool {
+    let (text, _) = extract_code_blocks(chunk);
+    if text.contains("```") {
+        return false;
+    }
+    let (text, _) = extract_inline_codes(&text);
+    if text.contains('`') {
+        return false;
+    }
+    // Mirrors the link pattern: `[`, text up to the first `]`, then `(` and text up to `)`.
+    let mut rest = text.as_str();
+    while let Some(open) = rest.find('[') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find(']') else {
+            return false;
+        };
+        if close > 0 && rest[close + 1..].starts_with('(') && !rest[close + 2..].contains(')') {
+            return false;
+        }
+    }
+    true
+}
+
+/// Converted chunks are joined with a blank line; one that is empty or starts or ends with a
+/// line break would merge with its neighbours differently than in the full conversion.
+fn joins_cleanly(html: &str) -> bool {
+    !html.is_empty()
+        && !html.starts_with('\n')
+        && !html.ends_with('\n')
+        && !has_numbered_placeholder(html)
+}
+
+/// A fence inside an inline code span is never restored and shows its placeholder, whose number
+/// counts the blocks before it in the whole text, not just in this chunk.
+fn has_numbered_placeholder(html: &str) -> bool {
+    html.contains("\0CODEBLOCK")
+}
+
 fn extract_code_blocks(input: &str) -> (String, Vec<String>) {
     let mut blocks = Vec::new();
     let mut out = String::new();
@@ -315,28 +452,12 @@ fn code(text: &str) -> String {
 }
 
 /// Format tool use for display in Telegram (HTML mode).
-pub fn format_tool_status(tool_name: &str, tool_input: &serde_json::Value) -> String {
-    let emoji_map = [
-        ("Read", "📖"),
-        ("Write", "📝"),
-        ("Edit", "✏️"),
-        ("Bash", "▶️"),
-        ("Glob", "🔍"),
-        ("Grep", "🔎"),
-        ("WebSearch", "🔍"),
-        ("WebFetch", "🌐"),
-        ("Task", "🎯"),
-        ("TodoWrite", "📋"),
-        ("mcp__", "🔧"),
-    ];
-
-    let mut emoji = "🔧";
-    for (k, v) in emoji_map {
-        if tool_name.contains(k) {
-            emoji = v;
-            break;
-        }
-    }
+pub fn format_tool_status(
+    theme: &StatusTheme,
+    tool_name: &str,
+    tool_input: &serde_json::Value,
+) -> String {
+    let emoji = theme.tool_icon(tool_name);
 
     let get = |k: &str| tool_input.get(k).and_then(|v| v.as_str()).unwrap_or("");
 
@@ -347,7 +468,7 @@ pub fn format_tool_status(tool_name: &str, tool_input: &serde_json::Value) -> St
             ".jpg", ".jpeg", ".png", ".gif", ".webp", ".bmp", ".svg", ".ico",
         ];
         if image_exts.iter().any(|ext| lower.ends_with(ext)) {
-            return "👀 Viewing".to_string();
+            return format!("{} Viewing", theme.viewing);
         }
         return format!("{emoji} Reading {}", code(&shorten_path(file_path)));
     }
@@ -419,9 +540,312 @@ pub fn format_tool_status(tool_name: &str, tool_input: &serde_json::Value) -> St
     format!("{emoji} {}", escape_html(tool_name))
 }
 
+/// What a tool status does, without its subject: statuses with equal actions can share one
+/// message (see [`format_tool_group`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ToolAction {
+    /// Emoji and verb, e.g. `📖 Reading`.
+    pub label: String,
+    /// Plural of what the subjects are, e.g. `files`.
+    pub noun: &'static str,
+}
+
+/// Subjects listed in a grouped status before the rest is elided.
+const TOOL_GROUP_SHOWN: usize = 3;
+
+/// Split a groupable tool status into its action and subject (HTML); `None` for tools that are
+/// always shown on their own. `"{label} {subject}"` equals [`format_tool_status`].
+pub fn tool_status_parts(
+    theme: &StatusTheme,
+    tool_name: &str,
+    tool_input: &serde_json::Value,
+) -> Option<(ToolAction, String)> {
+    let get = |k: &str| tool_input.get(k).and_then(|v| v.as_str()).unwrap_or("");
+    let (verb, noun, subject) = match tool_name {
+        "Read" => {
+            if format_tool_status(theme, tool_name, tool_input).ends_with(" Viewing") {
+                return None;
+            }
+            ("Reading", "files", code(&shorten_path(get("file_path"))))
+        }
+        "Write" => ("Writing", "files", code(&shorten_path(get("file_path")))),
+        "Edit" => ("Editing", "files", code(&shorten_path(get("file_path")))),
+        "Glob" => (
+            "Finding",
+            "patterns",
+            code(&truncate_one_line(get("pattern"), 50)),
+        ),
+        _ => return None,
+    };
+    let action = ToolAction {
+        label: format!("{} {verb}", theme.tool_icon(tool_name)),
+        noun,
+    };
+    Some((action, subject))
+}
+
+/// One status line for several calls of the same action, e.g.
+/// `📖 Reading 4 files: a.rs, b.rs, c.rs, …`. A single subject renders like a plain status.
+pub fn format_tool_group(theme: &StatusTheme, action: &ToolAction, subjects: &[String]) -> String {
+    match subjects {
+        [] => action.label.clone(),
+        [one] => format!("{} {one}", action.label),
+        _ => {
+            let mut shown = subjects[..subjects.len().min(TOOL_GROUP_SHOWN)].join(", ");
+            if subjects.len() > TOOL_GROUP_SHOWN {
+                shown.push_str(", ");
+                shown.push_str(theme.ellipsis);
+            }
+            format!(
+                "{} {} {}: {shown}",
+                action.label,
+                subjects.len(),
+                action.noun
+            )
+        }
+    }
+}
+
+const TOOL_OUTCOME_SUMMARY_LEN: usize = 200;
+const TOOL_OUTCOME_FULL_LEN: usize = 3000;
+
+/// Format a tool result as a compact suffix for the tool status message.
+///
+/// Failures show the exit code (when the CLI reports one) and the first line(s) of output;
+/// `full` includes the complete (truncated) output for successes and failures alike.
+pub fn format_tool_outcome(
+    theme: &StatusTheme,
+    is_error: bool,
+    output: &str,
+    full: bool,
+) -> String {
+    let output = output.trim();
+    let first_line = output.lines().next().unwrap_or("");
+    let exit_code = first_line
+        .trim_start_matches("Error: ")
+        .strip_prefix("Exit code ")
+        .and_then(|n| n.trim().parse::<i32>().ok());
+    let detail = if exit_code.is_some() {
+        output[first_line.len()..].trim()
+    } else {
+        output
+    };
+
+    let prefix = match (is_error, exit_code) {
+        (false, _) => theme.done.to_string(),
+        (true, Some(code)) => format!("{} exit {code}", theme.failed),
+        (true, None) => theme.failed.to_string(),
+    };
+
+    if full {
+        if detail.is_empty() {
+            return prefix;
+        }
+        let body = escape_html(detail);
+        return format!(
+            "{prefix}\n<pre>{}</pre>",
+            truncate_escaped(&body, TOOL_OUTCOME_FULL_LEN)
+        );
+    }
+
+    if !is_error || detail.is_empty() {
+        return prefix;
+    }
+    let summary = escape_html(&detail.replace('\n', " "));
+    let sep = if exit_code.is_some() { ":" } else { "" };
+    format!(
+        "{prefix}{sep} {}",
+        truncate_escaped(&summary, TOOL_OUTCOME_SUMMARY_LEN).trim_end()
+    )
+}
+
+/// Cut already-escaped `html` to at most `max_chars` characters, never inside an entity.
+fn truncate_escaped(html: &str, max_chars: usize) -> &str {
+    let Some((cut, _)) = html.char_indices().nth(max_chars) else {
+        return html;
+    };
+    let head = &html[..cut];
+    match head.rfind('&') {
+        Some(amp) if !head[amp..].contains(';') => &head[..amp],
+        _ => head,
+    }
+}
+
+/// A fenced (```) code block located in Markdown text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FencedBlock {
+    /// First word of the fence info string (empty when absent).
+    pub lang: String,
+    pub code: String,
+    /// Byte range of the whole block, both fence lines included (trailing newline excluded).
+    pub range: std::ops::Range<usize>,
+}
+
+/// Closed fenced code blocks in `text`, in order. An unterminated fence (still streaming) is
+/// not reported.
+pub fn find_fenced_blocks(text: &str) -> Vec<FencedBlock> {
+    let mut out = Vec::new();
+    // (block start, code start, lang) of the currently open fence.
+    let mut open: Option<(usize, usize, String)> = None;
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        let start = pos;
+        pos += line.len();
+        let trimmed = line.trim();
+        match open.take() {
+            None => {
+                if let Some(info) = trimmed.strip_prefix("```") {
+                    let lang = info.split_whitespace().next().unwrap_or("").to_string();
+                    open = Some((start, pos, lang));
+                }
+            }
+            Some((block_start, code_start, lang)) => {
+                if trimmed.starts_with("```") && trimmed.trim_start_matches('`').is_empty() {
+                    let code = text[code_start..start]
+                        .strip_suffix('\n')
+                        .unwrap_or(&text[code_start..start])
+                        .to_string();
+                    let end = pos - usize::from(line.ends_with('\n'));
+                    out.push(FencedBlock {
+                        lang,
+                        code,
+                        range: block_start..end,
+                    });
+                } else {
+                    open = Some((block_start, code_start, lang));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// File extension for a fence language tag (`txt` when unknown).
+pub fn code_file_extension(lang: &str) -> &'static str {
+    match lang.to_lowercase().as_str() {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "javascript" | "js" | "node" => "js",
+        "typescript" | "ts" => "ts",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "bash" | "sh" | "shell" | "zsh" => "sh",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "html" => "html",
+        "css" => "css",
+        "go" | "golang" => "go",
+        "java" => "java",
+        "kotlin" | "kt" => "kt",
+        "swift" => "swift",
+        "c" => "c",
+        "cpp" | "c++" | "cc" => "cpp",
+        "csharp" | "cs" | "c#" => "cs",
+        "ruby" | "rb" => "rb",
+        "php" => "php",
+        "sql" => "sql",
+        "markdown" | "md" => "md",
+        "xml" => "xml",
+        "diff" | "patch" => "diff",
+        "dockerfile" | "docker" => "dockerfile",
+        _ => "txt",
+    }
+}
+
+/// Compact token count: `950`, `2.1k`, `1.3M` (counts below 100 stay exact).
+pub fn format_token_count(n: u64) -> String {
+    match n {
+        0..=99 => n.to_string(),
+        // Round-up boundary: 999_950 renders as `1.0M`, not `1000.0k`.
+        100..=999_949 => format!("{:.1}k", n as f64 / 1_000.0),
+        _ => format!("{:.1}M", n as f64 / 1_000_000.0),
+    }
+}
+
+/// Coarse age with its two largest units: `2d 3h`, `5h 12m`, `14m` (under a minute: `0m`).
+pub fn format_idle(d: std::time::Duration) -> String {
+    let mins = d.as_secs() / 60;
+    let (days, hours, mins) = (mins / 1440, mins / 60 % 24, mins % 60);
+    match (days, hours) {
+        (0, 0) => format!("{mins}m"),
+        (0, _) => format!("{hours}h {mins}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+/// Turn length with its two largest units: `42s`, `1m42s`, `1h05m`.
+pub fn format_turn_duration(d: std::time::Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+/// An estimated cost (HTML): `~$0.04`, or `<$0.01` below a cent.
+fn format_cost_brief(cost: f64) -> String {
+    if cost < 0.01 {
+        "&lt;$0.01".to_string()
+    } else {
+        format!("~${cost:.2}")
+    }
+}
+
+/// Usage suffix for the progress spinner: `↑2.1k ↓0.8k tok · ~$0.04`, with `cost_usd` at the
+/// model's rates.
+pub fn format_usage_brief(theme: &StatusTheme, usage: &TokenUsage, cost_usd: f64) -> String {
+    let cost = format_cost_brief(cost_usd);
+    format!(
+        "{}{} {}{} tok {} {cost}",
+        theme.up,
+        format_token_count(usage.prompt_tokens()),
+        theme.down,
+        format_token_count(usage.output_tokens),
+        theme.separator
+    )
+}
+
+/// One-line footer after a turn (`TURN_SUMMARY`), e.g.
+/// `⏱ 1m42s · 3 tools · ↑4.1k ↓1.2k tok · ~$0.06 · session a1b2c3d4`. `usage` comes with its
+/// cost at the model's rates; without it (a cancelled turn) tokens and cost are left out.
+pub fn format_turn_summary(
+    theme: &StatusTheme,
+    duration: std::time::Duration,
+    tools: usize,
+    usage: Option<(&TokenUsage, f64)>,
+    session_id: Option<&str>,
+) -> String {
+    let mut parts = vec![format!(
+        "{}{}",
+        theme.deco("⏱"),
+        format_turn_duration(duration)
+    )];
+    if tools > 0 {
+        parts.push(format!("{tools} tool{}", if tools == 1 { "" } else { "s" }));
+    }
+    if let Some((u, cost)) = usage {
+        parts.push(format!(
+            "{}{} {}{} tok",
+            theme.up,
+            format_token_count(u.prompt_tokens()),
+            theme.down,
+            format_token_count(u.output_tokens)
+        ));
+        parts.push(format_cost_brief(cost));
+    }
+    if let Some(id) = session_id {
+        let short: String = id.chars().take(8).collect();
+        parts.push(format!("session {}", escape_html(&short)));
+    }
+    parts.join(&format!(" {} ", theme.separator))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::theme::UNICODE;
 
     #[test]
     fn escapes_html() {
@@ -453,9 +877,324 @@ mod tests {
         assert_eq!(html, r#"<a href="https://example.com">x</a>"#);
     }
 
+    thread_local! {
+        static CONVERTED: std::cell::Cell<(usize, usize)> = const { std::cell::Cell::new((0, 0)) };
+    }
+
+    /// `convert_markdown_to_html`, counting calls and input bytes per thread.
+    fn counted(md: &str) -> String {
+        CONVERTED.with(|c| {
+            let (calls, bytes) = c.get();
+            c.set((calls + 1, bytes + md.len()));
+        });
+        convert_markdown_to_html(md)
+    }
+
+    /// Streams `doc` in `step`-byte updates and checks every update against the full conversion.
+    fn assert_incremental_matches(doc: &str, step: usize) {
+        let mut inc = IncrementalHtml::with_converter(counted);
+        let mut end = 0;
+        while end < doc.len() {
+            end = (end + step).min(doc.len());
+            while !doc.is_char_boundary(end) {
+                end += 1;
+            }
+            let prefix = &doc[..end];
+            assert_eq!(
+                inc.convert(prefix),
+                convert_markdown_to_html(prefix),
+                "diverged after {end} bytes of {doc:?}"
+            );
+        }
+    }
+
+    fn doc_fixtures() -> Vec<String> {
+        fn texts(v: &serde_json::Value, out: &mut Vec<String>) {
+            match v {
+                serde_json::Value::Object(map) => {
+                    for (k, v) in map {
+                        match v.as_str() {
+                            Some(t) if k == "text" || k == "result" => out.push(t.to_string()),
+                            _ => texts(v, out),
+                        }
+                    }
+                }
+                serde_json::Value::Array(items) => items.iter().for_each(|v| texts(v, out)),
+                _ => {}
+            }
+        }
+
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../..");
+        let mut docs = vec![std::fs::read_to_string(root.join("README.md")).unwrap()];
+        for dir in ["docs/rust-port", "docs/rust-port/fixtures"] {
+            let mut paths: Vec<_> = std::fs::read_dir(root.join(dir))
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect();
+            paths.sort();
+            for path in paths {
+                let txt = std::fs::read_to_string(&path).unwrap_or_default();
+                match path.extension().and_then(|e| e.to_str()) {
+                    Some("md") => docs.push(txt),
+                    Some("jsonl") => {
+                        for line in txt.lines().filter(|l| !l.trim().is_empty()) {
+                            texts(&serde_json::from_str(line).unwrap(), &mut docs);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        docs
+    }
+
+    #[test]
+    fn incremental_conversion_matches_full_conversion_on_docs() {
+        let docs = doc_fixtures();
+        assert!(docs.len() > 5, "{} docs", docs.len());
+        for doc in &docs {
+            assert_incremental_matches(doc, 61);
+        }
+
+        // A long answer re-converts only its last paragraph per update.
+        let readme = &docs[0];
+        CONVERTED.with(|c| c.set((0, 0)));
+        assert_incremental_matches(readme, 200);
+        let (calls, incremental_bytes) = CONVERTED.with(|c| c.get());
+        let steps = readme.len().div_ceil(200);
+        let full_bytes: usize = (1..=steps).map(|i| (i * 200).min(readme.len())).sum();
+        assert!(calls >= steps);
+        assert!(
+            incremental_bytes * 5 < full_bytes,
+            "converted {incremental_bytes} bytes incrementally vs {full_bytes} in full"
+        );
+    }
+
+    #[test]
+    fn incremental_conversion_matches_full_conversion_on_random_markdown() {
+        const PIECES: &[&str] = &[
+            "word",
+            " ",
+            "**",
+            "*",
+            "_",
+            "__",
+            "`",
+            "```",
+            "```rust\n",
+            "[",
+            "]",
+            "(",
+            ")",
+            "](https://x.y)",
+            "# ",
+            "## ",
+            "> ",
+            ">",
+            "- ",
+            "* ",
+            "---",
+            "***",
+            "\n",
+            "\n\n",
+            "\n\n\n",
+            "\r\n",
+            "<",
+            "&",
+            "\"",
+            ".",
+            "é",
+            "\u{0}",
+        ];
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..100 {
+            let len = 1 + next() % 60;
+            let doc: String = (0..len)
+                .map(|_| PIECES[(next() % PIECES.len() as u64) as usize])
+                .collect();
+            assert_incremental_matches(&doc, 1);
+        }
+    }
+
+    #[test]
+    fn finds_closed_fenced_blocks_with_ranges() {
+        let md = "intro\n```rust title\nfn main() {}\n```\nmid\n```\nplain\n```\n```py\nopen";
+        let blocks = find_fenced_blocks(md);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].lang, "rust");
+        assert_eq!(blocks[0].code, "fn main() {}");
+        assert_eq!(
+            &md[blocks[0].range.clone()],
+            "```rust title\nfn main() {}\n```"
+        );
+        assert_eq!(blocks[1].lang, "");
+        assert_eq!(blocks[1].code, "plain");
+    }
+
+    #[test]
+    fn code_file_extensions() {
+        assert_eq!(code_file_extension("Rust"), "rs");
+        assert_eq!(code_file_extension("typescript"), "ts");
+        assert_eq!(code_file_extension("bash"), "sh");
+        assert_eq!(code_file_extension(""), "txt");
+        assert_eq!(code_file_extension("brainfuck"), "txt");
+    }
+
+    #[test]
+    fn token_counts_are_compact() {
+        assert_eq!(format_token_count(0), "0");
+        assert_eq!(format_token_count(99), "99");
+        assert_eq!(format_token_count(800), "0.8k");
+        assert_eq!(format_token_count(2_130), "2.1k");
+        assert_eq!(format_token_count(999_949), "999.9k");
+        assert_eq!(format_token_count(999_999), "1.0M");
+        assert_eq!(format_token_count(1_260_000), "1.3M");
+    }
+
+    #[test]
+    fn idle_times_use_two_units() {
+        use std::time::Duration;
+        assert_eq!(format_idle(Duration::from_secs(59)), "0m");
+        assert_eq!(format_idle(Duration::from_secs(14 * 60 + 5)), "14m");
+        assert_eq!(
+            format_idle(Duration::from_secs(5 * 3600 + 12 * 60)),
+            "5h 12m"
+        );
+        assert_eq!(
+            format_idle(Duration::from_secs(51 * 3600 + 59 * 60)),
+            "2d 3h"
+        );
+    }
+
+    #[test]
+    fn usage_brief_shows_prompt_output_and_cost() {
+        let usage = TokenUsage {
+            input_tokens: 100,
+            output_tokens: 800,
+            cache_read_input_tokens: 2_000,
+            cache_creation_input_tokens: 0,
+        };
+        assert_eq!(
+            format_usage_brief(&UNICODE, &usage, 0.0129),
+            "↑2.1k ↓0.8k tok · ~$0.01"
+        );
+        assert_eq!(
+            format_usage_brief(&UNICODE, &TokenUsage::default(), 0.0),
+            "↑0 ↓0 tok · &lt;$0.01"
+        );
+    }
+
+    #[test]
+    fn turn_summary_shows_what_the_turn_reported() {
+        let usage = TokenUsage {
+            input_tokens: 100,
+            output_tokens: 1_200,
+            cache_read_input_tokens: 4_000,
+            cache_creation_input_tokens: 0,
+        };
+        assert_eq!(
+            format_turn_summary(
+                &UNICODE,
+                std::time::Duration::from_secs(102),
+                3,
+                Some((&usage, 0.0612)),
+                Some("a1b2c3d4-e5f6")
+            ),
+            "⏱ 1m42s · 3 tools · ↑4.1k ↓1.2k tok · ~$0.06 · session a1b2c3d4"
+        );
+        // Cancelled before any usage or session id was reported.
+        assert_eq!(
+            format_turn_summary(
+                &crate::theme::ASCII,
+                std::time::Duration::from_secs(7),
+                1,
+                None,
+                None
+            ),
+            "7s | 1 tool"
+        );
+        assert_eq!(
+            format_turn_duration(std::time::Duration::from_secs(3_900)),
+            "1h05m"
+        );
+    }
+
     #[test]
     fn tool_status_read_image() {
         let v = serde_json::json!({"file_path":"/tmp/a.png"});
-        assert_eq!(format_tool_status("Read", &v), "👀 Viewing");
+        assert_eq!(format_tool_status(&UNICODE, "Read", &v), "👀 Viewing");
+    }
+
+    #[test]
+    fn tool_groups_list_their_subjects() {
+        let read =
+            |p: &str| tool_status_parts(&UNICODE, "Read", &serde_json::json!({ "file_path": p }));
+        let (action, first) = read("/src/a.rs").unwrap();
+        assert_eq!(
+            format_tool_group(&UNICODE, &action, std::slice::from_ref(&first)),
+            format_tool_status(
+                &UNICODE,
+                "Read",
+                &serde_json::json!({"file_path": "/src/a.rs"})
+            )
+        );
+        assert!(read("/tmp/a.png").is_none());
+        assert!(
+            tool_status_parts(&UNICODE, "Bash", &serde_json::json!({"command": "ls"})).is_none()
+        );
+
+        let subjects: Vec<String> = ["a.rs", "b.rs", "c.rs", "d.rs"]
+            .iter()
+            .map(|f| read(&format!("/src/{f}")).unwrap().1)
+            .collect();
+        assert_eq!(
+            format_tool_group(&UNICODE, &action, &subjects),
+            "📖 Reading 4 files: <code>src/a.rs</code>, <code>src/b.rs</code>, <code>src/c.rs</code>, …"
+        );
+        let (edit, _) =
+            tool_status_parts(&UNICODE, "Edit", &serde_json::json!({"file_path": "x"})).unwrap();
+        assert_ne!(edit, action);
+    }
+
+    #[test]
+    fn tool_outcome_compact_and_full() {
+        let out = "Exit code 1\nnpm ERR! <test> failed";
+        assert_eq!(
+            format_tool_outcome(&UNICODE, true, out, false),
+            "❌ exit 1: npm ERR! &lt;test&gt; failed"
+        );
+        assert_eq!(
+            format_tool_outcome(&UNICODE, false, "lots of output", false),
+            "✅"
+        );
+        assert_eq!(
+            format_tool_outcome(&UNICODE, false, "ok\n", true),
+            "✅\n<pre>ok</pre>"
+        );
+        assert_eq!(
+            format_tool_outcome(&UNICODE, true, "boom", false),
+            "❌ boom"
+        );
+    }
+
+    #[test]
+    fn tool_outcome_is_cut_after_escaping() {
+        let full = format_tool_outcome(&UNICODE, false, &"<".repeat(5000), true);
+        let body = full
+            .strip_prefix("✅\n<pre>")
+            .and_then(|b| b.strip_suffix("</pre>"))
+            .unwrap();
+        assert!(body.chars().count() <= TOOL_OUTCOME_FULL_LEN);
+        assert!(body.ends_with("&lt;"), "{}", &body[body.len() - 10..]);
+
+        let summary = format_tool_outcome(&UNICODE, true, &format!("é{}", "&".repeat(500)), false);
+        assert!(summary.chars().count() <= "❌ ".chars().count() + TOOL_OUTCOME_SUMMARY_LEN);
+        assert!(summary.ends_with("&amp;"));
     }
 }
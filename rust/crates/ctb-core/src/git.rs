@@ -0,0 +1,326 @@
+//! `/diff` and `/commit`: review and commit the working directory's changes from chat.
+//!
+//! `git` is run directly (no shell), but the equivalent command line is first checked with
+//! [`check_command_safety`] against the bot's blocked patterns and command allowlist, so they are
+//! refused wherever the same Bash tool call would be. A commit message is only ever an argument,
+//! never shell text, so it is left out of that check.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use tokio::process::Command;
+
+use crate::{
+    config::Config,
+    security::{check_command_safety, PathPolicy},
+    Error, Result,
+};
+
+/// How long a single `git` invocation may take.
+pub const GIT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Patch bytes kept by `/diff`; longer patches are cut at a line boundary.
+pub const DIFF_MAX_BYTES: usize = 256 * 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct GitDiff {
+    /// `git status --short`, which also lists untracked files.
+    pub status: String,
+    /// `git diff --stat` against HEAD.
+    pub stat: String,
+    pub patch: String,
+    /// `patch` was cut at [`DIFF_MAX_BYTES`].
+    pub truncated: bool,
+}
+
+impl GitDiff {
+    /// Nothing to commit (no tracked changes, no untracked files).
+    pub fn is_empty(&self) -> bool {
+        self.status.trim().is_empty()
+    }
+}
+
+/// The working directory plus the rules `git` commands are checked against.
+#[derive(Clone, Debug)]
+pub struct WorkTree {
+    dir: PathBuf,
+    paths: PathPolicy,
+    blocked_patterns: Vec<String>,
+    allowed_prefixes: Vec<String>,
+}
+
+impl WorkTree {
+    /// `cfg.claude_working_dir`, checked against `paths` (usually the chat's path policy).
+    pub fn from_config(cfg: &Config, paths: PathPolicy) -> Self {
+        Self {
+            dir: cfg.claude_working_dir.clone(),
+            paths,
+            blocked_patterns: cfg.blocked_patterns.clone(),
+            allowed_prefixes: cfg.allowed_command_prefixes.clone(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Status, stat and patch against HEAD, optionally limited to `path`.
+    pub async fn diff(&self, path: Option<&str>) -> Result<GitDiff> {
+        let path = path.map(str::trim).filter(|p| !p.is_empty());
+        if let Some(p) = path {
+            if p.starts_with('-') {
+                return Err(Error::InvalidPath {
+                    path: PathBuf::from(p),
+                    reason: "must not start with '-'".to_string(),
+                });
+            }
+            if !self
+                .paths
+                .is_path_allowed(&self.dir.join(p).to_string_lossy())
+            {
+                return Err(Error::Security(format!("Path outside allowed paths: {p}")));
+            }
+        }
+        self.check_safety(&format!(
+            "git diff HEAD -- {}",
+            shell_quote(path.unwrap_or("."))
+        ))?;
+        self.ensure_repo().await?;
+
+        let mut diff_args = vec!["diff"];
+        if self.has_head().await {
+            diff_args.push("HEAD");
+        }
+        let pathspec = ["--", path.unwrap_or(".")];
+
+        let status = self
+            .git(&[&["status", "--short"][..], &pathspec].concat())
+            .await?;
+        let stat = self
+            .git(&[&diff_args[..], &["--stat"], &pathspec].concat())
+            .await?;
+        let mut patch = self.git(&[&diff_args[..], &pathspec].concat()).await?;
+        let truncated = truncate_at_line(&mut patch, DIFF_MAX_BYTES);
+        Ok(GitDiff {
+            status,
+            stat,
+            patch,
+            truncated,
+        })
+    }
+
+    /// Stage everything and commit; returns the short hash, or `None` when the tree is clean.
+    pub async fn commit(&self, message: &str) -> Result<Option<String>> {
+        let message = message.trim();
+        if message.is_empty() {
+            return Err(Error::External("Commit message is empty".to_string()));
+        }
+        if !self.paths.is_path_allowed(&self.dir.to_string_lossy()) {
+            return Err(Error::Security(format!(
+                "Working directory is outside allowed paths: {}",
+                self.dir.display()
+            )));
+        }
+        self.check_safety("git add -A && git commit")?;
+        self.ensure_repo().await?;
+
+        if self
+            .git(&["status", "--porcelain"])
+            .await?
+            .trim()
+            .is_empty()
+        {
+            return Ok(None);
+        }
+        self.git(&["add", "-A"]).await?;
+        self.git(&["commit", "--quiet", "-m", message]).await?;
+        let hash = self.git(&["rev-parse", "--short", "HEAD"]).await?;
+        Ok(Some(hash.trim().to_string()))
+    }
+
+    fn check_safety(&self, command: &str) -> Result<()> {
+        let (ok, reason) = check_command_safety(
+            command,
+            &self.blocked_patterns,
+            &self.allowed_prefixes,
+            &self.paths,
+        );
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::Security(reason))
+        }
+    }
+
+    async fn ensure_repo(&self) -> Result<()> {
+        match self.git(&["rev-parse", "--is-inside-work-tree"]).await {
+            Ok(out) if out.trim() == "true" => Ok(()),
+            _ => Err(Error::InvalidPath {
+                path: self.dir.clone(),
+                reason: "not a git repository".to_string(),
+            }),
+        }
+    }
+
+    async fn has_head(&self) -> bool {
+        self.git(&["rev-parse", "--verify", "--quiet", "HEAD"])
+            .await
+            .is_ok()
+    }
+
+    async fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.dir)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let out = tokio::time::timeout(GIT_TIMEOUT, output)
+            .await
+            .map_err(|_| Error::External(format!("git {} timed out", args[0])))?
+            .map_err(|e| Error::External(format!("failed to run git: {e}")))?;
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            return Err(Error::External(format!(
+                "git {} failed: {}",
+                args[0],
+                stderr.trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Cut `text` to at most `max` bytes, ending on a line break. Returns whether it was cut.
+fn truncate_at_line(text: &mut String, max: usize) -> bool {
+    if text.len() <= max {
+        return false;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let end = text[..end].rfind('\n').map_or(end, |i| i + 1);
+    text.truncate(end);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_repo(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ctb-git-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::canonicalize(dir).unwrap()
+    }
+
+    fn work_tree(dir: &Path) -> WorkTree {
+        WorkTree {
+            dir: dir.to_path_buf(),
+            paths: PathPolicy {
+                allowed_paths: vec![dir.to_path_buf()],
+                temp_paths: vec![],
+                read_only_paths: vec![],
+                home_dir: None,
+                base_dir: Some(dir.to_path_buf()),
+            },
+            blocked_patterns: vec!["rm -rf /".to_string()],
+            allowed_prefixes: vec![],
+        }
+    }
+
+    async fn init(tree: &WorkTree) {
+        tree.git(&["init", "--quiet"]).await.unwrap();
+        tree.git(&["config", "user.name", "Test"]).await.unwrap();
+        tree.git(&["config", "user.email", "test@example.com"])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_outside_a_repository() {
+        let dir = scratch_repo("norepo");
+        let tree = work_tree(&dir);
+        let err = tree.diff(None).await.unwrap_err();
+        assert!(err.to_string().contains("not a git repository"), "{err}");
+        assert!(matches!(
+            tree.commit("wip").await,
+            Err(Error::InvalidPath { .. })
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn diff_then_commit_round_trip() {
+        let dir = scratch_repo("roundtrip");
+        let tree = work_tree(&dir);
+        init(&tree).await;
+
+        assert!(tree.diff(None).await.unwrap().is_empty());
+        assert_eq!(tree.commit("nothing").await.unwrap(), None);
+
+        std::fs::write(dir.join("notes.txt"), "hello\n").unwrap();
+        let diff = tree.diff(None).await.unwrap();
+        assert!(diff.status.contains("?? notes.txt"), "{diff:?}");
+        let hash = tree.commit("Add notes").await.unwrap().expect("committed");
+        assert!(tree
+            .git(&["log", "-1", "--format=%h %s"])
+            .await
+            .unwrap()
+            .starts_with(&format!("{hash} Add notes")));
+
+        std::fs::write(dir.join("notes.txt"), "hello\nworld\n").unwrap();
+        let diff = tree.diff(Some("notes.txt")).await.unwrap();
+        assert!(diff.stat.contains("notes.txt | 1 +"), "{diff:?}");
+        assert!(diff.patch.contains("+world"));
+        assert!(!diff.truncated);
+
+        // Messages are arguments, so text matching a blocked pattern is fine.
+        tree.commit("Document why `rm -rf /` is blocked; tidy notes")
+            .await
+            .unwrap()
+            .expect("committed");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn rejects_unsafe_arguments_and_paths() {
+        let dir = scratch_repo("unsafe");
+        let tree = work_tree(&dir);
+        init(&tree).await;
+
+        assert!(matches!(
+            tree.diff(Some("--output=/tmp/x")).await,
+            Err(Error::InvalidPath { .. })
+        ));
+        assert!(matches!(
+            tree.diff(Some("/etc")).await,
+            Err(Error::Security(_))
+        ));
+
+        let mut outside = work_tree(&dir);
+        outside.paths.allowed_paths = vec![PathBuf::from("/nonexistent-ctb")];
+        assert!(matches!(
+            outside.commit("wip").await,
+            Err(Error::Security(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn truncates_patch_on_line_boundary() {
+        let mut patch = "+aaa\n+bbb\n+ccc\n".to_string();
+        assert!(truncate_at_line(&mut patch, 12));
+        assert_eq!(patch, "+aaa\n+bbb\n");
+        assert!(!truncate_at_line(&mut patch, 100));
+    }
+}
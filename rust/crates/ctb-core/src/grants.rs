@@ -0,0 +1,243 @@
+//! Runtime directory grants (`/grant`, `/revoke`).
+//!
+//! Each chat can hold extra directories on top of `cfg.allowed_paths`. They are passed to the CLI
+//! as `--add-dir` and merged into the `PathPolicy` used by the tool safety checks. Grants are read
+//! at the start of every turn, so changes apply from the next turn on. State lives in a small
+//! JSON file (`cfg.grants_file`) so grants survive restarts.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, restart::now_ms, Error, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    /// Canonicalized directory.
+    pub path: PathBuf,
+    /// Unix epoch milliseconds after which the grant no longer applies (`None` = until revoked).
+    #[serde(default)]
+    pub expires_at_ms: Option<u64>,
+}
+
+impl Grant {
+    fn is_active(&self, now_ms: u64) -> bool {
+        self.expires_at_ms.is_none_or(|t| now_ms < t)
+    }
+}
+
+/// Per-chat grants keyed by chat id, persisted to `file` on every change.
+#[derive(Debug)]
+pub struct DirectoryGrants {
+    file: PathBuf,
+    ttl: Option<Duration>,
+    chats: Mutex<BTreeMap<i64, Vec<Grant>>>,
+}
+
+impl DirectoryGrants {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self::load(cfg.grants_file.clone(), cfg.grant_ttl)
+    }
+
+    /// Load `file`; a missing or unreadable file starts with no grants.
+    pub fn load(file: PathBuf, ttl: Option<Duration>) -> Self {
+        let chats = match fs::read_to_string(&file) {
+            Ok(txt) => serde_json::from_str(&txt).unwrap_or_else(|e| {
+                log_line!("[GRANTS] Ignoring unreadable {}: {e}", file.display());
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            file,
+            ttl,
+            chats: Mutex::new(chats),
+        }
+    }
+
+    /// Grant `raw` (absolute or `~/...`) to `chat_id`. Re-granting refreshes the expiry.
+    pub fn grant(&self, chat_id: i64, raw: &str) -> Result<Grant> {
+        self.grant_at(chat_id, raw, now_ms())
+    }
+
+    fn grant_at(&self, chat_id: i64, raw: &str, now_ms: u64) -> Result<Grant> {
+        let path = canonical_dir(raw)?;
+        let grant = Grant {
+            path,
+            expires_at_ms: self.ttl.map(|ttl| now_ms + ttl.as_millis() as u64),
+        };
+
+        let mut chats = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        let grants = chats.entry(chat_id).or_default();
+        grants.retain(|g| g.path != grant.path && g.is_active(now_ms));
+        grants.push(grant.clone());
+        self.save(&chats)?;
+        Ok(grant)
+    }
+
+    /// Remove a grant; `raw` may be the path as granted or any spelling resolving to it.
+    /// Returns `false` when the chat held no such grant.
+    pub fn revoke(&self, chat_id: i64, raw: &str) -> Result<bool> {
+        let resolved = canonical_dir(raw).ok();
+        let literal = expand_home(raw.trim());
+
+        let mut chats = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(grants) = chats.get_mut(&chat_id) else {
+            return Ok(false);
+        };
+        let before = grants.len();
+        grants.retain(|g| g.path != literal && Some(&g.path) != resolved.as_ref());
+        let removed = grants.len() != before;
+        if grants.is_empty() {
+            chats.remove(&chat_id);
+        }
+        if removed {
+            self.save(&chats)?;
+        }
+        Ok(removed)
+    }
+
+    /// Unexpired grants for `chat_id`.
+    pub fn active(&self, chat_id: i64) -> Vec<Grant> {
+        self.active_at(chat_id, now_ms())
+    }
+
+    fn active_at(&self, chat_id: i64, now_ms: u64) -> Vec<Grant> {
+        let chats = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        chats
+            .get(&chat_id)
+            .map(|grants| {
+                grants
+                    .iter()
+                    .filter(|g| g.is_active(now_ms))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Directories to add for `chat_id` this turn.
+    pub fn dirs(&self, chat_id: i64) -> Vec<PathBuf> {
+        self.active(chat_id).into_iter().map(|g| g.path).collect()
+    }
+
+    /// Every chat's grants, expired ones included (for `/export_state`).
+    pub fn all(&self) -> BTreeMap<i64, Vec<Grant>> {
+        self.chats.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the grants of each chat in `chats`; other chats keep theirs.
+    pub fn restore(&self, chats: BTreeMap<i64, Vec<Grant>>) -> Result<()> {
+        let mut current = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        current.extend(chats);
+        current.retain(|_, grants| !grants.is_empty());
+        self.save(&current)
+    }
+
+    fn save(&self, chats: &BTreeMap<i64, Vec<Grant>>) -> Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.file, serde_json::to_string_pretty(chats)?)?;
+        Ok(())
+    }
+}
+
+fn expand_home(raw: &str) -> PathBuf {
+    match (std::env::var_os("HOME"), raw) {
+        (Some(home), "~") => PathBuf::from(home),
+        (Some(home), s) if s.starts_with("~/") => Path::new(&home).join(&s[2..]),
+        _ => PathBuf::from(raw),
+    }
+}
+
+/// Resolve `raw` to an existing absolute directory.
+fn canonical_dir(raw: &str) -> Result<PathBuf> {
+    let expanded = expand_home(raw.trim());
+    let invalid = |reason: &str| Error::InvalidPath {
+        path: expanded.clone(),
+        reason: reason.to_string(),
+    };
+    if !expanded.is_absolute() {
+        return Err(invalid("must be an absolute path"));
+    }
+    let path = fs::canonicalize(&expanded).map_err(|e| invalid(&e.to_string()))?;
+    if !path.is_dir() {
+        return Err(invalid("not a directory"));
+    }
+    if path.parent().is_none() {
+        return Err(invalid("refusing to grant the filesystem root"));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ctb-grants-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("data")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn grants_are_validated_persisted_and_revocable() {
+        let dir = scratch("persist");
+        let file = dir.join("grants.json");
+        let grants = DirectoryGrants::load(file.clone(), None);
+
+        assert!(grants.grant(1, "relative/path").is_err());
+        assert!(grants
+            .grant(1, &dir.join("missing").to_string_lossy())
+            .is_err());
+        assert!(
+            grants.grant(1, &file.to_string_lossy()).is_err(),
+            "files are rejected"
+        );
+        assert!(grants.grant(1, "/").is_err());
+
+        let data = fs::canonicalize(dir.join("data")).unwrap();
+        let spelled = format!("{}/data/../data/", dir.display());
+        assert_eq!(grants.grant(1, &spelled).unwrap().path, data);
+        grants.grant(1, &spelled).unwrap();
+        assert_eq!(
+            grants.dirs(1),
+            vec![data.clone()],
+            "re-grant does not duplicate"
+        );
+        assert!(grants.dirs(2).is_empty(), "grants are per chat");
+
+        let reloaded = DirectoryGrants::load(file.clone(), None);
+        assert_eq!(reloaded.dirs(1), vec![data.clone()]);
+
+        assert!(!reloaded.revoke(2, &data.to_string_lossy()).unwrap());
+        assert!(reloaded.revoke(1, &spelled).unwrap());
+        assert!(reloaded.dirs(1).is_empty());
+        assert!(DirectoryGrants::load(file, None).dirs(1).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn grants_expire_after_ttl() {
+        let dir = scratch("ttl");
+        let grants = DirectoryGrants::load(dir.join("grants.json"), Some(Duration::from_secs(60)));
+
+        let g = grants
+            .grant_at(7, &dir.join("data").to_string_lossy(), 1_000)
+            .unwrap();
+        assert_eq!(g.expires_at_ms, Some(61_000));
+        assert_eq!(grants.active_at(7, 60_999).len(), 1);
+        assert!(grants.active_at(7, 61_000).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
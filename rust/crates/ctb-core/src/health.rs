@@ -0,0 +1,515 @@
+//! Deployment self-check (`ctb doctor`, `/health`, boot diagnostics).
+//!
+//! Each check is independent and never fails the caller: problems are reported as
+//! [`Severity::Warn`] / [`Severity::Error`] items in a [`HealthReport`]. `Info` marks features that
+//! are off by configuration (e.g. no OpenAI key) and is not surfaced at startup.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use tokio::process::Command;
+
+use crate::{
+    config::{which_in_path, Config},
+    formatting::escape_html,
+    session::load_base_mcp_servers,
+    Error, Result,
+};
+
+/// How long `claude --version` may take before the CLI counts as broken.
+pub const CLI_VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+static CLI_VERSION: OnceLock<String> = OnceLock::new();
+
+/// `major.minor.patch` of the Claude CLI. Ordering is numeric, so `2.0.10 > 2.0.9`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CliVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl CliVersion {
+    /// First `x.y[.z]` in `s`, e.g. `2.0.1` in `2.0.1 (Claude Code)`. A missing patch is 0 and
+    /// pre-release/build suffixes are ignored.
+    pub fn parse(s: &str) -> Option<Self> {
+        s.split(|c: char| c.is_whitespace() || c == 'v' || c == '(')
+            .find_map(|word| {
+                let core = word.split(['-', '+']).next()?;
+                let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+                let major = parts.next()??;
+                let minor = parts.next()??;
+                let patch = parts.next().unwrap_or(Some(0))?;
+                parts.next().is_none().then_some(Self {
+                    major,
+                    minor,
+                    patch,
+                })
+            })
+    }
+}
+
+impl std::fmt::Display for CliVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// `claude --version` output recorded by [`verify_claude_cli`] at startup (for `/status` and the
+/// audit log).
+pub fn claude_cli_version() -> Option<&'static str> {
+    CLI_VERSION.get().map(String::as_str)
+}
+
+/// Startup probe of the Claude CLI against `CLAUDE_CLI_MIN_VERSION` / `CLAUDE_CLI_MAX_VERSION`.
+///
+/// Only a CLI older than the minimum is an error. A missing or broken CLI, one newer than the
+/// maximum, or an unparseable version is logged here and reported to the owner by the boot
+/// diagnostics.
+pub async fn verify_claude_cli(cfg: &Config) -> Result<()> {
+    let raw = match claude_cli_version_output(&cfg.claude_cli_path, CLI_VERSION_TIMEOUT).await {
+        Ok(raw) => raw,
+        Err(detail) => {
+            log_line!("[CLI] Claude CLI unavailable: {detail}");
+            return Ok(());
+        }
+    };
+    let check = version_check(&raw, cfg.claude_cli_min_version, cfg.claude_cli_max_version);
+    match check.severity {
+        Severity::Error => {
+            return Err(Error::Config(format!("Claude CLI {}", check.detail)));
+        }
+        Severity::Ok | Severity::Info => log_info!("[CLI] Claude CLI {raw}"),
+        Severity::Warn => log_line!("[CLI] ⚠️ Claude CLI {}", check.detail),
+    }
+    let _ = CLI_VERSION.set(raw);
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Severity {
+    fn icon(self) -> &'static str {
+        match self {
+            Severity::Ok => "✅",
+            Severity::Info => "ℹ️",
+            Severity::Warn => "⚠️",
+            Severity::Error => "❌",
+        }
+    }
+
+    /// Worth telling the owner about unprompted.
+    pub fn is_problem(self) -> bool {
+        self >= Severity::Warn
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub severity: Severity,
+    pub detail: String,
+}
+
+impl HealthCheck {
+    fn new(name: &'static str, severity: Severity, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            severity,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn worst(&self) -> Severity {
+        self.checks
+            .iter()
+            .map(|c| c.severity)
+            .max()
+            .unwrap_or(Severity::Ok)
+    }
+
+    pub fn problems(&self) -> impl Iterator<Item = &HealthCheck> {
+        self.checks.iter().filter(|c| c.severity.is_problem())
+    }
+
+    /// Full report for `/health`.
+    pub fn to_html(&self) -> String {
+        let mut lines = vec!["🩺 <b>Health check</b>\n".to_string()];
+        lines.extend(self.checks.iter().map(check_html));
+        lines.join("\n")
+    }
+
+    /// Boot message listing only problems; `None` when everything is fine.
+    pub fn problems_html(&self) -> Option<String> {
+        let problems: Vec<String> = self.problems().map(check_html).collect();
+        if problems.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "🩺 <b>Startup check: {} problem(s)</b>\n\n{}\n\n<i>Run /health after fixing to re-check.</i>",
+            problems.len(),
+            problems.join("\n")
+        ))
+    }
+
+    /// Plain-text report for the terminal (`ctb doctor`).
+    pub fn to_text(&self) -> String {
+        self.checks
+            .iter()
+            .map(|c| format!("{} {}: {}", c.severity.icon(), c.name, c.detail))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn check_html(c: &HealthCheck) -> String {
+    format!(
+        "{} <b>{}</b>: {}",
+        c.severity.icon(),
+        escape_html(c.name),
+        escape_html(&c.detail)
+    )
+}
+
+/// Run every check against `cfg`.
+pub async fn run_health_checks(cfg: &Config) -> HealthReport {
+    let mut checks = vec![
+        check_claude_cli(
+            &cfg.claude_cli_path,
+            CLI_VERSION_TIMEOUT,
+            cfg.claude_cli_min_version,
+            cfg.claude_cli_max_version,
+        )
+        .await,
+    ];
+    if let Some(dir) = &cfg.claude_config_dir {
+        checks.push(check_dir_writable("CLAUDE_CONFIG_DIR", dir));
+    }
+    checks.push(check_dir_writable("Temp dir", &cfg.temp_dir));
+    checks.push(check_file_writable("Session file", &cfg.session_file));
+    checks.push(check_file_writable("Audit log", &cfg.audit_log_path));
+    checks.push(check_mcp_config());
+    checks.push(check_pdftotext());
+    checks.push(check_tesseract(cfg.photo_ocr_force));
+    checks.push(if cfg.transcription_available {
+        HealthCheck::new(
+            "Transcription",
+            Severity::Ok,
+            format!("enabled ({})", cfg.transcription_model),
+        )
+    } else {
+        HealthCheck::new(
+            "Transcription",
+            Severity::Info,
+            "disabled (OPENAI_API_KEY not set)",
+        )
+    });
+    HealthReport { checks }
+}
+
+/// The CLI exists, `--version` exits successfully within `timeout`, and the version is within
+/// `min..=max` (either bound optional).
+pub async fn check_claude_cli(
+    path: &Path,
+    timeout: Duration,
+    min: Option<CliVersion>,
+    max: Option<CliVersion>,
+) -> HealthCheck {
+    match claude_cli_version_output(path, timeout).await {
+        Ok(raw) => version_check(&raw, min, max),
+        Err(detail) => HealthCheck::new(CLI_CHECK_NAME, Severity::Error, detail),
+    }
+}
+
+const CLI_CHECK_NAME: &str = "Claude CLI";
+
+/// Trimmed `--version` output, or why it could not be read.
+async fn claude_cli_version_output(
+    path: &Path,
+    timeout: Duration,
+) -> std::result::Result<String, String> {
+    if !path.is_file() {
+        return Err(format!("not found at {}", path.display()));
+    }
+    let output = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(timeout, output).await {
+        Err(_) => Err(format!(
+            "`--version` timed out after {}s",
+            timeout.as_secs_f32()
+        )),
+        Ok(Err(e)) => Err(format!("failed to run: {e}")),
+        Ok(Ok(out)) if !out.status.success() => {
+            Err(format!("`--version` exited with {}", out.status))
+        }
+        Ok(Ok(out)) => Ok(String::from_utf8_lossy(&out.stdout).trim().to_string()),
+    }
+}
+
+/// Below `min` is an error (the bot refuses to start); above `max` or unparseable while a
+/// bound is set is a warning, since the stream-json format may have changed.
+fn version_check(raw: &str, min: Option<CliVersion>, max: Option<CliVersion>) -> HealthCheck {
+    if min.is_none() && max.is_none() {
+        return HealthCheck::new(CLI_CHECK_NAME, Severity::Ok, raw);
+    }
+    let Some(version) = CliVersion::parse(raw) else {
+        return HealthCheck::new(
+            CLI_CHECK_NAME,
+            Severity::Warn,
+            format!("{raw} (version not recognised, range not checked)"),
+        );
+    };
+    match (min, max) {
+        (Some(min), _) if version < min => HealthCheck::new(
+            CLI_CHECK_NAME,
+            Severity::Error,
+            format!("{version} is older than CLAUDE_CLI_MIN_VERSION {min}"),
+        ),
+        (_, Some(max)) if version > max => HealthCheck::new(
+            CLI_CHECK_NAME,
+            Severity::Warn,
+            format!(
+                "{version} is newer than CLAUDE_CLI_MAX_VERSION {max}; stream-json output may have changed"
+            ),
+        ),
+        _ => HealthCheck::new(CLI_CHECK_NAME, Severity::Ok, raw),
+    }
+}
+
+fn check_dir_writable(name: &'static str, dir: &Path) -> HealthCheck {
+    if !dir.is_dir() {
+        return HealthCheck::new(
+            name,
+            Severity::Error,
+            format!("{} is not a directory", dir.display()),
+        );
+    }
+    let probe = dir.join(format!(".ctb-health-{}", std::process::id()));
+    let written = std::fs::write(&probe, b"ok");
+    let _ = std::fs::remove_file(&probe);
+    match written {
+        Ok(()) => HealthCheck::new(name, Severity::Ok, format!("{} writable", dir.display())),
+        Err(e) => HealthCheck::new(
+            name,
+            Severity::Error,
+            format!("{} not writable: {e}", dir.display()),
+        ),
+    }
+}
+
+/// An existing file must open for append; a missing one needs a writable parent directory.
+fn check_file_writable(name: &'static str, path: &Path) -> HealthCheck {
+    if !path.exists() {
+        let parent = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut check = check_dir_writable(name, &parent);
+        if check.severity == Severity::Ok {
+            check.detail = format!("{} (will be created)", path.display());
+        }
+        return check;
+    }
+    match OpenOptions::new()
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.flush())
+    {
+        Ok(()) => HealthCheck::new(name, Severity::Ok, format!("{} writable", path.display())),
+        Err(e) => HealthCheck::new(
+            name,
+            Severity::Error,
+            format!("{} not writable: {e}", path.display()),
+        ),
+    }
+}
+
+fn check_mcp_config() -> HealthCheck {
+    const NAME: &str = "MCP config";
+    let servers = match load_base_mcp_servers() {
+        Ok(Some(servers)) => servers,
+        Ok(None) => return HealthCheck::new(NAME, Severity::Info, "no mcp-config.json"),
+        Err(e) => return HealthCheck::new(NAME, Severity::Error, format!("failed to load: {e}")),
+    };
+    let mut warnings: Vec<String> = servers
+        .iter()
+        .filter_map(|(name, s)| s.command_warning().map(|w| format!("{name}: {w}")))
+        .collect();
+    if warnings.is_empty() {
+        return HealthCheck::new(
+            NAME,
+            Severity::Ok,
+            format!("{} server(s) loaded", servers.len()),
+        );
+    }
+    warnings.sort();
+    HealthCheck::new(NAME, Severity::Warn, warnings.join("; "))
+}
+
+fn check_pdftotext() -> HealthCheck {
+    match which_in_path("pdftotext") {
+        Some(path) => HealthCheck::new("pdftotext", Severity::Ok, path.display().to_string()),
+        None => HealthCheck::new(
+            "pdftotext",
+            Severity::Warn,
+            "not on PATH; PDF documents cannot be read (install poppler)",
+        ),
+    }
+}
+
+/// Only needed for OCR: a problem when `PHOTO_OCR_FORCE` asks for it, informational otherwise.
+fn check_tesseract(forced: bool) -> HealthCheck {
+    match which_in_path("tesseract") {
+        Some(path) => HealthCheck::new("tesseract", Severity::Ok, path.display().to_string()),
+        None if forced => HealthCheck::new(
+            "tesseract",
+            Severity::Warn,
+            "not on PATH; PHOTO_OCR_FORCE has no effect (install tesseract)",
+        ),
+        None => HealthCheck::new(
+            "tesseract",
+            Severity::Info,
+            "not on PATH; photos can't be OCR'd for models without vision",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script(name: &str, body: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ctb-health-{name}-{}", std::process::id()));
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn claude_cli_check_covers_missing_ok_and_hanging_binaries() {
+        let missing = check_claude_cli(
+            Path::new("/nonexistent/claude"),
+            CLI_VERSION_TIMEOUT,
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(missing.severity, Severity::Error);
+
+        let ok = script("ok", "echo '2.0.1 (Claude Code)'");
+        let check = check_claude_cli(&ok, CLI_VERSION_TIMEOUT, None, None).await;
+        assert_eq!(check.severity, Severity::Ok);
+        assert_eq!(check.detail, "2.0.1 (Claude Code)");
+
+        let too_old =
+            check_claude_cli(&ok, CLI_VERSION_TIMEOUT, CliVersion::parse("2.1"), None).await;
+        assert_eq!(too_old.severity, Severity::Error);
+        assert!(
+            too_old.detail.contains("CLAUDE_CLI_MIN_VERSION 2.1.0"),
+            "{}",
+            too_old.detail
+        );
+
+        let hang = script("hang", "sleep 5");
+        let check = check_claude_cli(&hang, Duration::from_millis(200), None, None).await;
+        assert_eq!(check.severity, Severity::Error);
+        assert!(check.detail.contains("timed out"), "{}", check.detail);
+
+        let _ = std::fs::remove_file(ok);
+        let _ = std::fs::remove_file(hang);
+    }
+
+    #[test]
+    fn versions_parse_from_cli_output_and_compare_numerically() {
+        let v = |s: &str| CliVersion::parse(s).unwrap();
+        assert_eq!(v("2.0.1 (Claude Code)").to_string(), "2.0.1");
+        assert_eq!(v("claude v1.9").to_string(), "1.9.0");
+        assert_eq!(v("2.1.0-beta.3").to_string(), "2.1.0");
+        assert!(v("2.0.10") > v("2.0.9"));
+        assert_eq!(CliVersion::parse("Claude Code"), None);
+        assert_eq!(CliVersion::parse("1.2.3.4"), None);
+    }
+
+    #[test]
+    fn version_range_errors_below_min_and_warns_above_max() {
+        let v = CliVersion::parse;
+        let raw = "2.0.1 (Claude Code)";
+        assert_eq!(version_check(raw, None, None).severity, Severity::Ok);
+        assert_eq!(
+            version_check(raw, v("2.0.1"), v("2.0.1")).severity,
+            Severity::Ok
+        );
+        assert_eq!(
+            version_check(raw, v("2.0.2"), None).severity,
+            Severity::Error
+        );
+        let newer = version_check(raw, None, v("2.0.0"));
+        assert_eq!(newer.severity, Severity::Warn);
+        assert!(newer.detail.contains("stream-json"), "{}", newer.detail);
+        assert_eq!(
+            version_check("unknown", v("1.0"), None).severity,
+            Severity::Warn
+        );
+    }
+
+    #[test]
+    fn writability_checks() {
+        let tmp = std::env::temp_dir();
+        assert_eq!(check_dir_writable("t", &tmp).severity, Severity::Ok);
+        assert_eq!(
+            check_dir_writable("t", Path::new("/nonexistent/dir")).severity,
+            Severity::Error
+        );
+        let new_file = tmp.join(format!("ctb-health-new-{}.json", std::process::id()));
+        let check = check_file_writable("t", &new_file);
+        assert_eq!(check.severity, Severity::Ok);
+        assert!(check.detail.ends_with("(will be created)"));
+        assert!(!new_file.exists(), "probe must not create the file");
+    }
+
+    #[test]
+    fn startup_summary_lists_only_problems() {
+        let mut report = HealthReport {
+            checks: vec![
+                HealthCheck::new("Claude CLI", Severity::Ok, "2.0.1"),
+                HealthCheck::new("Transcription", Severity::Info, "disabled"),
+            ],
+        };
+        assert_eq!(report.worst(), Severity::Info);
+        assert!(report.problems_html().is_none());
+
+        report.checks.push(HealthCheck::new(
+            "pdftotext",
+            Severity::Warn,
+            "not on <PATH>",
+        ));
+        let html = report.problems_html().unwrap();
+        assert!(html.contains("1 problem(s)"));
+        assert!(html.contains("⚠️ <b>pdftotext</b>: not on &lt;PATH&gt;"));
+        assert!(!html.contains("Claude CLI"));
+        assert!(report.to_html().contains("Claude CLI"));
+    }
+}
@@ -0,0 +1,1949 @@
+//! User-facing copy (`BOT_LOCALE`, `/settings language`).
+//!
+//! Every string the bot writes to a chat on its own behalf comes from a [`Messages`] catalog.
+//! Catalogs are plain structs, so adding a string means adding a field and the compiler points at
+//! [`EN`], the one catalog that must spell out every field. Other locales start from
+//! `..EN`: a string nobody translated yet shows in English instead of going missing.
+//!
+//! Catalog strings are Telegram HTML. Parameters are escaped as they are filled in, so a user's
+//! file name or an error message can never inject markup. The few plain-text strings (button
+//! labels, callback notices) say so on their accessors and are filled as is.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    sync::{Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat_settings::ChatSetting, errors::CancelReason, formatting::escape_html, theme::StatusTheme,
+};
+
+/// Built-in locales.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    It,
+}
+
+impl Locale {
+    pub const ALL: [Self; 2] = [Self::En, Self::It];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        Self::ALL
+            .into_iter()
+            .find(|l| l.code().eq_ignore_ascii_case(s))
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::It => "it",
+        }
+    }
+
+    /// The locale's own name for itself, e.g. `Italiano`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::En => "English",
+            Self::It => "Italiano",
+        }
+    }
+
+    pub fn messages(self) -> &'static Messages {
+        match self {
+            Self::En => &EN,
+            Self::It => &IT,
+        }
+    }
+}
+
+/// One locale's strings. Templates name their parameters in braces (`{command}`); use the
+/// accessors, which fill and escape them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Messages {
+    // Access and errors
+    unauthorized: &'static str,
+    owner_only: &'static str,
+    unknown_command: &'static str,
+    rate_limited: &'static str,
+    daily_limit_reached: &'static str,
+    this_minute: &'static str,
+    this_hour: &'static str,
+    per_seconds: &'static str,
+    error: &'static str,
+    provider_error: &'static str,
+    provider_error_retrying: &'static str,
+    model_crashed_retrying: &'static str,
+    stopped: &'static str,
+    waiting_for_choice: &'static str,
+    timed_out: &'static str,
+
+    // Commands
+    help: &'static str,
+    session_active: &'static str,
+    session_none: &'static str,
+    session_cleared: &'static str,
+    session_already_active: &'static str,
+    query_already_running: &'static str,
+    nothing_to_retry: &'static str,
+    nothing_to_export: &'static str,
+    plan_empty: &'static str,
+    restarting: &'static str,
+
+    // /settings
+    settings_title: &'static str,
+    settings_overridden: &'static str,
+    settings_throttle_hint: &'static str,
+    settings_language_hint: &'static str,
+    settings_tools_hint: &'static str,
+    settings_usage: &'static str,
+    settings_throttle_usage: &'static str,
+    settings_language_usage: &'static str,
+    settings_tools_usage: &'static str,
+    settings_reset_button: &'static str,
+    settings_reset_done: &'static str,
+    settings_unknown: &'static str,
+    settings_save_failed: &'static str,
+    invalid_callback: &'static str,
+    on: &'static str,
+    off: &'static str,
+    delete_thinking_messages: &'static str,
+    delete_tool_messages: &'static str,
+    show_tool_outputs: &'static str,
+    streaming_throttle: &'static str,
+    language: &'static str,
+    allowed_tools: &'static str,
+    disallowed_tools: &'static str,
+    all_tools: &'static str,
+    no_tools: &'static str,
+
+    // Streaming status
+    working: &'static str,
+    completed: &'static str,
+    waiting_for_answer: &'static str,
+    code_file_caption: &'static str,
+    code_sent_as_file: &'static str,
+    context_limit_warning: &'static str,
+    context_compacted: &'static str,
+    context_compacted_was: &'static str,
+    budget_exceeded: &'static str,
+    blocked: &'static str,
+    blocked_needs_full_access: &'static str,
+    access_denied: &'static str,
+    access_denied_read_only: &'static str,
+    blocked_fetch: &'static str,
+    blocked_search: &'static str,
+    shutdown_notice: &'static str,
+    plan_ready: &'static str,
+
+    // Shared
+    failed: &'static str,
+    succeeded: &'static str,
+    warning: &'static str,
+    retrying: &'static str,
+    truncated: &'static str,
+    now: &'static str,
+    ago: &'static str,
+    none: &'static str,
+    input_tokens: &'static str,
+    output_tokens: &'static str,
+    cache_read_tokens: &'static str,
+
+    // /status
+    status_title: &'static str,
+    status_session_active: &'static str,
+    status_session_none: &'static str,
+    status_session_duration: &'static str,
+    status_idle_for: &'static str,
+    status_query_running: &'static str,
+    status_query_idle: &'static str,
+    status_model: &'static str,
+    status_thinking: &'static str,
+    status_tools: &'static str,
+    tools_only: &'static str,
+    tools_all_except: &'static str,
+    tools_only_except: &'static str,
+    status_cli_version: &'static str,
+    status_last_usage: &'static str,
+    status_working_dir: &'static str,
+    status_access: &'static str,
+    status_safety_blocks: &'static str,
+    status_granted_dirs: &'static str,
+    status_grant_expires: &'static str,
+
+    // /model, /think, /prompt
+    model_default: &'static str,
+    model_cli_default: &'static str,
+    model_show: &'static str,
+    model_set: &'static str,
+    thinking_auto: &'static str,
+    think_show: &'static str,
+    think_set: &'static str,
+    prompt_set: &'static str,
+    prompt_cleared: &'static str,
+    prompt_current: &'static str,
+    prompt_none: &'static str,
+    prompt_available: &'static str,
+    prompt_usage: &'static str,
+
+    // /vocab
+    vocab_already_known: &'static str,
+    vocab_added: &'static str,
+    vocab_removed: &'static str,
+    vocab_not_found: &'static str,
+    vocab_empty: &'static str,
+    vocab_list: &'static str,
+    vocab_usage: &'static str,
+
+    // /sessions, /title
+    sessions_title: &'static str,
+    sessions_current: &'static str,
+    sessions_untitled: &'static str,
+    sessions_resume_button: &'static str,
+    sessions_rename_button: &'static str,
+    sessions_delete_button: &'static str,
+    sessions_none: &'static str,
+    sessions_none_left: &'static str,
+    sessions_outdated: &'static str,
+    sessions_busy: &'static str,
+    session_not_saved: &'static str,
+    session_other_dir: &'static str,
+    session_resumed_at: &'static str,
+    session_no_longer_saved: &'static str,
+    session_resumed: &'static str,
+    sessions_rename_prompt: &'static str,
+    sessions_rename_cancelled: &'static str,
+    sessions_deleted: &'static str,
+    session_renamed: &'static str,
+    session_title: &'static str,
+    title_usage: &'static str,
+
+    // /cron, /remind
+    cron_test_usage: &'static str,
+    cron_test_started: &'static str,
+    cron_none_found: &'static str,
+    cron_reloaded_one: &'static str,
+    cron_reloaded: &'static str,
+    cron_skipped: &'static str,
+    cron_hint: &'static str,
+    cron_no_jobs: &'static str,
+    cron_jobs_title: &'static str,
+    cron_next_at: &'static str,
+    cron_never: &'static str,
+    cron_queued_title: &'static str,
+    reminders_title: &'static str,
+    reminders_none: &'static str,
+    reminder_set: &'static str,
+    reminder_set_ask: &'static str,
+    reminder_cancelled: &'static str,
+    reminder_not_found: &'static str,
+    remind_usage: &'static str,
+    remind_cancel_usage: &'static str,
+    logs_usage: &'static str,
+
+    // /mcp, /broadcast, /export
+    mcp_none: &'static str,
+    mcp_load_failed: &'static str,
+    mcp_title: &'static str,
+    mcp_ok_no_tools: &'static str,
+    mcp_not_probed: &'static str,
+    mcp_probe_hint: &'static str,
+    broadcast_usage: &'static str,
+    transcript_caption: &'static str,
+    state_export_caption: &'static str,
+    state_export_sent: &'static str,
+    state_export_failed: &'static str,
+
+    // /diff, /commit, /grant, /revoke
+    diff_clean: &'static str,
+    diff_title: &'static str,
+    diff_caption: &'static str,
+    diff_caption_truncated: &'static str,
+    commit_needs_full_access: &'static str,
+    commit_usage: &'static str,
+    committed: &'static str,
+    nothing_to_commit: &'static str,
+    grant_usage: &'static str,
+    granted: &'static str,
+    granted_for: &'static str,
+    revoked: &'static str,
+    no_grant: &'static str,
+
+    // /limits, /stats
+    limits_title: &'static str,
+    limits_cap: &'static str,
+    limits_no_cap: &'static str,
+    limits_empty: &'static str,
+    limits_totals: &'static str,
+    limits_today: &'static str,
+    limits_today_of_cap: &'static str,
+    limits_week: &'static str,
+    limits_failed: &'static str,
+    stats_title: &'static str,
+    stats_duration: &'static str,
+    stats_queries: &'static str,
+    stats_compactions: &'static str,
+    stats_no_session: &'static str,
+    stats_tokens_title: &'static str,
+    stats_cache_tokens: &'static str,
+    stats_cache_read: &'static str,
+    stats_cache_create: &'static str,
+    stats_total_tokens: &'static str,
+    stats_cost_title: &'static str,
+    stats_cost_input: &'static str,
+    stats_cost_output: &'static str,
+    stats_cost_cache: &'static str,
+    stats_cost_total: &'static str,
+    stats_average_title: &'static str,
+    stats_average_cost: &'static str,
+    stats_no_queries: &'static str,
+    stats_last_query: &'static str,
+    stats_pricing: &'static str,
+    provider_usage_title: &'static str,
+    provider_resets_in: &'static str,
+    provider_usage_percent: &'static str,
+    no_providers: &'static str,
+    provider_usage_fetched: &'static str,
+
+    // /t
+    templates_none: &'static str,
+    templates_title: &'static str,
+    templates_hint: &'static str,
+    template_missing: &'static str,
+}
+
+pub const EN: Messages = Messages {
+    unauthorized: "Unauthorized. Contact the bot owner for access.",
+    owner_only: "⛔ /{command} is only available to the bot owner.",
+    unknown_command: "Unknown command: /{command}",
+    rate_limited: "⏳ Rate limited: try again in {secs}s, {left}/{max} requests left {window}.",
+    daily_limit_reached: "🚫 Daily token limit reached ({used}/{cap}). Try again tomorrow.",
+    this_minute: "this minute",
+    this_hour: "this hour",
+    per_seconds: "per {secs}s",
+    error: "❌ Error: {error}",
+    provider_error: "❌ Provider error: {error}\n\n<i>The model could not be reached or refused \
+the request; this is not an answer. Send /retry to try again.</i>",
+    provider_error_retrying: "⚠️ Could not reach the model, retrying...",
+    model_crashed_retrying: "⚠️ Claude crashed, retrying...",
+    stopped: "⏹ Stopped.",
+    waiting_for_choice: "⏳ Waiting for your choice.",
+    timed_out: "⏱ Timed out.",
+
+    help: "🤖 <b>Claude Telegram Bot (Rust)</b>\n\n\
+Status: {status}\n\
+Working directory: <code>{work_dir}</code>\n\n\
+<b>📋 Commands:</b>\n\
+/start - Show this help message\n\
+/new - Start fresh session\n\
+/stop - Stop current query (silent)\n\
+/status - Show current session status\n\
+/model [name|default] - Show or set the Claude model\n\
+/think [off|normal|deep|N|default] - Thinking budget\n\
+/prompt [use &lt;name&gt;|show|clear] - Profile appended to the system prompt\n\
+/vocab [add|remove|list] - Terms voice transcription should spell right\n\
+/settings - Per-chat display settings and language\n\
+/stats - Show token usage & cost stats\n\
+/export - Download this session as Markdown\n\
+/diff [path] - Show uncommitted changes in the working dir\n\
+/commit &lt;message&gt; - Stage and commit all changes\n\
+/undo - Revert files changed by the last turn\n\
+/limits - Daily/weekly usage per user\n\
+/resume - Resume last saved session\n\
+/sessions - Browse saved sessions (resume, rename, delete)\n\
+/title &lt;text&gt; - Rename the current session\n\
+/retry - Retry last message\n\
+/t &lt;name&gt; [args] - Run a prompt template from templates.yaml (/t list)\n\
+/cron [reload|test &lt;name&gt;] - Scheduled jobs status/reload, or run one now\n\
+/remind &lt;40m|18:30&gt; [ask:]&lt;text&gt; - One-shot reminder (list, cancel &lt;id&gt;)\n\
+/mcp [probe] - MCP servers (probe: health check)\n\
+/health - Check CLI, paths and tools\n\
+/perf - Pipeline latency and Telegram call stats (owner)\n\
+/logs [n] [level|follow|stop] - Recent bot log lines (owner)\n\
+/broadcast [--all] &lt;text&gt; - Message every allowed user (owner)\n\
+/grant [dir] - Allow an extra directory in this chat (owner)\n\
+/revoke [dir] - Remove a granted directory (owner)\n\
+/export_state - Sessions, settings and grants of every chat as a file (owner)\n\
+/restart - Restart the bot process\n\n\
+<b>💡 Tips:</b>\n\
+• Prefix a message or caption with <code>!</code> to interrupt current query\n\
+• Use \"think\" keyword for extended reasoning\n\
+• Use \"ultrathink\" for deep analysis\n\
+• Send photos, voice messages, or documents\n\
+• Multiple photos = album (auto-grouped)",
+    session_active: "Active session",
+    session_none: "No active session",
+    session_cleared: "🆕 Session cleared. Next message starts fresh.",
+    session_already_active: "Session already active. Use /new to start fresh first.",
+    query_already_running: "⏳ A query is already running. Use /stop first.",
+    nothing_to_retry: "❌ No message to retry.",
+    nothing_to_export: "📭 Nothing to export yet.",
+    plan_empty: "Add a request after <code>{prefix}</code> to plan it first.",
+    restarting: "🔄 Restarting bot...",
+
+    settings_title: "⚙️ <b>Settings for this chat</b>",
+    settings_overridden: " (this chat)",
+    settings_throttle_hint:
+        "/settings throttle &lt;{min}-{max} ms|default&gt; changes the streaming throttle.",
+    settings_language_hint: "/settings language &lt;{codes}|default&gt; changes the language.",
+    settings_tools_hint: "/settings allowed_tools|disallowed_tools &lt;Tool,...|none|default&gt; \
+limits the tools Claude may use (owner only).",
+    settings_usage: "Usage: /settings [throttle &lt;ms|default&gt;|language &lt;code|default&gt;|\
+allowed_tools &lt;list|none|default&gt;|disallowed_tools &lt;list|none|default&gt;|reset]",
+    settings_throttle_usage: "Usage: /settings throttle &lt;ms|default&gt;",
+    settings_language_usage: "Usage: /settings language &lt;{codes}|default&gt;",
+    settings_tools_usage: "Usage: /settings {key} &lt;Tool,...|none|default&gt;",
+    settings_reset_button: "↩️ Reset to defaults",
+    settings_reset_done: "Back to the defaults",
+    settings_unknown: "Unknown setting",
+    settings_save_failed: "Failed to save settings",
+    invalid_callback: "Invalid callback data",
+    on: "on",
+    off: "off",
+    delete_thinking_messages: "Delete thinking messages",
+    delete_tool_messages: "Delete tool messages",
+    show_tool_outputs: "Show tool outputs",
+    streaming_throttle: "Streaming throttle",
+    language: "Language",
+    allowed_tools: "Allowed tools",
+    disallowed_tools: "Disallowed tools",
+    all_tools: "all",
+    no_tools: "none",
+
+    working: "Working...",
+    completed: "Completed",
+    waiting_for_answer: "Waiting for your answer...",
+    code_file_caption: "{name} ({lines} lines)",
+    code_sent_as_file: "sent as file: `{name}`",
+    context_limit_warning: "⚠️ Session is near its context limit — consider /new or /export",
+    context_compacted: "♻️ Context compacted — older messages summarized",
+    context_compacted_was: "♻️ Context compacted — older messages summarized (was {tokens} tokens)",
+    budget_exceeded: "💸 Budget exceeded (${spent} of ${limit})",
+    blocked: "🔒 BLOCKED: {reason}",
+    blocked_needs_full_access: "🔒 BLOCKED: {tool} needs full access (you have read-only access)",
+    access_denied: "🔒 Access denied: {path}",
+    access_denied_read_only: "🔒 Access denied (read-only): {path}",
+    blocked_fetch: "🌐 Blocked fetch to {host}",
+    blocked_search: "🌐 Blocked search to {host}",
+    shutdown_notice: "⚠️ Bot shutting down, query aborted",
+    plan_ready: "📝 Plan ready. Execute it?",
+
+    failed: "❌ {error}",
+    succeeded: "✅ {text}",
+    warning: "⚠️ {error}",
+    retrying: "🔄 Retrying: \"{preview}\"",
+    truncated: "… (truncated)",
+    now: "now",
+    ago: "{age} ago",
+    none: "none",
+    input_tokens: "Input: {count} tokens",
+    output_tokens: "Output: {count} tokens",
+    cache_read_tokens: "Cache read: {count}",
+
+    status_title: "<b>Bot Status</b>\n",
+    status_session_active: "Session: Active ({id}...)",
+    status_session_none: "Session: None",
+    status_session_duration: "Duration: {duration} | {queries} queries",
+    status_idle_for: "Idle for {idle}",
+    status_query_running: "Query: Running",
+    status_query_idle: "Query: Idle",
+    status_model: "Model: {model}",
+    status_thinking: "Thinking: {mode}",
+    status_tools: "Tools: {tools}",
+    tools_only: "only {allowed}",
+    tools_all_except: "all except {disallowed}",
+    tools_only_except: "only {allowed}, except {disallowed}",
+    status_cli_version: "Claude CLI: {version}",
+    status_last_usage: "Last query usage:",
+    status_working_dir: "Working dir: <code>{dir}</code>",
+    status_access: "Your access: {role}",
+    status_safety_blocks: "Safety blocks:",
+    status_granted_dirs: "Granted dirs:",
+    status_grant_expires: " (expires in {duration})",
+
+    model_default: "{model} (default)",
+    model_cli_default: "CLI default",
+    model_show: "🧠 <b>Model</b>: {model}\n\n\
+Aliases: {aliases}\n\
+Full model ids (e.g. <code>claude-sonnet-4-5</code>) also work.\n\n\
+<i>/model &lt;name&gt; to switch, /model default to clear.</i>",
+    model_set: "✅ Model set to {model}",
+    thinking_auto: "auto (keywords, default {mode})",
+    think_show: "💭 <b>Thinking</b>: {mode}\n\n\
+<i>/think off|normal|deep|&lt;tokens&gt; to override, /think default for keyword detection.</i>",
+    think_set: "✅ Thinking set to {mode}",
+    prompt_set: "✅ Prompt profile set to <code>{name}</code>",
+    prompt_cleared: "✅ Prompt profile cleared",
+    prompt_current: "📝 <b>Prompt profile</b>: <code>{name}</code>\n\n{preview}",
+    prompt_none: "📝 <b>Prompt profile</b>: none (safety prompt only)",
+    prompt_available: "\n\nAvailable: {profiles}\n\
+<i>Profiles are .md files in <code>{dir}</code>. /prompt use &lt;name&gt; to select, /prompt clear to remove.</i>",
+    prompt_usage: "Usage: /prompt [use &lt;name&gt;|show|clear]",
+
+    vocab_already_known: "Already in the vocabulary.",
+    vocab_added: "✅ Added {terms}",
+    vocab_removed: "✅ Removed {terms}",
+    vocab_not_found: "Not in the vocabulary: {terms}",
+    vocab_empty: "📖 <b>Vocabulary</b>: empty\n\n\
+<i>/vocab add term1, term2 helps transcriptions spell names and jargon.</i>",
+    vocab_list: "📖 <b>Vocabulary</b> ({count}/{max})\n\n{terms}",
+    vocab_usage: "Usage: /vocab [add &lt;terms&gt;|remove &lt;term&gt;|list]",
+
+    sessions_title: "🗂 <b>Saved sessions</b> ({count})\n",
+    sessions_current: " · <i>current</i>",
+    sessions_untitled: "Session {id}",
+    sessions_resume_button: "▶️ {number}. Resume",
+    sessions_rename_button: "✏️ Rename",
+    sessions_delete_button: "🗑 Delete",
+    sessions_none: "No saved sessions yet in this chat.",
+    sessions_none_left: "No saved sessions left in this chat.",
+    sessions_outdated: "This list is out of date; send /sessions again",
+    sessions_busy: "Wait for the current query to finish",
+    session_not_saved: "No saved session found",
+    session_other_dir: "Session was for different directory: {dir}",
+    session_resumed_at: "Resumed session `{id}` (saved at {saved_at})",
+    session_no_longer_saved: "That session is no longer saved",
+    session_resumed: "Resumed session \"{title}\"",
+    sessions_rename_prompt: "✏️ Send the new title for <b>{title}</b> as your next message within 2 minutes. /cancel or any other command keeps the old title.",
+    sessions_rename_cancelled: "Rename cancelled.",
+    sessions_deleted: "🗑 Deleted \"{title}\"",
+    session_renamed: "🏷 Session renamed to <b>{title}</b>",
+    session_title: "🏷 <b>{title}</b>\n\n<i>/title &lt;text&gt; renames it.</i>",
+    title_usage: "Usage: /title &lt;text&gt;",
+
+    cron_test_usage: "Usage: /cron test &lt;name&gt;",
+    cron_test_started: "🧪 Running <b>{name}</b> now; the result will be posted here.",
+    cron_none_found: "⚠️ No schedules found in cron.yaml",
+    cron_reloaded_one: "🔄 Reloaded 1 scheduled job",
+    cron_reloaded: "🔄 Reloaded {count} scheduled jobs",
+    cron_skipped: "⚠️ Skipped the cron.yaml entries with problems:\n<pre>{errors}</pre>",
+    cron_hint: "\n\n<i>cron.yaml is auto-monitored for changes.\n\
+You can also use /cron reload to force reload, or /cron test &lt;name&gt; to run a job now.</i>",
+    cron_no_jobs: "No scheduled jobs",
+    cron_jobs_title: "<b>Scheduled Jobs ({count})</b>",
+    cron_next_at: "{name}: next at {time}",
+    cron_never: "{name}: never",
+    cron_queued_title: "<b>Queued Jobs ({count})</b>",
+    reminders_title: "<b>Reminders ({count})</b>",
+    reminders_none: "No pending reminders in this chat.",
+    reminder_set: "Reminder #{id} set for {time}.",
+    reminder_set_ask: "Reminder #{id} set for {time}; it will run as a prompt.",
+    reminder_cancelled: "Reminder #{id} cancelled.",
+    reminder_not_found: "No reminder #{id} in this chat.",
+    remind_usage: "Usage: /remind &lt;40m|2h30m|18:30&gt; [ask:]&lt;text&gt;\n\
+/remind list · /remind cancel &lt;id&gt;",
+    remind_cancel_usage: "Usage: /remind cancel &lt;id&gt;",
+    logs_usage: "Usage: /logs [n] [info|warn|error] · /logs follow · /logs stop",
+
+    mcp_none: "🔌 No MCP servers configured (mcp-config.json)",
+    mcp_load_failed: "❌ Failed to load MCP config: {error}",
+    mcp_title: "🔌 <b>MCP Servers</b> ({count})\n",
+    mcp_ok_no_tools: "✅ OK (no tools)",
+    mcp_not_probed: "⚪ not probed (http)",
+    mcp_probe_hint: "\n<i>Use /mcp probe to health-check stdio servers.</i>",
+    broadcast_usage: "Usage: /broadcast [--all] &lt;text&gt;",
+    transcript_caption: "📝 Session transcript",
+    state_export_caption: "📦 Bot state. Restore on another server with: ctb import-state <file>",
+    state_export_sent: "📦 State export sent to you privately.",
+    state_export_failed: "❌ State export failed: {error}",
+
+    diff_clean: "✨ No changes in <code>{dir}</code>.",
+    diff_title: "📝 <b>Changes</b> in <code>{dir}</code>",
+    diff_caption: "🩹 Diff",
+    diff_caption_truncated: "🩹 Diff (truncated)",
+    commit_needs_full_access: "⛔ /commit needs full access.",
+    commit_usage: "Usage: /commit &lt;message&gt;",
+    committed: "✅ Committed <code>{hash}</code>: {message}",
+    nothing_to_commit: "✨ Nothing to commit, working tree clean.",
+    grant_usage: "Usage: /{command} &lt;absolute directory&gt;",
+    granted: "🔓 Granted <code>{dir}</code>. Applies from the next message.",
+    granted_for: "🔓 Granted <code>{dir}</code> for {duration}. Applies from the next message.",
+    revoked: "🔒 Revoked <code>{dir}</code>. Applies from the next message.",
+    no_grant: "No grant for <code>{dir}</code>.",
+
+    limits_title: "📒 <b>Usage Limits</b>\n",
+    limits_cap: "Daily token cap: <b>{cap}</b> per user",
+    limits_no_cap: "Daily token cap: <i>none</i>",
+    limits_empty: "\n📭 No usage recorded this week",
+    limits_totals: "{tokens} tokens · ${cost} · {turns} turns",
+    limits_today: "Today: {totals}",
+    limits_today_of_cap: "Today: {totals} ({percent}% of cap)",
+    limits_week: "This week: {totals}",
+    limits_failed: "❌ Failed to read usage ledger: {error}",
+    stats_title: "📊 <b>Session Statistics</b>\n",
+    stats_duration: "⏱️ Session duration: {duration}",
+    stats_queries: "🔢 Total queries: {count}",
+    stats_compactions: "♻️ Context compactions: {count}",
+    stats_no_session: "⚪ No active session",
+    stats_tokens_title: "\n🧠 <b>Token Usage</b>",
+    stats_cache_tokens: "Cache: {count} tokens",
+    stats_cache_read: "└─ Read: {count}",
+    stats_cache_create: "└─ Create: {count}",
+    stats_total_tokens: "<b>Total: {count} tokens</b>",
+    stats_cost_title: "\n💰 <b>Estimated Cost</b>",
+    stats_cost_input: "Input: ${cost}",
+    stats_cost_output: "Output: ${cost}",
+    stats_cost_cache: "Cache: ${cost}",
+    stats_cost_total: "<b>Total: ${cost}</b>",
+    stats_average_title: "\n📈 <b>Per Query Average</b>",
+    stats_average_cost: "Cost: ${cost}",
+    stats_no_queries: "\n📭 No queries in this session yet",
+    stats_last_query: "\n🔍 <b>Last Query</b>",
+    stats_pricing: "\n<i>Pricing: {key} rates (model: {model})</i>",
+    provider_usage_title: "\n🌐 <b>Provider Usage</b>",
+    provider_resets_in: " (resets in {time})",
+    provider_usage_percent: "Usage: {percent}%",
+    no_providers: "<i>No providers authenticated</i>",
+    provider_usage_fetched: "<i>fetched {age} ago</i>",
+
+    templates_none: "📝 No prompt templates yet. Add <code>name: prompt</code> lines to \
+<code>{path}</code>; <code>{arg}</code> is replaced by the text after the name.",
+    templates_title: "📝 <b>Prompt templates ({count})</b>",
+    templates_hint: "\n<i>/t &lt;name&gt; [args] to run one. Edit <code>{path}</code> to change them.</i>",
+    template_missing: "❌ No template <code>{name}</code>. /t list shows the available ones.",
+};
+
+pub const IT: Messages = Messages {
+    unauthorized: "Non autorizzato. Chiedi l'accesso al proprietario del bot.",
+    owner_only: "⛔ /{command} è riservato al proprietario del bot.",
+    unknown_command: "Comando sconosciuto: /{command}",
+    rate_limited:
+        "⏳ Troppe richieste: riprova tra {secs}s, {left}/{max} richieste rimaste {window}.",
+    daily_limit_reached: "🚫 Limite giornaliero di token raggiunto ({used}/{cap}). Riprova domani.",
+    this_minute: "in questo minuto",
+    this_hour: "in quest'ora",
+    per_seconds: "ogni {secs}s",
+    error: "❌ Errore: {error}",
+    provider_error: "❌ Errore del provider: {error}\n\n<i>Il modello non è raggiungibile o ha \
+rifiutato la richiesta; questa non è una risposta. Invia /retry per riprovare.</i>",
+    provider_error_retrying: "⚠️ Modello non raggiungibile, nuovo tentativo...",
+    model_crashed_retrying: "⚠️ Claude si è interrotto, nuovo tentativo...",
+    stopped: "⏹ Interrotto.",
+    waiting_for_choice: "⏳ In attesa della tua scelta.",
+    timed_out: "⏱ Tempo scaduto.",
+
+    help: "🤖 <b>Claude Telegram Bot (Rust)</b>\n\n\
+Stato: {status}\n\
+Cartella di lavoro: <code>{work_dir}</code>\n\n\
+<b>📋 Comandi:</b>\n\
+/start - Mostra questo messaggio di aiuto\n\
+/new - Inizia una nuova sessione\n\
+/stop - Ferma la richiesta in corso (in silenzio)\n\
+/status - Mostra lo stato della sessione\n\
+/model [nome|default] - Mostra o imposta il modello Claude\n\
+/think [off|normal|deep|N|default] - Budget di ragionamento\n\
+/prompt [use &lt;nome&gt;|show|clear] - Profilo aggiunto al prompt di sistema\n\
+/vocab [add|remove|list] - Parole che la trascrizione vocale deve scrivere giuste\n\
+/settings - Impostazioni di visualizzazione e lingua di questa chat\n\
+/stats - Statistiche di token e costi\n\
+/export - Scarica questa sessione in Markdown\n\
+/diff [percorso] - Modifiche non committate nella cartella di lavoro\n\
+/commit &lt;messaggio&gt; - Aggiungi e committa tutte le modifiche\n\
+/undo - Ripristina i file modificati dall'ultimo turno\n\
+/limits - Utilizzo giornaliero/settimanale per utente\n\
+/resume - Riprendi l'ultima sessione salvata\n\
+/sessions - Sessioni salvate (riprendi, rinomina, elimina)\n\
+/title &lt;testo&gt; - Rinomina la sessione corrente\n\
+/retry - Riprova l'ultimo messaggio\n\
+/t &lt;nome&gt; [argomenti] - Esegui un modello da templates.yaml (/t list)\n\
+/cron [reload|test &lt;nome&gt;] - Stato dei job pianificati, ricarica o esecuzione immediata\n\
+/remind &lt;40m|18:30&gt; [ask:]&lt;testo&gt; - Promemoria singolo (list, cancel &lt;id&gt;)\n\
+/mcp [probe] - Server MCP (probe: controllo di funzionamento)\n\
+/health - Controlla CLI, percorsi e strumenti\n\
+/perf - Latenze della pipeline e chiamate a Telegram (proprietario)\n\
+/logs [n] [level|follow|stop] - Ultime righe di log del bot (proprietario)\n\
+/broadcast [--all] &lt;testo&gt; - Scrivi a tutti gli utenti autorizzati (proprietario)\n\
+/grant [cartella] - Consenti una cartella in più in questa chat (proprietario)\n\
+/revoke [cartella] - Revoca una cartella concessa (proprietario)\n\
+/export_state - Sessioni, impostazioni e permessi di tutte le chat in un file (proprietario)\n\
+/restart - Riavvia il bot\n\n\
+<b>💡 Suggerimenti:</b>\n\
+• Inizia un messaggio o una didascalia con <code>!</code> per interrompere la richiesta in corso\n\
+• Scrivi \"think\" per un ragionamento esteso\n\
+• Scrivi \"ultrathink\" per un'analisi approfondita\n\
+• Invia foto, messaggi vocali o documenti\n\
+• Più foto insieme = album (raggruppate in automatico)",
+    session_active: "Sessione attiva",
+    session_none: "Nessuna sessione attiva",
+    session_cleared: "🆕 Sessione azzerata. Il prossimo messaggio ne inizia una nuova.",
+    session_already_active: "C'è già una sessione attiva. Usa prima /new per ricominciare.",
+    query_already_running: "⏳ C'è già una richiesta in corso. Usa prima /stop.",
+    nothing_to_retry: "❌ Nessun messaggio da riprovare.",
+    nothing_to_export: "📭 Ancora niente da esportare.",
+    plan_empty: "Aggiungi una richiesta dopo <code>{prefix}</code> per pianificarla prima.",
+    restarting: "🔄 Riavvio del bot...",
+
+    settings_title: "⚙️ <b>Impostazioni di questa chat</b>",
+    settings_overridden: " (questa chat)",
+    settings_throttle_hint:
+        "/settings throttle &lt;{min}-{max} ms|default&gt; cambia la frequenza di aggiornamento.",
+    settings_language_hint: "/settings language &lt;{codes}|default&gt; cambia la lingua.",
+    settings_tools_hint: "/settings allowed_tools|disallowed_tools &lt;Tool,...|none|default&gt; \
+limita gli strumenti che Claude può usare (solo proprietario).",
+    settings_usage: "Uso: /settings [throttle &lt;ms|default&gt;|language &lt;codice|default&gt;|\
+allowed_tools &lt;elenco|none|default&gt;|disallowed_tools &lt;elenco|none|default&gt;|reset]",
+    settings_throttle_usage: "Uso: /settings throttle &lt;ms|default&gt;",
+    settings_language_usage: "Uso: /settings language &lt;{codes}|default&gt;",
+    settings_tools_usage: "Uso: /settings {key} &lt;Tool,...|none|default&gt;",
+    settings_reset_button: "↩️ Ripristina i valori predefiniti",
+    settings_reset_done: "Valori predefiniti ripristinati",
+    settings_unknown: "Impostazione sconosciuta",
+    settings_save_failed: "Impossibile salvare le impostazioni",
+    invalid_callback: "Dati del pulsante non validi",
+    on: "sì",
+    off: "no",
+    delete_thinking_messages: "Elimina i messaggi di ragionamento",
+    delete_tool_messages: "Elimina i messaggi degli strumenti",
+    show_tool_outputs: "Mostra l'output degli strumenti",
+    streaming_throttle: "Frequenza di aggiornamento",
+    language: "Lingua",
+    allowed_tools: "Strumenti consentiti",
+    disallowed_tools: "Strumenti vietati",
+    all_tools: "tutti",
+    no_tools: "nessuno",
+
+    working: "Al lavoro...",
+    completed: "Completato",
+    waiting_for_answer: "In attesa della tua risposta...",
+    code_file_caption: "{name} ({lines} righe)",
+    context_limit_warning: "⚠️ La sessione è vicina al limite di contesto — valuta /new o /export",
+    context_compacted: "♻️ Contesto compattato — i messaggi più vecchi sono stati riassunti",
+    context_compacted_was: "♻️ Contesto compattato — i messaggi più vecchi sono stati riassunti (erano {tokens} token)",
+    budget_exceeded: "💸 Budget superato (${spent} su ${limit})",
+    blocked: "🔒 BLOCCATO: {reason}",
+    blocked_needs_full_access: "🔒 BLOCCATO: {tool} richiede l'accesso completo (hai accesso in sola lettura)",
+    access_denied: "🔒 Accesso negato: {path}",
+    access_denied_read_only: "🔒 Accesso negato (sola lettura): {path}",
+    blocked_fetch: "🌐 Download bloccato da {host}",
+    blocked_search: "🌐 Ricerca bloccata su {host}",
+    shutdown_notice: "⚠️ Il bot si sta arrestando, richiesta interrotta",
+    plan_ready: "📝 Piano pronto. Eseguirlo?",
+
+    retrying: "🔄 Nuovo tentativo: \"{preview}\"",
+    truncated: "… (troncato)",
+    now: "ora",
+    ago: "{age} fa",
+    none: "nessuno",
+    input_tokens: "Input: {count} token",
+    output_tokens: "Output: {count} token",
+    cache_read_tokens: "Cache letta: {count}",
+
+    status_title: "<b>Stato del bot</b>\n",
+    status_session_active: "Sessione: attiva ({id}...)",
+    status_session_none: "Sessione: nessuna",
+    status_session_duration: "Durata: {duration} | {queries} richieste",
+    status_idle_for: "Inattiva da {idle}",
+    status_query_running: "Richiesta: in corso",
+    status_query_idle: "Richiesta: nessuna",
+    status_model: "Modello: {model}",
+    status_thinking: "Ragionamento: {mode}",
+    status_tools: "Strumenti: {tools}",
+    tools_only: "solo {allowed}",
+    tools_all_except: "tutti tranne {disallowed}",
+    tools_only_except: "solo {allowed}, tranne {disallowed}",
+    status_last_usage: "Utilizzo dell'ultima richiesta:",
+    status_working_dir: "Cartella di lavoro: <code>{dir}</code>",
+    status_access: "Il tuo accesso: {role}",
+    status_safety_blocks: "Blocchi di sicurezza:",
+    status_granted_dirs: "Cartelle concesse:",
+    status_grant_expires: " (scade tra {duration})",
+
+    model_default: "{model} (predefinito)",
+    model_cli_default: "predefinito della CLI",
+    model_show: "🧠 <b>Modello</b>: {model}\n\n\
+Alias: {aliases}\n\
+Funzionano anche gli id completi (es. <code>claude-sonnet-4-5</code>).\n\n\
+<i>/model &lt;nome&gt; per cambiarlo, /model default per tornare al predefinito.</i>",
+    model_set: "✅ Modello impostato: {model}",
+    thinking_auto: "automatico (parole chiave, predefinito {mode})",
+    think_show: "💭 <b>Ragionamento</b>: {mode}\n\n\
+<i>/think off|normal|deep|&lt;token&gt; per forzarlo, /think default per deciderlo dalle parole chiave.</i>",
+    think_set: "✅ Ragionamento impostato: {mode}",
+    prompt_set: "✅ Profilo del prompt impostato: <code>{name}</code>",
+    prompt_cleared: "✅ Profilo del prompt rimosso",
+    prompt_current: "📝 <b>Profilo del prompt</b>: <code>{name}</code>\n\n{preview}",
+    prompt_none: "📝 <b>Profilo del prompt</b>: nessuno (solo il prompt di sicurezza)",
+    prompt_available: "\n\nDisponibili: {profiles}\n\
+<i>I profili sono file .md in <code>{dir}</code>. /prompt use &lt;nome&gt; per sceglierne uno, /prompt clear per rimuoverlo.</i>",
+    prompt_usage: "Uso: /prompt [use &lt;nome&gt;|show|clear]",
+
+    vocab_already_known: "Già nel vocabolario.",
+    vocab_added: "✅ Aggiunti: {terms}",
+    vocab_removed: "✅ Rimossi: {terms}",
+    vocab_not_found: "Non nel vocabolario: {terms}",
+    vocab_empty: "📖 <b>Vocabolario</b>: vuoto\n\n\
+<i>/vocab add termine1, termine2 aiuta la trascrizione a scrivere nomi e gergo.</i>",
+    vocab_list: "📖 <b>Vocabolario</b> ({count}/{max})\n\n{terms}",
+    vocab_usage: "Uso: /vocab [add &lt;termini&gt;|remove &lt;termine&gt;|list]",
+
+    sessions_title: "🗂 <b>Sessioni salvate</b> ({count})\n",
+    sessions_current: " · <i>attuale</i>",
+    sessions_untitled: "Sessione {id}",
+    sessions_resume_button: "▶️ {number}. Riprendi",
+    sessions_rename_button: "✏️ Rinomina",
+    sessions_delete_button: "🗑 Elimina",
+    sessions_none: "Ancora nessuna sessione salvata in questa chat.",
+    sessions_none_left: "Non restano sessioni salvate in questa chat.",
+    sessions_outdated: "Questo elenco non è aggiornato; invia di nuovo /sessions",
+    sessions_busy: "Attendi la fine della richiesta in corso",
+    session_not_saved: "Nessuna sessione salvata",
+    session_other_dir: "La sessione era di un'altra cartella: {dir}",
+    session_resumed_at: "Ripresa la sessione `{id}` (salvata il {saved_at})",
+    session_no_longer_saved: "Quella sessione non è più salvata",
+    session_resumed: "Ripresa la sessione \"{title}\"",
+    sessions_rename_prompt: "✏️ Invia il nuovo titolo di <b>{title}</b> come prossimo messaggio entro 2 minuti. /cancel o qualsiasi altro comando mantiene il titolo attuale.",
+    sessions_rename_cancelled: "Rinomina annullata.",
+    sessions_deleted: "🗑 Eliminata \"{title}\"",
+    session_renamed: "🏷 Sessione rinominata in <b>{title}</b>",
+    session_title: "🏷 <b>{title}</b>\n\n<i>/title &lt;testo&gt; la rinomina.</i>",
+    title_usage: "Uso: /title &lt;testo&gt;",
+
+    cron_test_usage: "Uso: /cron test &lt;nome&gt;",
+    cron_test_started: "🧪 Eseguo <b>{name}</b> ora; il risultato arriverà qui.",
+    cron_none_found: "⚠️ Nessuna pianificazione in cron.yaml",
+    cron_reloaded_one: "🔄 Ricaricato 1 job pianificato",
+    cron_reloaded: "🔄 Ricaricati {count} job pianificati",
+    cron_skipped: "⚠️ Voci di cron.yaml con problemi ignorate:\n<pre>{errors}</pre>",
+    cron_hint: "\n\n<i>Le modifiche a cron.yaml vengono rilevate in automatico.\n\
+Puoi anche usare /cron reload per ricaricarlo, o /cron test &lt;nome&gt; per eseguire subito un job.</i>",
+    cron_no_jobs: "Nessun job pianificato",
+    cron_jobs_title: "<b>Job pianificati ({count})</b>",
+    cron_next_at: "{name}: prossimo alle {time}",
+    cron_never: "{name}: mai",
+    cron_queued_title: "<b>Job in coda ({count})</b>",
+    reminders_title: "<b>Promemoria ({count})</b>",
+    reminders_none: "Nessun promemoria in attesa in questa chat.",
+    reminder_set: "Promemoria #{id} impostato per le {time}.",
+    reminder_set_ask: "Promemoria #{id} impostato per le {time}; verrà eseguito come richiesta.",
+    reminder_cancelled: "Promemoria #{id} annullato.",
+    reminder_not_found: "Nessun promemoria #{id} in questa chat.",
+    remind_usage: "Uso: /remind &lt;40m|2h30m|18:30&gt; [ask:]&lt;testo&gt;\n\
+/remind list · /remind cancel &lt;id&gt;",
+    remind_cancel_usage: "Uso: /remind cancel &lt;id&gt;",
+    logs_usage: "Uso: /logs [n] [info|warn|error] · /logs follow · /logs stop",
+
+    mcp_none: "🔌 Nessun server MCP configurato (mcp-config.json)",
+    mcp_load_failed: "❌ Impossibile caricare la configurazione MCP: {error}",
+    mcp_title: "🔌 <b>Server MCP</b> ({count})\n",
+    mcp_ok_no_tools: "✅ OK (nessuno strumento)",
+    mcp_not_probed: "⚪ non verificato (http)",
+    mcp_probe_hint: "\n<i>Usa /mcp probe per verificare i server stdio.</i>",
+    broadcast_usage: "Uso: /broadcast [--all] &lt;testo&gt;",
+    transcript_caption: "📝 Trascrizione della sessione",
+    state_export_caption: "📦 Stato del bot. Ripristinalo su un altro server con: ctb import-state <file>",
+    state_export_sent: "📦 Esportazione dello stato inviata in privato.",
+    state_export_failed: "❌ Esportazione dello stato non riuscita: {error}",
+
+    diff_clean: "✨ Nessuna modifica in <code>{dir}</code>.",
+    diff_title: "📝 <b>Modifiche</b> in <code>{dir}</code>",
+    diff_caption_truncated: "🩹 Diff (troncato)",
+    commit_needs_full_access: "⛔ /commit richiede l'accesso completo.",
+    commit_usage: "Uso: /commit &lt;messaggio&gt;",
+    committed: "✅ Commit <code>{hash}</code>: {message}",
+    nothing_to_commit: "✨ Niente da committare, nessuna modifica.",
+    grant_usage: "Uso: /{command} &lt;cartella assoluta&gt;",
+    granted: "🔓 Concessa <code>{dir}</code>. Vale dal prossimo messaggio.",
+    granted_for: "🔓 Concessa <code>{dir}</code> per {duration}. Vale dal prossimo messaggio.",
+    revoked: "🔒 Revocata <code>{dir}</code>. Vale dal prossimo messaggio.",
+    no_grant: "Nessun permesso per <code>{dir}</code>.",
+
+    limits_title: "📒 <b>Limiti di utilizzo</b>\n",
+    limits_cap: "Tetto giornaliero di token: <b>{cap}</b> per utente",
+    limits_no_cap: "Tetto giornaliero di token: <i>nessuno</i>",
+    limits_empty: "\n📭 Nessun utilizzo registrato questa settimana",
+    limits_totals: "{tokens} token · ${cost} · {turns} turni",
+    limits_today: "Oggi: {totals}",
+    limits_today_of_cap: "Oggi: {totals} ({percent}% del tetto)",
+    limits_week: "Questa settimana: {totals}",
+    limits_failed: "❌ Impossibile leggere il registro di utilizzo: {error}",
+    stats_title: "📊 <b>Statistiche della sessione</b>\n",
+    stats_duration: "⏱️ Durata della sessione: {duration}",
+    stats_queries: "🔢 Richieste totali: {count}",
+    stats_compactions: "♻️ Compattazioni del contesto: {count}",
+    stats_no_session: "⚪ Nessuna sessione attiva",
+    stats_tokens_title: "\n🧠 <b>Utilizzo dei token</b>",
+    stats_cache_tokens: "Cache: {count} token",
+    stats_cache_read: "└─ Lettura: {count}",
+    stats_cache_create: "└─ Scrittura: {count}",
+    stats_total_tokens: "<b>Totale: {count} token</b>",
+    stats_cost_title: "\n💰 <b>Costo stimato</b>",
+    stats_cost_total: "<b>Totale: ${cost}</b>",
+    stats_average_title: "\n📈 <b>Media per richiesta</b>",
+    stats_average_cost: "Costo: ${cost}",
+    stats_no_queries: "\n📭 Ancora nessuna richiesta in questa sessione",
+    stats_last_query: "\n🔍 <b>Ultima richiesta</b>",
+    stats_pricing: "\n<i>Prezzi: tariffe {key} (modello: {model})</i>",
+    provider_usage_title: "\n🌐 <b>Utilizzo dei provider</b>",
+    provider_resets_in: " (si azzera tra {time})",
+    provider_usage_percent: "Utilizzo: {percent}%",
+    no_providers: "<i>Nessun provider autenticato</i>",
+    provider_usage_fetched: "<i>aggiornato {age} fa</i>",
+
+    templates_none: "📝 Ancora nessun modello di prompt. Aggiungi righe <code>nome: prompt</code> a \
+<code>{path}</code>; <code>{arg}</code> viene sostituito dal testo dopo il nome.",
+    templates_title: "📝 <b>Modelli di prompt ({count})</b>",
+    templates_hint: "\n<i>/t &lt;nome&gt; [argomenti] per eseguirne uno. Modifica <code>{path}</code> per cambiarli.</i>",
+    template_missing: "❌ Nessun modello <code>{name}</code>. /t list mostra quelli disponibili.",
+    ..EN
+};
+
+/// Every field of [`Messages`]; the struct literal in `map` makes the compiler check the list.
+macro_rules! fields {
+    ($($name:ident),* $(,)?) => {
+        impl Messages {
+            fn map(&self, f: impl Fn(&'static str) -> &'static str) -> Messages {
+                Messages {
+                    $($name: f(self.$name)),*
+                }
+            }
+
+            /// Every template in the catalog, unfilled.
+            pub fn templates(&self) -> Vec<&'static str> {
+                vec![$(self.$name),*]
+            }
+        }
+    };
+}
+
+fields!(
+    unauthorized,
+    owner_only,
+    unknown_command,
+    rate_limited,
+    daily_limit_reached,
+    this_minute,
+    this_hour,
+    per_seconds,
+    error,
+    provider_error,
+    provider_error_retrying,
+    model_crashed_retrying,
+    stopped,
+    waiting_for_choice,
+    timed_out,
+    help,
+    session_active,
+    session_none,
+    session_cleared,
+    session_already_active,
+    query_already_running,
+    nothing_to_retry,
+    nothing_to_export,
+    plan_empty,
+    restarting,
+    settings_title,
+    settings_overridden,
+    settings_throttle_hint,
+    settings_language_hint,
+    settings_tools_hint,
+    settings_usage,
+    settings_throttle_usage,
+    settings_language_usage,
+    settings_tools_usage,
+    settings_reset_button,
+    settings_reset_done,
+    settings_unknown,
+    settings_save_failed,
+    invalid_callback,
+    on,
+    off,
+    delete_thinking_messages,
+    delete_tool_messages,
+    show_tool_outputs,
+    streaming_throttle,
+    language,
+    allowed_tools,
+    disallowed_tools,
+    all_tools,
+    no_tools,
+    working,
+    completed,
+    waiting_for_answer,
+    code_file_caption,
+    code_sent_as_file,
+    context_limit_warning,
+    context_compacted,
+    context_compacted_was,
+    budget_exceeded,
+    blocked,
+    blocked_needs_full_access,
+    access_denied,
+    access_denied_read_only,
+    blocked_fetch,
+    blocked_search,
+    shutdown_notice,
+    plan_ready,
+    failed,
+    succeeded,
+    warning,
+    retrying,
+    truncated,
+    now,
+    ago,
+    none,
+    input_tokens,
+    output_tokens,
+    cache_read_tokens,
+    status_title,
+    status_session_active,
+    status_session_none,
+    status_session_duration,
+    status_idle_for,
+    status_query_running,
+    status_query_idle,
+    status_model,
+    status_thinking,
+    status_tools,
+    tools_only,
+    tools_all_except,
+    tools_only_except,
+    status_cli_version,
+    status_last_usage,
+    status_working_dir,
+    status_access,
+    status_safety_blocks,
+    status_granted_dirs,
+    status_grant_expires,
+    model_default,
+    model_cli_default,
+    model_show,
+    model_set,
+    thinking_auto,
+    think_show,
+    think_set,
+    prompt_set,
+    prompt_cleared,
+    prompt_current,
+    prompt_none,
+    prompt_available,
+    prompt_usage,
+    vocab_already_known,
+    vocab_added,
+    vocab_removed,
+    vocab_not_found,
+    vocab_empty,
+    vocab_list,
+    vocab_usage,
+    sessions_title,
+    sessions_current,
+    sessions_untitled,
+    sessions_resume_button,
+    sessions_rename_button,
+    sessions_delete_button,
+    sessions_none,
+    sessions_none_left,
+    sessions_outdated,
+    sessions_busy,
+    session_not_saved,
+    session_other_dir,
+    session_resumed_at,
+    session_no_longer_saved,
+    session_resumed,
+    sessions_rename_prompt,
+    sessions_rename_cancelled,
+    sessions_deleted,
+    session_renamed,
+    session_title,
+    title_usage,
+    cron_test_usage,
+    cron_test_started,
+    cron_none_found,
+    cron_reloaded_one,
+    cron_reloaded,
+    cron_skipped,
+    cron_hint,
+    cron_no_jobs,
+    cron_jobs_title,
+    cron_next_at,
+    cron_never,
+    cron_queued_title,
+    reminders_title,
+    reminders_none,
+    reminder_set,
+    reminder_set_ask,
+    reminder_cancelled,
+    reminder_not_found,
+    remind_usage,
+    remind_cancel_usage,
+    logs_usage,
+    mcp_none,
+    mcp_load_failed,
+    mcp_title,
+    mcp_ok_no_tools,
+    mcp_not_probed,
+    mcp_probe_hint,
+    broadcast_usage,
+    transcript_caption,
+    state_export_caption,
+    state_export_sent,
+    state_export_failed,
+    diff_clean,
+    diff_title,
+    diff_caption,
+    diff_caption_truncated,
+    commit_needs_full_access,
+    commit_usage,
+    committed,
+    nothing_to_commit,
+    grant_usage,
+    granted,
+    granted_for,
+    revoked,
+    no_grant,
+    limits_title,
+    limits_cap,
+    limits_no_cap,
+    limits_empty,
+    limits_totals,
+    limits_today,
+    limits_today_of_cap,
+    limits_week,
+    limits_failed,
+    stats_title,
+    stats_duration,
+    stats_queries,
+    stats_compactions,
+    stats_no_session,
+    stats_tokens_title,
+    stats_cache_tokens,
+    stats_cache_read,
+    stats_cache_create,
+    stats_total_tokens,
+    stats_cost_title,
+    stats_cost_input,
+    stats_cost_output,
+    stats_cost_cache,
+    stats_cost_total,
+    stats_average_title,
+    stats_average_cost,
+    stats_no_queries,
+    stats_last_query,
+    stats_pricing,
+    provider_usage_title,
+    provider_resets_in,
+    provider_usage_percent,
+    no_providers,
+    provider_usage_fetched,
+    templates_none,
+    templates_title,
+    templates_hint,
+    template_missing,
+);
+
+/// Accessors for strings without parameters.
+macro_rules! plain {
+    ($($name:ident),* $(,)?) => {
+        $(
+            pub fn $name(&self) -> &'static str {
+                self.$name
+            }
+        )*
+    };
+}
+
+/// Accessors for strings with one parameter, named like the argument.
+macro_rules! one {
+    ($($name:ident($param:ident: $ty:ty)),* $(,)?) => {
+        $(
+            pub fn $name(&self, $param: $ty) -> String {
+                fill(self.$name, &[(stringify!($param), &$param)])
+            }
+        )*
+    };
+}
+
+/// Accessors for a dollar amount, shown with four decimals.
+macro_rules! cost {
+    ($($name:ident),* $(,)?) => {
+        $(
+            pub fn $name(&self, usd: f64) -> String {
+                fill(self.$name, &[("cost", &format!("{usd:.4}"))])
+            }
+        )*
+    };
+}
+
+impl Messages {
+    /// This catalog drawn with `theme`'s glyphs (see [`StatusTheme::render`]); each catalog and
+    /// theme pair is rendered once.
+    pub fn themed(&'static self, theme: &'static StatusTheme) -> &'static Messages {
+        if theme.emoji {
+            return self;
+        }
+        static THEMED: OnceLock<Mutex<HashMap<(usize, &'static str), &'static Messages>>> =
+            OnceLock::new();
+        let key = (self as *const Messages as usize, theme.name);
+        let mut themed = THEMED
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        themed.entry(key).or_insert_with(|| {
+            Box::leak(Box::new(self.map(|s| match theme.render(s) {
+                Cow::Borrowed(s) => s,
+                Cow::Owned(s) => Box::leak(s.into_boxed_str()),
+            })))
+        })
+    }
+
+    plain!(
+        unauthorized,
+        provider_error_retrying,
+        model_crashed_retrying,
+        session_active,
+        session_none,
+        session_cleared,
+        session_already_active,
+        query_already_running,
+        nothing_to_retry,
+        nothing_to_export,
+        restarting,
+        settings_title,
+        settings_overridden,
+        settings_usage,
+        settings_throttle_usage,
+        settings_reset_button,
+        settings_reset_done,
+        settings_unknown,
+        settings_tools_hint,
+        all_tools,
+        no_tools,
+        settings_save_failed,
+        invalid_callback,
+        working,
+        completed,
+        waiting_for_answer,
+        context_limit_warning,
+        context_compacted,
+        shutdown_notice,
+        plan_ready,
+        truncated,
+        now,
+        none,
+        status_title,
+        status_session_none,
+        status_query_running,
+        status_query_idle,
+        status_last_usage,
+        status_safety_blocks,
+        status_granted_dirs,
+        prompt_cleared,
+        prompt_none,
+        prompt_usage,
+        vocab_already_known,
+        vocab_empty,
+        vocab_usage,
+        sessions_current,
+        sessions_rename_button,
+        sessions_delete_button,
+        sessions_none,
+        sessions_none_left,
+        sessions_rename_cancelled,
+        sessions_outdated,
+        sessions_busy,
+        session_not_saved,
+        session_no_longer_saved,
+        title_usage,
+        cron_test_usage,
+        cron_none_found,
+        cron_hint,
+        cron_no_jobs,
+        cron_never,
+        reminders_none,
+        remind_usage,
+        remind_cancel_usage,
+        logs_usage,
+        mcp_none,
+        mcp_ok_no_tools,
+        mcp_not_probed,
+        mcp_probe_hint,
+        broadcast_usage,
+        transcript_caption,
+        state_export_caption,
+        state_export_sent,
+        diff_caption,
+        diff_caption_truncated,
+        commit_needs_full_access,
+        commit_usage,
+        nothing_to_commit,
+        limits_title,
+        limits_no_cap,
+        limits_empty,
+        stats_title,
+        stats_no_session,
+        stats_tokens_title,
+        stats_cost_title,
+        stats_average_title,
+        stats_no_queries,
+        stats_last_query,
+        provider_usage_title,
+        no_providers,
+    );
+
+    one!(
+        failed(error: &str),
+        plan_empty(prefix: &str),
+        context_compacted_was(tokens: &str),
+        blocked(reason: &str),
+        blocked_needs_full_access(tool: &str),
+        access_denied(path: &str),
+        access_denied_read_only(path: &str),
+        cron_skipped(errors: &str),
+        succeeded(text: &str),
+        warning(error: &str),
+        retrying(preview: &str),
+        input_tokens(count: u64),
+        output_tokens(count: u64),
+        cache_read_tokens(count: u64),
+        status_session_active(id: &str),
+        status_idle_for(idle: &str),
+        status_cli_version(version: &str),
+        status_working_dir(dir: &str),
+        status_access(role: &str),
+        status_grant_expires(duration: &str),
+        prompt_set(name: &str),
+        vocab_added(terms: &str),
+        vocab_removed(terms: &str),
+        vocab_not_found(terms: &str),
+        sessions_title(count: usize),
+        sessions_rename_prompt(title: &str),
+        session_renamed(title: &str),
+        session_title(title: &str),
+        cron_test_started(name: &str),
+        cron_jobs_title(count: usize),
+        cron_queued_title(count: usize),
+        reminders_title(count: usize),
+        reminder_cancelled(id: u32),
+        reminder_not_found(id: u32),
+        mcp_load_failed(error: &str),
+        mcp_title(count: usize),
+        state_export_failed(error: &str),
+        diff_clean(dir: &str),
+        diff_title(dir: &str),
+        grant_usage(command: &str),
+        revoked(dir: &str),
+        no_grant(dir: &str),
+        limits_cap(cap: u64),
+        limits_failed(error: &str),
+        stats_duration(duration: &str),
+        stats_queries(count: u64),
+        stats_compactions(count: u32),
+        stats_cache_tokens(count: u64),
+        stats_cache_read(count: u64),
+        stats_cache_create(count: u64),
+        stats_total_tokens(count: u64),
+        provider_resets_in(time: &str),
+        provider_usage_percent(percent: u32),
+        provider_usage_fetched(age: &str),
+        templates_none(path: &str),
+        templates_title(count: usize),
+        templates_hint(path: &str),
+        template_missing(name: &str),
+    );
+
+    cost!(
+        stats_cost_input,
+        stats_cost_output,
+        stats_cost_cache,
+        stats_cost_total,
+        stats_average_cost,
+    );
+
+    pub fn owner_only(&self, command: &str) -> String {
+        fill(self.owner_only, &[("command", &command)])
+    }
+
+    pub fn unknown_command(&self, command: &str) -> String {
+        fill(self.unknown_command, &[("command", &command)])
+    }
+
+    /// `⏳ Rate limited: try again in 12s, 0/20 requests left this minute.`
+    pub fn rate_limited(&self, retry_secs: u64, left: u64, max: u64, window_secs: u64) -> String {
+        let window = match window_secs {
+            60 => self.this_minute.to_string(),
+            3600 => self.this_hour.to_string(),
+            secs => fill(self.per_seconds, &[("secs", &secs)]),
+        };
+        fill(
+            self.rate_limited,
+            &[
+                ("secs", &retry_secs),
+                ("left", &left),
+                ("max", &max),
+                ("window", &Raw(&window)),
+            ],
+        )
+    }
+
+    pub fn daily_limit_reached(&self, used: u64, cap: u64) -> String {
+        fill(self.daily_limit_reached, &[("used", &used), ("cap", &cap)])
+    }
+
+    /// Reply to a failed turn; `error` is shown as text, never as markup.
+    pub fn error(&self, error: &str) -> String {
+        fill(self.error, &[("error", &error)])
+    }
+
+    /// Reply to a turn the provider failed; `error` is its result text, shown as text.
+    pub fn provider_error(&self, error: &str) -> String {
+        fill(self.provider_error, &[("error", &error)])
+    }
+
+    /// What to tell the chat when a run ends this way; `None` when the cause already explains
+    /// itself (a new prompt follows, or a block/budget/shutdown notice was sent).
+    pub fn cancel_notice(&self, reason: CancelReason) -> Option<&'static str> {
+        match reason {
+            CancelReason::UserStop => Some(self.stopped),
+            CancelReason::AskUser => Some(self.waiting_for_choice),
+            CancelReason::Timeout => Some(self.timed_out),
+            CancelReason::Interrupt
+            | CancelReason::SafetyBlock
+            | CancelReason::Budget
+            | CancelReason::Shutdown => None,
+        }
+    }
+
+    /// `/start` and `/help`.
+    pub fn help(&self, active: bool, work_dir: &str) -> String {
+        let status = if active {
+            self.session_active
+        } else {
+            self.session_none
+        };
+        fill(self.help, &[("status", &status), ("work_dir", &work_dir)])
+    }
+
+    pub fn settings_throttle_hint(&self, min_ms: u64, max_ms: u64) -> String {
+        fill(
+            self.settings_throttle_hint,
+            &[("min", &min_ms), ("max", &max_ms)],
+        )
+    }
+
+    pub fn settings_language_hint(&self) -> String {
+        fill(self.settings_language_hint, &[("codes", &locale_codes())])
+    }
+
+    pub fn settings_language_usage(&self) -> String {
+        fill(self.settings_language_usage, &[("codes", &locale_codes())])
+    }
+
+    /// `setting` is `allowed_tools` or `disallowed_tools`.
+    pub fn settings_tools_usage(&self, setting: ChatSetting) -> String {
+        fill(self.settings_tools_usage, &[("key", &setting.key())])
+    }
+
+    pub fn on_off(&self, on: bool) -> &'static str {
+        if on {
+            self.on
+        } else {
+            self.off
+        }
+    }
+
+    pub fn setting_label(&self, setting: ChatSetting) -> &'static str {
+        match setting {
+            ChatSetting::DeleteThinkingMessages => self.delete_thinking_messages,
+            ChatSetting::DeleteToolMessages => self.delete_tool_messages,
+            ChatSetting::ShowToolOutputs => self.show_tool_outputs,
+            ChatSetting::StreamingThrottle => self.streaming_throttle,
+            ChatSetting::Locale => self.language,
+            ChatSetting::AllowedTools => self.allowed_tools,
+            ChatSetting::DisallowedTools => self.disallowed_tools,
+        }
+    }
+
+    /// Spend and per-turn budget in dollars, four decimals.
+    pub fn budget_exceeded(&self, spent: f64, limit: f64) -> String {
+        fill(
+            self.budget_exceeded,
+            &[
+                ("spent", &format!("{spent:.4}")),
+                ("limit", &format!("{limit:.4}")),
+            ],
+        )
+    }
+
+    /// `action` is `fetch` (WebFetch) or `search` (WebSearch).
+    pub fn blocked_web(&self, action: &str, host: &str) -> String {
+        let template = if action == "search" {
+            self.blocked_search
+        } else {
+            self.blocked_fetch
+        };
+        fill(template, &[("host", &host)])
+    }
+
+    /// Caption of a code block sent as a file.
+    pub fn code_file_caption(&self, name: &str, lines: usize) -> String {
+        fill(
+            self.code_file_caption,
+            &[("name", &name), ("lines", &lines)],
+        )
+    }
+
+    /// Markdown left in the reply where a code block was sent as a file.
+    pub fn code_sent_as_file(&self, name: &str) -> String {
+        fill(self.code_sent_as_file, &[("name", &name)])
+    }
+
+    /// `{age} ago`, e.g. for a saved session's last use.
+    pub fn ago(&self, age: &str) -> String {
+        fill(self.ago, &[("age", &age)])
+    }
+
+    pub fn status_session_duration(&self, duration: &str, queries: u64) -> String {
+        fill(
+            self.status_session_duration,
+            &[("duration", &duration), ("queries", &queries)],
+        )
+    }
+
+    /// `model` is a label from [`Self::model_label`].
+    pub fn status_model(&self, model: &str) -> String {
+        fill(self.status_model, &[("model", &Raw(model))])
+    }
+
+    /// `tools` is a label from [`Self::tool_policy`].
+    pub fn status_tools(&self, tools: &str) -> String {
+        fill(self.status_tools, &[("tools", &Raw(tools))])
+    }
+
+    /// The active model: the `/model` override, else `default` (`CLAUDE_MODEL`), else the
+    /// CLI's own default.
+    pub fn model_label(&self, selected: Option<&str>, default: Option<&str>) -> String {
+        match (selected, default) {
+            (Some(m), _) => escape_html(m),
+            (None, Some(m)) => fill(self.model_default, &[("model", &m)]),
+            (None, None) => self.model_cli_default.to_string(),
+        }
+    }
+
+    /// A chat's CLI tool lists: "all", "only Read, Grep", "all except WebFetch".
+    pub fn tool_policy(&self, allowed: Option<&[String]>, disallowed: Option<&[String]>) -> String {
+        match (
+            allowed.map(|t| t.join(", ")),
+            disallowed.map(|t| t.join(", ")),
+        ) {
+            (None, None) => self.all_tools.to_string(),
+            (Some(a), None) => fill(self.tools_only, &[("allowed", &a)]),
+            (None, Some(d)) => fill(self.tools_all_except, &[("disallowed", &d)]),
+            (Some(a), Some(d)) => fill(
+                self.tools_only_except,
+                &[("allowed", &a), ("disallowed", &d)],
+            ),
+        }
+    }
+
+    /// The thinking budget: the `/think` override, else keyword detection with `default`. Both
+    /// are labels from `thinking_label`.
+    pub fn thinking_mode(&self, selected: Option<&str>, default: &str) -> String {
+        match selected {
+            Some(label) => escape_html(label),
+            None => fill(self.thinking_auto, &[("mode", &default)]),
+        }
+    }
+
+    /// `mode` is a label from [`Self::thinking_mode`].
+    pub fn status_thinking(&self, mode: &str) -> String {
+        fill(self.status_thinking, &[("mode", &Raw(mode))])
+    }
+
+    /// `/think` without arguments; `mode` is a label from [`Self::thinking_mode`].
+    pub fn think_show(&self, mode: &str) -> String {
+        fill(self.think_show, &[("mode", &Raw(mode))])
+    }
+
+    /// `mode` is a label from [`Self::thinking_mode`].
+    pub fn think_set(&self, mode: &str) -> String {
+        fill(self.think_set, &[("mode", &Raw(mode))])
+    }
+
+    /// `/model` without arguments; `model` is a label from [`Self::model_label`].
+    pub fn model_show(&self, model: &str, aliases: &[&str]) -> String {
+        let aliases = aliases
+            .iter()
+            .map(|a| format!("<code>{}</code>", escape_html(a)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        fill(
+            self.model_show,
+            &[("model", &Raw(model)), ("aliases", &Raw(&aliases))],
+        )
+    }
+
+    /// `model` is a label from [`Self::model_label`].
+    pub fn model_set(&self, model: &str) -> String {
+        fill(self.model_set, &[("model", &Raw(model))])
+    }
+
+    /// `preview` is HTML: the profile in a `<pre>` block, or why it can't be read.
+    pub fn prompt_current(&self, name: &str, preview: &str) -> String {
+        fill(
+            self.prompt_current,
+            &[("name", &name), ("preview", &Raw(preview))],
+        )
+    }
+
+    /// The profiles `/prompt use` accepts, found in `dir`.
+    pub fn prompt_available(&self, profiles: &[String], dir: &str) -> String {
+        let profiles = if profiles.is_empty() {
+            self.none.to_string()
+        } else {
+            profiles
+                .iter()
+                .map(|p| format!("<code>{}</code>", escape_html(p)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        fill(
+            self.prompt_available,
+            &[("profiles", &Raw(&profiles)), ("dir", &dir)],
+        )
+    }
+
+    pub fn vocab_list(&self, terms: &[String], max: usize) -> String {
+        fill(
+            self.vocab_list,
+            &[
+                ("count", &terms.len()),
+                ("max", &max),
+                ("terms", &terms.join(", ")),
+            ],
+        )
+    }
+
+    /// Plain text: a button label and callback notices show it as is.
+    pub fn sessions_untitled(&self, id: &str) -> String {
+        fill_text(self.sessions_untitled, &[("id", &id)])
+    }
+
+    /// Plain text, for a button label.
+    pub fn sessions_resume_button(&self, number: usize) -> String {
+        fill_text(self.sessions_resume_button, &[("number", &number)])
+    }
+
+    /// Plain text; callers wrap it in [`Self::failed`].
+    pub fn session_other_dir(&self, dir: &str) -> String {
+        fill_text(self.session_other_dir, &[("dir", &dir)])
+    }
+
+    /// Plain text; callers wrap it in [`Self::succeeded`].
+    pub fn session_resumed_at(&self, id: &str, saved_at: &str) -> String {
+        fill_text(
+            self.session_resumed_at,
+            &[("id", &id), ("saved_at", &saved_at)],
+        )
+    }
+
+    /// Plain text; callers wrap it in [`Self::succeeded`].
+    pub fn session_resumed(&self, title: &str) -> String {
+        fill_text(self.session_resumed, &[("title", &title)])
+    }
+
+    /// Plain text, for a callback notice.
+    pub fn sessions_deleted(&self, title: &str) -> String {
+        fill_text(self.sessions_deleted, &[("title", &title)])
+    }
+
+    pub fn cron_reloaded(&self, count: usize) -> String {
+        match count {
+            1 => self.cron_reloaded_one.to_string(),
+            count => fill(self.cron_reloaded, &[("count", &count)]),
+        }
+    }
+
+    /// A job's next run, `None` when it never fires again.
+    pub fn cron_next_at(&self, name: &str, time: Option<&str>) -> String {
+        match time {
+            Some(time) => fill(self.cron_next_at, &[("name", &name), ("time", &time)]),
+            None => fill(self.cron_never, &[("name", &name)]),
+        }
+    }
+
+    pub fn reminder_set(&self, id: u32, time: &str, ask: bool) -> String {
+        let template = if ask {
+            self.reminder_set_ask
+        } else {
+            self.reminder_set
+        };
+        fill(template, &[("id", &id), ("time", &time)])
+    }
+
+    pub fn committed(&self, hash: &str, message: &str) -> String {
+        fill(self.committed, &[("hash", &hash), ("message", &message)])
+    }
+
+    /// `ttl` is how long the grant lasts, when it expires.
+    pub fn granted(&self, dir: &str, ttl: Option<&str>) -> String {
+        match ttl {
+            Some(duration) => fill(self.granted_for, &[("dir", &dir), ("duration", &duration)]),
+            None => fill(self.granted, &[("dir", &dir)]),
+        }
+    }
+
+    /// `/limits` line for one user's totals: `today`, `week` or `today_of_cap`.
+    pub fn limits_totals(&self, tokens: u64, cost_usd: f64, turns: u64) -> String {
+        fill(
+            self.limits_totals,
+            &[
+                ("tokens", &tokens),
+                ("cost", &format!("{cost_usd:.4}")),
+                ("turns", &turns),
+            ],
+        )
+    }
+
+    /// `totals` is a line from [`Self::limits_totals`]; `percent` is the share of the daily cap.
+    pub fn limits_today(&self, totals: &str, percent: Option<f64>) -> String {
+        match percent {
+            Some(percent) => fill(
+                self.limits_today_of_cap,
+                &[
+                    ("totals", &Raw(totals)),
+                    ("percent", &format!("{percent:.0}")),
+                ],
+            ),
+            None => fill(self.limits_today, &[("totals", &Raw(totals))]),
+        }
+    }
+
+    /// `totals` is a line from [`Self::limits_totals`].
+    pub fn limits_week(&self, totals: &str) -> String {
+        fill(self.limits_week, &[("totals", &Raw(totals))])
+    }
+
+    /// `model` is a label from [`Self::model_label`].
+    pub fn stats_pricing(&self, key: &str, model: &str) -> String {
+        fill(self.stats_pricing, &[("key", &key), ("model", &Raw(model))])
+    }
+}
+
+/// `en|it`, for usage lines.
+fn locale_codes() -> String {
+    Locale::ALL.map(Locale::code).join("|")
+}
+
+/// A parameter that is already HTML (another filled template).
+struct Raw<'a>(&'a str);
+
+trait Param {
+    fn html(&self) -> String;
+    fn text(&self) -> String;
+}
+
+impl<T: Display> Param for T {
+    fn html(&self) -> String {
+        escape_html(&self.to_string())
+    }
+
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Param for Raw<'_> {
+    fn html(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn text(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Replace each `{name}` in `template` with its escaped parameter, in one pass: text coming
+/// from a parameter is never scanned for further placeholders. Unknown names stay as written.
+fn fill(template: &str, params: &[(&str, &dyn Param)]) -> String {
+    substitute(template, params, |value| value.html())
+}
+
+/// [`fill`] for plain-text strings (button labels, callback notices): nothing is escaped.
+fn fill_text(template: &str, params: &[(&str, &dyn Param)]) -> String {
+    substitute(template, params, |value| value.text())
+}
+
+fn substitute(
+    template: &str,
+    params: &[(&str, &dyn Param)],
+    render: impl Fn(&dyn Param) -> String,
+) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let param = after
+            .find('}')
+            .and_then(|close| Some((close, params.iter().find(|(k, _)| *k == &after[..close])?)));
+        match param {
+            Some((close, (_, value))) => {
+                out.push_str(&render(*value));
+                rest = &after[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn italian_catalog_renders_and_falls_back_to_english() {
+        let it = Locale::parse(" IT ").unwrap().messages();
+        assert_eq!(
+            it.session_cleared(),
+            "🆕 Sessione azzerata. Il prossimo messaggio ne inizia una nuova."
+        );
+        assert_eq!(
+            it.rate_limited(12, 0, 20, 60),
+            "⏳ Troppe richieste: riprova tra 12s, 0/20 richieste rimaste in questo minuto."
+        );
+        assert_eq!(
+            it.owner_only("logs"),
+            "⛔ /logs è riservato al proprietario del bot."
+        );
+        assert!(it.help(true, "/srv").contains("Stato: Sessione attiva"));
+        assert_eq!(
+            it.cancel_notice(CancelReason::UserStop),
+            Some("⏹ Interrotto.")
+        );
+        assert_eq!(it.setting_label(ChatSetting::Locale), "Lingua");
+        // Not translated yet: English, not a gap.
+        assert_eq!(it.code_sent_as_file("a.rs"), EN.code_sent_as_file("a.rs"));
+        assert_eq!(Locale::parse("fr"), None);
+    }
+
+    #[test]
+    fn parameters_are_escaped_and_never_expanded() {
+        assert_eq!(
+            EN.error("<b>boom</b> & {error}"),
+            "❌ Error: &lt;b&gt;boom&lt;/b&gt; &amp; {error}"
+        );
+        assert_eq!(
+            EN.help(false, "/home/<me>"),
+            EN.help(false, "x")
+                .replace("<code>x</code>", "<code>/home/&lt;me&gt;</code>")
+        );
+        assert_eq!(
+            EN.rate_limited(3, 1, 5, 90),
+            "⏳ Rate limited: try again in 3s, 1/5 requests left per 90s."
+        );
+        assert_eq!(fill("{a} {b} {", &[("a", &"{b}")]), "{b} {b} {");
+        // Plain-text strings are not HTML, so nothing is escaped.
+        assert_eq!(EN.sessions_deleted("a & <b>"), "🗑 Deleted \"a & <b>\"");
+        assert_eq!(EN.vocab_added("<x>"), "✅ Added &lt;x&gt;");
+    }
+
+    #[test]
+    fn command_replies_come_from_the_catalog() {
+        let it = Locale::It.messages();
+        assert_eq!(it.cron_reloaded(1), "🔄 Ricaricato 1 job pianificato");
+        assert_eq!(it.cron_reloaded(3), "🔄 Ricaricati 3 job pianificati");
+        assert_eq!(it.cron_next_at("backup", None), "backup: mai");
+        assert_eq!(
+            it.granted("/srv/a", Some("1h 0m 0s")),
+            "🔓 Concessa <code>/srv/a</code> per 1h 0m 0s. Vale dal prossimo messaggio."
+        );
+        assert_eq!(
+            it.limits_today(&it.limits_totals(1200, 0.5, 3), Some(12.0)),
+            "Oggi: 1200 token · $0.5000 · 3 turni (12% del tetto)"
+        );
+        assert_eq!(it.stats_cost_total(1.23456), "<b>Totale: $1.2346</b>");
+        assert_eq!(
+            EN.model_show(&EN.model_label(None, Some("opus")), &["sonnet"]),
+            "🧠 <b>Model</b>: opus (default)\n\nAliases: <code>sonnet</code>\n\
+Full model ids (e.g. <code>claude-sonnet-4-5</code>) also work.\n\n\
+<i>/model &lt;name&gt; to switch, /model default to clear.</i>"
+        );
+        assert_eq!(
+            EN.templates_none("/t.yaml"),
+            "📝 No prompt templates yet. Add <code>name: prompt</code> lines to \
+<code>/t.yaml</code>; <code>{arg}</code> is replaced by the text after the name."
+        );
+    }
+
+    #[test]
+    fn streaming_and_resume_notices_are_translated() {
+        let it = Locale::It.messages();
+        for (en, it) in [
+            (EN.context_limit_warning(), it.context_limit_warning()),
+            (EN.context_compacted(), it.context_compacted()),
+            (EN.shutdown_notice(), it.shutdown_notice()),
+            (EN.plan_ready(), it.plan_ready()),
+            (EN.session_not_saved(), it.session_not_saved()),
+            (EN.session_no_longer_saved(), it.session_no_longer_saved()),
+        ] {
+            assert_ne!(en, it);
+        }
+        for (en, it) in [
+            (
+                EN.context_compacted_was("1k"),
+                it.context_compacted_was("1k"),
+            ),
+            (EN.budget_exceeded(1.0, 0.5), it.budget_exceeded(1.0, 0.5)),
+            (EN.blocked("x"), it.blocked("x")),
+            (
+                EN.blocked_needs_full_access("Bash"),
+                it.blocked_needs_full_access("Bash"),
+            ),
+            (EN.access_denied("/a"), it.access_denied("/a")),
+            (
+                EN.access_denied_read_only("/a"),
+                it.access_denied_read_only("/a"),
+            ),
+            (EN.blocked_web("fetch", "h"), it.blocked_web("fetch", "h")),
+            (EN.blocked_web("search", "h"), it.blocked_web("search", "h")),
+            (EN.session_other_dir("/a"), it.session_other_dir("/a")),
+            (
+                EN.session_resumed_at("s", "t"),
+                it.session_resumed_at("s", "t"),
+            ),
+            (EN.session_resumed("t"), it.session_resumed("t")),
+        ] {
+            assert_ne!(en, it);
+        }
+        assert_eq!(
+            EN.budget_exceeded(0.012, 0.01),
+            "💸 Budget exceeded ($0.0120 of $0.0100)"
+        );
+        assert_eq!(
+            EN.access_denied_read_only("/a<b>"),
+            "🔒 Access denied (read-only): /a&lt;b&gt;"
+        );
+        // Plain text: the resume replies are escaped once, by `succeeded`/`failed`.
+        assert_eq!(EN.session_resumed("a & b"), "Resumed session \"a & b\"");
+    }
+
+    #[test]
+    fn tool_policy_reads_as_a_sentence() {
+        let allowed = ["Read".to_string(), "Bash(git log:*)".to_string()];
+        let disallowed = ["WebFetch".to_string(), "WebSearch".to_string()];
+        assert_eq!(EN.tool_policy(None, None), "all");
+        assert_eq!(
+            EN.tool_policy(None, Some(&disallowed)),
+            "all except WebFetch, WebSearch"
+        );
+        assert_eq!(
+            EN.tool_policy(Some(&allowed), Some(&disallowed)),
+            "only Read, Bash(git log:*), except WebFetch, WebSearch"
+        );
+        assert_eq!(
+            Locale::It.messages().tool_policy(Some(&allowed), None),
+            "solo Read, Bash(git log:*)"
+        );
+    }
+}
@@ -3,21 +3,65 @@
 //! This crate is intentionally framework-agnostic. Telegram / Claude CLI / OpenAI
 //! live behind ports (traits) implemented in adapter crates.
 
+/// `eprintln!` that also keeps the line for `/logs` (see [`logging::write_line`]).
+#[macro_export]
+macro_rules! log_line {
+    ($($arg:tt)*) => {
+        $crate::logging::write_line(&format!($($arg)*))
+    };
+}
+
+/// `println!` that also keeps the line for `/logs` (see [`logging::write_info`]).
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logging::write_info(&format!($($arg)*))
+    };
+}
+
 pub mod archive_security;
+pub mod ask_user;
+pub mod broadcast;
+pub mod chat_settings;
 pub mod config;
 pub mod domain;
+pub mod entities;
 pub mod errors;
 pub mod formatting;
+pub mod git;
+pub mod grants;
+pub mod health;
+pub mod i18n;
+pub mod location;
 pub mod logging;
 pub mod mcp_config;
 pub mod messaging;
+pub mod metrics;
 pub mod model;
+pub mod ocr;
+pub mod office;
+pub mod pipeline;
+pub mod plan_mode;
 pub mod ports;
+pub mod processes;
+pub mod prompt_profiles;
+pub mod prompt_templates;
+pub mod restart;
 pub mod scheduler;
 pub mod security;
+pub mod security_events;
 pub mod session;
+pub mod session_history;
+pub mod shutdown;
+pub mod state_export;
+pub mod storage;
 pub mod streaming;
+pub mod theme;
+pub mod transcript;
+pub mod undo;
 pub mod usage;
+pub mod usage_ledger;
 pub mod utils;
+pub mod vocab;
 
-pub use errors::{Error, Result};
+pub use errors::{CancelReason, Error, Result};
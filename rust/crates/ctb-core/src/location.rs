@@ -0,0 +1,98 @@
+//! Location / contact messages turned into prompt context.
+//!
+//! Reverse geocoding is optional and pluggable: the bot ships with [`CoordinatesOnly`], which
+//! never resolves a place name, so no geocoding service is required to use location pins.
+
+use async_trait::async_trait;
+
+use crate::Result;
+
+/// Resolve coordinates to a human-readable place (e.g. "Alexanderplatz, Berlin").
+#[async_trait]
+pub trait ReverseGeocoder: Send + Sync {
+    /// `Ok(None)` means "no name for this spot"; the prompt then only carries coordinates.
+    async fn reverse(&self, latitude: f64, longitude: f64) -> Result<Option<String>>;
+}
+
+/// Default geocoder: coordinates only.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CoordinatesOnly;
+
+#[async_trait]
+impl ReverseGeocoder for CoordinatesOnly {
+    async fn reverse(&self, _latitude: f64, _longitude: f64) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Prompt for a shared location pin.
+pub fn location_prompt(
+    latitude: f64,
+    longitude: f64,
+    accuracy_m: Option<f64>,
+    place: Option<&str>,
+) -> String {
+    let mut out = format!("User shared location: {latitude:.6}, {longitude:.6}");
+    if let Some(acc) = accuracy_m {
+        out.push_str(&format!(" (±{acc:.0} m)"));
+    }
+    if let Some(place) = place.map(str::trim).filter(|p| !p.is_empty()) {
+        out.push_str(&format!("\nPlace: {place}"));
+    }
+    out
+}
+
+/// Prompt for a shared contact card.
+pub fn contact_prompt(
+    first_name: &str,
+    last_name: Option<&str>,
+    phone_number: &str,
+    telegram_user_id: Option<u64>,
+) -> String {
+    let name = match last_name.map(str::trim).filter(|l| !l.is_empty()) {
+        Some(last) => format!("{} {last}", first_name.trim()),
+        None => first_name.trim().to_string(),
+    };
+    let mut out = format!(
+        "User shared contact: {name}, phone: {}",
+        phone_number.trim()
+    );
+    if let Some(id) = telegram_user_id {
+        out.push_str(&format!(" (Telegram user id {id})"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_prompt_formats_accuracy_and_place() {
+        assert_eq!(
+            location_prompt(52.520008, 13.404954, None, None),
+            "User shared location: 52.520008, 13.404954"
+        );
+        assert_eq!(
+            location_prompt(52.5, 13.4, Some(14.7), Some(" Berlin ")),
+            "User shared location: 52.500000, 13.400000 (±15 m)\nPlace: Berlin"
+        );
+    }
+
+    #[test]
+    fn contact_prompt_joins_name_and_phone() {
+        assert_eq!(
+            contact_prompt("Ada", Some("Lovelace"), "+44 20 7946 0000", Some(42)),
+            "User shared contact: Ada Lovelace, phone: +44 20 7946 0000 (Telegram user id 42)"
+        );
+        assert_eq!(
+            contact_prompt("Ada", Some(""), "123", None),
+            "User shared contact: Ada, phone: 123"
+        );
+    }
+
+    #[tokio::test]
+    async fn default_geocoder_resolves_nothing() {
+        assert_eq!(CoordinatesOnly.reverse(0.0, 0.0).await.unwrap(), None);
+    }
+}
@@ -1,12 +1,208 @@
+//! Process logging: optional `tracing` subscriber plus an in-memory tail of the log for `/logs`.
+//!
+//! The bot logs tagged lines such as `[CTX] ...` with [`log_line!`](crate::log_line), which
+//! prints to stderr (journald, the terminal) and copies the line into a fixed-size [`LogBuffer`].
+//! The `tracing` subscriber writes through the same tee, and panics are kept as well.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Local};
+
 use crate::Result;
 
-/// Initialize logging/tracing for the bot.
+/// Lines kept by default (`LOG_BUFFER_LINES`).
+pub const DEFAULT_LOG_BUFFER_LINES: usize = 500;
+
+static BUFFER: OnceLock<LogBuffer> = OnceLock::new();
+
+/// Severity guessed from a log line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "info" | "all" => Some(Self::Info),
+            "warn" | "warning" => Some(Self::Warn),
+            "error" | "err" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    /// Level of a free-form line: `eprintln!` output has no level, so go by its wording
+    /// (this also matches `tracing`'s ` WARN ` / ` ERROR ` prefixes).
+    pub fn classify(line: &str) -> Self {
+        let lower = line.to_ascii_lowercase();
+        if ["error", "failed", "panicked", "fatal"]
+            .iter()
+            .any(|w| lower.contains(w))
+        {
+            Self::Error
+        } else if lower.contains("warn") || line.contains('⚠') {
+            Self::Warn
+        } else {
+            Self::Info
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// One captured line.
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    /// Position in the stream of all lines ever captured (see [`LogBuffer::since`]).
+    pub seq: u64,
+    pub at: DateTime<Local>,
+    pub level: LogLevel,
+    pub text: String,
+}
+
+impl LogLine {
+    /// `HH:MM:SS text`.
+    pub fn display(&self) -> String {
+        format!("{} {}", self.at.format("%H:%M:%S"), self.text)
+    }
+}
+
+#[derive(Debug)]
+struct Ring {
+    lines: VecDeque<LogLine>,
+    next_seq: u64,
+}
+
+/// The last `capacity` log lines. Slots are allocated once; a full buffer reuses the oldest
+/// line's `String` for the newest.
+#[derive(Debug)]
+pub struct LogBuffer {
+    capacity: usize,
+    ring: Mutex<Ring>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ring: Mutex::new(Ring {
+                lines: VecDeque::with_capacity(capacity.max(1)),
+                next_seq: 0,
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn push(&self, text: &str) {
+        let text = text.trim_end();
+        let level = LogLevel::classify(text);
+        let mut ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = ring.next_seq;
+        ring.next_seq += 1;
+
+        let mut line = if ring.lines.len() >= self.capacity {
+            ring.lines.pop_front().expect("full ring has lines")
+        } else {
+            LogLine {
+                seq,
+                at: Local::now(),
+                level,
+                text: String::new(),
+            }
+        };
+        line.seq = seq;
+        line.at = Local::now();
+        line.level = level;
+        line.text.clear();
+        line.text.push_str(text);
+        ring.lines.push_back(line);
+    }
+
+    /// The newest `n` lines at `min_level` or above, oldest first.
+    pub fn tail(&self, n: usize, min_level: LogLevel) -> Vec<LogLine> {
+        let ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out: Vec<LogLine> = ring
+            .lines
+            .iter()
+            .rev()
+            .filter(|l| l.level >= min_level)
+            .take(n)
+            .cloned()
+            .collect();
+        out.reverse();
+        out
+    }
+
+    /// Lines captured at or after `seq` (at `min_level` or above), and the `seq` to pass next
+    /// time. Lines already evicted are skipped.
+    pub fn since(&self, seq: u64, min_level: LogLevel) -> (Vec<LogLine>, u64) {
+        let ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+        let lines = ring
+            .lines
+            .iter()
+            .filter(|l| l.seq >= seq && l.level >= min_level)
+            .cloned()
+            .collect();
+        (lines, ring.next_seq)
+    }
+
+    /// `seq` of the next line to be captured.
+    pub fn next_seq(&self) -> u64 {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner()).next_seq
+    }
+}
+
+/// The buffer filled by [`init`], if capture is running.
+pub fn buffer() -> Option<&'static LogBuffer> {
+    BUFFER.get()
+}
+
+/// Print `line` to stderr and, once [`init`] started capture, keep it for `/logs`.
+pub fn write_line(line: &str) {
+    eprintln!("{line}");
+    keep(line);
+}
+
+/// Print `line` to stdout and, once [`init`] started capture, keep it for `/logs`.
+pub fn write_info(line: &str) {
+    println!("{line}");
+    keep(line);
+}
+
+fn keep(text: &str) {
+    if let Some(buffer) = BUFFER.get() {
+        text.lines().for_each(|line| buffer.push(line));
+    }
+}
+
+/// Initialize logging/tracing for the bot and keep the last `buffer_lines` log lines for
+/// `/logs` (0 = no capture).
 ///
-/// In "offline" sandbox builds we keep this as a no-op (feature disabled),
+/// In "offline" sandbox builds the `tracing` subscriber is a no-op (feature disabled),
 /// but the public API stays stable.
-pub fn init(service_name: &str) -> Result<()> {
+pub fn init(service_name: &str, buffer_lines: usize) -> Result<()> {
     let _ = service_name;
 
+    if buffer_lines > 0 && BUFFER.set(LogBuffer::new(buffer_lines)).is_ok() {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            keep(&info.to_string());
+            default_hook(info);
+        }));
+    }
+
     #[cfg(feature = "tracing")]
     {
         use tracing_subscriber::{fmt, EnvFilter};
@@ -17,12 +213,95 @@ pub fn init(service_name: &str) -> Result<()> {
             EnvFilter::new(format!("info,ctb=info,ctb_core=info,{service_name}=info"))
         });
 
+        // stderr, teed into the `/logs` buffer.
         fmt()
             .with_env_filter(filter)
             .with_target(false)
-            .with_ansi(true)
+            .with_ansi(false)
+            .with_writer(|| LogWriter)
             .init();
     }
 
     Ok(())
 }
+
+/// Writer for the `tracing` subscriber: stderr plus the `/logs` buffer.
+#[cfg(feature = "tracing")]
+struct LogWriter;
+
+#[cfg(feature = "tracing")]
+impl std::io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = std::io::stderr().write(buf)?;
+        keep(&String::from_utf8_lossy(&buf[..n]));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_guessed_from_the_wording() {
+        assert_eq!(
+            LogLevel::classify("[CTX] context limit approaching"),
+            LogLevel::Info
+        );
+        assert_eq!(
+            LogLevel::classify("[AUDIT] Failed to write message event: disk full"),
+            LogLevel::Error
+        );
+        assert_eq!(
+            LogLevel::classify("2026-01-01  WARN slow reply"),
+            LogLevel::Warn
+        );
+        assert_eq!(LogLevel::classify("⚠️ budget at 90%"), LogLevel::Warn);
+        assert_eq!(LogLevel::parse("WARNING"), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::parse("debug"), None);
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_lines_and_reuses_slots() {
+        let buf = LogBuffer::new(3);
+        for i in 0..5 {
+            buf.push(&format!("line {i}\n"));
+        }
+        let tail = buf.tail(10, LogLevel::Info);
+        let texts: Vec<&str> = tail.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, ["line 2", "line 3", "line 4"]);
+        assert_eq!(tail.iter().map(|l| l.seq).collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(buf.ring.lock().unwrap().lines.capacity(), 3);
+
+        assert_eq!(buf.tail(2, LogLevel::Info).len(), 2);
+        assert_eq!(buf.tail(2, LogLevel::Info)[1].text, "line 4");
+    }
+
+    #[test]
+    fn tail_and_since_filter_by_level() {
+        let buf = LogBuffer::new(10);
+        buf.push("[SESSION] started");
+        buf.push("[AUDIT] Failed to write");
+        let cursor = buf.next_seq();
+        buf.push("[SESSION] idle");
+        buf.push("warn: slow edit");
+        buf.push("[CLI] error: exited with code 1");
+
+        let errors = buf.tail(10, LogLevel::Error);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].text, "[AUDIT] Failed to write");
+
+        let (new, next) = buf.since(cursor, LogLevel::Warn);
+        let texts: Vec<&str> = new.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            ["warn: slow edit", "[CLI] error: exited with code 1"]
+        );
+        assert_eq!(next, 5);
+        assert!(buf.since(next, LogLevel::Info).0.is_empty());
+    }
+}
@@ -1,12 +1,22 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     env,
     path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
 };
 
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{ChildStdin, ChildStdout, Command},
+};
 
-use crate::Result;
+use crate::{
+    config::{is_executable_file, which_in_path},
+    Error, Result,
+};
 
 /// MCP server configuration (matches Claude's MCP schema).
 ///
@@ -52,6 +62,10 @@ pub fn load_mcp_servers(path: &Path) -> Result<McpServers> {
 /// Load MCP servers from a JSON file and interpolate `${ENV_VAR}` placeholders, with optional
 /// override values (used to inject process-stable context without mutating global process env).
 ///
+/// Every string (command, args, env values, URLs, headers) is interpolated; `$$` escapes a
+/// literal `$`. Unset variables are an error listing all of them, so a broken config never
+/// reaches the CLI.
+///
 /// If the file does not exist, returns an empty map.
 pub fn load_mcp_servers_with_overrides(
     path: &Path,
@@ -63,69 +77,99 @@ pub fn load_mcp_servers_with_overrides(
 
     let raw = std::fs::read_to_string(path)?;
     let value: serde_json::Value = serde_json::from_str(&raw)?;
-    let interpolated = interpolate_env(value, overrides);
+    let mut unresolved = BTreeSet::new();
+    let interpolated = interpolate_env(value, overrides, &mut unresolved);
+    if !unresolved.is_empty() {
+        let names = unresolved.into_iter().collect::<Vec<_>>().join(", ");
+        return Err(Error::Config(format!(
+            "{} references unset variables: {names}",
+            path.display()
+        )));
+    }
     let servers: McpServers = serde_json::from_value(interpolated)?;
     Ok(servers)
 }
 
 /// Recursively interpolate `${VAR}` placeholders in all JSON strings.
-fn interpolate_env(v: serde_json::Value, overrides: &HashMap<String, String>) -> serde_json::Value {
+fn interpolate_env(
+    v: serde_json::Value,
+    overrides: &HashMap<String, String>,
+    unresolved: &mut BTreeSet<String>,
+) -> serde_json::Value {
     match v {
         serde_json::Value::String(s) => {
-            serde_json::Value::String(interpolate_env_str(&s, overrides))
+            serde_json::Value::String(interpolate_env_str(&s, overrides, unresolved))
         }
         serde_json::Value::Array(xs) => serde_json::Value::Array(
             xs.into_iter()
-                .map(|x| interpolate_env(x, overrides))
+                .map(|x| interpolate_env(x, overrides, unresolved))
                 .collect(),
         ),
         serde_json::Value::Object(map) => serde_json::Value::Object(
             map.into_iter()
-                .map(|(k, v)| (k, interpolate_env(v, overrides)))
+                .map(|(k, v)| (k, interpolate_env(v, overrides, unresolved)))
                 .collect(),
         ),
         other => other,
     }
 }
 
-fn interpolate_env_str(s: &str, overrides: &HashMap<String, String>) -> String {
-    // Minimal `${VAR}` expansion (no defaults). Unset vars become empty string.
+fn interpolate_env_str(
+    s: &str,
+    overrides: &HashMap<String, String>,
+    unresolved: &mut BTreeSet<String>,
+) -> String {
+    // Minimal `${VAR}` expansion (no defaults); `$$` is a literal `$`.
     let mut out = String::with_capacity(s.len());
     let mut rest = s;
 
-    while let Some(start) = rest.find("${") {
+    while let Some(start) = rest.find('$') {
         out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
 
-        let after = &rest[start + 2..];
-        let Some(end) = after.find('}') else {
+        if let Some(tail) = after.strip_prefix('$') {
+            out.push('$');
+            rest = tail;
+            continue;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            out.push('$');
+            rest = after;
+            continue;
+        };
+        let Some(end) = body.find('}') else {
             // No closing '}' — treat literally.
             out.push_str("${");
-            rest = after;
+            rest = body;
             continue;
         };
 
-        let name = &after[..end];
-        let val = resolve_env(name, overrides);
-        out.push_str(&val);
-        rest = &after[end + 1..];
+        let name = &body[..end];
+        match resolve_env(name, overrides) {
+            Some(val) => out.push_str(&val),
+            None => {
+                unresolved.insert(name.to_string());
+            }
+        }
+        rest = &body[end + 1..];
     }
 
     out.push_str(rest);
     out
 }
 
-fn resolve_env(name: &str, overrides: &HashMap<String, String>) -> String {
+fn resolve_env(name: &str, overrides: &HashMap<String, String>) -> Option<String> {
     match env::var(name) {
         Ok(v) => {
             // If we have an override for this name, only use it when the env var is
             // empty (parity with the previous CTB_REPO_ROOT "set if empty" behavior).
             if overrides.contains_key(name) && v.trim().is_empty() {
-                overrides.get(name).cloned().unwrap_or_default()
+                overrides.get(name).cloned()
             } else {
-                v
+                Some(v)
             }
         }
-        Err(_) => overrides.get(name).cloned().unwrap_or_default(),
+        Err(_) => overrides.get(name).cloned(),
     }
 }
 
@@ -140,6 +184,152 @@ pub fn default_example_path(repo_root: &Path) -> PathBuf {
     repo_root.join("mcp-config.example.json")
 }
 
+/// Budget for a full `initialize` + `tools/list` handshake in [`probe_stdio_server`].
+pub const MCP_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl McpServerConfig {
+    /// One-line transport description (`stdio: cmd args` / `http: url`) for status output.
+    ///
+    /// URL query strings are dropped since they commonly carry API keys.
+    pub fn transport(&self) -> String {
+        match self {
+            McpServerConfig::Stdio { command, args, .. } if args.is_empty() => {
+                format!("stdio: {command}")
+            }
+            McpServerConfig::Stdio { command, args, .. } => {
+                format!("stdio: {command} {}", args.join(" "))
+            }
+            McpServerConfig::Http { url, .. } => {
+                format!("http: {}", url.split('?').next().unwrap_or(url))
+            }
+        }
+    }
+
+    /// Warning for stdio servers whose command is not an executable file (or not on `PATH`).
+    ///
+    /// Not fatal: the CLI reports the failed server and the rest of the turn still works.
+    pub fn command_warning(&self) -> Option<String> {
+        let McpServerConfig::Stdio { command, .. } = self else {
+            return None;
+        };
+        let found = if command.contains('/') {
+            is_executable_file(Path::new(command))
+        } else {
+            which_in_path(command).is_some()
+        };
+        (!found).then(|| format!("command not found or not executable: {command}"))
+    }
+}
+
+/// Health-check a stdio MCP server: spawn it, run the JSON-RPC handshake and return the names
+/// from `tools/list`.
+///
+/// The child is killed when the probe finishes or times out.
+pub async fn probe_stdio_server(
+    command: &str,
+    args: &[String],
+    env: &HashMap<String, String>,
+    timeout: Duration,
+) -> Result<Vec<String>> {
+    let mut child = Command::new(command)
+        .args(args)
+        .envs(env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| Error::External(format!("failed to spawn {command}: {e}")))?;
+
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(Error::External("MCP server stdio unavailable".to_string()));
+    };
+
+    let handshake = probe_handshake(stdin, BufReader::new(stdout).lines());
+    let result = match tokio::time::timeout(timeout, handshake).await {
+        Ok(r) => r,
+        Err(_) => Err(Error::External(format!(
+            "no response within {}s",
+            timeout.as_secs_f64()
+        ))),
+    };
+    let _ = child.kill().await;
+    result
+}
+
+async fn probe_handshake(
+    mut stdin: ChildStdin,
+    mut lines: Lines<BufReader<ChildStdout>>,
+) -> Result<Vec<String>> {
+    async fn send(stdin: &mut ChildStdin, msg: serde_json::Value) -> Result<()> {
+        stdin.write_all(format!("{msg}\n").as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Skip notifications/log lines until the response for `id` arrives.
+    async fn response(
+        lines: &mut Lines<BufReader<ChildStdout>>,
+        id: u64,
+    ) -> Result<serde_json::Value> {
+        while let Some(line) = lines.next_line().await? {
+            let Ok(msg) = serde_json::from_str::<serde_json::Value>(&line) else {
+                continue;
+            };
+            if msg.get("id").and_then(|v| v.as_u64()) != Some(id) {
+                continue;
+            }
+            if let Some(err) = msg.get("error") {
+                let text = err.get("message").and_then(|m| m.as_str()).unwrap_or("");
+                return Err(Error::External(format!("MCP error: {text}")));
+            }
+            return Ok(msg.get("result").cloned().unwrap_or_default());
+        }
+        Err(Error::External("MCP server closed stdout".to_string()))
+    }
+
+    send(
+        &mut stdin,
+        json!({
+          "jsonrpc": "2.0",
+          "id": 1,
+          "method": "initialize",
+          "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": { "name": "ctb-mcp-probe", "version": "1.0.0" }
+          }
+        }),
+    )
+    .await?;
+    response(&mut lines, 1).await?;
+
+    send(
+        &mut stdin,
+        json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+    )
+    .await?;
+    send(
+        &mut stdin,
+        json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+    )
+    .await?;
+    let result = response(&mut lines, 2).await?;
+
+    let names = result
+        .get("tools")
+        .and_then(|t| t.as_array())
+        .map(|tools| {
+            tools
+                .iter()
+                .filter_map(|t| t.get("name").and_then(|n| n.as_str()))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(names)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -151,10 +341,12 @@ mod tests {
         env::set_var(&key, "abc123");
 
         let s = format!("https://x.test/?k=${{{key}}}");
+        let mut unresolved = BTreeSet::new();
         assert_eq!(
-            interpolate_env_str(&s, &HashMap::new()),
+            interpolate_env_str(&s, &HashMap::new(), &mut unresolved),
             "https://x.test/?k=abc123"
         );
+        assert!(unresolved.is_empty());
 
         match prev {
             Some(v) => env::set_var(&key, v),
@@ -162,6 +354,123 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dollar_dollar_escapes_interpolation() {
+        let mut unresolved = BTreeSet::new();
+        let overrides = HashMap::from([("CTB_TEST_ESC".to_string(), "v".to_string())]);
+        assert_eq!(
+            interpolate_env_str(
+                "$${CTB_TEST_ESC}/${CTB_TEST_ESC}/$5",
+                &overrides,
+                &mut unresolved
+            ),
+            "${CTB_TEST_ESC}/v/$5"
+        );
+        assert!(unresolved.is_empty());
+    }
+
+    #[test]
+    fn interpolates_nested_values_and_reports_every_missing_var() {
+        let tmp = PathBuf::from(format!("/tmp/ctb-mcp-missing-{}.json", std::process::id()));
+        let raw = r#"
+{
+  "local": {
+    "command": "${CTB_REPO_ROOT}/bin/server",
+    "args": ["--token", "${CTB_TEST_MISSING_A}"],
+    "env": { "NESTED": "x-${CTB_TEST_MISSING_B}-${CTB_REPO_ROOT}" }
+  }
+}
+"#;
+        std::fs::write(&tmp, raw).unwrap();
+        let overrides = HashMap::from([("CTB_REPO_ROOT".to_string(), "/repo".to_string())]);
+
+        let err = load_mcp_servers_with_overrides(&tmp, &overrides).unwrap_err();
+        let msg = err.to_string();
+        assert!(matches!(err, Error::Config(_)));
+        assert!(
+            msg.contains("CTB_TEST_MISSING_A, CTB_TEST_MISSING_B"),
+            "{msg}"
+        );
+
+        let ok = raw
+            .replace("${CTB_TEST_MISSING_A}", "t")
+            .replace("${CTB_TEST_MISSING_B}", "$$B");
+        std::fs::write(&tmp, ok).unwrap();
+        let servers = load_mcp_servers_with_overrides(&tmp, &overrides).unwrap();
+        let _ = std::fs::remove_file(&tmp);
+        match servers.get("local").unwrap() {
+            McpServerConfig::Stdio { command, args, env } => {
+                assert_eq!(command, "/repo/bin/server");
+                assert_eq!(args, &vec!["--token".to_string(), "t".to_string()]);
+                assert_eq!(env.get("NESTED").unwrap(), "x-$B-/repo");
+            }
+            _ => panic!("expected stdio config"),
+        }
+    }
+
+    #[test]
+    fn command_warning_flags_missing_executables() {
+        let server = |command: &str| McpServerConfig::Stdio {
+            command: command.to_string(),
+            args: vec![],
+            env: HashMap::new(),
+        };
+        assert_eq!(server("sh").command_warning(), None);
+        assert_eq!(server("/bin/sh").command_warning(), None);
+        assert!(server("/nonexistent/mcp-server")
+            .command_warning()
+            .unwrap()
+            .contains("/nonexistent/mcp-server"));
+
+        let plain = format!("/tmp/ctb-mcp-noexec-{}", std::process::id());
+        std::fs::write(&plain, "#!/bin/sh\n").unwrap();
+        let warning = server(&plain).command_warning();
+        let _ = std::fs::remove_file(&plain);
+        assert!(warning.is_some(), "non-executable file must be flagged");
+    }
+
+    #[test]
+    fn transport_describes_stdio_and_http() {
+        let stdio = McpServerConfig::Stdio {
+            command: "node".to_string(),
+            args: vec!["server.js".to_string()],
+            env: HashMap::new(),
+        };
+        assert_eq!(stdio.transport(), "stdio: node server.js");
+        let http = McpServerConfig::Http {
+            kind: McpHttpKind::Http,
+            url: "https://x.test/mcp?API_KEY=secret".to_string(),
+            headers: HashMap::new(),
+        };
+        assert_eq!(http.transport(), "http: https://x.test/mcp");
+    }
+
+    #[tokio::test]
+    async fn probe_reports_spawn_failures() {
+        let err = probe_stdio_server(
+            "/nonexistent/ctb-mcp-server",
+            &[],
+            &HashMap::new(),
+            MCP_PROBE_TIMEOUT,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("failed to spawn"));
+    }
+
+    #[tokio::test]
+    async fn probe_times_out_on_silent_servers() {
+        let err = probe_stdio_server(
+            "sleep",
+            &["5".to_string()],
+            &HashMap::new(),
+            Duration::from_millis(200),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("no response"));
+    }
+
     #[test]
     fn loads_and_interpolates_json() {
         let tmp = PathBuf::from(format!("/tmp/ctb-mcp-{}.json", std::process::id()));
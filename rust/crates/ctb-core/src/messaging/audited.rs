@@ -0,0 +1,266 @@
+use std::{path::Path, sync::Arc};
+
+use crate::{
+    domain::{ChatTarget, MessageRef},
+    messaging::{
+        port::MessagingPort,
+        types::{ChatAction, InlineKeyboard, MessagingCapabilities},
+    },
+    utils::{AuditEvent, AuditLogger},
+    Result,
+};
+
+/// MessagingPort decorator that writes a `delivery` audit event for every send, edit and delete
+/// (`AUDIT_DELIVERY`), so the log shows what reached the messenger after conversion, splitting
+/// and cleanup rather than only the model's text.
+///
+/// Results are passed through untouched; failed calls are recorded with their error.
+pub struct AuditedMessenger {
+    inner: Arc<dyn MessagingPort>,
+    audit: Arc<AuditLogger>,
+}
+
+impl AuditedMessenger {
+    pub fn new(inner: Arc<dyn MessagingPort>, audit: Arc<AuditLogger>) -> Self {
+        Self { inner, audit }
+    }
+
+    fn record<T>(
+        &self,
+        op: &str,
+        chat_id: i64,
+        message_id: Option<i32>,
+        content: Option<&str>,
+        result: &Result<T>,
+    ) {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let event = AuditEvent::delivery(op, chat_id, message_id, content, error.as_deref());
+        if let Err(e) = self.audit.write(event) {
+            log_line!("[AUDIT] Failed to write delivery event: {e}");
+        }
+    }
+
+    /// Record a send: the message id comes from the result.
+    fn record_send(
+        &self,
+        op: &str,
+        target: ChatTarget,
+        content: &str,
+        result: &Result<MessageRef>,
+    ) {
+        let message_id = result.as_ref().ok().map(|m| m.message_id.0);
+        self.record(op, target.chat_id.0, message_id, Some(content), result);
+    }
+}
+
+#[async_trait::async_trait]
+impl MessagingPort for AuditedMessenger {
+    fn capabilities(&self) -> MessagingCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn send_html(&self, target: ChatTarget, html: &str) -> Result<MessageRef> {
+        let result = self.inner.send_html(target, html).await;
+        self.record_send("send", target, html, &result);
+        result
+    }
+
+    async fn edit_html(&self, msg: MessageRef, html: &str) -> Result<()> {
+        let result = self.inner.edit_html(msg, html).await;
+        self.record(
+            "edit",
+            msg.chat_id.0,
+            Some(msg.message_id.0),
+            Some(html),
+            &result,
+        );
+        result
+    }
+
+    async fn edit_html_best_effort(&self, msg: MessageRef, html: &str) -> Result<()> {
+        let result = self.inner.edit_html_best_effort(msg, html).await;
+        self.record(
+            "edit",
+            msg.chat_id.0,
+            Some(msg.message_id.0),
+            Some(html),
+            &result,
+        );
+        result
+    }
+
+    async fn delete_message(&self, msg: MessageRef) -> Result<()> {
+        let result = self.inner.delete_message(msg).await;
+        self.record(
+            "delete",
+            msg.chat_id.0,
+            Some(msg.message_id.0),
+            None,
+            &result,
+        );
+        result
+    }
+
+    async fn send_chat_action(&self, target: ChatTarget, action: ChatAction) -> Result<()> {
+        self.inner.send_chat_action(target, action).await
+    }
+
+    async fn set_reaction(&self, msg: MessageRef, emoji: &str) -> Result<()> {
+        self.inner.set_reaction(msg, emoji).await
+    }
+
+    async fn send_inline_keyboard(
+        &self,
+        target: ChatTarget,
+        text: &str,
+        keyboard: InlineKeyboard,
+    ) -> Result<MessageRef> {
+        let result = self
+            .inner
+            .send_inline_keyboard(target, text, keyboard)
+            .await;
+        self.record_send("send_keyboard", target, text, &result);
+        result
+    }
+
+    async fn edit_inline_keyboard(
+        &self,
+        msg: MessageRef,
+        html: &str,
+        keyboard: Option<InlineKeyboard>,
+    ) -> Result<()> {
+        let result = self.inner.edit_inline_keyboard(msg, html, keyboard).await;
+        self.record(
+            "edit_keyboard",
+            msg.chat_id.0,
+            Some(msg.message_id.0),
+            Some(html),
+            &result,
+        );
+        result
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()> {
+        self.inner
+            .answer_callback_query(callback_id, text, show_alert)
+            .await
+    }
+
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        path: &Path,
+        caption: Option<&str>,
+    ) -> Result<MessageRef> {
+        let result = self.inner.send_document(target, path, caption).await;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let content = match caption {
+            Some(caption) => format!("📎 {file_name}: {caption}"),
+            None => format!("📎 {file_name}"),
+        };
+        self.record_send("send_document", target, &content, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{ChatId, MessageId};
+    use crate::messaging::test_support::{Failure, Op, RecordingMessenger};
+    use crate::utils::DELIVERY_PREVIEW_CHARS;
+
+    fn read_events(audit: &AuditLogger) -> Vec<AuditEvent> {
+        std::fs::read_to_string(audit.path())
+            .unwrap()
+            .lines()
+            .filter_map(AuditEvent::parse_json_line)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn forwards_results_and_records_each_delivery() {
+        let path = std::env::temp_dir().join(format!(
+            "ctb-audited-messenger-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let audit = Arc::new(AuditLogger::new(&path, true));
+        let recording = Arc::new(RecordingMessenger::new());
+        let messenger = AuditedMessenger::new(recording.clone(), audit.clone());
+        let target = ChatTarget::from(ChatId(77));
+
+        let long = format!("<b>{}</b>", "é".repeat(200));
+        let sent = messenger.send_html(target, &long).await.unwrap();
+        assert_eq!(recording.sends(), vec![long.clone()]);
+        messenger.edit_html(sent, "edited").await.unwrap();
+
+        recording.fail_next(Op::Delete, Failure::MessageGone);
+        let err = messenger.delete_message(sent).await.unwrap_err();
+        assert!(matches!(err, crate::Error::MessageGone(_)), "{err}");
+
+        // Not deliveries: nothing recorded.
+        messenger
+            .send_chat_action(target, ChatAction::Typing)
+            .await
+            .unwrap();
+
+        let events = read_events(&audit);
+        let ops: Vec<_> = events
+            .iter()
+            .map(|e| e.message_type.as_deref().unwrap())
+            .collect();
+        assert_eq!(ops, ["send", "edit", "delete"]);
+        assert!(events.iter().all(|e| e.event == "delivery"));
+        assert!(events.iter().all(|e| e.chat_id == Some(77)));
+        assert!(events
+            .iter()
+            .all(|e| e.message_id == Some(sent.message_id.0)));
+
+        let send = &events[0];
+        assert_eq!(send.content_bytes, Some(long.len()));
+        assert_eq!(
+            send.content.as_deref().unwrap().chars().count(),
+            DELIVERY_PREVIEW_CHARS
+        );
+        assert_eq!(send.content_hash.as_deref().map(str::len), Some(16));
+        assert_ne!(send.content_hash, events[1].content_hash);
+
+        let delete = &events[2];
+        assert_eq!(delete.content_hash, None);
+        assert!(delete.error.is_some());
+        assert_eq!(delete.outcome, Some(crate::utils::AuditOutcome::Error));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn failed_sends_have_no_message_id() {
+        let path = std::env::temp_dir().join(format!(
+            "ctb-audited-messenger-fail-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let audit = Arc::new(AuditLogger::new(&path, true));
+        let recording = Arc::new(RecordingMessenger::new());
+        let messenger = AuditedMessenger::new(recording.clone(), audit.clone());
+
+        recording.fail_next(Op::Send, Failure::Error("boom".to_string()));
+        let target = ChatTarget::from(ChatId(5));
+        assert!(messenger.send_html(target, "hi").await.is_err());
+        let ok = messenger.send_html(target, "hi").await.unwrap();
+        assert_ne!(ok.message_id, MessageId(0));
+
+        let events = read_events(&audit);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].message_id, None);
+        assert_eq!(events[0].error.as_deref(), Some("external error: boom"));
+        assert_eq!(events[1].message_id, Some(ok.message_id.0));
+        assert_eq!(events[0].content_hash, events[1].content_hash);
+        let _ = std::fs::remove_file(&path);
+    }
+}
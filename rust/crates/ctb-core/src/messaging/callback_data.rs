@@ -0,0 +1,212 @@
+//! Signed, versioned callback data for inline buttons.
+//!
+//! Button data is sealed as `v1:<kind>:<payload>:<sig>` when a keyboard is sent, where `sig` is
+//! a truncated HMAC-SHA256 over the chat id and `<kind>:<payload>`, keyed from the bot token.
+//! The callback handler opens the envelope before acting on it, so forged data (e.g. an answer
+//! to an `ask_user` request that was never offered) and buttons from before a format change are
+//! rejected instead of reaching the session.
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{domain::ChatId, Error, Result};
+
+/// Telegram's limit on `callback_data`, in bytes.
+pub const CALLBACK_DATA_MAX_LEN: usize = 64;
+
+/// Current envelope version.
+pub const CALLBACK_VERSION: &str = "v1";
+
+/// Bytes of the HMAC kept in the envelope (hex-encoded, so twice as many characters).
+const SIG_BYTES: usize = 8;
+
+/// Why a callback was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CallbackRejection {
+    /// Plain data from a keyboard sent before callbacks were signed.
+    #[error("unsigned callback data")]
+    Unsigned,
+    #[error("unknown callback version {0}")]
+    UnknownVersion(String),
+    #[error("malformed callback data")]
+    Malformed,
+    #[error("callback signature mismatch")]
+    BadSignature,
+}
+
+/// Seals and opens callback envelopes with a key derived from the bot token.
+#[derive(Clone)]
+pub struct CallbackSigner {
+    key: [u8; 32],
+}
+
+impl std::fmt::Debug for CallbackSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CallbackSigner(..)")
+    }
+}
+
+impl CallbackSigner {
+    pub fn from_bot_token(token: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"ctb-callback-data\0");
+        hasher.update(token.as_bytes());
+        Self {
+            key: hasher.finalize().into(),
+        }
+    }
+
+    fn mac(&self, chat_id: ChatId, data: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(chat_id.0.to_string().as_bytes());
+        mac.update(b":");
+        mac.update(data.as_bytes());
+        mac
+    }
+
+    /// Envelope for `data` (`<kind>:<payload>`) on a button in `chat_id`.
+    pub fn seal(&self, chat_id: ChatId, data: &str) -> Result<String> {
+        let tag = self.mac(chat_id, data).finalize().into_bytes();
+        let sig: String = tag[..SIG_BYTES]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let envelope = format!("{CALLBACK_VERSION}:{data}:{sig}");
+        if envelope.len() > CALLBACK_DATA_MAX_LEN {
+            return Err(Error::External(format!(
+                "callback data too long ({} > {CALLBACK_DATA_MAX_LEN} bytes): {data}",
+                envelope.len()
+            )));
+        }
+        Ok(envelope)
+    }
+
+    /// The `<kind>:<payload>` sealed in `envelope`, if it was signed by this bot for `chat_id`.
+    pub fn open<'a>(
+        &self,
+        chat_id: ChatId,
+        envelope: &'a str,
+    ) -> std::result::Result<&'a str, CallbackRejection> {
+        let Some((version, rest)) = envelope.split_once(':') else {
+            return Err(CallbackRejection::Unsigned);
+        };
+        if version != CALLBACK_VERSION {
+            return Err(if is_version(version) {
+                CallbackRejection::UnknownVersion(version.to_string())
+            } else {
+                CallbackRejection::Unsigned
+            });
+        }
+        let (data, sig) = rest.rsplit_once(':').ok_or(CallbackRejection::Malformed)?;
+        if !data.contains(':') || sig.len() != SIG_BYTES * 2 {
+            return Err(CallbackRejection::Malformed);
+        }
+        let sig = decode_hex(sig).ok_or(CallbackRejection::Malformed)?;
+        self.mac(chat_id, data)
+            .verify_truncated_left(&sig)
+            .map_err(|_| CallbackRejection::BadSignature)?;
+        Ok(data)
+    }
+}
+
+fn is_version(s: &str) -> bool {
+    s.strip_prefix('v')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> CallbackSigner {
+        CallbackSigner::from_bot_token("123456:test-token")
+    }
+
+    #[test]
+    fn sealed_data_opens_in_the_same_chat() {
+        let s = signer();
+        let envelope = s.seal(ChatId(-100123), "askuser:1a2b3c4d:7").unwrap();
+        assert!(envelope.starts_with("v1:askuser:1a2b3c4d:7:"));
+        assert_eq!(s.open(ChatId(-100123), &envelope), Ok("askuser:1a2b3c4d:7"));
+
+        // Another chat, another bot token or any edit fails the signature.
+        assert_eq!(
+            s.open(ChatId(42), &envelope),
+            Err(CallbackRejection::BadSignature)
+        );
+        let other_bot = CallbackSigner::from_bot_token("654321:other");
+        assert_eq!(
+            other_bot.open(ChatId(-100123), &envelope),
+            Err(CallbackRejection::BadSignature)
+        );
+        let tampered = envelope.replace("askuser:1a2b3c4d:7", "askuser:1a2b3c4d:0");
+        assert_eq!(
+            s.open(ChatId(-100123), &tampered),
+            Err(CallbackRejection::BadSignature)
+        );
+    }
+
+    #[test]
+    fn unsigned_and_malformed_data_is_rejected() {
+        let s = signer();
+        let chat = ChatId(1);
+        assert_eq!(
+            s.open(chat, "askuser:1a2b3c4d:7"),
+            Err(CallbackRejection::Unsigned)
+        );
+        assert_eq!(s.open(chat, "noop"), Err(CallbackRejection::Unsigned));
+        assert_eq!(
+            s.open(chat, "v2:askuser:1a2b3c4d:7:0011223344556677"),
+            Err(CallbackRejection::UnknownVersion("v2".to_string()))
+        );
+        for bad in [
+            "v1:",
+            "v1:askuser",
+            "v1:askuser:0011223344556677",
+            "v1:askuser:1:00112233",
+            "v1:askuser:1:zz11223344556677",
+            "v1:askuser:1:é01122334455667",
+        ] {
+            assert_eq!(
+                s.open(chat, bad),
+                Err(CallbackRejection::Malformed),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn realistic_payloads_fit_telegrams_limit() {
+        let s = signer();
+        // Longest chat ids are supergroups (-100 + 10 digits); they are signed, not embedded.
+        let chat = ChatId(-1_009_876_543_210);
+        for data in [
+            "askuser:1a2b3c4d:19",
+            "askuser:1a2b3c4d:p12",
+            "settings:toggle:delete_thinking_messages",
+            "settings:reset",
+            "idle:continue",
+            "plan:execute",
+            "rerun:2147483647",
+        ] {
+            let envelope = s.seal(chat, data).unwrap();
+            assert!(
+                envelope.len() <= CALLBACK_DATA_MAX_LEN,
+                "{envelope} is {} bytes",
+                envelope.len()
+            );
+            assert_eq!(s.open(chat, &envelope), Ok(data));
+        }
+
+        let too_long = format!("askuser:{}", "x".repeat(60));
+        assert!(s.seal(chat, &too_long).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Chat actions ("uploading photo…", "sending file…") shown while slow work runs.
+//!
+//! Plain turns show "typing" from the event pipeline. Media ingestion and file uploads pick the
+//! action matching the work instead and keep it alive with [`with_chat_action`] until the work
+//! finishes.
+
+use std::{future::Future, path::Path, time::Duration};
+
+use crate::{
+    domain::{ChatTarget, MessageRef},
+    messaging::{port::MessagingPort, types::ChatAction},
+    Result,
+};
+
+/// Telegram drops a chat action after about 5 seconds; an action is re-sent this often.
+pub const CHAT_ACTION_RENEW: Duration = Duration::from_secs(4);
+
+/// Run `work` while `target` shows `action`, renewed until `work` finishes. Best-effort: a
+/// failed or unsupported chat action never affects `work`.
+pub async fn with_chat_action<T>(
+    messenger: &dyn MessagingPort,
+    target: ChatTarget,
+    action: ChatAction,
+    work: impl Future<Output = T>,
+) -> T {
+    renewing(messenger, target, action, CHAT_ACTION_RENEW, work).await
+}
+
+/// Upload `path` as a document, showing "sending file…" while it transfers.
+pub async fn upload_document(
+    messenger: &dyn MessagingPort,
+    target: ChatTarget,
+    path: &Path,
+    caption: Option<&str>,
+) -> Result<MessageRef> {
+    with_chat_action(
+        messenger,
+        target,
+        ChatAction::UploadDocument,
+        messenger.send_document(target, path, caption),
+    )
+    .await
+}
+
+async fn renewing<T>(
+    messenger: &dyn MessagingPort,
+    target: ChatTarget,
+    action: ChatAction,
+    every: Duration,
+    work: impl Future<Output = T>,
+) -> T {
+    if !messenger.capabilities().supports_chat_actions {
+        return work.await;
+    }
+    tokio::pin!(work);
+    loop {
+        let _ = messenger.send_chat_action(target, action).await;
+        tokio::select! {
+            out = &mut work => return out,
+            _ = tokio::time::sleep(every) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::ChatId,
+        messaging::test_support::{Call, Op, RecordingMessenger},
+    };
+
+    fn actions(recording: &RecordingMessenger) -> Vec<ChatAction> {
+        recording
+            .calls()
+            .into_iter()
+            .filter_map(|c| match c.call {
+                Call::ChatAction { action, .. } => Some(action),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn action_is_shown_before_and_renewed_during_the_work() {
+        let recording = RecordingMessenger::new();
+        let target = ChatTarget::from(ChatId(5));
+        let out = renewing(
+            &recording,
+            target,
+            ChatAction::UploadPhoto,
+            Duration::from_millis(20),
+            async {
+                tokio::time::sleep(Duration::from_millis(70)).await;
+                7
+            },
+        )
+        .await;
+        assert_eq!(out, 7);
+        let sent = actions(&recording);
+        assert!(sent.len() >= 3, "{sent:?}");
+        assert!(sent.iter().all(|a| *a == ChatAction::UploadPhoto));
+
+        let path = std::env::temp_dir().join(format!("ctb-upload-{}.txt", std::process::id()));
+        std::fs::write(&path, "x").unwrap();
+        let recording = RecordingMessenger::new();
+        upload_document(&recording, target, &path, None)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        let ops: Vec<_> = recording.calls().iter().map(|c| c.call.op()).collect();
+        assert_eq!(ops, [Op::ChatAction, Op::Document]);
+        assert_eq!(actions(&recording), [ChatAction::UploadDocument]);
+
+        // No chat actions on transports without them.
+        let plain = RecordingMessenger::send_only(4096);
+        with_chat_action(&plain, target, ChatAction::Typing, async {}).await;
+        assert!(plain.calls().is_empty());
+    }
+}
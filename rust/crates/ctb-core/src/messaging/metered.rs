@@ -0,0 +1,103 @@
+use std::{path::Path, sync::Arc};
+
+use crate::{
+    domain::{ChatTarget, MessageRef},
+    messaging::{
+        port::MessagingPort,
+        types::{ChatAction, InlineKeyboard, MessagingCapabilities},
+    },
+    metrics::{Counter, MetricsSink},
+    Result,
+};
+
+/// MessagingPort decorator that counts sends, edits and deletes into a [`MetricsSink`].
+///
+/// Calls are counted whether or not they succeed: a failed edit still cost a round trip.
+pub struct MeteredMessenger {
+    inner: Arc<dyn MessagingPort>,
+    metrics: Arc<dyn MetricsSink>,
+}
+
+impl MeteredMessenger {
+    pub fn new(inner: Arc<dyn MessagingPort>, metrics: Arc<dyn MetricsSink>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+#[async_trait::async_trait]
+impl MessagingPort for MeteredMessenger {
+    fn capabilities(&self) -> MessagingCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn send_html(&self, target: ChatTarget, html: &str) -> Result<MessageRef> {
+        self.metrics.incr(Counter::Sends, 1);
+        self.inner.send_html(target, html).await
+    }
+
+    async fn edit_html(&self, msg: MessageRef, html: &str) -> Result<()> {
+        self.metrics.incr(Counter::Edits, 1);
+        self.inner.edit_html(msg, html).await
+    }
+
+    async fn edit_html_best_effort(&self, msg: MessageRef, html: &str) -> Result<()> {
+        self.metrics.incr(Counter::Edits, 1);
+        self.inner.edit_html_best_effort(msg, html).await
+    }
+
+    async fn delete_message(&self, msg: MessageRef) -> Result<()> {
+        self.metrics.incr(Counter::Deletes, 1);
+        self.inner.delete_message(msg).await
+    }
+
+    async fn send_chat_action(&self, target: ChatTarget, action: ChatAction) -> Result<()> {
+        self.inner.send_chat_action(target, action).await
+    }
+
+    async fn set_reaction(&self, msg: MessageRef, emoji: &str) -> Result<()> {
+        self.inner.set_reaction(msg, emoji).await
+    }
+
+    async fn send_inline_keyboard(
+        &self,
+        target: ChatTarget,
+        text: &str,
+        keyboard: InlineKeyboard,
+    ) -> Result<MessageRef> {
+        self.metrics.incr(Counter::Sends, 1);
+        self.inner
+            .send_inline_keyboard(target, text, keyboard)
+            .await
+    }
+
+    async fn edit_inline_keyboard(
+        &self,
+        msg: MessageRef,
+        html: &str,
+        keyboard: Option<InlineKeyboard>,
+    ) -> Result<()> {
+        self.metrics.incr(Counter::Edits, 1);
+        self.inner.edit_inline_keyboard(msg, html, keyboard).await
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()> {
+        self.inner
+            .answer_callback_query(callback_id, text, show_alert)
+            .await
+    }
+
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        path: &Path,
+        caption: Option<&str>,
+    ) -> Result<MessageRef> {
+        self.metrics.incr(Counter::Sends, 1);
+        self.inner.send_document(target, path, caption).await
+    }
+}
@@ -1,5 +1,11 @@
 //! Cross-messenger abstractions (Telegram today; Slack/Discord later).
 
+pub mod audited;
+pub mod callback_data;
+pub mod chat_action;
+pub mod metered;
 pub mod port;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_support;
 pub mod throttled;
 pub mod types;
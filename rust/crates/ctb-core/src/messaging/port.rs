@@ -1,7 +1,9 @@
+use std::path::Path;
+
 use async_trait::async_trait;
 
 use crate::{
-    domain::{ChatId, MessageRef},
+    domain::{ChatTarget, MessageRef},
     messaging::types::{ChatAction, InlineKeyboard, MessagingCapabilities},
     Result,
 };
@@ -10,24 +12,55 @@ use crate::{
 ///
 /// Telegram is the first implementation; the shape is designed so future adapters
 /// (Slack/Discord/WhatsApp) can fit behind the same interface with capability flags.
+///
+/// Sends take a `ChatTarget` so replies land in the right forum topic; edits/deletes address an
+/// existing message and need no thread.
 #[async_trait]
 pub trait MessagingPort: Send + Sync {
     fn capabilities(&self) -> MessagingCapabilities;
 
-    async fn send_html(&self, chat_id: ChatId, html: &str) -> Result<MessageRef>;
+    async fn send_html(&self, target: ChatTarget, html: &str) -> Result<MessageRef>;
     async fn edit_html(&self, msg: MessageRef, html: &str) -> Result<()>;
+
+    /// Edit that may be skipped under rate pressure (spinner ticks). Decorators that shape
+    /// traffic override this; everyone else treats it as a plain edit.
+    async fn edit_html_best_effort(&self, msg: MessageRef, html: &str) -> Result<()> {
+        self.edit_html(msg, html).await
+    }
     async fn delete_message(&self, msg: MessageRef) -> Result<()>;
 
-    async fn send_chat_action(&self, chat_id: ChatId, action: ChatAction) -> Result<()>;
+    async fn send_chat_action(&self, target: ChatTarget, action: ChatAction) -> Result<()>;
 
     async fn set_reaction(&self, msg: MessageRef, emoji: &str) -> Result<()>;
 
     async fn send_inline_keyboard(
         &self,
-        chat_id: ChatId,
+        target: ChatTarget,
         text: &str,
         keyboard: InlineKeyboard,
     ) -> Result<MessageRef>;
 
-    async fn answer_callback_query(&self, callback_id: &str, text: Option<&str>) -> Result<()>;
+    /// Replace a keyboard message's text and buttons; `None` removes the keyboard.
+    async fn edit_inline_keyboard(
+        &self,
+        msg: MessageRef,
+        html: &str,
+        keyboard: Option<InlineKeyboard>,
+    ) -> Result<()>;
+
+    /// `show_alert` shows `text` as a dismissable dialog instead of a toast.
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()>;
+
+    /// Upload a local file as a document attachment (see `supports_documents`).
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        path: &Path,
+        caption: Option<&str>,
+    ) -> Result<MessageRef>;
 }
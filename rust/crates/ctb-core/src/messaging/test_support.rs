@@ -0,0 +1,801 @@
+//! Canonical in-memory [`MessagingPort`] and [`ModelClient`] for tests.
+//!
+//! [`RecordingMessenger`] records every call with a timestamp, hands out sequential message ids
+//! and can be scripted to fail specific calls (`fail_nth(Op::Edit, 2, ..)`) or to act as if the
+//! user deleted a message (`delete_by_user`), so streaming and session behavior can be asserted
+//! without a live chat. [`ScriptedModel`] plays the other end: it replays a list of model events
+//! on every run and can hold the run open until it is cancelled. Compiled for this crate's tests
+//! and, for downstream crates, behind the `test-util` feature.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    domain::{ChatId, ChatTarget, MessageId, MessageRef},
+    errors::{CancelReason, Error},
+    messaging::{
+        port::MessagingPort,
+        types::{ChatAction, InlineKeyboard, MessagingCapabilities},
+    },
+    model::{
+        client::{EventSink, ModelClient},
+        types::{
+            ModelCapabilities, ModelEvent, ProviderKind, RunId, RunRequest, RunResult, SessionRef,
+            TokenUsage,
+        },
+    },
+    Result,
+};
+
+/// Kind of port call, used to address scripted failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    Send,
+    Edit,
+    Delete,
+    ChatAction,
+    Reaction,
+    Keyboard,
+    EditKeyboard,
+    CallbackAnswer,
+    Document,
+}
+
+/// How a scripted call fails.
+#[derive(Clone, Debug)]
+pub enum Failure {
+    Error(String),
+    /// Shaped like the Telegram adapter's error once its own 429 retry is exhausted.
+    RateLimited(Duration),
+    /// The addressed message no longer exists.
+    MessageGone,
+    /// The recipient blocked the bot or never started it.
+    Unreachable(&'static str),
+}
+
+impl Failure {
+    fn into_error(self) -> Error {
+        match self {
+            Self::Error(msg) => Error::External(msg),
+            Self::RateLimited(d) => Error::External(format!(
+                "telegram error: Retry after {}s (Too Many Requests)",
+                d.as_secs()
+            )),
+            Self::MessageGone => Error::MessageGone("message to edit not found".to_string()),
+            Self::Unreachable(reason) => Error::Unreachable(reason.to_string()),
+        }
+    }
+}
+
+/// One successful port call.
+#[derive(Clone, Debug)]
+pub enum Call {
+    Send {
+        target: ChatTarget,
+        html: String,
+        msg: MessageRef,
+    },
+    Edit {
+        msg: MessageRef,
+        html: String,
+    },
+    Delete {
+        msg: MessageRef,
+    },
+    ChatAction {
+        target: ChatTarget,
+        action: ChatAction,
+    },
+    Reaction {
+        msg: MessageRef,
+        emoji: String,
+    },
+    Keyboard {
+        target: ChatTarget,
+        text: String,
+        keyboard: InlineKeyboard,
+        msg: MessageRef,
+    },
+    EditKeyboard {
+        msg: MessageRef,
+        html: String,
+        keyboard: Option<InlineKeyboard>,
+    },
+    CallbackAnswer {
+        callback_id: String,
+        text: Option<String>,
+        show_alert: bool,
+    },
+    Document {
+        target: ChatTarget,
+        path: PathBuf,
+        /// File contents read at upload time (the caller may delete the file afterwards).
+        body: String,
+        caption: Option<String>,
+        msg: MessageRef,
+    },
+}
+
+impl Call {
+    pub fn op(&self) -> Op {
+        match self {
+            Self::Send { .. } => Op::Send,
+            Self::Edit { .. } => Op::Edit,
+            Self::Delete { .. } => Op::Delete,
+            Self::ChatAction { .. } => Op::ChatAction,
+            Self::Reaction { .. } => Op::Reaction,
+            Self::Keyboard { .. } => Op::Keyboard,
+            Self::EditKeyboard { .. } => Op::EditKeyboard,
+            Self::CallbackAnswer { .. } => Op::CallbackAnswer,
+            Self::Document { .. } => Op::Document,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordedCall {
+    pub at: Instant,
+    pub call: Call,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: i32,
+    calls: Vec<RecordedCall>,
+    /// Attempts per op, including failed ones (1-based numbering for `fail_nth`).
+    attempts: HashMap<Op, usize>,
+    scripted: HashMap<(Op, usize), Failure>,
+    /// Messages deleted behind the bot's back; editing or deleting them fails.
+    gone: HashSet<MessageRef>,
+    /// Time every send and edit takes.
+    latency: Duration,
+}
+
+pub struct RecordingMessenger {
+    caps: MessagingCapabilities,
+    inner: Mutex<Inner>,
+}
+
+impl Default for RecordingMessenger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordingMessenger {
+    /// Every capability enabled.
+    pub fn new() -> Self {
+        Self::with_capabilities(MessagingCapabilities {
+            supports_html: true,
+            supports_edit: true,
+            supports_reactions: true,
+            supports_chat_actions: true,
+            supports_inline_keyboards: true,
+            supports_documents: true,
+            max_message_len: 4096,
+        })
+    }
+
+    /// A plain chat transport: sends and deletes only, messages capped at `max_message_len`.
+    pub fn send_only(max_message_len: usize) -> Self {
+        Self::with_capabilities(MessagingCapabilities {
+            supports_html: true,
+            supports_edit: false,
+            supports_reactions: false,
+            supports_chat_actions: false,
+            supports_inline_keyboards: false,
+            supports_documents: false,
+            max_message_len,
+        })
+    }
+
+    /// Calls whose capability flag is off fail instead of being recorded.
+    pub fn with_capabilities(caps: MessagingCapabilities) -> Self {
+        Self {
+            caps,
+            inner: Mutex::new(Inner {
+                next_id: 1,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Fail the `n`th (1-based, counting every attempt) call of `op`.
+    pub fn fail_nth(&self, op: Op, n: usize, failure: Failure) {
+        self.inner
+            .lock()
+            .unwrap()
+            .scripted
+            .insert((op, n.max(1)), failure);
+    }
+
+    /// Fail the next call of `op`.
+    pub fn fail_next(&self, op: Op, failure: Failure) {
+        let mut inner = self.inner.lock().unwrap();
+        let n = inner.attempts.get(&op).copied().unwrap_or(0) + 1;
+        inner.scripted.insert((op, n), failure);
+    }
+
+    /// Act as if the user deleted `msg`: later edits and deletes of it fail with
+    /// [`Error::MessageGone`].
+    pub fn delete_by_user(&self, msg: MessageRef) {
+        self.inner.lock().unwrap().gone.insert(msg);
+    }
+
+    /// Make every later send and edit take `latency`, like a slow network.
+    pub fn set_latency(&self, latency: Duration) {
+        self.inner.lock().unwrap().latency = latency;
+    }
+
+    /// Make the next send answer like a 429.
+    pub fn rate_limit_next_send(&self, retry_after: Duration) {
+        self.fail_next(Op::Send, Failure::RateLimited(retry_after));
+    }
+
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.inner.lock().unwrap().calls.clone()
+    }
+
+    /// Number of attempts of `op`, failed ones included.
+    pub fn attempts(&self, op: Op) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .attempts
+            .get(&op)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn sends(&self) -> Vec<String> {
+        self.collect(|c| match c {
+            Call::Send { html, .. } => Some(html.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn edits(&self) -> Vec<(MessageRef, String)> {
+        self.collect(|c| match c {
+            Call::Edit { msg, html } => Some((*msg, html.clone())),
+            _ => None,
+        })
+    }
+
+    pub fn edited_html(&self) -> Vec<String> {
+        self.edits().into_iter().map(|(_, html)| html).collect()
+    }
+
+    pub fn deletes(&self) -> Vec<MessageRef> {
+        self.collect(|c| match c {
+            Call::Delete { msg } => Some(*msg),
+            _ => None,
+        })
+    }
+
+    pub fn reactions(&self) -> Vec<(MessageRef, String)> {
+        self.collect(|c| match c {
+            Call::Reaction { msg, emoji } => Some((*msg, emoji.clone())),
+            _ => None,
+        })
+    }
+
+    pub fn keyboards(&self) -> Vec<(ChatTarget, String, InlineKeyboard)> {
+        self.collect(|c| match c {
+            Call::Keyboard {
+                target,
+                text,
+                keyboard,
+                ..
+            } => Some((*target, text.clone(), keyboard.clone())),
+            _ => None,
+        })
+    }
+
+    /// `(message, html, keyboard)` of every keyboard edit.
+    pub fn keyboard_edits(&self) -> Vec<(MessageRef, String, Option<InlineKeyboard>)> {
+        self.collect(|c| match c {
+            Call::EditKeyboard {
+                msg,
+                html,
+                keyboard,
+            } => Some((*msg, html.clone(), keyboard.clone())),
+            _ => None,
+        })
+    }
+
+    /// `(body, caption)` of every uploaded document.
+    pub fn documents(&self) -> Vec<(String, Option<String>)> {
+        self.collect(|c| match c {
+            Call::Document { body, caption, .. } => Some((body.clone(), caption.clone())),
+            _ => None,
+        })
+    }
+
+    #[track_caller]
+    pub fn assert_sent_containing(&self, needle: &str) {
+        let sends = self.sends();
+        assert!(
+            sends.iter().any(|s| s.contains(needle)),
+            "no send contains {needle:?}; sends: {sends:#?}"
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_edited_containing(&self, needle: &str) {
+        let edits = self.edited_html();
+        assert!(
+            edits.iter().any(|s| s.contains(needle)),
+            "no edit contains {needle:?}; edits: {edits:#?}"
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_never_sent_containing(&self, needle: &str) {
+        let sends = self.sends();
+        assert!(
+            !sends.iter().any(|s| s.contains(needle)),
+            "unexpected send containing {needle:?}; sends: {sends:#?}"
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_deleted(&self, msg: MessageRef) {
+        assert!(
+            self.deletes().contains(&msg),
+            "{msg:?} was not deleted; deletes: {:?}",
+            self.deletes()
+        );
+    }
+
+    fn collect<T>(&self, f: impl Fn(&Call) -> Option<T>) -> Vec<T> {
+        self.inner
+            .lock()
+            .unwrap()
+            .calls
+            .iter()
+            .filter_map(|r| f(&r.call))
+            .collect()
+    }
+
+    /// Count the attempt and apply any scripted failure or missing capability.
+    fn attempt(&self, op: Op, supported: bool) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let n = {
+            let n = inner.attempts.entry(op).or_insert(0);
+            *n += 1;
+            *n
+        };
+        if let Some(failure) = inner.scripted.remove(&(op, n)) {
+            return Err(failure.into_error());
+        }
+        if !supported {
+            return Err(Error::External(format!("{op:?} not supported")));
+        }
+        Ok(())
+    }
+
+    fn ensure_exists(&self, msg: MessageRef) -> Result<()> {
+        if self.inner.lock().unwrap().gone.contains(&msg) {
+            return Err(Failure::MessageGone.into_error());
+        }
+        Ok(())
+    }
+
+    async fn wait_latency(&self) {
+        let latency = self.inner.lock().unwrap().latency;
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+    }
+
+    fn record(&self, call: Call) {
+        self.inner.lock().unwrap().calls.push(RecordedCall {
+            at: Instant::now(),
+            call,
+        });
+    }
+
+    fn alloc(&self, chat_id: ChatId) -> MessageRef {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        MessageRef {
+            chat_id,
+            message_id: MessageId(id),
+        }
+    }
+}
+
+#[async_trait]
+impl MessagingPort for RecordingMessenger {
+    fn capabilities(&self) -> MessagingCapabilities {
+        self.caps
+    }
+
+    async fn send_html(&self, target: ChatTarget, html: &str) -> Result<MessageRef> {
+        self.wait_latency().await;
+        self.attempt(Op::Send, true)?;
+        let msg = self.alloc(target.chat_id);
+        self.record(Call::Send {
+            target,
+            html: html.to_string(),
+            msg,
+        });
+        Ok(msg)
+    }
+
+    async fn edit_html(&self, msg: MessageRef, html: &str) -> Result<()> {
+        self.wait_latency().await;
+        self.attempt(Op::Edit, self.caps.supports_edit)?;
+        self.ensure_exists(msg)?;
+        self.record(Call::Edit {
+            msg,
+            html: html.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn delete_message(&self, msg: MessageRef) -> Result<()> {
+        self.attempt(Op::Delete, true)?;
+        self.ensure_exists(msg)?;
+        self.record(Call::Delete { msg });
+        Ok(())
+    }
+
+    async fn send_chat_action(&self, target: ChatTarget, action: ChatAction) -> Result<()> {
+        self.attempt(Op::ChatAction, self.caps.supports_chat_actions)?;
+        self.record(Call::ChatAction { target, action });
+        Ok(())
+    }
+
+    async fn set_reaction(&self, msg: MessageRef, emoji: &str) -> Result<()> {
+        self.attempt(Op::Reaction, self.caps.supports_reactions)?;
+        self.record(Call::Reaction {
+            msg,
+            emoji: emoji.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn send_inline_keyboard(
+        &self,
+        target: ChatTarget,
+        text: &str,
+        keyboard: InlineKeyboard,
+    ) -> Result<MessageRef> {
+        self.attempt(Op::Keyboard, self.caps.supports_inline_keyboards)?;
+        let msg = self.alloc(target.chat_id);
+        self.record(Call::Keyboard {
+            target,
+            text: text.to_string(),
+            keyboard,
+            msg,
+        });
+        Ok(msg)
+    }
+
+    async fn edit_inline_keyboard(
+        &self,
+        msg: MessageRef,
+        html: &str,
+        keyboard: Option<InlineKeyboard>,
+    ) -> Result<()> {
+        self.attempt(Op::EditKeyboard, self.caps.supports_edit)?;
+        self.ensure_exists(msg)?;
+        self.record(Call::EditKeyboard {
+            msg,
+            html: html.to_string(),
+            keyboard,
+        });
+        Ok(())
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()> {
+        self.attempt(Op::CallbackAnswer, true)?;
+        self.record(Call::CallbackAnswer {
+            callback_id: callback_id.to_string(),
+            text: text.map(str::to_string),
+            show_alert,
+        });
+        Ok(())
+    }
+
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        path: &Path,
+        caption: Option<&str>,
+    ) -> Result<MessageRef> {
+        self.attempt(Op::Document, self.caps.supports_documents)?;
+        let body = std::fs::read_to_string(path).unwrap_or_default();
+        let msg = self.alloc(target.chat_id);
+        self.record(Call::Document {
+            target,
+            path: path.to_path_buf(),
+            body,
+            caption: caption.map(str::to_string),
+            msg,
+        });
+        Ok(msg)
+    }
+}
+
+/// Scripted Claude CLI stand-in. Every run records its request, emits the scripted events in
+/// order and returns the scripted result; by default that is a successful run answering "done".
+///
+/// A [`blocking`](Self::blocking) model holds each run open after its events until the run is
+/// cancelled (ending in [`Error::Cancelled`]) or the model is [`release`](Self::release)d. A
+/// [`stuck`](Self::stuck) model refuses to cancel, like a CLI that ignores its signals.
+pub struct ScriptedModel {
+    events: Vec<ModelEvent>,
+    result: RunResult,
+    error: Option<String>,
+    block: bool,
+    number_sessions: bool,
+    state: Mutex<ModelState>,
+}
+
+#[derive(Default)]
+struct ModelState {
+    requests: Vec<RunRequest>,
+    /// Runs that emitted their events, counting finished ones.
+    started: usize,
+    running: HashSet<RunId>,
+    cancels: Vec<(RunId, CancelReason)>,
+    released: bool,
+    stuck: bool,
+}
+
+impl Default for ScriptedModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedModel {
+    pub fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            result: RunResult {
+                session: None,
+                is_error: false,
+                text: "done".to_string(),
+                usage: None,
+            },
+            error: None,
+            block: false,
+            number_sessions: false,
+            state: Mutex::new(ModelState::default()),
+        }
+    }
+
+    /// Emit `events`, in order, at the start of every run.
+    pub fn emitting(mut self, events: impl IntoIterator<Item = ModelEvent>) -> Self {
+        self.events.extend(events);
+        self
+    }
+
+    /// End every run with `text` as its result text.
+    pub fn answering(mut self, text: &str) -> Self {
+        self.result.text = text.to_string();
+        self
+    }
+
+    /// End every run as a failed result with `text`.
+    pub fn failing(mut self, text: &str) -> Self {
+        self.result.is_error = true;
+        self.answering(text)
+    }
+
+    /// End every run with an error instead of a result.
+    pub fn erroring(mut self, msg: &str) -> Self {
+        self.error = Some(msg.to_string());
+        self
+    }
+
+    pub fn with_usage(mut self, usage: TokenUsage) -> Self {
+        self.result.usage = Some(usage);
+        self
+    }
+
+    /// Give every run a session, stamped on its events and result: the resumed one, or a new
+    /// `s-1`, `s-2`, ... per run that resumes nothing.
+    pub fn numbering_sessions(mut self) -> Self {
+        self.number_sessions = true;
+        self
+    }
+
+    /// Hold every run open after its events until it is cancelled or [`release`](Self::release)d.
+    pub fn blocking(mut self) -> Self {
+        self.block = true;
+        self
+    }
+
+    /// Fail every cancel; blocked runs keep running.
+    pub fn stuck(self) -> Self {
+        self.state.lock().unwrap().stuck = true;
+        self
+    }
+
+    /// Let blocked runs, and every later one, finish with the scripted result.
+    pub fn release(&self) {
+        self.state.lock().unwrap().released = true;
+    }
+
+    /// Runs that got through their events so far.
+    pub fn started(&self) -> usize {
+        self.state.lock().unwrap().started
+    }
+
+    pub fn requests(&self) -> Vec<RunRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Every accepted cancel, in order. `cancel_all` counts as a [`CancelReason::Shutdown`] of
+    /// each run in flight.
+    pub fn cancels(&self) -> Vec<(RunId, CancelReason)> {
+        self.state.lock().unwrap().cancels.clone()
+    }
+
+    pub fn last_cancel_reason(&self) -> Option<CancelReason> {
+        self.state.lock().unwrap().cancels.last().map(|(_, r)| *r)
+    }
+
+    async fn wait_for_cancel_or_release(&self, run_id: RunId) -> Result<()> {
+        loop {
+            {
+                let st = self.state.lock().unwrap();
+                if let Some((_, reason)) = st.cancels.iter().find(|(id, _)| *id == run_id) {
+                    return Err(Error::Cancelled(*reason));
+                }
+                if st.released {
+                    return Ok(());
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl ModelClient for ScriptedModel {
+    fn provider(&self) -> ProviderKind {
+        ProviderKind::ClaudeCli
+    }
+
+    fn capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities {
+            supports_streaming: true,
+            supports_tools: true,
+            supports_vision: true,
+            supports_thinking: true,
+            supports_mcp: true,
+        }
+    }
+
+    async fn run(&self, req: RunRequest, on_event: &mut dyn EventSink) -> Result<RunResult> {
+        let run_id = req.run_id;
+        let mut result = self.result.clone();
+        let mut events = self.events.clone();
+        {
+            let mut st = self.state.lock().unwrap();
+            if self.number_sessions {
+                let id = match &req.resume {
+                    Some(session) => session.id.clone(),
+                    None => {
+                        let new = st.requests.iter().filter(|r| r.resume.is_none()).count();
+                        format!("s-{}", new + 1)
+                    }
+                };
+                for event in &mut events {
+                    if let ModelEvent::Assistant { raw } | ModelEvent::Result { raw } = event {
+                        raw["session_id"] = id.clone().into();
+                    }
+                }
+                result.session = Some(SessionRef {
+                    provider: ProviderKind::ClaudeCli,
+                    id,
+                });
+            }
+            st.requests.push(req);
+        }
+        for event in events {
+            on_event.emit(event).await?;
+        }
+        {
+            let mut st = self.state.lock().unwrap();
+            st.started += 1;
+            st.running.insert(run_id);
+        }
+        let outcome = if self.block {
+            self.wait_for_cancel_or_release(run_id).await
+        } else {
+            Ok(())
+        };
+        self.state.lock().unwrap().running.remove(&run_id);
+        outcome?;
+        match &self.error {
+            Some(msg) => Err(Error::External(msg.clone())),
+            None => Ok(result),
+        }
+    }
+
+    async fn cancel(&self, run_id: RunId, reason: CancelReason) -> Result<()> {
+        let mut st = self.state.lock().unwrap();
+        if st.stuck {
+            return Err(Error::External("cancel failed".to_string()));
+        }
+        st.cancels.push((run_id, reason));
+        Ok(())
+    }
+
+    async fn cancel_all(&self) -> Result<()> {
+        let mut st = self.state.lock().unwrap();
+        if st.stuck {
+            return Err(Error::External("cancel failed".to_string()));
+        }
+        let running: Vec<RunId> = st.running.iter().copied().collect();
+        st.cancels
+            .extend(running.into_iter().map(|id| (id, CancelReason::Shutdown)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_calls_and_applies_scripted_failures() {
+        let m = RecordingMessenger::new();
+        let target = ChatTarget::from(ChatId(7));
+
+        let first = m.send_html(target, "one").await.unwrap();
+        m.fail_nth(Op::Edit, 2, Failure::Error("boom".to_string()));
+        m.edit_html(first, "edit 1").await.unwrap();
+        assert!(m.edit_html(first, "edit 2").await.is_err());
+        m.edit_html(first, "edit 3").await.unwrap();
+
+        m.rate_limit_next_send(Duration::from_secs(3));
+        let err = m.send_html(target, "two").await.unwrap_err();
+        assert!(err.to_string().contains("Retry after 3s"), "{err}");
+        let second = m.send_html(target, "three").await.unwrap();
+
+        assert_eq!(first.message_id, MessageId(1));
+        assert_eq!(second.message_id, MessageId(2));
+        assert_eq!(m.sends(), ["one", "three"]);
+        assert_eq!(m.edited_html(), ["edit 1", "edit 3"]);
+        assert_eq!(m.attempts(Op::Edit), 3);
+        m.assert_sent_containing("thr");
+        m.assert_never_sent_containing("two");
+        m.assert_edited_containing("edit 3");
+
+        let calls = m.calls();
+        assert!(calls.windows(2).all(|w| w[0].at <= w[1].at));
+        assert_eq!(calls[0].call.op(), Op::Send);
+    }
+
+    #[tokio::test]
+    async fn disabled_capabilities_fail() {
+        let m = RecordingMessenger::with_capabilities(MessagingCapabilities {
+            supports_documents: false,
+            ..RecordingMessenger::new().capabilities()
+        });
+        let res = m
+            .send_document(ChatId(1).into(), Path::new("/nonexistent"), None)
+            .await;
+        assert!(res.is_err());
+        assert!(m.documents().is_empty());
+    }
+}
@@ -1,10 +1,15 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
 use tokio::sync::Mutex;
 use tokio::time::{sleep, Instant};
 
 use crate::{
-    domain::{ChatId, MessageRef},
+    domain::{ChatTarget, MessageRef},
     messaging::{
         port::MessagingPort,
         types::{ChatAction, InlineKeyboard, MessagingCapabilities},
@@ -18,6 +23,10 @@ pub struct ThrottleConfig {
     pub global_min_interval: Duration,
     /// Minimum spacing between calls per chat (Telegram 1 msg/sec style limits).
     pub per_chat_min_interval: Duration,
+    /// Sends/edits allowed per group chat within `budget_window` (0 = unlimited). Private chats
+    /// are only spaced by `per_chat_min_interval`.
+    pub group_budget: u32,
+    pub budget_window: Duration,
 }
 
 impl Default for ThrottleConfig {
@@ -26,10 +35,31 @@ impl Default for ThrottleConfig {
         Self {
             global_min_interval: Duration::from_millis(40), // ~25/sec
             per_chat_min_interval: Duration::from_millis(1050), // ~0.95/sec
+            // Telegram allows ~20 messages/min in groups.
+            group_budget: 20,
+            budget_window: Duration::from_secs(60),
         }
     }
 }
 
+/// How a call is treated by the per-chat budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Priority {
+    /// Counts against the budget; waits for room when it is spent.
+    Normal,
+    /// Counts against the budget but is dropped instead of waiting (spinner ticks). Also dropped
+    /// once the last quarter of the budget is reached, so content edits keep some headroom.
+    BestEffort,
+    /// Spaced but not counted (deletes, reactions, chat actions).
+    Unmetered,
+}
+
+enum Admission {
+    Go(Duration),
+    Wait(Duration),
+    Drop,
+}
+
 #[derive(Debug)]
 struct IntervalLimiter {
     interval: Duration,
@@ -53,15 +83,60 @@ impl IntervalLimiter {
     }
 }
 
+#[derive(Debug)]
+struct ChatLimiter {
+    spacing: IntervalLimiter,
+    /// Start times of budgeted calls inside the current window (may lie in the near future
+    /// for calls still waiting on `spacing`).
+    recent: VecDeque<Instant>,
+}
+
+impl ChatLimiter {
+    fn admit(&mut self, priority: Priority, budget: Option<(u32, Duration)>) -> Admission {
+        let metered = priority != Priority::Unmetered;
+        if let (true, Some((limit, window))) = (metered, budget) {
+            let now = Instant::now();
+            while self
+                .recent
+                .front()
+                .is_some_and(|t| now.saturating_duration_since(*t) >= window)
+            {
+                self.recent.pop_front();
+            }
+            let limit = limit as usize;
+            if priority == Priority::BestEffort && self.recent.len() + limit / 4 >= limit {
+                return Admission::Drop;
+            }
+            if self.recent.len() >= limit {
+                let oldest = self.recent[0];
+                return Admission::Wait((oldest + window).saturating_duration_since(now));
+            }
+        }
+
+        let wait = self.spacing.reserve();
+        if metered {
+            self.recent.push_back(Instant::now() + wait);
+        }
+        Admission::Go(wait)
+    }
+}
+
 /// MessagingPort decorator that rate-limits outbound calls.
 ///
 /// This is a best-effort defense against Telegram 429 errors, primarily for streaming/edit-heavy
 /// workloads. It does not guarantee zero 429s, but it should drastically reduce them.
+///
+/// Besides spacing calls, it keeps group chats under `group_budget` sends/edits per window:
+/// spinner ticks (`edit_html_best_effort`) are dropped first, other calls queue until the window
+/// has room. Edits queued for the same message are coalesced: only the newest content is sent,
+/// by whichever call got in line first, so the final text of a segment is always delivered.
 pub struct ThrottledMessenger {
     inner: Arc<dyn MessagingPort>,
     cfg: ThrottleConfig,
     global: Mutex<IntervalLimiter>,
-    per_chat: Mutex<HashMap<i64, Arc<Mutex<IntervalLimiter>>>>,
+    per_chat: Mutex<HashMap<i64, Arc<Mutex<ChatLimiter>>>>,
+    /// Latest content of edits waiting for a slot, per message.
+    pending_edits: Mutex<HashMap<MessageRef, String>>,
 }
 
 impl ThrottledMessenger {
@@ -71,36 +146,49 @@ impl ThrottledMessenger {
             cfg,
             global: Mutex::new(IntervalLimiter::new(cfg.global_min_interval)),
             per_chat: Mutex::new(HashMap::new()),
+            pending_edits: Mutex::new(HashMap::new()),
         }
     }
 
-    async fn limiter_for_chat(&self, chat_id: i64) -> Arc<Mutex<IntervalLimiter>> {
+    async fn limiter_for_chat(&self, chat_id: i64) -> Arc<Mutex<ChatLimiter>> {
         let mut map = self.per_chat.lock().await;
         map.entry(chat_id)
             .or_insert_with(|| {
-                Arc::new(Mutex::new(IntervalLimiter::new(
-                    self.cfg.per_chat_min_interval,
-                )))
+                Arc::new(Mutex::new(ChatLimiter {
+                    spacing: IntervalLimiter::new(self.cfg.per_chat_min_interval),
+                    recent: VecDeque::new(),
+                }))
             })
             .clone()
     }
 
-    async fn throttle_chat(&self, chat_id: i64) {
-        let global_wait = { self.global.lock().await.reserve() };
-        let chat_wait = {
-            let lim = self.limiter_for_chat(chat_id).await;
-            let mut guard = lim.lock().await;
-            guard.reserve()
-        };
+    fn budget_for(&self, chat_id: i64) -> Option<(u32, Duration)> {
+        // Group and supergroup ids are negative.
+        (chat_id < 0 && self.cfg.group_budget > 0)
+            .then_some((self.cfg.group_budget, self.cfg.budget_window))
+    }
 
-        let wait = if global_wait > chat_wait {
-            global_wait
-        } else {
-            chat_wait
+    /// Wait for this chat's turn; `false` means the call should be skipped.
+    async fn throttle_chat(&self, chat_id: i64, priority: Priority) -> bool {
+        let limiter = self.limiter_for_chat(chat_id).await;
+        let chat_wait = loop {
+            let admission = limiter
+                .lock()
+                .await
+                .admit(priority, self.budget_for(chat_id));
+            match admission {
+                Admission::Go(wait) => break wait,
+                Admission::Wait(wait) => sleep(wait).await,
+                Admission::Drop => return false,
+            }
         };
+        let global_wait = { self.global.lock().await.reserve() };
+
+        let wait = global_wait.max(chat_wait);
         if wait > Duration::from_millis(0) {
             sleep(wait).await;
         }
+        true
     }
 
     async fn throttle_global(&self) {
@@ -117,46 +205,175 @@ impl MessagingPort for ThrottledMessenger {
         self.inner.capabilities()
     }
 
-    async fn send_html(&self, chat_id: ChatId, html: &str) -> Result<MessageRef> {
-        self.throttle_chat(chat_id.0).await;
-        self.inner.send_html(chat_id, html).await
+    async fn send_html(&self, target: ChatTarget, html: &str) -> Result<MessageRef> {
+        self.throttle_chat(target.chat_id.0, Priority::Normal).await;
+        self.inner.send_html(target, html).await
     }
 
     async fn edit_html(&self, msg: MessageRef, html: &str) -> Result<()> {
-        self.throttle_chat(msg.chat_id.0).await;
+        {
+            let mut pending = self.pending_edits.lock().await;
+            if pending.insert(msg, html.to_string()).is_some() {
+                // An earlier edit is already queued and will send this content instead.
+                return Ok(());
+            }
+        }
+        self.throttle_chat(msg.chat_id.0, Priority::Normal).await;
+        let latest = self.pending_edits.lock().await.remove(&msg);
+        self.inner
+            .edit_html(msg, latest.as_deref().unwrap_or(html))
+            .await
+    }
+
+    async fn edit_html_best_effort(&self, msg: MessageRef, html: &str) -> Result<()> {
+        // A queued edit of the same message supersedes a spinner frame.
+        if self.pending_edits.lock().await.contains_key(&msg) {
+            return Ok(());
+        }
+        if !self
+            .throttle_chat(msg.chat_id.0, Priority::BestEffort)
+            .await
+        {
+            return Ok(());
+        }
         self.inner.edit_html(msg, html).await
     }
 
     async fn delete_message(&self, msg: MessageRef) -> Result<()> {
-        self.throttle_chat(msg.chat_id.0).await;
+        self.throttle_chat(msg.chat_id.0, Priority::Unmetered).await;
         self.inner.delete_message(msg).await
     }
 
-    async fn send_chat_action(&self, chat_id: ChatId, action: ChatAction) -> Result<()> {
-        self.throttle_chat(chat_id.0).await;
-        self.inner.send_chat_action(chat_id, action).await
+    async fn send_chat_action(&self, target: ChatTarget, action: ChatAction) -> Result<()> {
+        self.throttle_chat(target.chat_id.0, Priority::Unmetered)
+            .await;
+        self.inner.send_chat_action(target, action).await
     }
 
     async fn set_reaction(&self, msg: MessageRef, emoji: &str) -> Result<()> {
-        self.throttle_chat(msg.chat_id.0).await;
+        self.throttle_chat(msg.chat_id.0, Priority::Unmetered).await;
         self.inner.set_reaction(msg, emoji).await
     }
 
     async fn send_inline_keyboard(
         &self,
-        chat_id: ChatId,
+        target: ChatTarget,
         text: &str,
         keyboard: InlineKeyboard,
     ) -> Result<MessageRef> {
-        self.throttle_chat(chat_id.0).await;
+        self.throttle_chat(target.chat_id.0, Priority::Normal).await;
         self.inner
-            .send_inline_keyboard(chat_id, text, keyboard)
+            .send_inline_keyboard(target, text, keyboard)
             .await
     }
 
-    async fn answer_callback_query(&self, callback_id: &str, text: Option<&str>) -> Result<()> {
+    async fn edit_inline_keyboard(
+        &self,
+        msg: MessageRef,
+        html: &str,
+        keyboard: Option<InlineKeyboard>,
+    ) -> Result<()> {
+        self.throttle_chat(msg.chat_id.0, Priority::Normal).await;
+        self.inner.edit_inline_keyboard(msg, html, keyboard).await
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()> {
         // No chat_id available here; apply global throttling only.
         self.throttle_global().await;
-        self.inner.answer_callback_query(callback_id, text).await
+        self.inner
+            .answer_callback_query(callback_id, text, show_alert)
+            .await
+    }
+
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        path: &Path,
+        caption: Option<&str>,
+    ) -> Result<MessageRef> {
+        self.throttle_chat(target.chat_id.0, Priority::Normal).await;
+        self.inner.send_document(target, path, caption).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{domain::ChatId, messaging::test_support::RecordingMessenger};
+
+    fn governor(
+        inner: Arc<RecordingMessenger>,
+        budget: u32,
+        window: Duration,
+    ) -> ThrottledMessenger {
+        ThrottledMessenger::new(
+            inner,
+            ThrottleConfig {
+                global_min_interval: Duration::ZERO,
+                per_chat_min_interval: Duration::ZERO,
+                group_budget: budget,
+                budget_window: window,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn group_burst_drops_spinner_and_coalesces_edits() {
+        let inner = Arc::new(RecordingMessenger::new());
+        let window = Duration::from_millis(300);
+        let m = governor(inner.clone(), 4, window);
+        let group = ChatTarget::from(ChatId(-100));
+
+        let text = m.send_html(group, "v0").await.unwrap();
+        let spinner = m.send_html(group, "⏳").await.unwrap();
+        m.edit_html(text, "v1").await.unwrap();
+        // 3 of 4 used: spinner frames are dropped to keep headroom for content.
+        m.edit_html_best_effort(spinner, "⌛").await.unwrap();
+
+        m.edit_html(text, "v2").await.unwrap();
+        let started = Instant::now();
+        // Budget spent: the first edit queues, the rest only replace its content.
+        let (a, b, c, d) = tokio::join!(
+            m.edit_html(text, "v3"),
+            async {
+                sleep(Duration::from_millis(20)).await;
+                m.edit_html(text, "v4").await
+            },
+            async {
+                sleep(Duration::from_millis(40)).await;
+                m.edit_html_best_effort(text, "⌛").await
+            },
+            async {
+                sleep(Duration::from_millis(60)).await;
+                m.edit_html(text, "v5 final").await
+            },
+        );
+        for r in [a, b, c, d] {
+            r.unwrap();
+        }
+
+        assert!(started.elapsed() >= window - Duration::from_millis(50));
+        assert_eq!(inner.sends(), ["v0", "⏳"]);
+        assert_eq!(inner.edited_html(), ["v1", "v2", "v5 final"]);
+    }
+
+    #[tokio::test]
+    async fn private_chats_are_not_budgeted() {
+        let inner = Arc::new(RecordingMessenger::new());
+        let m = governor(inner.clone(), 2, Duration::from_secs(60));
+        let private = ChatTarget::from(ChatId(42));
+
+        let msg = m.send_html(private, "hi").await.unwrap();
+        for i in 0..5 {
+            m.edit_html_best_effort(msg, &format!("tick {i}"))
+                .await
+                .unwrap();
+        }
+        assert_eq!(inner.edited_html().len(), 5);
     }
 }
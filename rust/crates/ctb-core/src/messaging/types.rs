@@ -1,4 +1,5 @@
 use crate::domain::{ChatId, MessageId, MessageRef, UserId};
+use crate::theme::{StatusTheme, UNICODE};
 
 /// Cross-messenger incoming update model.
 ///
@@ -78,10 +79,15 @@ pub enum ChatAction {
     UploadDocument,
 }
 
-/// Inline keyboard (buttons) used for callbacks like `ask_user`.
+impl ChatAction {
+    /// Every variant; adapters test their mapping against this list.
+    pub const ALL: [ChatAction; 3] = [Self::Typing, Self::UploadPhoto, Self::UploadDocument];
+}
+
+/// Inline keyboard (rows of buttons) used for callbacks like `ask_user`.
 #[derive(Clone, Debug)]
 pub struct InlineKeyboard {
-    pub buttons: Vec<InlineButton>,
+    pub rows: Vec<Vec<InlineButton>>,
 }
 
 #[derive(Clone, Debug)]
@@ -91,26 +97,129 @@ pub struct InlineButton {
 }
 
 impl InlineKeyboard {
+    /// One button per row.
     pub fn new(buttons: Vec<InlineButton>) -> Self {
-        Self { buttons }
+        Self {
+            rows: buttons.into_iter().map(|b| vec![b]).collect(),
+        }
     }
 
-    /// Convenience for "one button per row" layouts.
-    pub fn one_per_row(request_id: &str, options: &[String], max_label_len: usize) -> Self {
-        let mut buttons = Vec::new();
-        for (idx, opt) in options.iter().enumerate() {
-            let label = if opt.len() > max_label_len {
-                format!("{}...", opt.chars().take(max_label_len).collect::<String>())
-            } else {
-                opt.clone()
-            };
-            let callback_data = format!("askuser:{request_id}:{idx}");
-            buttons.push(InlineButton {
+    pub fn from_rows(rows: Vec<Vec<InlineButton>>) -> Self {
+        Self { rows }
+    }
+
+    pub fn buttons(&self) -> impl Iterator<Item = &InlineButton> {
+        self.rows.iter().flatten()
+    }
+}
+
+/// `ask_user` options shown per keyboard page; more add a "More…" row.
+pub const OPTIONS_PER_PAGE: usize = 8;
+
+/// Shorten `label` to `max_chars` with an ellipsis; the flag says whether it was cut.
+pub fn truncate_label(label: &str, max_chars: usize) -> (String, bool) {
+    if label.chars().count() <= max_chars {
+        return (label.to_string(), false);
+    }
+    let kept: String = label.chars().take(max_chars.saturating_sub(1)).collect();
+    (format!("{}…", kept.trim_end()), true)
+}
+
+/// Builder for `ask_user` option keyboards.
+///
+/// Callback data is `askuser:{request_id}:{index}` for options and `askuser:{request_id}:p{page}`
+/// for page navigation. Options whose labels fit in half the label budget share a row.
+#[derive(Clone, Debug)]
+pub struct OptionsKeyboard<'a> {
+    request_id: &'a str,
+    options: &'a [String],
+    max_label_len: usize,
+    page: usize,
+    theme: &'a StatusTheme,
+}
+
+impl<'a> OptionsKeyboard<'a> {
+    pub fn new(request_id: &'a str, options: &'a [String]) -> Self {
+        Self {
+            request_id,
+            options,
+            max_label_len: 30,
+            page: 0,
+            theme: &UNICODE,
+        }
+    }
+
+    /// Draw the navigation buttons and cut labels with `theme`'s glyphs.
+    pub fn theme(mut self, theme: &'a StatusTheme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    pub fn max_label_len(mut self, max_label_len: usize) -> Self {
+        self.max_label_len = max_label_len.max(2);
+        self
+    }
+
+    /// Zero-based page; clamped to the last page.
+    pub fn page(mut self, page: usize) -> Self {
+        self.page = page;
+        self
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.options.len().div_ceil(OPTIONS_PER_PAGE).max(1)
+    }
+
+    pub fn build(self) -> InlineKeyboard {
+        let page = self.page.min(self.page_count() - 1);
+        let start = page * OPTIONS_PER_PAGE;
+        let end = (start + OPTIONS_PER_PAGE).min(self.options.len());
+        let short = self.max_label_len / 2;
+
+        let mut rows: Vec<Vec<InlineButton>> = Vec::new();
+        let mut pending_short: Option<InlineButton> = None;
+        for (idx, opt) in self.options.iter().enumerate().take(end).skip(start) {
+            let (mut label, cut) = truncate_label(opt, self.max_label_len);
+            if cut {
+                label.pop();
+                label.push_str(self.theme.ellipsis);
+            }
+            let is_short = label.chars().count() <= short;
+            let button = InlineButton {
                 label,
-                callback_data,
+                callback_data: format!("askuser:{}:{idx}", self.request_id),
+            };
+            match (pending_short.take(), is_short) {
+                (Some(prev), true) => rows.push(vec![prev, button]),
+                (Some(prev), false) => {
+                    rows.push(vec![prev]);
+                    rows.push(vec![button]);
+                }
+                (None, true) => pending_short = Some(button),
+                (None, false) => rows.push(vec![button]),
+            }
+        }
+        if let Some(prev) = pending_short {
+            rows.push(vec![prev]);
+        }
+
+        let mut nav = Vec::new();
+        if page > 0 {
+            nav.push(InlineButton {
+                label: self.theme.render("◀️ Back").into_owned(),
+                callback_data: format!("askuser:{}:p{}", self.request_id, page - 1),
+            });
+        }
+        if end < self.options.len() {
+            nav.push(InlineButton {
+                label: self.theme.render("▶️ More…").into_owned(),
+                callback_data: format!("askuser:{}:p{}", self.request_id, page + 1),
             });
         }
-        Self { buttons }
+        if !nav.is_empty() {
+            rows.push(nav);
+        }
+        InlineKeyboard { rows }
     }
 }
 
@@ -122,6 +231,7 @@ pub struct MessagingCapabilities {
     pub supports_reactions: bool,
     pub supports_chat_actions: bool,
     pub supports_inline_keyboards: bool,
+    pub supports_documents: bool,
     pub max_message_len: usize,
 }
 
@@ -145,3 +255,56 @@ impl From<MessageRef> for OutgoingMessageMeta {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(kb: &InlineKeyboard) -> Vec<Vec<&str>> {
+        kb.rows
+            .iter()
+            .map(|r| r.iter().map(|b| b.label.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn short_options_share_rows_and_long_ones_are_truncated() {
+        let options: Vec<String> = ["Yes", "No", "A considerably longer option label", "Skip"]
+            .map(String::from)
+            .to_vec();
+        let kb = OptionsKeyboard::new("r1", &options)
+            .max_label_len(20)
+            .build();
+        assert_eq!(
+            labels(&kb),
+            vec![
+                vec!["Yes", "No"],
+                vec!["A considerably long…"],
+                vec!["Skip"]
+            ]
+        );
+        assert_eq!(kb.rows[1][0].callback_data, "askuser:r1:2");
+        assert_eq!(truncate_label("abcdef", 4), ("abc…".to_string(), true));
+    }
+
+    #[test]
+    fn more_than_a_page_of_options_paginates() {
+        let options: Vec<String> = (0..10).map(|i| format!("Option number {i}")).collect();
+        let first = OptionsKeyboard::new("r2", &options).max_label_len(20);
+        assert_eq!(first.page_count(), 2);
+
+        let kb = first.clone().build();
+        assert_eq!(kb.buttons().count(), OPTIONS_PER_PAGE + 1);
+        let nav = kb.rows.last().unwrap();
+        assert_eq!(nav.len(), 1);
+        assert_eq!(nav[0].callback_data, "askuser:r2:p1");
+
+        let kb = first.page(1).build();
+        let data: Vec<_> = kb.buttons().map(|b| b.callback_data.as_str()).collect();
+        assert_eq!(
+            data,
+            ["askuser:r2:8", "askuser:r2:9", "askuser:r2:p0"],
+            "second page has the rest plus Back"
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! Lightweight latency/throughput metrics for the streaming pipeline (`/perf`, `DEBUG_TIMINGS`).
+//!
+//! Instrumented code records into a [`MetricsSink`]. Each turn gets a [`TurnMetrics`] that keeps
+//! its own numbers (for the completion debug line) and forwards everything to the process-wide
+//! sink, normally a [`ProcessMetrics`].
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::formatting::escape_html;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Counter {
+    Turns,
+    Sends,
+    Edits,
+    Deletes,
+    /// Streaming text updates dropped by the edit throttle.
+    ThrottleSkips,
+    StdoutBytes,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 6] = [
+        Counter::Turns,
+        Counter::Sends,
+        Counter::Edits,
+        Counter::Deletes,
+        Counter::ThrottleSkips,
+        Counter::StdoutBytes,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Counter::Turns => "turns",
+            Counter::Sends => "sends",
+            Counter::Edits => "edits",
+            Counter::Deletes => "deletes",
+            Counter::ThrottleSkips => "throttled edits",
+            Counter::StdoutBytes => "stdout bytes",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Histogram {
+    TimeToFirstToken,
+    CliWallTime,
+}
+
+impl Histogram {
+    pub const ALL: [Histogram; 2] = [Histogram::TimeToFirstToken, Histogram::CliWallTime];
+
+    fn label(self) -> &'static str {
+        match self {
+            Histogram::TimeToFirstToken => "time to first token",
+            Histogram::CliWallTime => "CLI wall time",
+        }
+    }
+}
+
+/// Values where only the highest one seen matters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Peak {
+    /// Model events waiting for the pipeline (bounded by `EVENT_QUEUE_DEPTH`).
+    EventQueueDepth,
+}
+
+impl Peak {
+    pub const ALL: [Peak; 1] = [Peak::EventQueueDepth];
+
+    fn label(self) -> &'static str {
+        match self {
+            Peak::EventQueueDepth => "event queue peak",
+        }
+    }
+}
+
+/// Destination for metrics; implementations must be cheap and never fail.
+pub trait MetricsSink: Send + Sync + fmt::Debug {
+    fn incr(&self, counter: Counter, by: u64);
+    fn observe(&self, histogram: Histogram, value: Duration);
+    /// Raise `peak` to `value` if that is higher.
+    fn peak(&self, peak: Peak, value: u64);
+}
+
+/// Discards everything (default when nothing is wired up).
+#[derive(Debug, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {
+    fn incr(&self, _counter: Counter, _by: u64) {}
+    fn observe(&self, _histogram: Histogram, _value: Duration) {}
+    fn peak(&self, _peak: Peak, _value: u64) {}
+}
+
+/// Upper bounds of the histogram buckets, in milliseconds (last bucket is open-ended).
+const BUCKET_BOUNDS_MS: [u64; 10] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000, 300_000,
+];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistogramStats {
+    pub count: u64,
+    pub sum: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    buckets: [u64; BUCKET_BOUNDS_MS.len() + 1],
+}
+
+impl HistogramStats {
+    fn record(&mut self, value: Duration) {
+        self.min = if self.count == 0 {
+            value
+        } else {
+            self.min.min(value)
+        };
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum += value;
+        let ms = value.as_millis();
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&b| ms <= u128::from(b))
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.sum / self.count as u32)
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, capped at the observed maximum.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64) * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(match BUCKET_BOUNDS_MS.get(i) {
+                    Some(&ms) => Duration::from_millis(ms).min(self.max),
+                    None => self.max,
+                });
+            }
+        }
+        Some(self.max)
+    }
+}
+
+/// Process-wide totals since startup.
+#[derive(Debug, Default)]
+pub struct ProcessMetrics {
+    counters: [AtomicU64; Counter::ALL.len()],
+    peaks: [AtomicU64; Peak::ALL.len()],
+    histograms: Mutex<HashMap<Histogram, HistogramStats>>,
+}
+
+impl MetricsSink for ProcessMetrics {
+    fn incr(&self, counter: Counter, by: u64) {
+        self.counters[counter as usize].fetch_add(by, Ordering::Relaxed);
+    }
+
+    fn observe(&self, histogram: Histogram, value: Duration) {
+        let mut map = self.histograms.lock().unwrap_or_else(|e| e.into_inner());
+        map.entry(histogram).or_default().record(value);
+    }
+
+    fn peak(&self, peak: Peak, value: u64) {
+        self.peaks[peak as usize].fetch_max(value, Ordering::Relaxed);
+    }
+}
+
+impl ProcessMetrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn counter(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn histogram(&self, histogram: Histogram) -> HistogramStats {
+        let map = self.histograms.lock().unwrap_or_else(|e| e.into_inner());
+        map.get(&histogram).cloned().unwrap_or_default()
+    }
+
+    pub fn peak_value(&self, peak: Peak) -> u64 {
+        self.peaks[peak as usize].load(Ordering::Relaxed)
+    }
+
+    /// Report for `/perf`.
+    pub fn to_html(&self) -> String {
+        let mut lines = vec!["⏱️ <b>Pipeline performance</b> (since start)\n".to_string()];
+        for h in Histogram::ALL {
+            let stats = self.histogram(h);
+            let body = match (stats.mean(), stats.quantile(0.5), stats.quantile(0.95)) {
+                (Some(mean), Some(p50), Some(p95)) => format!(
+                    "n={} · avg {} · p50 ≤{} · p95 ≤{} · max {}",
+                    stats.count,
+                    format_secs(mean),
+                    format_secs(p50),
+                    format_secs(p95),
+                    format_secs(stats.max)
+                ),
+                _ => "no data".to_string(),
+            };
+            lines.push(format!("<b>{}</b>: {}", escape_html(h.label()), body));
+        }
+        lines.push(String::new());
+        let turns = self.counter(Counter::Turns);
+        for c in Counter::ALL {
+            let n = self.counter(c);
+            let value = match c {
+                Counter::StdoutBytes => format_bytes(n),
+                _ => n.to_string(),
+            };
+            let per_turn = match c {
+                Counter::Turns => String::new(),
+                _ if turns > 0 => format!(" ({:.1}/turn)", n as f64 / turns as f64),
+                _ => String::new(),
+            };
+            lines.push(format!("{}: {value}{per_turn}", escape_html(c.label())));
+        }
+        for p in Peak::ALL {
+            lines.push(format!(
+                "{}: {}",
+                escape_html(p.label()),
+                self.peak_value(p)
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Numbers for a single turn, forwarded to a parent sink as they are recorded.
+#[derive(Debug)]
+pub struct TurnMetrics {
+    parent: Arc<dyn MetricsSink>,
+    started: Instant,
+    counters: [AtomicU64; Counter::ALL.len()],
+    peaks: [AtomicU64; Peak::ALL.len()],
+    durations: Mutex<HashMap<Histogram, Duration>>,
+}
+
+impl TurnMetrics {
+    pub fn new(parent: Arc<dyn MetricsSink>) -> Self {
+        Self::started_at(parent, Instant::now())
+    }
+
+    pub fn started_at(parent: Arc<dyn MetricsSink>, started: Instant) -> Self {
+        Self {
+            parent,
+            started,
+            counters: Default::default(),
+            peaks: Default::default(),
+            durations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn counter(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn peak_value(&self, peak: Peak) -> u64 {
+        self.peaks[peak as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn duration(&self, histogram: Histogram) -> Option<Duration> {
+        let map = self.durations.lock().unwrap_or_else(|e| e.into_inner());
+        map.get(&histogram).copied()
+    }
+
+    /// Record time-to-first-token at `now`; later calls are ignored.
+    pub fn mark_first_token(&self, now: Instant) {
+        if self.duration(Histogram::TimeToFirstToken).is_none() {
+            let ttft = now.saturating_duration_since(self.started);
+            self.observe(Histogram::TimeToFirstToken, ttft);
+        }
+    }
+
+    /// One-line summary appended to the completion message when `DEBUG_TIMINGS` is on.
+    pub fn debug_line(&self) -> String {
+        let secs = |h| {
+            self.duration(h)
+                .map(format_secs)
+                .unwrap_or_else(|| "–".to_string())
+        };
+        format!(
+            "🔧 ttft {} · cli {} · {} stdout · {} sends / {} edits / {} deletes · {} throttled",
+            secs(Histogram::TimeToFirstToken),
+            secs(Histogram::CliWallTime),
+            format_bytes(self.counter(Counter::StdoutBytes)),
+            self.counter(Counter::Sends),
+            self.counter(Counter::Edits),
+            self.counter(Counter::Deletes),
+            self.counter(Counter::ThrottleSkips),
+        )
+    }
+}
+
+impl MetricsSink for TurnMetrics {
+    fn incr(&self, counter: Counter, by: u64) {
+        self.counters[counter as usize].fetch_add(by, Ordering::Relaxed);
+        self.parent.incr(counter, by);
+    }
+
+    fn observe(&self, histogram: Histogram, value: Duration) {
+        {
+            let mut map = self.durations.lock().unwrap_or_else(|e| e.into_inner());
+            map.insert(histogram, value);
+        }
+        self.parent.observe(histogram, value);
+    }
+
+    fn peak(&self, peak: Peak, value: u64) {
+        self.peaks[peak as usize].fetch_max(value, Ordering::Relaxed);
+        self.parent.peak(peak, value);
+    }
+}
+
+fn format_secs(d: Duration) -> String {
+    format!("{:.1}s", d.as_secs_f64())
+}
+
+fn format_bytes(n: u64) -> String {
+    match n {
+        0..=1023 => format!("{n} B"),
+        1024..=1_048_575 => format!("{:.1} KB", n as f64 / 1024.0),
+        _ => format!("{:.1} MB", n as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turn_metrics_forward_to_process_totals() {
+        let process = ProcessMetrics::new();
+        let start = Instant::now();
+        for _ in 0..2 {
+            let turn = TurnMetrics::started_at(process.clone(), start);
+            turn.incr(Counter::Edits, 3);
+            turn.mark_first_token(start + Duration::from_millis(800));
+            turn.mark_first_token(start + Duration::from_secs(5));
+            assert_eq!(
+                turn.duration(Histogram::TimeToFirstToken),
+                Some(Duration::from_millis(800)),
+                "only the first token counts"
+            );
+            assert_eq!(turn.counter(Counter::Edits), 3);
+        }
+
+        assert_eq!(process.counter(Counter::Edits), 6);
+        TurnMetrics::new(process.clone()).peak(Peak::EventQueueDepth, 40);
+        let turn = TurnMetrics::new(process.clone());
+        turn.peak(Peak::EventQueueDepth, 12);
+        assert_eq!(turn.peak_value(Peak::EventQueueDepth), 12);
+        assert_eq!(
+            process.peak_value(Peak::EventQueueDepth),
+            40,
+            "the highest turn wins"
+        );
+        let ttft = process.histogram(Histogram::TimeToFirstToken);
+        assert_eq!(ttft.count, 2);
+        assert_eq!(ttft.mean(), Some(Duration::from_millis(800)));
+    }
+
+    #[test]
+    fn histogram_quantiles_use_bucket_bounds() {
+        let mut h = HistogramStats::default();
+        assert_eq!(h.quantile(0.5), None);
+        for ms in [50, 80, 90, 400, 7_000] {
+            h.record(Duration::from_millis(ms));
+        }
+        assert_eq!(h.min, Duration::from_millis(50));
+        assert_eq!(h.quantile(0.5), Some(Duration::from_millis(100)));
+        assert_eq!(h.quantile(0.95), Some(Duration::from_millis(7_000)));
+    }
+
+    #[test]
+    fn debug_line_and_report_render() {
+        let process = ProcessMetrics::new();
+        let start = Instant::now();
+        let turn = TurnMetrics::started_at(process.clone(), start);
+        turn.mark_first_token(start + Duration::from_millis(1_250));
+        turn.observe(Histogram::CliWallTime, Duration::from_secs(4));
+        turn.incr(Counter::StdoutBytes, 2_048);
+        turn.incr(Counter::Sends, 2);
+        turn.incr(Counter::Turns, 1);
+        assert_eq!(
+            turn.debug_line(),
+            "🔧 ttft 1.2s · cli 4.0s · 2.0 KB stdout · 2 sends / 0 edits / 0 deletes · 0 throttled"
+        );
+
+        let html = process.to_html();
+        assert!(
+            html.contains("<b>CLI wall time</b>: n=1 · avg 4.0s"),
+            "{html}"
+        );
+        assert!(html.contains("sends: 2 (2.0/turn)"), "{html}");
+    }
+}
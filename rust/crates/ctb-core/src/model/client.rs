@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use async_trait::async_trait;
 
-use crate::Result;
+use crate::{CancelReason, Result};
 
 use super::types::*;
 
@@ -48,11 +48,15 @@ impl ClaudeCliPromptAdapter {
             "--output-format".to_string(),
             "stream-json".to_string(),
             "--verbose".to_string(),
-            // Permissions / tools
+            // Permissions / tools (a per-run mode also drops the provider's skip flag, so a
+            // plan-only turn can't edit files).
             "--permission-mode".to_string(),
-            self.cfg.permission_mode.as_claude_cli_flag().to_string(),
+            req.permission_mode
+                .unwrap_or(self.cfg.permission_mode)
+                .as_claude_cli_flag()
+                .to_string(),
         ];
-        if self.cfg.dangerously_skip_permissions {
+        if self.cfg.dangerously_skip_permissions && req.permission_mode.is_none() {
             args.push("--dangerously-skip-permissions".to_string());
         }
 
@@ -61,20 +65,68 @@ impl ClaudeCliPromptAdapter {
             args.push("--include-partial-messages".to_string());
         }
 
-        // Model selection
-        if let Some(model) = &self.cfg.model {
+        // Model selection (per-run override wins over the provider default).
+        if let Some(model) = req.model.as_ref().or(self.cfg.model.as_ref()) {
             args.push("--model".to_string());
             args.push(model.clone());
         }
 
-        // Append system prompt (safety prompt).
-        if let Some(sys) = &req.system_prompt {
-            args.push("--append-system-prompt".to_string());
-            args.push(sys.clone());
+        if let Some(n) = req.max_turns {
+            args.push("--max-turns".to_string());
+            args.push(n.to_string());
         }
-        if let Some(sys) = &req.append_system_prompt {
+
+        // Appended system prompt: the safety prompt, the sandbox directive, then the `/prompt`
+        // profile. All go in one flag so a repeated option can't drop the safety prompt.
+        let sandbox = req.bash_wrapper.as_deref().map(bash_wrapper_instruction);
+        let system = [&req.system_prompt, &sandbox, &req.append_system_prompt]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if !system.is_empty() {
             args.push("--append-system-prompt".to_string());
-            args.push(sys.clone());
+            args.push(system);
+        }
+
+        // Where the CLI asks before running Bash, pre-approve only wrapped commands; unwrapped
+        // ones are then refused by the CLI itself. Bypass and plan modes ignore allow rules.
+        let mode = req.permission_mode.unwrap_or(self.cfg.permission_mode);
+        let skips_permissions =
+            self.cfg.dangerously_skip_permissions && req.permission_mode.is_none();
+        if let Some(wrapper) = &req.bash_wrapper {
+            if !skips_permissions
+                && matches!(mode, PermissionMode::Default | PermissionMode::AcceptEdits)
+            {
+                let settings = serde_json::json!({
+                    "permissions": {"allow": [format!("Bash({wrapper}:*)")]}
+                });
+                args.push("--settings".to_string());
+                args.push(settings.to_string());
+            }
+        }
+
+        // Tool lists, enforced by the CLI itself (the pipeline's checks still apply).
+        let tool_flags = [
+            (
+                "--allowedTools",
+                &req.allowed_tools,
+                &self.cfg.allowed_tools,
+            ),
+            (
+                "--disallowedTools",
+                &req.disallowed_tools,
+                &self.cfg.disallowed_tools,
+            ),
+        ];
+        for (flag, run, provider) in tool_flags {
+            if let Some(tools) = run.as_ref().or(provider.as_ref()) {
+                if !tools.is_empty() {
+                    args.push(flag.to_string());
+                    args.push(tools.join(","));
+                }
+            }
         }
 
         // Allowed dirs (tools).
@@ -114,6 +166,34 @@ impl ClaudeCliPromptAdapter {
     }
 }
 
+/// System prompt directive asking the model to run every Bash command through `wrapper`.
+pub fn bash_wrapper_instruction(wrapper: &str) -> String {
+    format!(
+        "Bash commands must run inside the sandbox `{wrapper}`: start every command, including \
+         each part of a pipeline or chain, with `{wrapper} `. Wrap compound commands as \
+         `{wrapper} sh -c '...'`. Commands that don't start with it are refused."
+    )
+}
+
+/// Receives a run's events as the provider reads them.
+///
+/// Emitting may wait (the session feeds a bounded queue), which slows the provider's reading
+/// until the consumer catches up. Closures returning `Result<()>` are sinks too.
+#[async_trait]
+pub trait EventSink: Send {
+    async fn emit(&mut self, ev: ModelEvent) -> Result<()>;
+}
+
+#[async_trait]
+impl<F> EventSink for F
+where
+    F: FnMut(ModelEvent) -> Result<()> + Send,
+{
+    async fn emit(&mut self, ev: ModelEvent) -> Result<()> {
+        self(ev)
+    }
+}
+
 /// Model client interface used by the session runner.
 ///
 /// We prefer a callback-based streaming interface over `Stream<Item=...>` to keep
@@ -123,11 +203,112 @@ pub trait ModelClient: Send + Sync {
     fn provider(&self) -> ProviderKind;
     fn capabilities(&self) -> ModelCapabilities;
 
-    async fn run(
-        &self,
-        req: RunRequest,
-        on_event: &mut (dyn FnMut(ModelEvent) -> Result<()> + Send),
-    ) -> Result<RunResult>;
+    async fn run(&self, req: RunRequest, on_event: &mut dyn EventSink) -> Result<RunResult>;
 
-    async fn cancel(&self) -> Result<()>;
+    /// Cancel one in-flight run (no-op if it already finished). Its `run()` returns
+    /// `Error::Cancelled(reason)`.
+    async fn cancel(&self, run_id: RunId, reason: CancelReason) -> Result<()>;
+
+    /// Cancel every in-flight run (shutdown / restart paths: [`CancelReason::Shutdown`]).
+    async fn cancel_all(&self) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn adapter(allowed: Option<&[&str]>, disallowed: Option<&[&str]>) -> ClaudeCliPromptAdapter {
+        let list =
+            |tools: Option<&[&str]>| tools.map(|t| t.iter().map(|s| s.to_string()).collect());
+        ClaudeCliPromptAdapter {
+            cfg: ClaudeCliConfig {
+                claude_path: "/usr/bin/claude".into(),
+                model: None,
+                permission_mode: PermissionMode::Default,
+                dangerously_skip_permissions: false,
+                include_partial_messages: false,
+                kill_grace: Duration::from_millis(100),
+                allowed_tools: list(allowed),
+                disallowed_tools: list(disallowed),
+                stderr_notices: Default::default(),
+            },
+        }
+    }
+
+    fn request() -> RunRequest {
+        RunRequest {
+            run_id: RunId(1),
+            prompt: "hi".to_string(),
+            cwd: "/tmp".into(),
+            add_dirs: Vec::new(),
+            mcp_config_path: None,
+            system_prompt: None,
+            append_system_prompt: None,
+            bash_wrapper: None,
+            allowed_tools: None,
+            disallowed_tools: None,
+            resume: None,
+            fork_session: false,
+            max_thinking_tokens: None,
+            model: None,
+            permission_mode: None,
+            max_turns: None,
+            max_budget_usd: None,
+            metrics: None,
+        }
+    }
+
+    fn flag(args: &[String], name: &str) -> Option<String> {
+        let i = args.iter().position(|a| a == name)?;
+        Some(args[i + 1].clone())
+    }
+
+    #[test]
+    fn tool_lists_become_cli_flags() {
+        let args = adapter(
+            Some(&["Read", "Bash(git log:*)"]),
+            Some(&["WebFetch", "WebSearch"]),
+        )
+        .build_invocation(&request())
+        .args;
+        assert_eq!(
+            flag(&args, "--allowedTools").unwrap(),
+            "Read,Bash(git log:*)"
+        );
+        assert_eq!(
+            flag(&args, "--disallowedTools").unwrap(),
+            "WebFetch,WebSearch"
+        );
+        assert_eq!(args.last().unwrap(), "hi", "the prompt stays last");
+
+        // A run's own list replaces the provider's; an empty one drops the flag.
+        let req = RunRequest {
+            allowed_tools: Some(Vec::new()),
+            disallowed_tools: Some(vec!["Bash".to_string()]),
+            ..request()
+        };
+        let args = adapter(Some(&["Read"]), Some(&["WebFetch"]))
+            .build_invocation(&req)
+            .args;
+        assert_eq!(flag(&args, "--allowedTools"), None);
+        assert_eq!(flag(&args, "--disallowedTools").unwrap(), "Bash");
+    }
+
+    #[test]
+    fn unset_tool_lists_are_omitted() {
+        let args = adapter(None, None).build_invocation(&request()).args;
+        assert!(!args.iter().any(|a| a.ends_with("Tools")), "{args:?}");
+    }
+
+    #[test]
+    fn tool_lists_parse_and_refuse_flag_like_entries() {
+        assert_eq!(
+            parse_tool_list(" Read, ,Bash(git diff:*) ").unwrap(),
+            ["Read", "Bash(git diff:*)"]
+        );
+        assert!(parse_tool_list("").unwrap().is_empty());
+        assert!(parse_tool_list("Read,--dangerously-skip-permissions").is_err());
+    }
 }
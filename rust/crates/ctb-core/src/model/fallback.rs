@@ -0,0 +1,478 @@
+//! Provider failover: try an ordered list of [`ModelClient`]s until one answers.
+//!
+//! A provider is only abandoned while nothing of its run has reached the chat. Events are held
+//! back until the first real assistant message (text or tool call); a failure before that point
+//! discards them and the same request goes to the next provider. Once a message is forwarded the
+//! run belongs to that provider, so an answer is never shown twice and tools never run twice.
+//!
+//! A fallback runs its own model (when it has one) and starts a new conversation: the session
+//! being resumed belongs to the provider that failed.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+use crate::{CancelReason, Error, Result};
+
+use super::{
+    client::{EventSink, ModelClient},
+    types::*,
+};
+
+/// Result text of the CLI's synthetic reply when its API key is rejected.
+const INVALID_API_KEY: &str = "Invalid API key";
+
+struct Provider {
+    name: String,
+    client: Arc<dyn ModelClient>,
+    /// Replaces the request's model (e.g. a `/model` override) when set.
+    model: Option<String>,
+}
+
+/// [`ModelClient`] that fails over to the next provider on a transient failure (see
+/// [`should_fail_over`]). Capabilities and provider kind are the primary's.
+pub struct FallbackModelClient {
+    providers: Vec<Provider>,
+    // In-flight runs -> why they were cancelled, so a cancel landing between two providers
+    // sticks.
+    runs: Mutex<HashMap<RunId, Option<CancelReason>>>,
+}
+
+impl FallbackModelClient {
+    /// `name` is shown to the user when the run moves away from or onto this provider.
+    pub fn new(name: impl Into<String>, primary: Arc<dyn ModelClient>) -> Self {
+        Self {
+            providers: vec![Provider {
+                name: name.into(),
+                client: primary,
+                model: None,
+            }],
+            runs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Append a provider, tried after the ones added before it, running `model` (`None` keeps
+    /// the request's).
+    pub fn with_fallback(
+        mut self,
+        name: impl Into<String>,
+        client: Arc<dyn ModelClient>,
+        model: Option<String>,
+    ) -> Self {
+        self.providers.push(Provider {
+            name: name.into(),
+            client,
+            model,
+        });
+        self
+    }
+
+    fn runs(&self) -> std::sync::MutexGuard<'_, HashMap<RunId, Option<CancelReason>>> {
+        self.runs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn cancelled(&self, run_id: RunId) -> Option<CancelReason> {
+        self.runs().get(&run_id).copied().flatten()
+    }
+
+    async fn run_providers(
+        &self,
+        req: RunRequest,
+        on_event: &mut dyn EventSink,
+    ) -> Result<RunResult> {
+        let mut result = Err(Error::External("no model provider configured".to_string()));
+        for (i, provider) in self.providers.iter().enumerate() {
+            if let Some(reason) = self.cancelled(req.run_id) {
+                return Err(Error::Cancelled(reason));
+            }
+
+            let mut forward = HoldingSink {
+                inner: &mut *on_event,
+                held: Vec::new(),
+                committed: false,
+            };
+            let mut attempt = req.clone();
+            if i > 0 {
+                attempt.resume = None;
+                attempt.fork_session = false;
+                if provider.model.is_some() {
+                    attempt.model = provider.model.clone();
+                }
+            }
+            result = provider.client.run(attempt, &mut forward).await;
+            let HoldingSink {
+                held, committed, ..
+            } = forward;
+
+            match self.providers.get(i + 1) {
+                Some(next) if !committed && should_fail_over(&result) => {
+                    let reason = match &result {
+                        Ok(r) => r.text.clone(),
+                        Err(e) => e.to_string(),
+                    };
+                    log_line!(
+                        "[MODEL] {} failed ({}), retrying with {}",
+                        provider.name,
+                        reason.lines().next().unwrap_or_default(),
+                        next.name
+                    );
+                    on_event
+                        .emit(ModelEvent::Notice {
+                            text: format!(
+                                "⚠️ {} failed, retrying with {}",
+                                provider.name, next.name
+                            ),
+                        })
+                        .await?;
+                }
+                _ => {
+                    // Final outcome: whatever was held back (e.g. the error reply) is shown now.
+                    for held_ev in held {
+                        on_event.emit(held_ev).await?;
+                    }
+                    break;
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Holds a provider's events back until one commits the run (see [`commits_run`]), then
+/// forwards everything to `inner`.
+struct HoldingSink<'a> {
+    inner: &'a mut dyn EventSink,
+    held: Vec<ModelEvent>,
+    committed: bool,
+}
+
+#[async_trait]
+impl EventSink for HoldingSink<'_> {
+    async fn emit(&mut self, ev: ModelEvent) -> Result<()> {
+        if !self.committed {
+            if !commits_run(&ev) {
+                self.held.push(ev);
+                return Ok(());
+            }
+            self.committed = true;
+            for held_ev in self.held.drain(..) {
+                self.inner.emit(held_ev).await?;
+            }
+        }
+        self.inner.emit(ev).await
+    }
+}
+
+/// The provider failed without answering in a way the next one may not: it could not be
+/// started, exited non-zero without a result, or rejected its API key.
+pub fn should_fail_over(result: &Result<RunResult>) -> bool {
+    match result {
+        Ok(r) => r.is_error && r.text.contains(INVALID_API_KEY),
+        Err(Error::Io(_)) => true,
+        Err(Error::ModelCrashed(_)) => true,
+        Err(_) => false,
+    }
+}
+
+/// An assistant message that will be shown; the CLI's `<synthetic>` error replies are not.
+fn commits_run(ev: &ModelEvent) -> bool {
+    let ModelEvent::Assistant { raw } = ev else {
+        return false;
+    };
+    raw.get("message")
+        .and_then(|m| m.get("model"))
+        .and_then(|m| m.as_str())
+        != Some("<synthetic>")
+}
+
+#[async_trait]
+impl ModelClient for FallbackModelClient {
+    fn provider(&self) -> ProviderKind {
+        self.providers[0].client.provider()
+    }
+
+    fn capabilities(&self) -> ModelCapabilities {
+        self.providers[0].client.capabilities()
+    }
+
+    async fn run(&self, req: RunRequest, on_event: &mut dyn EventSink) -> Result<RunResult> {
+        let run_id = req.run_id;
+        self.runs().insert(run_id, None);
+        let result = self.run_providers(req, on_event).await;
+        self.runs().remove(&run_id);
+        result
+    }
+
+    async fn cancel(&self, run_id: RunId, reason: CancelReason) -> Result<()> {
+        if let Some(cancelled) = self.runs().get_mut(&run_id) {
+            cancelled.get_or_insert(reason);
+        }
+        for p in &self.providers {
+            p.client.cancel(run_id, reason).await?;
+        }
+        Ok(())
+    }
+
+    async fn cancel_all(&self) -> Result<()> {
+        for cancelled in self.runs().values_mut() {
+            cancelled.get_or_insert(CancelReason::Shutdown);
+        }
+        for p in &self.providers {
+            p.client.cancel_all().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Replays `events` and then returns `outcome`; records every request it gets.
+    struct Scripted {
+        events: Vec<ModelEvent>,
+        outcome: fn() -> Result<RunResult>,
+        requests: Mutex<Vec<RunRequest>>,
+    }
+
+    impl Scripted {
+        fn new(events: Vec<ModelEvent>, outcome: fn() -> Result<RunResult>) -> Arc<Self> {
+            Arc::new(Self {
+                events,
+                outcome,
+                requests: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn calls(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+
+        fn last_request(&self) -> RunRequest {
+            self.requests.lock().unwrap().last().cloned().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl ModelClient for Scripted {
+        fn provider(&self) -> ProviderKind {
+            ProviderKind::ClaudeCli
+        }
+
+        fn capabilities(&self) -> ModelCapabilities {
+            ModelCapabilities {
+                supports_streaming: true,
+                supports_tools: true,
+                supports_vision: false,
+                supports_thinking: true,
+                supports_mcp: true,
+            }
+        }
+
+        async fn run(&self, req: RunRequest, on_event: &mut dyn EventSink) -> Result<RunResult> {
+            self.requests.lock().unwrap().push(req);
+            for ev in &self.events {
+                on_event.emit(ev.clone()).await?;
+            }
+            (self.outcome)()
+        }
+
+        async fn cancel(&self, _run_id: RunId, _reason: CancelReason) -> Result<()> {
+            Ok(())
+        }
+
+        async fn cancel_all(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request() -> RunRequest {
+        RunRequest {
+            run_id: RunId::next(),
+            prompt: "hi".to_string(),
+            cwd: "/tmp".into(),
+            add_dirs: vec![],
+            mcp_config_path: None,
+            system_prompt: None,
+            append_system_prompt: None,
+            bash_wrapper: None,
+            allowed_tools: None,
+            disallowed_tools: None,
+            resume: Some(SessionRef {
+                provider: ProviderKind::ClaudeCli,
+                id: "s-1".to_string(),
+            }),
+            fork_session: false,
+            max_thinking_tokens: None,
+            model: Some("sonnet".to_string()),
+            permission_mode: None,
+            max_turns: None,
+            max_budget_usd: None,
+            metrics: None,
+        }
+    }
+
+    fn assistant(model: &str, text: &str) -> ModelEvent {
+        ModelEvent::Assistant {
+            raw: json!({"message": {"model": model, "content": [{"type": "text", "text": text}]}}),
+        }
+    }
+
+    fn answered(text: &'static str) -> Result<RunResult> {
+        Ok(RunResult {
+            session: None,
+            is_error: false,
+            text: text.to_string(),
+            usage: None,
+        })
+    }
+
+    async fn run(client: &FallbackModelClient) -> (Result<RunResult>, Vec<ModelEvent>) {
+        let mut seen = Vec::new();
+        let result = client
+            .run(request(), &mut |ev| {
+                seen.push(ev);
+                Ok(())
+            })
+            .await;
+        (result, seen)
+    }
+
+    fn texts(events: &[ModelEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|ev| match ev {
+                ModelEvent::Notice { text } => text.clone(),
+                ModelEvent::Assistant { raw } => raw["message"]["content"][0]["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fails_over_before_any_answer_is_shown() {
+        let crashed = Scripted::new(vec![ModelEvent::SystemInit { raw: json!({}) }], || {
+            Err(Error::ModelCrashed(
+                "claude exited with status 1".to_string(),
+            ))
+        });
+        let backup = Scripted::new(vec![assistant("opus", "from backup")], || {
+            answered("from backup")
+        });
+        let client = FallbackModelClient::new("Claude CLI", crashed.clone()).with_fallback(
+            "Claude CLI (opus)",
+            backup.clone(),
+            Some("opus".to_string()),
+        );
+
+        let (result, seen) = run(&client).await;
+        assert_eq!(result.unwrap().text, "from backup");
+        assert_eq!(
+            texts(&seen),
+            [
+                "⚠️ Claude CLI failed, retrying with Claude CLI (opus)",
+                "from backup"
+            ]
+        );
+        assert_eq!((crashed.calls(), backup.calls()), (1, 1));
+
+        // The primary got the request as sent; the backup its own model and a new session.
+        let first = crashed.last_request();
+        assert_eq!(first.model.as_deref(), Some("sonnet"));
+        assert!(first.resume.is_some());
+        let retried = backup.last_request();
+        assert_eq!(retried.model.as_deref(), Some("opus"));
+        assert!(retried.resume.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_api_key_reply_is_replaced_by_the_fallback() {
+        let rejected = Scripted::new(
+            vec![assistant(
+                "<synthetic>",
+                "Invalid API key · Fix external API key",
+            )],
+            || {
+                Ok(RunResult {
+                    session: None,
+                    is_error: true,
+                    text: "Invalid API key · Fix external API key".to_string(),
+                    usage: None,
+                })
+            },
+        );
+        let backup = Scripted::new(vec![assistant("opus", "ok")], || answered("ok"));
+        let client =
+            FallbackModelClient::new("Claude CLI", rejected).with_fallback("backup", backup, None);
+
+        let (result, seen) = run(&client).await;
+        assert!(!result.unwrap().is_error);
+        assert_eq!(
+            texts(&seen),
+            ["⚠️ Claude CLI failed, retrying with backup", "ok"]
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_the_failure_once_output_was_shown() {
+        let partial = Scripted::new(vec![assistant("sonnet", "half an answer")], || {
+            Err(Error::ModelCrashed(
+                "claude exited with status 1".to_string(),
+            ))
+        });
+        let backup = Scripted::new(vec![], || answered("unused"));
+        let client = FallbackModelClient::new("Claude CLI", partial).with_fallback(
+            "backup",
+            backup.clone(),
+            None,
+        );
+
+        let (result, seen) = run(&client).await;
+        assert!(result.is_err());
+        assert_eq!(texts(&seen), ["half an answer"]);
+        assert_eq!(backup.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn last_provider_failure_shows_its_held_events() {
+        let rejected = Scripted::new(vec![assistant("<synthetic>", "Invalid API key")], || {
+            Ok(RunResult {
+                session: None,
+                is_error: true,
+                text: "Invalid API key".to_string(),
+                usage: None,
+            })
+        });
+        let client = FallbackModelClient::new("Claude CLI", rejected);
+
+        let (result, seen) = run(&client).await;
+        assert!(result.unwrap().is_error);
+        assert_eq!(texts(&seen), ["Invalid API key"]);
+    }
+
+    #[test]
+    fn classifies_transient_failures() {
+        let io = Err(Error::Io(std::io::Error::from(
+            std::io::ErrorKind::NotFound,
+        )));
+        assert!(should_fail_over(&io));
+        assert!(should_fail_over(&Err(Error::ModelCrashed(
+            "claude exited with status 2\nstderr (tail):\nboom".to_string()
+        ))));
+        assert!(!should_fail_over(&Err(Error::External(
+            "claude exited with status 2".to_string()
+        ))));
+        assert!(!should_fail_over(&Err(Error::Cancelled(
+            CancelReason::UserStop
+        ))));
+        assert!(!should_fail_over(&answered("fine")));
+    }
+}
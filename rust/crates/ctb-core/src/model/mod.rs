@@ -1,4 +1,5 @@
 //! Model provider abstraction (Claude CLI primary; others optional).
 
 pub mod client;
+pub mod fallback;
 pub mod types;
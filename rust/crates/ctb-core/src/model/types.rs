@@ -1,7 +1,16 @@
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 
+use crate::{metrics::MetricsSink, usage::pricing::ModelPricing};
+
 /// The provider backend used for a session.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ProviderKind {
@@ -60,6 +69,45 @@ pub struct TokenUsage {
     pub cache_creation_input_tokens: u64,
 }
 
+impl TokenUsage {
+    /// Prompt-side tokens (fresh input plus cache reads/writes).
+    pub fn prompt_tokens(&self) -> u64 {
+        self.input_tokens + self.cache_read_input_tokens + self.cache_creation_input_tokens
+    }
+
+    /// A stream-json `usage` object (missing counters are 0).
+    pub fn from_json(v: &serde_json::Value) -> Self {
+        let get = |k: &str| v.get(k).and_then(|x| x.as_u64()).unwrap_or(0);
+        TokenUsage {
+            input_tokens: get("input_tokens"),
+            output_tokens: get("output_tokens"),
+            cache_read_input_tokens: get("cache_read_input_tokens"),
+            cache_creation_input_tokens: get("cache_creation_input_tokens"),
+        }
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+    }
+
+    /// Field-wise maximum (merging partial snapshots of the same message).
+    pub fn max(&self, other: &TokenUsage) -> TokenUsage {
+        TokenUsage {
+            input_tokens: self.input_tokens.max(other.input_tokens),
+            output_tokens: self.output_tokens.max(other.output_tokens),
+            cache_read_input_tokens: self
+                .cache_read_input_tokens
+                .max(other.cache_read_input_tokens),
+            cache_creation_input_tokens: self
+                .cache_creation_input_tokens
+                .max(other.cache_creation_input_tokens),
+        }
+    }
+}
+
 /// Provider selection for the Rust port.
 #[derive(Clone, Debug)]
 pub enum ModelConfig {
@@ -74,22 +122,172 @@ pub struct ClaudeCliConfig {
     pub permission_mode: PermissionMode,
     pub dangerously_skip_permissions: bool,
     pub include_partial_messages: bool,
+    /// How long a cancelled run may take to exit after SIGINT before it is SIGKILLed.
+    pub kill_grace: Duration,
+    /// Default tool lists (`--allowedTools` / `--disallowedTools`) for runs that don't set
+    /// their own.
+    pub allowed_tools: Option<Vec<String>>,
+    pub disallowed_tools: Option<Vec<String>>,
+    /// stderr lines shown in the chat as notices (everything else only feeds the error tail).
+    pub stderr_notices: NoticePatterns,
+}
+
+/// Case-insensitive patterns picking the CLI stderr lines worth showing to the user
+/// (`CLAUDE_STDERR_NOTICES`). The default set matches nothing.
+#[derive(Clone, Debug, Default)]
+pub struct NoticePatterns(Option<regex::RegexSet>);
+
+impl NoticePatterns {
+    /// Warnings, deprecations and MCP servers that failed to start.
+    pub const DEFAULTS: [&'static str; 3] = ["warn", "deprecat", "failed to connect to MCP"];
+
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, String> {
+        case_insensitive_set(patterns).map(Self)
+    }
+
+    pub fn defaults() -> Self {
+        Self::new(&Self::DEFAULTS).expect("valid default notice patterns")
+    }
+
+    pub fn matches(&self, line: &str) -> bool {
+        self.0.as_ref().is_some_and(|set| set.is_match(line))
+    }
+}
+
+/// Case-insensitive patterns for result texts that report a provider failure rather than an
+/// answer (`PROVIDER_ERROR_PATTERNS`). The default set matches nothing.
+#[derive(Clone, Debug, Default)]
+pub struct ProviderErrorPatterns(Option<regex::RegexSet>);
+
+impl ProviderErrorPatterns {
+    /// What the CLI answers with when it can't reach the API or its credentials are refused.
+    pub const DEFAULTS: [&'static str; 4] = [
+        "^API Error",
+        "^Invalid API key",
+        "^Credit balance is too low",
+        "^OAuth token has expired",
+    ];
+
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, String> {
+        case_insensitive_set(patterns).map(Self)
+    }
+
+    pub fn defaults() -> Self {
+        Self::new(&Self::DEFAULTS).expect("valid default provider error patterns")
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        self.0.as_ref().is_some_and(|set| set.is_match(text.trim()))
+    }
+}
+
+fn case_insensitive_set<S: AsRef<str>>(patterns: &[S]) -> Result<Option<regex::RegexSet>, String> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    regex::RegexSetBuilder::new(patterns.iter().map(|p| p.as_ref()))
+        .case_insensitive(true)
+        .build()
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// A provider error that a second attempt may not hit: the API could not be reached.
+pub fn is_connection_error(text: &str) -> bool {
+    let text = text.to_ascii_lowercase();
+    [
+        "connection error",
+        "connection refused",
+        "econnreset",
+        "socket hang up",
+        "fetch failed",
+    ]
+    .iter()
+    .any(|needle| text.contains(needle))
+}
+
+/// Model aliases understood by the Claude CLI (`--model <alias>`).
+///
+/// Full model ids (e.g. `claude-sonnet-4-5-20250929`) are accepted as well.
+pub const KNOWN_MODEL_ALIASES: &[&str] = &["sonnet", "opus", "haiku"];
+
+/// Reject model names that could not possibly be valid and would be unsafe to forward as a CLI
+/// argument (whitespace, shell metacharacters, leading `-` that would parse as a flag).
+pub fn is_valid_model_name(name: &str) -> bool {
+    if name.is_empty() || name.len() > 100 || name.starts_with('-') {
+        return false;
+    }
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '/' | '[' | ']'))
+}
+
+/// Parse a comma-separated tool list such as `Read,Grep,Bash(git log:*)`.
+///
+/// Entries starting with `-` are refused: the list is passed as a CLI argument and must not
+/// parse as a flag.
+pub fn parse_tool_list(raw: &str) -> Result<Vec<String>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(|t| {
+            if t.starts_with('-') || t.len() > 200 || t.chars().any(char::is_control) {
+                Err(format!("invalid tool {t:?}"))
+            } else {
+                Ok(t.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Identifies one in-flight `ModelClient::run()` so it can be cancelled without touching
+/// concurrent runs for other chats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RunId(pub u64);
+
+impl RunId {
+    /// Allocate a process-unique run id.
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 /// Normalized request for a single run.
 #[derive(Clone, Debug)]
 pub struct RunRequest {
+    /// Handle used by `ModelClient::cancel()` to target this run.
+    pub run_id: RunId,
     pub prompt: String,
     pub cwd: PathBuf,
     pub add_dirs: Vec<PathBuf>,
     pub mcp_config_path: Option<PathBuf>,
     pub system_prompt: Option<String>,
     pub append_system_prompt: Option<String>,
+    /// Sandbox command Bash calls must start with (`BASH_SANDBOX_WRAPPER`).
+    pub bash_wrapper: Option<String>,
+    /// Tools the CLI may use / must not use. `None` uses the provider's list; an empty list
+    /// runs without one.
+    pub allowed_tools: Option<Vec<String>>,
+    pub disallowed_tools: Option<Vec<String>>,
 
     pub resume: Option<SessionRef>,
     pub fork_session: bool,
 
     pub max_thinking_tokens: Option<u32>,
+
+    /// Per-run model override (falls back to the provider config when `None`).
+    pub model: Option<String>,
+    /// Per-run permission mode (e.g. `Plan` for plan-only turns). Replaces the provider's mode
+    /// and its `--dangerously-skip-permissions`.
+    pub permission_mode: Option<PermissionMode>,
+
+    /// Maximum agentic turns (`--max-turns`).
+    pub max_turns: Option<u32>,
+    /// Estimated spend cap in USD. Enforced by the session pipeline, not the provider.
+    pub max_budget_usd: Option<f64>,
+
+    /// Receives provider-side timings (process wall time, stdout volume) for this run.
+    pub metrics: Option<Arc<dyn MetricsSink>>,
 }
 
 #[derive(Clone, Debug)]
@@ -100,14 +298,138 @@ pub struct RunResult {
     pub usage: Option<TokenUsage>,
 }
 
+/// The `result` lines of one run, merged. The CLI can emit more than one (an error result
+/// followed by a retried final one, or one per subtask):
+/// - error: the final result's `is_error`, so a final success clears earlier failures;
+/// - text: the final result's when it failed, else the last successful result's, so text and
+///   error never disagree;
+/// - usage: summed over all results;
+/// - cost: `total_cost_usd` is the run's running total, so the latest one stands for every
+///   result before it; results after it that report none are priced at the rates passed to
+///   [`MergedResult::cost_usd`].
+#[derive(Clone, Debug, Default)]
+pub struct MergedResult {
+    count: usize,
+    success_text: Option<String>,
+    last_text: Option<String>,
+    final_is_error: Option<bool>,
+    usage: Option<TokenUsage>,
+    reported_cost_usd: Option<f64>,
+    unpriced_usage: Option<TokenUsage>,
+}
+
+impl MergedResult {
+    /// Fold in one `{"type":"result"}` line.
+    pub fn push(&mut self, raw: &serde_json::Value) {
+        self.count += 1;
+        let is_error = raw.get("is_error").and_then(|v| v.as_bool());
+        self.final_is_error = is_error;
+        if let Some(text) = raw.get("result").and_then(|v| v.as_str()) {
+            if is_error != Some(true) {
+                self.success_text = Some(text.to_string());
+            }
+            self.last_text = Some(text.to_string());
+        }
+        let usage = raw.get("usage").map(TokenUsage::from_json);
+        if let Some(u) = &usage {
+            self.usage.get_or_insert_with(TokenUsage::default).add(u);
+        }
+        match (raw.get("total_cost_usd").and_then(|v| v.as_f64()), usage) {
+            (Some(total), _) => {
+                self.reported_cost_usd = Some(total.max(self.reported_cost_usd.unwrap_or(0.0)));
+                self.unpriced_usage = None;
+            }
+            (None, Some(u)) => self
+                .unpriced_usage
+                .get_or_insert_with(TokenUsage::default)
+                .add(&u),
+            (None, None) => {}
+        }
+    }
+
+    /// Number of result lines seen.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        if self.final_is_error == Some(true) {
+            return self.last_text.as_deref();
+        }
+        self.success_text.as_deref().or(self.last_text.as_deref())
+    }
+
+    /// `None` when the final result did not report `is_error`.
+    pub fn is_error(&self) -> Option<bool> {
+        self.final_is_error
+    }
+
+    pub fn usage(&self) -> Option<&TokenUsage> {
+        self.usage.as_ref()
+    }
+
+    /// The reported cost plus `pricing`'s estimate for results that reported none.
+    pub fn cost_usd(&self, pricing: &ModelPricing) -> Option<f64> {
+        let estimated = self
+            .unpriced_usage
+            .as_ref()
+            .map(|u| pricing.cost(u).total());
+        match (self.reported_cost_usd, estimated) {
+            (None, None) => None,
+            (reported, estimated) => Some(reported.unwrap_or(0.0) + estimated.unwrap_or(0.0)),
+        }
+    }
+}
+
 /// Provider-agnostic model events emitted during a run.
 ///
 /// The Rust port keeps `raw` JSON for forward-compat as CLI schemas evolve.
 #[derive(Clone, Debug)]
 pub enum ModelEvent {
-    SystemInit { raw: serde_json::Value },
-    Assistant { raw: serde_json::Value },
-    Tool { raw: serde_json::Value },
-    Result { raw: serde_json::Value },
-    Unknown { raw: serde_json::Value },
+    SystemInit {
+        raw: serde_json::Value,
+    },
+    /// `system` lines with subtype `compact_boundary`: the CLI summarized older context.
+    ContextCompacted {
+        raw: serde_json::Value,
+    },
+    Assistant {
+        raw: serde_json::Value,
+    },
+    Tool {
+        raw: serde_json::Value,
+    },
+    /// `user` lines carrying `tool_result` blocks (outcome of a previous `tool_use`).
+    ToolResult {
+        raw: serde_json::Value,
+    },
+    Result {
+        raw: serde_json::Value,
+    },
+    Unknown {
+        raw: serde_json::Value,
+    },
+    /// Status line from the client itself rather than the provider (e.g. a provider failover).
+    Notice {
+        text: String,
+    },
+}
+
+impl ModelEvent {
+    /// Classify one Claude CLI `stream-json` line by its `type` (and `subtype`).
+    pub fn from_stream_json(raw: serde_json::Value) -> Self {
+        match raw.get("type").and_then(|v| v.as_str()) {
+            Some("system")
+                if raw.get("subtype").and_then(|v| v.as_str()) == Some("compact_boundary") =>
+            {
+                ModelEvent::ContextCompacted { raw }
+            }
+            Some("system") => ModelEvent::SystemInit { raw },
+            Some("assistant") => ModelEvent::Assistant { raw },
+            Some("result") => ModelEvent::Result { raw },
+            Some("user") => ModelEvent::ToolResult { raw },
+            Some("tool_progress") | Some("tool_use_summary") => ModelEvent::Tool { raw },
+            _ => ModelEvent::Unknown { raw },
+        }
+    }
 }
@@ -0,0 +1,170 @@
+//! OCR fallback for photo prompts.
+//!
+//! Photos are normally passed to the model by path for it to read. When the model can't see
+//! images (`supports_vision: false`) or `PHOTO_OCR_FORCE` is set, each photo's text is extracted
+//! here and appended to the prompt. Extraction is best-effort: a photo that yields nothing, or an
+//! extractor that isn't installed, leaves the prompt as it was.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::{errors::Error, model::types::ModelCapabilities, Result};
+
+/// Longest a single `tesseract` run may take.
+const TESSERACT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Text kept per image.
+pub const MAX_OCR_CHARS: usize = 4000;
+
+/// Read the text in an image file.
+#[async_trait]
+pub trait ImageTextExtractor: Send + Sync {
+    /// The recognised text; empty when the image has none.
+    async fn extract(&self, path: &Path) -> Result<String>;
+}
+
+/// Default extractor: the `tesseract` CLI, if installed.
+#[derive(Clone, Debug)]
+pub struct Tesseract {
+    pub program: PathBuf,
+}
+
+impl Default for Tesseract {
+    fn default() -> Self {
+        Self {
+            program: PathBuf::from("tesseract"),
+        }
+    }
+}
+
+#[async_trait]
+impl ImageTextExtractor for Tesseract {
+    async fn extract(&self, path: &Path) -> Result<String> {
+        let run = tokio::process::Command::new(&self.program)
+            .arg(path)
+            .arg("stdout")
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let out = tokio::time::timeout(TESSERACT_TIMEOUT, run)
+            .await
+            .map_err(|_| Error::External("tesseract timed out".to_string()))?
+            .map_err(|e| Error::External(format!("tesseract: {e}")))?;
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            return Err(Error::External(format!(
+                "tesseract exited with {}: {}",
+                out.status,
+                stderr.trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).to_string())
+    }
+}
+
+/// Whether photo prompts should carry OCR text.
+pub fn needs_ocr(force: bool, capabilities: ModelCapabilities) -> bool {
+    force || !capabilities.supports_vision
+}
+
+/// `prompt` followed by an `[OCR of image N]: ...` block for each photo with text. Photos whose
+/// extraction fails or finds nothing are skipped.
+pub async fn append_ocr(
+    prompt: String,
+    photo_paths: &[String],
+    extractor: &dyn ImageTextExtractor,
+) -> String {
+    let mut blocks = Vec::new();
+    for (i, path) in photo_paths.iter().enumerate() {
+        match extractor.extract(Path::new(path)).await {
+            Ok(text) => {
+                let text = text.trim();
+                if !text.is_empty() {
+                    let text: String = text.chars().take(MAX_OCR_CHARS).collect();
+                    blocks.push(format!("[OCR of image {}]: {text}", i + 1));
+                }
+            }
+            Err(e) => log_line!("[OCR] Skipping {path}: {e}"),
+        }
+    }
+    if blocks.is_empty() {
+        return prompt;
+    }
+    format!("{prompt}\n\n{}", blocks.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Text per file name; other files fail.
+    struct FakeExtractor(HashMap<&'static str, &'static str>);
+
+    #[async_trait]
+    impl ImageTextExtractor for FakeExtractor {
+        async fn extract(&self, path: &Path) -> Result<String> {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            self.0
+                .get(name)
+                .map(|t| t.to_string())
+                .ok_or_else(|| Error::External(format!("cannot read {name}")))
+        }
+    }
+
+    fn capabilities(supports_vision: bool) -> ModelCapabilities {
+        ModelCapabilities {
+            supports_streaming: true,
+            supports_tools: true,
+            supports_vision,
+            supports_thinking: true,
+            supports_mcp: true,
+        }
+    }
+
+    #[test]
+    fn ocr_runs_without_vision_or_when_forced() {
+        assert!(!needs_ocr(false, capabilities(true)));
+        assert!(needs_ocr(false, capabilities(false)));
+        assert!(needs_ocr(true, capabilities(true)));
+    }
+
+    #[tokio::test]
+    async fn ocr_text_is_appended_per_image_and_failures_are_skipped() {
+        let fake = FakeExtractor(HashMap::from([
+            ("receipt.jpg", "  TOTAL 12.50 EUR\n"),
+            ("blank.jpg", " \n"),
+            ("sign.jpg", "NO PARKING"),
+        ]));
+        let photos = [
+            "/tmp/receipt.jpg",
+            "/tmp/blank.jpg",
+            "/tmp/gone.jpg",
+            "/tmp/sign.jpg",
+        ]
+        .map(String::from);
+
+        let prompt = append_ocr("[Photos: ...]\n\nwhat is this?".to_string(), &photos, &fake).await;
+        assert_eq!(
+            prompt,
+            "[Photos: ...]\n\nwhat is this?\n\n[OCR of image 1]: TOTAL 12.50 EUR\n\n\
+             [OCR of image 4]: NO PARKING"
+        );
+
+        let unchanged = append_ocr("look".to_string(), &photos[1..3], &fake).await;
+        assert_eq!(unchanged, "look");
+    }
+
+    #[tokio::test]
+    async fn missing_tesseract_is_an_error() {
+        let tesseract = Tesseract {
+            program: PathBuf::from("/nonexistent/tesseract"),
+        };
+        assert!(tesseract.extract(Path::new("/tmp/x.jpg")).await.is_err());
+    }
+}
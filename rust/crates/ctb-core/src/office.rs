@@ -0,0 +1,602 @@
+//! Plain-text extraction from Office Open XML documents (.docx, .xlsx).
+//!
+//! Both formats are zip archives of XML parts. The handful of elements we care about is read with
+//! a minimal tag scanner instead of a full XML parser; anything unrecognised is skipped.
+
+use std::{fs::File, io::Read, path::Path};
+
+use zip::ZipArchive;
+
+use crate::{errors::Error, Result};
+
+/// Largest XML part we decompress (guards against zip bombs).
+const MAX_PART_BYTES: u64 = 20 * 1024 * 1024;
+/// Rows / columns rendered per worksheet.
+pub const XLSX_MAX_ROWS: usize = 500;
+pub const XLSX_MAX_COLS: usize = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OfficeKind {
+    Docx,
+    Xlsx,
+}
+
+impl OfficeKind {
+    /// Detect by extension, falling back to the OOXML MIME types.
+    pub fn detect(file_name: &str, mime: Option<&str>) -> Option<Self> {
+        let lower = file_name.to_lowercase();
+        if lower.ends_with(".docx")
+            || mime
+                == Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document")
+        {
+            return Some(Self::Docx);
+        }
+        if lower.ends_with(".xlsx")
+            || mime == Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+        {
+            return Some(Self::Xlsx);
+        }
+        None
+    }
+
+    pub fn extract(self, path: &Path) -> Result<String> {
+        match self {
+            Self::Docx => docx_to_text(path),
+            Self::Xlsx => xlsx_to_text(path),
+        }
+    }
+}
+
+type Archive = ZipArchive<File>;
+
+fn open(path: &Path) -> Result<Archive> {
+    ZipArchive::new(File::open(path)?).map_err(|e| Error::External(format!("zip error: {e}")))
+}
+
+fn read_part(zip: &mut Archive, name: &str) -> Result<Option<String>> {
+    let part = match zip.by_name(name) {
+        Ok(part) => part,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(Error::External(format!("zip error: {e}"))),
+    };
+    let mut xml = String::new();
+    part.take(MAX_PART_BYTES).read_to_string(&mut xml)?;
+    Ok(Some(xml))
+}
+
+/// Paragraph text of `word/document.xml`, one paragraph per line.
+pub fn docx_to_text(path: &Path) -> Result<String> {
+    let mut zip = open(path)?;
+    let xml = read_part(&mut zip, "word/document.xml")?
+        .ok_or_else(|| Error::External("not a .docx file (word/document.xml missing)".into()))?;
+
+    let mut out = String::new();
+    let mut in_text = false;
+    for token in XmlTokens::new(&xml) {
+        match token {
+            Token::Open { name: "w:t", .. } => in_text = true,
+            Token::Close("w:t") => in_text = false,
+            Token::Text(t) if in_text => out.push_str(&decode_entities(t)),
+            Token::Open { name: "w:tab", .. } => out.push('\t'),
+            Token::Open {
+                name: "w:br" | "w:cr",
+                ..
+            } => out.push('\n'),
+            Token::Close("w:p") => out.push('\n'),
+            _ => {}
+        }
+    }
+    Ok(out.trim_end().to_string())
+}
+
+/// Every worksheet as comma-separated rows, capped at [`XLSX_MAX_ROWS`] x [`XLSX_MAX_COLS`].
+///
+/// Sheets come in workbook (tab) order, resolved through `xl/_rels/workbook.xml.rels`; without
+/// that part the numbered `sheetN.xml` parts are used in numeric order.
+pub fn xlsx_to_text(path: &Path) -> Result<String> {
+    let mut zip = open(path)?;
+    let shared = read_part(&mut zip, "xl/sharedStrings.xml")?
+        .map(|xml| shared_strings(&xml))
+        .unwrap_or_default();
+    let workbook = read_part(&mut zip, "xl/workbook.xml")?
+        .map(|xml| workbook_sheets(&xml))
+        .unwrap_or_default();
+    let targets = read_part(&mut zip, "xl/_rels/workbook.xml.rels")?
+        .map(|xml| relationship_targets(&xml))
+        .unwrap_or_default();
+
+    let mut numbered: Vec<(u32, String)> = zip
+        .file_names()
+        .filter_map(|n| {
+            let num = n
+                .strip_prefix("xl/worksheets/sheet")?
+                .strip_suffix(".xml")?
+                .parse()
+                .ok()?;
+            Some((num, n.to_string()))
+        })
+        .collect();
+    numbered.sort();
+
+    let in_order: Vec<(String, String)> = workbook
+        .iter()
+        .filter_map(|(title, rid)| {
+            let part = targets.iter().find(|(id, _)| id == rid)?.1.clone();
+            Some((title.clone(), part))
+        })
+        .collect();
+    let sheets = if in_order.is_empty() {
+        numbered
+            .into_iter()
+            .enumerate()
+            .map(|(i, (num, part))| {
+                let title = workbook
+                    .get(i)
+                    .map(|(title, _)| title.clone())
+                    .unwrap_or_else(|| format!("Sheet{num}"));
+                (title, part)
+            })
+            .collect()
+    } else {
+        in_order
+    };
+    if sheets.is_empty() {
+        return Err(Error::External(
+            "not a .xlsx file (no worksheets found)".into(),
+        ));
+    }
+
+    let mut out = Vec::new();
+    for (title, part) in &sheets {
+        let Some(xml) = read_part(&mut zip, part)? else {
+            continue;
+        };
+        out.push(format!(
+            "## Sheet: {title}\n{}",
+            sheet_to_csv(&xml, &shared)
+        ));
+    }
+    Ok(out.join("\n\n"))
+}
+
+/// `(name, r:id)` of every `<sheet>` in `xl/workbook.xml`, in tab order.
+fn workbook_sheets(xml: &str) -> Vec<(String, String)> {
+    XmlTokens::new(xml)
+        .filter_map(|t| match t {
+            Token::Open {
+                name: "sheet",
+                attrs,
+                ..
+            } => Some((
+                decode_entities(attr(attrs, "name")?),
+                attr(attrs, "r:id").unwrap_or_default().to_string(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `(Id, part path)` of every relationship in `xl/_rels/workbook.xml.rels`.
+fn relationship_targets(xml: &str) -> Vec<(String, String)> {
+    XmlTokens::new(xml)
+        .filter_map(|t| match t {
+            Token::Open {
+                name: "Relationship",
+                attrs,
+                ..
+            } => {
+                let target = decode_entities(attr(attrs, "Target")?);
+                let part = match target.strip_prefix('/') {
+                    Some(absolute) => absolute.to_string(),
+                    None => format!("xl/{target}"),
+                };
+                Some((attr(attrs, "Id")?.to_string(), part))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// `<si>` entries in order; rich-text runs are concatenated, phonetic hints (`<rPh>`) dropped.
+fn shared_strings(xml: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current: Option<String> = None;
+    let (mut in_text, mut in_phonetic) = (false, false);
+    for token in XmlTokens::new(xml) {
+        match token {
+            Token::Open { name: "si", .. } => current = Some(String::new()),
+            Token::Close("si") => out.extend(current.take()),
+            Token::Open { name: "rPh", .. } => in_phonetic = true,
+            Token::Close("rPh") => in_phonetic = false,
+            Token::Open {
+                name: "t",
+                self_closing: false,
+                ..
+            } => in_text = true,
+            Token::Close("t") => in_text = false,
+            Token::Text(t) if in_text && !in_phonetic => {
+                if let Some(s) = current.as_mut() {
+                    s.push_str(&decode_entities(t));
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+fn sheet_to_csv(xml: &str, shared: &[String]) -> String {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut total_rows = 0usize;
+    let mut wide = false;
+
+    let mut row: Option<Vec<String>> = None;
+    // (column, type, value) of the cell being read.
+    let mut cell: Option<(usize, String, String)> = None;
+    let mut in_value = false;
+    for token in XmlTokens::new(xml) {
+        match token {
+            Token::Open {
+                name: "row",
+                self_closing,
+                ..
+            } => {
+                total_rows += 1;
+                if !self_closing {
+                    row = Some(Vec::new());
+                }
+            }
+            Token::Close("row") => {
+                if let Some(r) = row.take() {
+                    if rows.len() < XLSX_MAX_ROWS {
+                        rows.push(r);
+                    }
+                }
+            }
+            Token::Open {
+                name: "c",
+                attrs,
+                self_closing,
+            } => {
+                let col = row.as_ref().map_or(0, Vec::len);
+                let col = attr(attrs, "r").and_then(column_index).unwrap_or(col);
+                let kind = attr(attrs, "t").unwrap_or("n").to_string();
+                cell = (!self_closing).then_some((col, kind, String::new()));
+            }
+            Token::Close("c") => {
+                if let (Some((col, kind, raw)), Some(r)) = (cell.take(), row.as_mut()) {
+                    if col >= XLSX_MAX_COLS {
+                        wide = true;
+                        continue;
+                    }
+                    let value = match kind.as_str() {
+                        "s" => raw
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|i| shared.get(i).cloned())
+                            .unwrap_or_default(),
+                        "b" => (if raw.trim() == "1" { "TRUE" } else { "FALSE" }).to_string(),
+                        _ => raw,
+                    };
+                    if r.len() <= col {
+                        r.resize(col + 1, String::new());
+                    }
+                    r[col] = value;
+                }
+            }
+            // `<v>` for stored values, `<t>` inside `<is>` for inline strings.
+            Token::Open {
+                name: "v" | "t",
+                self_closing: false,
+                ..
+            } => in_value = true,
+            Token::Close("v" | "t") => in_value = false,
+            Token::Text(t) if in_value => {
+                if let Some((_, _, raw)) = cell.as_mut() {
+                    raw.push_str(&decode_entities(t));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut lines: Vec<String> = rows
+        .iter()
+        .map(|r| r.iter().map(|v| csv_field(v)).collect::<Vec<_>>().join(","))
+        .collect();
+    if total_rows > rows.len() {
+        lines.push(format!(
+            "[... {} more rows not shown]",
+            total_rows - rows.len()
+        ));
+    }
+    if wide {
+        lines.push(format!("[columns beyond {XLSX_MAX_COLS} not shown]"));
+    }
+    lines.join("\n")
+}
+
+fn csv_field(v: &str) -> String {
+    if v.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", v.replace('"', "\"\""))
+    } else {
+        v.to_string()
+    }
+}
+
+/// Zero-based column of a cell reference like `AB12`.
+fn column_index(cell_ref: &str) -> Option<usize> {
+    let letters: Vec<u8> = cell_ref
+        .bytes()
+        .take_while(u8::is_ascii_alphabetic)
+        .collect();
+    if letters.is_empty() {
+        return None;
+    }
+    letters
+        .iter()
+        .try_fold(0usize, |acc, b| {
+            acc.checked_mul(26)?
+                .checked_add(usize::from(b.to_ascii_uppercase() - b'A') + 1)
+        })
+        .map(|n| n - 1)
+}
+
+fn attr<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attrs;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        let after = rest[eq + 1..].trim_start();
+        let quote = after.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let value_end = after[1..].find(quote)? + 1;
+        if key == name {
+            return Some(&after[1..value_end]);
+        }
+        rest = &after[value_end + 1..];
+    }
+    None
+}
+
+fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse::<u32>))
+                .and_then(|n| n.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Open {
+        name: &'a str,
+        attrs: &'a str,
+        self_closing: bool,
+    },
+    Close(&'a str),
+    Text(&'a str),
+}
+
+/// Tags and text runs of an XML document; declarations, comments and CDATA markers are skipped.
+struct XmlTokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> XmlTokens<'a> {
+    fn new(xml: &'a str) -> Self {
+        Self { rest: xml }
+    }
+}
+
+impl<'a> Iterator for XmlTokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            if !self.rest.starts_with('<') {
+                let end = self.rest.find('<').unwrap_or(self.rest.len());
+                let text = &self.rest[..end];
+                self.rest = &self.rest[end..];
+                return Some(Token::Text(text));
+            }
+            let end = self.rest.find('>')?;
+            let tag = &self.rest[1..end];
+            self.rest = &self.rest[end + 1..];
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                return Some(Token::Close(name.trim()));
+            }
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(t) => (t, true),
+                None => (tag, false),
+            };
+            let (name, attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            return Some(Token::Open {
+                name,
+                attrs,
+                self_closing,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use zip::write::{FileOptions, ZipWriter};
+
+    fn write_zip(name: &str, parts: &[(&str, &str)]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ctb-office-{}-{name}", std::process::id()));
+        let mut zw = ZipWriter::new(File::create(&path).unwrap());
+        for (part, body) in parts {
+            zw.start_file(*part, FileOptions::default()).unwrap();
+            zw.write_all(body.as_bytes()).unwrap();
+        }
+        zw.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn docx_paragraphs_tabs_and_entities() {
+        let path = write_zip(
+            "a.docx",
+            &[(
+                "word/document.xml",
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+<w:p><w:r><w:t>Quarterly </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t xml:space="preserve">report &amp; plan</w:t></w:r></w:p>
+<w:p><w:r><w:t>Q1</w:t><w:tab/><w:t>&lt;draft&gt;</w:t><w:br/><w:t>caf&#233;</w:t></w:r></w:p>
+</w:body></w:document>"#,
+            )],
+        );
+        let text = docx_to_text(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(text, "Quarterly report & plan\nQ1\t<draft>\ncafé");
+    }
+
+    #[test]
+    fn xlsx_sheets_to_csv_rows() {
+        let path = write_zip(
+            "b.xlsx",
+            &[
+                (
+                    "xl/workbook.xml",
+                    r#"<workbook><sheets><sheet name="Budget" sheetId="1" r:id="rId1"/><sheet name="Notes" sheetId="2" r:id="rId2"/></sheets></workbook>"#,
+                ),
+                (
+                    "xl/sharedStrings.xml",
+                    r#"<sst count="3"><si><t>Item</t></si><si><t>Cost</t></si><si><r><t>Rent, </t></r><r><t>office</t></r><rPh><t>x</t></rPh></si></sst>"#,
+                ),
+                (
+                    "xl/worksheets/sheet1.xml",
+                    r#"<worksheet><sheetData>
+<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>
+<row r="2"><c r="A2" t="s"><v>2</v></c><c r="C2"><v>1200.5</v></c></row>
+<row r="3"><c r="A3" t="inlineStr"><is><t>Paid</t></is></c><c r="B3" t="b"><v>1</v></c></row>
+</sheetData></worksheet>"#,
+                ),
+                (
+                    "xl/worksheets/sheet2.xml",
+                    r#"<worksheet><sheetData><row r="1"><c r="A1" t="str"><v>ok</v></c></row></sheetData></worksheet>"#,
+                ),
+            ],
+        );
+        let text = xlsx_to_text(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            text,
+            "## Sheet: Budget\nItem,Cost\n\"Rent, office\",,1200.5\nPaid,TRUE\n\n## Sheet: Notes\nok"
+        );
+    }
+
+    #[test]
+    fn xlsx_sheets_follow_workbook_order() {
+        let path = write_zip(
+            "order.xlsx",
+            &[
+                (
+                    "xl/workbook.xml",
+                    r#"<workbook><sheets><sheet name="Summary" sheetId="3" r:id="rId3"/><sheet name="Data" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+                ),
+                (
+                    "xl/_rels/workbook.xml.rels",
+                    r#"<Relationships><Relationship Id="rId1" Type="worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId3" Type="worksheet" Target="/xl/worksheets/sheet10.xml"/></Relationships>"#,
+                ),
+                (
+                    "xl/worksheets/sheet1.xml",
+                    r#"<worksheet><sheetData><row r="1"><c r="A1" t="str"><v>raw</v></c></row></sheetData></worksheet>"#,
+                ),
+                (
+                    "xl/worksheets/sheet10.xml",
+                    r#"<worksheet><sheetData><row r="1"><c r="A1" t="str"><v>total</v></c></row></sheetData></worksheet>"#,
+                ),
+            ],
+        );
+        let text = xlsx_to_text(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(text, "## Sheet: Summary\ntotal\n\n## Sheet: Data\nraw");
+    }
+
+    #[test]
+    fn xlsx_caps_rows_and_columns() {
+        let mut sheet = String::from("<worksheet><sheetData>");
+        for r in 1..=(XLSX_MAX_ROWS + 3) {
+            sheet.push_str(&format!(
+                r#"<row r="{r}"><c r="A{r}"><v>{r}</v></c><c r="ZZ{r}"><v>9</v></c></row>"#
+            ));
+        }
+        sheet.push_str("</sheetData></worksheet>");
+        let path = write_zip("c.xlsx", &[("xl/worksheets/sheet1.xml", &sheet)]);
+        let text = xlsx_to_text(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(
+            text.starts_with("## Sheet: Sheet1\n1\n2\n"),
+            "{}",
+            &text[..40]
+        );
+        assert!(text.contains("[... 3 more rows not shown]"));
+        assert!(text.contains(&format!("[columns beyond {XLSX_MAX_COLS} not shown]")));
+    }
+
+    #[test]
+    fn detects_office_kinds_and_rejects_other_zips() {
+        assert_eq!(
+            OfficeKind::detect("Plan.DOCX", None),
+            Some(OfficeKind::Docx)
+        );
+        assert_eq!(
+            OfficeKind::detect("data.xlsx", None),
+            Some(OfficeKind::Xlsx)
+        );
+        assert_eq!(OfficeKind::detect("notes.txt", None), None);
+
+        let path = write_zip("d.docx", &[("readme.txt", "hi")]);
+        assert!(docx_to_text(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(column_index("AB12"), Some(27));
+    }
+}
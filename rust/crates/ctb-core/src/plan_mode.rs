@@ -0,0 +1,62 @@
+//! Peer-review ("plan") mode.
+//!
+//! A prompt starting with `PLAN_MODE_PREFIX` (default `?`) runs with the CLI's `plan` permission
+//! mode and an instruction to only describe what it would do. The turn ends with
+//! "▶️ Execute plan" / "✖️ Discard" buttons (`plan:execute`, `plan:discard`); executing resumes
+//! the same session with [`EXECUTE_PLAN_PROMPT`] under the normal permissions.
+
+use crate::messaging::types::{InlineButton, InlineKeyboard};
+use crate::theme::StatusTheme;
+
+pub const PLAN_CALLBACK_PREFIX: &str = "plan:";
+
+/// Appended to the system prompt of a plan-only turn.
+pub const PLAN_ONLY_INSTRUCTION: &str = "You are in plan-only mode. Investigate as needed with \
+read-only tools, then reply with a concise, numbered plan of the changes and commands you would \
+run. Do not modify files or run commands with side effects; the user reviews the plan first.";
+
+/// The prompt sent when the user approves a plan.
+pub const EXECUTE_PLAN_PROMPT: &str = "The plan above is approved. Execute it now.";
+
+/// The prompt without its plan-mode prefix, or `None` when it does not start with `prefix`
+/// (an empty prefix turns plan mode off).
+pub fn strip_plan_prefix(text: &str, prefix: &str) -> Option<String> {
+    if prefix.is_empty() {
+        return None;
+    }
+    text.strip_prefix(prefix)
+        .map(|rest| rest.trim_start().to_string())
+}
+
+/// "▶️ Execute plan" / "✖️ Discard" buttons for the end of a plan-only turn.
+pub fn review_keyboard(theme: &StatusTheme) -> InlineKeyboard {
+    InlineKeyboard::new(vec![
+        InlineButton {
+            label: theme.render("▶️ Execute plan").into_owned(),
+            callback_data: format!("{PLAN_CALLBACK_PREFIX}execute"),
+        },
+        InlineButton {
+            label: theme.render("✖️ Discard").into_owned(),
+            callback_data: format!("{PLAN_CALLBACK_PREFIX}discard"),
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_is_stripped_and_empty_prefix_disables_plan_mode() {
+        assert_eq!(
+            strip_plan_prefix("? refactor the parser", "?").as_deref(),
+            Some("refactor the parser")
+        );
+        assert_eq!(
+            strip_plan_prefix("/plan migrate db", "/plan").as_deref(),
+            Some("migrate db")
+        );
+        assert_eq!(strip_plan_prefix("what is this?", "?"), None);
+        assert_eq!(strip_plan_prefix("? anything", ""), None);
+    }
+}
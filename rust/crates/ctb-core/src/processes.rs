@@ -0,0 +1,441 @@
+//! Bookkeeping for spawned `claude` CLI processes.
+//!
+//! The CLI adapter records every child it spawns in `TEMP_DIR/claude-processes.json` and removes
+//! the entry once the child is reaped. Entries left behind mean the bot died (crash, OOM,
+//! SIGKILL) with the CLI and its MCP servers still running; [`recover_orphans`] stops those on
+//! the next start. Pids get reused, so an entry only counts when the live process still looks
+//! like the one recorded (same start time, same program).
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config, formatting::escape_html, storage::write_atomic, utils::iso_timestamp_utc,
+    Result,
+};
+
+pub const PROCESS_FILE_NAME: &str = "claude-processes.json";
+
+/// One spawned CLI child. The CLI runs in its own process group, so `pgid == pid`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackedProcess {
+    pub pid: u32,
+    pub pgid: u32,
+    /// Program the child was spawned as, checked against its command line on recovery.
+    pub program: String,
+    pub run_id: u64,
+    /// Session the run resumed, if any.
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub cwd: PathBuf,
+    pub started_at: String,
+    /// Kernel start time (`/proc/<pid>/stat`, clock ticks since boot), where available.
+    #[serde(default)]
+    pub start_ticks: Option<u64>,
+}
+
+impl TrackedProcess {
+    /// Entry for a child that was just spawned as the leader of its own process group.
+    pub fn spawned(
+        pid: u32,
+        program: &Path,
+        run_id: u64,
+        session_id: Option<String>,
+        cwd: &Path,
+    ) -> Self {
+        Self {
+            pid,
+            pgid: pid,
+            program: program.display().to_string(),
+            run_id,
+            session_id,
+            cwd: cwd.to_path_buf(),
+            started_at: iso_timestamp_utc(),
+            start_ticks: start_ticks(pid),
+        }
+    }
+}
+
+/// What a leftover entry's pid refers to now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessCheck {
+    /// No such process (or only a zombie).
+    Gone,
+    /// Alive, but a different program took over the pid.
+    Reused,
+    /// Still the CLI we spawned.
+    Orphan,
+}
+
+/// The tracking file. Writers share one lock, so concurrent runs never drop each other's entries.
+#[derive(Debug)]
+pub struct ProcessTracker {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl ProcessTracker {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn from_config(cfg: &Config) -> Self {
+        Self::new(cfg.temp_dir.join(PROCESS_FILE_NAME))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn track(&self, entry: TrackedProcess) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = self.read();
+        entries.retain(|e| e.pid != entry.pid);
+        entries.push(entry);
+        self.write(&entries)
+    }
+
+    /// Forget `pid` once its child has been reaped.
+    pub fn untrack(&self, pid: u32) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = self.read();
+        let before = entries.len();
+        entries.retain(|e| e.pid != pid);
+        if entries.len() == before {
+            return Ok(());
+        }
+        self.write(&entries)
+    }
+
+    pub fn entries(&self) -> Vec<TrackedProcess> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.read()
+    }
+
+    /// Forget `pids`, keeping entries recorded since they were read.
+    pub fn forget(&self, pids: &[u32]) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = self.read();
+        entries.retain(|e| !pids.contains(&e.pid));
+        self.write(&entries)
+    }
+
+    fn read(&self) -> Vec<TrackedProcess> {
+        let Ok(raw) = std::fs::read_to_string(&self.path) else {
+            return Vec::new();
+        };
+        if raw.trim().is_empty() {
+            return Vec::new();
+        }
+        serde_json::from_str(&raw).unwrap_or_else(|e| {
+            log_line!(
+                "[PROCESS] ⚠️ Ignoring unreadable {}: {e}",
+                self.path.display()
+            );
+            Vec::new()
+        })
+    }
+
+    fn write(&self, entries: &[TrackedProcess]) -> Result<()> {
+        if entries.is_empty() {
+            return match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        write_atomic(&self.path, &serde_json::to_vec_pretty(entries)?)
+    }
+}
+
+/// Whether `entry.pid` is still the process that was recorded.
+pub fn check_process(entry: &TrackedProcess) -> ProcessCheck {
+    if entry.pid == std::process::id() || !is_alive(entry.pid) {
+        return ProcessCheck::Gone;
+    }
+    if let (Some(recorded), Some(now)) = (entry.start_ticks, start_ticks(entry.pid)) {
+        if recorded != now {
+            return ProcessCheck::Reused;
+        }
+    }
+    let program = Path::new(&entry.program)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| entry.program.clone());
+    match command_line(entry.pid) {
+        Some(cmdline) if cmdline.contains(&program) => ProcessCheck::Orphan,
+        Some(_) => ProcessCheck::Reused,
+        None => ProcessCheck::Gone,
+    }
+}
+
+/// Stop every orphan left in `tracker` (SIGINT to its group, SIGKILL after `grace`), then drop
+/// the entries. A group whose leader already exited is stopped too: its pgid cannot be handed
+/// out again while any member is alive. Returns the processes that were stopped.
+pub async fn recover_orphans(tracker: Arc<ProcessTracker>, grace: Duration) -> Vec<TrackedProcess> {
+    let checked = {
+        let tracker = tracker.clone();
+        tokio::task::spawn_blocking(move || {
+            tracker
+                .entries()
+                .into_iter()
+                .map(|entry| (check_process(&entry), entry))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default()
+    };
+    let pids: Vec<u32> = checked.iter().map(|(_, entry)| entry.pid).collect();
+
+    let mut stopped = Vec::new();
+    for (check, entry) in checked {
+        match check {
+            ProcessCheck::Gone if group_alive(entry.pgid) => {
+                log_line!(
+                    "[PROCESS] Stopping processes left in the group of claude process {} (run {})",
+                    entry.pid,
+                    entry.run_id
+                );
+                terminate_group(entry.pgid, grace).await;
+                stopped.push(entry);
+            }
+            ProcessCheck::Gone => {}
+            ProcessCheck::Reused => log_info!(
+                "[PROCESS] pid {} now belongs to another program, leaving it alone",
+                entry.pid
+            ),
+            ProcessCheck::Orphan => {
+                log_line!(
+                    "[PROCESS] Stopping orphaned claude process {} (run {}, {})",
+                    entry.pid,
+                    entry.run_id,
+                    entry.cwd.display()
+                );
+                terminate_group(entry.pgid, grace).await;
+                stopped.push(entry);
+            }
+        }
+    }
+
+    let cleared = tokio::task::spawn_blocking(move || tracker.forget(&pids)).await;
+    match cleared {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log_line!("[PROCESS] Failed to clear the process file: {e}"),
+        Err(e) => log_line!("[PROCESS] Failed to clear the process file: {e}"),
+    }
+    stopped
+}
+
+/// Owner notice for [`recover_orphans`]' result.
+pub fn format_orphan_notice(stopped: &[TrackedProcess]) -> String {
+    let mut out = format!(
+        "🧹 Stopped {} orphaned Claude process(es) left by the previous run:",
+        stopped.len()
+    );
+    for p in stopped {
+        out.push_str(&format!(
+            "\n• pid {} in <code>{}</code>, started {}",
+            p.pid,
+            escape_html(&p.cwd.display().to_string()),
+            escape_html(&p.started_at)
+        ));
+        if let Some(id) = &p.session_id {
+            out.push_str(&format!(" (session <code>{}</code>)", escape_html(id)));
+        }
+    }
+    out
+}
+
+/// SIGINT the group, give it `grace` to exit, then SIGKILL whatever is left.
+async fn terminate_group(pgid: u32, grace: Duration) {
+    #[cfg(unix)]
+    {
+        signal_group(pgid, libc::SIGINT);
+        let deadline = tokio::time::Instant::now() + grace;
+        while group_alive(pgid) && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        signal_group(pgid, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = (pgid, grace);
+}
+
+/// Whether any process is left in the group `pgid` (never our own group).
+fn group_alive(pgid: u32) -> bool {
+    #[cfg(unix)]
+    if let Ok(pgid) = libc::pid_t::try_from(pgid) {
+        // SAFETY: `getpgrp` cannot fail; signal 0 only checks that the group has members.
+        return pgid != unsafe { libc::getpgrp() } && unsafe { libc::kill(-pgid, 0) } == 0;
+    }
+    let _ = pgid;
+    false
+}
+
+/// Send `signal` to the process group led by `pgid`.
+#[cfg(unix)]
+pub fn signal_group(pgid: u32, signal: libc::c_int) {
+    let Ok(pgid) = libc::pid_t::try_from(pgid) else {
+        return;
+    };
+    // SAFETY: plain syscall; a negative pid addresses the process group. ESRCH (group already
+    // gone) is the expected outcome after a clean exit and is ignored.
+    unsafe {
+        libc::kill(-pgid, signal);
+    }
+}
+
+/// Whether `pid` is running. An exited but unreaped child (zombie) counts as gone where
+/// `/proc` says so.
+pub fn is_alive(pid: u32) -> bool {
+    if let Ok(stat) = std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+        return stat
+            .rsplit_once(") ")
+            .is_some_and(|(_, rest)| !rest.starts_with('Z'));
+    }
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(pid) {
+        // SAFETY: signal 0 only checks that the pid exists.
+        return unsafe { libc::kill(pid, 0) } == 0;
+    }
+    false
+}
+
+/// Field 22 of `/proc/<pid>/stat`; `None` off Linux.
+fn start_ticks(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // Fields after the parenthesised command name start at field 3 (state).
+    let (_, rest) = stat.rsplit_once(") ")?;
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// `/proc/<pid>/cmdline`, falling back to `ps` where there is no procfs.
+fn command_line(pid: u32) -> Option<String> {
+    if let Ok(raw) = std::fs::read(format!("/proc/{pid}/cmdline")) {
+        return Some(String::from_utf8_lossy(&raw).replace('\0', " "));
+    }
+    let out = std::process::Command::new("ps")
+        .args(["-o", "command=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let cmdline = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (out.status.success() && !cmdline.is_empty()).then_some(cmdline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pid: u32, program: &str) -> TrackedProcess {
+        TrackedProcess::spawned(pid, Path::new(program), 7, None, Path::new("/tmp"))
+    }
+
+    fn tracker(name: &str) -> ProcessTracker {
+        let dir = std::env::temp_dir().join(format!("ctb-processes-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        ProcessTracker::new(dir.join(PROCESS_FILE_NAME))
+    }
+
+    #[test]
+    fn tracker_records_and_clears_spawned_processes() {
+        let tracker = tracker("bookkeeping");
+        tracker.track(entry(101, "/usr/bin/claude")).unwrap();
+        tracker.track(entry(102, "/usr/bin/claude")).unwrap();
+        tracker.untrack(101).unwrap();
+        tracker.untrack(999).unwrap();
+
+        let left: Vec<u32> = tracker.entries().iter().map(|e| e.pid).collect();
+        assert_eq!(left, vec![102]);
+
+        tracker.untrack(102).unwrap();
+        assert!(!tracker.path().exists(), "an empty tracker leaves no file");
+
+        tracker.track(entry(103, "claude")).unwrap();
+        tracker.track(entry(104, "claude")).unwrap();
+        tracker.forget(&[103]).unwrap();
+        let left: Vec<u32> = tracker.entries().iter().map(|e| e.pid).collect();
+        assert_eq!(left, vec![104], "entries recorded after the read are kept");
+        tracker.forget(&[104]).unwrap();
+        assert!(!tracker.path().exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn only_the_recorded_program_is_stopped() {
+        use std::os::unix::process::CommandExt;
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+
+        // The pid now belongs to `sleep`, not to a claude CLI.
+        assert_eq!(
+            check_process(&entry(pid, "/usr/local/bin/claude")),
+            ProcessCheck::Reused
+        );
+        let mut restarted = entry(pid, "sleep");
+        restarted.start_ticks = restarted.start_ticks.map(|t| t + 1);
+        assert_eq!(check_process(&restarted), ProcessCheck::Reused);
+
+        let tracker = Arc::new(tracker("recovery"));
+        tracker.track(entry(pid, "/usr/local/bin/claude")).unwrap();
+        assert!(recover_orphans(tracker.clone(), Duration::from_millis(100))
+            .await
+            .is_empty());
+        assert!(is_alive(pid), "a reused pid must survive recovery");
+
+        tracker.track(entry(pid, "sleep")).unwrap();
+        let stopped = recover_orphans(tracker.clone(), Duration::from_millis(100)).await;
+        assert_eq!(stopped.len(), 1);
+        assert!(child.wait().is_ok());
+        assert_eq!(check_process(&stopped[0]), ProcessCheck::Gone);
+        assert!(tracker.entries().is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn groups_outliving_their_leader_are_stopped() {
+        use std::{io::BufRead, os::unix::process::CommandExt};
+
+        let mut leader = std::process::Command::new("sh")
+            .args(["-c", "sleep 30 & echo $!"])
+            .process_group(0)
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pid = leader.id();
+        let mut line = String::new();
+        std::io::BufReader::new(leader.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let member: u32 = line.trim().parse().unwrap();
+        leader.wait().unwrap();
+
+        let tracker = Arc::new(tracker("leaderless"));
+        tracker.track(entry(pid, "sh")).unwrap();
+        assert_eq!(check_process(&tracker.entries()[0]), ProcessCheck::Gone);
+        assert!(is_alive(member));
+
+        let stopped = recover_orphans(tracker.clone(), Duration::from_millis(100)).await;
+        assert_eq!(stopped.len(), 1);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while is_alive(member) && std::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(
+            !is_alive(member),
+            "the rest of the group is stopped by pgid"
+        );
+        assert!(tracker.entries().is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+//! Named system-prompt profiles (`/prompt`).
+//!
+//! A profile is a Markdown file in `<working dir>/.ctb/prompts/<name>.md`. The selected profile is
+//! appended after the safety prompt on every run, so it can shape tone and focus but never
+//! replaces the safety rules. Files are re-read for each run, so edits apply to the next message.
+
+use std::{fs, path::PathBuf};
+
+use crate::{config::Config, security::PathPolicy, Error, Result};
+
+/// Largest profile file accepted.
+pub const PROMPT_PROFILE_MAX_BYTES: u64 = 16 * 1024;
+
+/// `<working dir>/.ctb/prompts`.
+pub fn profiles_dir(cfg: &Config) -> PathBuf {
+    cfg.claude_working_dir.join(".ctb").join("prompts")
+}
+
+/// Letters, digits, `-` and `_` (1-64 chars), so a name can't address other files.
+pub fn is_valid_profile_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Read profile `name`; the file must lie inside `paths` and stay under the size cap.
+pub fn load_profile(cfg: &Config, paths: &PathPolicy, name: &str) -> Result<String> {
+    let path = profiles_dir(cfg).join(format!("{name}.md"));
+    let invalid = |reason: String| Error::InvalidPath {
+        path: path.clone(),
+        reason,
+    };
+    if !is_valid_profile_name(name) {
+        return Err(invalid(
+            "profile names may only use letters, digits, '-' and '_'".to_string(),
+        ));
+    }
+    if !paths.is_path_allowed(&path.to_string_lossy()) {
+        return Err(Error::Security(format!(
+            "Prompt profile outside allowed paths: {}",
+            path.display()
+        )));
+    }
+    let meta = fs::metadata(&path).map_err(|e| invalid(e.to_string()))?;
+    if !meta.is_file() {
+        return Err(invalid("not a file".to_string()));
+    }
+    if meta.len() > PROMPT_PROFILE_MAX_BYTES {
+        return Err(invalid(format!(
+            "larger than {} KB",
+            PROMPT_PROFILE_MAX_BYTES / 1024
+        )));
+    }
+    let text = fs::read_to_string(&path).map_err(|e| invalid(e.to_string()))?;
+    if text.trim().is_empty() {
+        return Err(invalid("profile is empty".to_string()));
+    }
+    Ok(text.trim().to_string())
+}
+
+/// Names of the available profiles, sorted.
+pub fn list_profiles(cfg: &Config) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(profiles_dir(cfg)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            name.strip_suffix(".md").map(str::to_string)
+        })
+        .filter(|n| is_valid_profile_name(n))
+        .collect();
+    names.sort();
+    names
+}
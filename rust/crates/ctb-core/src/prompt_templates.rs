@@ -0,0 +1,507 @@
+//! User-defined prompt templates (`/t <name> [args]`).
+//!
+//! `<working dir>/templates.yaml` maps a template name to a prompt, in the same YAML subset as
+//! `cron.yaml` (plain or quoted scalars, `|` / `>` block scalars, `# comments`):
+//!
+//! ```yaml
+//! review: Review the last commit and point out anything risky.
+//! standup: |
+//!   Summarize today's standup notes in ~/notes, focusing on {arg}.
+//! ```
+//!
+//! Placeholders are `{arg}` (all text after the name) and `{1}`..`{9}` (single words); `{{` and
+//! `}}` are literal braces. A template without placeholders gets the arguments appended. The
+//! file is validated as a whole on load and re-read whenever it changes.
+
+use std::{fs, path::PathBuf, sync::Mutex, time::SystemTime};
+
+use crate::{
+    config::Config,
+    prompt_profiles::is_valid_profile_name,
+    scheduler::{
+        count_indent, expand_indent, fold_lines, is_blank_or_comment, is_block_indicator,
+        parse_scalar, strip_comment, MAX_PROMPT_LENGTH,
+    },
+    Error, Result,
+};
+
+/// `<working dir>/templates.yaml`.
+pub fn templates_path(cfg: &Config) -> PathBuf {
+    cfg.claude_working_dir.join("templates.yaml")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub body: String,
+}
+
+/// One `{...}` in a template body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    /// `{arg}`: everything after the template name.
+    Arg,
+    /// `{1}`..`{9}`: one whitespace-separated word.
+    Word(usize),
+}
+
+/// A template body split into literal text and placeholders.
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(Placeholder),
+    /// `{name}` that is not a known placeholder.
+    Unknown(&'a str),
+}
+
+/// Split `body` into pieces; `{{`/`}}` become single braces and a `{` that does not open a
+/// `{word}` stays literal.
+fn pieces(body: &str) -> Vec<Piece<'_>> {
+    let mut out = Vec::new();
+    let mut rest = body;
+    while let Some(idx) = rest.find(['{', '}']) {
+        if idx > 0 {
+            out.push(Piece::Text(&rest[..idx]));
+        }
+        let tail = &rest[idx..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push(Piece::Text(&tail[..1]));
+            rest = &tail[2..];
+            continue;
+        }
+        let name_len = tail[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(tail.len() - 1);
+        if tail.starts_with('{') && name_len > 0 && tail[1 + name_len..].starts_with('}') {
+            let name = &tail[1..1 + name_len];
+            out.push(match name {
+                "arg" => Piece::Placeholder(Placeholder::Arg),
+                n if n.len() == 1 && ('1'..='9').contains(&n.chars().next().unwrap_or('0')) => {
+                    Piece::Placeholder(Placeholder::Word(n.parse().unwrap_or(1)))
+                }
+                n => Piece::Unknown(n),
+            });
+            rest = &tail[name_len + 2..];
+        } else {
+            out.push(Piece::Text(&tail[..1]));
+            rest = &tail[1..];
+        }
+    }
+    if !rest.is_empty() {
+        out.push(Piece::Text(rest));
+    }
+    out
+}
+
+impl PromptTemplate {
+    /// The prompt for `/t <name> <args>`. Fails when a `{n}` has no matching word or the
+    /// result exceeds [`MAX_PROMPT_LENGTH`].
+    pub fn render(&self, args: &str) -> Result<String> {
+        let args = args.trim();
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let mut out = String::new();
+        let mut used = false;
+        for piece in pieces(&self.body) {
+            match piece {
+                Piece::Text(t) => out.push_str(t),
+                Piece::Placeholder(Placeholder::Arg) => {
+                    used = true;
+                    out.push_str(args);
+                }
+                Piece::Placeholder(Placeholder::Word(n)) => {
+                    used = true;
+                    let word = words.get(n - 1).ok_or_else(|| {
+                        Error::Config(format!(
+                            "template `{}` needs at least {n} argument{}",
+                            self.name,
+                            if n == 1 { "" } else { "s" }
+                        ))
+                    })?;
+                    out.push_str(word);
+                }
+                // Rejected on load; kept verbatim should one slip through.
+                Piece::Unknown(name) => {
+                    out.push('{');
+                    out.push_str(name);
+                    out.push('}');
+                }
+            }
+        }
+        if !used && !args.is_empty() {
+            out.push_str("\n\n");
+            out.push_str(args);
+        }
+        let len = out.chars().count();
+        if len > MAX_PROMPT_LENGTH {
+            return Err(Error::Config(format!(
+                "rendered prompt is too long ({len} chars, max {MAX_PROMPT_LENGTH})"
+            )));
+        }
+        Ok(out)
+    }
+
+    /// First line of the body, for `/t list`.
+    pub fn preview(&self, max_chars: usize) -> String {
+        let first = self.body.lines().next().unwrap_or("");
+        let mut preview: String = first.chars().take(max_chars).collect();
+        if preview.len() < first.len() || self.body.lines().nth(1).is_some() {
+            preview.push('…');
+        }
+        preview
+    }
+}
+
+/// Parse `templates.yaml`: top-level `name: prompt` pairs. Every problem (bad or duplicate
+/// names, unknown placeholders, prompts over [`MAX_PROMPT_LENGTH`]) is reported with its line
+/// number, and any problem fails the whole load.
+pub fn parse_templates(input: &str) -> Result<Vec<PromptTemplate>> {
+    let lines: Vec<String> = input.lines().map(expand_indent).collect();
+    let mut errors: Vec<String> = Vec::new();
+    let mut templates: Vec<PromptTemplate> = Vec::new();
+    let error = |errors: &mut Vec<String>, line: usize, msg: String| {
+        errors.push(format!("line {line}: {msg}"))
+    };
+
+    let mut pos = 0;
+    while pos < lines.len() {
+        let no = pos + 1;
+        let line = &lines[pos];
+        pos += 1;
+        if is_blank_or_comment(line) {
+            continue;
+        }
+        if count_indent(line) > 0 {
+            error(
+                &mut errors,
+                no,
+                "expected `name: prompt` at the start of the line".to_string(),
+            );
+            continue;
+        }
+        let text = strip_comment(line.trim());
+        let Some((name, value)) = text.split_once(':') else {
+            error(
+                &mut errors,
+                no,
+                format!("expected `name: prompt`, got `{text}`"),
+            );
+            continue;
+        };
+        let name = name.trim();
+        let value = value.trim();
+
+        // Source lines of the body, for placeholder errors.
+        let mut source: Vec<(usize, String)> = Vec::new();
+        let body = if value.starts_with(['|', '>']) {
+            if !is_block_indicator(value) {
+                error(
+                    &mut errors,
+                    no,
+                    format!("unsupported block scalar header `{value}`"),
+                );
+                continue;
+            }
+            let mut block: Vec<String> = Vec::new();
+            let mut cut = None;
+            while pos < lines.len() {
+                let line = &lines[pos];
+                if line.trim().is_empty() {
+                    block.push(String::new());
+                    pos += 1;
+                    continue;
+                }
+                let indent = count_indent(line);
+                if indent == 0 {
+                    break;
+                }
+                let cut = *cut.get_or_insert(indent);
+                if indent < cut {
+                    error(
+                        &mut errors,
+                        pos + 1,
+                        format!("block line is indented less than the first ({cut} columns)"),
+                    );
+                    break;
+                }
+                block.push(line[cut..].to_string());
+                source.push((pos + 1, line[cut..].to_string()));
+                pos += 1;
+            }
+            while block.last().is_some_and(|l| l.is_empty()) {
+                block.pop();
+            }
+            if value.starts_with('>') {
+                fold_lines(&block)
+            } else {
+                block.join("\n")
+            }
+        } else {
+            match parse_scalar(value) {
+                Ok(v) => {
+                    source.push((no, v.clone()));
+                    v
+                }
+                Err(e) => {
+                    error(&mut errors, no, format!("`{name}`: {e}"));
+                    continue;
+                }
+            }
+        };
+
+        if !is_valid_profile_name(name) {
+            error(
+                &mut errors,
+                no,
+                format!("invalid template name `{name}` (letters, digits, '-' and '_' only)"),
+            );
+            continue;
+        }
+        if name.eq_ignore_ascii_case("list") {
+            error(
+                &mut errors,
+                no,
+                "`list` is reserved for /t list".to_string(),
+            );
+            continue;
+        }
+        if templates.iter().any(|t| t.name.eq_ignore_ascii_case(name)) {
+            error(&mut errors, no, format!("duplicate template name `{name}`"));
+            continue;
+        }
+        let body = body.trim().to_string();
+        if body.is_empty() {
+            error(&mut errors, no, format!("template `{name}` is empty"));
+            continue;
+        }
+        let len = body.chars().count();
+        if len > MAX_PROMPT_LENGTH {
+            error(
+                &mut errors,
+                no,
+                format!("template `{name}` is too long ({len} chars, max {MAX_PROMPT_LENGTH})"),
+            );
+            continue;
+        }
+        let before = errors.len();
+        for (line_no, text) in &source {
+            for piece in pieces(text) {
+                if let Piece::Unknown(p) = piece {
+                    error(
+                        &mut errors,
+                        *line_no,
+                        format!(
+                            "template `{name}`: unknown placeholder `{{{p}}}` \
+                             (use {{arg}} or {{1}}..{{9}}, {{{{ for a literal brace)"
+                        ),
+                    );
+                }
+            }
+        }
+        if errors.len() == before {
+            templates.push(PromptTemplate {
+                name: name.to_string(),
+                body,
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        return Ok(templates);
+    }
+    Err(Error::Config(format!(
+        "templates.yaml has {} error{}:\n{}",
+        errors.len(),
+        if errors.len() == 1 { "" } else { "s" },
+        errors.join("\n")
+    )))
+}
+
+/// `templates.yaml`, re-read whenever its modification time or size changes.
+#[derive(Debug)]
+pub struct TemplateStore {
+    path: PathBuf,
+    cache: Mutex<Option<CachedTemplates>>,
+}
+
+#[derive(Debug)]
+struct CachedTemplates {
+    /// Modification time and size the templates were read at; `None` = no file.
+    stamp: Option<(SystemTime, u64)>,
+    /// The templates, or the load error to report until the file is fixed.
+    templates: std::result::Result<Vec<PromptTemplate>, String>,
+}
+
+impl TemplateStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            cache: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(cfg: &Config) -> Self {
+        Self::new(templates_path(cfg))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// All templates in file order; no file means none.
+    pub fn templates(&self) -> Result<Vec<PromptTemplate>> {
+        let stamp = fs::metadata(&self.path)
+            .ok()
+            .and_then(|m| Some((m.modified().ok()?, m.len())));
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.as_ref().is_none_or(|c| c.stamp != stamp) {
+            let templates = match stamp {
+                None => Ok(Vec::new()),
+                Some(_) => fs::read_to_string(&self.path)
+                    .map_err(Error::from)
+                    .and_then(|text| parse_templates(&text))
+                    .map_err(|e| e.to_string()),
+            };
+            if let Ok(t) = &templates {
+                if cache.is_some() {
+                    log_info!("[TEMPLATES] Reloaded {} template(s)", t.len());
+                }
+            }
+            *cache = Some(CachedTemplates { stamp, templates });
+        }
+        match cache.as_ref().map(|c| &c.templates) {
+            Some(Ok(t)) => Ok(t.clone()),
+            Some(Err(e)) => Err(Error::Config(e.clone())),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Template `name` (case-insensitive).
+    pub fn get(&self, name: &str) -> Result<Option<PromptTemplate>> {
+        Ok(self
+            .templates()?
+            .into_iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(body: &str) -> PromptTemplate {
+        PromptTemplate {
+            name: "t".to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_scalars_and_blocks() {
+        let yaml = r#"
+# Daily shortcuts
+review: Review the last commit   # trailing comment
+standup: |
+  Summarize today's standup notes in ~/notes.
+  Focus on {arg}.
+
+fix-issue: >
+  Fix issue #{1}
+  in {2}.
+quoted: "Say {{hi}}"
+"#;
+        let templates = parse_templates(yaml).unwrap();
+        let bodies: Vec<(&str, &str)> = templates
+            .iter()
+            .map(|t| (t.name.as_str(), t.body.as_str()))
+            .collect();
+        assert_eq!(
+            bodies,
+            [
+                ("review", "Review the last commit"),
+                (
+                    "standup",
+                    "Summarize today's standup notes in ~/notes.\nFocus on {arg}."
+                ),
+                ("fix-issue", "Fix issue #{1} in {2}."),
+                ("quoted", "Say {{hi}}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_error_with_line_numbers() {
+        let long = "x".repeat(MAX_PROMPT_LENGTH + 1);
+        let yaml = format!(
+            "ok: fine\nbad name: x\nok: again\nlist: x\nlong: {long}\nblock: |\n  line one\n  uses {{topic}}\n  indented: no\n"
+        );
+        let err = parse_templates(&yaml).unwrap_err().to_string();
+        assert!(err.contains("5 errors"), "{err}");
+        assert!(
+            err.contains("line 2: invalid template name `bad name`"),
+            "{err}"
+        );
+        assert!(
+            err.contains("line 3: duplicate template name `ok`"),
+            "{err}"
+        );
+        assert!(err.contains("line 4: `list` is reserved"), "{err}");
+        assert!(
+            err.contains(&format!(
+                "line 5: template `long` is too long ({} chars",
+                MAX_PROMPT_LENGTH + 1
+            )),
+            "{err}"
+        );
+        assert!(
+            err.contains("line 8: template `block`: unknown placeholder `{topic}`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            template("Notes on {arg}, please")
+                .render(" the db migration ")
+                .unwrap(),
+            "Notes on the db migration, please"
+        );
+        assert_eq!(
+            template("Fix #{1} in {2}; {{keep}} {x")
+                .render("42 parser")
+                .unwrap(),
+            "Fix #42 in parser; {keep} {x"
+        );
+        assert_eq!(
+            template("Review the last commit")
+                .render("focus on tests")
+                .unwrap(),
+            "Review the last commit\n\nfocus on tests"
+        );
+        assert_eq!(template("Review").render("").unwrap(), "Review");
+        let err = template("Fix #{1} in {2}").render("42").unwrap_err();
+        assert!(err.to_string().contains("needs at least 2 arguments"));
+        let long = "y".repeat(MAX_PROMPT_LENGTH);
+        assert!(template("{arg}!").render(&long).is_err());
+    }
+
+    #[test]
+    fn store_reloads_when_the_file_changes() {
+        let dir = std::env::temp_dir().join(format!("ctb-templates-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("templates.yaml");
+        let store = TemplateStore::new(path.clone());
+        assert!(store.templates().unwrap().is_empty());
+
+        fs::write(&path, "review: Review it\n").unwrap();
+        assert_eq!(store.get("REVIEW").unwrap().unwrap().body, "Review it");
+
+        fs::write(&path, "review: Review {oops}\n").unwrap();
+        let err = store.templates().unwrap_err().to_string();
+        assert!(err.contains("line 1"), "{err}");
+
+        fs::write(&path, "review: Review it again\nship: Ship it\n").unwrap();
+        assert_eq!(store.templates().unwrap().len(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,169 @@
+//! Restart bookkeeping (parity with TS `/restart` + startup confirmation).
+//!
+//! `/restart` saves `{ chat_id, message_id, timestamp }` to the state store (`RESTART_FILE` with
+//! the JSON backend) before exiting. On startup we read it back, edit the "Restarting..." message
+//! to a confirmation with the observed downtime, and delete the marker. Stale markers are ignored
+//! so an old crash does not produce a confusing confirmation hours later.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::{ChatId, MessageId, MessageRef},
+    messaging::port::MessagingPort,
+    storage::{StateStore, StateStoreExt, RESTART_NAMESPACE},
+    theme::StatusTheme,
+    Result,
+};
+
+/// Restart markers older than this are considered stale and ignored.
+pub const RESTART_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Stored restart marker (field names match the TS bot).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestartData {
+    pub chat_id: i64,
+    pub message_id: i32,
+    /// Unix epoch milliseconds when `/restart` was issued.
+    pub timestamp: u64,
+}
+
+impl RestartData {
+    pub fn message(&self) -> MessageRef {
+        MessageRef {
+            chat_id: ChatId(self.chat_id),
+            message_id: MessageId(self.message_id),
+        }
+    }
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Save the restart marker for the "Restarting..." message.
+pub fn write_restart_marker(store: &dyn StateStore, message: MessageRef) -> Result<()> {
+    let data = RestartData {
+        chat_id: message.chat_id.0,
+        message_id: message.message_id.0,
+        timestamp: now_ms(),
+    };
+    store.put_json(RESTART_NAMESPACE, "", &data)
+}
+
+/// Read and delete the restart marker.
+///
+/// Returns `None` when the marker is missing, malformed (it is backed up), or older than
+/// `max_age` (relative to `now_ms`). The marker is always removed so it is only ever considered
+/// once.
+pub fn take_restart_marker(
+    store: &dyn StateStore,
+    now_ms: u64,
+    max_age: Duration,
+) -> Option<RestartData> {
+    let data = store
+        .get_json::<RestartData>(RESTART_NAMESPACE, "")
+        .ok()
+        .flatten();
+    let _ = store.delete(RESTART_NAMESPACE, "");
+
+    let data = data?;
+    let age = now_ms.saturating_sub(data.timestamp);
+    if age > max_age.as_millis() as u64 {
+        return None;
+    }
+    Some(data)
+}
+
+/// Confirm a completed restart to the user.
+///
+/// Edits the original "Restarting..." message; if that fails (deleted / too old to edit) a fresh
+/// message is sent instead.
+pub async fn confirm_restart(
+    messenger: &dyn MessagingPort,
+    theme: &StatusTheme,
+    data: &RestartData,
+    now_ms: u64,
+) -> Result<()> {
+    let downtime = Duration::from_millis(now_ms.saturating_sub(data.timestamp));
+    let text = format!(
+        "{} Bot restarted (downtime: {:.1}s)",
+        theme.done,
+        downtime.as_secs_f64()
+    );
+
+    if messenger.edit_html(data.message(), &text).await.is_ok() {
+        return Ok(());
+    }
+    messenger
+        .send_html(ChatId(data.chat_id).into(), &text)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::JsonFileStore;
+
+    fn tmp_store(name: &str) -> (JsonFileStore, std::path::PathBuf) {
+        let path = std::path::PathBuf::from(format!(
+            "/tmp/ctb-restart-{name}-{}.json",
+            std::process::id()
+        ));
+        let store = JsonFileStore::new("/tmp").with_path(RESTART_NAMESPACE, &path);
+        (store, path)
+    }
+
+    #[test]
+    fn fresh_restart_marker_is_taken_and_removed() {
+        let (store, path) = tmp_store("fresh");
+        let data = RestartData {
+            chat_id: 7,
+            message_id: 42,
+            timestamp: 1_000_000,
+        };
+        std::fs::write(&path, serde_json::to_string(&data).unwrap()).unwrap();
+
+        let got = take_restart_marker(&store, 1_005_000, RESTART_STALE_AFTER);
+        assert_eq!(got, Some(data));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_restart_marker_is_ignored_and_removed() {
+        let (store, path) = tmp_store("stale");
+        let data = RestartData {
+            chat_id: 7,
+            message_id: 42,
+            timestamp: 1_000_000,
+        };
+        std::fs::write(&path, serde_json::to_string(&data).unwrap()).unwrap();
+
+        let now = 1_000_000 + RESTART_STALE_AFTER.as_millis() as u64 + 1;
+        assert_eq!(take_restart_marker(&store, now, RESTART_STALE_AFTER), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn malformed_restart_marker_is_ignored() {
+        let (store, path) = tmp_store("bad");
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(take_restart_marker(&store, 0, RESTART_STALE_AFTER), None);
+        assert!(!path.exists());
+
+        // Kept for inspection next to where the marker was.
+        let prefix = format!("{}.corrupt-", path.file_name().unwrap().to_string_lossy());
+        let backups: Vec<_> = std::fs::read_dir("/tmp")
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        let _ = std::fs::remove_file(backups[0].path());
+    }
+}
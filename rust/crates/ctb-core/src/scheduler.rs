@@ -6,6 +6,8 @@
 //! - Queues jobs if a session is already running
 //! - Rate limits job executions per hour
 //! - Auto-reloads when `cron.yaml` changes (polling mtime)
+//! - `/cron test <name>` runs a schedule immediately and always reports back
+//! - `/remind` adds one-shot [`OneShotJob`]s, persisted to `cfg.reminders_file` until they fire
 //!
 //! Notes:
 //! - We intentionally avoid a YAML/cron dependency to keep offline builds working.
@@ -13,7 +15,7 @@
 use std::{
     collections::{HashMap, VecDeque},
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicI32, Ordering},
         Arc,
@@ -21,40 +23,179 @@ use std::{
     time::{Duration, Instant, SystemTime},
 };
 
-use chrono::{DateTime, Datelike, Local, Timelike};
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
     config::Config,
-    domain::{ChatId, MessageId, MessageRef},
+    domain::{ChatId, ChatTarget, MessageId, MessageRef},
     formatting::escape_html,
+    i18n::Messages,
     messaging::{
         port::MessagingPort,
         types::{ChatAction, InlineKeyboard, MessagingCapabilities},
     },
-    security::PathPolicy,
-    session::ClaudeSession,
+    pipeline::TurnOutput,
+    security::{scrub_secrets, PathPolicy, Role},
+    session::{ClaudeSession, RunLimits},
+    theme::StatusTheme,
     Error, Result,
 };
 
-const MAX_PROMPT_LENGTH: usize = 10_000;
+/// Longest scheduled, reminder or template prompt, in characters.
+pub const MAX_PROMPT_LENGTH: usize = 10_000;
 const MAX_JOBS_PER_HOUR: usize = 60;
 const MAX_PENDING_QUEUE_SIZE: usize = 100;
+const MAX_REMINDERS: usize = 100;
+/// Furthest ahead `/remind` schedules.
+pub const MAX_REMINDER_DELAY: Duration = Duration::from_secs(30 * 24 * 3600);
+/// Delay before an `ask:` reminder that found the session busy tries again.
+/// Bytes of answer text in a cron completion notification.
+const NOTIFY_TEXT_MAX: usize = 3500;
 
-#[derive(Clone, Debug)]
+const REMINDER_BUSY_RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CronSchedule {
     pub name: String,
     pub cron: String,
     pub prompt: String,
     pub enabled: bool,
     pub notify: bool,
+    /// Per-schedule overrides for `MAX_TURNS` / `MAX_BUDGET_USD`.
+    #[serde(default)]
+    pub max_turns: Option<u32>,
+    #[serde(default)]
+    pub max_budget_usd: Option<f64>,
+}
+
+/// A one-shot reminder from `/remind`: posted to its chat at `fire_at`, or run there as a
+/// prompt when `ask` is set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OneShotJob {
+    pub id: u32,
+    pub chat_id: i64,
+    pub thread_id: Option<i32>,
+    /// Unix seconds.
+    pub fire_at: i64,
+    pub text: String,
+    pub ask: bool,
+    /// Role of the user who set it; an `ask:` reminder runs with this role. Reminders saved
+    /// before roles were recorded run read-only.
+    #[serde(default = "legacy_reminder_role")]
+    pub role: Role,
+}
+
+fn legacy_reminder_role() -> Role {
+    Role::ReadOnly
+}
+
+impl OneShotJob {
+    pub fn target(&self) -> ChatTarget {
+        ChatTarget::new(ChatId(self.chat_id), self.thread_id)
+    }
+
+    pub fn fire_at_local(&self) -> DateTime<Local> {
+        Local
+            .timestamp_opt(self.fire_at, 0)
+            .single()
+            .unwrap_or_else(Local::now)
+    }
+
+    /// `#id HH:MM` (with the date when not today), `ask` marker and a text preview.
+    pub fn summary_html(&self, theme: &StatusTheme, now: DateTime<Local>) -> String {
+        let at = self.fire_at_local();
+        let when = if at.date_naive() == now.date_naive() {
+            at.format("%H:%M").to_string()
+        } else {
+            at.format("%b %-d %H:%M").to_string()
+        };
+        let mut preview: String = self.text.chars().take(60).collect();
+        if preview.len() < self.text.len() {
+            preview.push_str(theme.ellipsis);
+        }
+        format!(
+            "{} #{} at {}{}: {}",
+            theme.bullet,
+            self.id,
+            when,
+            if self.ask { " (ask)" } else { "" },
+            escape_html(&preview)
+        )
+    }
+}
+
+/// Why `/remind` did not add a reminder.
+#[derive(Debug, thiserror::Error)]
+pub enum ReminderError {
+    #[error("reminder text is empty")]
+    Empty,
+    #[error("reminder text is too long ({0} chars, max {MAX_PROMPT_LENGTH})")]
+    TooLong(usize),
+    #[error("too many pending reminders (max {MAX_REMINDERS})")]
+    TooMany,
+    #[error("{0}")]
+    Persist(Error),
+}
+
+/// Why `/cron test` did not start a job.
+#[derive(Debug, thiserror::Error)]
+pub enum CronTestError {
+    #[error("no cron.yaml with schedules found")]
+    NoSchedules,
+    #[error("{0}")]
+    Load(Error),
+    #[error("no schedule named \"{name}\"{}", suggestion.as_ref().map(|s| format!(" (did you mean \"{s}\"?)")).unwrap_or_default())]
+    NotFound {
+        name: String,
+        suggestion: Option<String>,
+    },
+    #[error("a query is running; try again when it has finished")]
+    Busy,
+    #[error("the hourly limit of {MAX_JOBS_PER_HOUR} scheduled runs is reached")]
+    RateLimited,
+}
+
+/// What started a run: the cron expression, or `/cron test` from `reply_to`.
+#[derive(Clone, Copy, Debug)]
+enum Trigger {
+    Schedule,
+    Test {
+        reply_to: ChatTarget,
+    },
+    /// An `ask:` reminder, run in and reported to the chat that set it.
+    Reminder {
+        reply_to: ChatTarget,
+        role: Role,
+    },
+}
+
+impl Trigger {
+    /// Marks test runs and reminders in the logs.
+    fn log_tag(self) -> &'static str {
+        match self {
+            Self::Schedule => "",
+            Self::Test { .. } => " [test run]",
+            Self::Reminder { .. } => " [reminder]",
+        }
+    }
+}
+
+/// Result of the busy check and the hourly window before a run.
+enum Admission {
+    Run,
+    Busy,
+    RateLimited,
 }
 
 #[derive(Clone, Debug, Default)]
 struct CronConfig {
     schedules: Vec<CronSchedule>,
+    /// Line-numbered problems; the entries they belong to are left out of `schedules`.
+    errors: Vec<String>,
 }
 
 #[derive(Clone)]
@@ -79,6 +220,17 @@ struct SchedulerState {
     execution_lock: bool,
     executions: VecDeque<Instant>,
     pending: VecDeque<PendingJob>,
+
+    reminders: HashMap<u32, ReminderEntry>,
+    next_reminder_id: u32,
+
+    /// Problems found by the last cron.yaml load.
+    config_errors: Vec<String>,
+}
+
+struct ReminderEntry {
+    job: OneShotJob,
+    cancel: CancellationToken,
 }
 
 struct PendingJob {
@@ -109,37 +261,40 @@ impl CronScheduler {
 
     pub async fn start(&self) -> Result<usize> {
         self.stop_jobs_only().await;
+        self.inner.state.lock().await.config_errors.clear();
 
         let config = match load_cron_config(&self.inner.cfg) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!("[CRON] Failed to load cron.yaml: {e}");
-                None
+                // Surface the line-numbered errors to `/cron reload` instead of reporting 0 jobs.
+                log_line!("[CRON] Failed to load cron.yaml: {e}");
+                return Err(e);
             }
         };
 
         let Some(config) = config else {
-            println!("[CRON] No schedules configured");
+            log_info!("[CRON] No schedules configured");
             return Ok(0);
         };
+        self.inner.state.lock().await.config_errors = config.errors.clone();
         if config.schedules.is_empty() {
-            println!("[CRON] No schedules configured");
+            log_info!("[CRON] No schedules configured");
             return Ok(0);
         }
 
-        println!("[CRON] Loading {} schedules", config.schedules.len());
+        log_info!("[CRON] Loading {} schedules", config.schedules.len());
 
         let mut loaded = 0usize;
         for schedule in config.schedules.into_iter() {
             if !schedule.enabled {
-                println!("[CRON] Skipping disabled schedule: {}", schedule.name);
+                log_info!("[CRON] Skipping disabled schedule: {}", schedule.name);
                 continue;
             }
 
             let expr = match CronExpr::parse(&schedule.cron) {
                 Ok(v) => v,
                 Err(e) => {
-                    eprintln!("[CRON] Invalid cron expression for {}: {e}", schedule.name);
+                    log_line!("[CRON] Invalid cron expression for {}: {e}", schedule.name);
                     continue;
                 }
             };
@@ -168,9 +323,9 @@ impl CronScheduler {
         }
 
         if loaded > 0 {
-            println!("[CRON] Started {loaded} jobs");
+            log_info!("[CRON] Started {loaded} jobs");
         } else {
-            println!("[CRON] No jobs started");
+            log_info!("[CRON] No jobs started");
         }
 
         Ok(loaded)
@@ -196,13 +351,22 @@ impl CronScheduler {
             job.cancel.cancel();
             job.handle.abort(); // best-effort
         }
+        // Reminders stay in the file and are restored on the next start.
+        for (_, reminder) in st.reminders.drain() {
+            reminder.cancel.cancel();
+        }
     }
 
     pub async fn reload(&self) -> Result<usize> {
-        println!("[CRON] Reloading configuration");
+        log_info!("[CRON] Reloading configuration");
         self.start().await
     }
 
+    /// Line-numbered problems from the last cron.yaml load; those entries were skipped.
+    pub async fn config_errors(&self) -> Vec<String> {
+        self.inner.state.lock().await.config_errors.clone()
+    }
+
     async fn stop_jobs_only(&self) {
         let mut st = self.inner.state.lock().await;
         for (_, job) in st.jobs.drain() {
@@ -212,14 +376,23 @@ impl CronScheduler {
         st.execution_lock = false;
     }
 
-    pub async fn status_html(&self) -> String {
+    pub async fn status_html(&self, msgs: &Messages) -> String {
+        let theme = self.inner.cfg.status_theme;
         let st = self.inner.state.lock().await;
-        if st.jobs.is_empty() {
-            return "No scheduled jobs".to_string();
+        if st.jobs.is_empty() && st.pending.is_empty() && st.reminders.is_empty() {
+            return msgs.cron_no_jobs().to_string();
         }
 
         let mut lines = Vec::new();
-        lines.push(format!("📅 <b>Scheduled Jobs ({})</b>", st.jobs.len()));
+        if st.jobs.is_empty() {
+            lines.push(msgs.cron_no_jobs().to_string());
+        } else {
+            lines.push(format!(
+                "{}{}",
+                theme.deco("📅"),
+                msgs.cron_jobs_title(st.jobs.len())
+            ));
+        }
 
         let mut names: Vec<_> = st.jobs.keys().cloned().collect();
         names.sort();
@@ -228,20 +401,38 @@ impl CronScheduler {
                 continue;
             };
             let next = job.expr.next_after(Local::now());
-            let next_str = next
-                .map(|dt| format!("{:02}:{:02}", dt.hour(), dt.minute()))
-                .unwrap_or_else(|| "never".to_string());
+            let next = next.map(|dt| format!("{:02}:{:02}", dt.hour(), dt.minute()));
             lines.push(format!(
-                "• {}: next at {}",
-                escape_html(&name),
-                escape_html(&next_str)
+                "{} {}",
+                theme.bullet,
+                msgs.cron_next_at(&name, next.as_deref())
             ));
         }
 
         if !st.pending.is_empty() {
-            lines.push(format!("\n⏳ <b>Queued Jobs ({})</b>", st.pending.len()));
+            lines.push(format!(
+                "\n{}{}",
+                theme.deco("⏳"),
+                msgs.cron_queued_title(st.pending.len())
+            ));
             for pending in st.pending.iter() {
-                lines.push(format!("• {}", escape_html(&pending.schedule.name)));
+                lines.push(format!(
+                    "{} {}",
+                    theme.bullet,
+                    escape_html(&pending.schedule.name)
+                ));
+            }
+        }
+
+        if !st.reminders.is_empty() {
+            lines.push(format!(
+                "\n{} {}",
+                theme.clock,
+                msgs.reminders_title(st.reminders.len())
+            ));
+            let now = Local::now();
+            for job in sorted_reminders(st.reminders.values().map(|r| &r.job)) {
+                lines.push(job.summary_html(theme, now));
             }
         }
 
@@ -266,12 +457,242 @@ impl CronScheduler {
             return Ok(());
         };
 
-        println!("[CRON] Processing queued job: {}", schedule.name);
+        log_info!("[CRON] Processing queued job: {}", schedule.name);
         self.execute_scheduled_prompt(schedule).await?;
 
         Ok(())
     }
 
+    /// Write queued jobs to `cfg.cron_queue_file` so they survive a restart.
+    ///
+    /// Returns the number of jobs written. An empty queue removes any stale file.
+    pub async fn persist_pending_jobs(&self) -> Result<usize> {
+        let schedules: Vec<CronSchedule> = {
+            let st = self.inner.state.lock().await;
+            st.pending.iter().map(|p| p.schedule.clone()).collect()
+        };
+        let path = &self.inner.cfg.cron_queue_file;
+        if schedules.is_empty() {
+            let _ = fs::remove_file(path);
+            return Ok(0);
+        }
+        save_pending_jobs(path, &schedules)?;
+        log_info!("[CRON] Persisted {} queued job(s)", schedules.len());
+        Ok(schedules.len())
+    }
+
+    /// Re-enqueue jobs persisted by `persist_pending_jobs()` and delete the file.
+    pub async fn restore_pending_jobs(&self) -> Result<usize> {
+        let path = &self.inner.cfg.cron_queue_file;
+        if !path.exists() {
+            return Ok(0);
+        }
+        let loaded = load_pending_jobs(path);
+        let _ = fs::remove_file(path);
+        let schedules = loaded?;
+
+        for schedule in schedules.iter().cloned() {
+            self.queue_job(schedule).await;
+        }
+        if !schedules.is_empty() {
+            log_info!("[CRON] Restored {} queued job(s)", schedules.len());
+        }
+        Ok(schedules.len())
+    }
+
+    /// Schedule a one-shot reminder in `target`. Text starting with `ask:` is run as a prompt
+    /// at `fire_at` instead of being posted, with the tools `role` (the creator's) allows.
+    pub async fn add_reminder(
+        &self,
+        target: ChatTarget,
+        fire_at: DateTime<Local>,
+        text: &str,
+        role: Role,
+    ) -> std::result::Result<OneShotJob, ReminderError> {
+        let (ask, text) = match text.trim().strip_prefix("ask:") {
+            Some(prompt) => (true, prompt.trim()),
+            None => (false, text.trim()),
+        };
+        if text.is_empty() {
+            return Err(ReminderError::Empty);
+        }
+        if text.chars().count() > MAX_PROMPT_LENGTH {
+            return Err(ReminderError::TooLong(text.chars().count()));
+        }
+
+        let mut st = self.inner.state.lock().await;
+        if st.reminders.len() >= MAX_REMINDERS {
+            return Err(ReminderError::TooMany);
+        }
+        st.next_reminder_id += 1;
+        let job = OneShotJob {
+            id: st.next_reminder_id,
+            chat_id: target.chat_id.0,
+            thread_id: target.thread_id,
+            fire_at: fire_at.timestamp(),
+            text: text.to_string(),
+            ask,
+            role,
+        };
+        self.arm_reminder(&mut st, job.clone());
+        self.save_reminders(&st).map_err(ReminderError::Persist)?;
+        log_info!(
+            "[CRON] Reminder #{} set for {}",
+            job.id,
+            job.fire_at_local().format("%Y-%m-%d %H:%M")
+        );
+        Ok(job)
+    }
+
+    /// Pending reminders of `target`, soonest first.
+    pub async fn reminders_for(&self, target: ChatTarget) -> Vec<OneShotJob> {
+        let st = self.inner.state.lock().await;
+        sorted_reminders(
+            st.reminders
+                .values()
+                .map(|r| &r.job)
+                .filter(|j| j.target() == target),
+        )
+        .into_iter()
+        .cloned()
+        .collect()
+    }
+
+    /// Cancel reminder `id` of `target`; `false` if there is no such reminder in that chat.
+    pub async fn cancel_reminder(&self, target: ChatTarget, id: u32) -> Result<bool> {
+        let mut st = self.inner.state.lock().await;
+        if st.reminders.get(&id).map(|r| r.job.target()) != Some(target) {
+            return Ok(false);
+        }
+        if let Some(entry) = st.reminders.remove(&id) {
+            entry.cancel.cancel();
+        }
+        self.save_reminders(&st)?;
+        Ok(true)
+    }
+
+    /// Re-arm reminders saved in `cfg.reminders_file`. Ones that came due while the bot was
+    /// down fire right away.
+    pub async fn restore_reminders(&self) -> Result<usize> {
+        let path = &self.inner.cfg.reminders_file;
+        if !path.exists() {
+            return Ok(0);
+        }
+        let jobs = load_reminders(path)?;
+        let mut st = self.inner.state.lock().await;
+        for job in jobs.iter().cloned() {
+            st.next_reminder_id = st.next_reminder_id.max(job.id);
+            self.arm_reminder(&mut st, job);
+        }
+        if !jobs.is_empty() {
+            log_info!("[CRON] Restored {} reminder(s)", jobs.len());
+        }
+        Ok(jobs.len())
+    }
+
+    /// Track `job` and start the task that fires it.
+    fn arm_reminder(&self, st: &mut SchedulerState, job: OneShotJob) {
+        if let Some(old) = st.reminders.remove(&job.id) {
+            old.cancel.cancel();
+        }
+        let cancel = CancellationToken::new();
+        let wait = (job.fire_at_local() - Local::now())
+            .to_std()
+            .unwrap_or_default();
+        let scheduler = self.clone();
+        let id = job.id;
+        let token = cancel.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+              _ = token.cancelled() => {}
+              _ = sleep(wait) => scheduler.fire_reminder(id).await,
+            }
+        });
+        st.reminders.insert(id, ReminderEntry { job, cancel });
+    }
+
+    fn save_reminders(&self, st: &SchedulerState) -> Result<()> {
+        let path = &self.inner.cfg.reminders_file;
+        let jobs = sorted_reminders(st.reminders.values().map(|r| &r.job));
+        if jobs.is_empty() {
+            let _ = fs::remove_file(path);
+            return Ok(());
+        }
+        save_reminders(path, &jobs)
+    }
+
+    async fn fire_reminder(&self, id: u32) {
+        let job = {
+            let mut st = self.inner.state.lock().await;
+            let Some(entry) = st.reminders.remove(&id) else {
+                return; // cancelled meanwhile
+            };
+            if let Err(e) = self.save_reminders(&st) {
+                log_line!("[CRON] Failed to save reminders: {e}");
+            }
+            entry.job
+        };
+        let target = job.target();
+
+        if !job.ask {
+            let msg = format!(
+                "{} <b>Reminder</b>\n\n{}",
+                self.inner.cfg.status_theme.clock,
+                escape_html(&job.text)
+            );
+            if let Err(e) = self.inner.messenger.send_html(target, &msg).await {
+                log_line!("[CRON] Failed to send reminder #{id}: {e}");
+            }
+            return;
+        }
+
+        let schedule = CronSchedule {
+            name: format!("reminder #{id}"),
+            cron: String::new(),
+            prompt: job.text.clone(),
+            enabled: true,
+            notify: true,
+            max_turns: None,
+            max_budget_usd: None,
+        };
+        let trigger = Trigger::Reminder {
+            reply_to: target,
+            role: job.role,
+        };
+        match self.admit(&schedule, trigger).await {
+            Admission::Run => {
+                if let Err(e) = self.run_admitted(schedule, trigger).await {
+                    log_line!("[CRON] Reminder #{id} failed: {e}");
+                }
+            }
+            Admission::Busy => {
+                log_info!(
+                    "[CRON] Session busy - retrying reminder #{id} in {}s",
+                    REMINDER_BUSY_RETRY.as_secs()
+                );
+                let mut st = self.inner.state.lock().await;
+                let retry = OneShotJob {
+                    fire_at: Local::now().timestamp() + REMINDER_BUSY_RETRY.as_secs() as i64,
+                    ..job
+                };
+                self.arm_reminder(&mut st, retry);
+                if let Err(e) = self.save_reminders(&st) {
+                    log_line!("[CRON] Failed to save reminders: {e}");
+                }
+            }
+            Admission::RateLimited => {
+                let msg = format!(
+                    "{} <b>Reminder</b> (not run: the hourly limit of {MAX_JOBS_PER_HOUR} scheduled runs is reached)\n\n{}",
+                    self.inner.cfg.status_theme.clock,
+                    escape_html(&job.text)
+                );
+                if let Err(e) = self.inner.messenger.send_html(target, &msg).await {
+                    log_line!("[CRON] Failed to send reminder #{id}: {e}");
+                }
+            }
+        }
+    }
+
     async fn start_file_watcher(&self) {
         let cron_path = cron_config_path(&self.inner.cfg);
 
@@ -315,7 +736,7 @@ impl CronScheduler {
                     };
 
                     if should_reload {
-                      println!("[CRON] Detected cron.yaml change, auto-reloading...");
+                      log_info!("[CRON] Detected cron.yaml change, auto-reloading...");
                       sleep(Duration::from_millis(100)).await;
                       let _ = scheduler.reload().await;
                     }
@@ -328,13 +749,13 @@ impl CronScheduler {
         });
 
         st.watcher = Some(handle);
-        println!("[CRON] File watcher started");
+        log_info!("[CRON] File watcher started");
     }
 
     async fn job_loop(&self, schedule: CronSchedule, expr: CronExpr, cancel: CancellationToken) {
         loop {
             let Some(next) = expr.next_after(Local::now()) else {
-                eprintln!("[CRON] Job {} has no next run (stopping)", schedule.name);
+                log_line!("[CRON] Job {} has no next run (stopping)", schedule.name);
                 break;
             };
 
@@ -350,48 +771,100 @@ impl CronScheduler {
                 let scheduler = self.clone();
                 let schedule = schedule.clone();
                 if let Err(e) = scheduler.execute_scheduled_prompt(schedule).await {
-                  eprintln!("[CRON] Scheduled job failed: {e}");
+                  log_line!("[CRON] Scheduled job failed: {e}");
                 }
               }
             }
         }
     }
 
-    async fn execute_scheduled_prompt(&self, schedule: CronSchedule) -> Result<()> {
-        // If session is busy, queue.
-        if self.inner.session.is_running().await {
-            self.queue_job(schedule).await;
-            return Ok(());
+    /// Run `name` from cron.yaml right away, ignoring its cron expression. The run takes the
+    /// same path as a scheduled one (execution lock, hourly window, silent messenger), except
+    /// that a busy session refuses instead of queueing and the result always goes to
+    /// `reply_to`. Returns the schedule's name once the run has started in the background.
+    pub async fn test_job(
+        &self,
+        name: &str,
+        reply_to: ChatTarget,
+    ) -> std::result::Result<String, CronTestError> {
+        let config = load_cron_config(&self.inner.cfg)
+            .map_err(CronTestError::Load)?
+            .filter(|c| !c.schedules.is_empty())
+            .ok_or(CronTestError::NoSchedules)?;
+        let schedule = find_schedule(&config.schedules, name)?.clone();
+
+        let trigger = Trigger::Test { reply_to };
+        match self.admit(&schedule, trigger).await {
+            Admission::Run => {}
+            Admission::Busy => return Err(CronTestError::Busy),
+            Admission::RateLimited => return Err(CronTestError::RateLimited),
         }
+        let scheduler = self.clone();
+        let started = schedule.name.clone();
+        tokio::spawn(async move {
+            if let Err(e) = scheduler.run_admitted(schedule, trigger).await {
+                log_line!("[CRON] Test run failed: {e}");
+            }
+        });
+        Ok(started)
+    }
 
-        // Take execution lock + rate limit window.
-        {
-            let mut st = self.inner.state.lock().await;
-            if st.execution_lock {
-                drop(st);
+    async fn execute_scheduled_prompt(&self, schedule: CronSchedule) -> Result<()> {
+        match self.admit(&schedule, Trigger::Schedule).await {
+            Admission::Run => self.run_admitted(schedule, Trigger::Schedule).await,
+            // If session is busy, queue.
+            Admission::Busy => {
                 self.queue_job(schedule).await;
-                return Ok(());
+                Ok(())
             }
+            Admission::RateLimited => Ok(()),
+        }
+    }
 
-            let now = Instant::now();
-            let one_hour = Duration::from_secs(3600);
-            while st
-                .executions
-                .front()
-                .map(|t| now.duration_since(*t) > one_hour)
-                .unwrap_or(false)
-            {
-                st.executions.pop_front();
-            }
-            if st.executions.len() >= MAX_JOBS_PER_HOUR {
-                println!("[CRON] Rate limit reached, skipping {}", schedule.name);
-                return Ok(());
-            }
+    /// Take the execution lock and a slot in the hourly window, unless the session is busy.
+    async fn admit(&self, schedule: &CronSchedule, trigger: Trigger) -> Admission {
+        if self.inner.session.is_running().await {
+            return Admission::Busy;
+        }
 
-            st.execution_lock = true;
-            st.executions.push_back(now);
+        let mut st = self.inner.state.lock().await;
+        if st.execution_lock {
+            return Admission::Busy;
+        }
+
+        let now = Instant::now();
+        let one_hour = Duration::from_secs(3600);
+        while st
+            .executions
+            .front()
+            .map(|t| now.duration_since(*t) > one_hour)
+            .unwrap_or(false)
+        {
+            st.executions.pop_front();
+        }
+        if st.executions.len() >= MAX_JOBS_PER_HOUR {
+            log_info!(
+                "[CRON] Rate limit reached, skipping {}{}",
+                schedule.name,
+                trigger.log_tag()
+            );
+            return Admission::RateLimited;
         }
 
+        st.execution_lock = true;
+        st.executions.push_back(now);
+        if let Trigger::Test { .. } = trigger {
+            log_info!(
+                "[CRON] Test run of {} counts toward the hourly limit ({}/{MAX_JOBS_PER_HOUR})",
+                schedule.name,
+                st.executions.len()
+            );
+        }
+        Admission::Run
+    }
+
+    /// Run a job admitted by [`Self::admit`] and release the execution lock afterwards.
+    async fn run_admitted(&self, schedule: CronSchedule, trigger: Trigger) -> Result<()> {
         let chat_id = self
             .inner
             .cfg
@@ -401,33 +874,57 @@ impl CronScheduler {
             .unwrap_or_default();
         let chat_id = ChatId(chat_id);
 
-        println!("[CRON] Executing scheduled job: {}", schedule.name);
+        log_info!(
+            "[CRON] Executing scheduled job: {}{}",
+            schedule.name,
+            trigger.log_tag()
+        );
+        let (run_target, notify_target, title) = match trigger {
+            Trigger::Schedule => (
+                chat_id.into(),
+                schedule.notify.then_some(chat_id.into()),
+                "Scheduled",
+            ),
+            Trigger::Test { reply_to } => (chat_id.into(), Some(reply_to), "Test run"),
+            Trigger::Reminder { reply_to, .. } => (reply_to, Some(reply_to), "Reminder"),
+        };
 
         let cron_messenger: Arc<dyn MessagingPort> =
             Arc::new(CronMessenger::new(self.inner.messenger.clone()));
         let prompt = schedule.prompt.clone();
+        let defaults = RunLimits::from_config(&self.inner.cfg);
+        let mut limits = RunLimits {
+            max_turns: schedule.max_turns.or(defaults.max_turns),
+            max_budget_usd: schedule.max_budget_usd.or(defaults.max_budget_usd),
+            ..defaults
+        };
+        // Reminders run with their creator's role; cron jobs are the operator's.
+        if let Trigger::Reminder { role, .. } = trigger {
+            limits = limits.with_role(role);
+        }
 
         let res = self
             .inner
             .session
-            .send_message_to_chat(chat_id, &prompt, cron_messenger)
+            .send_message_to_chat_with_limits(run_target, &prompt, cron_messenger, limits)
             .await;
 
         match res {
             Ok(out) => {
-                println!("[CRON] Job {} completed", schedule.name);
-                if schedule.notify {
+                log_info!(
+                    "[CRON] Job {} completed{}",
+                    schedule.name,
+                    trigger.log_tag()
+                );
+                if let Some(notify_target) = notify_target {
                     let safe_name = escape_html(&schedule.name);
-                    let mut snippet = out.text;
-                    if snippet.len() > 3500 {
-                        snippet.truncate(3500);
-                    }
                     let msg = format!(
-                        "🕐 <b>Scheduled: {safe_name}</b>\n\n{}",
-                        escape_html(&snippet)
+                        "{}<b>{title}: {safe_name}</b>\n\n{}",
+                        self.inner.cfg.status_theme.deco("🕐"),
+                        escape_html(&notify_text(&out, NOTIFY_TEXT_MAX))
                     );
-                    if let Err(e) = self.inner.messenger.send_html(chat_id, &msg).await {
-                        eprintln!(
+                    if let Err(e) = self.inner.messenger.send_html(notify_target, &msg).await {
+                        log_line!(
                             "[CRON] Failed to send completion notification for {}: {e}",
                             schedule.name
                         );
@@ -435,19 +932,24 @@ impl CronScheduler {
                 }
             }
             Err(e) => {
-                eprintln!("[CRON] Job {} failed: {e}", schedule.name);
-                if schedule.notify {
+                log_line!(
+                    "[CRON] Job {} failed{}: {e}",
+                    schedule.name,
+                    trigger.log_tag()
+                );
+                if let Some(notify_target) = notify_target {
                     let safe_name = escape_html(&schedule.name);
-                    let mut err_txt = format!("{e}");
-                    if err_txt.len() > 500 {
-                        err_txt.truncate(500);
-                    }
+                    let err_txt: String = scrub_secrets(&e.to_string(), &self.inner.cfg)
+                        .chars()
+                        .take(500)
+                        .collect();
                     let msg = format!(
-                        "❌ <b>Scheduled job failed: {safe_name}</b>\n\n{}",
+                        "{} <b>{title} failed: {safe_name}</b>\n\n{}",
+                        self.inner.cfg.status_theme.failed,
                         escape_html(&err_txt)
                     );
-                    if let Err(send_e) = self.inner.messenger.send_html(chat_id, &msg).await {
-                        eprintln!(
+                    if let Err(send_e) = self.inner.messenger.send_html(notify_target, &msg).await {
+                        log_line!(
                             "[CRON] Failed to send failure notification for {}: {send_e}",
                             schedule.name
                         );
@@ -468,13 +970,13 @@ impl CronScheduler {
     async fn queue_job(&self, schedule: CronSchedule) {
         let mut st = self.inner.state.lock().await;
         if st.pending.len() >= MAX_PENDING_QUEUE_SIZE {
-            println!(
+            log_info!(
                 "[CRON] Queue full ({}), dropping oldest job",
                 MAX_PENDING_QUEUE_SIZE
             );
             st.pending.pop_front();
         }
-        println!("[CRON] Session busy - queuing job: {}", schedule.name);
+        log_info!("[CRON] Session busy - queuing job: {}", schedule.name);
         st.pending.push_back(PendingJob { schedule });
     }
 }
@@ -512,8 +1014,8 @@ impl MessagingPort for CronMessenger {
         self.real.capabilities()
     }
 
-    async fn send_html(&self, chat_id: ChatId, _html: &str) -> Result<MessageRef> {
-        Ok(self.alloc(chat_id))
+    async fn send_html(&self, target: ChatTarget, _html: &str) -> Result<MessageRef> {
+        Ok(self.alloc(target.chat_id))
     }
 
     async fn edit_html(&self, _msg: MessageRef, _html: &str) -> Result<()> {
@@ -524,7 +1026,16 @@ impl MessagingPort for CronMessenger {
         Ok(())
     }
 
-    async fn send_chat_action(&self, _chat_id: ChatId, _action: ChatAction) -> Result<()> {
+    async fn edit_inline_keyboard(
+        &self,
+        _msg: MessageRef,
+        _html: &str,
+        _keyboard: Option<InlineKeyboard>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_chat_action(&self, _target: ChatTarget, _action: ChatAction) -> Result<()> {
         Ok(())
     }
 
@@ -534,234 +1045,613 @@ impl MessagingPort for CronMessenger {
 
     async fn send_inline_keyboard(
         &self,
-        chat_id: ChatId,
+        target: ChatTarget,
         text_html: &str,
         keyboard: InlineKeyboard,
     ) -> Result<MessageRef> {
         self.real
-            .send_inline_keyboard(chat_id, text_html, keyboard)
+            .send_inline_keyboard(target, text_html, keyboard)
+            .await
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()> {
+        self.real
+            .answer_callback_query(callback_id, text, show_alert)
             .await
     }
 
-    async fn answer_callback_query(&self, callback_id: &str, text: Option<&str>) -> Result<()> {
-        self.real.answer_callback_query(callback_id, text).await
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        _path: &Path,
+        _caption: Option<&str>,
+    ) -> Result<MessageRef> {
+        Ok(self.alloc(target.chat_id))
     }
 }
 
 // === cron.yaml loading ===
 
+/// Answer text for a completion notification: the whole turn when it fits in `max` bytes, else
+/// its last segment (the final answer), cut at `max` only if that alone is too long.
+fn notify_text(out: &TurnOutput, max: usize) -> String {
+    if out.text.len() <= max {
+        return out.text.clone();
+    }
+    let last = out.segments.last().unwrap_or(&out.text);
+    let mut end = last.len().min(max);
+    while !last.is_char_boundary(end) {
+        end -= 1;
+    }
+    last[..end].to_string()
+}
+
 fn cron_config_path(cfg: &Config) -> PathBuf {
     cfg.claude_working_dir.join("cron.yaml")
 }
 
+fn save_pending_jobs(path: &Path, schedules: &[CronSchedule]) -> Result<()> {
+    fs::write(path, serde_json::to_string(schedules)?)?;
+    Ok(())
+}
+
+fn load_pending_jobs(path: &Path) -> Result<Vec<CronSchedule>> {
+    let txt = fs::read_to_string(path)?;
+    let mut schedules: Vec<CronSchedule> = serde_json::from_str(&txt)?;
+    schedules.truncate(MAX_PENDING_QUEUE_SIZE);
+    Ok(schedules)
+}
+
+fn save_reminders(path: &Path, jobs: &[&OneShotJob]) -> Result<()> {
+    fs::write(path, serde_json::to_string(jobs)?)?;
+    Ok(())
+}
+
+fn load_reminders(path: &Path) -> Result<Vec<OneShotJob>> {
+    let txt = fs::read_to_string(path)?;
+    let mut jobs: Vec<OneShotJob> = serde_json::from_str(&txt)?;
+    jobs.truncate(MAX_REMINDERS);
+    Ok(jobs)
+}
+
+fn sorted_reminders<'a>(jobs: impl Iterator<Item = &'a OneShotJob>) -> Vec<&'a OneShotJob> {
+    let mut jobs: Vec<_> = jobs.collect();
+    jobs.sort_by_key(|j| (j.fire_at, j.id));
+    jobs
+}
+
+/// When a `/remind` spec fires: a duration from `now` (`40m`, `2h30m`, `1d`, `90s`) or the
+/// next `HH:MM` on the local clock.
+pub fn parse_reminder_time(
+    spec: &str,
+    now: DateTime<Local>,
+) -> std::result::Result<DateTime<Local>, String> {
+    let spec = spec.trim().to_ascii_lowercase();
+    if let Some((h, m)) = spec.split_once(':') {
+        let (Ok(h), Ok(m)) = (h.parse::<u32>(), m.parse::<u32>()) else {
+            return Err(format!("invalid time `{spec}` (use HH:MM)"));
+        };
+        let mut day = now.date_naive();
+        for _ in 0..2 {
+            let at = day
+                .and_hms_opt(h, m, 0)
+                .ok_or_else(|| format!("invalid time `{spec}` (use HH:MM)"))?
+                .and_local_timezone(Local)
+                .earliest();
+            if let Some(at) = at.filter(|at| *at > now) {
+                return Ok(at);
+            }
+            day = day.succ_opt().ok_or("date out of range")?;
+        }
+        return Err(format!("`{spec}` does not exist on the local clock"));
+    }
+
+    let mut total = 0u64;
+    let mut digits = String::new();
+    for c in spec.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 86_400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("unknown unit `{c}` in `{spec}` (use d, h, m, s)")),
+        };
+        let n: u64 = digits
+            .parse()
+            .map_err(|_| format!("invalid duration `{spec}` (e.g. 40m, 2h30m)"))?;
+        total = total.saturating_add(n.saturating_mul(unit));
+        digits.clear();
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(format!("invalid duration `{spec}` (e.g. 40m, 2h30m)"));
+    }
+    if total > MAX_REMINDER_DELAY.as_secs() {
+        return Err(format!(
+            "reminders can be at most {} days ahead",
+            MAX_REMINDER_DELAY.as_secs() / 86_400
+        ));
+    }
+    Ok(now + chrono::Duration::seconds(total as i64))
+}
+
+/// Schedule named `name`, ignoring case; otherwise suggest the closest name.
+fn find_schedule<'a>(
+    schedules: &'a [CronSchedule],
+    name: &str,
+) -> std::result::Result<&'a CronSchedule, CronTestError> {
+    let wanted = name.trim().to_lowercase();
+    if let Some(found) = schedules.iter().find(|s| s.name.to_lowercase() == wanted) {
+        return Ok(found);
+    }
+    let suggestion = schedules
+        .iter()
+        .map(|s| (edit_distance(&wanted, &s.name.to_lowercase()), &s.name))
+        .filter(|(d, s)| *d <= (s.chars().count() / 3).max(2))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, s)| s.clone());
+    Err(CronTestError::NotFound {
+        name: name.trim().to_string(),
+        suggestion,
+    })
+}
+
+/// Levenshtein distance over chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
 fn load_cron_config(cfg: &Config) -> Result<Option<CronConfig>> {
     let path = cron_config_path(cfg);
 
     // Path allowlist parity with TS.
-    let policy = PathPolicy {
-        allowed_paths: cfg.allowed_paths.clone(),
-        temp_paths: cfg.temp_paths.clone(),
-        home_dir: std::env::var_os("HOME").map(PathBuf::from),
-        base_dir: Some(cfg.claude_working_dir.clone()),
-    };
+    let policy = PathPolicy::from_config(cfg);
 
     if !policy.is_path_allowed(&path.to_string_lossy()) {
-        eprintln!("[CRON] cron.yaml path not in allowed directories");
+        log_line!("[CRON] cron.yaml path not in allowed directories");
         return Ok(None);
     }
 
     if !path.exists() {
-        println!("[CRON] No cron.yaml found at {}", path.display());
+        log_info!("[CRON] No cron.yaml found at {}", path.display());
         return Ok(None);
     }
 
     let content = fs::read_to_string(&path)?;
-    let config = parse_cron_yaml(&content)?;
+    let config = parse_cron_yaml(&content);
+    if !config.errors.is_empty() {
+        log_line!(
+            "[CRON] Skipping cron.yaml entries with {} error{}:\n{}",
+            config.errors.len(),
+            if config.errors.len() == 1 { "" } else { "s" },
+            config.errors.join("\n")
+        );
+    }
     Ok(Some(config))
 }
 
-fn parse_cron_yaml(input: &str) -> Result<CronConfig> {
-    // A tiny YAML subset parser:
-    // - top-level `schedules:`
-    // - list items under schedules with `- name: ...` and indented key/value pairs
-    // - `prompt: |` block scalars
-    let mut lines: Vec<&str> = input.lines().collect();
-    // Normalize Windows line endings if present.
-    for l in lines.iter_mut() {
-        if l.ends_with('\r') {
-            *l = l.trim_end_matches('\r');
-        }
+/// Columns a leading tab counts for; tabs are invalid YAML but common in hand-edited files.
+const TAB_WIDTH: usize = 4;
+
+/// Parse the subset of YAML `cron.yaml` uses:
+/// - a top-level `schedules:` list of mappings (any consistent indentation, spaces or tabs)
+/// - plain, single- and double-quoted scalars, with trailing `# comments` outside quotes
+/// - `|` literal and `>` folded block scalars (chomping indicators accepted)
+///
+/// Every problem is reported with its line number. An entry with a problem is skipped; the
+/// others still load, so one typo doesn't take every schedule down with it.
+fn parse_cron_yaml(input: &str) -> CronConfig {
+    let mut parser = CronYamlParser {
+        lines: input.lines().map(expand_indent).collect(),
+        pos: 0,
+        errors: Vec::new(),
+    };
+    let schedules = parser.parse();
+    CronConfig {
+        schedules,
+        errors: parser.errors,
     }
+}
 
-    let mut i = 0usize;
-    let mut in_schedules = false;
-    let mut schedules = Vec::new();
-
-    while i < lines.len() {
-        let raw = lines[i];
-        let line = raw.trim_end();
-        let trimmed = line.trim();
+struct CronYamlParser {
+    /// Lines with `\r` removed and leading tabs expanded to spaces.
+    lines: Vec<String>,
+    pos: usize,
+    errors: Vec<String>,
+}
 
-        i += 1;
+impl CronYamlParser {
+    fn error(&mut self, line: usize, msg: impl std::fmt::Display) {
+        self.errors.push(format!("line {line}: {msg}"));
+    }
 
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            continue;
-        }
+    /// Next line that is neither blank nor a comment, as (1-based number, indent, text without
+    /// the indent and trailing comment). Does not advance.
+    fn peek(&self) -> Option<(usize, usize, String)> {
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(self.pos)
+            .find(|(_, l)| !is_blank_or_comment(l))
+            .map(|(idx, l)| {
+                (
+                    idx + 1,
+                    count_indent(l),
+                    strip_comment(l.trim()).to_string(),
+                )
+            })
+    }
 
-        if !in_schedules {
-            if trimmed == "schedules:" {
-                in_schedules = true;
+    fn parse(&mut self) -> Vec<CronSchedule> {
+        // Skip to the top-level `schedules:` key; other top-level keys are ignored.
+        loop {
+            let Some((no, indent, text)) = self.peek() else {
+                return Vec::new();
+            };
+            self.pos = no;
+            if indent != 0 {
+                continue;
+            }
+            let Some(rest) = text.strip_prefix("schedules:") else {
+                continue;
+            };
+            match rest.trim() {
+                "" => break,
+                "[]" => return Vec::new(),
+                _ => {
+                    self.error(
+                        no,
+                        "expected a list of schedules on the lines below `schedules:`",
+                    );
+                    return Vec::new();
+                }
             }
-            continue;
         }
 
-        // Expect a list item starting with `-`.
-        let indent = count_indent(line);
-        if indent != 2 || !trimmed.starts_with('-') {
-            // tolerate comments / extra top-level keys.
-            continue;
+        let mut schedules = Vec::new();
+        let mut names: Vec<String> = Vec::new();
+        let mut item_indent = None;
+        while let Some((no, indent, text)) = self.peek() {
+            let is_item = text == "-" || text.starts_with("- ");
+            if indent == 0 && !is_item {
+                break; // next top-level key
+            }
+            let expected = *item_indent.get_or_insert(indent);
+            if !is_item || indent != expected {
+                self.error(no, format!("expected `- name: ...` at indent {expected}"));
+                self.pos = no;
+                continue;
+            }
+            let errors_before = self.errors.len();
+            let Some(schedule) = self.parse_item(no, indent, &text) else {
+                continue;
+            };
+            if names.contains(&schedule.name) {
+                self.error(no, format!("duplicate schedule name `{}`", schedule.name));
+            }
+            names.push(schedule.name.clone());
+            if self.errors.len() == errors_before {
+                schedules.push(schedule);
+            }
         }
+        schedules
+    }
 
-        // Parse the first line after `-`.
-        let after_dash = trimmed.trim_start_matches('-').trim_start();
+    /// Parse one list item whose `-` is on line `no` at `dash_indent`. Problems are recorded
+    /// in `errors`; `None` only when the item has no name at all.
+    fn parse_item(&mut self, no: usize, dash_indent: usize, text: &str) -> Option<CronSchedule> {
+        self.pos = no;
         let mut current = CronSchedule {
             name: String::new(),
             cron: String::new(),
             prompt: String::new(),
             enabled: true,
             notify: false,
+            max_turns: None,
+            max_budget_usd: None,
         };
+        let mut cron_line = no;
 
-        if !after_dash.is_empty() {
-            parse_schedule_kv(after_dash, &mut current, &mut i, &lines, 2)?;
+        let first = text[1..].trim_start();
+        if !first.is_empty() {
+            let first_indent = dash_indent + (text.len() - first.len());
+            self.parse_field(no, first_indent, first, &mut current);
         }
-
-        // Parse subsequent indented fields (indent 4).
-        while i < lines.len() {
-            let raw2 = lines[i];
-            let line2 = raw2.trim_end();
-            let trimmed2 = line2.trim();
-            if trimmed2.is_empty() || trimmed2.starts_with('#') {
-                i += 1;
+        // Following keys only need to agree with each other, which tolerates tab indentation
+        // that doesn't line up with the text after `- `.
+        let mut key_indent = None;
+        while let Some((line_no, indent, field)) = self.peek() {
+            if indent <= dash_indent {
+                break;
+            }
+            self.pos = line_no;
+            let expected = *key_indent.get_or_insert(indent);
+            if indent != expected {
+                self.error(
+                    line_no,
+                    format!("unexpected indentation ({indent} columns, expected {expected})"),
+                );
                 continue;
             }
-
-            let indent2 = count_indent(line2);
-            if indent2 <= 2 {
-                break; // next item or end
+            if self.parse_field(line_no, indent, &field, &mut current) {
+                cron_line = line_no;
             }
-            if indent2 != 4 {
-                i += 1;
-                continue;
+        }
+
+        if current.name.trim().is_empty() {
+            self.error(no, "schedule is missing `name`");
+            return None;
+        }
+        let name = current.name.clone();
+        if current.cron.trim().is_empty() {
+            self.error(no, format!("schedule `{name}` is missing `cron`"));
+        } else if let Err(e) = CronExpr::parse(&current.cron) {
+            let reason = match e {
+                Error::Config(reason) => reason,
+                other => other.to_string(),
+            };
+            self.error(cron_line, format!("schedule `{name}`: {reason}"));
+        }
+        if current.prompt.trim().is_empty() {
+            self.error(no, format!("schedule `{name}` is missing `prompt`"));
+        } else if current.prompt.len() > MAX_PROMPT_LENGTH {
+            self.error(
+                no,
+                format!(
+                    "schedule `{name}` prompt too long: {} chars",
+                    current.prompt.len()
+                ),
+            );
+        }
+        Some(current)
+    }
+
+    /// Apply one `key: value` line (already consumed) to `current`. Returns whether it was
+    /// the `cron` key, so expression errors can point at it.
+    fn parse_field(
+        &mut self,
+        no: usize,
+        key_indent: usize,
+        field: &str,
+        current: &mut CronSchedule,
+    ) -> bool {
+        let Some((key, value)) = field.split_once(':') else {
+            self.error(no, format!("expected `key: value`, got `{field}`"));
+            return false;
+        };
+        let key = key.trim();
+        let value = value.trim();
+
+        if key == "prompt" && value.starts_with(['|', '>']) {
+            if !is_block_indicator(value) {
+                self.error(no, format!("unsupported block scalar header `{value}`"));
+                return false;
             }
+            current.prompt = self.read_block(no, key_indent, value.starts_with('>'));
+            return false;
+        }
 
-            // `key: value` at indent 4
-            let kv = trimmed2;
-            i += 1;
-            parse_schedule_kv(kv, &mut current, &mut i, &lines, indent2)?;
+        let value = match parse_scalar(value) {
+            Ok(v) => v,
+            Err(e) => {
+                self.error(no, format!("`{key}`: {e}"));
+                return false;
+            }
+        };
+        let is_null = matches!(value.as_str(), "" | "~" | "null");
+        match key {
+            "name" => current.name = value,
+            "cron" => {
+                current.cron = value;
+                return true;
+            }
+            "prompt" => current.prompt = value,
+            "enabled" | "notify" => match parse_bool(&value) {
+                Some(b) if key == "enabled" => current.enabled = b,
+                Some(b) => current.notify = b,
+                None => self.error(no, format!("`{key}` must be true or false, got `{value}`")),
+            },
+            "max_turns" if is_null => current.max_turns = None,
+            "max_turns" => match value.parse::<u32>() {
+                Ok(n) if n > 0 => current.max_turns = Some(n),
+                _ => self.error(
+                    no,
+                    format!("`max_turns` must be a positive integer, got `{value}`"),
+                ),
+            },
+            "max_budget_usd" if is_null => current.max_budget_usd = None,
+            "max_budget_usd" => match value.parse::<f64>() {
+                Ok(b) if b > 0.0 && b.is_finite() => current.max_budget_usd = Some(b),
+                _ => self.error(
+                    no,
+                    format!("`max_budget_usd` must be a positive number, got `{value}`"),
+                ),
+            },
+            _ => self.error(no, format!("unknown key `{key}`")),
         }
+        false
+    }
 
-        validate_schedule(&current)?;
-        schedules.push(current);
+    /// Read the block scalar following a `key: |` / `key: >` line. Content is every following
+    /// line indented past the key (plus blank lines), cut at the first content line's indent.
+    fn read_block(&mut self, header_no: usize, key_indent: usize, folded: bool) -> String {
+        let mut block: Vec<String> = Vec::new();
+        let mut block_indent = None;
+        while self.pos < self.lines.len() {
+            let line = &self.lines[self.pos];
+            if line.trim().is_empty() {
+                block.push(String::new());
+                self.pos += 1;
+                continue;
+            }
+            let indent = count_indent(line);
+            if indent <= key_indent {
+                break;
+            }
+            let cut = *block_indent.get_or_insert(indent);
+            if indent < cut {
+                let msg = format!("block line is indented less than the first ({cut} columns)");
+                self.error(self.pos + 1, msg);
+                break;
+            }
+            block.push(line[cut..].to_string());
+            self.pos += 1;
+        }
+        // Trailing blank lines belong to whatever follows.
+        while block.last().is_some_and(|l| l.is_empty()) {
+            block.pop();
+        }
+        if block.is_empty() {
+            self.error(header_no, "block scalar has no content");
+        }
+        if folded {
+            fold_lines(&block)
+        } else {
+            block.join("\n")
+        }
     }
+}
 
-    Ok(CronConfig { schedules })
+/// `|` or `>`, optionally followed by a chomping indicator (`-`/`+`). Trailing newlines are
+/// always trimmed from prompts, so chomping makes no difference here.
+pub(crate) fn is_block_indicator(value: &str) -> bool {
+    matches!(value, "|" | "|-" | "|+" | ">" | ">-" | ">+")
 }
 
-fn validate_schedule(s: &CronSchedule) -> Result<()> {
-    if s.name.trim().is_empty() {
-        return Err(Error::Config("cron schedule missing name".to_string()));
-    }
-    if s.cron.trim().is_empty() {
-        return Err(Error::Config(format!(
-            "cron schedule {} missing cron",
-            s.name
-        )));
-    }
-    if s.prompt.trim().is_empty() {
-        return Err(Error::Config(format!(
-            "cron schedule {} missing prompt",
-            s.name
-        )));
-    }
-    if s.prompt.len() > MAX_PROMPT_LENGTH {
-        return Err(Error::Config(format!(
-            "cron schedule {} prompt too long: {} chars",
-            s.name,
-            s.prompt.len()
-        )));
+/// YAML folding: single line breaks between plain lines become spaces, blank lines become
+/// newlines, and more-indented lines keep their breaks.
+pub(crate) fn fold_lines(lines: &[String]) -> String {
+    let mut out = String::new();
+    let mut blanks = 0;
+    let mut prev_indented: Option<bool> = None;
+    for line in lines {
+        if line.is_empty() {
+            blanks += 1;
+            continue;
+        }
+        let indented = line.starts_with(' ');
+        match prev_indented {
+            None => out.push_str(&"\n".repeat(blanks)),
+            Some(prev) if !prev && !indented => {
+                if blanks == 0 {
+                    out.push(' ');
+                } else {
+                    out.push_str(&"\n".repeat(blanks));
+                }
+            }
+            Some(_) => out.push_str(&"\n".repeat(blanks + 1)),
+        }
+        out.push_str(line);
+        blanks = 0;
+        prev_indented = Some(indented);
     }
-    Ok(())
+    out
 }
 
-fn parse_schedule_kv(
-    kv: &str,
-    current: &mut CronSchedule,
-    i: &mut usize,
-    lines: &[&str],
-    indent: usize,
-) -> Result<()> {
-    let Some((k, vraw)) = kv.split_once(':') else {
-        return Ok(());
-    };
-    let key = k.trim();
-    let value = vraw.trim();
-
-    match key {
-        "name" => current.name = strip_quotes(value).to_string(),
-        "cron" => current.cron = strip_quotes(value).to_string(),
-        "enabled" => current.enabled = parse_bool(value).unwrap_or(true),
-        "notify" => current.notify = parse_bool(value).unwrap_or(false),
-        "prompt" => {
-            if value == "|" {
-                // Block scalar. Capture until indent <= current indent.
-                let mut block = Vec::new();
-                // Determine indentation of block content from the first non-empty line.
-                let mut block_indent: Option<usize> = None;
-
-                while *i < lines.len() {
-                    let raw = lines[*i];
-                    let line = raw.trim_end_matches('\r');
-                    let trimmed = line.trim_end();
-                    let trimmed_ws = trimmed.trim();
-
-                    let ind = count_indent(trimmed);
-                    if !trimmed_ws.is_empty() {
-                        if ind <= indent {
-                            break;
-                        }
-                        if block_indent.is_none() {
-                            block_indent = Some(ind);
-                        }
-                    } else {
-                        // Empty line inside block is allowed.
-                        if block_indent.is_none() {
-                            // keep waiting for first content line
-                        }
-                    }
-
-                    *i += 1;
-
-                    // Inside the block, keep raw text (including leading spaces beyond the block indent).
-                    let cut = block_indent.unwrap_or(indent + 2);
-                    let out_line = if trimmed.len() >= cut {
-                        &trimmed[cut..]
-                    } else {
-                        ""
-                    };
-                    block.push(out_line.to_string());
+/// Unquote a flow scalar. Plain scalars are returned as-is (comments are already stripped).
+pub(crate) fn parse_scalar(value: &str) -> std::result::Result<String, String> {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some('"') => {
+            let mut out = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => return expect_end(chars.as_str(), out),
+                    '\\' => match chars.next() {
+                        Some('n') => out.push('\n'),
+                        Some('t') => out.push('\t'),
+                        Some(c @ ('"' | '\\' | '/')) => out.push(c),
+                        Some(c) => return Err(format!("unsupported escape `\\{c}`")),
+                        None => break,
+                    },
+                    c => out.push(c),
                 }
-
-                // YAML `|` preserves final newline, but TS prompt usage doesn't care.
-                current.prompt = block.join("\n").trim_end_matches('\n').to_string();
-            } else {
-                current.prompt = strip_quotes(value).to_string();
             }
+            Err("unterminated double-quoted string".to_string())
         }
-        _ => {}
+        Some('\'') => {
+            let mut out = String::new();
+            while let Some(c) = chars.next() {
+                if c != '\'' {
+                    out.push(c);
+                } else if chars.as_str().starts_with('\'') {
+                    chars.next();
+                    out.push('\'');
+                } else {
+                    return expect_end(chars.as_str(), out);
+                }
+            }
+            Err("unterminated single-quoted string".to_string())
+        }
+        _ => Ok(value.to_string()),
     }
+}
 
-    Ok(())
+fn expect_end(rest: &str, out: String) -> std::result::Result<String, String> {
+    if rest.trim().is_empty() {
+        Ok(out)
+    } else {
+        Err(format!(
+            "unexpected text after closing quote: `{}`",
+            rest.trim()
+        ))
+    }
+}
+
+/// Cut a `# comment` that starts the text or follows whitespace, outside quotes.
+pub(crate) fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut prev = ' ';
+    let mut escaped = false;
+    for (idx, c) in text.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && prev.is_whitespace() => return text[..idx].trim_end(),
+            None => {}
+        }
+        prev = c;
+    }
+    text
+}
+
+pub(crate) fn is_blank_or_comment(line: &str) -> bool {
+    let t = line.trim();
+    t.is_empty() || t.starts_with('#')
+}
+
+/// Drop a trailing `\r` and expand leading tabs to [`TAB_WIDTH`] spaces.
+pub(crate) fn expand_indent(line: &str) -> String {
+    let line = line.trim_end_matches('\r');
+    let body = line.trim_start_matches([' ', '\t']);
+    let lead = &line[..line.len() - body.len()];
+    let width: usize = lead
+        .chars()
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum();
+    format!("{}{body}", " ".repeat(width))
 }
 
 fn parse_bool(s: &str) -> Option<bool> {
@@ -772,18 +1662,7 @@ fn parse_bool(s: &str) -> Option<bool> {
     }
 }
 
-fn strip_quotes(s: &str) -> &str {
-    let t = s.trim();
-    if t.len() >= 2
-        && ((t.starts_with('\"') && t.ends_with('\"'))
-            || (t.starts_with('\'') && t.ends_with('\'')))
-    {
-        return &t[1..t.len() - 1];
-    }
-    t
-}
-
-fn count_indent(line: &str) -> usize {
+pub(crate) fn count_indent(line: &str) -> usize {
     line.chars().take_while(|c| *c == ' ').count()
 }
 
@@ -993,6 +1872,38 @@ mod tests {
     use super::*;
     use chrono::TimeZone;
 
+    #[test]
+    fn long_notifications_keep_the_last_segment_whole() {
+        let segments = vec![
+            "Checking the logs.".to_string(),
+            "x".repeat(40),
+            "All green: 3 jobs ran.".to_string(),
+        ];
+        let out = TurnOutput {
+            text: segments.join("\n\n---\n\n"),
+            segments,
+            waiting_for_user: false,
+            usage: None,
+            session: None,
+            budget_exceeded: false,
+            tools: Vec::new(),
+            context_compacted: false,
+            context_pressure: None,
+            suspended: None,
+            provider_error: None,
+        };
+        assert_eq!(notify_text(&out, 1000), out.text);
+        assert_eq!(notify_text(&out, 50), "All green: 3 jobs ran.");
+        // A final segment that alone is too long is cut on a char boundary.
+        assert_eq!(notify_text(&out, 10), "All green:");
+        let accented = TurnOutput {
+            text: "é".repeat(10),
+            segments: vec!["é".repeat(10)],
+            ..out
+        };
+        assert_eq!(notify_text(&accented, 5), "éé");
+    }
+
     #[test]
     fn cron_expr_parses_and_matches_basic() {
         let expr = CronExpr::parse("0 * * * *").unwrap();
@@ -1023,7 +1934,8 @@ schedules:
     enabled: true
     notify: false
 "#;
-        let cfg = parse_cron_yaml(yaml).unwrap();
+        let cfg = parse_cron_yaml(yaml);
+        assert!(cfg.errors.is_empty(), "{:?}", cfg.errors);
         assert_eq!(cfg.schedules.len(), 1);
         let s = &cfg.schedules[0];
         assert_eq!(s.name, "heartbeat");
@@ -1033,4 +1945,242 @@ schedules:
         assert!(s.enabled);
         assert!(!s.notify);
     }
+
+    #[test]
+    fn cron_yaml_parses_run_limits() {
+        let yaml = r#"
+schedules:
+  - name: nightly
+    cron: "0 3 * * *"
+    prompt: "tidy up"
+    max_turns: 10
+    max_budget_usd: 0.5
+  - name: plain
+    cron: "0 4 * * *"
+    prompt: "hello"
+"#;
+        let cfg = parse_cron_yaml(yaml);
+        assert!(cfg.errors.is_empty(), "{:?}", cfg.errors);
+        assert_eq!(cfg.schedules[0].max_turns, Some(10));
+        assert_eq!(cfg.schedules[0].max_budget_usd, Some(0.5));
+        assert_eq!(cfg.schedules[1].max_turns, None);
+        assert_eq!(cfg.schedules[1].max_budget_usd, None);
+    }
+
+    #[test]
+    fn cron_yaml_accepts_real_world_layouts() {
+        // (description, file, expected schedule names)
+        let corpus: &[(&str, &str, &[&str])] = &[
+            (
+                "tab indentation",
+                "schedules:\n\t- name: tabs\n\t\tcron: \"0 * * * *\"\n\t\tprompt: hi\n",
+                &["tabs"],
+            ),
+            (
+                "4-space list items",
+                "schedules:\n    - name: a\n      cron: \"0 * * * *\"\n      prompt: one\n    - name: b\n      cron: \"5 * * * *\"\n      prompt: two\n",
+                &["a", "b"],
+            ),
+            (
+                "items flush with the key",
+                "schedules:\n- name: flush\n  cron: \"0 * * * *\"\n  prompt: hi\nother: ignored\n",
+                &["flush"],
+            ),
+            (
+                "bare dash",
+                "schedules:\n  -\n    name: bare\n    cron: \"0 * * * *\"\n    prompt: hi\n",
+                &["bare"],
+            ),
+            ("empty list", "schedules: []\n", &[]),
+        ];
+        for (what, yaml, names) in corpus {
+            let cfg = parse_cron_yaml(yaml);
+            assert!(cfg.errors.is_empty(), "{what}: {:?}", cfg.errors);
+            let got: Vec<_> = cfg.schedules.iter().map(|s| s.name.as_str()).collect();
+            assert_eq!(got, *names, "{what}");
+        }
+    }
+
+    #[test]
+    fn cron_yaml_folds_scalars_and_strips_comments() {
+        let yaml = r#"
+schedules:
+  - name: "digest"   # daily
+    cron: '0 9 * * 1-5'  # weekdays
+    enabled: true  # prod only
+    notify: yes
+    max_turns: 4 # cap
+    prompt: >
+      Summarize yesterday's
+      commits.
+
+      Keep it # short.
+        - bullets
+        - only
+  - name: literal
+    cron: "0 10 * * *"
+    prompt: |-
+      first
+        indented # kept
+    enabled: "false"
+"#;
+        let cfg = parse_cron_yaml(yaml);
+        assert!(cfg.errors.is_empty(), "{:?}", cfg.errors);
+        let digest = &cfg.schedules[0];
+        assert_eq!(digest.name, "digest");
+        assert_eq!(digest.cron, "0 9 * * 1-5");
+        assert!(digest.enabled && digest.notify);
+        assert_eq!(digest.max_turns, Some(4));
+        assert_eq!(
+            digest.prompt,
+            "Summarize yesterday's commits.\nKeep it # short.\n  - bullets\n  - only"
+        );
+        let literal = &cfg.schedules[1];
+        assert_eq!(literal.prompt, "first\n  indented # kept");
+        assert!(!literal.enabled);
+    }
+
+    #[test]
+    fn cron_yaml_reports_every_error_and_skips_only_bad_entries() {
+        let yaml = r#"schedules:
+  - name: good
+    cron: "0 * * * *"
+    prompt: fine
+  - name: broken
+    cron: "61 * * * *"
+    prompt: "unterminated
+    enabeld: true
+  - name: good
+    cron: "0 * * * *"
+    prompt: dup
+      stray: indent
+"#;
+        let cfg = parse_cron_yaml(yaml);
+        let err = cfg.errors.join("\n");
+        for expected in [
+            "line 6: schedule `broken`: invalid range: 61",
+            "line 7: `prompt`: unterminated double-quoted string",
+            "line 8: unknown key `enabeld`",
+            "line 12: unexpected indentation",
+            "line 9: duplicate schedule name `good`",
+        ] {
+            assert!(err.contains(expected), "missing {expected:?} in:\n{err}");
+        }
+        assert_eq!(cfg.errors.len(), 6, "{err}");
+        let loaded: Vec<_> = cfg.schedules.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            loaded,
+            ["good"],
+            "only the entries with problems are skipped"
+        );
+    }
+
+    #[test]
+    fn pending_jobs_roundtrip_through_file() {
+        let path = PathBuf::from(format!("/tmp/ctb-cron-queue-{}.json", std::process::id()));
+        let schedules = vec![CronSchedule {
+            name: "daily".to_string(),
+            cron: "0 9 * * *".to_string(),
+            prompt: "summarize".to_string(),
+            enabled: true,
+            notify: false,
+            max_turns: Some(5),
+            max_budget_usd: None,
+        }];
+        save_pending_jobs(&path, &schedules).unwrap();
+        let loaded = load_pending_jobs(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded, schedules);
+    }
+
+    #[test]
+    fn test_schedule_lookup_ignores_case_and_suggests_close_names() {
+        let schedule = |name: &str| CronSchedule {
+            name: name.to_string(),
+            cron: "0 6 * * *".to_string(),
+            prompt: "p".to_string(),
+            enabled: true,
+            notify: false,
+            max_turns: None,
+            max_budget_usd: None,
+        };
+        let schedules = vec![schedule("Morning Digest"), schedule("backup-check")];
+
+        assert_eq!(
+            find_schedule(&schedules, " morning digest ").unwrap().name,
+            "Morning Digest"
+        );
+        let err = find_schedule(&schedules, "mornin digest").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no schedule named \"mornin digest\" (did you mean \"Morning Digest\"?)"
+        );
+        let err = find_schedule(&schedules, "weekly").unwrap_err();
+        assert_eq!(err.to_string(), "no schedule named \"weekly\"");
+    }
+
+    #[test]
+    fn reminder_times_parse_durations_and_clock_times() {
+        let now = Local.with_ymd_and_hms(2026, 3, 10, 17, 0, 0).unwrap();
+        let at = |spec| parse_reminder_time(spec, now);
+
+        assert_eq!(at("40m").unwrap(), now + chrono::Duration::minutes(40));
+        assert_eq!(at("2h30m").unwrap(), now + chrono::Duration::minutes(150));
+        assert_eq!(at("1d").unwrap(), now + chrono::Duration::days(1));
+        assert_eq!(at("90S").unwrap(), now + chrono::Duration::seconds(90));
+
+        // Later today, or tomorrow once the time has passed.
+        let later = at("18:30").unwrap();
+        assert_eq!((later.day(), later.hour(), later.minute()), (10, 18, 30));
+        let tomorrow = at("9:05").unwrap();
+        assert_eq!(
+            (tomorrow.day(), tomorrow.hour(), tomorrow.minute()),
+            (11, 9, 5)
+        );
+        assert_eq!(at("17:00").unwrap().day(), 11);
+
+        for bad in [
+            "", "0m", "40", "m", "5x", "2h30", "25:00", "12:60", "ab:cd", "31d",
+        ] {
+            assert!(at(bad).is_err(), "{bad:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn reminders_roundtrip_through_file_in_fire_order() {
+        let path = PathBuf::from(format!("/tmp/ctb-reminders-{}.json", std::process::id()));
+        let job = |id, fire_at, ask| OneShotJob {
+            id,
+            chat_id: -100,
+            thread_id: Some(7),
+            fire_at,
+            text: format!("check deploy {id}"),
+            ask,
+            role: Role::ReadOnly,
+        };
+        let (late, early) = (job(1, 2_000, false), job(2, 1_000, true));
+        let jobs = sorted_reminders([&late, &early].into_iter());
+        assert_eq!(jobs[0].id, 2);
+        save_reminders(&path, &jobs).unwrap();
+        let loaded = load_reminders(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded, vec![early.clone(), late]);
+        assert_eq!(loaded[0].target(), ChatTarget::new(ChatId(-100), Some(7)));
+
+        // Reminders saved before roles were recorded run read-only.
+        let legacy =
+            r#"[{"id":3,"chat_id":1,"thread_id":null,"fire_at":5,"text":"ask: x","ask":true}]"#;
+        fs::write(&path, legacy).unwrap();
+        let loaded = load_reminders(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded[0].role, Role::ReadOnly);
+    }
+
+    #[test]
+    fn edit_distance_counts_single_char_edits() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("backup", "backup"), 0);
+        assert_eq!(edit_distance("bakcup", "backup"), 2);
+    }
 }
@@ -1,33 +1,70 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use chrono::Local;
+use async_trait::async_trait;
+use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
-use tokio::time::{interval, Duration, Instant};
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
 
 use crate::{
-    config::Config,
-    errors::Error,
-    formatting::{escape_html, format_tool_status},
-    messaging::{port::MessagingPort, types::InlineKeyboard},
+    chat_settings::ChatSettings,
+    config::{Config, IdleExpiry, MAX_THINKING_TOKENS},
+    domain::{ChatTarget, MessageId, UserId},
+    errors::{CancelReason, Error},
+    formatting::format_turn_summary,
+    grants::DirectoryGrants,
+    i18n::Messages,
+    messaging::port::MessagingPort,
+    metrics::{MetricsSink, NoopMetrics, Peak, TurnMetrics},
     model::{
-        client::ModelClient,
-        types::{ModelEvent, ProviderKind, RunRequest, RunResult, SessionRef, TokenUsage},
+        client::{EventSink, ModelClient},
+        types::{
+            is_valid_model_name, ModelCapabilities, ModelEvent, PermissionMode, ProviderKind,
+            RunId, RunRequest, RunResult, SessionRef, TokenUsage,
+        },
     },
-    security::{check_command_safety, PathPolicy},
-    streaming::{StatusType, StreamingState},
+    pipeline::{ContextPressure, EventPipeline, ReplayGuard, SuspendedStream},
+    plan_mode::PLAN_ONLY_INSTRUCTION,
+    prompt_profiles::{is_valid_profile_name, load_profile},
+    security::{role_of, PathPolicy, Role},
+    security_events::{RunUser, SecurityMonitor},
+    session_history::{derive_title, sanitize_title, SessionHistory},
+    shutdown::Shutdown,
+    storage::{
+        JsonFileStore, StateStore, StateStoreExt, SESSION_HISTORY_NAMESPACE, SESSION_NAMESPACE,
+    },
+    theme::StatusTheme,
+    transcript::{Transcript, TranscriptTurn},
+    undo::{UndoJournal, UndoRecorder},
+    usage::pricing::ModelPricing,
     utils::iso_timestamp_utc,
     Result,
 };
 
+pub use crate::pipeline::TurnOutput;
+
+/// A plan-only turn awaiting review.
+#[derive(Debug)]
+struct PendingPlan {
+    session_id: String,
+    /// Who asked for it (`None` for scheduled runs).
+    requester: Option<i64>,
+}
+
 #[derive(Debug, Default)]
 struct SessionState {
     session: Option<SessionRef>,
-    is_running: bool,
-    stop_requested: bool,
-    interrupted_by_new_message: bool,
+    /// In-flight run per chat, so `/stop` cancels only that chat's process.
+    active_runs: HashMap<ChatTarget, RunId>,
+    /// Set per chat by `stop()`; a run of that chat that starts before it is cleared is
+    /// cancelled for this reason.
+    stop_requested: HashMap<ChatTarget, CancelReason>,
     last_message: Option<String>,
+    /// Most recent text prompt per chat with its message id, so edits can re-run it.
+    last_prompts: HashMap<ChatTarget, (MessageId, String)>,
+    /// Plan-only turn per chat, until it is executed, discarded or superseded.
+    pending_plans: HashMap<ChatTarget, PendingPlan>,
 
     // Token usage parity with TS (cumulative across turns).
     session_start_time: Option<String>,
@@ -37,11 +74,38 @@ struct SessionState {
     total_cache_create_tokens: u64,
     total_queries: u64,
     last_usage: Option<TokenUsage>,
+    /// End of the last completed turn (RFC 3339), for idle expiry; persisted in the session file.
+    last_activity: Option<String>,
 
     // Context-limit tracking parity with TS (used by startup auto-load + future warnings).
     context_limit_warned: bool,
     recently_restored: bool,
     messages_since_restore: u64,
+    /// Cumulative tokens at the last CLI compaction; context size is counted from here.
+    context_base_tokens: u64,
+    compactions: u32,
+
+    // Sticky `/model` and `/think` overrides (persisted in the session file).
+    model: Option<String>,
+    thinking_tokens: Option<u32>,
+
+    /// Completed turns for `/export`.
+    transcript: Transcript,
+    /// Tool uses and messages already shown, so a resumed run's replay is skipped.
+    replay_guard: Arc<std::sync::Mutex<ReplayGuard>>,
+}
+
+impl SessionState {
+    fn context_tokens(&self) -> u64 {
+        (self.total_input_tokens + self.total_output_tokens)
+            .saturating_sub(self.context_base_tokens)
+    }
+
+    fn idle_for(&self) -> Option<Duration> {
+        self.session.as_ref()?;
+        let last = DateTime::parse_from_rfc3339(self.last_activity.as_deref()?).ok()?;
+        (Utc::now() - last.with_timezone(&Utc)).to_std().ok()
+    }
 }
 
 /// High-level session manager (provider-agnostic).
@@ -53,14 +117,49 @@ pub struct ClaudeSession {
     cfg: Arc<Config>,
     model: Arc<dyn ModelClient>,
     state: Mutex<SessionState>,
+    shutdown: Shutdown,
+    metrics: Arc<dyn MetricsSink>,
+    grants: Option<Arc<DirectoryGrants>>,
+    chat_settings: Option<Arc<ChatSettings>>,
+    security: Option<Arc<SecurityMonitor>>,
+    undo: Option<Arc<UndoJournal>>,
+    /// Where the `/resume` pointer is saved, under `store_key` (empty for the default session,
+    /// the topic key for forum topics). The `/sessions` history is kept per chat target instead,
+    /// since non-topic chats share the default session.
+    store: Arc<dyn StateStore>,
+    store_key: String,
+    /// Serializes read-modify-write of the history (chats sharing this session run concurrently).
+    history_lock: std::sync::Mutex<()>,
 }
 
-#[derive(Clone, Debug)]
-pub struct TurnOutput {
-    pub text: String,
-    pub waiting_for_user: bool,
-    pub usage: Option<TokenUsage>,
-    pub session: Option<SessionRef>,
+/// Per-query guardrails. Defaults come from `Config`; cron schedules may override them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RunLimits {
+    pub max_turns: Option<u32>,
+    pub max_budget_usd: Option<f64>,
+    /// Role of the user the run is for (bot-initiated runs have full access).
+    pub role: Role,
+    /// Plan-only turn: CLI `plan` permission mode, and the turn ends with review buttons.
+    pub plan_only: bool,
+}
+
+impl RunLimits {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            max_turns: cfg.max_turns,
+            max_budget_usd: cfg.max_budget_usd,
+            role: Role::Full,
+            plan_only: false,
+        }
+    }
+
+    pub fn with_role(self, role: Role) -> Self {
+        Self { role, ..self }
+    }
+
+    pub fn with_plan_only(self, plan_only: bool) -> Self {
+        Self { plan_only, ..self }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -76,68 +175,178 @@ pub struct SessionStats {
     pub total_cache_create_tokens: u64,
     pub total_queries: u64,
     pub last_usage: Option<TokenUsage>,
+    /// Times the CLI compacted the context this session.
+    pub compactions: u32,
+    /// Time since the last completed turn, if the session has one.
+    pub idle_for: Option<Duration>,
+
+    pub model: Option<String>,
+    /// `/think` override; `None` = keyword detection / `default_thinking_tokens`.
+    pub thinking_tokens: Option<u32>,
 }
 
 impl ClaudeSession {
     pub fn new(cfg: Arc<Config>, model: Arc<dyn ModelClient>) -> Self {
+        let store = Arc::new(JsonFileStore::from_config(&cfg));
         Self {
             cfg,
             model,
             state: Mutex::new(SessionState::default()),
+            shutdown: Shutdown::new(),
+            metrics: Arc::new(NoopMetrics),
+            grants: None,
+            chat_settings: None,
+            security: None,
+            undo: None,
+            store,
+            store_key: String::new(),
+            history_lock: std::sync::Mutex::new(()),
         }
     }
 
-    pub async fn is_active(&self) -> bool {
-        self.state.lock().await.session.is_some()
+    /// Persist the session through `store` (`STATE_BACKEND`) instead of `SESSION_FILE`.
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = store;
+        self
     }
 
-    pub async fn is_running(&self) -> bool {
-        self.state.lock().await.is_running
+    /// The state store sessions persist through, shared with other startup/shutdown markers.
+    pub fn store(&self) -> Arc<dyn StateStore> {
+        self.store.clone()
     }
 
-    pub async fn mark_interrupt(&self) {
-        let mut st = self.state.lock().await;
-        st.interrupted_by_new_message = true;
+    /// Observe `shutdown` so in-flight turns end with a shutdown notice instead of hanging.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
     }
 
-    /// Clear the stop flag without consuming the interrupt marker.
-    ///
-    /// Parity with TS `clearStopRequested()` used after `!` interrupts so the new
-    /// message can proceed, while still suppressing the "Query stopped" message.
-    pub async fn clear_stop_requested(&self) {
-        let mut st = self.state.lock().await;
-        st.stop_requested = false;
+    /// Merge per-chat `/grant` directories into `--add-dir` and the tool path checks.
+    pub fn with_grants(mut self, grants: Arc<DirectoryGrants>) -> Self {
+        self.grants = Some(grants);
+        self
     }
 
-    pub async fn consume_interrupt_flag(&self) -> bool {
-        let mut st = self.state.lock().await;
-        let was = st.interrupted_by_new_message;
-        st.interrupted_by_new_message = false;
-        if was {
-            st.stop_requested = false;
+    /// Apply per-chat `/settings` overrides to the turns of each chat.
+    pub fn with_chat_settings(mut self, settings: Arc<ChatSettings>) -> Self {
+        self.chat_settings = Some(settings);
+        self
+    }
+
+    /// Report safety blocks of every turn through `monitor`.
+    pub fn with_security_monitor(mut self, monitor: Arc<SecurityMonitor>) -> Self {
+        self.security = Some(monitor);
+        self
+    }
+
+    /// Where this session's safety blocks are reported (`/status` reads the counts).
+    pub fn security_monitor(&self) -> Option<Arc<SecurityMonitor>> {
+        self.security.clone()
+    }
+
+    /// Snapshot the files each turn writes into `journal` (`/undo`).
+    pub fn with_undo_journal(mut self, journal: Arc<UndoJournal>) -> Self {
+        self.undo = Some(journal);
+        self
+    }
+
+    /// Where this session's turns record their file snapshots.
+    pub fn undo_journal(&self) -> Option<Arc<UndoJournal>> {
+        self.undo.clone()
+    }
+
+    /// The config a turn in `target` runs with (see
+    /// [`EffectiveConfig`](crate::chat_settings::EffectiveConfig)).
+    fn chat_config(&self, target: ChatTarget) -> Arc<Config> {
+        match &self.chat_settings {
+            Some(settings) => Arc::new(
+                settings
+                    .effective(&self.cfg, target.chat_id.0)
+                    .into_config(),
+            ),
+            None => self.cfg.clone(),
+        }
+    }
+
+    /// `cfg.allowed_paths` plus the chat's active grants, read fresh for each turn.
+    fn allowed_dirs(&self, target: ChatTarget) -> Vec<std::path::PathBuf> {
+        let mut dirs = self.cfg.allowed_paths.clone();
+        if let Some(grants) = &self.grants {
+            dirs.extend(grants.dirs(target.chat_id.0));
+        }
+        dirs
+    }
+
+    /// Path checks for `target`: the configured paths plus its grants.
+    pub fn path_policy(&self, target: ChatTarget) -> PathPolicy {
+        PathPolicy {
+            allowed_paths: self.allowed_dirs(target),
+            ..PathPolicy::from_config(&self.cfg)
         }
-        was
     }
 
-    pub async fn stop(&self) -> Result<bool> {
+    /// Process-wide sink for pipeline timings and Telegram call counts (`/perf`).
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// What the model behind this session can do (e.g. read images).
+    pub fn model_capabilities(&self) -> ModelCapabilities {
+        self.model.capabilities()
+    }
+
+    pub async fn is_active(&self) -> bool {
+        self.state.lock().await.session.is_some()
+    }
+
+    pub async fn is_running(&self) -> bool {
+        !self.state.lock().await.active_runs.is_empty()
+    }
+
+    /// Clear `target`'s stop flag so its next message can run.
+    ///
+    /// Parity with TS `clearStopRequested()` used after `!` interrupts; the interrupted run
+    /// still ends with `Error::Cancelled(CancelReason::Interrupt)`.
+    pub async fn clear_stop_requested(&self, target: ChatTarget) {
+        let mut st = self.state.lock().await;
+        st.stop_requested.remove(&target);
+    }
+
+    /// Cancel the run in flight for `target` (other chats sharing this session keep running).
+    pub async fn stop(&self, target: ChatTarget, reason: CancelReason) -> Result<bool> {
         let mut st = self.state.lock().await;
-        if !st.is_running {
+        let Some(run_id) = st.active_runs.get(&target).copied() else {
             return Ok(false);
-        }
-        st.stop_requested = true;
+        };
+        st.stop_requested.insert(target, reason);
         drop(st);
 
-        self.model.cancel().await?;
+        self.model.cancel(run_id, reason).await?;
         Ok(true)
     }
 
+    /// Drop the conversation, cancelling the runs still in flight on it.
     pub async fn kill(&self) -> Result<()> {
+        let runs: Vec<RunId> = self
+            .state
+            .lock()
+            .await
+            .active_runs
+            .values()
+            .copied()
+            .collect();
+        for run_id in runs {
+            if let Err(e) = self.model.cancel(run_id, CancelReason::UserStop).await {
+                log_line!("[SESSION] Failed to cancel a run of the dropped session: {e}");
+            }
+        }
         let mut st = self.state.lock().await;
         st.session = None;
-        st.is_running = false;
-        st.stop_requested = false;
-        st.interrupted_by_new_message = false;
+        st.active_runs.clear();
+        st.stop_requested.clear();
         st.last_message = None;
+        st.pending_plans.clear();
         st.session_start_time = None;
         st.total_input_tokens = 0;
         st.total_output_tokens = 0;
@@ -145,9 +354,17 @@ impl ClaudeSession {
         st.total_cache_create_tokens = 0;
         st.total_queries = 0;
         st.last_usage = None;
+        st.last_activity = None;
         st.context_limit_warned = false;
         st.recently_restored = false;
         st.messages_since_restore = 0;
+        st.context_base_tokens = 0;
+        st.compactions = 0;
+        st.transcript.clear();
+        st.replay_guard
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
         Ok(())
     }
 
@@ -160,17 +377,85 @@ impl ClaudeSession {
         self.state.lock().await.last_message.clone()
     }
 
-    pub async fn resume_last(&self) -> Result<(bool, String)> {
-        let Some(data) = load_session_file(&self.cfg.session_file)? else {
-            return Ok((false, "No saved session found".to_string()));
+    /// Remember `message_id` as the latest prompt sent in `target`.
+    pub async fn record_prompt(&self, target: ChatTarget, message_id: MessageId, text: String) {
+        let mut st = self.state.lock().await;
+        st.last_prompts.insert(target, (message_id, text));
+    }
+
+    pub async fn last_prompt(&self, target: ChatTarget) -> Option<(MessageId, String)> {
+        self.state.lock().await.last_prompts.get(&target).cloned()
+    }
+
+    /// Replace the text of the latest prompt if `message_id` is still the latest one.
+    ///
+    /// Returns `false` for edits of older messages, which are ignored.
+    pub async fn update_last_prompt(
+        &self,
+        target: ChatTarget,
+        message_id: MessageId,
+        text: String,
+    ) -> bool {
+        let mut st = self.state.lock().await;
+        match st.last_prompts.get_mut(&target) {
+            Some((id, prompt)) if *id == message_id => {
+                *prompt = text;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `user_id` may execute or discard the plan awaiting review in `target`: only the
+    /// user who asked for it, or an owner. `true` when no plan is pending.
+    pub async fn may_review_plan(&self, target: ChatTarget, user_id: i64) -> bool {
+        let requester = match self.state.lock().await.pending_plans.get(&target) {
+            Some(plan) => plan.requester,
+            None => return true,
+        };
+        requester.is_none_or(|r| r == user_id)
+            || role_of(UserId(user_id), &self.cfg) == Some(Role::Owner)
+    }
+
+    /// Claim the plan awaiting review in `target`. `false` when there is none, or the session
+    /// has changed since it was made (e.g. `/new` or `/resume`), so there is nothing to execute.
+    pub async fn take_pending_plan(&self, target: ChatTarget) -> bool {
+        let mut st = self.state.lock().await;
+        let Some(plan) = st.pending_plans.remove(&target) else {
+            return false;
+        };
+        st.session.as_ref().is_some_and(|s| s.id == plan.session_id)
+    }
+
+    /// Whether `target` itself has a run in flight (other chats may share this session).
+    pub async fn is_running_for(&self, target: ChatTarget) -> bool {
+        self.state.lock().await.active_runs.contains_key(&target)
+    }
+
+    /// Resume the last saved session; the reply is plain text in `msgs`' language.
+    pub async fn resume_last(&self, msgs: &Messages) -> Result<(bool, String)> {
+        let Some(data) = self.load_session_data()? else {
+            return Ok((false, msgs.session_not_saved().to_string()));
         };
 
+        // The model override is sticky across directories and restarts.
+        {
+            let mut st = self.state.lock().await;
+            if let Some(model) = data.model.clone().filter(|m| is_valid_model_name(m)) {
+                st.model = Some(model);
+            }
+            if let Some(tokens) = data.thinking_tokens {
+                st.thinking_tokens = Some(tokens.min(MAX_THINKING_TOKENS));
+            }
+            st.last_activity = data.last_activity.clone();
+        }
+        if data.session_id.is_empty() {
+            return Ok((false, msgs.session_not_saved().to_string()));
+        }
+
         // Working dir check (parity with TS).
         if data.working_dir != self.cfg.claude_working_dir.to_string_lossy() {
-            return Ok((
-                false,
-                format!("Session was for different directory: {}", data.working_dir),
-            ));
+            return Ok((false, msgs.session_other_dir(&data.working_dir)));
         }
 
         let provider = match data.provider.as_str() {
@@ -185,19 +470,104 @@ impl ClaudeSession {
         });
         Ok((
             true,
-            format!(
-                "Resumed session `{}` (saved at {})",
-                short_id(&data.session_id),
-                data.saved_at
-            ),
+            msgs.session_resumed_at(&short_id(&data.session_id), &data.saved_at),
         ))
     }
 
+    /// Switch to saved session `session_id` from the `/sessions` history. The conversation in
+    /// memory is dropped as with `/new`; the sticky overrides stay. The reply is plain text in
+    /// `msgs`' language.
+    pub async fn resume_session(
+        &self,
+        target: ChatTarget,
+        session_id: &str,
+        msgs: &Messages,
+    ) -> Result<(bool, String)> {
+        let Some(entry) = self.history(target)?.get(session_id).cloned() else {
+            return Ok((false, msgs.session_no_longer_saved().to_string()));
+        };
+        if entry.working_dir != self.cfg.claude_working_dir.to_string_lossy() {
+            return Ok((false, msgs.session_other_dir(&entry.working_dir)));
+        }
+        if self.is_running().await {
+            return Ok((false, msgs.sessions_busy().to_string()));
+        }
+        self.kill().await?;
+        self.state.lock().await.session = Some(SessionRef {
+            provider: ProviderKind::ClaudeCli,
+            id: entry.session_id.clone(),
+        });
+        self.save_session(&entry.session_id).await?;
+        self.touch_history(target, &entry.session_id);
+        let title = entry.title.unwrap_or_else(|| short_id(&entry.session_id));
+        Ok((true, msgs.session_resumed(&title)))
+    }
+
+    /// Saved sessions of `target`, most recently used first.
+    pub fn history(&self, target: ChatTarget) -> Result<SessionHistory> {
+        Ok(self
+            .store
+            .get_json(SESSION_HISTORY_NAMESPACE, &target_key(target))?
+            .unwrap_or_default())
+    }
+
+    /// Title of the current session in `target`'s history, if it has one.
+    pub async fn title(&self, target: ChatTarget) -> Option<String> {
+        let id = self.state.lock().await.session.as_ref()?.id.clone();
+        self.history(target).ok()?.get(&id)?.title.clone()
+    }
+
+    /// Rename saved session `session_id` (`/title`, `/sessions` rename). Returns the title as
+    /// stored: one line, at most [`crate::session_history::TITLE_MAX_CHARS`] characters.
+    pub fn set_title(&self, target: ChatTarget, session_id: &str, title: &str) -> Result<String> {
+        let title =
+            sanitize_title(title).ok_or_else(|| Error::Config("the title is empty".to_string()))?;
+        let renamed = self.update_history(target, |history| {
+            history.set_title(session_id, title.clone())
+        })?;
+        if !renamed {
+            return Err(Error::Config("that session is no longer saved".to_string()));
+        }
+        Ok(title)
+    }
+
+    /// `/title <text>`: rename the current session, adding it to the history if a turn never
+    /// recorded it there (e.g. one resumed from before the history existed).
+    pub async fn set_current_title(&self, target: ChatTarget, title: &str) -> Result<String> {
+        let Some(id) = self
+            .state
+            .lock()
+            .await
+            .session
+            .as_ref()
+            .map(|s| s.id.clone())
+        else {
+            return Err(Error::Config(
+                "no active session; send a message first".to_string(),
+            ));
+        };
+        let working_dir = self.cfg.claude_working_dir.to_string_lossy();
+        self.update_history(target, |history| {
+            let missing = history.get(&id).is_none();
+            if missing {
+                history.touch(&id, &working_dir, &iso_timestamp_utc());
+            }
+            missing
+        })?;
+        self.set_title(target, &id, title)
+    }
+
+    /// Drop `session_id` from the `/sessions` history; `false` when it wasn't there. The
+    /// `/resume` pointer is left alone.
+    pub fn delete_saved_session(&self, target: ChatTarget, session_id: &str) -> Result<bool> {
+        self.update_history(target, |history| history.remove(session_id))
+    }
+
     pub async fn stats(&self) -> SessionStats {
         let st = self.state.lock().await;
         SessionStats {
             session: st.session.clone(),
-            is_running: st.is_running,
+            is_running: !st.active_runs.is_empty(),
             last_message: st.last_message.clone(),
             session_start_time: st.session_start_time.clone(),
             total_input_tokens: st.total_input_tokens,
@@ -206,7 +576,87 @@ impl ClaudeSession {
             total_cache_create_tokens: st.total_cache_create_tokens,
             total_queries: st.total_queries,
             last_usage: st.last_usage.clone(),
+            compactions: st.compactions,
+            idle_for: st.idle_for(),
+            model: st.model.clone(),
+            thinking_tokens: st.thinking_tokens,
+        }
+    }
+
+    /// Current `/model` override (`None` = provider default).
+    pub async fn model(&self) -> Option<String> {
+        self.state.lock().await.model.clone()
+    }
+
+    /// Set or clear the `/model` override used for subsequent runs.
+    ///
+    /// The selection is written to the session file right away so it survives restarts even
+    /// before the next run saves a session id.
+    pub async fn set_model(&self, model: Option<String>) -> Result<()> {
+        if let Some(m) = &model {
+            if !is_valid_model_name(m) {
+                return Err(Error::Config(format!("invalid model name: {m}")));
+            }
+        }
+        self.state.lock().await.model = model;
+        self.save_overrides().await
+    }
+
+    /// Current `/think` override (`None` = keyword detection).
+    pub async fn thinking_tokens(&self) -> Option<u32> {
+        self.state.lock().await.thinking_tokens
+    }
+
+    /// Set or clear the `/think` override (capped at [`MAX_THINKING_TOKENS`]), persisted like
+    /// the `/model` override.
+    pub async fn set_thinking_tokens(&self, tokens: Option<u32>) -> Result<()> {
+        self.state.lock().await.thinking_tokens = tokens.map(|t| t.min(MAX_THINKING_TOKENS));
+        self.save_overrides().await
+    }
+
+    /// `target`'s `/prompt` profile (`None` = safety prompt only).
+    pub fn prompt_profile(&self, target: ChatTarget) -> Option<String> {
+        self.chat_settings
+            .as_ref()?
+            .overrides(target.chat_id.0)
+            .prompt_profile
+            .filter(|n| is_valid_profile_name(n))
+    }
+
+    /// Select or clear `target`'s `/prompt` profile, stored with the chat's settings. The profile
+    /// is loaded once here so a missing or oversized file is reported right away.
+    pub async fn set_prompt_profile(&self, target: ChatTarget, name: Option<String>) -> Result<()> {
+        let Some(settings) = &self.chat_settings else {
+            return Err(Error::Config(
+                "per-chat settings are not enabled".to_string(),
+            ));
+        };
+        if let Some(name) = &name {
+            load_profile(&self.cfg, &self.path_policy(target), name)?;
         }
+        settings.set_prompt_profile(target.chat_id.0, name)
+    }
+
+    /// Write the sticky overrides, keeping the current (or on-disk) session id for `/resume`.
+    async fn save_overrides(&self) -> Result<()> {
+        let session_id = self
+            .state
+            .lock()
+            .await
+            .session
+            .as_ref()
+            .map(|s| s.id.clone());
+        let session_id = match session_id {
+            Some(id) => id,
+            // Keep whatever session id is on disk so `/resume` still works.
+            None => self
+                .load_session_data()
+                .ok()
+                .flatten()
+                .map(|d| d.session_id)
+                .unwrap_or_default(),
+        };
+        self.save_session(&session_id).await
     }
 
     /// Mark that the session context was just restored (via `oh-my-claude:load`).
@@ -219,12 +669,54 @@ impl ClaudeSession {
         st.context_limit_warned = false;
     }
 
-    /// Current cumulative context tokens (input + output) for this session.
+    /// Cumulative context tokens (input + output) since the session started or was last
+    /// compacted.
     pub async fn current_context_tokens(&self) -> u64 {
-        let st = self.state.lock().await;
-        st.total_input_tokens + st.total_output_tokens
+        self.state.lock().await.context_tokens()
+    }
+
+    /// The CLI summarized older context: restart context-size tracking from the compacting
+    /// turn's own usage, so the auto-save warning doesn't fire on totals that no longer reflect
+    /// the conversation.
+    async fn note_context_compacted(&self, turn_usage: Option<&TokenUsage>) {
+        let mut st = self.state.lock().await;
+        let turn_tokens = turn_usage.map_or(0, |u| u.input_tokens + u.output_tokens);
+        st.context_base_tokens =
+            (st.total_input_tokens + st.total_output_tokens).saturating_sub(turn_tokens);
+        st.context_limit_warned = false;
+        st.compactions += 1;
+        log_line!("[CTX] context compacted by the CLI (#{})", st.compactions);
+    }
+
+    /// A turn reported the context running out (its warning was sent by the pipeline).
+    async fn note_context_pressure(&self, pressure: ContextPressure) {
+        let mut st = self.state.lock().await;
+        if !st.context_limit_warned {
+            log_line!("[CTX] context limit approaching: {pressure:?}");
+        }
+        st.context_limit_warned = true;
+    }
+
+    /// How long the session has been idle, when that is past `cfg.session_idle_after` and
+    /// idle expiry is enabled.
+    pub async fn expired_idle(&self) -> Option<Duration> {
+        if self.cfg.session_idle_expiry == IdleExpiry::Off {
+            return None;
+        }
+        self.state
+            .lock()
+            .await
+            .idle_for()
+            .filter(|idle| *idle >= self.cfg.session_idle_after)
+    }
+
+    /// Count now as activity, e.g. when the user chose to continue an idle session.
+    pub async fn touch_activity(&self) {
+        self.state.lock().await.last_activity = Some(iso_timestamp_utc());
     }
 
+    /// The context is close to full (cumulative tokens past `cfg.context_save_threshold_tokens`
+    /// or a turn reporting [`ContextPressure`]) and no restore cooldown is active.
     pub async fn needs_save(&self) -> bool {
         let st = self.state.lock().await;
         st.context_limit_warned && !st.recently_restored
@@ -232,14 +724,36 @@ impl ClaudeSession {
 
     pub async fn send_message_streaming(
         &self,
-        chat_id: crate::domain::ChatId,
+        target: ChatTarget,
+        run_id: RunId,
+        prompt: &str,
+        limits: RunLimits,
+        on_event: &mut dyn EventSink,
+    ) -> Result<RunResult> {
+        let metrics = self.metrics.clone();
+        self.run_streaming(target, run_id, prompt, limits, metrics, on_event)
+            .await
+    }
+
+    async fn run_streaming(
+        &self,
+        target: ChatTarget,
+        run_id: RunId,
         prompt: &str,
-        on_event: &mut (dyn FnMut(ModelEvent) -> Result<()> + Send),
+        limits: RunLimits,
+        metrics: Arc<dyn MetricsSink>,
+        on_event: &mut dyn EventSink,
     ) -> Result<RunResult> {
-        let (resume, is_new_session) = {
+        let (resume, is_new_session, model, thinking_override) = {
             let st = self.state.lock().await;
-            (st.session.clone(), st.session.is_none())
+            (
+                st.session.clone(),
+                st.session.is_none(),
+                st.model.clone(),
+                st.thinking_tokens,
+            )
         };
+        let profile = self.prompt_profile(target);
 
         // Inject date/time at session start (parity with TS).
         let mut prompt_to_send = prompt.to_string();
@@ -248,42 +762,91 @@ impl ClaudeSession {
             prompt_to_send = format!("[Current date/time: {now}]\n\n{prompt_to_send}");
         }
 
-        // Thinking token selection (keyword triggers parity).
-        let max_thinking_tokens = thinking_tokens_for_prompt(&self.cfg, &prompt_to_send);
+        // Thinking token selection: `/think` override, else keyword triggers (TS parity).
+        let max_thinking_tokens =
+            thinking_tokens_for_prompt(&self.cfg, thinking_override, &prompt_to_send);
 
         // MCP config is optional; if present we materialize an interpolated JSON file and inject
         // the current chat context so `ask_user` can target the right conversation.
-        let mcp_config_path = prepare_mcp_config_for_chat(&self.cfg, chat_id)?;
+        let mcp_config_path = prepare_mcp_config_for_chat(&self.cfg, target)?;
+
+        // The `/prompt` profile is re-read each run; if it went missing, run without it.
+        let mut append_system_prompt = profile.and_then(|name| {
+            load_profile(&self.cfg, &self.path_policy(target), &name)
+                .map_err(|e| log_line!("[PROMPT] Skipping profile {name}: {e}"))
+                .ok()
+        });
+        if limits.plan_only {
+            append_system_prompt = Some(match append_system_prompt {
+                Some(profile) => format!("{profile}\n\n{PLAN_ONLY_INSTRUCTION}"),
+                None => PLAN_ONLY_INSTRUCTION.to_string(),
+            });
+        }
 
+        // The chat's tool lists, resolved here so a `/settings ... none` also drops the
+        // provider's default.
+        let chat_cfg = self.chat_config(target);
         let req = RunRequest {
+            run_id,
             prompt: prompt_to_send,
             cwd: self.cfg.claude_working_dir.clone(),
-            add_dirs: self.cfg.allowed_paths.clone(),
+            add_dirs: self.allowed_dirs(target),
             mcp_config_path,
             system_prompt: Some(self.cfg.safety_prompt.clone()),
-            append_system_prompt: None,
+            append_system_prompt,
+            bash_wrapper: self.cfg.bash_sandbox_wrapper.clone(),
+            allowed_tools: Some(chat_cfg.claude_allowed_tools.clone().unwrap_or_default()),
+            disallowed_tools: Some(chat_cfg.claude_disallowed_tools.clone().unwrap_or_default()),
             resume,
             fork_session: false,
             max_thinking_tokens: Some(max_thinking_tokens),
+            model,
+            permission_mode: limits.plan_only.then_some(PermissionMode::Plan),
+            max_turns: limits.max_turns,
+            max_budget_usd: limits.max_budget_usd,
+            metrics: Some(metrics),
         };
 
         {
             let mut st = self.state.lock().await;
-            if st.stop_requested {
-                st.stop_requested = false;
-                return Err(Error::External(
-                    "Query cancelled before starting".to_string(),
-                ));
+            if let Some(reason) = st.stop_requested.remove(&target) {
+                return Err(Error::Cancelled(reason));
             }
-            st.is_running = true;
+            st.active_runs.insert(target, run_id);
         }
 
-        let result = self.model.run(req, on_event).await;
+        let result = match self.cfg.query_timeout {
+            Some(limit) => {
+                let run = self.model.run(req, on_event);
+                tokio::pin!(run);
+                tokio::select! {
+                    result = &mut run => result,
+                    _ = tokio::time::sleep(limit) => {
+                        log_line!("[SESSION] Run exceeded {}s; cancelling", limit.as_secs());
+                        if let Err(e) = self.model.cancel(run_id, CancelReason::Timeout).await {
+                            log_line!("[SESSION] Failed to cancel timed-out run: {e}");
+                        }
+                        // The client escalates to SIGKILL after the grace; past that, give up on it.
+                        let wait = self.cfg.cli_kill_grace + CANCEL_SETTLE;
+                        match tokio::time::timeout(wait, run).await {
+                            Ok(result) => result,
+                            Err(_) => {
+                                log_line!("[SESSION] Timed-out run did not stop; abandoning it");
+                                Err(Error::Cancelled(CancelReason::Timeout))
+                            }
+                        }
+                    }
+                }
+            }
+            None => self.model.run(req, on_event).await,
+        };
 
         {
             let mut st = self.state.lock().await;
-            st.is_running = false;
-            st.stop_requested = false;
+            if st.active_runs.get(&target) == Some(&run_id) {
+                st.active_runs.remove(&target);
+            }
+            st.stop_requested.remove(&target);
         }
 
         let result = result?;
@@ -293,15 +856,8 @@ impl ClaudeSession {
                 let mut st = self.state.lock().await;
                 st.session = Some(session.clone());
             }
-            save_session_file(
-                &self.cfg.session_file,
-                &SessionFileData {
-                    provider: "claude_cli".to_string(),
-                    session_id: session.id.clone(),
-                    saved_at: iso_timestamp_utc(),
-                    working_dir: self.cfg.claude_working_dir.to_string_lossy().to_string(),
-                },
-            )?;
+            self.save_session(&session.id).await?;
+            self.touch_history(target, &session.id);
         }
 
         // Accumulate token usage (parity with TS).
@@ -317,26 +873,158 @@ impl ClaudeSession {
     /// This implements the TS behavior of:
     /// - thinking/tool/text/segment_end/done events
     /// - tool safety checks for Bash + file ops
-    /// - ask_user trigger hook (scans `/tmp/ask-user-*.json` and sends inline keyboard)
+    /// - ask_user trigger hook (waits for `<temp_dir>/ask-user-*.json` and sends inline keyboard)
     pub async fn send_message_to_chat(
         &self,
-        chat_id: crate::domain::ChatId,
+        target: ChatTarget,
+        prompt: &str,
+        messenger: Arc<dyn MessagingPort>,
+    ) -> Result<TurnOutput> {
+        let limits = RunLimits::from_config(&self.cfg);
+        self.send_message_to_chat_with_limits(target, prompt, messenger, limits)
+            .await
+    }
+
+    /// Same as `send_message_to_chat()` on behalf of `requester` (their role gates tools).
+    pub async fn send_message_to_chat_as(
+        &self,
+        target: ChatTarget,
+        prompt: &str,
+        messenger: Arc<dyn MessagingPort>,
+        requester: RunUser,
+    ) -> Result<TurnOutput> {
+        let limits = RunLimits::from_config(&self.cfg).with_role(requester.role);
+        self.run_turn(target, prompt, messenger, limits, None, Some(requester))
+            .await
+    }
+
+    /// Plan-only turn for `requester`: the CLI may not edit files, and the turn ends with
+    /// "Execute plan" / "Discard" buttons.
+    pub async fn plan_message_to_chat_as(
+        &self,
+        target: ChatTarget,
+        prompt: &str,
+        messenger: Arc<dyn MessagingPort>,
+        requester: RunUser,
+    ) -> Result<TurnOutput> {
+        let limits = RunLimits::from_config(&self.cfg)
+            .with_role(requester.role)
+            .with_plan_only(true);
+        self.run_turn(target, prompt, messenger, limits, None, Some(requester))
+            .await
+    }
+
+    /// Same as `send_message_to_chat()` with explicit per-query limits.
+    pub async fn send_message_to_chat_with_limits(
+        &self,
+        target: ChatTarget,
+        prompt: &str,
+        messenger: Arc<dyn MessagingPort>,
+        limits: RunLimits,
+    ) -> Result<TurnOutput> {
+        self.run_turn(target, prompt, messenger, limits, None, None)
+            .await
+    }
+
+    /// Send an `ask_user` answer; with the stream of the suspended turn, the answer continues
+    /// its messages instead of starting new ones.
+    pub async fn resume_message_to_chat(
+        &self,
+        target: ChatTarget,
+        answer: &str,
+        messenger: Arc<dyn MessagingPort>,
+        suspended: Option<SuspendedStream>,
+        requester: RunUser,
+    ) -> Result<TurnOutput> {
+        let limits = RunLimits::from_config(&self.cfg).with_role(requester.role);
+        self.run_turn(
+            target,
+            answer,
+            messenger,
+            limits,
+            suspended,
+            Some(requester),
+        )
+        .await
+    }
+
+    async fn run_turn(
+        &self,
+        target: ChatTarget,
         prompt: &str,
         messenger: Arc<dyn MessagingPort>,
+        limits: RunLimits,
+        suspended: Option<SuspendedStream>,
+        requester: Option<RunUser>,
     ) -> Result<TurnOutput> {
-        let (tx, mut rx) = mpsc::unbounded_channel::<ModelEvent>();
+        let run_id = RunId::next();
+        let started_at = iso_timestamp_utc();
+        let turn_started = Instant::now();
+        // A newer turn supersedes a plan still awaiting review.
+        let new_session = {
+            let mut st = self.state.lock().await;
+            st.pending_plans.remove(&target);
+            st.session.is_none()
+        };
 
         // Spawn event processor which owns the streaming state and ticks the spinner.
-        let cfg = self.cfg.clone();
+        let cfg = self.chat_config(target);
+        let (tx, mut rx) = mpsc::channel::<ModelEvent>(cfg.event_queue_depth);
+        let summary_theme = cfg.turn_summary.then_some(cfg.status_theme);
+        let requester_id = requester.as_ref().map(|r| r.user_id);
         let model = self.model.clone();
         let messenger_for_task = messenger.clone();
+        let shutdown = self.shutdown.clone();
+        let turn_metrics = Arc::new(TurnMetrics::new(self.metrics.clone()));
+        let pipeline_metrics = turn_metrics.clone();
+        let paths = self.path_policy(target);
+        let undo_paths = paths.clone();
+        let security = self.security.clone();
+        let undo = self.undo.as_ref().map(|j| j.begin_turn(target));
+        let (replay_guard, context_tokens, context_warned, pricing) = {
+            let st = self.state.lock().await;
+            (
+                st.replay_guard.clone(),
+                st.context_tokens(),
+                st.context_limit_warned || st.recently_restored,
+                self.pricing_for(st.model.as_deref()),
+            )
+        };
         let processor = tokio::spawn(async move {
-            let mut pipeline = EventPipeline::new(cfg, model, messenger_for_task, chat_id);
+            let mut pipeline = EventPipeline::with_turn_metrics(
+                cfg,
+                model,
+                run_id,
+                messenger_for_task,
+                paths,
+                target,
+                pipeline_metrics,
+            )
+            .with_max_budget_usd(limits.max_budget_usd)
+            .with_pricing(pricing)
+            .with_role(limits.role)
+            .with_replay_guard(replay_guard)
+            .with_context_state(context_tokens, context_warned)
+            .with_plan_review(limits.plan_only);
+            if let Some(suspended) = suspended {
+                pipeline = pipeline.with_suspended(suspended);
+            }
+            if let Some(requester) = requester {
+                pipeline = pipeline.with_requester(requester);
+            }
+            if let Some(security) = security {
+                pipeline = pipeline.with_security_monitor(security);
+            }
             let mut tick = interval(Duration::from_secs(1));
+            // A spinner frame is only worth drawing when events aren't backed up.
+            tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
             loop {
                 tokio::select! {
-                  _ = tick.tick() => {
-                    pipeline.tick_progress().await?;
+                  biased;
+                  // Keep draining events: the run is cancelled by `shutdown::drain()`, which
+                  // closes the channel once the model returns.
+                  _ = shutdown.triggered(), if !pipeline.is_shutting_down() => {
+                    pipeline.mark_shutting_down();
                   }
                   maybe = rx.recv() => {
                     let Some(ev) = maybe else { break; };
@@ -345,73 +1033,261 @@ impl ClaudeSession {
                       break;
                     }
                   }
+                  _ = tick.tick() => {
+                    pipeline.tick_progress().await?;
+                  }
                 }
             }
+            // Unblock a reader waiting on a full queue after an early stop.
+            drop(rx);
+            // The channel can close before the shutdown branch above is ever polled.
+            if shutdown.is_triggered() {
+                pipeline.mark_shutting_down();
+            }
             pipeline.finish().await
         });
 
-        let mut on_event = |ev: ModelEvent| -> Result<()> {
-            tx.send(ev)
-                .map_err(|_| Error::External("event processor stopped".to_string()))?;
-            Ok(())
+        // Run the model while the processor consumes events. The sender is moved into the
+        // sink so the channel closes (and the processor finishes) when the run returns.
+        let model_result = {
+            let mut on_event = QueueSink {
+                tx,
+                metrics: turn_metrics.clone(),
+                undo,
+                paths: undo_paths,
+            };
+            self.run_streaming(target, run_id, prompt, limits, turn_metrics, &mut on_event)
+                .await
         };
 
-        // Run the model while the processor consumes events.
-        let model_result = self
-            .send_message_streaming(chat_id, prompt, &mut on_event)
-            .await;
-
         // Wait for processor completion and use its output as source-of-truth for streaming semantics.
         let pipeline_out = processor
             .await
             .map_err(|e| Error::External(format!("event processor task failed: {e}")))??;
 
+        self.touch_activity().await;
+
         // Persist observed session even if the model was cancelled (parity with TS which saves
         // session_id as soon as it's seen).
         if let Some(session) = pipeline_out.session.clone() {
-            self.persist_observed_session(&session).await?;
+            self.persist_observed_session(target, &session).await?;
         }
 
-        // If the model errored due to our own ask_user cancellation, suppress it.
-        if pipeline_out.waiting_for_user {
-            return Ok(pipeline_out);
+        if let Some(pressure) = pipeline_out.context_pressure {
+            self.note_context_pressure(pressure).await;
         }
 
-        // Otherwise propagate the model error if present.
-        match model_result {
-            Ok(_) => Ok(pipeline_out),
-            Err(e) => Err(e),
+        // Cancelled and failed turns are summed up too; only a turn waiting on the user isn't done.
+        if let (Some(theme), false) = (summary_theme, pipeline_out.waiting_for_user) {
+            let footer = self
+                .turn_summary(theme, turn_started.elapsed(), &pipeline_out)
+                .await;
+            let _ = messenger.send_html(target, &footer).await;
         }
-    }
 
-    async fn persist_observed_session(&self, session: &SessionRef) -> Result<()> {
-        // Keep in memory for subsequent `/resume`.
-        {
+        // If the model errored due to our own ask_user/budget cancellation, suppress it;
+        // otherwise propagate the model error if present.
+        if !(pipeline_out.waiting_for_user || pipeline_out.budget_exceeded) {
+            model_result?;
+        }
+        // A provider failure is not an answer: no title or transcript entry.
+        if let Some(message) = pipeline_out.provider_error.clone() {
+            return Err(Error::Provider(message));
+        }
+        if let (true, Some(session)) = (new_session, &pipeline_out.session) {
+            self.title_new_session(target, &session.id, prompt);
+        }
+        if pipeline_out.context_compacted {
+            self.note_context_compacted(pipeline_out.usage.as_ref())
+                .await;
+        }
+        if limits.plan_only && !pipeline_out.waiting_for_user {
             let mut st = self.state.lock().await;
-            if st.session.is_none() {
-                st.session = Some(session.clone());
+            if let Some(session) = st.session.clone() {
+                let plan = PendingPlan {
+                    session_id: session.id,
+                    requester: requester_id,
+                };
+                st.pending_plans.insert(target, plan);
             }
         }
 
-        // Persist for process restarts.
-        save_session_file(
-            &self.cfg.session_file,
-            &SessionFileData {
-                provider: "claude_cli".to_string(),
-                session_id: session.id.clone(),
-                saved_at: iso_timestamp_utc(),
-                working_dir: self.cfg.claude_working_dir.to_string_lossy().to_string(),
-            },
-        )?;
-        Ok(())
+        self.state.lock().await.transcript.push(TranscriptTurn {
+            started_at,
+            finished_at: iso_timestamp_utc(),
+            prompt: prompt.to_string(),
+            response: pipeline_out.text.clone(),
+            tools: pipeline_out.tools.clone(),
+            usage: pipeline_out.usage.clone(),
+        });
+        Ok(pipeline_out)
     }
 
-    async fn accumulate_usage(&self, u: &TokenUsage) {
-        const CONTEXT_LIMIT: u64 = 200_000;
-        const SAVE_THRESHOLD: u64 = 180_000;
-        const COOLDOWN_MESSAGES: u64 = 50;
-
-        let mut st = self.state.lock().await;
+    /// `TURN_SUMMARY` footer for a finished turn, priced at the session model's rates.
+    async fn turn_summary(
+        &self,
+        theme: &StatusTheme,
+        elapsed: Duration,
+        out: &TurnOutput,
+    ) -> String {
+        let (model, session_id) = {
+            let st = self.state.lock().await;
+            let id = out
+                .session
+                .as_ref()
+                .or(st.session.as_ref())
+                .map(|s| s.id.clone());
+            (st.model.clone(), id)
+        };
+        let pricing = self.pricing_for(model.as_deref());
+        let usage = out.usage.as_ref().map(|u| (u, pricing.cost(u).total()));
+        format_turn_summary(
+            theme,
+            elapsed,
+            out.tools.len(),
+            usage,
+            session_id.as_deref(),
+        )
+    }
+
+    /// Rates of the session's model: the `/model` override, else `CLAUDE_MODEL`.
+    pub async fn pricing(&self) -> ModelPricing {
+        let model = self.state.lock().await.model.clone();
+        self.pricing_for(model.as_deref())
+    }
+
+    /// Rates of `model` (`None` = `CLAUDE_MODEL`).
+    fn pricing_for(&self, model: Option<&str>) -> ModelPricing {
+        let model = model.or(self.cfg.claude_model.as_deref());
+        self.cfg.pricing.resolve(model).pricing
+    }
+
+    /// `/export` payload: file name (`session-<short id>.md`) and Markdown, or `None` when no
+    /// turn has completed since the session started.
+    pub async fn export_transcript(&self) -> Option<(String, String)> {
+        let st = self.state.lock().await;
+        if st.transcript.is_empty() {
+            return None;
+        }
+        let id = st.session.as_ref().map(|s| s.id.as_str());
+        let pricing = self.pricing_for(st.model.as_deref());
+        let usage = TokenUsage {
+            input_tokens: st.total_input_tokens,
+            output_tokens: st.total_output_tokens,
+            cache_read_input_tokens: st.total_cache_read_tokens,
+            cache_creation_input_tokens: st.total_cache_create_tokens,
+        };
+        let file_name = format!(
+            "session-{}.md",
+            id.map(short_id).as_deref().unwrap_or("new")
+        );
+        let markdown = st
+            .transcript
+            .to_markdown(id, &usage, &pricing, &iso_timestamp_utc());
+        Some((file_name, markdown))
+    }
+
+    async fn persist_observed_session(
+        &self,
+        target: ChatTarget,
+        session: &SessionRef,
+    ) -> Result<()> {
+        // Keep in memory for subsequent `/resume`.
+        {
+            let mut st = self.state.lock().await;
+            if st.session.is_none() {
+                st.session = Some(session.clone());
+            }
+        }
+
+        // Persist for process restarts.
+        self.save_session(&session.id).await?;
+        self.touch_history(target, &session.id);
+        Ok(())
+    }
+
+    /// The saved `/resume` pointer; a corrupt one is backed up and reads as none.
+    fn load_session_data(&self) -> Result<Option<SessionFileData>> {
+        self.store.get_json(SESSION_NAMESPACE, &self.store_key)
+    }
+
+    /// Load, change and (when `change` reports a difference) save `target`'s history.
+    fn update_history(
+        &self,
+        target: ChatTarget,
+        change: impl FnOnce(&mut SessionHistory) -> bool,
+    ) -> Result<bool> {
+        let _guard = self.history_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut history = self.history(target)?;
+        let changed = change(&mut history);
+        if changed {
+            self.store
+                .put_json(SESSION_HISTORY_NAMESPACE, &target_key(target), &history)?;
+        }
+        Ok(changed)
+    }
+
+    /// Record a turn of `session_id` in the history. Failures only cost the `/sessions` entry,
+    /// so they are logged instead of failing the turn.
+    fn touch_history(&self, target: ChatTarget, session_id: &str) {
+        let working_dir = self.cfg.claude_working_dir.to_string_lossy();
+        let touched = self.update_history(target, |history| {
+            history.touch(session_id, &working_dir, &iso_timestamp_utc());
+            true
+        });
+        if let Err(e) = touched {
+            log_line!("[SESSION] Failed to update the session history: {e}");
+        }
+    }
+
+    /// Title a session after its first turn from `prompt`, unless it already has one.
+    fn title_new_session(&self, target: ChatTarget, session_id: &str, prompt: &str) {
+        let Some(title) = derive_title(prompt) else {
+            return;
+        };
+        let titled = self.update_history(target, |history| {
+            let untitled = history
+                .get(session_id)
+                .is_some_and(|entry| entry.title.is_none());
+            untitled && history.set_title(session_id, title)
+        });
+        if let Err(e) = titled {
+            log_line!(
+                "[SESSION] Failed to title session {}: {e}",
+                short_id(session_id)
+            );
+        }
+    }
+
+    async fn save_session(&self, session_id: &str) -> Result<()> {
+        let (model, thinking_tokens, last_activity) = {
+            let st = self.state.lock().await;
+            (
+                st.model.clone(),
+                st.thinking_tokens,
+                st.last_activity.clone(),
+            )
+        };
+        self.store.put_json(
+            SESSION_NAMESPACE,
+            &self.store_key,
+            &SessionFileData {
+                provider: "claude_cli".to_string(),
+                session_id: session_id.to_string(),
+                saved_at: iso_timestamp_utc(),
+                working_dir: self.cfg.claude_working_dir.to_string_lossy().to_string(),
+                model,
+                thinking_tokens,
+                last_activity,
+            },
+        )
+    }
+
+    async fn accumulate_usage(&self, u: &TokenUsage) {
+        const CONTEXT_LIMIT: u64 = 200_000;
+        const COOLDOWN_MESSAGES: u64 = 50;
+
+        let mut st = self.state.lock().await;
         if st.session_start_time.is_none() {
             st.session_start_time = Some(iso_timestamp_utc());
         }
@@ -431,31 +1307,178 @@ impl ClaudeSession {
             }
         }
 
-        let current_context = st.total_input_tokens + st.total_output_tokens;
-        if current_context >= SAVE_THRESHOLD && !st.context_limit_warned && !st.recently_restored {
+        let save_threshold = self.cfg.context_save_threshold_tokens;
+        let current_context = st.context_tokens();
+        if current_context >= save_threshold && !st.context_limit_warned && !st.recently_restored {
             st.context_limit_warned = true;
-            eprintln!(
-                "[CTX] context limit approaching: {current_context}/{CONTEXT_LIMIT} (>= {SAVE_THRESHOLD})"
+            log_line!(
+                "[CTX] context limit approaching: {current_context}/{CONTEXT_LIMIT} (>= {save_threshold})"
             );
         }
     }
 }
 
-fn thinking_tokens_for_prompt(cfg: &Config, prompt: &str) -> u32 {
+/// Feeds a turn's model events to its pipeline task. The queue is bounded: a full queue makes
+/// the model's reader wait instead of piling up events the pipeline can't keep up with.
+struct QueueSink {
+    tx: mpsc::Sender<ModelEvent>,
+    metrics: Arc<TurnMetrics>,
+    /// Snapshots files for `/undo` as their writes are read, not once the pipeline gets to them.
+    undo: Option<UndoRecorder>,
+    paths: PathPolicy,
+}
+
+#[async_trait]
+impl EventSink for QueueSink {
+    async fn emit(&mut self, ev: ModelEvent) -> Result<()> {
+        if let Some(undo) = &mut self.undo {
+            undo.before_event(&ev, &self.paths);
+        }
+        self.tx
+            .send(ev)
+            .await
+            .map_err(|_| Error::External("event processor stopped".to_string()))?;
+        let depth = self.tx.max_capacity() - self.tx.capacity();
+        self.metrics.peak(Peak::EventQueueDepth, depth as u64);
+        Ok(())
+    }
+}
+
+/// Sessions keyed by forum topic.
+///
+/// The plain chat (`thread_id: None`) always maps to the default session, so non-forum chats and
+/// cron jobs behave exactly as before. Each forum topic gets its own `ClaudeSession` with a
+/// separate session file, which lets topics in one supergroup hold independent conversations.
+pub struct SessionRegistry {
+    default: Arc<ClaudeSession>,
+    topics: Mutex<HashMap<(i64, i32), Arc<ClaudeSession>>>,
+}
+
+impl SessionRegistry {
+    pub fn new(default: Arc<ClaudeSession>) -> Self {
+        Self {
+            default,
+            topics: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn default_session(&self) -> Arc<ClaudeSession> {
+        self.default.clone()
+    }
+
+    /// Whether any session (default or topic) has a run in flight.
+    pub async fn any_running(&self) -> bool {
+        if self.default.is_running().await {
+            return true;
+        }
+        let topics: Vec<_> = self.topics.lock().await.values().cloned().collect();
+        for session in topics {
+            if session.is_running().await {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Cancel every run of the shared model client (all chats and topics).
+    pub async fn cancel_all_runs(&self) -> Result<()> {
+        self.default.model.cancel_all().await
+    }
+
+    /// Session for `target`, created (and resumed from its session file) on first use.
+    pub async fn for_target(&self, target: ChatTarget) -> Arc<ClaudeSession> {
+        let Some(thread_id) = target.thread_id else {
+            return self.default.clone();
+        };
+
+        let mut topics = self.topics.lock().await;
+        if let Some(session) = topics.get(&(target.chat_id.0, thread_id)) {
+            return session.clone();
+        }
+
+        let mut session = ClaudeSession::new(self.default.cfg.clone(), self.default.model.clone())
+            .with_shutdown(self.default.shutdown.clone())
+            .with_metrics(self.default.metrics.clone());
+        session.grants = self.default.grants.clone();
+        session.chat_settings = self.default.chat_settings.clone();
+        session.security = self.default.security.clone();
+        session.undo = self.default.undo.clone();
+        session.store = self.default.store.clone();
+        session.store_key = target_key(target);
+        let session = Arc::new(session);
+        if let Err(e) = session
+            .resume_last(self.default.cfg.bot_locale.messages())
+            .await
+        {
+            log_line!("[SESSION] Failed to resume topic session {thread_id}: {e}");
+        }
+        topics.insert((target.chat_id.0, thread_id), session.clone());
+        session
+    }
+}
+
+/// File-name-safe key for a chat target (`<chat>` or `<chat>-t<thread>`).
+fn target_key(target: ChatTarget) -> String {
+    match target.thread_id {
+        Some(thread_id) => format!("{}-t{thread_id}", target.chat_id.0),
+        None => target.chat_id.0.to_string(),
+    }
+}
+
+/// Extra wait, past `cli_kill_grace`, for a timed-out run to return after it was cancelled.
+const CANCEL_SETTLE: Duration = Duration::from_secs(1);
+
+/// Budget for `think` keywords and `/think normal`.
+pub const THINKING_NORMAL_TOKENS: u32 = 10_000;
+/// Budget for `ultrathink` keywords and `/think deep`.
+pub const THINKING_DEEP_TOKENS: u32 = 50_000;
+
+/// Parse a `/think` argument: `off`, `normal`, `deep` or an explicit token count (capped).
+pub fn parse_thinking_budget(arg: &str) -> Result<u32> {
+    match arg.trim().to_lowercase().as_str() {
+        "off" | "0" => Ok(0),
+        "normal" => Ok(THINKING_NORMAL_TOKENS),
+        "deep" => Ok(THINKING_DEEP_TOKENS),
+        other => other
+            .parse::<u32>()
+            .map(|n| n.min(MAX_THINKING_TOKENS))
+            .map_err(|_| {
+                Error::Config(format!(
+                    "invalid thinking budget: {arg} (use off, normal, deep or a number)"
+                ))
+            }),
+    }
+}
+
+/// Human label for a thinking budget (`/think`, `/status`).
+pub fn thinking_label(tokens: u32) -> String {
+    match tokens {
+        0 => "off".to_string(),
+        THINKING_NORMAL_TOKENS => format!("normal ({tokens} tokens)"),
+        THINKING_DEEP_TOKENS => format!("deep ({tokens} tokens)"),
+        n => format!("{n} tokens"),
+    }
+}
+
+/// Precedence: explicit `/think` override, then keywords, then `default_thinking_tokens`.
+fn thinking_tokens_for_prompt(cfg: &Config, override_tokens: Option<u32>, prompt: &str) -> u32 {
+    if let Some(tokens) = override_tokens {
+        return tokens;
+    }
     let lower = prompt.to_lowercase();
     if cfg
         .thinking_deep_keywords
         .iter()
         .any(|k| !k.is_empty() && lower.contains(k))
     {
-        return 50_000;
+        return THINKING_DEEP_TOKENS;
     }
     if cfg
         .thinking_keywords
         .iter()
         .any(|k| !k.is_empty() && lower.contains(k))
     {
-        return 10_000;
+        return THINKING_NORMAL_TOKENS;
     }
     cfg.default_thinking_tokens
 }
@@ -464,10 +1487,10 @@ fn short_id(id: &str) -> String {
     id.chars().take(8).collect()
 }
 
-fn prepare_mcp_config_for_chat(
-    cfg: &Config,
-    chat_id: crate::domain::ChatId,
-) -> Result<Option<std::path::PathBuf>> {
+/// Load `mcp-config.json` from the working directory with `${CTB_REPO_ROOT}` interpolated.
+///
+/// Returns `None` when the file is missing.
+pub fn load_base_mcp_servers() -> Result<Option<crate::mcp_config::McpServers>> {
     let repo_root = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
     let base = repo_root.join("mcp-config.json");
     if !base.exists() {
@@ -482,21 +1505,52 @@ fn prepare_mcp_config_for_chat(
         repo_root.to_string_lossy().to_string(),
     );
 
-    let mut servers = crate::mcp_config::load_mcp_servers_with_overrides(&base, &overrides)?;
+    crate::mcp_config::load_mcp_servers_with_overrides(&base, &overrides).map(Some)
+}
+
+/// Write the interpolated MCP config for `target` (with chat context injected for `ask_user`).
+///
+/// Returns `None` when `mcp-config.json` is missing or empty.
+pub fn prepare_mcp_config_for_chat(
+    cfg: &Config,
+    target: ChatTarget,
+) -> Result<Option<std::path::PathBuf>> {
+    let Some(mut servers) = load_base_mcp_servers()? else {
+        return Ok(None);
+    };
     if servers.is_empty() {
         return Ok(None);
     }
+    for (name, server) in &servers {
+        if let Some(warning) = server.command_warning() {
+            log_line!("[MCP] {name}: {warning}");
+        }
+    }
 
     // Provide chat context for ask_user MCP so it can target the correct Telegram conversation.
     if let Some(crate::mcp_config::McpServerConfig::Stdio { env, .. }) = servers.get_mut("ask-user")
     {
-        env.insert("TELEGRAM_CHAT_ID".to_string(), chat_id.0.to_string());
+        env.insert("TELEGRAM_CHAT_ID".to_string(), target.chat_id.0.to_string());
+        if let Some(thread_id) = target.thread_id {
+            env.insert("TELEGRAM_THREAD_ID".to_string(), thread_id.to_string());
+        }
+        env.insert(
+            "ASK_USER_DIR".to_string(),
+            cfg.temp_dir.to_string_lossy().to_string(),
+        );
+        if cfg.ask_user_sync {
+            env.insert("ASK_USER_SYNC".to_string(), "1".to_string());
+            env.insert(
+                "ASK_USER_SYNC_TIMEOUT_SECS".to_string(),
+                cfg.ask_user_sync_timeout.as_secs().to_string(),
+            );
+        }
     }
 
     let pid = std::process::id();
     let path = cfg
         .temp_dir
-        .join(format!("mcp-config-{}-{pid}.json", chat_id.0));
+        .join(format!("mcp-config-{}-{pid}.json", target_key(target)));
     crate::mcp_config::write_mcp_servers_json(&path, &servers)?;
     Ok(Some(path))
 }
@@ -507,893 +1561,964 @@ struct SessionFileData {
     session_id: String,
     saved_at: String,
     working_dir: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thinking_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_activity: Option<String>,
 }
 
-fn load_session_file(path: &std::path::Path) -> Result<Option<SessionFileData>> {
-    if !path.exists() {
-        return Ok(None);
-    }
-    let txt = std::fs::read_to_string(path)?;
-    if txt.trim().is_empty() {
-        return Ok(None);
-    }
-    let data: SessionFileData = serde_json::from_str(&txt)?;
-    Ok(Some(data))
-}
-
-fn save_session_file(path: &std::path::Path, data: &SessionFileData) -> Result<()> {
-    let txt = serde_json::to_string(data)?;
-    std::fs::write(path, txt)?;
-    Ok(())
-}
-
-struct EventPipeline {
-    cfg: Arc<Config>,
-    model: Arc<dyn ModelClient>,
-    messenger: Arc<dyn MessagingPort>,
-    stream: StreamingState,
-    paths: PathPolicy,
-
-    response_parts: Vec<String>,
-    current_segment_id: u32,
-    current_segment_text: String,
-    last_snapshot_text: String,
-    last_text_emit: Option<Instant>,
-
-    observed_session: Option<SessionRef>,
-    last_usage: Option<TokenUsage>,
-    ask_user_triggered: bool,
-    ask_user_buttons_sent: bool,
-    final_result_text: Option<String>,
-}
-
-impl EventPipeline {
-    fn new(
-        cfg: Arc<Config>,
-        model: Arc<dyn ModelClient>,
-        messenger: Arc<dyn MessagingPort>,
-        chat_id: crate::domain::ChatId,
-    ) -> Self {
-        let paths = PathPolicy {
-            allowed_paths: cfg.allowed_paths.clone(),
-            temp_paths: cfg.temp_paths.clone(),
-            home_dir: std::env::var_os("HOME").map(std::path::PathBuf::from),
-            base_dir: Some(cfg.claude_working_dir.clone()),
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::test_support::{RecordingMessenger, ScriptedModel};
+    use crate::model::types::{ModelEvent, ProviderKind};
+    use serde_json::json;
 
-        Self {
-            cfg,
-            model,
-            messenger,
-            stream: StreamingState::new(chat_id),
-            paths,
-            response_parts: Vec::new(),
-            current_segment_id: 0,
-            current_segment_text: String::new(),
-            last_snapshot_text: String::new(),
-            last_text_emit: None,
-            observed_session: None,
-            last_usage: None,
-            ask_user_triggered: false,
-            ask_user_buttons_sent: false,
-            final_result_text: None,
-        }
+    fn test_config() -> Arc<Config> {
+        Arc::new(Config::for_tests())
     }
 
-    fn should_stop_early(&self) -> bool {
-        self.ask_user_triggered
+    fn assistant_raw(session_id: &str, blocks: Vec<serde_json::Value>) -> serde_json::Value {
+        json!({
+          "session_id": session_id,
+          "message": { "content": blocks }
+        })
     }
 
-    async fn tick_progress(&mut self) -> Result<()> {
-        self.stream.tick_progress(self.messenger.as_ref()).await
+    #[tokio::test]
+    async fn model_override_is_validated_and_survives_restart() {
+        let mut cfg = (*test_config()).clone();
+        cfg.session_file = format!("/tmp/ctb-session-model-{}.json", std::process::id()).into();
+        let cfg = Arc::new(cfg);
+
+        let session = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()));
+        assert!(session
+            .set_model(Some("opus; rm -rf /".to_string()))
+            .await
+            .is_err());
+        assert!(session
+            .set_model(Some("--verbose".to_string()))
+            .await
+            .is_err());
+        session.set_model(Some("opus".to_string())).await.unwrap();
+
+        let restarted = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()));
+        let (resumed, _) = restarted.resume_last(&crate::i18n::EN).await.unwrap();
+        let _ = std::fs::remove_file(&cfg.session_file);
+        assert!(!resumed, "no session id was ever saved");
+        assert_eq!(restarted.model().await.as_deref(), Some("opus"));
     }
 
-    async fn handle_event(&mut self, ev: ModelEvent) -> Result<()> {
-        let raw = match &ev {
-            ModelEvent::SystemInit { raw }
-            | ModelEvent::Assistant { raw }
-            | ModelEvent::Tool { raw }
-            | ModelEvent::Result { raw }
-            | ModelEvent::Unknown { raw } => raw,
-        };
-        self.observe_session_id(raw);
+    #[test]
+    fn thinking_override_beats_keywords_beats_default() {
+        let mut cfg = (*test_config()).clone();
+        cfg.thinking_keywords = vec!["think".to_string()];
+        cfg.thinking_deep_keywords = vec!["ultrathink".to_string()];
+        cfg.default_thinking_tokens = 1_000;
+
+        assert_eq!(thinking_tokens_for_prompt(&cfg, None, "hello"), 1_000);
+        assert_eq!(
+            thinking_tokens_for_prompt(&cfg, None, "think about it"),
+            THINKING_NORMAL_TOKENS
+        );
+        assert_eq!(
+            thinking_tokens_for_prompt(&cfg, None, "ULTRATHINK this"),
+            THINKING_DEEP_TOKENS
+        );
+        assert_eq!(thinking_tokens_for_prompt(&cfg, Some(0), "ultrathink"), 0);
+        assert_eq!(
+            thinking_tokens_for_prompt(&cfg, Some(20_000), "hello"),
+            20_000
+        );
+    }
 
-        match ev {
-            ModelEvent::Assistant { raw } => self.handle_assistant_raw(&raw).await,
-            ModelEvent::Result { raw } => {
-                self.handle_result_raw(&raw);
-                Ok(())
-            }
-            _ => Ok(()),
-        }
+    #[test]
+    fn thinking_budget_arguments_parse_and_cap() {
+        assert_eq!(parse_thinking_budget("off").unwrap(), 0);
+        assert_eq!(
+            parse_thinking_budget(" Normal ").unwrap(),
+            THINKING_NORMAL_TOKENS
+        );
+        assert_eq!(parse_thinking_budget("deep").unwrap(), THINKING_DEEP_TOKENS);
+        assert_eq!(parse_thinking_budget("4096").unwrap(), 4096);
+        assert_eq!(
+            parse_thinking_budget("999999").unwrap(),
+            MAX_THINKING_TOKENS
+        );
+        assert!(parse_thinking_budget("lots").is_err());
+        assert!(parse_thinking_budget("-5").is_err());
     }
 
-    fn observe_session_id(&mut self, raw: &serde_json::Value) {
-        if self.observed_session.is_some() {
-            return;
-        }
-        let Some(id) = raw.get("session_id").and_then(|v| v.as_str()) else {
-            return;
-        };
-        self.observed_session = Some(SessionRef {
-            provider: ProviderKind::ClaudeCli,
-            id: id.to_string(),
-        });
+    #[tokio::test]
+    async fn thinking_override_survives_restart() {
+        let mut cfg = (*test_config()).clone();
+        cfg.session_file = format!("/tmp/ctb-session-think-{}.json", std::process::id()).into();
+        let cfg = Arc::new(cfg);
+
+        let session = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()));
+        session
+            .set_thinking_tokens(Some(THINKING_DEEP_TOKENS))
+            .await
+            .unwrap();
+
+        let restarted = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()));
+        let _ = restarted.resume_last(&crate::i18n::EN).await.unwrap();
+        let _ = std::fs::remove_file(&cfg.session_file);
+        assert_eq!(
+            restarted.thinking_tokens().await,
+            Some(THINKING_DEEP_TOKENS)
+        );
+        assert_eq!(
+            restarted.stats().await.thinking_tokens,
+            Some(THINKING_DEEP_TOKENS)
+        );
     }
 
-    fn handle_result_raw(&mut self, raw: &serde_json::Value) {
-        if let Some(result) = raw.get("result").and_then(|v| v.as_str()) {
-            self.final_result_text = Some(result.to_string());
-        }
-        if let Some(usage) = raw.get("usage") {
-            self.last_usage = parse_usage(usage);
-        }
+    #[tokio::test]
+    async fn truncated_session_file_is_backed_up_on_resume() {
+        let scratch =
+            std::env::temp_dir().join(format!("ctb-session-corrupt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&scratch);
+        std::fs::create_dir_all(&scratch).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.session_file = scratch.join("session.json");
+        std::fs::write(&cfg.session_file, r#"{"provider":"claude_cli","sess"#).unwrap();
+
+        let session = ClaudeSession::new(Arc::new(cfg), Arc::new(ScriptedModel::new()));
+        let (resumed, message) = session.resume_last(&crate::i18n::EN).await.unwrap();
+        assert!(!resumed);
+        assert_eq!(message, "No saved session found");
+        let names: Vec<String> = std::fs::read_dir(&scratch)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].starts_with("session.json.corrupt-"), "{names:?}");
+        let _ = std::fs::remove_dir_all(&scratch);
     }
 
-    async fn handle_assistant_raw(&mut self, raw: &serde_json::Value) -> Result<()> {
-        let Some(content) = raw
-            .get("message")
-            .and_then(|m| m.get("content"))
-            .and_then(|c| c.as_array())
-        else {
-            return Ok(());
+    #[tokio::test]
+    async fn stop_cancels_only_the_target_chat_run() {
+        let model = Arc::new(ScriptedModel::new().blocking());
+        let session = Arc::new(ClaudeSession::new(test_config(), model.clone()));
+        let chat_a = ChatTarget::from(crate::domain::ChatId(1));
+        let chat_b = ChatTarget::from(crate::domain::ChatId(2));
+        let (run_a, run_b) = (RunId::next(), RunId::next());
+
+        let spawn_run = |target: ChatTarget, run_id: RunId| {
+            let session = session.clone();
+            tokio::spawn(async move {
+                session
+                    .send_message_streaming(target, run_id, "hi", RunLimits::default(), &mut |_| {
+                        Ok(())
+                    })
+                    .await
+            })
         };
-
-        let all_text = content
-            .iter()
-            .all(|b| b.get("type").and_then(|t| t.as_str()) == Some("text"));
-
-        if all_text {
-            let snapshot = content
-                .iter()
-                .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
-                .collect::<String>();
-            self.handle_text_snapshot(&snapshot).await?;
-            return Ok(());
-        }
-
-        for block in content {
-            let Some(ty) = block.get("type").and_then(|t| t.as_str()) else {
-                continue;
-            };
-            match ty {
-                "thinking" => {
-                    if let Some(t) = block.get("thinking").and_then(|t| t.as_str()) {
-                        self.stream
-                            .on_status(
-                                &self.cfg,
-                                self.messenger.as_ref(),
-                                StatusType::Thinking,
-                                t,
-                                None,
-                            )
-                            .await?;
-                    }
-                }
-                "tool_use" => {
-                    self.handle_tool_use(block).await?;
-                }
-                "text" => {
-                    if let Some(t) = block.get("text").and_then(|t| t.as_str()) {
-                        self.append_text_delta(t).await?;
-                    }
-                }
-                _ => {}
-            }
+        let task_a = spawn_run(chat_a, run_a);
+        let task_b = spawn_run(chat_b, run_b);
+        while model.started() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
 
-        Ok(())
+        assert!(session.stop(chat_a, CancelReason::Interrupt).await.unwrap());
+        assert!(matches!(
+            task_a.await.unwrap(),
+            Err(Error::Cancelled(CancelReason::Interrupt))
+        ));
+        assert!(session.is_running().await, "chat B must still be running");
+
+        model.release();
+        assert_eq!(task_b.await.unwrap().unwrap().text, "done");
+        assert_eq!(model.cancels(), vec![(run_a, CancelReason::Interrupt)]);
+        assert!(!session.is_running().await);
+
+        // A stop that lands between two runs of chat A holds back A's next run only.
+        session
+            .state
+            .lock()
+            .await
+            .stop_requested
+            .insert(chat_a, CancelReason::UserStop);
+        assert!(spawn_run(chat_b, RunId::next()).await.unwrap().is_ok());
+        assert!(matches!(
+            spawn_run(chat_a, RunId::next()).await.unwrap(),
+            Err(Error::Cancelled(CancelReason::UserStop))
+        ));
+        assert!(spawn_run(chat_a, RunId::next()).await.unwrap().is_ok());
     }
 
-    async fn handle_text_snapshot(&mut self, snapshot: &str) -> Result<()> {
-        if snapshot.starts_with(&self.last_snapshot_text) {
-            let delta = &snapshot[self.last_snapshot_text.len()..];
-            if !delta.is_empty() {
-                self.append_text_delta(delta).await?;
-            }
-            self.last_snapshot_text = snapshot.to_string();
-            return Ok(());
-        }
-
-        // Fallback: treat as delta-like (best-effort). Do not reset segment state mid-turn.
-        if !snapshot.is_empty() {
-            self.append_text_delta(snapshot).await?;
-        }
-        self.last_snapshot_text = self.current_segment_text.clone();
-        Ok(())
+    #[tokio::test]
+    async fn timed_out_runs_end_even_when_cancelling_fails() {
+        let mut cfg = (*test_config()).clone();
+        cfg.query_timeout = Some(Duration::from_millis(50));
+        let cfg = Arc::new(cfg);
+        let chat = ChatTarget::from(crate::domain::ChatId(1));
+
+        let model = Arc::new(ScriptedModel::new().blocking());
+        let session = ClaudeSession::new(cfg.clone(), model.clone());
+        let run_id = RunId::next();
+        let result = session
+            .send_message_streaming(chat, run_id, "hi", RunLimits::default(), &mut |_| Ok(()))
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::Cancelled(CancelReason::Timeout))
+        ));
+        assert_eq!(model.cancels(), vec![(run_id, CancelReason::Timeout)]);
+
+        let model = Arc::new(ScriptedModel::new().blocking().stuck());
+        let session = ClaudeSession::new(cfg.clone(), model.clone());
+        let started = Instant::now();
+        let result = session
+            .send_message_streaming(chat, RunId::next(), "hi", RunLimits::default(), &mut |_| {
+                Ok(())
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::Cancelled(CancelReason::Timeout))
+        ));
+        assert!(started.elapsed() < cfg.cli_kill_grace + CANCEL_SETTLE * 2);
+        assert!(!session.is_running().await);
     }
 
-    async fn append_text_delta(&mut self, text: &str) -> Result<()> {
-        self.response_parts.push(text.to_string());
-        self.current_segment_text.push_str(text);
-        self.last_snapshot_text.push_str(text);
-
-        let now = Instant::now();
-        let should_emit = self.current_segment_text.len() > 20
-            && self
-                .last_text_emit
-                .map(|t| now.duration_since(t) > self.cfg.streaming_throttle)
-                .unwrap_or(true);
-
-        if should_emit {
-            self.stream
-                .on_status(
-                    &self.cfg,
-                    self.messenger.as_ref(),
-                    StatusType::Text,
-                    &self.current_segment_text,
-                    Some(self.current_segment_id),
-                )
-                .await?;
-            self.last_text_emit = Some(now);
+    #[tokio::test]
+    async fn kill_cancels_the_runs_in_flight() {
+        let model = Arc::new(ScriptedModel::new().blocking());
+        let session = Arc::new(ClaudeSession::new(test_config(), model.clone()));
+        let chat = ChatTarget::from(crate::domain::ChatId(1));
+        let run_id = RunId::next();
+
+        let task = {
+            let session = session.clone();
+            tokio::spawn(async move {
+                session
+                    .send_message_streaming(chat, run_id, "hi", RunLimits::default(), &mut |_| {
+                        Ok(())
+                    })
+                    .await
+            })
+        };
+        while model.started() < 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
 
-        Ok(())
+        session.kill().await.unwrap();
+        assert!(matches!(
+            task.await.unwrap(),
+            Err(Error::Cancelled(CancelReason::UserStop))
+        ));
+        assert_eq!(model.cancels(), vec![(run_id, CancelReason::UserStop)]);
+        assert!(!session.is_running().await);
     }
 
-    async fn handle_tool_use(&mut self, block: &serde_json::Value) -> Result<()> {
-        let tool_name = block.get("name").and_then(|v| v.as_str()).unwrap_or("Tool");
-        let tool_input = block.get("input").unwrap_or(&serde_json::Value::Null);
-
-        // Safety check for Bash.
-        if tool_name.eq_ignore_ascii_case("Bash") {
-            let cmd = tool_input
-                .get("command")
-                .and_then(|v| v.as_str())
-                .unwrap_or("");
-            let (ok, reason) = check_command_safety(cmd, &self.cfg.blocked_patterns, &self.paths);
-            if !ok {
-                if let Err(e) = self.model.cancel().await {
-                    return Err(Error::External(format!(
-                        "Failed to cancel run after blocking unsafe command: {e}"
-                    )));
-                }
-                let msg = format!("BLOCKED: {}", escape_html(&reason));
-                let _ = self
-                    .stream
-                    .on_status(
-                        &self.cfg,
-                        self.messenger.as_ref(),
-                        StatusType::Tool,
-                        &msg,
-                        None,
-                    )
-                    .await;
-                return Err(Error::Security(format!("Unsafe command blocked: {reason}")));
-            }
-        }
-
-        // Safety check for file operations.
-        if ["Read", "Write", "Edit"]
-            .iter()
-            .any(|t| tool_name.eq_ignore_ascii_case(t))
-        {
-            let file_path = tool_input
-                .get("file_path")
-                .and_then(|v| v.as_str())
-                .unwrap_or("");
-
-            if !file_path.is_empty() && !self.paths.is_path_allowed(file_path) {
-                if let Err(e) = self.model.cancel().await {
-                    return Err(Error::External(format!(
-                        "Failed to cancel run after blocking file access: {e}"
-                    )));
-                }
-                let msg = format!("Access denied: {}", escape_html(file_path));
-                let _ = self
-                    .stream
-                    .on_status(
-                        &self.cfg,
-                        self.messenger.as_ref(),
-                        StatusType::Tool,
-                        &msg,
-                        None,
-                    )
-                    .await;
-                return Err(Error::Security(format!("File access blocked: {file_path}")));
-            }
-        }
-
-        // Segment ends when tool starts.
-        if !self.current_segment_text.is_empty() {
-            self.stream
-                .on_status(
-                    &self.cfg,
-                    self.messenger.as_ref(),
-                    StatusType::SegmentEnd,
-                    &self.current_segment_text,
-                    Some(self.current_segment_id),
-                )
-                .await?;
-            self.current_segment_id += 1;
-            self.current_segment_text.clear();
-            self.last_snapshot_text.clear();
-            self.last_text_emit = None;
-        }
+    #[tokio::test]
+    async fn only_the_latest_prompt_accepts_edits() {
+        let session = ClaudeSession::new(test_config(), Arc::new(ScriptedModel::new()));
+        let chat = ChatTarget::from(crate::domain::ChatId(1));
+        let other = ChatTarget::from(crate::domain::ChatId(2));
 
-        // ask_user MCP tool: don't spam tool status; instead send inline keyboard if request file is present.
-        if is_ask_user_tool(tool_name) {
-            self.ask_user_triggered = true;
-
-            // Give MCP server a moment to write the request file, then retry a few times.
-            tokio::time::sleep(Duration::from_millis(200)).await;
-            let mut last_err: Option<Error> = None;
-            for attempt in 0..3 {
-                match check_pending_ask_user_requests(
-                    &*self.messenger,
-                    &self.cfg,
-                    self.stream.chat_id,
-                )
+        session
+            .record_prompt(chat, MessageId(10), "helo".into())
+            .await;
+        session
+            .record_prompt(chat, MessageId(11), "second".into())
+            .await;
+        assert!(
+            !session
+                .update_last_prompt(chat, MessageId(10), "hello".into())
                 .await
-                {
-                    Ok(true) => {
-                        self.ask_user_buttons_sent = true;
-                        break;
-                    }
-                    Ok(false) => {}
-                    Err(e) => {
-                        last_err = Some(e);
-                        break;
-                    }
-                }
-                if attempt < 2 {
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                }
-            }
-
-            // Stop the current run so the bot can wait for the user's callback response.
-            if let Err(e) = self.model.cancel().await {
-                if let Some(prev) = last_err {
-                    return Err(Error::External(format!(
-                        "Failed to cancel run after ask_user trigger: {e} (ask_user file handling error: {prev})"
-                    )));
-                }
-                return Err(Error::External(format!(
-                    "Failed to cancel run after ask_user trigger: {e}"
-                )));
-            }
-
-            if let Some(e) = last_err {
-                return Err(e);
-            }
-
-            return Ok(());
-        }
-
-        let tool_display = format_tool_status(tool_name, tool_input);
-        self.stream
-            .on_status(
-                &self.cfg,
-                self.messenger.as_ref(),
-                StatusType::Tool,
-                &tool_display,
-                None,
-            )
-            .await?;
+        );
+        assert!(
+            !session
+                .update_last_prompt(other, MessageId(11), "x".into())
+                .await
+        );
 
-        Ok(())
+        assert!(
+            session
+                .update_last_prompt(chat, MessageId(11), "2nd".into())
+                .await
+        );
+        assert_eq!(
+            session.last_prompt(chat).await,
+            Some((MessageId(11), "2nd".to_string()))
+        );
+        assert!(!session.is_running_for(chat).await);
     }
 
-    async fn finish(mut self) -> Result<TurnOutput> {
-        // If ask_user was triggered, return early: user will respond via callback.
-        if self.ask_user_triggered {
-            self.stream
-                .on_status(
-                    &self.cfg,
-                    self.messenger.as_ref(),
-                    StatusType::Done,
-                    "",
-                    None,
-                )
-                .await?;
-            return Ok(TurnOutput {
-                text: if self.ask_user_buttons_sent {
-                    "[Waiting for user selection]".to_string()
-                } else {
-                    "[Waiting for user selection (no request file found yet)]".to_string()
+    #[tokio::test]
+    async fn forum_topics_get_independent_sessions() {
+        let mut cfg = (*test_config()).clone();
+        cfg.session_file = format!("/tmp/ctb-session-topics-{}.json", std::process::id()).into();
+        let default = Arc::new(ClaudeSession::new(
+            Arc::new(cfg),
+            Arc::new(ScriptedModel::new()),
+        ));
+        let registry = SessionRegistry::new(default.clone());
+
+        let chat = crate::domain::ChatId(-100);
+        let plain = registry.for_target(chat.into()).await;
+        let topic_a = registry.for_target(ChatTarget::new(chat, Some(11))).await;
+        let topic_b = registry.for_target(ChatTarget::new(chat, Some(22))).await;
+        assert!(Arc::ptr_eq(&plain, &default));
+        assert!(!Arc::ptr_eq(&topic_a, &topic_b));
+        assert!(Arc::ptr_eq(
+            &topic_a,
+            &registry.for_target(ChatTarget::new(chat, Some(11))).await
+        ));
+
+        topic_a.set_model(Some("opus".to_string())).await.unwrap();
+        topic_a
+            .persist_observed_session(
+                ChatTarget::new(chat, Some(11)),
+                &SessionRef {
+                    provider: ProviderKind::ClaudeCli,
+                    id: "session-a".to_string(),
                 },
-                waiting_for_user: true,
-                usage: self.last_usage,
-                session: self.observed_session,
-            });
-        }
-
-        if !self.current_segment_text.is_empty() {
-            self.stream
-                .on_status(
-                    &self.cfg,
-                    self.messenger.as_ref(),
-                    StatusType::SegmentEnd,
-                    &self.current_segment_text,
-                    Some(self.current_segment_id),
-                )
-                .await?;
-        }
-
-        self.stream
-            .on_status(
-                &self.cfg,
-                self.messenger.as_ref(),
-                StatusType::Done,
-                "",
-                None,
             )
-            .await?;
-
-        let joined = if !self.response_parts.is_empty() {
-            self.response_parts.join("")
-        } else {
-            self.final_result_text
-                .unwrap_or_else(|| "No response from Claude.".to_string())
-        };
-
-        Ok(TurnOutput {
-            text: joined,
-            waiting_for_user: false,
-            usage: self.last_usage,
-            session: self.observed_session,
-        })
-    }
-}
-
-fn is_ask_user_tool(tool_name: &str) -> bool {
-    tool_name.starts_with("mcp__ask-user") || tool_name == "AskUserQuestion"
-}
-
-async fn check_pending_ask_user_requests(
-    messenger: &dyn MessagingPort,
-    cfg: &Config,
-    chat_id: crate::domain::ChatId,
-) -> Result<bool> {
-    let dir = std::path::Path::new("/tmp");
-    let Ok(rd) = std::fs::read_dir(dir) else {
-        return Ok(false);
-    };
-
-    let mut any_sent = false;
-    for ent in rd.flatten() {
-        let name = ent.file_name().to_string_lossy().to_string();
-        if !name.starts_with("ask-user-") || !name.ends_with(".json") {
-            continue;
-        }
-
-        let path = ent.path();
-        let Ok(txt) = std::fs::read_to_string(&path) else {
-            continue;
-        };
-        let Ok(mut v) = serde_json::from_str::<serde_json::Value>(&txt) else {
-            continue;
-        };
-
-        if v.get("status").and_then(|s| s.as_str()) != Some("pending") {
-            continue;
-        }
-        let file_chat = v
-            .get("chat_id")
-            .and_then(|c| {
-                if let Some(n) = c.as_i64() {
-                    return Some(n);
-                }
-                c.as_str().and_then(|s| s.parse::<i64>().ok())
-            })
-            .unwrap_or_default();
-        if file_chat != chat_id.0 {
-            continue;
-        }
-
-        let question = v
-            .get("question")
-            .and_then(|q| q.as_str())
-            .unwrap_or("Please choose:");
-        let request_id = v.get("request_id").and_then(|r| r.as_str()).unwrap_or("");
-        let options = v
-            .get("options")
-            .and_then(|o| o.as_array())
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|x| x.as_str().map(|s| s.to_string()))
-                    .collect::<Vec<String>>()
-            })
-            .unwrap_or_default();
-
-        if request_id.is_empty() || options.is_empty() {
-            continue;
-        }
-
-        let keyboard =
-            InlineKeyboard::one_per_row(request_id, &options, cfg.button_label_max_length);
-        messenger
-            .send_inline_keyboard(chat_id, &format!("❓ {}", escape_html(question)), keyboard)
-            .await?;
-
-        // Mark as sent.
-        v["status"] = serde_json::Value::String("sent".to_string());
-        std::fs::write(&path, serde_json::to_string(&v)?)?;
-        any_sent = true;
+            .await
+            .unwrap();
+        assert_eq!(topic_b.model().await, None);
+        assert!(!topic_b.is_active().await);
+        assert!(!default.is_active().await);
+
+        let file_a = crate::storage::keyed_path(
+            &default.cfg.session_file,
+            &target_key(ChatTarget::new(chat, Some(11))),
+        );
+        assert!(file_a.ends_with(format!(
+            "ctb-session-topics-{}--100-t11.json",
+            std::process::id()
+        )));
+        let _ = std::fs::remove_file(&file_a);
     }
 
-    Ok(any_sent)
-}
+    #[tokio::test]
+    async fn grants_extend_path_checks_until_revoked() {
+        let scratch =
+            std::env::temp_dir().join(format!("ctb-session-grants-{}", std::process::id()));
+        let granted = scratch.join("granted");
+        std::fs::create_dir_all(&granted).unwrap();
+        let granted = std::fs::canonicalize(granted).unwrap();
+
+        let mut cfg = (*test_config()).clone();
+        cfg.allowed_paths = vec![];
+        cfg.temp_paths = vec![];
+        cfg.session_file = scratch.join("session.json");
+        let grants = Arc::new(DirectoryGrants::load(scratch.join("grants.json"), None));
+        let read = json!({"type":"tool_use","id":"t1","name":"Read","input":{"file_path": granted.join("notes.txt")}});
+        let model = Arc::new(ScriptedModel::new().emitting([ModelEvent::Assistant {
+            raw: assistant_raw("s-grants", vec![read]),
+        }]));
+        let session = ClaudeSession::new(Arc::new(cfg), model.clone()).with_grants(grants.clone());
+        let target = ChatTarget::from(crate::domain::ChatId(5));
+        let messenger = Arc::new(RecordingMessenger::new());
+
+        let err = session
+            .send_message_to_chat(target, "read it", messenger.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Security(_)), "{err}");
 
-fn parse_usage(v: &serde_json::Value) -> Option<TokenUsage> {
-    let get = |k: &str| v.get(k).and_then(|x| x.as_u64()).unwrap_or(0);
-    Some(TokenUsage {
-        input_tokens: get("input_tokens"),
-        output_tokens: get("output_tokens"),
-        cache_read_input_tokens: get("cache_read_input_tokens"),
-        cache_creation_input_tokens: get("cache_creation_input_tokens"),
-    })
-}
+        grants.grant(5, &granted.to_string_lossy()).unwrap();
+        session
+            .send_message_to_chat(target, "read it", messenger.clone())
+            .await
+            .expect("granted directory is readable");
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::domain::MessageRef;
-    use crate::model::types::{ModelCapabilities, ProviderKind, RunRequest, RunResult};
-    use async_trait::async_trait;
-    use serde_json::json;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::Mutex;
+        assert!(grants.revoke(5, &granted.to_string_lossy()).unwrap());
+        let err = session
+            .send_message_to_chat(target, "read it", messenger)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Security(_)), "{err}");
 
-    #[derive(Default)]
-    struct FakeModel {
-        cancels: AtomicUsize,
+        let add_dirs: Vec<_> = model.requests().into_iter().map(|r| r.add_dirs).collect();
+        assert_eq!(add_dirs, vec![vec![], vec![granted], vec![]]);
+        let _ = std::fs::remove_dir_all(&scratch);
     }
 
-    impl FakeModel {
-        fn cancel_calls(&self) -> usize {
-            self.cancels.load(Ordering::SeqCst)
+    #[tokio::test]
+    async fn idle_sessions_expire_only_when_enabled() {
+        let scratch = std::env::temp_dir().join(format!("ctb-session-idle-{}", std::process::id()));
+        std::fs::create_dir_all(&scratch).unwrap();
+        let mut cfg = (*test_config()).clone();
+        cfg.session_file = scratch.join("session.json");
+        cfg.session_idle_expiry = IdleExpiry::Ask;
+        cfg.session_idle_after = Duration::from_secs(3600);
+        let cfg = Arc::new(cfg);
+
+        let session = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()));
+        assert_eq!(
+            session.expired_idle().await,
+            None,
+            "no session, nothing to expire"
+        );
+        {
+            let mut st = session.state.lock().await;
+            st.session = Some(SessionRef {
+                provider: ProviderKind::ClaudeCli,
+                id: "idle-session".to_string(),
+            });
+            st.last_activity = Some((Utc::now() - chrono::Duration::hours(50)).to_rfc3339());
         }
+        let idle = session.expired_idle().await.expect("past the idle timeout");
+        assert!(idle >= Duration::from_secs(50 * 3600), "{idle:?}");
+
+        // The last activity survives a restart.
+        session.save_session("idle-session").await.unwrap();
+        let restored = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()));
+        assert!(restored.resume_last(&crate::i18n::EN).await.unwrap().0);
+        assert!(restored.expired_idle().await.is_some());
+        restored.touch_activity().await;
+        assert_eq!(restored.expired_idle().await, None);
+
+        let mut off = (*cfg).clone();
+        off.session_idle_expiry = IdleExpiry::Off;
+        let disabled = ClaudeSession::new(Arc::new(off), Arc::new(ScriptedModel::new()));
+        assert!(disabled.resume_last(&crate::i18n::EN).await.unwrap().0);
+        assert_eq!(disabled.expired_idle().await, None);
+        let _ = std::fs::remove_dir_all(&scratch);
     }
 
-    #[async_trait]
-    impl ModelClient for FakeModel {
-        fn provider(&self) -> ProviderKind {
-            ProviderKind::ClaudeCli
-        }
+    #[tokio::test]
+    async fn prompt_profile_is_appended_and_survives_restart() {
+        use crate::model::{
+            client::ClaudeCliPromptAdapter,
+            types::{ClaudeCliConfig, PermissionMode},
+        };
 
-        fn capabilities(&self) -> ModelCapabilities {
-            ModelCapabilities {
-                supports_streaming: true,
-                supports_tools: true,
-                supports_vision: true,
-                supports_thinking: true,
-                supports_mcp: true,
-            }
-        }
+        let scratch =
+            std::env::temp_dir().join(format!("ctb-session-prompt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&scratch);
+        std::fs::create_dir_all(scratch.join(".ctb/prompts")).unwrap();
+        let scratch = std::fs::canonicalize(scratch).unwrap();
+        std::fs::write(
+            scratch.join(".ctb/prompts/reviewer.md"),
+            "You are a terse code reviewer.\n",
+        )
+        .unwrap();
 
-        async fn run(
-            &self,
-            _req: RunRequest,
-            _on_event: &mut (dyn FnMut(ModelEvent) -> Result<()> + Send),
-        ) -> Result<RunResult> {
-            Err(Error::External(
-                "FakeModel::run not implemented for tests".to_string(),
-            ))
-        }
+        let mut cfg = (*test_config()).clone();
+        cfg.claude_working_dir = scratch.clone();
+        cfg.allowed_paths = vec![scratch.clone()];
+        cfg.session_file = scratch.join("session.json");
+        cfg.safety_prompt = "Stay safe.".to_string();
+        let cfg = Arc::new(cfg);
+        let target = ChatTarget::from(crate::domain::ChatId(5));
+        let settings_file = scratch.join("chat-settings.json");
+        let settings = || Arc::new(ChatSettings::load(settings_file.clone()));
+
+        let session = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()))
+            .with_chat_settings(settings());
+        assert!(session
+            .set_prompt_profile(target, Some("missing".to_string()))
+            .await
+            .is_err());
+        assert!(session
+            .set_prompt_profile(target, Some("../etc".to_string()))
+            .await
+            .is_err());
+        session
+            .set_prompt_profile(target, Some("reviewer".to_string()))
+            .await
+            .unwrap();
+
+        let model = Arc::new(ScriptedModel::new());
+        let restarted =
+            ClaudeSession::new(cfg.clone(), model.clone()).with_chat_settings(settings());
+        assert_eq!(
+            restarted.prompt_profile(target).as_deref(),
+            Some("reviewer")
+        );
+        let other = ChatTarget::from(crate::domain::ChatId(6));
+        assert_eq!(restarted.prompt_profile(other), None);
+        restarted
+            .send_message_to_chat(target, "hi", Arc::new(RecordingMessenger::new()))
+            .await
+            .unwrap();
 
-        async fn cancel(&self) -> Result<()> {
-            self.cancels.fetch_add(1, Ordering::SeqCst);
-            Ok(())
-        }
-    }
+        let req = model.requests()[0].clone();
+        assert_eq!(
+            req.append_system_prompt.as_deref(),
+            Some("You are a terse code reviewer.")
+        );
+        let adapter = ClaudeCliPromptAdapter {
+            cfg: ClaudeCliConfig {
+                claude_path: "/usr/bin/claude".into(),
+                model: None,
+                permission_mode: PermissionMode::Default,
+                dangerously_skip_permissions: false,
+                include_partial_messages: false,
+                kill_grace: Duration::from_millis(100),
+                allowed_tools: None,
+                disallowed_tools: None,
+                stderr_notices: Default::default(),
+            },
+        };
+        let args = adapter.build_invocation(&req).args;
+        let flags: Vec<_> = args
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| *a == "--append-system-prompt")
+            .collect();
+        assert_eq!(flags.len(), 1, "{args:?}");
+        assert_eq!(
+            args[flags[0].0 + 1],
+            "Stay safe.\n\nYou are a terse code reviewer."
+        );
 
-    #[derive(Default)]
-    struct FakeMessenger {
-        next_id: Mutex<i32>,
-        sends: Mutex<Vec<String>>,
-        keyboards: Mutex<Vec<(crate::domain::ChatId, String, InlineKeyboard)>>,
+        restarted.set_prompt_profile(target, None).await.unwrap();
+        let cleared = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new()))
+            .with_chat_settings(settings());
+        assert_eq!(cleared.prompt_profile(target), None);
+        let _ = std::fs::remove_dir_all(&scratch);
     }
 
-    impl FakeMessenger {
-        fn alloc(&self, chat_id: crate::domain::ChatId) -> MessageRef {
-            use crate::domain::MessageId;
-            let mut guard = self.next_id.lock().unwrap();
-            if *guard == 0 {
-                *guard = 1;
-            }
-            let id = *guard;
-            *guard += 1;
-            MessageRef {
-                chat_id,
-                message_id: MessageId(id),
-            }
-        }
+    #[tokio::test]
+    async fn sandbox_wrapper_is_passed_to_the_cli() {
+        use crate::model::client::{bash_wrapper_instruction, ClaudeCliPromptAdapter};
+        use crate::model::types::ClaudeCliConfig;
+
+        let mut cfg = (*test_config()).clone();
+        cfg.safety_prompt = "Stay safe.".to_string();
+        cfg.bash_sandbox_wrapper = Some("firejail --quiet".to_string());
+        let model = Arc::new(ScriptedModel::new());
+        let session = ClaudeSession::new(Arc::new(cfg), model.clone());
+        let target = ChatTarget::from(crate::domain::ChatId(8));
+        session
+            .send_message_to_chat(target, "build it", Arc::new(RecordingMessenger::new()))
+            .await
+            .unwrap();
+        let req = model.requests()[0].clone();
+        assert_eq!(req.bash_wrapper.as_deref(), Some("firejail --quiet"));
+
+        let adapter = |mode, skip| ClaudeCliPromptAdapter {
+            cfg: ClaudeCliConfig {
+                claude_path: "/usr/bin/claude".into(),
+                model: None,
+                permission_mode: mode,
+                dangerously_skip_permissions: skip,
+                include_partial_messages: false,
+                kill_grace: Duration::from_millis(100),
+                allowed_tools: None,
+                disallowed_tools: None,
+                stderr_notices: Default::default(),
+            },
+        };
+        let flag = |args: &[String], name: &str| {
+            let i = args.iter().position(|a| a == name)?;
+            Some(args[i + 1].clone())
+        };
 
-        fn sent_html(&self) -> Vec<String> {
-            self.sends.lock().unwrap().clone()
-        }
+        // The directive follows the safety prompt; with permission prompts on, only wrapped
+        // Bash is pre-approved.
+        let args = adapter(PermissionMode::Default, false)
+            .build_invocation(&req)
+            .args;
+        assert_eq!(
+            flag(&args, "--append-system-prompt").unwrap(),
+            format!(
+                "Stay safe.\n\n{}",
+                bash_wrapper_instruction("firejail --quiet")
+            )
+        );
+        let settings: serde_json::Value =
+            serde_json::from_str(&flag(&args, "--settings").unwrap()).unwrap();
+        assert_eq!(
+            settings["permissions"]["allow"],
+            serde_json::json!(["Bash(firejail --quiet:*)"])
+        );
 
-        fn keyboard_sends(&self) -> Vec<(crate::domain::ChatId, String, InlineKeyboard)> {
-            self.keyboards.lock().unwrap().clone()
-        }
+        // Skipped permissions ignore allow rules, so only the directive is sent.
+        let args = adapter(PermissionMode::BypassPermissions, true)
+            .build_invocation(&req)
+            .args;
+        assert!(flag(&args, "--append-system-prompt")
+            .unwrap()
+            .contains("`firejail --quiet sh -c '...'`"));
+        assert_eq!(flag(&args, "--settings"), None);
+
+        // Without a wrapper nothing changes.
+        let plain = RunRequest {
+            bash_wrapper: None,
+            allowed_tools: None,
+            disallowed_tools: None,
+            ..req
+        };
+        let args = adapter(PermissionMode::Default, false)
+            .build_invocation(&plain)
+            .args;
+        assert_eq!(flag(&args, "--append-system-prompt").unwrap(), "Stay safe.");
+        assert_eq!(flag(&args, "--settings"), None);
     }
 
-    #[async_trait]
-    impl MessagingPort for FakeMessenger {
-        fn capabilities(&self) -> crate::messaging::types::MessagingCapabilities {
-            crate::messaging::types::MessagingCapabilities {
-                supports_html: true,
-                supports_edit: true,
-                supports_reactions: true,
-                supports_chat_actions: true,
-                supports_inline_keyboards: true,
-                max_message_len: 4096,
-            }
-        }
-
-        async fn send_html(
-            &self,
-            chat_id: crate::domain::ChatId,
-            html: &str,
-        ) -> Result<MessageRef> {
-            self.sends.lock().unwrap().push(html.to_string());
-            Ok(self.alloc(chat_id))
-        }
-
-        async fn edit_html(&self, _msg: MessageRef, _html: &str) -> Result<()> {
-            Ok(())
-        }
-
-        async fn delete_message(&self, _msg: MessageRef) -> Result<()> {
-            Ok(())
-        }
-
-        async fn send_chat_action(
-            &self,
-            _chat_id: crate::domain::ChatId,
-            _action: crate::messaging::types::ChatAction,
-        ) -> Result<()> {
-            Ok(())
-        }
-
-        async fn set_reaction(&self, _msg: MessageRef, _emoji: &str) -> Result<()> {
-            Ok(())
-        }
-
-        async fn send_inline_keyboard(
-            &self,
-            chat_id: crate::domain::ChatId,
-            text: &str,
-            keyboard: InlineKeyboard,
-        ) -> Result<MessageRef> {
-            self.keyboards
-                .lock()
-                .unwrap()
-                .push((chat_id, text.to_string(), keyboard));
-            Ok(self.alloc(chat_id))
-        }
+    #[tokio::test]
+    async fn plan_turn_runs_in_plan_mode_and_waits_for_review() {
+        use crate::model::{client::ClaudeCliPromptAdapter, types::ClaudeCliConfig};
+        use crate::plan_mode::EXECUTE_PLAN_PROMPT;
 
-        async fn answer_callback_query(
-            &self,
-            _callback_id: &str,
-            _text: Option<&str>,
-        ) -> Result<()> {
-            Ok(())
-        }
-    }
+        let model = Arc::new(ScriptedModel::new());
+        let session = ClaudeSession::new(test_config(), model.clone());
+        session.state.lock().await.session = Some(SessionRef {
+            provider: ProviderKind::ClaudeCli,
+            id: "s-plan".to_string(),
+        });
+        let target = ChatTarget::from(crate::domain::ChatId(7));
+        let messenger = Arc::new(RecordingMessenger::new());
+        let alice = RunUser {
+            user_id: 5,
+            username: "alice".to_string(),
+            role: Role::Full,
+        };
 
-    fn test_config() -> Arc<Config> {
-        use std::time::Duration;
-        Arc::new(Config {
-            telegram_bot_token: "x".to_string(),
-            telegram_allowed_users: vec![1],
-            claude_working_dir: "/tmp".into(),
-            openai_api_key: None,
-            transcription_prompt: "x".to_string(),
-            transcription_available: false,
-            claude_cli_path: "/usr/bin/claude".into(),
-            claude_config_dir: None,
-            allowed_paths: vec!["/tmp".into()],
-            temp_paths: vec!["/tmp/".into()],
-            blocked_patterns: vec!["rm -rf /".to_string()],
-            safety_prompt: "x".to_string(),
-            query_timeout: Duration::from_secs(1),
-            temp_dir: "/tmp".into(),
-            session_file: "/tmp/claude-telegram-session.json".into(),
-            restart_file: "/tmp/claude-telegram-restart.json".into(),
-            telegram_message_limit: 4096,
-            telegram_safe_limit: 4000,
-            streaming_throttle: Duration::from_millis(0),
-            button_label_max_length: 30,
-            default_thinking_tokens: 0,
-            thinking_keywords: vec![],
-            thinking_deep_keywords: vec![],
-            delete_thinking_messages: false,
-            delete_tool_messages: false,
-            audit_log_path: "/tmp/a.log".into(),
-            audit_log_json: false,
-            rate_limit_enabled: false,
-            rate_limit_requests: 20,
-            rate_limit_window: Duration::from_secs(60),
-            media_group_timeout: Duration::from_millis(1000),
-        })
+        session
+            .plan_message_to_chat_as(target, "refactor it", messenger.clone(), alice.clone())
+            .await
+            .unwrap();
+        let req = model.requests()[0].clone();
+        assert_eq!(req.permission_mode, Some(PermissionMode::Plan));
+        assert_eq!(
+            req.append_system_prompt.as_deref(),
+            Some(PLAN_ONLY_INSTRUCTION)
+        );
+        let keyboards = messenger.keyboards();
+        assert_eq!(keyboards.len(), 1);
+        assert_eq!(keyboards[0].1, crate::i18n::EN.plan_ready());
+        let data: Vec<_> = keyboards[0]
+            .2
+            .rows
+            .iter()
+            .flatten()
+            .map(|b| b.callback_data.as_str())
+            .collect();
+        assert_eq!(data, ["plan:execute", "plan:discard"]);
+
+        // Plan mode replaces the provider's skip-permissions flag.
+        let adapter = ClaudeCliPromptAdapter {
+            cfg: ClaudeCliConfig {
+                claude_path: "/usr/bin/claude".into(),
+                model: None,
+                permission_mode: PermissionMode::BypassPermissions,
+                dangerously_skip_permissions: true,
+                include_partial_messages: false,
+                kill_grace: Duration::from_millis(100),
+                allowed_tools: None,
+                disallowed_tools: None,
+                stderr_notices: Default::default(),
+            },
+        };
+        let args = adapter.build_invocation(&req).args;
+        let mode = args.iter().position(|a| a == "--permission-mode").unwrap();
+        assert_eq!(args[mode + 1], "plan");
+        assert!(!args.contains(&"--dangerously-skip-permissions".to_string()));
+
+        // Only the requester and the owner may review it.
+        assert!(session.may_review_plan(target, 5).await);
+        assert!(session.may_review_plan(target, 1).await);
+        assert!(!session.may_review_plan(target, 7).await);
+
+        // Approving runs with normal permissions and claims the plan once.
+        assert!(session.take_pending_plan(target).await);
+        assert!(!session.take_pending_plan(target).await);
+        session
+            .send_message_to_chat_as(
+                target,
+                EXECUTE_PLAN_PROMPT,
+                messenger.clone(),
+                alice.clone(),
+            )
+            .await
+            .unwrap();
+        let req = model.requests()[1].clone();
+        assert_eq!(req.permission_mode, None);
+        assert_eq!(req.append_system_prompt, None);
+        assert_eq!(messenger.keyboards().len(), 1);
+
+        // A plan made in a session that has since been replaced is not executed.
+        session
+            .plan_message_to_chat_as(target, "again", messenger.clone(), alice)
+            .await
+            .unwrap();
+        session.state.lock().await.session = Some(SessionRef {
+            provider: ProviderKind::ClaudeCli,
+            id: "s-other".to_string(),
+        });
+        assert!(!session.take_pending_plan(target).await);
     }
 
-    fn assistant_raw(session_id: &str, blocks: Vec<serde_json::Value>) -> serde_json::Value {
-        json!({
-          "session_id": session_id,
-          "message": { "content": blocks }
-        })
+    /// Replays the compaction fixture as one run.
+    fn compacting_model() -> Arc<ScriptedModel> {
+        let usage = TokenUsage {
+            input_tokens: 9_000,
+            output_tokens: 100,
+            ..Default::default()
+        };
+        Arc::new(
+            ScriptedModel::new()
+                .emitting([
+                    ModelEvent::ContextCompacted {
+                        raw: json!({"type":"system","subtype":"compact_boundary","compact_metadata":{"trigger":"auto","pre_tokens":185_000}}),
+                    },
+                    ModelEvent::Result {
+                        raw: json!({"type":"result","result":"summarized","usage":usage}),
+                    },
+                ])
+                .answering("summarized")
+                .with_usage(usage),
+        )
     }
 
     #[tokio::test]
-    async fn text_snapshot_prefix_diff_dedupes() {
-        let cfg = test_config();
-        let model = Arc::new(FakeModel::default());
-        let messenger = Arc::new(FakeMessenger::default());
-        let mut p = EventPipeline::new(cfg, model, messenger, crate::domain::ChatId(1));
-
-        p.handle_event(ModelEvent::Assistant {
-            raw: assistant_raw("s1", vec![json!({"type":"text","text":"hello"})]),
-        })
-        .await
-        .unwrap();
-        p.handle_event(ModelEvent::Assistant {
-            raw: assistant_raw("s1", vec![json!({"type":"text","text":"hello world"})]),
-        })
-        .await
-        .unwrap();
+    async fn compaction_restarts_context_tracking() {
+        let session = ClaudeSession::new(test_config(), compacting_model());
+        session
+            .accumulate_usage(&TokenUsage {
+                input_tokens: 180_000,
+                output_tokens: 5_000,
+                ..Default::default()
+            })
+            .await;
+        assert!(session.needs_save().await);
 
-        assert_eq!(p.current_segment_text, "hello world");
-        assert_eq!(p.response_parts.join(""), "hello world");
+        let messenger = Arc::new(RecordingMessenger::new());
+        let out = session
+            .send_message_to_chat(
+                ChatTarget::from(crate::domain::ChatId(1)),
+                "go on",
+                messenger.clone(),
+            )
+            .await
+            .unwrap();
+
+        assert!(out.context_compacted);
+        messenger.assert_sent_containing("♻️ Context compacted");
+        assert!(!session.needs_save().await);
+        assert_eq!(session.current_context_tokens().await, 9_100);
+        let stats = session.stats().await;
+        assert_eq!(stats.compactions, 1);
+        assert_eq!(
+            stats.total_input_tokens, 189_000,
+            "cost totals keep counting"
+        );
     }
 
     #[tokio::test]
-    async fn tool_use_splits_segments_and_formats_status() {
-        let cfg = test_config();
-        let model = Arc::new(FakeModel::default());
-        let messenger = Arc::new(FakeMessenger::default());
-        let mut p = EventPipeline::new(cfg, model, messenger.clone(), crate::domain::ChatId(1));
-
-        p.handle_event(ModelEvent::Assistant {
-            raw: assistant_raw("s1", vec![json!({"type":"text","text":"hi"})]),
-        })
-        .await
-        .unwrap();
-
-        p.handle_event(ModelEvent::Assistant {
-      raw: assistant_raw(
-        "s1",
-        vec![json!({"type":"tool_use","name":"Write","input":{"file_path":"/tmp/x.txt","content":"hello"}})],
-      ),
-    })
-    .await
-    .unwrap();
-
-        assert_eq!(p.current_segment_id, 1);
-        assert!(p.current_segment_text.is_empty());
+    async fn turn_summary_is_priced_for_the_session_model() {
+        let mut cfg = (*test_config()).clone();
+        cfg.session_file = format!("/tmp/ctb-session-summary-{}.json", std::process::id()).into();
+        cfg.turn_summary = true;
+        let cfg = Arc::new(cfg);
+        let session = ClaudeSession::new(cfg.clone(), compacting_model());
+        session.set_model(Some("opus".to_string())).await.unwrap();
+        let messenger = Arc::new(RecordingMessenger::new());
+        session
+            .send_message_to_chat(
+                ChatTarget::from(crate::domain::ChatId(1)),
+                "go on",
+                messenger.clone(),
+            )
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(&cfg.session_file);
+
+        let sends = messenger.sends();
+        let footer = sends.last().unwrap();
+        assert!(footer.starts_with("⏱ "), "{footer}");
+        // 9k in / 100 out at Opus rates is ~$0.05 (Sonnet's would be ~$0.03).
+        assert!(footer.ends_with(" · ↑9.0k ↓0.1k tok · ~$0.05"), "{footer}");
+    }
 
-        let sent = messenger.sent_html();
-        assert!(
-            sent.iter().any(|s| s.contains("hi")),
-            "expected a segment_end message containing hi"
-        );
+    #[tokio::test]
+    async fn failed_turns_still_get_a_summary() {
+        let mut cfg = (*test_config()).clone();
+        cfg.turn_summary = true;
+        let model = ScriptedModel::new().erroring("the CLI crashed");
+        let session = ClaudeSession::new(Arc::new(cfg), Arc::new(model));
+        let messenger = Arc::new(RecordingMessenger::new());
+        let result = session
+            .send_message_to_chat(
+                ChatTarget::from(crate::domain::ChatId(1)),
+                "go on",
+                messenger.clone(),
+            )
+            .await;
+        assert!(result.is_err());
         assert!(
-            sent.iter().any(|s| s.contains("Writing")),
-            "expected a tool status message for Write"
+            messenger.sends().iter().any(|s| s.starts_with("⏱ ")),
+            "{:?}",
+            messenger.sends()
         );
     }
 
     #[tokio::test]
-    async fn bash_unsafe_command_is_blocked_and_cancels() {
-        let cfg = test_config();
-        let model = Arc::new(FakeModel::default());
-        let messenger = Arc::new(FakeMessenger::default());
-        let mut p = EventPipeline::new(
-            cfg,
-            model.clone(),
-            messenger.clone(),
-            crate::domain::ChatId(1),
+    async fn sessions_are_titled_renamed_resumed_and_deleted() {
+        let scratch =
+            std::env::temp_dir().join(format!("ctb-session-history-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&scratch);
+        let mut cfg = (*test_config()).clone();
+        cfg.state_dir = scratch.clone();
+        cfg.session_file = scratch.join("session.json");
+        let cfg = Arc::new(cfg);
+        let model = Arc::new(
+            ScriptedModel::new()
+                .emitting([ModelEvent::Assistant {
+                    raw: json!({"message": {"content": [{"type":"text","text":"ok"}]}}),
+                }])
+                .answering("ok")
+                .numbering_sessions(),
+        );
+        let target = ChatTarget::from(crate::domain::ChatId(1));
+        let messenger = Arc::new(RecordingMessenger::new());
+        let session = ClaudeSession::new(cfg.clone(), model.clone());
+        let titles = |session: &ClaudeSession| -> Vec<(String, Option<String>)> {
+            let history = session.history(target).unwrap();
+            history
+                .entries()
+                .iter()
+                .map(|e| (e.session_id.clone(), e.title.clone()))
+                .collect()
+        };
+
+        for prompt in ["Fix the *flaky* upload test in CI please", "and the docs"] {
+            session
+                .send_message_to_chat(target, prompt, messenger.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            titles(&session),
+            [("s-1".into(), Some("Fix the flaky upload test in".into()))]
+        );
+        assert_eq!(
+            session
+                .set_current_title(target, "  Upload\nflake ")
+                .await
+                .unwrap(),
+            "Upload flake"
+        );
+        session.kill().await.unwrap();
+        assert!(session.set_current_title(target, "nope").await.is_err());
+        session
+            .send_message_to_chat(target, "Write release notes", messenger.clone())
+            .await
+            .unwrap();
+
+        let restarted = ClaudeSession::new(cfg.clone(), model.clone());
+        assert_eq!(
+            titles(&restarted),
+            [
+                ("s-2".into(), Some("Write release notes".into())),
+                ("s-1".into(), Some("Upload flake".into())),
+            ]
+        );
+        // Another chat sharing the default session has its own (empty) list.
+        let other = ChatTarget::from(crate::domain::ChatId(2));
+        assert!(restarted.history(other).unwrap().is_empty());
+        assert!(
+            !restarted
+                .resume_session(other, "s-1", &crate::i18n::EN)
+                .await
+                .unwrap()
+                .0
         );
 
-        let err = p
-      .handle_event(ModelEvent::Assistant {
-        raw: assistant_raw(
-          "s1",
-          vec![json!({"type":"tool_use","name":"Bash","input":{"command":"rm /etc/passwd"}})],
-        ),
-      })
-      .await
-      .unwrap_err();
-
-        assert!(matches!(err, Error::Security(_)));
-        assert_eq!(model.cancel_calls(), 1);
+        let (resumed, msg) = restarted
+            .resume_session(target, "s-1", &crate::i18n::EN)
+            .await
+            .unwrap();
+        assert!(resumed, "{msg}");
+        assert_eq!(
+            restarted.title(target).await.as_deref(),
+            Some("Upload flake")
+        );
+        assert!(restarted.delete_saved_session(target, "s-2").unwrap());
         assert!(
-            messenger.sent_html().iter().any(|s| s.contains("BLOCKED:")),
-            "expected a BLOCKED tool message"
+            !restarted
+                .resume_session(target, "s-2", &crate::i18n::EN)
+                .await
+                .unwrap()
+                .0
         );
+        assert_eq!(titles(&restarted).len(), 1);
+        let _ = std::fs::remove_dir_all(&scratch);
     }
 
     #[tokio::test]
-    async fn ask_user_scans_tmp_sends_keyboard_and_marks_sent() {
-        let cfg = test_config();
-        let model = Arc::new(FakeModel::default());
-        let messenger = Arc::new(FakeMessenger::default());
-
-        let path = std::path::Path::new("/tmp/ask-user-test.json");
-        let payload = json!({
-          "status": "pending",
-          "chat_id": 1,
-          "question": "Pick one",
-          "options": ["a", "b"],
-          "request_id": "req123"
-        });
-        std::fs::write(path, serde_json::to_string(&payload).unwrap()).unwrap();
+    async fn slow_messenger_backpressures_the_model_without_losing_events() {
+        let mut cfg = (*test_config()).clone();
+        cfg.session_file = format!("/tmp/ctb-session-flood-{}.json", std::process::id()).into();
+        cfg.event_queue_depth = 4;
+        let cfg = Arc::new(cfg);
+        let metrics = crate::metrics::ProcessMetrics::new();
+        let session = ClaudeSession::new(cfg.clone(), Arc::new(ScriptedModel::new().answering("").emitting((0..30).map(|i| {
+            ModelEvent::Assistant {
+                raw: json!({
+                  "session_id": "s-flood",
+                  "message": {"id": format!("m{i}"), "content": [{"type": "text", "text": format!("chunk {i}")}]}
+                }),
+            }
+        }))))
+            .with_metrics(metrics.clone());
+        let messenger = Arc::new(RecordingMessenger::new());
+        messenger.set_latency(Duration::from_millis(5));
+
+        let out = session
+            .send_message_to_chat(
+                ChatTarget::from(crate::domain::ChatId(1)),
+                "go",
+                messenger.clone(),
+            )
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(&cfg.session_file);
 
-        let mut p = EventPipeline::new(
-            cfg,
-            model.clone(),
-            messenger.clone(),
-            crate::domain::ChatId(1),
+        assert_eq!(
+            metrics.peak_value(Peak::EventQueueDepth),
+            4,
+            "the reader ran ahead until the queue was full, and no further"
         );
-        p.handle_event(ModelEvent::Assistant {
-            raw: assistant_raw(
-                "s1",
-                vec![json!({"type":"tool_use","name":"mcp__ask-user__askUser","input":{}})],
-            ),
-        })
-        .await
-        .unwrap();
-
-        let out = p.finish().await.unwrap();
-        assert!(out.waiting_for_user);
-        assert_eq!(model.cancel_calls(), 1);
-
-        let keyboards = messenger.keyboard_sends();
-        assert!(!keyboards.is_empty(), "expected an inline keyboard send");
-
-        let updated: serde_json::Value =
-            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
-        assert_eq!(updated.get("status").and_then(|s| s.as_str()), Some("sent"));
+        let expected: Vec<String> = (0..30).map(|i| format!("chunk {i}")).collect();
+        let mut rest = out.text.as_str();
+        for chunk in &expected {
+            let at = rest
+                .find(chunk.as_str())
+                .unwrap_or_else(|| panic!("{chunk} missing or out of order"));
+            rest = &rest[at + chunk.len()..];
+        }
     }
 
     #[tokio::test]
-    async fn parses_doc_fixtures_into_pipeline_output() {
-        let cfg = test_config();
-        let model = Arc::new(FakeModel::default());
-        let messenger = Arc::new(FakeMessenger::default());
-
-        let base = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join("../../../docs/rust-port/fixtures");
-
-        for (fixture_name, expected) in [
-            (
-                "claude-stream-json.sample.jsonl",
-                "API Error: Connection error.",
-            ),
-            (
-                "claude-stream-json.invalid-api-key.jsonl",
-                "Invalid API key · Fix external API key",
-            ),
-            ("claude-stream-json.synthetic-tool-use.jsonl", "done"),
-        ] {
-            let txt = std::fs::read_to_string(base.join(fixture_name)).unwrap();
-
-            let mut p = EventPipeline::new(
-                cfg.clone(),
-                model.clone(),
-                messenger.clone(),
-                crate::domain::ChatId(1),
-            );
-            for line in txt.lines().filter(|l| !l.trim().is_empty()) {
-                let raw: serde_json::Value = serde_json::from_str(line).unwrap();
-                let ty = raw.get("type").and_then(|t| t.as_str()).unwrap_or("");
-                let ev = match ty {
-                    "system" => ModelEvent::SystemInit { raw },
-                    "assistant" => ModelEvent::Assistant { raw },
-                    "result" => ModelEvent::Result { raw },
-                    _ => ModelEvent::Unknown { raw },
-                };
-                p.handle_event(ev).await.unwrap();
-            }
-            let out = p.finish().await.unwrap();
-            assert!(!out.waiting_for_user);
-            assert!(
-                out.text.contains(expected),
-                "fixture {fixture_name} expected text to contain: {expected}, got: {}",
-                out.text
-            );
+    async fn context_pressure_needs_save_and_warns_once() {
+        let session = ClaudeSession::new(test_config(), Arc::new(
+            ScriptedModel::new()
+                .emitting([ModelEvent::Result {
+                    raw: json!({"type":"result","subtype":"success","is_error":true,"result":"Prompt is too long"}),
+                }])
+                .failing("Prompt is too long"),
+        ));
+        let messenger = Arc::new(RecordingMessenger::new());
+        let target = ChatTarget::from(crate::domain::ChatId(1));
+        assert!(!session.needs_save().await);
+
+        for _ in 0..2 {
+            let out = session
+                .send_message_to_chat(target, "go on", messenger.clone())
+                .await
+                .unwrap();
+            assert_eq!(out.context_pressure, Some(ContextPressure::Exhausted));
+            assert!(session.needs_save().await);
         }
+        let warnings = messenger
+            .sends()
+            .iter()
+            .filter(|s| **s == crate::i18n::EN.context_limit_warning())
+            .count();
+        assert_eq!(warnings, 1);
     }
 }
@@ -0,0 +1,189 @@
+//! Saved conversations per chat or topic, for `/sessions`.
+//!
+//! Every session a chat runs is recorded here next to the single `/resume` pointer: its id,
+//! working directory, when it started and was last used, and a short title. The title comes from
+//! the first prompt of the session and can be replaced with `/title`. Entries are kept newest
+//! first and capped at [`SESSION_HISTORY_MAX`].
+
+use serde::{Deserialize, Serialize};
+
+/// Saved sessions kept per chat or topic; the least recently used ones are dropped.
+pub const SESSION_HISTORY_MAX: usize = 20;
+/// Words of the first prompt used for a session's title.
+pub const TITLE_MAX_WORDS: usize = 6;
+/// Longest title, in characters (derived and `/title` alike).
+pub const TITLE_MAX_CHARS: usize = 60;
+
+/// Marker closing the reply context block in front of a prompt.
+const REPLY_CONTEXT_END: &str = "[End of replied-to message]";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub session_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub working_dir: String,
+    pub created_at: String,
+    pub last_used_at: String,
+}
+
+/// A chat's saved sessions, most recently used first.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SessionHistory {
+    entries: Vec<SavedSession>,
+}
+
+impl SessionHistory {
+    pub fn entries(&self) -> &[SavedSession] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<&SavedSession> {
+        self.entries.iter().find(|e| e.session_id == session_id)
+    }
+
+    /// Record a use of `session_id` at `now`, moving it to the front (new sessions are added).
+    pub fn touch(&mut self, session_id: &str, working_dir: &str, now: &str) {
+        let entry = match self.entries.iter().position(|e| e.session_id == session_id) {
+            Some(i) => {
+                let mut entry = self.entries.remove(i);
+                entry.working_dir = working_dir.to_string();
+                entry.last_used_at = now.to_string();
+                entry
+            }
+            None => SavedSession {
+                session_id: session_id.to_string(),
+                title: None,
+                working_dir: working_dir.to_string(),
+                created_at: now.to_string(),
+                last_used_at: now.to_string(),
+            },
+        };
+        self.entries.insert(0, entry);
+        self.entries.truncate(SESSION_HISTORY_MAX);
+    }
+
+    /// Set the title of `session_id`; `false` when it isn't in the history.
+    pub fn set_title(&mut self, session_id: &str, title: String) -> bool {
+        match self.entries.iter_mut().find(|e| e.session_id == session_id) {
+            Some(entry) => {
+                entry.title = Some(title);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget `session_id`; `false` when it wasn't in the history.
+    pub fn remove(&mut self, session_id: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.session_id != session_id);
+        self.entries.len() != before
+    }
+}
+
+/// One-line title: control characters dropped, whitespace collapsed, capped at
+/// [`TITLE_MAX_CHARS`]. `None` when nothing is left.
+pub fn sanitize_title(text: &str) -> Option<String> {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|w| w.chars().filter(|c| !c.is_control()).collect::<String>())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let title = words.join(" ");
+    let mut chars = title.chars();
+    let mut capped: String = chars.by_ref().take(TITLE_MAX_CHARS).collect();
+    if chars.next().is_some() {
+        capped = format!("{}…", capped.trim_end());
+    }
+    (!capped.is_empty()).then_some(capped)
+}
+
+/// Title for a session started with `prompt`: its first [`TITLE_MAX_WORDS`] words, after the
+/// bot's bracketed context lines (reply quote, forward header) and Markdown markers.
+pub fn derive_title(prompt: &str) -> Option<String> {
+    let body = prompt
+        .rsplit_once(REPLY_CONTEXT_END)
+        .map_or(prompt, |(_, rest)| rest);
+    let text: Vec<&str> = body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !(line.starts_with('[') && line.ends_with(']')))
+        .collect();
+    let text = text.join(" ");
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c: char| matches!(c, '*' | '_' | '`' | '#' | '>' | '~')))
+        .filter(|w| !w.is_empty())
+        .take(TITLE_MAX_WORDS)
+        .collect();
+    sanitize_title(&words.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_take_the_first_words_of_the_prompt() {
+        assert_eq!(
+            derive_title("Please **refactor** the   storage layer into two modules today"),
+            Some("Please refactor the storage layer into".to_string())
+        );
+        assert_eq!(
+            derive_title(
+                "[Forwarded from @bob, originally posted 2024-05-01]\n`cargo test` fails on CI"
+            ),
+            Some("cargo test fails on CI".to_string())
+        );
+        assert_eq!(
+            derive_title(
+                "[Replying to you]\nold answer\n[End of replied-to message]\n\nwhy is that?"
+            ),
+            Some("why is that?".to_string())
+        );
+        assert_eq!(derive_title("  \n*** \n"), None);
+        assert_eq!(
+            sanitize_title(&format!("a\u{7}b {}", "x".repeat(80))),
+            Some(format!("ab {}…", "x".repeat(57)))
+        );
+    }
+
+    #[test]
+    fn history_keeps_the_most_recent_sessions_first() {
+        let mut history = SessionHistory::default();
+        history.touch("a", "/w", "t1");
+        history.touch("b", "/w", "t2");
+        assert!(history.set_title("a", "first".to_string()));
+        history.touch("a", "/w2", "t3");
+        let ids: Vec<_> = history
+            .entries()
+            .iter()
+            .map(|e| e.session_id.as_str())
+            .collect();
+        assert_eq!(ids, ["a", "b"]);
+        let a = history.get("a").unwrap();
+        assert_eq!(
+            (
+                a.title.as_deref(),
+                a.created_at.as_str(),
+                a.working_dir.as_str()
+            ),
+            (Some("first"), "t1", "/w2")
+        );
+
+        for i in 0..SESSION_HISTORY_MAX {
+            history.touch(&format!("s{i}"), "/w", "t4");
+        }
+        assert_eq!(history.entries().len(), SESSION_HISTORY_MAX);
+        assert!(history.get("a").is_none());
+        assert!(history.remove("s0"));
+        assert!(!history.remove("s0"));
+        assert!(!history.set_title("s0", "gone".to_string()));
+    }
+}
@@ -0,0 +1,156 @@
+//! Graceful shutdown (SIGINT/SIGTERM).
+//!
+//! `ctb::main` owns the OS signal handling and flips a shared [`Shutdown`] trigger. In-flight
+//! event pipelines observe the trigger and replace their "Working..." spinner with
+//! the chat's shutdown notice; [`drain`] cancels every model run, stops the scheduler and waits (bounded)
+//! for turns to wind down so observed session ids are persisted for `/resume`.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::{scheduler::CronScheduler, session::SessionRegistry};
+
+/// How long `drain()` waits for in-flight turns after cancelling them.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Process-wide shutdown trigger (cheap to clone).
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once `trigger()` has been called.
+    pub async fn triggered(&self) {
+        self.token.cancelled().await
+    }
+}
+
+/// Coordinate shutdown: trigger, stop cron, cancel all runs, then wait up to `grace` for every
+/// session to go idle.
+///
+/// Returns `false` if some turn was still running when the grace period ran out.
+pub async fn drain(
+    shutdown: &Shutdown,
+    sessions: &SessionRegistry,
+    scheduler: Option<&CronScheduler>,
+    grace: Duration,
+) -> bool {
+    shutdown.trigger();
+    if let Some(scheduler) = scheduler {
+        scheduler.stop().await;
+    }
+    if let Err(e) = sessions.cancel_all_runs().await {
+        log_line!("[SHUTDOWN] Failed to cancel model runs: {e}");
+    }
+
+    let deadline = Instant::now() + grace;
+    while sessions.any_running().await {
+        if Instant::now() >= deadline {
+            log_line!("[SHUTDOWN] Grace period elapsed with queries still running");
+            return false;
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        domain::ChatId,
+        messaging::{
+            port::MessagingPort,
+            test_support::{RecordingMessenger, ScriptedModel},
+        },
+        model::types::ModelEvent,
+        session::ClaudeSession,
+    };
+
+    fn test_config(session_file: std::path::PathBuf) -> Config {
+        Config {
+            session_file,
+            ..Config::for_tests()
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_aborts_running_query_and_keeps_session() {
+        let session_file =
+            std::path::PathBuf::from(format!("/tmp/ctb-shutdown-{}.json", std::process::id()));
+        let cfg = Arc::new(test_config(session_file.clone()));
+        let shutdown = Shutdown::new();
+        // One assistant message carrying a session id, then the run hangs until cancelled.
+        let model = Arc::new(
+            ScriptedModel::new()
+                .emitting([ModelEvent::Assistant {
+                    raw: json!({
+                      "type": "assistant",
+                      "session_id": "sess-shutdown",
+                      "message": {"id": "m1", "content": [{"type": "text", "text": "Working on it"}]}
+                    }),
+                }])
+                .blocking(),
+        );
+        let session =
+            Arc::new(ClaudeSession::new(cfg, model.clone()).with_shutdown(shutdown.clone()));
+        let sessions = SessionRegistry::new(session.clone());
+        let messenger = Arc::new(RecordingMessenger::new());
+
+        let turn = {
+            let session = session.clone();
+            let messenger: Arc<dyn MessagingPort> = messenger.clone();
+            tokio::spawn(async move {
+                session
+                    .send_message_to_chat(ChatId(1).into(), "hi", messenger)
+                    .await
+            })
+        };
+        while model.started() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(drain(&shutdown, &sessions, None, SHUTDOWN_GRACE).await);
+        assert!(shutdown.is_triggered());
+        assert!(
+            turn.await.unwrap().is_err(),
+            "aborted query reports an error"
+        );
+        assert!(
+            messenger
+                .edited_html()
+                .iter()
+                .any(|e| e == crate::i18n::EN.shutdown_notice()),
+            "progress message should show the shutdown notice"
+        );
+
+        let saved = std::fs::read_to_string(&session_file).unwrap_or_default();
+        let _ = std::fs::remove_file(&session_file);
+        assert!(
+            saved.contains("sess-shutdown"),
+            "observed session id persisted"
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Per-chat bot state as one versioned JSON document (`/export_state`, `ctb import-state`).
+//!
+//! The document carries what a chat has set up over time: saved sessions (with their model,
+//! `/think` and `/prompt` selections), `/settings` overrides, `/grant` directories and `/vocab`
+//! terms. Configuration and credentials are never part of it; as a backstop, the bot token and
+//! API keys are redacted from the serialized document.
+//!
+//! Importing merges: each chat (or session key) in the document replaces that chat's state, and
+//! chats the document doesn't mention are left alone.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat_settings::{ChatOverrides, ChatSettings},
+    config::Config,
+    grants::{DirectoryGrants, Grant},
+    security::scrub_secrets_with,
+    storage::{StateStore, SESSION_NAMESPACE},
+    utils::iso_timestamp_utc,
+    vocab::Vocabulary,
+    Error, Result,
+};
+
+/// Format version written by this build; documents up to it can be imported.
+pub const STATE_EXPORT_VERSION: u32 = 1;
+/// File name of the exported document.
+pub const STATE_EXPORT_FILE_NAME: &str = "ctb-state.json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateExport {
+    pub version: u32,
+    pub exported_at: String,
+    /// Saved sessions by store key (`""` for the default session, `<chat>-t<thread>` for topics).
+    #[serde(default)]
+    pub sessions: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub chat_settings: BTreeMap<i64, ChatOverrides>,
+    #[serde(default)]
+    pub grants: BTreeMap<i64, Vec<Grant>>,
+    #[serde(default)]
+    pub vocabulary: BTreeMap<i64, Vec<String>>,
+}
+
+impl StateExport {
+    /// Pretty JSON with `cfg`'s secrets redacted.
+    pub fn to_json(&self, cfg: &Config) -> Result<String> {
+        let mut secrets = vec![cfg.telegram_bot_token.as_str()];
+        secrets.extend(cfg.openai_api_key.as_deref());
+        self.to_json_redacting(&secrets)
+    }
+
+    fn to_json_redacting(&self, secrets: &[&str]) -> Result<String> {
+        let json = serde_json::to_string_pretty(self)?;
+        Ok(scrub_secrets_with(&json, secrets, &[]))
+    }
+
+    /// Read a document, refusing ones without a version or from a newer build.
+    pub fn parse(txt: &str) -> Result<Self> {
+        let raw: serde_json::Value = serde_json::from_str(txt)?;
+        let version = raw
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| Error::Config("not a ctb state export (no version)".to_string()))?;
+        if version == 0 || version > u64::from(STATE_EXPORT_VERSION) {
+            return Err(Error::Config(format!(
+                "unsupported state export version {version} (this build reads 1 to \
+                 {STATE_EXPORT_VERSION})"
+            )));
+        }
+        Ok(serde_json::from_value(raw)?)
+    }
+}
+
+/// What an import restored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub sessions: usize,
+    pub chat_settings: usize,
+    pub grants: usize,
+    pub vocabulary: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} session(s), settings for {} chat(s), grants for {} chat(s), vocabulary for {} \
+             chat(s)",
+            self.sessions, self.chat_settings, self.grants, self.vocabulary
+        )
+    }
+}
+
+/// The stores a document is read from and restored into.
+pub struct BotState<'a> {
+    pub sessions: &'a dyn StateStore,
+    pub chat_settings: &'a ChatSettings,
+    pub grants: &'a DirectoryGrants,
+    pub vocabulary: &'a Vocabulary,
+}
+
+impl BotState<'_> {
+    /// Everything worth moving; an unreadable saved session is skipped with a warning.
+    pub fn export(&self) -> Result<StateExport> {
+        let mut sessions = BTreeMap::new();
+        for key in self.sessions.keys(SESSION_NAMESPACE)? {
+            let Some(raw) = self.sessions.get(SESSION_NAMESPACE, &key)? else {
+                continue;
+            };
+            match serde_json::from_str::<serde_json::Value>(&raw) {
+                Ok(value) if value.is_object() => {
+                    sessions.insert(key, value);
+                }
+                _ => log_line!("[STATE] Skipping unreadable session {key:?}"),
+            }
+        }
+        Ok(StateExport {
+            version: STATE_EXPORT_VERSION,
+            exported_at: iso_timestamp_utc(),
+            sessions,
+            chat_settings: self.chat_settings.all(),
+            grants: self.grants.all(),
+            vocabulary: self.vocabulary.all(),
+        })
+    }
+
+    /// Restore `doc`. It is checked as a whole first, so a bad entry writes nothing.
+    pub fn import(&self, doc: StateExport) -> Result<ImportSummary> {
+        for (key, value) in &doc.sessions {
+            if !is_session_key(key) {
+                return Err(Error::Config(format!("invalid session key {key:?}")));
+            }
+            if value.get("session_id").and_then(|v| v.as_str()).is_none() {
+                return Err(Error::Config(format!("session {key:?} has no session_id")));
+            }
+        }
+
+        let summary = ImportSummary {
+            sessions: doc.sessions.len(),
+            chat_settings: doc.chat_settings.len(),
+            grants: doc.grants.len(),
+            vocabulary: doc.vocabulary.len(),
+        };
+        for (key, value) in &doc.sessions {
+            self.sessions
+                .put(SESSION_NAMESPACE, key, &serde_json::to_string(value)?)?;
+        }
+        self.chat_settings.restore(doc.chat_settings)?;
+        self.grants.restore(doc.grants)?;
+        self.vocabulary.restore(doc.vocabulary)?;
+        Ok(summary)
+    }
+}
+
+/// `""` or `<chat id>-t<thread id>`; anything else could point outside the state directory.
+fn is_session_key(key: &str) -> bool {
+    if key.is_empty() {
+        return true;
+    }
+    let Some((chat, thread)) = key.split_once("-t") else {
+        return false;
+    };
+    let chat = chat.strip_prefix('-').unwrap_or(chat);
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    digits(chat) && digits(thread)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{i18n::Locale, storage::JsonFileStore};
+
+    struct Stores {
+        dir: PathBuf,
+        sessions: JsonFileStore,
+        chat_settings: ChatSettings,
+        grants: DirectoryGrants,
+        vocabulary: Vocabulary,
+    }
+
+    impl Stores {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("ctb-state-export-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self {
+                sessions: JsonFileStore::new(&dir),
+                chat_settings: ChatSettings::load(dir.join("chat-settings.json")),
+                grants: DirectoryGrants::load(dir.join("directory-grants.json"), None),
+                vocabulary: Vocabulary::load(dir.join("vocabulary.json")),
+                dir,
+            }
+        }
+
+        fn state(&self) -> BotState<'_> {
+            BotState {
+                sessions: &self.sessions,
+                chat_settings: &self.chat_settings,
+                grants: &self.grants,
+                vocabulary: &self.vocabulary,
+            }
+        }
+    }
+
+    impl Drop for Stores {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn state_round_trips_through_an_export_document() {
+        let old = Stores::new("old");
+        let session = serde_json::json!({
+            "provider": "claude_cli",
+            "session_id": "s-1",
+            "saved_at": "2026-01-01T00:00:00Z",
+            "working_dir": "/srv/work",
+            "model": "opus",
+            "thinking_tokens": 50000,
+            "prompt_profile": "reviewer"
+        });
+        old.sessions
+            .put(SESSION_NAMESPACE, "", &session.to_string())
+            .unwrap();
+        old.sessions
+            .put(SESSION_NAMESPACE, "-100-t7", r#"{"session_id":"s-topic"}"#)
+            .unwrap();
+        old.sessions
+            .put(SESSION_NAMESPACE, "-100-t8", "{truncated")
+            .unwrap();
+        old.chat_settings.set_locale(5, Some(Locale::It)).unwrap();
+        old.grants.grant(5, old.dir.to_str().unwrap()).unwrap();
+        old.vocabulary.add(5, "Kubernetes, GraphQL").unwrap();
+        // A token pasted into a session field is still redacted.
+        let token = "123456789:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        old.sessions
+            .put(
+                SESSION_NAMESPACE,
+                "-100-t9",
+                &serde_json::json!({"session_id": "s-9", "model": token}).to_string(),
+            )
+            .unwrap();
+
+        let json = old
+            .state()
+            .export()
+            .unwrap()
+            .to_json_redacting(&[token])
+            .unwrap();
+        assert!(!json.contains(token));
+        assert!(json.contains("[REDACTED]"));
+        let doc = StateExport::parse(&json).unwrap();
+        assert_eq!(doc.version, STATE_EXPORT_VERSION);
+        assert_eq!(
+            doc.sessions.keys().collect::<Vec<_>>(),
+            ["", "-100-t7", "-100-t9"],
+            "unreadable sessions are skipped"
+        );
+
+        let new = Stores::new("new");
+        new.vocabulary.add(9, "keep").unwrap();
+        let summary = new.state().import(doc.clone()).unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                sessions: 3,
+                chat_settings: 1,
+                grants: 1,
+                vocabulary: 1
+            }
+        );
+
+        let mut again = new.state().export().unwrap();
+        assert_eq!(again.vocabulary.remove(&9), Some(vec!["keep".to_string()]));
+        again.exported_at = doc.exported_at.clone();
+        assert_eq!(again, doc);
+        assert_eq!(new.sessions.get(SESSION_NAMESPACE, "").unwrap().unwrap(), {
+            serde_json::to_string(&session).unwrap()
+        });
+    }
+
+    #[test]
+    fn documents_are_versioned_and_checked_before_writing() {
+        assert!(StateExport::parse(r#"{"sessions":{}}"#).is_err());
+        assert!(StateExport::parse(r#"{"version":0,"exported_at":""}"#).is_err());
+        let newer = format!(
+            r#"{{"version":{},"exported_at":""}}"#,
+            STATE_EXPORT_VERSION + 1
+        );
+        let err = StateExport::parse(&newer).unwrap_err().to_string();
+        assert!(err.contains("unsupported state export version"), "{err}");
+        let minimal = StateExport::parse(r#"{"version":1,"exported_at":"x"}"#).unwrap();
+        assert!(minimal.sessions.is_empty() && minimal.grants.is_empty());
+
+        let stores = Stores::new("invalid");
+        for key in ["../../etc/passwd", "5", "-100-t", "x-t1"] {
+            let doc = StateExport {
+                sessions: BTreeMap::from([(
+                    key.to_string(),
+                    serde_json::json!({"session_id": "s"}),
+                )]),
+                vocabulary: BTreeMap::from([(1, vec!["term".to_string()])]),
+                ..minimal.clone()
+            };
+            assert!(stores.state().import(doc).is_err(), "{key}");
+        }
+        assert!(stores.vocabulary.all().is_empty(), "nothing written");
+        assert!(stores.sessions.keys(SESSION_NAMESPACE).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,660 @@
+//! Persistent bot state behind a key-value [`StateStore`] (`STATE_BACKEND`).
+//!
+//! Values are strings (JSON in practice) under a namespace (`session`, `session_history`,
+//! `restart`) and a key (empty for the single default entry, `<chat>-t<thread>` for a forum
+//! topic). Two backends:
+//!
+//! - [`JsonFileStore`] (default): one JSON file per entry, written to a temp file and renamed
+//!   into place so a crash mid-write never leaves a truncated file.
+//! - `SqliteStore` (`sqlite` feature): one `state.db` under `STATE_DIR`.
+//!
+//! [`StateStoreExt::get_json`] treats an unparseable value as corrupt: it is moved aside with a
+//! warning and the caller starts clean instead of failing on every read.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    config::{Config, StateBackend},
+    utils::iso_timestamp_utc,
+    Result,
+};
+
+/// `/resume` session pointer per chat or topic.
+pub const SESSION_NAMESPACE: &str = "session";
+/// `/sessions` list of saved conversations per chat or topic.
+pub const SESSION_HISTORY_NAMESPACE: &str = "session_history";
+/// `/restart` marker for the startup confirmation.
+pub const RESTART_NAMESPACE: &str = "restart";
+
+pub trait StateStore: Send + Sync {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>>;
+    /// Replace the value atomically: readers see the old value or the new one, never a mix.
+    fn put(&self, namespace: &str, key: &str, value: &str) -> Result<()>;
+    /// Remove the value; a missing one is not an error.
+    fn delete(&self, namespace: &str, key: &str) -> Result<()>;
+    /// Move an unreadable value out of the way, keeping it for inspection. Returns where it went.
+    fn quarantine(&self, namespace: &str, key: &str) -> Result<String>;
+    /// Keys with a value under `namespace`, sorted; the default entry is `""`.
+    fn keys(&self, namespace: &str) -> Result<Vec<String>>;
+}
+
+/// Typed helpers for every [`StateStore`].
+pub trait StateStoreExt: StateStore {
+    /// Read a JSON value. Missing or blank values are `None`; an unparseable one is quarantined
+    /// (with a warning) and also reads as `None`.
+    fn get_json<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> Result<Option<T>> {
+        let Some(txt) = self.get(namespace, key)? else {
+            return Ok(None);
+        };
+        if txt.trim().is_empty() {
+            return Ok(None);
+        }
+        match serde_json::from_str(&txt) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                let moved = self.quarantine(namespace, key)?;
+                log_line!(
+                    "[STORAGE] ⚠️ Unreadable {} ({e}); backed up to {moved}, starting clean",
+                    entry_name(namespace, key)
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    fn put_json<T: Serialize>(&self, namespace: &str, key: &str, value: &T) -> Result<()> {
+        self.put(namespace, key, &serde_json::to_string(value)?)
+    }
+}
+
+impl<S: StateStore + ?Sized> StateStoreExt for S {}
+
+fn entry_name(namespace: &str, key: &str) -> String {
+    if key.is_empty() {
+        namespace.to_string()
+    } else {
+        format!("{namespace}/{key}")
+    }
+}
+
+/// Open the backend selected by `cfg.state_backend`.
+pub fn open(cfg: &Config) -> Result<Arc<dyn StateStore>> {
+    match cfg.state_backend {
+        StateBackend::Json => Ok(Arc::new(JsonFileStore::from_config(cfg))),
+        #[cfg(feature = "sqlite")]
+        StateBackend::Sqlite => Ok(Arc::new(SqliteStore::open(
+            &cfg.state_dir.join("state.db"),
+        )?)),
+        #[cfg(not(feature = "sqlite"))]
+        StateBackend::Sqlite => Err(crate::Error::Config(
+            "STATE_BACKEND=sqlite needs a build with the `sqlite` feature".to_string(),
+        )),
+    }
+}
+
+/// One JSON file per entry: `<dir>/<namespace>.json`, or the path set with
+/// [`JsonFileStore::with_path`]; a non-empty key is added to the file stem
+/// (`session.json` -> `session-<key>.json`).
+#[derive(Clone, Debug)]
+pub struct JsonFileStore {
+    dir: PathBuf,
+    paths: HashMap<String, PathBuf>,
+}
+
+impl JsonFileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            paths: HashMap::new(),
+        }
+    }
+
+    /// Files under `STATE_DIR`, with `SESSION_FILE` and `RESTART_FILE` where they always were.
+    pub fn from_config(cfg: &Config) -> Self {
+        Self::new(&cfg.state_dir)
+            .with_path(SESSION_NAMESPACE, &cfg.session_file)
+            .with_path(RESTART_NAMESPACE, &cfg.restart_file)
+    }
+
+    /// Store `namespace` at `path` instead of under the store directory.
+    pub fn with_path(mut self, namespace: &str, path: impl Into<PathBuf>) -> Self {
+        self.paths.insert(namespace.to_string(), path.into());
+        self
+    }
+
+    /// File holding `namespace`/`key`.
+    pub fn path(&self, namespace: &str, key: &str) -> PathBuf {
+        let base = self
+            .paths
+            .get(namespace)
+            .cloned()
+            .unwrap_or_else(|| self.dir.join(format!("{namespace}.json")));
+        keyed_path(&base, key)
+    }
+}
+
+/// `/tmp/session.json` + `-100-t11` -> `/tmp/session--100-t11.json`; an empty key keeps `base`.
+pub fn keyed_path(base: &Path, key: &str) -> PathBuf {
+    if key.is_empty() {
+        return base.to_path_buf();
+    }
+    let stem = base
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "state".to_string());
+    let name = match base.extension() {
+        Some(ext) => format!("{stem}-{key}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{key}"),
+    };
+    base.with_file_name(name)
+}
+
+/// Numbers temp files, so concurrent writers in one process never share one.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Write `contents` to a sibling temp file, sync it and rename it over `path`.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".tmp-{}-{}",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = PathBuf::from(tmp);
+    let written = std::fs::File::create(&tmp).and_then(|mut f| {
+        f.write_all(contents)?;
+        f.sync_all()
+    });
+    if let Err(e) = written.and_then(|()| std::fs::rename(&tmp, path)) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// `<path>.corrupt-<utc timestamp>`, for a file moved aside.
+fn backup_path(path: &Path) -> PathBuf {
+    let stamp: String = iso_timestamp_utc()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".corrupt-{stamp}"));
+    PathBuf::from(name)
+}
+
+/// Move state files from their defaults before `STATE_DIR` (fixed `/tmp` files and `TEMP_DIR`)
+/// into `STATE_DIR`, so an upgrade keeps them. Runs once in effect: a file moves only when its
+/// path is still the default and nothing exists there yet. Returns the files moved.
+pub fn migrate_legacy_files(cfg: &Config) -> Vec<PathBuf> {
+    let tmp = Path::new("/tmp");
+    let legacy = [
+        (tmp.join("claude-telegram-session.json"), &cfg.session_file),
+        (tmp.join("claude-telegram-restart.json"), &cfg.restart_file),
+        (
+            tmp.join("claude-telegram-cron-queue.json"),
+            &cfg.cron_queue_file,
+        ),
+        (
+            tmp.join("claude-telegram-reminders.json"),
+            &cfg.reminders_file,
+        ),
+        (cfg.temp_dir.join("directory-grants.json"), &cfg.grants_file),
+        (
+            cfg.temp_dir.join("usage-ledger.jsonl"),
+            &cfg.usage_ledger_path,
+        ),
+    ];
+    let fixed = [
+        "session_history.json",
+        "chat-settings.json",
+        "vocabulary.json",
+        "state.db",
+    ]
+    .map(|name| (cfg.temp_dir.join(name), cfg.state_dir.join(name)));
+
+    let mut moved = Vec::new();
+    for (old, new) in legacy {
+        // Set explicitly in the environment: not ours to move.
+        let default = old
+            .file_name()
+            .map(|name| {
+                let name = name.to_string_lossy();
+                cfg.state_dir
+                    .join(name.strip_prefix("claude-telegram-").unwrap_or(&name))
+            })
+            .is_some_and(|default| default == *new);
+        if default {
+            moved.extend(move_legacy_file(&old, new));
+        }
+    }
+    for (old, new) in &fixed {
+        moved.extend(move_legacy_file(old, new));
+    }
+    moved
+}
+
+/// Move `old` (and its forum-topic siblings `<stem>-<key>.<ext>`) to `new`, unless `new` already
+/// exists. Returns the new paths.
+fn move_legacy_file(old: &Path, new: &Path) -> Vec<PathBuf> {
+    let mut pairs = vec![(old.to_path_buf(), new.to_path_buf())];
+    if let (Some(dir), Some(stem), Some(ext)) = (old.parent(), old.file_stem(), old.extension()) {
+        let (prefix, suffix) = (
+            format!("{}-", stem.to_string_lossy()),
+            format!(".{}", ext.to_string_lossy()),
+        );
+        let siblings = std::fs::read_dir(dir).into_iter().flatten().flatten();
+        for entry in siblings {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let key = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(&suffix));
+            if let Some(key) = key.filter(|k| !k.is_empty()) {
+                pairs.push((entry.path(), keyed_path(new, key)));
+            }
+        }
+    }
+    let mut moved = Vec::new();
+    for (from, to) in pairs {
+        if !from.is_file() || to.exists() {
+            continue;
+        }
+        let result =
+            std::fs::create_dir_all(to.parent().unwrap_or(Path::new("."))).and_then(|()| {
+                match std::fs::rename(&from, &to) {
+                    Ok(()) => Ok(()),
+                    // `/tmp` is often another filesystem.
+                    Err(_) => std::fs::copy(&from, &to).and_then(|_| std::fs::remove_file(&from)),
+                }
+            });
+        match result {
+            Ok(()) => {
+                log_info!("[STATE] Moved {} to {}", from.display(), to.display());
+                moved.push(to);
+            }
+            Err(e) => log_line!("[STATE] Failed to move {}: {e}", from.display()),
+        }
+    }
+    moved
+}
+
+impl StateStore for JsonFileStore {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+        match std::fs::read(self.path(namespace, key)) {
+            // Invalid UTF-8 stays unparseable and is quarantined by `get_json`.
+            Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &str) -> Result<()> {
+        write_atomic(&self.path(namespace, key), value.as_bytes())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<()> {
+        match std::fs::remove_file(self.path(namespace, key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn quarantine(&self, namespace: &str, key: &str) -> Result<String> {
+        let path = self.path(namespace, key);
+        let backup = backup_path(&path);
+        std::fs::rename(&path, &backup)?;
+        Ok(backup.display().to_string())
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>> {
+        let base = self.path(namespace, "");
+        let (Some(name), Some(stem)) = (base.file_name(), base.file_stem()) else {
+            return Ok(Vec::new());
+        };
+        let (name, stem) = (name.to_string_lossy(), stem.to_string_lossy());
+        let suffix = &name[stem.len()..];
+        let dir = base.parent().unwrap_or(Path::new("."));
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut keys: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let file = e.file_name().to_string_lossy().to_string();
+                if file == *name {
+                    return Some(String::new());
+                }
+                let key = file
+                    .strip_prefix(&format!("{stem}-"))?
+                    .strip_suffix(suffix)?;
+                (!key.is_empty()).then(|| key.to_string())
+            })
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// All entries in one SQLite table; quarantined values move to `<namespace>.corrupt`.
+#[cfg(feature = "sqlite")]
+pub struct SqliteStore {
+    conn: std::sync::Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_error(e: rusqlite::Error) -> crate::Error {
+    crate::Error::External(format!("sqlite: {e}"))
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::init(rusqlite::Connection::open(path).map_err(sqlite_error)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(rusqlite::Connection::open_in_memory().map_err(sqlite_error)?)
+    }
+
+    fn init(conn: rusqlite::Connection) -> Result<Self> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS state (
+               namespace  TEXT NOT NULL,
+               key        TEXT NOT NULL,
+               value      TEXT NOT NULL,
+               updated_at TEXT NOT NULL,
+               PRIMARY KEY (namespace, key)
+             );",
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self {
+            conn: std::sync::Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, rusqlite::Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl StateStore for SqliteStore {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+        use rusqlite::OptionalExtension;
+
+        self.conn()
+            .query_row(
+                "SELECT value FROM state WHERE namespace = ?1 AND key = ?2",
+                (namespace, key),
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sqlite_error)
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &str) -> Result<()> {
+        self.conn()
+            .execute(
+                "INSERT INTO state (namespace, key, value, updated_at) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (namespace, key)
+                 DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+                (namespace, key, value, iso_timestamp_utc()),
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<()> {
+        self.conn()
+            .execute(
+                "DELETE FROM state WHERE namespace = ?1 AND key = ?2",
+                (namespace, key),
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn quarantine(&self, namespace: &str, key: &str) -> Result<String> {
+        let corrupt_ns = format!("{namespace}.corrupt");
+        let corrupt_key = format!("{key}@{}", iso_timestamp_utc());
+        self.conn()
+            .execute(
+                "UPDATE state SET namespace = ?3, key = ?4 WHERE namespace = ?1 AND key = ?2",
+                (namespace, key, &corrupt_ns, &corrupt_key),
+            )
+            .map_err(sqlite_error)?;
+        Ok(entry_name(&corrupt_ns, &corrupt_key))
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn
+            .prepare("SELECT key FROM state WHERE namespace = ?1 ORDER BY key")
+            .map_err(sqlite_error)?;
+        let keys = stmt
+            .query_map([namespace], |row| row.get(0))
+            .map_err(sqlite_error)?
+            .collect::<std::result::Result<Vec<String>, _>>()
+            .map_err(sqlite_error)?;
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        id: String,
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ctb-storage-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn round_trip(store: &dyn StateStore) {
+        let entry = Entry {
+            id: "abc".to_string(),
+        };
+        assert_eq!(store.get_json::<Entry>("session", "").unwrap(), None);
+        store.put_json("session", "", &entry).unwrap();
+        store
+            .put_json("session", "-100-t11", &Entry { id: "topic".into() })
+            .unwrap();
+        assert_eq!(store.get_json("session", "").unwrap(), Some(entry));
+        assert_eq!(
+            store
+                .get_json::<Entry>("session", "-100-t11")
+                .unwrap()
+                .unwrap()
+                .id,
+            "topic"
+        );
+        assert_eq!(store.keys("session").unwrap(), ["", "-100-t11"]);
+        store.delete("session", "").unwrap();
+        store.delete("session", "").unwrap();
+        assert_eq!(store.get_json::<Entry>("session", "").unwrap(), None);
+        assert_eq!(store.keys("session").unwrap(), ["-100-t11"]);
+        assert!(store.keys("missing").unwrap().is_empty());
+    }
+
+    #[test]
+    fn concurrent_atomic_writes_leave_one_whole_file() {
+        let dir = scratch("atomic");
+        let path = dir.join("state.json");
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        write_atomic(&path, format!("writer {i}").repeat(512).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let first = text.split_at(8).0.to_string();
+        assert_eq!(text, first.repeat(512), "one writer's contents, not a mix");
+        assert_eq!(
+            std::fs::read_dir(&dir).unwrap().count(),
+            1,
+            "no temp files left"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn json_files_round_trip_and_keep_configured_paths() {
+        let dir = scratch("json");
+        let store = JsonFileStore::new(&dir).with_path("session", dir.join("sess.json"));
+        round_trip(&store);
+        assert!(dir.join("sess--100-t11.json").exists());
+
+        store.put("restart", "", "{}").unwrap();
+        assert!(dir.join("restart.json").exists());
+        let leftovers: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().contains(".tmp-"))
+            .collect();
+        assert!(leftovers.is_empty(), "temp files left behind");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn truncated_json_file_is_backed_up_and_reads_as_empty() {
+        let dir = scratch("corrupt");
+        let store = JsonFileStore::new(&dir);
+        let path = store.path("session", "");
+        std::fs::write(&path, r#"{"id":"ab"#).unwrap();
+
+        assert_eq!(store.get_json::<Entry>("session", "").unwrap(), None);
+        assert!(!path.exists(), "corrupt file left in place");
+        let backups: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.starts_with("session.json.corrupt-"))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.join(&backups[0])).unwrap(),
+            r#"{"id":"ab"#
+        );
+
+        // Starting clean: the next write and read work normally.
+        store
+            .put_json("session", "", &Entry { id: "new".into() })
+            .unwrap();
+        assert_eq!(
+            store.get_json::<Entry>("session", "").unwrap().unwrap().id,
+            "new"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn blank_json_file_reads_as_empty_without_backup() {
+        let dir = scratch("blank");
+        let store = JsonFileStore::new(&dir);
+        std::fs::write(store.path("session", ""), "  \n").unwrap();
+        assert_eq!(store.get_json::<Entry>("session", "").unwrap(), None);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_round_trips_and_quarantines_corrupt_values() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        round_trip(&store);
+
+        store.put("session", "", "not json").unwrap();
+        assert_eq!(store.get_json::<Entry>("session", "").unwrap(), None);
+        assert_eq!(store.get("session", "").unwrap(), None);
+        let kept: u32 = store
+            .conn()
+            .query_row(
+                "SELECT COUNT(*) FROM state WHERE namespace = 'session.corrupt'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(kept, 1);
+    }
+
+    #[test]
+    fn legacy_files_move_once_with_their_topic_siblings() {
+        let dir = scratch("legacy");
+        let (old, new) = (dir.join("tmp"), dir.join("state"));
+        std::fs::create_dir_all(&old).unwrap();
+        let session = old.join("claude-telegram-session.json");
+        std::fs::write(&session, "default").unwrap();
+        std::fs::write(old.join("claude-telegram-session--100-t11.json"), "topic").unwrap();
+        std::fs::write(old.join("claude-telegram-session.json.corrupt-1"), "x").unwrap();
+
+        let moved = move_legacy_file(&session, &new.join("session.json"));
+        assert_eq!(moved.len(), 2, "{moved:?}");
+        let store = JsonFileStore::new(&new);
+        assert_eq!(
+            store.get("session", "").unwrap().as_deref(),
+            Some("default")
+        );
+        assert_eq!(
+            store.get("session", "-100-t11").unwrap().as_deref(),
+            Some("topic")
+        );
+        assert!(!session.exists());
+
+        // Never over a file already at the new location.
+        std::fs::write(&session, "stale").unwrap();
+        assert!(move_legacy_file(&session, &new.join("session.json")).is_empty());
+        assert_eq!(
+            store.get("session", "").unwrap().as_deref(),
+            Some("default")
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_store_persists_across_reopen() {
+        let dir = scratch("sqlite");
+        let path = dir.join("state.db");
+        SqliteStore::open(&path)
+            .unwrap()
+            .put("restart", "", "{}")
+            .unwrap();
+        let reopened = SqliteStore::open(&path).unwrap();
+        assert_eq!(reopened.get("restart", "").unwrap().as_deref(), Some("{}"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -5,19 +5,40 @@
 //! - per-segment message tracking
 //! - progress spinner + completion message
 //! - optional deletion of thinking/tool messages
+//!
+//! Messengers that cannot edit get an append-only variant: no spinner, each segment is sent once
+//! when it ends, and the completion line is a message of its own. Reactions are skipped where
+//! unsupported, and message sizes never exceed the messenger's `max_message_len`.
 
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use chrono::Local;
 
 use crate::{
     config::Config,
-    domain::{ChatId, MessageRef},
-    formatting::convert_markdown_to_html,
-    messaging::port::MessagingPort,
+    domain::{ChatTarget, MessageRef},
+    errors::Error,
+    formatting::{
+        code_file_extension, convert_markdown_to_html, find_fenced_blocks, format_usage_brief,
+        IncrementalHtml,
+    },
+    i18n::{self, Messages},
+    messaging::{chat_action::upload_document, port::MessagingPort},
+    metrics::{Counter, MetricsSink, NoopMetrics},
+    model::types::TokenUsage,
+    theme::{self, StatusTheme},
     Result,
 };
 
+static CODE_FILE_COUNTER: AtomicUsize = AtomicUsize::new(1);
+
 /// Status callback event types (parity with TS).
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StatusType {
@@ -28,9 +49,32 @@ pub enum StatusType {
     Done,
 }
 
+/// When the spinner is re-sent to stay below the messages of a run
+/// (`PROGRESS_RESEND_AFTER_MESSAGES` / `PROGRESS_RESEND_AFTER_SECS`).
+///
+/// Between re-sends the spinner stays where it is and only its ticks edit it, saving a
+/// delete + send pair per status message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgressPolicy {
+    /// Re-send once more than this many messages were sent below the spinner; 0 re-sends after
+    /// every message.
+    pub resend_after_messages: usize,
+    /// Also re-send when a message arrives and the spinner is at least this old.
+    pub resend_after: Option<Duration>,
+}
+
+impl ProgressPolicy {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            resend_after_messages: cfg.progress_resend_after_messages,
+            resend_after: cfg.progress_resend_after,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct StreamingState {
-    pub chat_id: ChatId,
+    pub target: ChatTarget,
 
     pub text_messages: HashMap<u32, MessageRef>, // segment_id -> message
     pub thinking_messages: Vec<MessageRef>,
@@ -38,10 +82,23 @@ pub struct StreamingState {
 
     last_edit_times: HashMap<u32, Instant>,
     last_content: HashMap<u32, String>,
+    /// Markdown conversion of each streamed segment, reusing its finished paragraphs.
+    html: HashMap<u32, IncrementalHtml>,
 
     progress_message: Option<MessageRef>,
+    progress_policy: ProgressPolicy,
+    /// Messages sent since the spinner was, and when it was sent.
+    messages_below_progress: usize,
+    progress_sent_at: Option<Instant>,
     start_time: Option<ProgressStart>,
     frame_index: usize,
+    /// Latest cumulative usage of the run, rendered next to the spinner.
+    usage: Option<(TokenUsage, f64)>,
+    /// Extra line for the completion message (`DEBUG_TIMINGS`).
+    completion_note: Option<String>,
+    metrics: Arc<dyn MetricsSink>,
+    theme: &'static StatusTheme,
+    messages: &'static Messages,
 }
 
 #[derive(Clone, Debug)]
@@ -51,17 +108,75 @@ struct ProgressStart {
 }
 
 impl StreamingState {
-    pub fn new(chat_id: ChatId) -> Self {
+    pub fn new(target: ChatTarget) -> Self {
         Self {
-            chat_id,
+            target,
             text_messages: HashMap::new(),
             thinking_messages: Vec::new(),
             tool_messages: Vec::new(),
             last_edit_times: HashMap::new(),
             last_content: HashMap::new(),
+            html: HashMap::new(),
             progress_message: None,
+            progress_policy: ProgressPolicy::default(),
+            messages_below_progress: 0,
+            progress_sent_at: None,
             start_time: None,
             frame_index: 0,
+            usage: None,
+            completion_note: None,
+            metrics: Arc::new(NoopMetrics),
+            theme: &theme::UNICODE,
+            messages: &i18n::EN,
+        }
+    }
+
+    /// Count throttled edits into `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Re-send the spinner according to `policy` instead of after every message.
+    pub fn with_progress_policy(mut self, policy: ProgressPolicy) -> Self {
+        self.progress_policy = policy;
+        self
+    }
+
+    /// Draw the spinner, completion line and notices with `theme` (`STATUS_THEME`).
+    pub fn with_theme(mut self, theme: &'static StatusTheme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Write the spinner, completion line and notices in `messages`' language.
+    pub fn with_messages(mut self, messages: &'static Messages) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Line appended to the "✅ Completed" message on `Done`.
+    pub fn set_completion_note(&mut self, note: String) {
+        self.completion_note = Some(note);
+    }
+
+    /// Record the run's cumulative usage and its cost; shown on the next spinner tick (no extra
+    /// edit).
+    pub fn update_usage(&mut self, u: &TokenUsage, cost_usd: f64) {
+        self.usage = Some((u.clone(), cost_usd));
+    }
+
+    fn progress_text(&self, elapsed: &str) -> String {
+        let t = self.theme;
+        let spinner = t.spinner_frame(self.frame_index);
+        let working = self.messages.working();
+        match &self.usage {
+            Some((u, cost)) => format!(
+                "{spinner} {working} ({elapsed}) {} {}",
+                t.separator,
+                format_usage_brief(t, u, *cost)
+            ),
+            None => format!("{spinner} {working} ({elapsed})"),
         }
     }
 
@@ -100,17 +215,21 @@ impl StreamingState {
                 let preview = truncate_with_ellipsis(content, 500);
                 let msg = api
                     .send_html(
-                        self.chat_id,
-                        &format!("🧠 <i>{}</i>", crate::formatting::escape_html(&preview)),
+                        self.target,
+                        &format!(
+                            "{} <i>{}</i>",
+                            self.theme.thinking,
+                            crate::formatting::escape_html(&preview)
+                        ),
                     )
                     .await?;
                 self.thinking_messages.push(msg);
-                self.recreate_progress(api).await?;
+                self.after_message_sent(api).await?;
             }
             StatusType::Tool => {
-                let msg = api.send_html(self.chat_id, content).await?;
+                let msg = api.send_html(self.target, content).await?;
                 self.tool_messages.push(msg);
-                self.recreate_progress(api).await?;
+                self.after_message_sent(api).await?;
             }
             StatusType::Text => {
                 let Some(seg) = segment_id else {
@@ -132,6 +251,21 @@ impl StreamingState {
         Ok(())
     }
 
+    /// Rewrite a tool status sent earlier, e.g. to fold another call of the same action into it.
+    /// A message the user deleted is forgotten and reported as [`Error::MessageGone`].
+    pub async fn edit_tool_message(
+        &mut self,
+        api: &dyn MessagingPort,
+        msg: MessageRef,
+        html: &str,
+    ) -> Result<()> {
+        let res = api.edit_html(msg, html).await;
+        if let Err(Error::MessageGone(_)) = res {
+            self.tool_messages.retain(|m| *m != msg);
+        }
+        res
+    }
+
     /// Tick the progress spinner (call from an interval timer).
     pub async fn tick_progress(&mut self, api: &dyn MessagingPort) -> Result<()> {
         let Some(start) = self.start_time.as_ref() else {
@@ -141,12 +275,15 @@ impl StreamingState {
             return Ok(());
         };
 
-        self.frame_index = self.frame_index.wrapping_add(1);
-        let spinner = SPINNER_FRAMES[self.frame_index % SPINNER_FRAMES.len()];
         let elapsed = format_elapsed(start.instant);
-        let text = format!("{spinner} Working... ({elapsed})");
-        // Best-effort; ignore edit errors.
-        let _ = api.edit_html(msg, &text).await;
+        self.frame_index = self.frame_index.wrapping_add(1);
+        let text = self.progress_text(&elapsed);
+        // Best-effort (may be dropped by the rate governor); a failed frame is simply skipped,
+        // but a spinner the user deleted is sent again.
+        if let Err(Error::MessageGone(_)) = api.edit_html_best_effort(msg, &text).await {
+            self.progress_message = None;
+            self.recreate_progress(api).await?;
+        }
         Ok(())
     }
 
@@ -158,29 +295,35 @@ impl StreamingState {
         content: &str,
         now: Instant,
     ) -> Result<()> {
+        // Without edits a partial message could never be completed: wait for the segment end.
+        if !api.capabilities().supports_edit {
+            return Ok(());
+        }
+        let (_, safe_limit) = message_limits(cfg, api);
         let last_edit = self.last_edit_times.get(&segment_id).copied();
 
         if !self.text_messages.contains_key(&segment_id) {
             // New segment: create message.
-            let display = truncate_with_ellipsis(content, cfg.telegram_safe_limit);
-            let formatted = convert_markdown_to_html(&display);
-            let msg = api.send_html(self.chat_id, &formatted).await?;
+            let display = truncate_with_ellipsis(content, safe_limit);
+            let formatted = self.html.entry(segment_id).or_default().convert(&display);
+            let msg = api.send_html(self.target, &formatted).await?;
             self.text_messages.insert(segment_id, msg);
             self.last_content.insert(segment_id, formatted);
             self.last_edit_times.insert(segment_id, now);
-            self.recreate_progress(api).await?;
+            self.after_message_sent(api).await?;
             return Ok(());
         }
 
         if let Some(last) = last_edit {
             if now.duration_since(last) <= cfg.streaming_throttle {
+                self.metrics.incr(Counter::ThrottleSkips, 1);
                 return Ok(());
             }
         }
 
         let msg = self.text_messages[&segment_id];
-        let display = truncate_with_ellipsis(content, cfg.telegram_safe_limit);
-        let formatted = convert_markdown_to_html(&display);
+        let display = truncate_with_ellipsis(content, safe_limit);
+        let formatted = self.html.entry(segment_id).or_default().convert(&display);
 
         if self
             .last_content
@@ -196,15 +339,18 @@ impl StreamingState {
                 self.last_content.insert(segment_id, formatted);
                 self.last_edit_times.insert(segment_id, now);
             }
-            Err(_) => {
-                // If the message was deleted or can no longer be edited, fall back to sending
-                // a new message so the stream continues rather than silently stalling.
-                let new_msg = api.send_html(self.chat_id, &formatted).await?;
+            Err(Error::MessageGone(_)) => {
+                // Deleted by the user: continue the segment in a fresh message and keep
+                // editing that one.
+                let new_msg = api.send_html(self.target, &formatted).await?;
                 self.text_messages.insert(segment_id, new_msg);
                 self.last_content.insert(segment_id, formatted);
                 self.last_edit_times.insert(segment_id, now);
-                let _ = api.delete_message(msg).await;
-                self.recreate_progress(api).await?;
+                self.after_message_sent(api).await?;
+            }
+            Err(e) => {
+                // Transient (e.g. rate limited): the next update or the segment end retries.
+                log_line!("[STREAM] Failed to edit segment {segment_id}: {e}");
             }
         }
         Ok(())
@@ -220,18 +366,27 @@ impl StreamingState {
         if content.is_empty() {
             return Ok(());
         }
+        let content = self.detach_long_code_blocks(cfg, api, content).await;
+        let content = content.as_str();
+        let (message_limit, safe_limit) = message_limits(cfg, api);
+        let formatted = self
+            .html
+            .remove(&segment_id)
+            .unwrap_or_default()
+            .convert(content);
 
-        // If short response and no message exists yet, send now.
-        if !self.text_messages.contains_key(&segment_id) {
-            let formatted = convert_markdown_to_html(content);
-            let msg = api.send_html(self.chat_id, &formatted).await?;
-            self.text_messages.insert(segment_id, msg);
-            self.recreate_progress(api).await?;
+        let Some(&msg) = self.text_messages.get(&segment_id) else {
+            // If short response and no message exists yet, send now.
+            if formatted.len() <= message_limit {
+                let msg = api.send_html(self.target, &formatted).await?;
+                self.text_messages.insert(segment_id, msg);
+                self.after_message_sent(api).await?;
+            } else {
+                self.send_chunks(api, content, safe_limit).await?;
+            }
             return Ok(());
-        }
+        };
 
-        let msg = self.text_messages[&segment_id];
-        let formatted = convert_markdown_to_html(content);
         if self
             .last_content
             .get(&segment_id)
@@ -241,19 +396,21 @@ impl StreamingState {
             return Ok(());
         }
 
-        if formatted.len() <= cfg.telegram_message_limit {
+        if formatted.len() <= message_limit {
             match api.edit_html(msg, &formatted).await {
                 Ok(()) => {
                     self.last_content.insert(segment_id, formatted);
                 }
-                Err(_) => {
-                    // Same fallback as streaming edits: send a fresh message so the final output
-                    // is not lost.
-                    let new_msg = api.send_html(self.chat_id, &formatted).await?;
+                Err(e) => {
+                    // The final output must not be lost: send it fresh, and remove the stale
+                    // partial unless the user already deleted it.
+                    let new_msg = api.send_html(self.target, &formatted).await?;
                     self.text_messages.insert(segment_id, new_msg);
                     self.last_content.insert(segment_id, formatted);
-                    let _ = api.delete_message(msg).await;
-                    self.recreate_progress(api).await?;
+                    if !matches!(e, Error::MessageGone(_)) {
+                        let _ = api.delete_message(msg).await;
+                    }
+                    self.after_message_sent(api).await?;
                 }
             }
             return Ok(());
@@ -264,45 +421,182 @@ impl StreamingState {
         self.text_messages.remove(&segment_id);
         self.last_content.remove(&segment_id);
         self.last_edit_times.remove(&segment_id);
+        self.send_chunks(api, content, safe_limit).await
+    }
 
-        for chunk in split_text(content, cfg.telegram_safe_limit) {
+    /// Send `content` as several messages of at most `limit` bytes of Markdown each (converted
+    /// per chunk to keep the HTML well-formed).
+    async fn send_chunks(
+        &mut self,
+        api: &dyn MessagingPort,
+        content: &str,
+        limit: usize,
+    ) -> Result<()> {
+        for chunk in split_text(content, limit) {
             let html = convert_markdown_to_html(&chunk);
-            api.send_html(self.chat_id, &html).await?;
+            api.send_html(self.target, &html).await?;
         }
+        self.after_message_sent(api).await
+    }
 
-        self.recreate_progress(api).await?;
-        Ok(())
+    /// Send fenced code blocks longer than `cfg.code_file_threshold` as documents and replace
+    /// them with a marker line, so long files don't get chopped into `<pre>` chunks.
+    ///
+    /// Blocks stay inline when the messenger cannot send documents or an upload fails.
+    async fn detach_long_code_blocks(
+        &self,
+        cfg: &Config,
+        api: &dyn MessagingPort,
+        content: &str,
+    ) -> String {
+        if cfg.code_file_threshold == 0 || !api.capabilities().supports_documents {
+            return content.to_string();
+        }
+
+        let mut out = String::with_capacity(content.len());
+        let mut copied = 0;
+        for block in find_fenced_blocks(content) {
+            if block.code.chars().count() <= cfg.code_file_threshold {
+                continue;
+            }
+            let name = format!(
+                "code_{}_{}.{}",
+                Local::now().format("%Y%m%d_%H%M%S"),
+                CODE_FILE_COUNTER.fetch_add(1, Ordering::SeqCst),
+                code_file_extension(&block.lang)
+            );
+            let path = cfg.temp_dir.join(&name);
+            let caption = format!(
+                "{} {}",
+                self.theme.attachment,
+                self.messages
+                    .code_file_caption(&name, block.code.lines().count())
+            );
+            let written = match tokio::fs::create_dir_all(&cfg.temp_dir).await {
+                Ok(()) => tokio::fs::write(&path, format!("{}\n", block.code)).await,
+                Err(e) => Err(e),
+            };
+            let sent = match written {
+                Ok(()) => upload_document(api, self.target, &path, Some(&caption)).await,
+                Err(e) => Err(e.into()),
+            };
+            let _ = tokio::fs::remove_file(&path).await;
+            if let Err(e) = sent {
+                log_line!("[STREAM] Failed to send code block as file: {e}");
+                continue;
+            }
+
+            out.push_str(&content[copied..block.range.start]);
+            out.push_str(&format!(
+                "{} {}",
+                self.theme.attachment,
+                self.messages.code_sent_as_file(&name)
+            ));
+            copied = block.range.end;
+        }
+        out.push_str(&content[copied..]);
+        out
     }
 
     async fn handle_done(&mut self, cfg: &Config, api: &dyn MessagingPort) -> Result<()> {
-        // Update progress message with completion info.
-        if let (Some(start), Some(progress_msg)) = (self.start_time.as_ref(), self.progress_message)
-        {
+        let caps = api.capabilities();
+        // Update progress message with completion info (a new message when edits are unsupported).
+        if let Some(start) = self.start_time.as_ref() {
             let duration = format_elapsed(start.instant);
             let start_str = start.wallclock.format("%H:%M:%S").to_string();
             let end_str = Local::now().format("%H:%M:%S").to_string();
 
-            let completion = format!("✅ Completed\n⏰ {start_str} → {end_str} ({duration})");
-            let _ = api.edit_html(progress_msg, &completion).await;
+            let t = self.theme;
+            let mut completion = format!(
+                "{} {}\n{} {start_str} {} {end_str} ({duration})",
+                t.done,
+                self.messages.completed(),
+                t.clock,
+                t.arrow
+            );
+            if let Some(note) = &self.completion_note {
+                completion.push('\n');
+                completion.push_str(&crate::formatting::escape_html(note));
+            }
+            match self.progress_message {
+                // The spinner is no longer last: the completion line goes to the bottom.
+                Some(progress_msg) if self.messages_below_progress > 0 => {
+                    let _ = api.delete_message(progress_msg).await;
+                    let _ = api.send_html(self.target, &completion).await;
+                }
+                Some(progress_msg) => {
+                    let _ = api.edit_html(progress_msg, &completion).await;
+                }
+                None if !caps.supports_edit => {
+                    let _ = api.send_html(self.target, &completion).await;
+                }
+                None => {}
+            }
+        }
+
+        self.delete_status_messages(cfg, api).await;
+
+        // Add completion reaction to the last segment message (reactions are always emoji).
+        if caps.supports_reactions && self.theme.emoji {
+            if let Some((_, &last_msg)) = self.text_messages.iter().max_by_key(|(k, _)| *k) {
+                let _ = api.set_reaction(last_msg, "👍").await;
+            }
         }
 
-        // Delete thinking/tool messages if configured.
+        Ok(())
+    }
+
+    /// Pause for an `ask_user` answer: the spinner shows a waiting notice and stops ticking
+    /// until the turn carrying the answer takes this state over (see `SuspendedStream`).
+    pub async fn suspend(&mut self, cfg: &Config, api: &dyn MessagingPort) -> Result<()> {
+        if let (Some(start), Some(msg)) = (self.start_time.as_ref(), self.progress_message) {
+            let notice = format!(
+                "{} {} ({})",
+                self.theme.paused,
+                self.messages.waiting_for_answer(),
+                format_elapsed(start.instant)
+            );
+            if let Err(Error::MessageGone(_)) = api.edit_html(msg, &notice).await {
+                self.progress_message = None;
+            }
+        }
+        self.delete_status_messages(cfg, api).await;
+        Ok(())
+    }
+
+    pub fn progress_message(&self) -> Option<MessageRef> {
+        self.progress_message
+    }
+
+    /// Delete thinking/tool messages if configured (best-effort).
+    async fn delete_status_messages(&mut self, cfg: &Config, api: &dyn MessagingPort) {
         if cfg.delete_thinking_messages {
-            for m in &self.thinking_messages {
-                let _ = api.delete_message(*m).await;
+            for m in self.thinking_messages.drain(..) {
+                let _ = api.delete_message(m).await;
             }
         }
         if cfg.delete_tool_messages {
-            for m in &self.tool_messages {
-                let _ = api.delete_message(*m).await;
+            for m in self.tool_messages.drain(..) {
+                let _ = api.delete_message(m).await;
             }
         }
+    }
 
-        // Add completion reaction to the last segment message.
-        if let Some((_, &last_msg)) = self.text_messages.iter().max_by_key(|(k, _)| *k) {
-            let _ = api.set_reaction(last_msg, "👍").await;
+    /// Replace the spinner with a final notice and stop ticking (run aborted, e.g. shutdown).
+    pub async fn abort_progress(&mut self, api: &dyn MessagingPort, notice: &str) -> Result<()> {
+        let started = self.start_time.take().is_some();
+        match self.progress_message.take() {
+            Some(msg) => match api.edit_html(msg, notice).await {
+                Err(Error::MessageGone(_)) => {
+                    api.send_html(self.target, notice).await?;
+                }
+                result => result?,
+            },
+            None if started && !api.capabilities().supports_edit => {
+                api.send_html(self.target, notice).await?;
+            }
+            None => {}
         }
-
         Ok(())
     }
 
@@ -310,22 +604,60 @@ impl StreamingState {
         let Some(start) = self.start_time.as_ref() else {
             return Ok(());
         };
+        // A spinner that can't be edited would never move.
+        if !api.capabilities().supports_edit {
+            return Ok(());
+        }
 
         // Delete old progress message (best-effort).
         if let Some(old) = self.progress_message {
             let _ = api.delete_message(old).await;
         }
 
-        let spinner = SPINNER_FRAMES[self.frame_index % SPINNER_FRAMES.len()];
         let elapsed = format_elapsed(start.instant);
-        let text = format!("{spinner} Working... ({elapsed})");
-        let msg = api.send_html(self.chat_id, &text).await?;
+        let text = self.progress_text(&elapsed);
+        let msg = api.send_html(self.target, &text).await?;
         self.progress_message = Some(msg);
+        self.messages_below_progress = 0;
+        self.progress_sent_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// A message was sent below the spinner: move the spinner back to the bottom when the
+    /// [`ProgressPolicy`] says it has drifted too far up.
+    async fn after_message_sent(&mut self, api: &dyn MessagingPort) -> Result<()> {
+        if self.progress_message.is_none() {
+            return self.recreate_progress(api).await;
+        }
+        self.messages_below_progress += 1;
+        let policy = self.progress_policy;
+        let stale = policy
+            .resend_after
+            .zip(self.progress_sent_at)
+            .is_some_and(|(after, at)| at.elapsed() >= after);
+        if self.messages_below_progress > policy.resend_after_messages || stale {
+            return self.recreate_progress(api).await;
+        }
         Ok(())
     }
 }
 
-const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+/// Hard and safe (chunking) message limits: the configured ones, capped by the messenger's
+/// `max_message_len` while keeping the configured headroom between them.
+fn message_limits(cfg: &Config, api: &dyn MessagingPort) -> (usize, usize) {
+    let hard = cfg
+        .telegram_message_limit
+        .min(api.capabilities().max_message_len);
+    let headroom = cfg
+        .telegram_message_limit
+        .saturating_sub(cfg.telegram_safe_limit);
+    let safe = cfg
+        .telegram_safe_limit
+        .min(hard.saturating_sub(headroom))
+        .max(hard / 2)
+        .max(1);
+    (hard, safe)
+}
 
 fn format_elapsed(start: Instant) -> String {
     let elapsed = start.elapsed().as_secs();
@@ -361,145 +693,130 @@ fn split_text(s: &str, max_len: usize) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::MessageId;
-    use crate::messaging::types::{ChatAction, InlineKeyboard, MessagingCapabilities};
-    use async_trait::async_trait;
-    use std::sync::Mutex;
+    use crate::domain::ChatId;
+    use crate::messaging::{
+        test_support::{Call, Failure, Op, RecordingMessenger},
+        types::MessagingCapabilities,
+    };
     use std::time::Duration;
 
-    #[derive(Default)]
-    struct FakeMessenger {
-        next_id: Mutex<i32>,
-        sends: Mutex<Vec<String>>,
-        edits: Mutex<Vec<(MessageRef, String)>>,
-        deletes: Mutex<Vec<MessageRef>>,
-        reactions: Mutex<Vec<(MessageRef, String)>>,
-    }
-
-    impl FakeMessenger {
-        fn new() -> Self {
-            Self {
-                next_id: Mutex::new(1),
-                ..Default::default()
-            }
-        }
-
-        fn alloc(&self, chat_id: ChatId) -> MessageRef {
-            let mut guard = self.next_id.lock().unwrap();
-            let id = *guard;
-            *guard += 1;
-            MessageRef {
-                chat_id,
-                message_id: MessageId(id),
-            }
+    fn test_config() -> Config {
+        Config {
+            telegram_safe_limit: 50,
+            streaming_throttle: Duration::from_millis(500),
+            delete_thinking_messages: true,
+            delete_tool_messages: true,
+            rate_limit_enabled: true,
+            ..Config::for_tests()
         }
     }
 
-    #[async_trait]
-    impl MessagingPort for FakeMessenger {
-        fn capabilities(&self) -> MessagingCapabilities {
-            MessagingCapabilities {
-                supports_html: true,
-                supports_edit: true,
-                supports_reactions: true,
-                supports_chat_actions: false,
-                supports_inline_keyboards: false,
-                max_message_len: 4096,
-            }
-        }
+    #[tokio::test]
+    async fn long_code_blocks_are_sent_as_files() {
+        let mut cfg = test_config();
+        cfg.telegram_safe_limit = 4000;
+        cfg.code_file_threshold = 20;
+        let mut st = StreamingState::new(ChatId(1).into());
+        let api = RecordingMessenger::new();
 
-        async fn send_html(&self, chat_id: ChatId, html: &str) -> Result<MessageRef> {
-            self.sends.lock().unwrap().push(html.to_string());
-            Ok(self.alloc(chat_id))
-        }
+        let long_code = "fn main() {\n    println!(\"hello world\");\n}";
+        let content = format!("Here:\n```rust\n{long_code}\n```\nand ```\nshort\n```\ndone");
+        st.on_status(&cfg, &api, StatusType::SegmentEnd, &content, Some(0))
+            .await
+            .unwrap();
 
-        async fn edit_html(&self, msg: MessageRef, html: &str) -> Result<()> {
-            self.edits.lock().unwrap().push((msg, html.to_string()));
-            Ok(())
-        }
+        let docs = api.documents();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].0, format!("{long_code}\n"));
+        let caption = docs[0].1.clone().unwrap();
+        assert!(caption.ends_with(".rs (3 lines)"), "{caption}");
 
-        async fn delete_message(&self, msg: MessageRef) -> Result<()> {
-            self.deletes.lock().unwrap().push(msg);
-            Ok(())
-        }
+        let sends = api.sends();
+        let text = sends.iter().find(|s| s.contains("sent as file")).unwrap();
+        assert!(text.contains("📎 sent as file: <code>code_"));
+        assert!(text.contains(".rs"));
+        assert!(!text.contains("println"));
+        assert!(text.contains("short"), "short blocks stay inline");
+    }
 
-        async fn send_chat_action(&self, _chat_id: ChatId, _action: ChatAction) -> Result<()> {
-            Ok(())
-        }
+    #[test]
+    fn progress_text_includes_usage_once_known() {
+        let mut st = StreamingState::new(ChatId(1).into());
+        assert_eq!(st.progress_text("1:23"), "⠋ Working... (1:23)");
 
-        async fn set_reaction(&self, msg: MessageRef, emoji: &str) -> Result<()> {
-            self.reactions
-                .lock()
-                .unwrap()
-                .push((msg, emoji.to_string()));
-            Ok(())
-        }
+        st.update_usage(
+            &TokenUsage {
+                input_tokens: 2_100,
+                output_tokens: 800,
+                cache_read_input_tokens: 0,
+                cache_creation_input_tokens: 0,
+            },
+            0.0183,
+        );
+        assert_eq!(
+            st.progress_text("1:23"),
+            "⠋ Working... (1:23) · ↑2.1k ↓0.8k tok · ~$0.02"
+        );
+    }
+
+    #[tokio::test]
+    async fn ascii_theme_keeps_progress_and_completion_ascii() {
+        let cfg = test_config();
+        let mut st = StreamingState::new(ChatId(1).into()).with_theme(&crate::theme::ASCII);
+        st.update_usage(
+            &TokenUsage {
+                input_tokens: 2_100,
+                output_tokens: 800,
+                ..Default::default()
+            },
+            0.0183,
+        );
+        assert_eq!(
+            st.progress_text("1:23"),
+            "- Working... (1:23) | in 2.1k out 0.8k tok | ~$0.02"
+        );
 
-        async fn send_inline_keyboard(
-            &self,
-            _chat_id: ChatId,
-            _text: &str,
-            _keyboard: InlineKeyboard,
-        ) -> Result<MessageRef> {
-            Ok(self.alloc(ChatId(0)))
+        let api = RecordingMessenger::new();
+        for (status, content, segment) in [
+            (StatusType::Thinking, "plan", None),
+            (StatusType::Text, "done", Some(0)),
+            (StatusType::SegmentEnd, "done", Some(0)),
+            (StatusType::Done, "", None),
+        ] {
+            st.on_status(&cfg, &api, status, content, segment)
+                .await
+                .unwrap();
         }
 
-        async fn answer_callback_query(
-            &self,
-            _callback_id: &str,
-            _text: Option<&str>,
-        ) -> Result<()> {
-            Ok(())
+        let rendered: Vec<String> = api
+            .calls()
+            .into_iter()
+            .filter_map(|c| match c.call {
+                Call::Send { html, .. } | Call::Edit { html, .. } => Some(html),
+                _ => None,
+            })
+            .collect();
+        assert!(rendered.iter().any(|h| h.starts_with("[ok] Completed\n@ ")));
+        for html in &rendered {
+            assert!(html.is_ascii(), "not ASCII: {html:?}");
         }
     }
 
     #[tokio::test]
     async fn creates_and_throttles_segment_edits() {
-        // Avoid Config::load() env dependency: hand-roll config.
-        let cfg = Config {
-            telegram_bot_token: "x".to_string(),
-            telegram_allowed_users: vec![1],
-            claude_working_dir: "/tmp".into(),
-            openai_api_key: None,
-            transcription_prompt: "x".to_string(),
-            transcription_available: false,
-            claude_cli_path: "/usr/bin/claude".into(),
-            claude_config_dir: None,
-            allowed_paths: vec!["/tmp".into()],
-            temp_paths: vec!["/tmp".into()],
-            blocked_patterns: vec![],
-            safety_prompt: "x".to_string(),
-            query_timeout: Duration::from_secs(1),
-            temp_dir: "/tmp".into(),
-            session_file: "/tmp/s.json".into(),
-            restart_file: "/tmp/r.json".into(),
-            telegram_message_limit: 4096,
-            telegram_safe_limit: 50,
-            streaming_throttle: Duration::from_millis(500),
-            button_label_max_length: 30,
-            default_thinking_tokens: 0,
-            thinking_keywords: vec![],
-            thinking_deep_keywords: vec![],
-            delete_thinking_messages: true,
-            delete_tool_messages: true,
-            audit_log_path: "/tmp/a.log".into(),
-            audit_log_json: false,
-            rate_limit_enabled: true,
-            rate_limit_requests: 20,
-            rate_limit_window: Duration::from_secs(60),
-            media_group_timeout: Duration::from_millis(1000),
-        };
+        let cfg = test_config();
 
         let chat = ChatId(1);
-        let mut st = StreamingState::new(chat);
-        let api = FakeMessenger::new();
+        let metrics = crate::metrics::ProcessMetrics::new();
+        let mut st = StreamingState::new(chat.into()).with_metrics(metrics.clone());
+        let api = RecordingMessenger::new();
         let now = Instant::now();
 
         st.on_status_at(&cfg, &api, StatusType::Text, "hello world", Some(0), now)
             .await
             .unwrap();
         assert_eq!(
-            api.sends.lock().unwrap().len(),
+            api.sends().len(),
             3 /* progress + segment + recreated progress */
         );
 
@@ -514,7 +831,8 @@ mod tests {
         )
         .await
         .unwrap();
-        assert!(api.edits.lock().unwrap().is_empty());
+        assert!(api.edits().is_empty());
+        assert_eq!(metrics.counter(Counter::ThrottleSkips), 1);
 
         // After throttle: edit happens.
         st.on_status_at(
@@ -527,48 +845,17 @@ mod tests {
         )
         .await
         .unwrap();
-        assert_eq!(api.edits.lock().unwrap().len(), 1);
+        assert_eq!(api.edits().len(), 1);
+        assert_eq!(metrics.counter(Counter::ThrottleSkips), 1);
     }
 
     #[tokio::test]
     async fn done_deletes_thinking_and_tool_and_sets_reaction() {
-        let cfg = Config {
-            telegram_bot_token: "x".to_string(),
-            telegram_allowed_users: vec![1],
-            claude_working_dir: "/tmp".into(),
-            openai_api_key: None,
-            transcription_prompt: "x".to_string(),
-            transcription_available: false,
-            claude_cli_path: "/usr/bin/claude".into(),
-            claude_config_dir: None,
-            allowed_paths: vec!["/tmp".into()],
-            temp_paths: vec!["/tmp".into()],
-            blocked_patterns: vec![],
-            safety_prompt: "x".to_string(),
-            query_timeout: Duration::from_secs(1),
-            temp_dir: "/tmp".into(),
-            session_file: "/tmp/s.json".into(),
-            restart_file: "/tmp/r.json".into(),
-            telegram_message_limit: 4096,
-            telegram_safe_limit: 50,
-            streaming_throttle: Duration::from_millis(500),
-            button_label_max_length: 30,
-            default_thinking_tokens: 0,
-            thinking_keywords: vec![],
-            thinking_deep_keywords: vec![],
-            delete_thinking_messages: true,
-            delete_tool_messages: true,
-            audit_log_path: "/tmp/a.log".into(),
-            audit_log_json: false,
-            rate_limit_enabled: true,
-            rate_limit_requests: 20,
-            rate_limit_window: Duration::from_secs(60),
-            media_group_timeout: Duration::from_millis(1000),
-        };
+        let cfg = test_config();
 
         let chat = ChatId(1);
-        let mut st = StreamingState::new(chat);
-        let api = FakeMessenger::new();
+        let mut st = StreamingState::new(chat.into());
+        let api = RecordingMessenger::new();
         let now = Instant::now();
 
         st.on_status_at(&cfg, &api, StatusType::Thinking, "t", None, now)
@@ -584,7 +871,261 @@ mod tests {
             .await
             .unwrap();
 
-        assert!(!api.deletes.lock().unwrap().is_empty());
-        assert!(!api.reactions.lock().unwrap().is_empty());
+        assert!(!api.deletes().is_empty());
+        assert!(!api.reactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn messenger_without_edits_gets_whole_segments_and_a_completion_message() {
+        let cfg = test_config();
+        let mut st = StreamingState::new(ChatId(1).into());
+        let api = RecordingMessenger::send_only(100);
+        let now = Instant::now();
+
+        st.on_status_at(&cfg, &api, StatusType::Text, "partial", Some(0), now)
+            .await
+            .unwrap();
+        assert!(api.sends().is_empty(), "no spinner, no partial text");
+
+        st.on_status_at(
+            &cfg,
+            &api,
+            StatusType::SegmentEnd,
+            "short answer",
+            Some(0),
+            now,
+        )
+        .await
+        .unwrap();
+        let long = "word ".repeat(50);
+        st.on_status_at(&cfg, &api, StatusType::SegmentEnd, &long, Some(1), now)
+            .await
+            .unwrap();
+        st.tick_progress(&api).await.unwrap();
+        st.on_status_at(&cfg, &api, StatusType::Done, "", None, now)
+            .await
+            .unwrap();
+
+        let sends = api.sends();
+        assert_eq!(sends[0], "short answer");
+        assert!(sends.len() >= 5, "long segment split: {sends:?}");
+        assert!(sends.iter().all(|s| s.len() <= 100), "{sends:?}");
+        assert!(sends.last().unwrap().starts_with("✅ Completed"));
+        assert_eq!(api.attempts(Op::Edit), 0);
+        assert_eq!(api.attempts(Op::Reaction), 0);
+    }
+
+    #[tokio::test]
+    async fn segments_respect_the_messengers_max_message_len() {
+        let cfg = test_config();
+        let mut st = StreamingState::new(ChatId(1).into());
+        let api = RecordingMessenger::with_capabilities(MessagingCapabilities {
+            max_message_len: 200,
+            ..RecordingMessenger::new().capabilities()
+        });
+        let now = Instant::now();
+
+        let text = "x".repeat(500);
+        st.on_status_at(&cfg, &api, StatusType::Text, &text, Some(0), now)
+            .await
+            .unwrap();
+        st.on_status_at(&cfg, &api, StatusType::SegmentEnd, &text, Some(0), now)
+            .await
+            .unwrap();
+
+        assert!(api.sends().iter().all(|s| s.len() <= 200));
+        assert!(api.edited_html().iter().all(|s| s.len() <= 200));
+        assert_eq!(
+            api.sends().iter().filter(|s| s.starts_with('x')).count(),
+            1 + 5
+        );
+    }
+
+    #[tokio::test]
+    async fn user_deleted_segment_is_resent_and_editing_continues() {
+        let cfg = test_config();
+        let mut st = StreamingState::new(ChatId(1).into());
+        let api = RecordingMessenger::new();
+        let now = Instant::now();
+        let at = |ms| now + Duration::from_millis(ms);
+
+        st.on_status_at(&cfg, &api, StatusType::Text, "hello", Some(0), now)
+            .await
+            .unwrap();
+        let original = st.text_messages[&0];
+
+        // A transient failure keeps the message; the next update edits it again.
+        api.fail_next(Op::Edit, Failure::Error("timeout".to_string()));
+        st.on_status_at(&cfg, &api, StatusType::Text, "hello wo", Some(0), at(600))
+            .await
+            .unwrap();
+        assert_eq!(st.text_messages[&0], original);
+
+        api.delete_by_user(original);
+        st.on_status_at(
+            &cfg,
+            &api,
+            StatusType::Text,
+            "hello world",
+            Some(0),
+            at(1200),
+        )
+        .await
+        .unwrap();
+        let resent = st.text_messages[&0];
+        assert_ne!(resent, original);
+        api.assert_sent_containing("hello world");
+
+        st.on_status_at(
+            &cfg,
+            &api,
+            StatusType::SegmentEnd,
+            "hello world!",
+            Some(0),
+            at(1800),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            api.edits().last().unwrap(),
+            &(resent, "hello world!".to_string())
+        );
+        assert!(
+            !api.deletes().contains(&original),
+            "gone message is not deleted again"
+        );
+    }
+
+    #[tokio::test]
+    async fn user_deleted_progress_is_recreated_on_tick() {
+        let cfg = test_config();
+        let mut st = StreamingState::new(ChatId(1).into());
+        let api = RecordingMessenger::new();
+
+        st.on_status(&cfg, &api, StatusType::Tool, "tool", None)
+            .await
+            .unwrap();
+        let progress = st.progress_message.unwrap();
+
+        api.delete_by_user(progress);
+        st.tick_progress(&api).await.unwrap();
+        let recreated = st.progress_message.unwrap();
+        assert_ne!(recreated, progress);
+
+        st.tick_progress(&api).await.unwrap();
+        assert_eq!(api.edits().last().unwrap().0, recreated);
+
+        api.delete_by_user(recreated);
+        st.abort_progress(&api, "stopped").await.unwrap();
+        api.assert_sent_containing("stopped");
+    }
+
+    /// Messages left in the chat, oldest first, with their latest HTML.
+    fn chat_layout(api: &RecordingMessenger) -> Vec<String> {
+        let mut layout: Vec<(MessageRef, String)> = Vec::new();
+        for c in api.calls() {
+            match c.call {
+                Call::Send { msg, html, .. } => layout.push((msg, html)),
+                Call::Edit { msg, html } => {
+                    if let Some(entry) = layout.iter_mut().find(|(m, _)| *m == msg) {
+                        entry.1 = html;
+                    }
+                }
+                Call::Delete { msg } => layout.retain(|(m, _)| *m != msg),
+                _ => {}
+            }
+        }
+        layout.into_iter().map(|(_, html)| html).collect()
+    }
+
+    /// Three rounds of thinking + four tool calls, then a streamed answer.
+    async fn busy_turn(policy: ProgressPolicy) -> (usize, Vec<String>) {
+        let cfg = test_config();
+        let mut st = StreamingState::new(ChatId(1).into()).with_progress_policy(policy);
+        let api = RecordingMessenger::new();
+        let now = Instant::now();
+        for round in 0..3 {
+            st.on_status_at(&cfg, &api, StatusType::Thinking, "hmm", None, now)
+                .await
+                .unwrap();
+            for tool in 0..4 {
+                let html = format!("🔧 tool {round}.{tool}");
+                st.on_status_at(&cfg, &api, StatusType::Tool, &html, None, now)
+                    .await
+                    .unwrap();
+            }
+        }
+        st.on_status_at(&cfg, &api, StatusType::Text, "The answer", Some(0), now)
+            .await
+            .unwrap();
+        st.on_status_at(
+            &cfg,
+            &api,
+            StatusType::SegmentEnd,
+            "The answer is 42.",
+            Some(0),
+            now,
+        )
+        .await
+        .unwrap();
+        st.on_status_at(&cfg, &api, StatusType::Done, "", None, now)
+            .await
+            .unwrap();
+        (api.calls().len(), chat_layout(&api))
+    }
+
+    #[tokio::test]
+    async fn lazy_progress_policy_saves_calls_and_still_ends_with_completion() {
+        let (every_time, legacy_layout) = busy_turn(ProgressPolicy::default()).await;
+        let (lazy, lazy_layout) = busy_turn(ProgressPolicy {
+            resend_after_messages: 5,
+            resend_after: None,
+        })
+        .await;
+
+        // 17 messages below the spinner: a delete + send pair after each of them vs one pair
+        // per six (plus one to move the completion line down). 67 calls become 40.
+        assert!(
+            lazy * 3 < every_time * 2,
+            "lazy policy made {lazy} calls, every-message policy {every_time}"
+        );
+        for layout in [&legacy_layout, &lazy_layout] {
+            assert!(
+                layout.last().unwrap().starts_with("✅ Completed"),
+                "{layout:?}"
+            );
+            assert!(layout.iter().any(|m| m.contains("The answer is 42.")));
+            assert_eq!(
+                layout.iter().filter(|m| m.contains("Working...")).count(),
+                0,
+                "{layout:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_progress_is_resent_on_the_next_message() {
+        let cfg = test_config();
+        let mut st = StreamingState::new(ChatId(1).into()).with_progress_policy(ProgressPolicy {
+            resend_after_messages: 100,
+            resend_after: Some(Duration::from_millis(20)),
+        });
+        let api = RecordingMessenger::new();
+
+        st.on_status(&cfg, &api, StatusType::Tool, "one", None)
+            .await
+            .unwrap();
+        let first = st.progress_message.unwrap();
+        st.on_status(&cfg, &api, StatusType::Tool, "two", None)
+            .await
+            .unwrap();
+        assert_eq!(st.progress_message, Some(first), "young spinner stays put");
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        st.on_status(&cfg, &api, StatusType::Tool, "three", None)
+            .await
+            .unwrap();
+        assert_ne!(st.progress_message, Some(first));
+        api.assert_deleted(first);
     }
 }
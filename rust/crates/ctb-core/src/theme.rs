@@ -0,0 +1,419 @@
+//! Status glyphs (`STATUS_THEME`).
+//!
+//! Everything the bot draws around its own output — spinner frames, the completion line, tool
+//! status icons, scheduler and `/status` markers — comes from a [`StatusTheme`], so clients that
+//! render emoji or braille poorly can switch to `ascii` (pure ASCII) or `minimal` (plain
+//! symbols, no emoji). Fixed copy (catalog strings, notices, buttons) is drawn with
+//! [`StatusTheme::render`], which swaps status emoji for the theme's glyphs and drops the
+//! decorative ones.
+
+use std::borrow::Cow;
+
+/// One set of status glyphs; see [`StatusTheme::by_name`] for the built-in ones.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StatusTheme {
+    pub name: &'static str,
+    /// Progress spinner frames, one per tick.
+    pub spinner: &'static [&'static str],
+    pub thinking: &'static str,
+    /// Completion line, successful tool results, active session.
+    pub done: &'static str,
+    /// Failed tool results and scheduled runs.
+    pub failed: &'static str,
+    /// Completion time range and reminders.
+    pub clock: &'static str,
+    pub arrow: &'static str,
+    /// Spinner while a turn waits for an `ask_user` answer.
+    pub paused: &'static str,
+    /// Code blocks sent as files.
+    pub attachment: &'static str,
+    /// Spinner usage suffix: input and output tokens, and the separator between parts.
+    pub up: &'static str,
+    pub down: &'static str,
+    pub separator: &'static str,
+    /// Tool icons by tool name substring; tools without one use `tool`.
+    pub tools: &'static [(&'static str, &'static str)],
+    pub tool: &'static str,
+    /// Reading an image.
+    pub viewing: &'static str,
+    pub ellipsis: &'static str,
+    pub dash: &'static str,
+    pub bullet: &'static str,
+    /// Tree branch under a `/status` line.
+    pub branch: &'static str,
+    pub idle: &'static str,
+    pub running: &'static str,
+    pub warning: &'static str,
+    pub budget: &'static str,
+    pub compacted: &'static str,
+    /// Tool call refused by a safety check; `web` for a blocked host.
+    pub blocked: &'static str,
+    pub web: &'static str,
+    pub question: &'static str,
+    /// Decorative emoji in headings (`📊 Bot Status`); off drops them.
+    pub emoji: bool,
+}
+
+pub static UNICODE: StatusTheme = StatusTheme {
+    name: "unicode",
+    spinner: &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"],
+    thinking: "🧠",
+    done: "✅",
+    failed: "❌",
+    clock: "⏰",
+    arrow: "→",
+    paused: "⏸",
+    attachment: "📎",
+    up: "↑",
+    down: "↓",
+    separator: "·",
+    tools: &[
+        ("Read", "📖"),
+        ("Write", "📝"),
+        ("Edit", "✏️"),
+        ("Bash", "▶️"),
+        ("Glob", "🔍"),
+        ("Grep", "🔎"),
+        ("WebSearch", "🔍"),
+        ("WebFetch", "🌐"),
+        ("Task", "🎯"),
+        ("TodoWrite", "📋"),
+        ("mcp__", "🔧"),
+    ],
+    tool: "🔧",
+    viewing: "👀",
+    ellipsis: "…",
+    dash: "—",
+    bullet: "•",
+    branch: "└─",
+    idle: "⚪",
+    running: "🔄",
+    warning: "⚠️",
+    budget: "💸",
+    compacted: "♻️",
+    blocked: "🔒",
+    web: "🌐",
+    question: "❓",
+    emoji: true,
+};
+
+pub static ASCII: StatusTheme = StatusTheme {
+    name: "ascii",
+    spinner: &["-", "\\", "|", "/"],
+    thinking: "~",
+    done: "[ok]",
+    failed: "[x]",
+    clock: "@",
+    arrow: "->",
+    paused: "||",
+    attachment: "[file]",
+    up: "in ",
+    down: "out ",
+    separator: "|",
+    tools: &[],
+    tool: ">",
+    viewing: ">",
+    ellipsis: "...",
+    dash: "-",
+    bullet: "-",
+    branch: "`-",
+    idle: "-",
+    running: "*",
+    warning: "[!]",
+    budget: "[$]",
+    compacted: "[~]",
+    blocked: "[x]",
+    web: "[x]",
+    question: "[?]",
+    emoji: false,
+};
+
+pub static MINIMAL: StatusTheme = StatusTheme {
+    name: "minimal",
+    spinner: &["◐", "◓", "◑", "◒"],
+    thinking: "∴",
+    done: "✓",
+    failed: "✗",
+    clock: "◷",
+    arrow: "→",
+    paused: "‖",
+    attachment: "+",
+    up: "↑",
+    down: "↓",
+    separator: "·",
+    tools: &[],
+    tool: "›",
+    viewing: "›",
+    ellipsis: "…",
+    dash: "—",
+    bullet: "•",
+    branch: "└",
+    idle: "○",
+    running: "●",
+    warning: "!",
+    budget: "$",
+    compacted: "↻",
+    blocked: "✗",
+    web: "✗",
+    question: "?",
+    emoji: false,
+};
+
+impl StatusTheme {
+    /// Built-in theme by name: `unicode` (default), `ascii` or `minimal`.
+    pub fn by_name(name: &str) -> Option<&'static StatusTheme> {
+        [&UNICODE, &ASCII, &MINIMAL]
+            .into_iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn spinner_frame(&self, tick: usize) -> &'static str {
+        self.spinner[tick % self.spinner.len()]
+    }
+
+    pub fn tool_icon(&self, tool_name: &str) -> &'static str {
+        self.tools
+            .iter()
+            .find(|(k, _)| tool_name.contains(k))
+            .map_or(self.tool, |(_, v)| v)
+    }
+
+    /// `icon` plus a space when the theme shows decorative emoji, else nothing.
+    pub fn deco(&self, icon: &str) -> String {
+        if self.emoji {
+            format!("{icon} ")
+        } else {
+            String::new()
+        }
+    }
+
+    /// `text` drawn in this theme: status emoji and typographic symbols become the theme's
+    /// glyphs, other emoji are dropped with the space after them. Unchanged when the theme shows
+    /// emoji.
+    pub fn render<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.emoji || text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c.is_ascii() {
+                out.push(c);
+                continue;
+            }
+            match self.glyph(c) {
+                Some(glyph) => out.push_str(glyph),
+                None if is_emoji(c) => {
+                    while chars.next_if(|n| is_emoji_modifier(*n)).is_some() {}
+                    chars.next_if_eq(&' ');
+                    continue;
+                }
+                None => out.push(c),
+            }
+            while chars.next_if(|n| is_emoji_modifier(*n)).is_some() {}
+        }
+        Cow::Owned(out)
+    }
+
+    /// This theme's glyph for a symbol used in fixed copy.
+    fn glyph(&self, c: char) -> Option<&'static str> {
+        Some(match c {
+            '✅' | '✔' => self.done,
+            '❌' | '✖' => self.failed,
+            '⏰' | '⏱' | '⏳' | '⌛' | '🕐' | '🕑' => self.clock,
+            '⏸' | '⏹' => self.paused,
+            '📎' => self.attachment,
+            '⚠' | '🚨' => self.warning,
+            '💸' => self.budget,
+            '♻' => self.compacted,
+            '🔒' | '⛔' => self.blocked,
+            '🌐' => self.web,
+            '❓' => self.question,
+            '🧠' => self.thinking,
+            '🔄' => self.running,
+            '⚪' | '⬜' => self.idle,
+            '🔧' => self.tool,
+            '👀' => self.viewing,
+            '…' => self.ellipsis,
+            '—' | '–' => self.dash,
+            '•' => self.bullet,
+            '·' => self.separator,
+            '→' => self.arrow,
+            '↑' => self.up,
+            '↓' => self.down,
+            '≤' => "<=",
+            '≥' => ">=",
+            _ => return None,
+        })
+    }
+}
+
+/// Pictographs and dingbats; drawn only by themes that show emoji.
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32, 0x2139 | 0x2190..=0x21FF | 0x2300..=0x23FF | 0x2500..=0x27BF | 0x2B00..=0x2BFF | 0x1F000..=0x1FAFF)
+}
+
+/// Variation selector and zero-width joiner trailing an emoji.
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c, '\u{FE0F}' | '\u{200D}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ask_user::render_text_options;
+    use crate::broadcast::{BroadcastReport, Delivery};
+    use crate::formatting::{
+        format_tool_group, format_tool_outcome, format_tool_status, format_usage_brief,
+        tool_status_parts,
+    };
+    use crate::health::{HealthCheck, HealthReport, Severity};
+    use crate::i18n::Locale;
+    use crate::messaging::types::OptionsKeyboard;
+    use crate::metrics::ProcessMetrics;
+    use crate::model::types::TokenUsage;
+    use crate::plan_mode::review_keyboard;
+    use crate::usage::monitor::{UsageWarning, UsageWindow};
+    use serde_json::json;
+
+    #[test]
+    fn themes_are_found_by_name() {
+        assert_eq!(StatusTheme::by_name("ASCII"), Some(&ASCII));
+        assert_eq!(StatusTheme::by_name(" minimal "), Some(&MINIMAL));
+        assert_eq!(StatusTheme::by_name("unicode"), Some(&UNICODE));
+        assert_eq!(StatusTheme::by_name("emoji"), None);
+    }
+
+    #[test]
+    fn ascii_theme_renders_pure_ascii() {
+        let theme = &ASCII;
+        let mut rendered: Vec<String> = theme.spinner.iter().map(|s| s.to_string()).collect();
+        for (tool, input) in [
+            ("Read", json!({"file_path": "/src/main.rs"})),
+            ("Read", json!({"file_path": "/img/shot.png"})),
+            ("Write", json!({"file_path": "/src/lib.rs"})),
+            ("Edit", json!({"file_path": "/src/lib.rs"})),
+            ("Bash", json!({"command": "cargo test"})),
+            ("Grep", json!({"pattern": "fn main", "path": "/src"})),
+            ("Glob", json!({"pattern": "**/*.rs"})),
+            ("WebSearch", json!({"query": "rust"})),
+            ("WebFetch", json!({"url": "https://example.com"})),
+            ("Task", json!({"description": "explore"})),
+            ("mcp__ask-user__ask_user", json!({})),
+        ] {
+            rendered.push(format_tool_status(theme, tool, &input));
+        }
+        let subjects: Vec<String> = (0..5).map(|i| format!("f{i}.rs")).collect();
+        let (action, _) = tool_status_parts(theme, "Read", &json!({"file_path": "/a.rs"})).unwrap();
+        rendered.push(format_tool_group(theme, &action, &subjects));
+        rendered.push(format_tool_outcome(theme, false, "ok", false));
+        rendered.push(format_tool_outcome(theme, true, "Exit code 2\nboom", false));
+        let usage = TokenUsage {
+            input_tokens: 2_100,
+            output_tokens: 800,
+            ..Default::default()
+        };
+        rendered.push(format_usage_brief(theme, &usage, 0.0183));
+        let glyphs = [
+            theme.thinking,
+            theme.done,
+            theme.failed,
+            theme.clock,
+            theme.arrow,
+            theme.paused,
+            theme.attachment,
+            theme.ellipsis,
+            theme.dash,
+            theme.bullet,
+            theme.branch,
+            theme.idle,
+            theme.running,
+            theme.warning,
+            theme.budget,
+            theme.compacted,
+            theme.blocked,
+            theme.web,
+            theme.question,
+        ];
+        rendered.extend(glyphs.iter().map(|g| g.to_string()));
+        rendered.push(theme.deco("📊"));
+
+        for s in &rendered {
+            assert!(s.is_ascii(), "not ASCII: {s:?}");
+        }
+    }
+
+    #[test]
+    fn ascii_theme_draws_every_bot_string_without_symbols() {
+        let theme = &ASCII;
+        let mut rendered: Vec<String> = Vec::new();
+        for locale in Locale::ALL {
+            let msgs = locale.messages().themed(theme);
+            rendered.extend(msgs.templates().iter().map(|t| t.to_string()));
+        }
+
+        let options: Vec<String> = (1..=12).map(|i| format!("Option number {i}")).collect();
+        let mut keyboards = vec![review_keyboard(theme)];
+        for page in 0..2 {
+            keyboards.push(
+                OptionsKeyboard::new("req", &options)
+                    .max_label_len(8)
+                    .page(page)
+                    .theme(theme)
+                    .build(),
+            );
+        }
+        for keyboard in &keyboards {
+            rendered.extend(keyboard.buttons().map(|b| b.label.clone()));
+        }
+        rendered.push(render_text_options(theme, "Pick one", &options));
+
+        let report = BroadcastReport {
+            deliveries: vec![(1, Delivery::Sent), (2, Delivery::Failed("boom".into()))],
+        };
+        rendered.push(report.summary(theme));
+        let health = HealthReport {
+            checks: [
+                Severity::Ok,
+                Severity::Info,
+                Severity::Warn,
+                Severity::Error,
+            ]
+            .into_iter()
+            .map(|severity| HealthCheck {
+                name: "check",
+                severity,
+                detail: "detail".into(),
+            })
+            .collect(),
+        };
+        rendered.push(theme.render(&health.to_html()).into_owned());
+        rendered.extend(
+            health
+                .problems_html()
+                .map(|h| theme.render(&h).into_owned()),
+        );
+        rendered.push(theme.render(&ProcessMetrics::new().to_html()).into_owned());
+        let warning = UsageWarning {
+            window: UsageWindow {
+                provider: "Claude",
+                window: "5h",
+                used_percent: 96.0,
+                resets_at: None,
+            },
+            threshold: 95.0,
+        };
+        rendered.push(
+            theme
+                .render(&warning.to_html(chrono::Utc::now()))
+                .into_owned(),
+        );
+
+        for s in &rendered {
+            assert!(
+                s.chars().all(|c| c.is_ascii() || c.is_alphanumeric()),
+                "symbol left in: {s:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Per-session turn history behind `/export`.
+//!
+//! Each completed turn keeps the prompt as sent and the model's raw Markdown output (before HTML
+//! conversion), so code fences survive the round trip. History is bounded by turn count and
+//! bytes; the oldest turns are dropped first.
+
+use std::collections::VecDeque;
+
+use crate::{
+    formatting::format_token_count, model::types::TokenUsage, usage::pricing::ModelPricing,
+};
+
+/// Turns kept per session.
+pub const TRANSCRIPT_MAX_TURNS: usize = 200;
+/// Prompt + response bytes kept per session.
+pub const TRANSCRIPT_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct TranscriptTurn {
+    pub started_at: String,
+    pub finished_at: String,
+    pub prompt: String,
+    /// Model output as Markdown (what the CLI returned, not the Telegram HTML).
+    pub response: String,
+    /// Tool names in call order.
+    pub tools: Vec<String>,
+    pub usage: Option<TokenUsage>,
+}
+
+impl TranscriptTurn {
+    fn size(&self) -> usize {
+        self.prompt.len() + self.response.len()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Transcript {
+    turns: VecDeque<TranscriptTurn>,
+    bytes: usize,
+    /// Turns evicted by the caps (still counted in turn numbering).
+    dropped: usize,
+    max_turns: usize,
+    max_bytes: usize,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self::with_limits(TRANSCRIPT_MAX_TURNS, TRANSCRIPT_MAX_BYTES)
+    }
+}
+
+impl Transcript {
+    pub fn with_limits(max_turns: usize, max_bytes: usize) -> Self {
+        Self {
+            turns: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+            max_turns: max_turns.max(1),
+            max_bytes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.turns.len()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::with_limits(self.max_turns, self.max_bytes);
+    }
+
+    /// Append a turn, evicting the oldest ones past the caps (the newest turn is always kept).
+    pub fn push(&mut self, turn: TranscriptTurn) {
+        self.bytes += turn.size();
+        self.turns.push_back(turn);
+        while self.turns.len() > 1
+            && (self.turns.len() > self.max_turns || self.bytes > self.max_bytes)
+        {
+            if let Some(old) = self.turns.pop_front() {
+                self.bytes -= old.size();
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Markdown document: one section per turn, then a usage footer for the whole session.
+    pub fn to_markdown(
+        &self,
+        session_id: Option<&str>,
+        session_usage: &TokenUsage,
+        pricing: &ModelPricing,
+        exported_at: &str,
+    ) -> String {
+        let mut out = format!(
+            "# Session {}\n\n_Exported {exported_at} · {} turn(s)",
+            session_id.unwrap_or("(new)"),
+            self.turns.len()
+        );
+        if self.dropped > 0 {
+            out.push_str(&format!(" · {} earlier turn(s) not retained", self.dropped));
+        }
+        out.push_str("_\n");
+
+        for (i, turn) in self.turns.iter().enumerate() {
+            out.push_str(&format!(
+                "\n## Turn {} — {}\n\n### Prompt\n\n{}\n\n### Response\n\n{}\n",
+                self.dropped + i + 1,
+                turn.started_at,
+                close_open_fence(turn.prompt.trim_end()),
+                close_open_fence(turn.response.trim_end())
+            ));
+            let mut meta = vec![format!("finished {}", turn.finished_at)];
+            if !turn.tools.is_empty() {
+                meta.push(format!("tools: {}", tool_summary(&turn.tools)));
+            }
+            if let Some(u) = &turn.usage {
+                meta.push(usage_line(u, pricing));
+            }
+            out.push_str(&format!("\n_{}_\n", meta.join(" · ")));
+        }
+
+        out.push_str(&format!(
+            "\n---\n\n## Usage\n\n| | Tokens |\n|---|---:|\n| Input | {} |\n| Output | {} |\n| Cache read | {} |\n| Cache write | {} |\n\nEstimated cost: ${:.2}\n",
+            session_usage.input_tokens,
+            session_usage.output_tokens,
+            session_usage.cache_read_input_tokens,
+            session_usage.cache_creation_input_tokens,
+            pricing.cost(session_usage).total()
+        ));
+        out
+    }
+}
+
+/// `Read ×3, Bash` in first-use order.
+fn tool_summary(tools: &[String]) -> String {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for t in tools {
+        match counts.iter_mut().find(|(name, _)| name == t) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((t, 1)),
+        }
+    }
+    counts
+        .iter()
+        .map(|(name, n)| match n {
+            1 => format!("`{name}`"),
+            n => format!("`{name}` ×{n}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn usage_line(u: &TokenUsage, pricing: &ModelPricing) -> String {
+    format!(
+        "↑{} ↓{} tokens · ~${:.2}",
+        format_token_count(u.prompt_tokens()),
+        format_token_count(u.output_tokens),
+        pricing.cost(u).total()
+    )
+}
+
+/// Close a code fence left open (e.g. a truncated answer) so it doesn't swallow later sections.
+fn close_open_fence(text: &str) -> String {
+    let open = text
+        .lines()
+        .filter(|l| l.trim_start().starts_with("```"))
+        .count()
+        % 2
+        == 1;
+    if open {
+        format!("{text}\n```")
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(prompt: &str, response: &str) -> TranscriptTurn {
+        TranscriptTurn {
+            started_at: "2026-01-01T10:00:00Z".to_string(),
+            finished_at: "2026-01-01T10:00:30Z".to_string(),
+            prompt: prompt.to_string(),
+            response: response.to_string(),
+            tools: Vec::new(),
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn renders_turns_with_code_fences_and_usage_footer() {
+        let mut t = Transcript::default();
+        let mut first = turn("fix the bug", "Done:\n```rust\nfn main() {}\n```");
+        first.tools = vec!["Read".into(), "Edit".into(), "Read".into()];
+        first.usage = Some(TokenUsage {
+            input_tokens: 1_200,
+            output_tokens: 300,
+            ..Default::default()
+        });
+        t.push(first);
+        t.push(turn("and now?", "```sh\ncargo test"));
+
+        let usage = TokenUsage {
+            input_tokens: 5_000,
+            output_tokens: 800,
+            ..Default::default()
+        };
+        let pricing = ModelPricing {
+            input: 10.0,
+            output: 50.0,
+            cache_read: 1.0,
+            cache_write: 12.5,
+        };
+        let md = t.to_markdown(Some("abcd1234"), &usage, &pricing, "2026-01-01T11:00:00Z");
+
+        assert!(
+            md.starts_with("# Session abcd1234\n\n_Exported 2026-01-01T11:00:00Z · 2 turn(s)_\n")
+        );
+        assert!(md.contains("## Turn 1 — 2026-01-01T10:00:00Z\n\n### Prompt\n\nfix the bug\n"));
+        assert!(md.contains("```rust\nfn main() {}\n```\n"));
+        assert!(md.contains("tools: `Read` ×2, `Edit`"), "{md}");
+        assert!(md.contains("↑1.2k ↓0.3k tokens · ~$0.03"), "{md}");
+        assert!(
+            md.contains("```sh\ncargo test\n```\n"),
+            "open fence is closed"
+        );
+        assert!(md.contains("| Input | 5000 |"));
+        assert!(md.ends_with("Estimated cost: $0.09\n"), "{md}");
+    }
+
+    #[test]
+    fn caps_turns_and_bytes() {
+        let mut t = Transcript::with_limits(3, 1_000);
+        for i in 0..5 {
+            t.push(turn(&format!("q{i}"), "a"));
+        }
+        assert_eq!(t.len(), 3);
+        let pricing = crate::usage::pricing::PricingTable::default()
+            .resolve(None)
+            .pricing;
+        let md = t.to_markdown(None, &TokenUsage::default(), &pricing, "now");
+        assert!(md.contains("2 earlier turn(s) not retained"));
+        assert!(md.contains("## Turn 3 —") && !md.contains("## Turn 2 —"));
+
+        t.push(turn(&"x".repeat(998), "y"));
+        assert_eq!(t.len(), 1, "byte cap evicts older turns");
+        t.push(turn(&"z".repeat(5_000), ""));
+        assert_eq!(t.len(), 1, "an oversized turn is still kept");
+
+        t.clear();
+        assert!(t.is_empty());
+    }
+}
@@ -0,0 +1,427 @@
+//! Undo journal for file changes made by Claude (`/undo`).
+//!
+//! The CLI runs with bypassed permissions, so a Write/Edit can't be held back until the bot has
+//! copied the file. Instead the file is copied the first time a turn announces a Write/Edit for
+//! it, as soon as the model client reads that line ([`UndoRecorder::before_event`]), before the
+//! event waits in the pipeline's queue. The CLI prints the call before running it, so the copy
+//! normally holds the file as it was before the turn, but this is a race the bot usually wins,
+//! not a guarantee; files changed any other way (e.g. by Bash) are not recorded at all.
+//!
+//! Snapshots live in `temp_dir/undo/<turn>/`; only the newest `UNDO_KEEP_TURNS` turns are kept,
+//! and the directory is emptied on startup since the index is in memory only.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    config::Config, domain::ChatTarget, model::types::ModelEvent, security::PathPolicy, Error,
+    Result,
+};
+
+/// Files larger than this are listed but not snapshotted.
+pub const UNDO_MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Tools whose calls write the file named in their input, and that input's key.
+const WRITE_TOOLS: [(&str, &str); 4] = [
+    ("Write", "file_path"),
+    ("Edit", "file_path"),
+    ("MultiEdit", "file_path"),
+    ("NotebookEdit", "notebook_path"),
+];
+
+/// The file a `tool_name` call writes, if it is a file-writing tool.
+pub fn written_path<'a>(tool_name: &str, input: &'a serde_json::Value) -> Option<&'a str> {
+    let (_, key) = WRITE_TOOLS
+        .iter()
+        .find(|(name, _)| tool_name.eq_ignore_ascii_case(name))?;
+    input
+        .get(*key)
+        .and_then(|v| v.as_str())
+        .filter(|p| !p.is_empty())
+}
+
+/// A file's state before the turn that touched it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Snapshot {
+    /// Copy of the previous contents.
+    Saved(PathBuf),
+    /// The file didn't exist: undoing deletes it.
+    Created,
+    /// No copy could be taken (too large, unreadable); the file can't be restored.
+    Unavailable(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UndoFile {
+    pub path: PathBuf,
+    pub snapshot: Snapshot,
+}
+
+/// Files one turn wrote, in the order it first touched them.
+#[derive(Clone, Debug)]
+pub struct UndoTurn {
+    pub id: u64,
+    pub target: ChatTarget,
+    pub files: Vec<UndoFile>,
+}
+
+/// What restoring one file did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RestoreOutcome {
+    Restored,
+    /// The turn created the file; it was deleted.
+    Removed,
+    Skipped(String),
+}
+
+#[derive(Debug)]
+struct JournalState {
+    next_turn: u64,
+    /// Oldest first.
+    turns: VecDeque<UndoTurn>,
+}
+
+/// Snapshots of recent turns, shared by every session.
+#[derive(Debug)]
+pub struct UndoJournal {
+    root: PathBuf,
+    keep_turns: usize,
+    max_file_bytes: u64,
+    state: Mutex<JournalState>,
+}
+
+impl UndoJournal {
+    pub fn from_config(cfg: &Config) -> Arc<Self> {
+        Self::new(
+            cfg.temp_dir.join("undo"),
+            cfg.undo_keep_turns,
+            UNDO_MAX_FILE_BYTES,
+        )
+    }
+
+    /// Journal under `root`, keeping the newest `keep_turns` turns that wrote files. Snapshots
+    /// left in `root` by an earlier process are removed.
+    pub fn new(root: PathBuf, keep_turns: usize, max_file_bytes: u64) -> Arc<Self> {
+        if let Err(e) = fs::remove_dir_all(&root) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log_line!("[UNDO] Failed to clear {}: {e}", root.display());
+            }
+        }
+        Arc::new(Self {
+            root,
+            keep_turns,
+            max_file_bytes,
+            state: Mutex::new(JournalState {
+                next_turn: 1,
+                turns: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Start recording a turn in `target`. Nothing is stored until the turn writes a file.
+    pub fn begin_turn(self: &Arc<Self>, target: ChatTarget) -> UndoRecorder {
+        let id = {
+            let mut st = self.lock();
+            let id = st.next_turn;
+            st.next_turn += 1;
+            id
+        };
+        UndoRecorder {
+            journal: Arc::clone(self),
+            turn: id,
+            target,
+            touched: HashSet::new(),
+        }
+    }
+
+    /// The latest turn in `target` that wrote files.
+    pub fn last_turn(&self, target: ChatTarget) -> Option<UndoTurn> {
+        self.lock()
+            .turns
+            .iter()
+            .rev()
+            .find(|t| t.target == target)
+            .cloned()
+    }
+
+    /// Restore file `index` of turn `turn_id` (every file if `None`) to its pre-turn state. Only
+    /// `target`'s turns are reachable, and paths `paths` no longer allows writing are skipped.
+    pub fn restore(
+        &self,
+        target: ChatTarget,
+        turn_id: u64,
+        index: Option<usize>,
+        paths: &PathPolicy,
+    ) -> Result<Vec<(PathBuf, RestoreOutcome)>> {
+        let turn = self
+            .lock()
+            .turns
+            .iter()
+            .find(|t| t.id == turn_id && t.target == target)
+            .cloned()
+            .ok_or_else(|| Error::External("those snapshots are no longer kept".to_string()))?;
+        let files: Vec<&UndoFile> = match index {
+            Some(i) => vec![turn
+                .files
+                .get(i)
+                .ok_or_else(|| Error::External(format!("no file #{i} in that turn")))?],
+            None => turn.files.iter().collect(),
+        };
+        Ok(files
+            .into_iter()
+            .map(|f| (f.path.clone(), restore_file(f, paths)))
+            .collect())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JournalState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, turn_id: u64, target: ChatTarget, file: UndoFile) {
+        let mut st = self.lock();
+        if let Some(turn) = st.turns.iter_mut().find(|t| t.id == turn_id) {
+            turn.files.push(file);
+            return;
+        }
+        st.turns.push_back(UndoTurn {
+            id: turn_id,
+            target,
+            files: vec![file],
+        });
+        while st.turns.len() > self.keep_turns {
+            let Some(old) = st.turns.pop_front() else {
+                break;
+            };
+            let _ = fs::remove_dir_all(self.turn_dir(old.id));
+        }
+    }
+
+    fn turn_dir(&self, turn_id: u64) -> PathBuf {
+        self.root.join(turn_id.to_string())
+    }
+
+    /// Copy `path` into the turn's directory as file number `n`.
+    fn snapshot(&self, turn_id: u64, n: usize, path: &Path) -> Snapshot {
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Snapshot::Created,
+            Err(e) => return Snapshot::Unavailable(e.to_string()),
+        };
+        if !meta.is_file() {
+            return Snapshot::Unavailable("not a regular file".to_string());
+        }
+        if meta.len() > self.max_file_bytes {
+            return Snapshot::Unavailable(format!("larger than {} bytes", self.max_file_bytes));
+        }
+        let dir = self.turn_dir(turn_id);
+        let copy = dir.join(n.to_string());
+        match fs::create_dir_all(&dir).and_then(|_| fs::copy(path, &copy)) {
+            Ok(_) => Snapshot::Saved(copy),
+            Err(e) => Snapshot::Unavailable(e.to_string()),
+        }
+    }
+}
+
+fn restore_file(file: &UndoFile, paths: &PathPolicy) -> RestoreOutcome {
+    if !paths.is_write_allowed(&file.path.to_string_lossy()) {
+        return RestoreOutcome::Skipped("no longer an allowed path".to_string());
+    }
+    match &file.snapshot {
+        Snapshot::Saved(copy) => match fs::copy(copy, &file.path) {
+            Ok(_) => RestoreOutcome::Restored,
+            Err(e) => RestoreOutcome::Skipped(e.to_string()),
+        },
+        Snapshot::Created => match fs::remove_file(&file.path) {
+            Ok(()) => RestoreOutcome::Removed,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RestoreOutcome::Removed,
+            Err(e) => RestoreOutcome::Skipped(e.to_string()),
+        },
+        Snapshot::Unavailable(reason) => RestoreOutcome::Skipped(format!("no snapshot: {reason}")),
+    }
+}
+
+/// One turn's handle on the journal, owned by its pipeline.
+#[derive(Debug)]
+pub struct UndoRecorder {
+    journal: Arc<UndoJournal>,
+    turn: u64,
+    target: ChatTarget,
+    touched: HashSet<PathBuf>,
+}
+
+impl UndoRecorder {
+    /// Snapshot the files `ev`'s tool calls are about to write. Called by the reader as each
+    /// event arrives, before it is queued for the pipeline.
+    pub fn before_event(&mut self, ev: &ModelEvent, paths: &PathPolicy) {
+        let ModelEvent::Assistant { raw } = ev else {
+            return;
+        };
+        let blocks = raw
+            .get("message")
+            .and_then(|m| m.get("content"))
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some("tool_use"));
+        for block in blocks {
+            let name = block.get("name").and_then(|v| v.as_str()).unwrap_or("");
+            let input = block.get("input").unwrap_or(&serde_json::Value::Null);
+            if let Some(path) = written_path(name, input) {
+                self.before_write(path, paths);
+            }
+        }
+    }
+
+    /// Snapshot `raw` before the turn's first write to it; later writes to the same file, and
+    /// paths `paths` doesn't allow writing, are ignored.
+    pub fn before_write(&mut self, raw: &str, paths: &PathPolicy) {
+        if !paths.is_write_allowed(raw) {
+            return;
+        }
+        let Ok(path) = paths.resolve_user_path(raw) else {
+            return;
+        };
+        if !self.touched.insert(path.clone()) {
+            return;
+        }
+        let snapshot = self.journal.snapshot(self.turn, self.touched.len(), &path);
+        if let Snapshot::Unavailable(reason) = &snapshot {
+            log_line!("[UNDO] Not snapshotting {}: {reason}", path.display());
+        }
+        self.journal
+            .record(self.turn, self.target, UndoFile { path, snapshot });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ChatId;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ctb-undo-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("work")).unwrap();
+        dir
+    }
+
+    fn policy(work: &Path) -> PathPolicy {
+        PathPolicy {
+            allowed_paths: vec![work.to_path_buf()],
+            temp_paths: vec![],
+            read_only_paths: vec![],
+            home_dir: None,
+            base_dir: None,
+        }
+    }
+
+    #[test]
+    fn only_the_first_write_of_a_file_is_snapshotted() {
+        let dir = scratch("first");
+        let work = dir.join("work");
+        let paths = policy(&work);
+        let file = work.join("notes.txt");
+        fs::write(&file, "before").unwrap();
+        let journal = UndoJournal::new(dir.join("undo"), 5, 1024);
+        let target = ChatTarget::from(ChatId(1));
+
+        let mut turn = journal.begin_turn(target);
+        turn.before_write(&file.to_string_lossy(), &paths);
+        fs::write(&file, "first edit").unwrap();
+        turn.before_write(&file.to_string_lossy(), &paths);
+        fs::write(&file, "second edit").unwrap();
+        turn.before_write(&work.join("new.txt").to_string_lossy(), &paths);
+        fs::write(work.join("new.txt"), "created").unwrap();
+        turn.before_write("/etc/passwd", &paths);
+
+        let last = journal.last_turn(target).unwrap();
+        assert_eq!(last.files.len(), 2, "{last:?}");
+        assert_eq!(last.files[1].snapshot, Snapshot::Created);
+        let Snapshot::Saved(copy) = &last.files[0].snapshot else {
+            panic!("{last:?}");
+        };
+        assert_eq!(fs::read_to_string(copy).unwrap(), "before");
+        assert!(journal.last_turn(ChatTarget::from(ChatId(2))).is_none());
+
+        // Events are scanned for writes as they are read.
+        let mut turn = journal.begin_turn(target);
+        let other = work.join("other.txt");
+        fs::write(&other, "old").unwrap();
+        let ev = ModelEvent::Assistant {
+            raw: serde_json::json!({"type": "assistant", "message": {"content": [
+                {"type": "text", "text": "editing"},
+                {"type": "tool_use", "id": "t1", "name": "Edit",
+                 "input": {"file_path": other.to_string_lossy(), "old_string": "old"}},
+                {"type": "tool_use", "id": "t2", "name": "Read",
+                 "input": {"file_path": file.to_string_lossy()}}
+            ]}}),
+        };
+        turn.before_event(&ev, &paths);
+        fs::write(&other, "new").unwrap();
+        let last = journal.last_turn(target).unwrap();
+        assert_eq!(last.files.len(), 1, "{last:?}");
+        assert_eq!(last.files[0].path, other);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_brings_back_previous_contents_and_deletes_created_files() {
+        let dir = scratch("restore");
+        let work = dir.join("work");
+        let paths = policy(&work);
+        let (a, b, c) = (work.join("a.txt"), work.join("b.txt"), work.join("c.txt"));
+        fs::write(&a, "a0").unwrap();
+        fs::write(&b, "b0").unwrap();
+        let journal = UndoJournal::new(dir.join("undo"), 5, 1024);
+        let target = ChatTarget::from(ChatId(1));
+
+        let mut turn = journal.begin_turn(target);
+        for (path, body) in [(&a, "a1"), (&b, "b1"), (&c, "c1")] {
+            turn.before_write(&path.to_string_lossy(), &paths);
+            fs::write(path, body).unwrap();
+        }
+        let id = journal.last_turn(target).unwrap().id;
+
+        let one = journal.restore(target, id, Some(1), &paths).unwrap();
+        assert_eq!(one, vec![(b.clone(), RestoreOutcome::Restored)]);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a1");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b0");
+
+        let all = journal.restore(target, id, None, &paths).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[2], (c.clone(), RestoreOutcome::Removed));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a0");
+        assert!(!c.exists());
+
+        let other = ChatTarget::from(ChatId(2));
+        assert!(journal.restore(other, id, None, &paths).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn old_turns_are_pruned_with_their_snapshots() {
+        let dir = scratch("prune");
+        let work = dir.join("work");
+        let paths = policy(&work);
+        let file = work.join("f.txt");
+        let journal = UndoJournal::new(dir.join("undo"), 2, 1024);
+        let target = ChatTarget::from(ChatId(1));
+
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            fs::write(&file, format!("v{i}")).unwrap();
+            let mut turn = journal.begin_turn(target);
+            turn.before_write(&file.to_string_lossy(), &paths);
+            ids.push(journal.last_turn(target).unwrap().id);
+        }
+
+        assert!(!journal.turn_dir(ids[0]).exists());
+        assert!(journal.restore(target, ids[0], None, &paths).is_err());
+        assert!(journal.turn_dir(ids[2]).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -4,23 +4,36 @@
 //! - Claude Code usage via oauth/usage endpoint (token from macOS keychain or ~/.claude/.credentials.json)
 //! - OpenAI Codex usage via ChatGPT backend endpoint (token from ~/.codex/auth.json)
 //! - Gemini usage via Code Assist API (token from macOS keychain or ~/.gemini/oauth_creds.json)
-//! - In-memory caching keyed by a short token hash
-//! - Best-effort: missing creds or API failures return `None` per provider
+//! - In-memory caching keyed by a short token hash; keychain lookups cached for the process
+//! - Best-effort: `fetch_all` is bounded by one deadline and reports a [`ProviderStatus`] per
+//!   provider instead of failing
+//! - Optional background warm-up ([`prefetch`]) so `/stats` reads warm caches
 
 use std::{
     collections::HashMap,
     path::PathBuf,
-    sync::Arc,
+    sync::{Arc, Mutex, OnceLock},
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use async_trait::async_trait;
 use regex::Regex;
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+pub mod monitor;
+pub mod prefetch;
+pub mod pricing;
+
 const API_TIMEOUT: Duration = Duration::from_secs(5);
 const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+/// Upper bound for a whole `fetch_all`, so `/stats` never waits on every per-request timeout.
+const FETCH_DEADLINE: Duration = Duration::from_secs(6);
+
+const CLAUDE_KEYCHAIN_SERVICE: &str = "Claude Code-credentials";
+const GEMINI_KEYCHAIN_SERVICE: &str = "gemini-cli-oauth";
+const GEMINI_KEYCHAIN_ACCOUNT: &str = "main-account";
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ClaudeUsageWindow {
@@ -56,26 +69,90 @@ pub struct GeminiUsage {
     pub reset_at: Option<String>,
 }
 
+/// Outcome of the last fetch for one provider.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderStatus {
+    Ok,
+    Timeout,
+    /// Credentials exist but were rejected (expired / revoked).
+    Unauthorized,
+    Error,
+    /// No local credentials; the provider is not in use.
+    #[default]
+    NotConfigured,
+}
+
+impl ProviderStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Timeout => "timed out",
+            Self::Unauthorized => "unauthorized",
+            Self::Error => "error",
+            Self::NotConfigured => "not configured",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderStatuses {
+    pub claude: ProviderStatus,
+    pub codex: ProviderStatus,
+    pub gemini: ProviderStatus,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AllUsage {
     pub claude: Option<ClaudeUsage>,
     pub codex: Option<CodexUsage>,
     pub gemini: Option<GeminiUsage>,
+    #[serde(default)]
+    pub status: ProviderStatuses,
+    /// When the oldest of the numbers was fetched; earlier than now when served from cache.
     pub fetched_at_ms: u64,
 }
 
-#[derive(Clone)]
-pub struct UsageService {
-    http: reqwest::Client,
-    claude_cache: Arc<tokio::sync::Mutex<HashMap<String, CacheEntry<ClaudeUsage>>>>,
-    codex_cache: Arc<tokio::sync::Mutex<HashMap<String, CacheEntry<CodexUsage>>>>,
-    gemini_cache: Arc<tokio::sync::Mutex<HashMap<String, CacheEntry<GeminiUsage>>>>,
+impl AllUsage {
+    /// How old the numbers are at `now_ms`.
+    pub fn age(&self, now_ms: u64) -> Duration {
+        Duration::from_millis(now_ms.saturating_sub(self.fetched_at_ms))
+    }
+}
+
+impl ProviderStatuses {
+    /// Whether any provider had credentials on the last fetch.
+    pub fn any_configured(&self) -> bool {
+        [self.claude, self.codex, self.gemini]
+            .iter()
+            .any(|s| *s != ProviderStatus::NotConfigured)
+    }
+}
+
+/// Failure side of a provider fetch (never `ProviderStatus::Ok`).
+pub type ProviderResult<T> = std::result::Result<T, ProviderStatus>;
+
+/// Provider numbers and when they were fetched (earlier than now when served from cache).
+#[derive(Clone, Debug)]
+pub struct Fetched<T> {
+    pub data: T,
+    pub at: Instant,
+}
+
+/// Where usage numbers come from; [`HttpUsageProviders`] in production, fakes in tests.
+#[async_trait]
+pub trait UsageProviders: Send + Sync {
+    async fn claude(&self, ttl: Duration) -> ProviderResult<Fetched<ClaudeUsage>>;
+    async fn codex(&self, ttl: Duration) -> ProviderResult<Fetched<CodexUsage>>;
+    async fn gemini(&self, ttl: Duration) -> ProviderResult<Fetched<GeminiUsage>>;
+    /// Whether any provider has local credentials (no network access).
+    async fn has_credentials(&self) -> bool;
 }
 
 #[derive(Clone)]
-struct CacheEntry<T> {
-    data: T,
-    at: Instant,
+pub struct UsageService {
+    providers: Arc<dyn UsageProviders>,
+    deadline: Duration,
 }
 
 impl Default for UsageService {
@@ -86,34 +163,152 @@ impl Default for UsageService {
 
 impl UsageService {
     pub fn new() -> Self {
-        let http = reqwest::Client::builder()
-            .timeout(API_TIMEOUT)
-            .user_agent("ctb-rust/0.1")
-            .build()
-            .expect("reqwest client build");
+        Self::with_providers(Arc::new(HttpUsageProviders::new()), FETCH_DEADLINE)
+    }
 
+    /// `deadline` bounds the whole `fetch_all`; providers still pending are reported as timed out.
+    pub fn with_providers(providers: Arc<dyn UsageProviders>, deadline: Duration) -> Self {
         Self {
-            http,
-            claude_cache: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
-            codex_cache: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
-            gemini_cache: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+            providers,
+            deadline,
         }
     }
 
     pub async fn fetch_all(&self, ttl: Option<Duration>) -> AllUsage {
         let ttl = ttl.unwrap_or(DEFAULT_CACHE_TTL);
+        let deadline = tokio::time::Instant::now() + self.deadline;
 
         let (claude, codex, gemini) = tokio::join!(
-            self.fetch_claude_usage(ttl),
-            self.fetch_codex_usage(ttl),
-            self.fetch_gemini_usage(ttl),
+            within(deadline, self.providers.claude(ttl)),
+            within(deadline, self.providers.codex(ttl)),
+            within(deadline, self.providers.gemini(ttl)),
         );
 
+        // Only numbers that are shown count; failed providers were just asked live.
+        let oldest = [
+            claude.as_ref().ok().map(|f| f.at),
+            codex.as_ref().ok().map(|f| f.at),
+            gemini.as_ref().ok().map(|f| f.at),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let age = oldest.map_or(Duration::ZERO, |at| at.elapsed());
+
         AllUsage {
-            claude,
-            codex,
-            gemini,
-            fetched_at_ms: now_ms(),
+            status: ProviderStatuses {
+                claude: status_of(&claude),
+                codex: status_of(&codex),
+                gemini: status_of(&gemini),
+            },
+            claude: claude.ok().map(|f| f.data),
+            codex: codex.ok().map(|f| f.data),
+            gemini: gemini.ok().map(|f| f.data),
+            fetched_at_ms: now_ms().saturating_sub(age.as_millis() as u64),
+        }
+    }
+
+    pub async fn has_credentials(&self) -> bool {
+        self.providers.has_credentials().await
+    }
+}
+
+async fn within<T>(
+    deadline: tokio::time::Instant,
+    fut: impl std::future::Future<Output = ProviderResult<T>>,
+) -> ProviderResult<T> {
+    tokio::time::timeout_at(deadline, fut)
+        .await
+        .unwrap_or(Err(ProviderStatus::Timeout))
+}
+
+fn status_of<T>(r: &ProviderResult<T>) -> ProviderStatus {
+    match r {
+        Ok(_) => ProviderStatus::Ok,
+        Err(s) => *s,
+    }
+}
+
+fn request_failed(e: reqwest::Error) -> ProviderStatus {
+    if e.is_timeout() {
+        ProviderStatus::Timeout
+    } else {
+        ProviderStatus::Error
+    }
+}
+
+fn check_response(resp: reqwest::Response) -> ProviderResult<reqwest::Response> {
+    match resp.status().as_u16() {
+        s if (200..300).contains(&s) => Ok(resp),
+        401 | 403 => Err(ProviderStatus::Unauthorized),
+        _ => Err(ProviderStatus::Error),
+    }
+}
+
+async fn json_body(resp: reqwest::Response) -> ProviderResult<serde_json::Value> {
+    check_response(resp)?
+        .json()
+        .await
+        .map_err(|_| ProviderStatus::Error)
+}
+
+/// Live provider endpoints, cached per access token.
+pub struct HttpUsageProviders {
+    http: reqwest::Client,
+    claude_cache: tokio::sync::Mutex<HashMap<String, Fetched<ClaudeUsage>>>,
+    codex_cache: tokio::sync::Mutex<HashMap<String, Fetched<CodexUsage>>>,
+    gemini_cache: tokio::sync::Mutex<HashMap<String, Fetched<GeminiUsage>>>,
+}
+
+impl Default for HttpUsageProviders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl UsageProviders for HttpUsageProviders {
+    async fn claude(&self, ttl: Duration) -> ProviderResult<Fetched<ClaudeUsage>> {
+        let result = self.fetch_claude_usage(ttl).await;
+        if result.as_ref().err() == Some(&ProviderStatus::Unauthorized) {
+            // The CLI may have refreshed the token since we cached it.
+            forget_keychain_password(CLAUDE_KEYCHAIN_SERVICE, None);
+        }
+        result
+    }
+
+    async fn codex(&self, ttl: Duration) -> ProviderResult<Fetched<CodexUsage>> {
+        self.fetch_codex_usage(ttl).await
+    }
+
+    async fn gemini(&self, ttl: Duration) -> ProviderResult<Fetched<GeminiUsage>> {
+        let result = self.fetch_gemini_usage(ttl).await;
+        if result.as_ref().err() == Some(&ProviderStatus::Unauthorized) {
+            forget_keychain_password(GEMINI_KEYCHAIN_SERVICE, Some(GEMINI_KEYCHAIN_ACCOUNT));
+        }
+        result
+    }
+
+    async fn has_credentials(&self) -> bool {
+        get_claude_access_token().await.is_some()
+            || get_codex_auth().await.is_some()
+            || get_gemini_credentials().await.is_some()
+    }
+}
+
+impl HttpUsageProviders {
+    pub fn new() -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(API_TIMEOUT)
+            .user_agent("ctb-rust/0.1")
+            .build()
+            .expect("reqwest client build");
+
+        Self {
+            http,
+            claude_cache: tokio::sync::Mutex::new(HashMap::new()),
+            codex_cache: tokio::sync::Mutex::new(HashMap::new()),
+            gemini_cache: tokio::sync::Mutex::new(HashMap::new()),
         }
     }
 
@@ -123,12 +318,14 @@ impl UsageService {
         self.gemini_cache.lock().await.clear();
     }
 
-    async fn fetch_claude_usage(&self, ttl: Duration) -> Option<ClaudeUsage> {
-        let token = get_claude_access_token().await?;
+    async fn fetch_claude_usage(&self, ttl: Duration) -> ProviderResult<Fetched<ClaudeUsage>> {
+        let token = get_claude_access_token()
+            .await
+            .ok_or(ProviderStatus::NotConfigured)?;
         let token_hash = hash_token(&token);
 
         if let Some(v) = self.get_cached(&self.claude_cache, &token_hash, ttl).await {
-            return Some(v);
+            return Ok(v);
         }
 
         let mut headers = HeaderMap::new();
@@ -136,7 +333,8 @@ impl UsageService {
         headers.insert("Content-Type", HeaderValue::from_static("application/json"));
         headers.insert(
             "Authorization",
-            HeaderValue::from_str(&format!("Bearer {token}")).ok()?,
+            HeaderValue::from_str(&format!("Bearer {token}"))
+                .map_err(|_| ProviderStatus::Unauthorized)?,
         );
         headers.insert(
             "anthropic-beta",
@@ -149,30 +347,26 @@ impl UsageService {
             .headers(headers)
             .send()
             .await
-            .ok()?;
-
-        if !resp.status().is_success() {
-            return None;
-        }
+            .map_err(request_failed)?;
 
-        let v: serde_json::Value = resp.json().await.ok()?;
+        let v = json_body(resp).await?;
         let usage = ClaudeUsage {
             five_hour: parse_claude_window(v.get("five_hour")),
             seven_day: parse_claude_window(v.get("seven_day")),
             seven_day_sonnet: parse_claude_window(v.get("seven_day_sonnet")),
         };
 
-        self.set_cached(&self.claude_cache, token_hash, usage.clone())
-            .await;
-        Some(usage)
+        Ok(self.set_cached(&self.claude_cache, token_hash, usage).await)
     }
 
-    async fn fetch_codex_usage(&self, ttl: Duration) -> Option<CodexUsage> {
-        let auth = get_codex_auth().await?;
+    async fn fetch_codex_usage(&self, ttl: Duration) -> ProviderResult<Fetched<CodexUsage>> {
+        let auth = get_codex_auth()
+            .await
+            .ok_or(ProviderStatus::NotConfigured)?;
         let token_hash = hash_token(&auth.access_token);
 
         if let Some(v) = self.get_cached(&self.codex_cache, &token_hash, ttl).await {
-            return Some(v);
+            return Ok(v);
         }
 
         let mut headers = HeaderMap::new();
@@ -180,11 +374,12 @@ impl UsageService {
         headers.insert("Content-Type", HeaderValue::from_static("application/json"));
         headers.insert(
             "Authorization",
-            HeaderValue::from_str(&format!("Bearer {}", auth.access_token)).ok()?,
+            HeaderValue::from_str(&format!("Bearer {}", auth.access_token))
+                .map_err(|_| ProviderStatus::Unauthorized)?,
         );
         headers.insert(
             "ChatGPT-Account-Id",
-            HeaderValue::from_str(&auth.account_id).ok()?,
+            HeaderValue::from_str(&auth.account_id).map_err(|_| ProviderStatus::Unauthorized)?,
         );
 
         let resp = self
@@ -193,15 +388,15 @@ impl UsageService {
             .headers(headers)
             .send()
             .await
-            .ok()?;
+            .map_err(request_failed)?;
 
-        if !resp.status().is_success() {
-            return None;
-        }
-
-        let v: serde_json::Value = resp.json().await.ok()?;
-        let plan_type = v.get("plan_type").and_then(|x| x.as_str())?.to_string();
-        let rate = v.get("rate_limit")?;
+        let v = json_body(resp).await?;
+        let plan_type = v
+            .get("plan_type")
+            .and_then(|x| x.as_str())
+            .ok_or(ProviderStatus::Error)?
+            .to_string();
+        let rate = v.get("rate_limit").ok_or(ProviderStatus::Error)?;
 
         let model = get_codex_model()
             .await
@@ -213,17 +408,15 @@ impl UsageService {
             secondary: parse_codex_window(rate.get("secondary_window")),
         };
 
-        self.set_cached(&self.codex_cache, token_hash, usage.clone())
-            .await;
-        Some(usage)
+        Ok(self.set_cached(&self.codex_cache, token_hash, usage).await)
     }
 
-    async fn fetch_gemini_usage(&self, ttl: Duration) -> Option<GeminiUsage> {
-        let creds = get_valid_gemini_credentials().await?;
+    async fn fetch_gemini_usage(&self, ttl: Duration) -> ProviderResult<Fetched<GeminiUsage>> {
+        let creds = get_valid_gemini_credentials(&self.http).await?;
         let token_hash = hash_token(&creds.access_token);
 
         if let Some(v) = self.get_cached(&self.gemini_cache, &token_hash, ttl).await {
-            return Some(v);
+            return Ok(v);
         }
 
         let project_id = get_gemini_project_id(&self.http, &creds).await?;
@@ -241,13 +434,9 @@ impl UsageService {
             .json(&serde_json::json!({ "project": project_id }))
             .send()
             .await
-            .ok()?;
-
-        if !resp.status().is_success() {
-            return None;
-        }
+            .map_err(request_failed)?;
 
-        let v: serde_json::Value = resp.json().await.ok()?;
+        let v = json_body(resp).await?;
         let buckets = v
             .get("buckets")
             .and_then(|b| b.as_array())
@@ -289,37 +478,34 @@ impl UsageService {
             reset_at,
         };
 
-        self.set_cached(&self.gemini_cache, token_hash, usage.clone())
-            .await;
-        Some(usage)
+        Ok(self.set_cached(&self.gemini_cache, token_hash, usage).await)
     }
 
     async fn get_cached<T: Clone>(
         &self,
-        cache: &tokio::sync::Mutex<HashMap<String, CacheEntry<T>>>,
+        cache: &tokio::sync::Mutex<HashMap<String, Fetched<T>>>,
         key: &str,
         ttl: Duration,
-    ) -> Option<T> {
+    ) -> Option<Fetched<T>> {
         let now = Instant::now();
         let map = cache.lock().await;
         map.get(key)
             .filter(|e| now.duration_since(e.at) < ttl)
-            .map(|e| e.data.clone())
+            .cloned()
     }
 
-    async fn set_cached<T>(
+    async fn set_cached<T: Clone>(
         &self,
-        cache: &tokio::sync::Mutex<HashMap<String, CacheEntry<T>>>,
+        cache: &tokio::sync::Mutex<HashMap<String, Fetched<T>>>,
         key: String,
         value: T,
-    ) {
-        cache.lock().await.insert(
-            key,
-            CacheEntry {
-                data: value,
-                at: Instant::now(),
-            },
-        );
+    ) -> Fetched<T> {
+        let entry = Fetched {
+            data: value,
+            at: Instant::now(),
+        };
+        cache.lock().await.insert(key, entry.clone());
+        entry
     }
 }
 
@@ -387,10 +573,7 @@ fn home_dir() -> Option<PathBuf> {
 
 async fn get_claude_access_token() -> Option<String> {
     if cfg!(target_os = "macos") {
-        if let Some(raw) =
-            security_find_generic_password("Claude Code-credentials", None, Duration::from_secs(3))
-                .await
-        {
+        if let Some(raw) = keychain_password(CLAUDE_KEYCHAIN_SERVICE, None).await {
             if let Ok(v) = serde_json::from_str::<serde_json::Value>(&raw) {
                 if let Some(tok) = v
                     .get("claudeAiOauth")
@@ -467,12 +650,8 @@ struct GeminiSettings {
 
 async fn get_gemini_credentials() -> Option<GeminiCredentials> {
     if cfg!(target_os = "macos") {
-        if let Some(raw) = security_find_generic_password(
-            "gemini-cli-oauth",
-            Some("main-account"),
-            Duration::from_secs(3),
-        )
-        .await
+        if let Some(raw) =
+            keychain_password(GEMINI_KEYCHAIN_SERVICE, Some(GEMINI_KEYCHAIN_ACCOUNT)).await
         {
             if let Ok(v) = serde_json::from_str::<serde_json::Value>(&raw) {
                 if let Some(tok) = v
@@ -560,25 +739,22 @@ async fn refresh_gemini_token(
     })
 }
 
-async fn get_valid_gemini_credentials() -> Option<GeminiCredentials> {
-    let creds = get_gemini_credentials().await?;
+async fn get_valid_gemini_credentials(http: &reqwest::Client) -> ProviderResult<GeminiCredentials> {
+    let creds = get_gemini_credentials()
+        .await
+        .ok_or(ProviderStatus::NotConfigured)?;
     let Some(expiry) = creds.expiry_date_ms else {
-        return Some(creds);
+        return Ok(creds);
     };
 
     // If expiring within 5 minutes, attempt refresh.
     if expiry < now_ms().saturating_add(5 * 60 * 1000) {
-        return refresh_gemini_token(
-            &reqwest::Client::builder()
-                .timeout(API_TIMEOUT)
-                .build()
-                .ok()?,
-            &creds,
-        )
-        .await;
+        return refresh_gemini_token(http, &creds)
+            .await
+            .ok_or(ProviderStatus::Unauthorized);
     }
 
-    Some(creds)
+    Ok(creds)
 }
 
 async fn get_gemini_settings() -> Option<GeminiSettings> {
@@ -603,21 +779,21 @@ async fn get_gemini_settings() -> Option<GeminiSettings> {
 async fn get_gemini_project_id(
     http: &reqwest::Client,
     creds: &GeminiCredentials,
-) -> Option<String> {
+) -> ProviderResult<String> {
     if let Ok(p) = std::env::var("GOOGLE_CLOUD_PROJECT") {
         if !p.trim().is_empty() {
-            return Some(p);
+            return Ok(p);
         }
     }
     if let Ok(p) = std::env::var("GOOGLE_CLOUD_PROJECT_ID") {
         if !p.trim().is_empty() {
-            return Some(p);
+            return Ok(p);
         }
     }
 
     if let Some(s) = get_gemini_settings().await {
         if let Some(p) = s.cloudaicompanion_project {
-            return Some(p);
+            return Ok(p);
         }
     }
 
@@ -634,15 +810,13 @@ async fn get_gemini_project_id(
         }))
         .send()
         .await
-        .ok()?;
+        .map_err(request_failed)?;
 
-    if !resp.status().is_success() {
-        return None;
-    }
-    let v: serde_json::Value = resp.json().await.ok()?;
+    let v = json_body(resp).await?;
     v.get("cloudaicompanionProject")
         .and_then(|x| x.as_str())
         .map(|s| s.to_string())
+        .ok_or(ProviderStatus::Error)
 }
 
 // === IO helpers ===
@@ -654,6 +828,34 @@ async fn read_file_to_string(path: PathBuf) -> Option<String> {
         .ok()
 }
 
+fn keychain_cache() -> &'static Mutex<HashMap<String, String>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+fn keychain_key(service: &str, account: Option<&str>) -> String {
+    format!("{service}\0{}", account.unwrap_or_default())
+}
+
+/// Keychain lookup, cached for the process lifetime (each miss spawns `security`).
+async fn keychain_password(service: &str, account: Option<&str>) -> Option<String> {
+    let key = keychain_key(service, account);
+    if let Some(v) = keychain_cache().lock().ok()?.get(&key) {
+        return Some(v.clone());
+    }
+    let v = security_find_generic_password(service, account, Duration::from_secs(3)).await?;
+    if let Ok(mut cache) = keychain_cache().lock() {
+        cache.insert(key, v.clone());
+    }
+    Some(v)
+}
+
+fn forget_keychain_password(service: &str, account: Option<&str>) {
+    if let Ok(mut cache) = keychain_cache().lock() {
+        cache.remove(&keychain_key(service, account));
+    }
+}
+
 async fn security_find_generic_password(
     service: &str,
     account: Option<&str>,
@@ -680,3 +882,101 @@ async fn security_find_generic_password(
         .ok()
         .map(|s| s.trim().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Claude answers from a cache filled at `claude_at`; Codex hangs; Gemini is not configured.
+    struct FakeProviders {
+        claude_at: Instant,
+    }
+
+    impl FakeProviders {
+        fn new() -> Self {
+            Self {
+                claude_at: Instant::now(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl UsageProviders for FakeProviders {
+        async fn claude(&self, _ttl: Duration) -> ProviderResult<Fetched<ClaudeUsage>> {
+            Ok(Fetched {
+                data: ClaudeUsage {
+                    five_hour: None,
+                    seven_day: None,
+                    seven_day_sonnet: None,
+                },
+                at: self.claude_at,
+            })
+        }
+
+        async fn codex(&self, _ttl: Duration) -> ProviderResult<Fetched<CodexUsage>> {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            Err(ProviderStatus::Error)
+        }
+
+        async fn gemini(&self, _ttl: Duration) -> ProviderResult<Fetched<GeminiUsage>> {
+            Err(ProviderStatus::NotConfigured)
+        }
+
+        async fn has_credentials(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_all_returns_partial_results_at_the_deadline() {
+        let service =
+            UsageService::with_providers(Arc::new(FakeProviders::new()), Duration::from_millis(50));
+        let started = Instant::now();
+        let all = service.fetch_all(None).await;
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(all.claude.is_some());
+        assert!(all.codex.is_none());
+        assert_eq!(
+            all.status,
+            ProviderStatuses {
+                claude: ProviderStatus::Ok,
+                codex: ProviderStatus::Timeout,
+                gemini: ProviderStatus::NotConfigured,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn age_is_that_of_the_oldest_cached_result() {
+        let cached_at = Instant::now();
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        let service = UsageService::with_providers(
+            Arc::new(FakeProviders {
+                claude_at: cached_at,
+            }),
+            Duration::from_millis(20),
+        );
+        let all = service.fetch_all(None).await;
+        assert!(all.claude.is_some());
+        assert!(all.age(now_ms()) >= Duration::from_millis(40));
+
+        // The timed-out Codex fetch doesn't make the age look fresher or older.
+        let fresh =
+            UsageService::with_providers(Arc::new(FakeProviders::new()), Duration::from_millis(20))
+                .fetch_all(None)
+                .await;
+        assert_eq!(fresh.status.codex, ProviderStatus::Timeout);
+        assert!(fresh.age(now_ms()) < Duration::from_millis(40));
+        assert!(fresh.status.any_configured());
+    }
+
+    #[test]
+    fn provider_status_serializes_snake_case() {
+        assert_eq!(
+            serde_json::to_string(&ProviderStatus::NotConfigured).unwrap(),
+            "\"not_configured\""
+        );
+        assert_eq!(ProviderStatus::Timeout.label(), "timed out");
+    }
+}
@@ -0,0 +1,315 @@
+//! Background provider-usage monitor.
+//!
+//! Polls [`UsageService::fetch_all`] and warns the owner chat once per window and reset period
+//! when utilization crosses one of [`WARN_THRESHOLDS`]. Dedup state is in memory only: after a
+//! restart a window that is still above a threshold is reported again, once.
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local, Utc};
+
+use super::{AllUsage, UsageService};
+use crate::{
+    domain::ChatTarget, formatting::escape_html, messaging::port::MessagingPort,
+    shutdown::Shutdown, theme::StatusTheme,
+};
+
+/// Utilization percentages that trigger a warning, ascending.
+pub const WARN_THRESHOLDS: [f64; 2] = [80.0, 95.0];
+
+/// One rate-limit window as reported by a provider.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsageWindow {
+    pub provider: &'static str,
+    pub window: &'static str,
+    pub used_percent: f64,
+    pub resets_at: Option<DateTime<Utc>>,
+}
+
+impl UsageWindow {
+    fn key(&self) -> String {
+        format!("{}/{}", self.provider, self.window)
+    }
+
+    /// Reset period identity; minute resolution absorbs jitter in reported timestamps.
+    fn period(&self) -> Option<i64> {
+        self.resets_at.map(|t| t.timestamp().div_euclid(60))
+    }
+}
+
+/// Flatten every known window out of a usage snapshot.
+pub fn usage_windows(all: &AllUsage) -> Vec<UsageWindow> {
+    let mut out = Vec::new();
+    if let Some(c) = &all.claude {
+        for (window, w) in [
+            ("5h", &c.five_hour),
+            ("7d", &c.seven_day),
+            ("7d Sonnet", &c.seven_day_sonnet),
+        ] {
+            if let Some(w) = w {
+                out.push(UsageWindow {
+                    provider: "Claude",
+                    window,
+                    used_percent: w.utilization,
+                    resets_at: parse_rfc3339(w.resets_at.as_deref()),
+                });
+            }
+        }
+    }
+    if let Some(c) = &all.codex {
+        for (window, w) in [("5h", &c.primary), ("7d", &c.secondary)] {
+            if let Some(w) = w {
+                out.push(UsageWindow {
+                    provider: "Codex",
+                    window,
+                    used_percent: w.used_percent,
+                    resets_at: (w.reset_at > 0)
+                        .then(|| DateTime::<Utc>::from_timestamp(w.reset_at as i64, 0))
+                        .flatten(),
+                });
+            }
+        }
+    }
+    if let Some(g) = &all.gemini {
+        if let Some(pct) = g.used_percent {
+            out.push(UsageWindow {
+                provider: "Gemini",
+                window: "quota",
+                used_percent: f64::from(pct),
+                resets_at: parse_rfc3339(g.reset_at.as_deref()),
+            });
+        }
+    }
+    out
+}
+
+fn parse_rfc3339(s: Option<&str>) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s?)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsageWarning {
+    pub window: UsageWindow,
+    pub threshold: f64,
+}
+
+impl UsageWarning {
+    pub fn to_html(&self, now: DateTime<Utc>) -> String {
+        let w = &self.window;
+        let icon = if self.threshold >= 95.0 {
+            "🚨"
+        } else {
+            "⚠️"
+        };
+        let mut out = format!(
+            "{icon} <b>{} {} usage at {:.0}%</b> (≥{:.0}%)",
+            escape_html(w.provider),
+            escape_html(w.window),
+            w.used_percent,
+            self.threshold
+        );
+        if let Some(reset) = w.resets_at {
+            out.push_str(&format!(
+                "\nResets {} ({})",
+                reset.with_timezone(&Local).format("%a %H:%M"),
+                format_remaining(reset - now)
+            ));
+        }
+        out
+    }
+}
+
+fn format_remaining(diff: chrono::Duration) -> String {
+    let secs = diff.num_seconds();
+    if secs <= 0 {
+        return "now".to_string();
+    }
+    let (days, hours, mins) = (secs / 86400, (secs % 86400) / 3600, (secs % 3600) / 60);
+    if days > 0 {
+        format!("in {days}d {hours}h")
+    } else if hours > 0 {
+        format!("in {hours}h {mins}m")
+    } else {
+        format!("in {mins}m")
+    }
+}
+
+/// Remembers which (window, reset period, threshold) triples were already reported.
+#[derive(Debug, Default)]
+pub struct UsageWarner {
+    warned: HashSet<(String, Option<i64>, u32)>,
+}
+
+impl UsageWarner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Warnings to send for `all`: at most one per window (the highest newly crossed threshold).
+    pub fn check(&mut self, all: &AllUsage) -> Vec<UsageWarning> {
+        let mut out = Vec::new();
+        for window in usage_windows(all) {
+            let key = window.key();
+            let period = window.period();
+            // A new reset period starts from a clean slate.
+            self.warned.retain(|(k, p, _)| k != &key || *p == period);
+
+            let crossed: Vec<f64> = WARN_THRESHOLDS
+                .into_iter()
+                .filter(|t| window.used_percent >= *t)
+                .collect();
+            let Some(&highest) = crossed.last() else {
+                continue;
+            };
+            let mut fresh = false;
+            for t in crossed {
+                fresh |= self.warned.insert((key.clone(), period, t as u32));
+            }
+            if fresh {
+                out.push(UsageWarning {
+                    window,
+                    threshold: highest,
+                });
+            }
+        }
+        out
+    }
+}
+
+/// Poll provider usage every `interval` and warn `target` until shutdown.
+///
+/// Returns immediately when no provider credentials are present.
+pub async fn run_usage_monitor(
+    usage: Arc<UsageService>,
+    messenger: Arc<dyn MessagingPort>,
+    target: ChatTarget,
+    theme: &'static StatusTheme,
+    interval: Duration,
+    shutdown: Shutdown,
+) {
+    if !usage.has_credentials().await {
+        log_info!("[USAGE] No provider credentials found; usage monitor disabled");
+        return;
+    }
+    log_info!(
+        "[USAGE] Monitoring provider usage every {}s",
+        interval.as_secs()
+    );
+
+    let mut warner = UsageWarner::new();
+    loop {
+        // `None` keeps the service's default TTL, so /stats and the monitor share the cache.
+        let all = usage.fetch_all(None).await;
+        for warning in warner.check(&all) {
+            if let Err(e) = messenger
+                .send_html(target, &theme.render(&warning.to_html(Utc::now())))
+                .await
+            {
+                log_line!("[USAGE] Failed to send usage warning: {e}");
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.triggered() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::{ClaudeUsage, ClaudeUsageWindow, CodexUsage, CodexWindow};
+
+    fn snapshot(five_hour: f64, resets_at: &str) -> AllUsage {
+        AllUsage {
+            claude: Some(ClaudeUsage {
+                five_hour: Some(ClaudeUsageWindow {
+                    utilization: five_hour,
+                    resets_at: Some(resets_at.to_string()),
+                }),
+                seven_day: Some(ClaudeUsageWindow {
+                    utilization: 10.0,
+                    resets_at: None,
+                }),
+                seven_day_sonnet: None,
+            }),
+            codex: None,
+            gemini: None,
+            status: Default::default(),
+            fetched_at_ms: 0,
+        }
+    }
+
+    #[test]
+    fn windows_are_flattened_across_providers() {
+        let mut all = snapshot(50.0, "2026-01-01T05:00:00Z");
+        all.codex = Some(CodexUsage {
+            model: "m".to_string(),
+            plan_type: "plus".to_string(),
+            primary: Some(CodexWindow {
+                used_percent: 81.0,
+                reset_at: 1_767_243_600,
+            }),
+            secondary: None,
+        });
+        let windows = usage_windows(&all);
+        let names: Vec<_> = windows.iter().map(|w| w.key()).collect();
+        assert_eq!(names, ["Claude/5h", "Claude/7d", "Codex/5h"]);
+        assert_eq!(
+            windows[2].resets_at,
+            DateTime::<Utc>::from_timestamp(1_767_243_600, 0)
+        );
+    }
+
+    #[test]
+    fn warns_once_per_threshold_and_reset_period() {
+        let mut warner = UsageWarner::new();
+        assert!(warner
+            .check(&snapshot(79.0, "2026-01-01T05:00:00Z"))
+            .is_empty());
+
+        let w = warner.check(&snapshot(82.0, "2026-01-01T05:00:00Z"));
+        assert_eq!(w.len(), 1);
+        assert_eq!(w[0].threshold, 80.0);
+        assert!(warner
+            .check(&snapshot(85.0, "2026-01-01T05:00:00.4Z"))
+            .is_empty());
+
+        let w = warner.check(&snapshot(96.0, "2026-01-01T05:00:00Z"));
+        assert_eq!(w[0].threshold, 95.0);
+        assert!(warner
+            .check(&snapshot(99.0, "2026-01-01T05:00:00Z"))
+            .is_empty());
+
+        // Next window: warn again.
+        let w = warner.check(&snapshot(97.0, "2026-01-01T10:00:00Z"));
+        assert_eq!(w.len(), 1);
+        assert_eq!(w[0].threshold, 95.0);
+        assert!(warner
+            .check(&snapshot(98.0, "2026-01-01T10:00:00Z"))
+            .is_empty());
+    }
+
+    #[test]
+    fn warning_mentions_reset_time() {
+        let warning = UsageWarning {
+            window: usage_windows(&snapshot(96.0, "2026-01-01T05:00:00Z"))
+                .into_iter()
+                .next()
+                .unwrap(),
+            threshold: 95.0,
+        };
+        let now = DateTime::parse_from_rfc3339("2026-01-01T02:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let html = warning.to_html(now);
+        assert!(
+            html.starts_with("🚨 <b>Claude 5h usage at 96%</b>"),
+            "{html}"
+        );
+        assert!(html.contains("(in 2h 30m)"), "{html}");
+    }
+}
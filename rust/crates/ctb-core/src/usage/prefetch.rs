@@ -0,0 +1,222 @@
+//! Background warm-up of the provider usage caches.
+//!
+//! Opt-in with `USAGE_PREFETCH`, since the lookups may raise macOS keychain prompts for
+//! operators who never use `/stats`. With `startup`, one [`UsageService::fetch_all`] a few seconds
+//! after startup fills the caches, so the first `/stats` doesn't pay for cold token lookups; with
+//! `repeat` the caches are warmed again each time they expire. Credentials are looked up
+//! once; when no provider has any, the task stops or, when repeating, retries with an
+//! exponential backoff instead of asking the keychain every interval.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{UsageService, DEFAULT_CACHE_TTL};
+use crate::shutdown::Shutdown;
+
+/// Whether and how often the usage caches are warmed (`USAGE_PREFETCH`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UsagePrefetch {
+    #[default]
+    Off,
+    /// Once, a few seconds after startup.
+    Startup,
+    /// After startup and again whenever the caches expire.
+    Repeat,
+}
+
+impl UsagePrefetch {
+    /// `off`, `startup` or `repeat`; `true`/`false` are read as `repeat`/`off`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" | "false" | "0" | "no" => Some(Self::Off),
+            "startup" => Some(Self::Startup),
+            "repeat" | "true" | "1" | "yes" => Some(Self::Repeat),
+            _ => None,
+        }
+    }
+}
+
+/// Delay before the first warm-up, so it never competes with bot startup.
+pub const STARTUP_DELAY: Duration = Duration::from_secs(5);
+/// Longest wait between credential lookups while none are found.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// When the next warm-up is due, driven by an explicit clock.
+#[derive(Clone, Debug)]
+pub struct PrefetchSchedule {
+    interval: Duration,
+    repeat: bool,
+    /// Consecutive runs that found no credentials.
+    misses: u32,
+    /// Credentials were found on the last run.
+    found: bool,
+    next_at: Option<Instant>,
+}
+
+impl PrefetchSchedule {
+    /// First run at `now + STARTUP_DELAY`; with `repeat`, again every `interval`.
+    pub fn new(now: Instant, interval: Duration, repeat: bool) -> Self {
+        Self {
+            interval,
+            repeat,
+            misses: 0,
+            found: false,
+            next_at: Some(now + STARTUP_DELAY),
+        }
+    }
+
+    /// When the next run is due; `None` once the task is done.
+    pub fn next_at(&self) -> Option<Instant> {
+        self.next_at
+    }
+
+    /// Whether the next run must look credentials up first (the first run, and after a miss).
+    pub fn needs_lookup(&self) -> bool {
+        !self.found
+    }
+
+    /// Record a run at `now` and whether any provider had credentials.
+    pub fn record(&mut self, now: Instant, credentials: bool) {
+        self.found = credentials;
+        if credentials {
+            self.misses = 0;
+            self.next_at = self.repeat.then(|| now + self.interval);
+        } else {
+            self.misses = self.misses.saturating_add(1);
+            self.next_at = self.repeat.then(|| now + self.backoff());
+        }
+    }
+
+    /// Wait after the latest miss: the interval doubled per consecutive miss, capped at
+    /// [`MAX_BACKOFF`].
+    pub fn backoff(&self) -> Duration {
+        let factor = 1u32 << self.misses.min(16);
+        self.interval.saturating_mul(factor).min(MAX_BACKOFF)
+    }
+}
+
+/// Warm the usage caches after startup and, with [`UsagePrefetch::Repeat`], whenever they expire,
+/// until shutdown; does nothing when `mode` is [`UsagePrefetch::Off`].
+pub async fn run_usage_prefetch(usage: Arc<UsageService>, mode: UsagePrefetch, shutdown: Shutdown) {
+    if mode == UsagePrefetch::Off {
+        return;
+    }
+    let repeat = mode == UsagePrefetch::Repeat;
+    let mut schedule = PrefetchSchedule::new(Instant::now(), DEFAULT_CACHE_TTL, repeat);
+    while let Some(at) = schedule.next_at() {
+        tokio::select! {
+            _ = tokio::time::sleep_until(at.into()) => {}
+            _ = shutdown.triggered() => return,
+        }
+        let mut credentials = !schedule.needs_lookup() || usage.has_credentials().await;
+        if credentials {
+            // The fetch repeats the token lookups, so it also notices credentials going away.
+            credentials = usage.fetch_all(None).await.status.any_configured();
+        }
+        schedule.record(Instant::now(), credentials);
+        if !credentials {
+            match schedule.next_at() {
+                Some(_) => log_info!(
+                    "[USAGE] No provider credentials; next usage warm-up in {}s",
+                    schedule.backoff().as_secs()
+                ),
+                None => log_info!("[USAGE] No provider credentials; usage warm-up disabled"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    /// Run the schedule on a fake clock for `span`, asking `lookup` for credentials whenever a
+    /// lookup is due; returns the times (since start) of every lookup and every fetch.
+    fn simulate(
+        repeat: bool,
+        span: Duration,
+        mut lookup: impl FnMut(Duration) -> bool,
+    ) -> (Vec<Duration>, Vec<Duration>) {
+        let start = Instant::now();
+        let mut schedule = PrefetchSchedule::new(start, MINUTE, repeat);
+        let (mut lookups, mut fetches) = (Vec::new(), Vec::new());
+        while let Some(now) = schedule.next_at().filter(|at| *at <= start + span) {
+            let t = now - start;
+            let mut credentials = true;
+            if schedule.needs_lookup() {
+                lookups.push(t);
+                credentials = lookup(t);
+            }
+            if credentials {
+                fetches.push(t);
+                credentials = lookup(t);
+            }
+            schedule.record(now, credentials);
+        }
+        (lookups, fetches)
+    }
+
+    #[test]
+    fn mode_parses_names_and_booleans() {
+        assert_eq!(
+            UsagePrefetch::parse("Startup"),
+            Some(UsagePrefetch::Startup)
+        );
+        assert_eq!(UsagePrefetch::parse("true"), Some(UsagePrefetch::Repeat));
+        assert_eq!(UsagePrefetch::parse("off"), Some(UsagePrefetch::Off));
+        assert_eq!(UsagePrefetch::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn warms_once_after_startup_without_repeat() {
+        let (lookups, fetches) = simulate(false, 24 * 60 * MINUTE, |_| true);
+        assert_eq!(lookups, [STARTUP_DELAY]);
+        assert_eq!(fetches, [STARTUP_DELAY]);
+
+        let (lookups, fetches) = simulate(false, 24 * 60 * MINUTE, |_| false);
+        assert_eq!(lookups, [STARTUP_DELAY]);
+        assert!(fetches.is_empty());
+    }
+
+    #[test]
+    fn repeats_every_interval_while_credentials_exist() {
+        let (lookups, fetches) = simulate(true, 10 * MINUTE, |_| true);
+        assert_eq!(lookups, [STARTUP_DELAY]);
+        assert_eq!(fetches.len(), 10);
+        assert!(fetches.windows(2).all(|w| w[1] - w[0] == MINUTE));
+    }
+
+    #[test]
+    fn backs_off_exponentially_without_credentials() {
+        let (lookups, fetches) = simulate(true, 24 * 60 * MINUTE, |_| false);
+        assert!(fetches.is_empty());
+        let gaps: Vec<_> = lookups.windows(2).map(|w| w[1] - w[0]).collect();
+        assert_eq!(gaps[..6], [2, 4, 8, 16, 32, 60].map(|m| m * MINUTE));
+        assert!(gaps[6..].iter().all(|g| *g == MAX_BACKOFF));
+        // Hourly at worst instead of once a minute.
+        assert!(lookups.len() < 30, "{}", lookups.len());
+    }
+
+    #[test]
+    fn recovers_when_credentials_appear_and_backs_off_when_they_go() {
+        let login = 30 * MINUTE;
+        let logout = 3 * 60 * MINUTE;
+        let (lookups, fetches) = simulate(true, 5 * 60 * MINUTE, |t| t >= login && t < logout);
+
+        // Misses at 5s, +2m, +4m, +8m; the lookup 16m later, after login, finds credentials.
+        let first_fetch = fetches[0];
+        assert_eq!(first_fetch, STARTUP_DELAY + 30 * MINUTE);
+        assert_eq!(lookups.iter().filter(|t| **t < first_fetch).count(), 4);
+        // Then every minute until logout, without further lookups.
+        let before_logout = fetches.iter().filter(|t| **t < logout).count();
+        assert!(before_logout > 100, "{before_logout}");
+        assert!(fetches.windows(2).all(|w| w[1] - w[0] == MINUTE));
+        // Logout is noticed by a fetch; lookups back off again starting from 2m.
+        let after: Vec<_> = lookups.iter().filter(|t| **t >= logout).collect();
+        assert_eq!(*after[1] - *after[0], 4 * MINUTE);
+    }
+}
@@ -0,0 +1,228 @@
+//! Per-model token pricing for `/stats` cost estimates.
+//!
+//! Built-in list prices are keyed by model family (`opus`, `sonnet`, `haiku`) plus a `default`
+//! entry used for unknown models and the CLI default. `PRICING_JSON` (inline JSON or a path to
+//! a JSON file) can override any entry, or add one for an exact model id, without a rebuild:
+//!
+//! ```json
+//! { "opus": { "input": 15, "output": 75, "cache_read": 1.5, "cache_write": 18.75 } }
+//! ```
+//!
+//! Lookup precedence: exact model id override, family override, built-in family, `default`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::Error, model::types::TokenUsage, Result};
+
+/// USD per million tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+    #[serde(default)]
+    pub cache_read: f64,
+    #[serde(default)]
+    pub cache_write: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, usage: &TokenUsage) -> CostBreakdown {
+        let per_token = |n: u64, rate: f64| n as f64 * rate / 1_000_000.0;
+        CostBreakdown {
+            input: per_token(usage.input_tokens, self.input),
+            output: per_token(usage.output_tokens, self.output),
+            cache_read: per_token(usage.cache_read_input_tokens, self.cache_read),
+            cache_write: per_token(usage.cache_creation_input_tokens, self.cache_write),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CostBreakdown {
+    pub input: f64,
+    pub output: f64,
+    pub cache_read: f64,
+    pub cache_write: f64,
+}
+
+impl CostBreakdown {
+    pub fn cache(&self) -> f64 {
+        self.cache_read + self.cache_write
+    }
+
+    pub fn total(&self) -> f64 {
+        self.input + self.output + self.cache()
+    }
+}
+
+pub const DEFAULT_FAMILY: &str = "default";
+
+const SONNET: ModelPricing = ModelPricing {
+    input: 3.0,
+    output: 15.0,
+    cache_read: 0.3,
+    cache_write: 3.75,
+};
+
+fn builtin_entries() -> BTreeMap<String, ModelPricing> {
+    let opus = ModelPricing {
+        input: 5.0,
+        output: 25.0,
+        cache_read: 0.5,
+        cache_write: 6.25,
+    };
+    let haiku = ModelPricing {
+        input: 1.0,
+        output: 5.0,
+        cache_read: 0.1,
+        cache_write: 1.25,
+    };
+    [
+        ("opus", opus),
+        ("sonnet", SONNET),
+        ("haiku", haiku),
+        // The CLI defaults to Sonnet.
+        (DEFAULT_FAMILY, SONNET),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect()
+}
+
+/// Family of a model alias or id (`claude-opus-4-1` -> `opus`); `default` when unknown.
+pub fn model_family(model: Option<&str>) -> &'static str {
+    let Some(model) = model.map(str::to_lowercase) else {
+        return DEFAULT_FAMILY;
+    };
+    ["opus", "sonnet", "haiku"]
+        .into_iter()
+        .find(|f| model.contains(f))
+        .unwrap_or(DEFAULT_FAMILY)
+}
+
+/// Rates resolved for a model, with the table key they came from (for display).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolvedPricing {
+    pub key: String,
+    pub pricing: ModelPricing,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PricingTable {
+    builtin: BTreeMap<String, ModelPricing>,
+    overrides: BTreeMap<String, ModelPricing>,
+}
+
+impl Default for PricingTable {
+    fn default() -> Self {
+        Self {
+            builtin: builtin_entries(),
+            overrides: BTreeMap::new(),
+        }
+    }
+}
+
+impl PricingTable {
+    /// Built-in rates plus optional `PRICING_JSON` overrides.
+    pub fn new(overrides_json: Option<&str>) -> Result<Self> {
+        let mut table = Self::default();
+        if let Some(json) = overrides_json {
+            table.overrides = parse_overrides(json)?;
+        }
+        Ok(table)
+    }
+
+    pub fn resolve(&self, model: Option<&str>) -> ResolvedPricing {
+        let family = model_family(model);
+        let exact = model
+            .map(str::to_lowercase)
+            .and_then(|m| self.overrides.get(&m).map(|p| (m, *p)));
+        let (key, pricing) = exact.unwrap_or_else(|| {
+            let pricing = self
+                .overrides
+                .get(family)
+                .or_else(|| self.builtin.get(family))
+                .copied()
+                .unwrap_or(SONNET);
+            (family.to_string(), pricing)
+        });
+        ResolvedPricing { key, pricing }
+    }
+}
+
+/// Parse `PRICING_JSON` (keys are lowercased so lookups are case-insensitive).
+pub fn parse_overrides(json: &str) -> Result<BTreeMap<String, ModelPricing>> {
+    let parsed: BTreeMap<String, ModelPricing> = serde_json::from_str(json)
+        .map_err(|e| Error::Config(format!("invalid PRICING_JSON: {e}")))?;
+    Ok(parsed
+        .into_iter()
+        .map(|(k, v)| (k.to_lowercase(), v))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn families_are_detected_from_aliases_and_ids() {
+        assert_eq!(model_family(Some("opus")), "opus");
+        assert_eq!(model_family(Some("claude-Sonnet-4-5")), "sonnet");
+        assert_eq!(model_family(Some("claude-3-5-haiku-latest")), "haiku");
+        assert_eq!(model_family(Some("gpt-5")), DEFAULT_FAMILY);
+        assert_eq!(model_family(None), DEFAULT_FAMILY);
+    }
+
+    #[test]
+    fn builtin_rates_are_selected_by_family() {
+        let table = PricingTable::default();
+        let opus = table.resolve(Some("claude-opus-4-5"));
+        assert_eq!(opus.key, "opus");
+        assert_eq!(opus.pricing.output, 25.0);
+        assert_eq!(table.resolve(None).pricing, SONNET);
+
+        let usage = TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cache_read_input_tokens: 1_000_000,
+            cache_creation_input_tokens: 0,
+        };
+        let cost = table.resolve(Some("sonnet")).pricing.cost(&usage);
+        assert!((cost.input - 3.0).abs() < 1e-9);
+        assert!((cost.output - 1.5).abs() < 1e-9);
+        assert!((cost.total() - 4.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn overrides_take_precedence_exact_then_family_then_default() {
+        let json = r#"{
+          "opus": {"input": 15, "output": 75},
+          "Claude-Opus-4-1-Special": {"input": 1, "output": 2},
+          "default": {"input": 9, "output": 9}
+        }"#;
+        let table = PricingTable::new(Some(json)).unwrap();
+
+        let exact = table.resolve(Some("claude-opus-4-1-special"));
+        assert_eq!(exact.key, "claude-opus-4-1-special");
+        assert_eq!(exact.pricing.input, 1.0);
+
+        let family = table.resolve(Some("claude-opus-4-5"));
+        assert_eq!(family.key, "opus");
+        assert_eq!(family.pricing.output, 75.0);
+        assert_eq!(family.pricing.cache_read, 0.0);
+
+        // Built-in families still win over an overridden `default`.
+        assert_eq!(table.resolve(Some("haiku")).pricing.input, 1.0);
+        assert_eq!(table.resolve(Some("mystery")).pricing.input, 9.0);
+    }
+
+    #[test]
+    fn invalid_override_json_is_a_config_error() {
+        assert!(matches!(
+            PricingTable::new(Some("{\"opus\": 3}")),
+            Err(Error::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,344 @@
+//! Persistent per-user / per-chat token usage ledger.
+//!
+//! Each completed turn appends one JSONL record to `cfg.usage_ledger_path`. Nothing is
+//! aggregated on disk: daily and weekly rollups (local time, weeks starting Monday) are
+//! computed on read, which keeps the file append-only and trivially inspectable.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Datelike, Duration, Local, LocalResult, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::{model::types::TokenUsage, usage::pricing::ModelPricing, Result};
+
+/// One ledger line.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LedgerRecord {
+    /// Unix epoch milliseconds when the turn finished.
+    pub ts_ms: u64,
+    pub user_id: i64,
+    pub username: String,
+    pub chat_id: i64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_read_input_tokens: u64,
+    #[serde(default)]
+    pub cache_creation_input_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl LedgerRecord {
+    /// A turn's record, its cost estimated at `pricing`.
+    pub fn new(
+        ts_ms: u64,
+        user_id: i64,
+        username: &str,
+        chat_id: i64,
+        usage: &TokenUsage,
+        pricing: &ModelPricing,
+    ) -> Self {
+        Self {
+            ts_ms,
+            user_id,
+            username: username.to_string(),
+            chat_id,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cache_read_input_tokens: usage.cache_read_input_tokens,
+            cache_creation_input_tokens: usage.cache_creation_input_tokens,
+            cost_usd: pricing.cost(usage).total(),
+        }
+    }
+
+    /// Tokens counted against `daily_token_cap` (cache traffic included).
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens
+            + self.output_tokens
+            + self.cache_read_input_tokens
+            + self.cache_creation_input_tokens
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UsageTotals {
+    pub turns: u64,
+    pub tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, rec: &LedgerRecord) {
+        self.turns += 1;
+        self.tokens += rec.total_tokens();
+        self.cost_usd += rec.cost_usd;
+    }
+}
+
+/// Today's and this week's totals for one user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserUsage {
+    pub user_id: i64,
+    pub username: String,
+    pub today: UsageTotals,
+    pub week: UsageTotals,
+}
+
+/// Epoch-millisecond boundaries of the current local day and week.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Periods {
+    pub day_start_ms: u64,
+    pub week_start_ms: u64,
+}
+
+impl Periods {
+    pub fn at(now: DateTime<Local>) -> Self {
+        let today = now.date_naive();
+        let monday = today - Duration::days(i64::from(today.weekday().num_days_from_monday()));
+        Self {
+            day_start_ms: local_midnight_ms(today),
+            week_start_ms: local_midnight_ms(monday),
+        }
+    }
+
+    pub fn now() -> Self {
+        Self::at(Local::now())
+    }
+}
+
+fn local_midnight_ms(date: chrono::NaiveDate) -> u64 {
+    let naive = date.and_time(NaiveTime::MIN);
+    let dt = match Local.from_local_datetime(&naive) {
+        LocalResult::Single(dt) | LocalResult::Ambiguous(dt, _) => dt,
+        // Midnight skipped by a DST jump: fall back to treating it as UTC.
+        LocalResult::None => return naive.and_utc().timestamp_millis().max(0) as u64,
+    };
+    dt.timestamp_millis().max(0) as u64
+}
+
+/// Per-user rollups, ordered by user id. Only users with activity this week are included.
+pub fn summarize(records: &[LedgerRecord], periods: Periods) -> Vec<UserUsage> {
+    let mut by_user: BTreeMap<i64, UserUsage> = BTreeMap::new();
+    for rec in records.iter().filter(|r| r.ts_ms >= periods.week_start_ms) {
+        let entry = by_user.entry(rec.user_id).or_insert_with(|| UserUsage {
+            user_id: rec.user_id,
+            ..Default::default()
+        });
+        // Latest username wins (users can rename themselves).
+        entry.username = rec.username.clone();
+        entry.week.add(rec);
+        if rec.ts_ms >= periods.day_start_ms {
+            entry.today.add(rec);
+        }
+    }
+    by_user.into_values().collect()
+}
+
+/// Per-user token totals since `day_start_ms`, kept so the daily cap check doesn't re-read
+/// the ledger every turn.
+#[derive(Debug)]
+struct DailyTotals {
+    day_start_ms: u64,
+    by_user: HashMap<i64, u64>,
+}
+
+impl DailyTotals {
+    fn add(&mut self, rec: &LedgerRecord) {
+        if rec.ts_ms >= self.day_start_ms {
+            *self.by_user.entry(rec.user_id).or_default() += rec.total_tokens();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UsageLedger {
+    path: PathBuf,
+    today: Mutex<Option<DailyTotals>>,
+}
+
+impl UsageLedger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            today: Mutex::new(None),
+        }
+    }
+
+    fn today(&self) -> std::sync::MutexGuard<'_, Option<DailyTotals>> {
+        self.today.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, record: &LedgerRecord) -> Result<()> {
+        // Held across the write so a concurrent rebuild can't count this record twice.
+        let mut today = self.today();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        if let Some(totals) = today.as_mut() {
+            totals.add(record);
+        }
+        Ok(())
+    }
+
+    /// All records on disk. A missing file is an empty ledger; malformed lines are skipped.
+    pub fn records(&self) -> Result<Vec<LedgerRecord>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut out = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<LedgerRecord>(&line) {
+                Ok(rec) => out.push(rec),
+                Err(e) => log_line!("[LEDGER] Skipping malformed line: {e}"),
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn summary(&self, periods: Periods) -> Result<Vec<UserUsage>> {
+        Ok(summarize(&self.records()?, periods))
+    }
+
+    /// Tokens `user_id` has used since `periods.day_start_ms`. The ledger is read once per day;
+    /// later appends update the cached totals.
+    pub fn tokens_today(&self, user_id: i64, periods: Periods) -> Result<u64> {
+        let mut today = self.today();
+        if today
+            .as_ref()
+            .is_none_or(|t| t.day_start_ms != periods.day_start_ms)
+        {
+            let mut totals = DailyTotals {
+                day_start_ms: periods.day_start_ms,
+                by_user: HashMap::new(),
+            };
+            for rec in self.records()? {
+                totals.add(&rec);
+            }
+            *today = Some(totals);
+        }
+        Ok(today
+            .as_ref()
+            .and_then(|t| t.by_user.get(&user_id).copied())
+            .unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rec(ts_ms: u64, user_id: i64, username: &str, tokens: u64) -> LedgerRecord {
+        LedgerRecord::new(
+            ts_ms,
+            user_id,
+            username,
+            -100,
+            &TokenUsage {
+                input_tokens: tokens,
+                output_tokens: 0,
+                cache_read_input_tokens: 0,
+                cache_creation_input_tokens: 0,
+            },
+            &crate::usage::pricing::PricingTable::default()
+                .resolve(None)
+                .pricing,
+        )
+    }
+
+    #[test]
+    fn periods_start_at_local_midnight_and_monday() {
+        // Thursday afternoon.
+        let now = Local.with_ymd_and_hms(2025, 1, 16, 15, 30, 0).unwrap();
+        let p = Periods::at(now);
+        let day = Local.timestamp_millis_opt(p.day_start_ms as i64).unwrap();
+        let week = Local.timestamp_millis_opt(p.week_start_ms as i64).unwrap();
+        assert_eq!(
+            day.naive_local(),
+            chrono::NaiveDate::from_ymd_opt(2025, 1, 16)
+                .unwrap()
+                .and_time(NaiveTime::MIN)
+        );
+        assert_eq!(
+            week.naive_local(),
+            chrono::NaiveDate::from_ymd_opt(2025, 1, 13)
+                .unwrap()
+                .and_time(NaiveTime::MIN)
+        );
+    }
+
+    #[test]
+    fn summarize_rolls_up_today_and_week_per_user() {
+        let periods = Periods {
+            day_start_ms: 1_000,
+            week_start_ms: 100,
+        };
+        let records = vec![
+            rec(50, 1, "old", 999), // before this week
+            rec(200, 1, "alice", 10),
+            rec(1_500, 1, "alice2", 5),
+            rec(1_600, 2, "bob", 7),
+        ];
+        let summary = summarize(&records, periods);
+        assert_eq!(summary.len(), 2);
+
+        assert_eq!(summary[0].user_id, 1);
+        assert_eq!(summary[0].username, "alice2");
+        assert_eq!(summary[0].week.tokens, 15);
+        assert_eq!(summary[0].week.turns, 2);
+        assert_eq!(summary[0].today.tokens, 5);
+        assert_eq!(summary[0].today.turns, 1);
+
+        assert_eq!(summary[1].user_id, 2);
+        assert_eq!(summary[1].today.tokens, 7);
+    }
+
+    #[test]
+    fn ledger_appends_and_reads_back() {
+        let path =
+            std::env::temp_dir().join(format!("ctb-ledger-test-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ledger = UsageLedger::new(&path);
+        assert!(ledger.records().unwrap().is_empty());
+
+        ledger.append(&rec(2_000, 1, "alice", 10)).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not json\n")
+            .unwrap();
+        ledger.append(&rec(3_000, 1, "alice", 20)).unwrap();
+
+        let periods = Periods {
+            day_start_ms: 2_500,
+            week_start_ms: 0,
+        };
+        assert_eq!(ledger.records().unwrap().len(), 2);
+        assert_eq!(ledger.tokens_today(1, periods).unwrap(), 20);
+        assert_eq!(ledger.tokens_today(2, periods).unwrap(), 0);
+
+        // Cached from here on: appends count, the file isn't re-read.
+        ledger.append(&rec(4_000, 1, "alice", 5)).unwrap();
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(ledger.tokens_today(1, periods).unwrap(), 25);
+        let _ = std::fs::remove_file(&path);
+    }
+}
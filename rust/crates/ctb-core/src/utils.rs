@@ -11,9 +11,9 @@ use std::{
 };
 
 use chrono::{Local, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{errors::Error, Result};
+use crate::{errors::Error, model::types::TokenUsage, Result};
 
 // ============== Timestamp Helpers ==============
 
@@ -84,7 +84,68 @@ pub fn start_interval_loop(
 
 const AUDIT_MAX_TEXT: usize = 500;
 
-#[derive(Clone, Debug, Serialize)]
+/// Characters of content kept in `delivery` events.
+pub const DELIVERY_PREVIEW_CHARS: usize = 120;
+
+/// First 16 hex digits of the SHA-256 of `content`.
+fn content_hash(content: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Fields rendered by `AuditEvent::turn_summary()` instead of one line each in text mode.
+const TURN_FIELDS: [&str; 4] = ["session_id", "duration_ms", "usage", "outcome"];
+
+/// How a turn ended (audit `outcome` field).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Ok,
+    Error,
+    Cancelled,
+    Blocked,
+}
+
+impl AuditOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditOutcome::Ok => "ok",
+            AuditOutcome::Error => "error",
+            AuditOutcome::Cancelled => "cancelled",
+            AuditOutcome::Blocked => "blocked",
+        }
+    }
+}
+
+/// Token usage as recorded in the audit log.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditUsage {
+    pub input: u64,
+    pub output: u64,
+    pub cache_read: u64,
+    pub cache_create: u64,
+}
+
+impl From<&TokenUsage> for AuditUsage {
+    fn from(u: &TokenUsage) -> Self {
+        Self {
+            input: u.input_tokens,
+            output: u.output_tokens,
+            cache_read: u.cache_read_input_tokens,
+            cache_create: u.cache_creation_input_tokens,
+        }
+    }
+}
+
+/// One audit log record.
+///
+/// Every field other than `timestamp`/`event` is optional so older log lines keep parsing as
+/// the schema grows.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuditEvent {
     pub timestamp: String,
     pub event: String,
@@ -120,6 +181,34 @@ pub struct AuditEvent {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub retry_after: Option<f64>,
+
+    // Per-turn metadata (see `with_turn()`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<AuditUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<AuditOutcome>,
+
+    // Delivery metadata (see `delivery()`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_bytes: Option<usize>,
+
+    /// `claude --version` output, on `session_start` events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cli_version: Option<String>,
+
+    /// Chat ids a `/broadcast` went to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipients: Option<Vec<i64>>,
 }
 
 impl AuditEvent {
@@ -146,6 +235,16 @@ impl AuditEvent {
             error: None,
             context: None,
             retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: None,
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: None,
         }
     }
 
@@ -166,6 +265,16 @@ impl AuditEvent {
             error: None,
             context: None,
             retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: None,
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: None,
         }
     }
 
@@ -193,6 +302,16 @@ impl AuditEvent {
             error: None,
             context: None,
             retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: None,
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: None,
         }
     }
 
@@ -213,6 +332,16 @@ impl AuditEvent {
             error: Some(error.to_string()),
             context: context.map(|s| s.to_string()),
             retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: None,
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: None,
         }
     }
 
@@ -233,8 +362,244 @@ impl AuditEvent {
             error: None,
             context: None,
             retry_after: Some(retry_after),
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: None,
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: None,
+        }
+    }
+
+    /// A refused button tap: the user is not allowed, or the callback data failed verification.
+    pub fn callback_rejected(user_id: i64, username: &str, data: &str, reason: &str) -> Self {
+        Self {
+            timestamp: iso_timestamp_utc(),
+            event: "callback_rejected".to_string(),
+            user_id: Some(user_id),
+            username: Some(username.to_string()),
+            message_type: None,
+            content: Some(data.to_string()),
+            response: None,
+            authorized: None,
+            tool_name: None,
+            tool_input: None,
+            blocked: Some(true),
+            reason: Some(reason.to_string()),
+            error: None,
+            context: None,
+            retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: Some(AuditOutcome::Blocked),
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: None,
+        }
+    }
+
+    /// A tool call refused by a safety check: `kind` is what tripped (`command`, `path`, `role`
+    /// or `domain`) and `detail` the offending command, path or host. `user` is `None` for
+    /// bot-initiated runs (cron).
+    pub fn security_block(
+        user: Option<(i64, &str)>,
+        kind: &str,
+        tool_name: &str,
+        detail: &str,
+    ) -> Self {
+        Self {
+            timestamp: iso_timestamp_utc(),
+            event: "security_block".to_string(),
+            user_id: user.map(|(id, _)| id),
+            username: user.map(|(_, name)| name.to_string()),
+            message_type: None,
+            content: Some(detail.to_string()),
+            response: None,
+            authorized: None,
+            tool_name: Some(tool_name.to_string()),
+            tool_input: None,
+            blocked: Some(true),
+            reason: Some(kind.to_string()),
+            error: None,
+            context: None,
+            retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: Some(AuditOutcome::Blocked),
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: None,
+        }
+    }
+
+    /// The first turn of a new model session, stamped with the CLI version that served it.
+    pub fn session_start(
+        user_id: i64,
+        username: &str,
+        session_id: &str,
+        cli_version: Option<&str>,
+    ) -> Self {
+        Self {
+            timestamp: iso_timestamp_utc(),
+            event: "session_start".to_string(),
+            user_id: Some(user_id),
+            username: Some(username.to_string()),
+            message_type: None,
+            content: None,
+            response: None,
+            authorized: None,
+            tool_name: None,
+            tool_input: None,
+            blocked: None,
+            reason: None,
+            error: None,
+            context: None,
+            retry_after: None,
+            session_id: Some(session_id.to_string()),
+            duration_ms: None,
+            usage: None,
+            outcome: None,
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: cli_version.map(|s| s.to_string()),
+            recipients: None,
+        }
+    }
+
+    /// A send, edit or delete as handed to the messenger (`AUDIT_DELIVERY`). The content is kept
+    /// only as a hash, its byte length and a [`DELIVERY_PREVIEW_CHARS`] preview.
+    pub fn delivery(
+        op: &str,
+        chat_id: i64,
+        message_id: Option<i32>,
+        content: Option<&str>,
+        error: Option<&str>,
+    ) -> Self {
+        let outcome = if error.is_some() {
+            AuditOutcome::Error
+        } else {
+            AuditOutcome::Ok
+        };
+        Self {
+            timestamp: iso_timestamp_utc(),
+            event: "delivery".to_string(),
+            user_id: None,
+            username: None,
+            message_type: Some(op.to_string()),
+            content: content.map(|c| c.chars().take(DELIVERY_PREVIEW_CHARS).collect()),
+            response: None,
+            authorized: None,
+            tool_name: None,
+            tool_input: None,
+            blocked: None,
+            reason: None,
+            error: error.map(|e| e.to_string()),
+            context: None,
+            retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: Some(outcome),
+            chat_id: Some(chat_id),
+            message_id,
+            content_hash: content.map(content_hash),
+            content_bytes: content.map(str::len),
+            cli_version: None,
+            recipients: None,
+        }
+    }
+
+    /// An owner `/broadcast`: the text, who it went to and the delivery summary.
+    pub fn broadcast(
+        user_id: i64,
+        username: &str,
+        text: &str,
+        recipients: &[i64],
+        summary: &str,
+    ) -> Self {
+        Self {
+            timestamp: iso_timestamp_utc(),
+            event: "broadcast".to_string(),
+            user_id: Some(user_id),
+            username: Some(username.to_string()),
+            message_type: None,
+            content: Some(text.to_string()),
+            response: Some(summary.to_string()),
+            authorized: None,
+            tool_name: None,
+            tool_input: None,
+            blocked: None,
+            reason: None,
+            error: None,
+            context: None,
+            retry_after: None,
+            session_id: None,
+            duration_ms: None,
+            usage: None,
+            outcome: None,
+            chat_id: None,
+            message_id: None,
+            content_hash: None,
+            content_bytes: None,
+            cli_version: None,
+            recipients: Some(recipients.to_vec()),
         }
     }
+
+    /// Attach per-turn metadata: session, latency, token usage and outcome.
+    pub fn with_turn(
+        mut self,
+        session_id: Option<&str>,
+        duration: Duration,
+        usage: Option<&TokenUsage>,
+        outcome: AuditOutcome,
+    ) -> Self {
+        self.session_id = session_id.map(|s| s.to_string());
+        self.duration_ms = Some(duration.as_millis() as u64);
+        self.usage = usage.map(AuditUsage::from);
+        self.outcome = Some(outcome);
+        self
+    }
+
+    /// Parse one line of a JSON-mode audit log (any schema version).
+    pub fn parse_json_line(line: &str) -> Option<Self> {
+        serde_json::from_str(line.trim()).ok()
+    }
+
+    /// Compact one-line summary of the per-turn fields (text mode).
+    fn turn_summary(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(o) = self.outcome {
+            parts.push(o.as_str().to_string());
+        }
+        if let Some(ms) = self.duration_ms {
+            parts.push(format!("{ms}ms"));
+        }
+        if let Some(u) = &self.usage {
+            parts.push(format!(
+                "in={} out={} cache_read={} cache_create={}",
+                u.input, u.output, u.cache_read, u.cache_create
+            ));
+        }
+        if let Some(id) = &self.session_id {
+            parts.push(format!("session={id}"));
+        }
+        (!parts.is_empty()).then(|| parts.join(" · "))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -290,11 +655,18 @@ impl AuditLogger {
             ));
         };
         for (k, v) in obj {
+            if TURN_FIELDS.contains(&k.as_str()) {
+                continue;
+            }
             out.push('\n');
             out.push_str(k);
             out.push_str(": ");
             out.push_str(&json_value_to_display(v));
         }
+        if let Some(summary) = event.turn_summary() {
+            out.push_str("\nturn: ");
+            out.push_str(&summary);
+        }
         out.push('\n');
 
         file.write_all(out.as_bytes())?;
@@ -387,4 +759,61 @@ mod tests {
         let written = std::fs::read_to_string(log.path()).unwrap();
         assert!(written.contains("..."));
     }
+
+    #[test]
+    fn audit_old_json_lines_still_parse() {
+        let old = r#"{"timestamp":"2026-01-01T00:00:00Z","event":"message","user_id":1,"username":"u","message_type":"TEXT","content":"hi","response":"hello"}"#;
+        let ev = AuditEvent::parse_json_line(old).unwrap();
+        assert_eq!(ev.event, "message");
+        assert_eq!(ev.response.as_deref(), Some("hello"));
+        assert!(ev.session_id.is_none());
+        assert!(ev.usage.is_none());
+        assert!(ev.outcome.is_none());
+    }
+
+    #[test]
+    fn session_start_events_carry_the_cli_version() {
+        let ev = AuditEvent::session_start(1, "u", "sess-1", Some("2.0.1 (Claude Code)"));
+        let line = serde_json::to_string(&ev).unwrap();
+        let parsed = AuditEvent::parse_json_line(&line).unwrap();
+        assert_eq!(parsed.event, "session_start");
+        assert_eq!(parsed.session_id.as_deref(), Some("sess-1"));
+        assert_eq!(parsed.cli_version.as_deref(), Some("2.0.1 (Claude Code)"));
+
+        let message = serde_json::to_string(&AuditEvent::auth(1, "u", true)).unwrap();
+        assert!(!message.contains("cli_version"));
+    }
+
+    #[test]
+    fn audit_turn_fields_in_json_and_text_modes() {
+        let usage = TokenUsage {
+            input_tokens: 10,
+            output_tokens: 5,
+            cache_read_input_tokens: 2,
+            cache_creation_input_tokens: 0,
+        };
+        let ev = AuditEvent::message(1, "u", "TEXT", "hi", Some("hello")).with_turn(
+            Some("sess-1"),
+            Duration::from_millis(1532),
+            Some(&usage),
+            AuditOutcome::Ok,
+        );
+
+        let json_log = AuditLogger::new(tmp_file("ctb-audit-turn-json"), true);
+        json_log.write(ev.clone()).unwrap();
+        let line = std::fs::read_to_string(json_log.path()).unwrap();
+        let parsed = AuditEvent::parse_json_line(&line).unwrap();
+        assert_eq!(parsed.session_id.as_deref(), Some("sess-1"));
+        assert_eq!(parsed.duration_ms, Some(1532));
+        assert_eq!(parsed.usage, Some(AuditUsage::from(&usage)));
+        assert_eq!(parsed.outcome, Some(AuditOutcome::Ok));
+
+        let text_log = AuditLogger::new(tmp_file("ctb-audit-turn-text"), false);
+        text_log.write(ev).unwrap();
+        let text = std::fs::read_to_string(text_log.path()).unwrap();
+        assert!(text.contains(
+            "turn: ok · 1532ms · in=10 out=5 cache_read=2 cache_create=0 · session=sess-1"
+        ));
+        assert!(!text.contains("duration_ms:"));
+    }
 }
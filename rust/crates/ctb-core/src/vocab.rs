@@ -0,0 +1,209 @@
+//! Per-chat transcription vocabulary (`/vocab`).
+//!
+//! Jargon and names the transcription model keeps mangling are listed per chat and appended to
+//! the transcription prompt. The lists live in `<state_dir>/vocabulary.json`.
+
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Mutex};
+
+use crate::{config::Config, Error, Result};
+
+/// Terms kept per chat.
+pub const VOCAB_MAX_TERMS: usize = 50;
+/// Longest accepted term, in characters.
+pub const VOCAB_TERM_MAX_CHARS: usize = 48;
+
+/// Vocabulary lists keyed by chat id, persisted to `file` on every change.
+#[derive(Debug)]
+pub struct Vocabulary {
+    file: PathBuf,
+    chats: Mutex<BTreeMap<i64, Vec<String>>>,
+}
+
+impl Vocabulary {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self::load(cfg.state_dir.join("vocabulary.json"))
+    }
+
+    /// Load `file`; a missing or unreadable file starts empty.
+    pub fn load(file: PathBuf) -> Self {
+        let chats = match fs::read_to_string(&file) {
+            Ok(txt) => serde_json::from_str(&txt).unwrap_or_else(|e| {
+                log_line!("[VOCAB] Ignoring unreadable {}: {e}", file.display());
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            file,
+            chats: Mutex::new(chats),
+        }
+    }
+
+    /// Add the comma-separated `raw` terms; returns the ones that were new. All terms are
+    /// validated before any is added.
+    pub fn add(&self, chat_id: i64, raw: &str) -> Result<Vec<String>> {
+        let terms = parse_terms(raw)?;
+        let mut chats = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        let list = chats.entry(chat_id).or_default();
+        let mut added: Vec<String> = Vec::new();
+        for term in terms {
+            if !list
+                .iter()
+                .chain(&added)
+                .any(|t| t.eq_ignore_ascii_case(&term))
+            {
+                added.push(term);
+            }
+        }
+        if list.len() + added.len() > VOCAB_MAX_TERMS {
+            return Err(Error::Config(format!(
+                "the vocabulary holds at most {VOCAB_MAX_TERMS} terms ({} used)",
+                list.len()
+            )));
+        }
+        if added.is_empty() {
+            return Ok(added);
+        }
+        list.extend(added.iter().cloned());
+        self.save(&chats)?;
+        Ok(added)
+    }
+
+    /// Remove `term` (case-insensitive); `false` if the chat did not have it.
+    pub fn remove(&self, chat_id: i64, term: &str) -> Result<bool> {
+        let term = term.trim();
+        let mut chats = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(list) = chats.get_mut(&chat_id) else {
+            return Ok(false);
+        };
+        let before = list.len();
+        list.retain(|t| !t.eq_ignore_ascii_case(term));
+        let removed = list.len() != before;
+        if list.is_empty() {
+            chats.remove(&chat_id);
+        }
+        if removed {
+            self.save(&chats)?;
+        }
+        Ok(removed)
+    }
+
+    /// Terms for `chat_id`, in the order they were added.
+    pub fn list(&self, chat_id: i64) -> Vec<String> {
+        let chats = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        chats.get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    /// Every chat's terms (for `/export_state`).
+    pub fn all(&self) -> BTreeMap<i64, Vec<String>> {
+        self.chats.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the terms of each chat in `chats`; other chats keep theirs.
+    pub fn restore(&self, chats: BTreeMap<i64, Vec<String>>) -> Result<()> {
+        let mut current = self.chats.lock().unwrap_or_else(|e| e.into_inner());
+        current.extend(chats);
+        current.retain(|_, terms| !terms.is_empty());
+        self.save(&current)
+    }
+
+    fn save(&self, chats: &BTreeMap<i64, Vec<String>>) -> Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.file, serde_json::to_string_pretty(chats)?)?;
+        Ok(())
+    }
+}
+
+fn parse_terms(raw: &str) -> Result<Vec<String>> {
+    let terms: Vec<String> = raw
+        .split(',')
+        .map(|t| t.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|t| !t.is_empty())
+        .collect();
+    if terms.is_empty() {
+        return Err(Error::Config("no terms given".to_string()));
+    }
+    if let Some(long) = terms
+        .iter()
+        .find(|t| t.chars().count() > VOCAB_TERM_MAX_CHARS)
+    {
+        return Err(Error::Config(format!(
+            "\"{long}\" is longer than {VOCAB_TERM_MAX_CHARS} characters"
+        )));
+    }
+    Ok(terms)
+}
+
+/// The prompt sent with a transcription: `base`, then the chat's vocabulary, if any.
+pub fn transcription_prompt(base: &str, terms: &[String]) -> String {
+    if terms.is_empty() {
+        return base.to_string();
+    }
+    format!(
+        "{base}\n\nVocabulary (spell these exactly as written): {}",
+        terms.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> Vocabulary {
+        let file = std::env::temp_dir()
+            .join(format!("ctb-vocab-{name}-{}", std::process::id()))
+            .join("vocabulary.json");
+        let _ = fs::remove_file(&file);
+        Vocabulary::load(file)
+    }
+
+    #[test]
+    fn add_remove_and_persist() {
+        let vocab = store("persist");
+        assert_eq!(
+            vocab
+                .add(1, "ctb, teloxide ,  claude  telegram bot")
+                .unwrap(),
+            ["ctb", "teloxide", "claude telegram bot"]
+        );
+        assert_eq!(vocab.add(1, "CTB, Teloxide, ratatui").unwrap(), ["ratatui"]);
+        assert!(vocab.list(2).is_empty(), "lists are per chat");
+
+        assert!(vocab.remove(1, "TELOXIDE").unwrap());
+        assert!(!vocab.remove(1, "teloxide").unwrap());
+
+        let reloaded = Vocabulary::load(vocab.file.clone());
+        assert_eq!(reloaded.list(1), ["ctb", "claude telegram bot", "ratatui"]);
+        let _ = fs::remove_dir_all(vocab.file.parent().unwrap());
+    }
+
+    #[test]
+    fn caps_terms_and_lengths() {
+        let vocab = store("caps");
+        assert!(vocab.add(1, " , ").is_err());
+        let long = "x".repeat(VOCAB_TERM_MAX_CHARS + 1);
+        let err = vocab.add(1, &format!("ok, {long}")).unwrap_err();
+        assert!(err.to_string().contains("longer than"), "{err}");
+        assert!(
+            vocab.list(1).is_empty(),
+            "nothing added when one term is invalid"
+        );
+
+        let many: Vec<String> = (0..VOCAB_MAX_TERMS).map(|i| format!("t{i}")).collect();
+        vocab.add(1, &many.join(",")).unwrap();
+        assert!(vocab.add(1, "one more").is_err());
+        assert_eq!(vocab.list(1).len(), VOCAB_MAX_TERMS);
+        let _ = fs::remove_dir_all(vocab.file.parent().unwrap());
+    }
+
+    #[test]
+    fn prompt_appends_the_vocabulary() {
+        assert_eq!(transcription_prompt("Base.", &[]), "Base.");
+        assert_eq!(
+            transcription_prompt("Base.", &["ctb".to_string(), "teloxide".to_string()]),
+            "Base.\n\nVocabulary (spell these exactly as written): ctb, teloxide"
+        );
+    }
+}
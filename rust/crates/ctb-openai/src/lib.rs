@@ -1,89 +1,420 @@
 //! OpenAI adapter (voice transcription).
 //!
-//! Uses the OpenAI `audio/transcriptions` endpoint (parity with TS voice handler).
+//! Uses the OpenAI `audio/transcriptions` endpoint (parity with TS voice handler). Model,
+//! endpoint, timeout and language hint come from [`TranscriptionConfig`]; 5xx responses and
+//! timeouts are retried once with a longer deadline.
 
-use std::path::Path;
+use std::{path::Path, time::Duration};
 
-use ctb_core::{errors::Error, Result};
+use ctb_core::{
+    config::{
+        Config, DEFAULT_OPENAI_BASE_URL, DEFAULT_TRANSCRIPTION_MODEL, DEFAULT_TRANSCRIPTION_TIMEOUT,
+    },
+    errors::Error,
+    log_line,
+};
+
+/// The transcription endpoint rejects uploads above 25MB.
+pub const MAX_TRANSCRIPTION_BYTES: u64 = 25 * 1024 * 1024;
+
+/// The retry gets this multiple of the configured timeout.
+const RETRY_TIMEOUT_FACTOR: u32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranscriptionConfig {
+    pub model: String,
+    /// API root, e.g. `https://api.openai.com/v1` (no trailing `/audio/...`).
+    pub base_url: String,
+    pub timeout: Duration,
+    /// ISO-639-1 code forwarded as `language` (improves accuracy and latency).
+    pub language_hint: Option<String>,
+}
+
+impl Default for TranscriptionConfig {
+    fn default() -> Self {
+        Self {
+            model: DEFAULT_TRANSCRIPTION_MODEL.to_string(),
+            base_url: DEFAULT_OPENAI_BASE_URL.to_string(),
+            timeout: DEFAULT_TRANSCRIPTION_TIMEOUT,
+            language_hint: None,
+        }
+    }
+}
+
+impl TranscriptionConfig {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            model: cfg.transcription_model.clone(),
+            base_url: cfg.openai_base_url.clone(),
+            timeout: cfg.transcription_timeout,
+            language_hint: cfg.transcription_language.clone(),
+        }
+    }
+
+    fn endpoint(&self) -> String {
+        format!(
+            "{}/audio/transcriptions",
+            self.base_url.trim_end_matches('/')
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranscriptionError {
+    /// The audio exceeds the API's size or duration limits; retrying will not help.
+    #[error("audio too long or too large for transcription: {0}")]
+    AudioTooLong(String),
+
+    #[error("transcription timed out")]
+    Timeout,
+
+    #[error("openai transcription failed: {}{message}", status.map(|s| format!("{s} ")).unwrap_or_default())]
+    Api {
+        status: Option<u16>,
+        message: String,
+    },
+
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl TranscriptionError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout => true,
+            Self::Api { status, .. } => status.is_some_and(|s| s >= 500),
+            _ => false,
+        }
+    }
+}
+
+impl From<TranscriptionError> for Error {
+    fn from(e: TranscriptionError) -> Self {
+        match e {
+            TranscriptionError::Io(e) => Error::Io(e),
+            other => Error::External(other.to_string()),
+        }
+    }
+}
+
+/// Upload mime type from the file extension (Telegram forwards audio as .oga/.mp3/.m4a too).
+pub fn audio_mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match ext.as_str() {
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "mp3" | "mpga" | "mpeg" => "audio/mpeg",
+        "m4a" | "mp4" => "audio/mp4",
+        "wav" => "audio/wav",
+        "webm" => "audio/webm",
+        "flac" => "audio/flac",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Error bodies that mean the input itself is over the limit.
+fn is_too_long_message(body: &str) -> bool {
+    let lower = body.to_lowercase();
+    ["too long", "too large", "duration", "maximum content size"]
+        .iter()
+        .any(|needle| lower.contains(needle))
+}
 
 #[derive(Clone, Debug)]
 pub struct OpenAiClient {
     pub api_key: String,
+    config: TranscriptionConfig,
     http: reqwest::Client,
 }
 
 impl OpenAiClient {
     pub fn new(api_key: impl Into<String>) -> Self {
-        let http = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
-            .build()
-            .expect("reqwest client build");
+        Self::with_config(api_key, TranscriptionConfig::default())
+    }
+
+    pub fn with_config(api_key: impl Into<String>, config: TranscriptionConfig) -> Self {
         Self {
             api_key: api_key.into(),
-            http,
+            config,
+            http: reqwest::Client::new(),
         }
     }
 
-    pub async fn transcribe_file(&self, path: &Path, prompt: Option<&str>) -> Result<String> {
-        let bytes = tokio::fs::read(path).await.map_err(Error::Io)?;
+    pub fn config(&self) -> &TranscriptionConfig {
+        &self.config
+    }
+
+    pub async fn transcribe_file(
+        &self,
+        path: &Path,
+        prompt: Option<&str>,
+    ) -> std::result::Result<String, TranscriptionError> {
+        let bytes = tokio::fs::read(path).await?;
+        if bytes.len() as u64 > MAX_TRANSCRIPTION_BYTES {
+            return Err(TranscriptionError::AudioTooLong(format!(
+                "{:.1}MB exceeds the {}MB upload limit",
+                bytes.len() as f64 / (1024.0 * 1024.0),
+                MAX_TRANSCRIPTION_BYTES / (1024 * 1024)
+            )));
+        }
 
         let file_name = path
             .file_name()
             .and_then(|s| s.to_str())
             .unwrap_or("audio.ogg")
             .to_string();
+        let mime = audio_mime_type(path);
+
+        match self
+            .attempt(&bytes, &file_name, mime, prompt, self.config.timeout)
+            .await
+        {
+            Err(e) if e.is_retryable() => {
+                log_line!("[OPENAI] Transcription attempt failed ({e}); retrying once");
+                self.attempt(
+                    &bytes,
+                    &file_name,
+                    mime,
+                    prompt,
+                    self.config.timeout * RETRY_TIMEOUT_FACTOR,
+                )
+                .await
+            }
+            other => other,
+        }
+    }
+
+    async fn attempt(
+        &self,
+        bytes: &[u8],
+        file_name: &str,
+        mime: &str,
+        prompt: Option<&str>,
+        timeout: Duration,
+    ) -> std::result::Result<String, TranscriptionError> {
+        let api_err = |message: String| TranscriptionError::Api {
+            status: None,
+            message,
+        };
 
         let mut form = reqwest::multipart::Form::new()
-            .text("model", "gpt-4o-transcribe")
+            .text("model", self.config.model.clone())
             .part(
                 "file",
-                reqwest::multipart::Part::bytes(bytes)
-                    .file_name(file_name)
-                    .mime_str("audio/ogg")
-                    .map_err(|e| Error::External(format!("openai multipart error: {e}")))?,
+                reqwest::multipart::Part::bytes(bytes.to_vec())
+                    .file_name(file_name.to_string())
+                    .mime_str(mime)
+                    .map_err(|e| api_err(format!("multipart error: {e}")))?,
             );
-
-        if let Some(p) = prompt {
-            if !p.trim().is_empty() {
-                form = form.text("prompt", p.to_string());
-            }
+        if let Some(p) = prompt.filter(|p| !p.trim().is_empty()) {
+            form = form.text("prompt", p.to_string());
+        }
+        if let Some(lang) = self
+            .config
+            .language_hint
+            .as_deref()
+            .filter(|l| !l.is_empty())
+        {
+            form = form.text("language", lang.to_string());
         }
 
         let resp = self
             .http
-            .post("https://api.openai.com/v1/audio/transcriptions")
+            .post(self.config.endpoint())
             .bearer_auth(&self.api_key)
+            .timeout(timeout)
             .multipart(form)
             .send()
             .await
-            .map_err(|e| Error::External(format!("openai request error: {e}")))?;
+            .map_err(|e| {
+                if e.is_timeout() {
+                    TranscriptionError::Timeout
+                } else {
+                    api_err(format!("request error: {e}"))
+                }
+            })?;
 
-        if !resp.status().is_success() {
-            let status = resp.status();
+        let status = resp.status();
+        if !status.is_success() {
             let body = resp.text().await.unwrap_or_default();
-            return Err(Error::External(format!(
-                "openai transcription failed: {status} {}",
-                body.chars().take(200).collect::<String>()
-            )));
+            let body: String = body.chars().take(200).collect();
+            if status == reqwest::StatusCode::PAYLOAD_TOO_LARGE
+                || (status.is_client_error() && is_too_long_message(&body))
+            {
+                return Err(TranscriptionError::AudioTooLong(body));
+            }
+            return Err(TranscriptionError::Api {
+                status: Some(status.as_u16()),
+                message: body,
+            });
         }
 
-        let v: serde_json::Value = resp
-            .json()
-            .await
-            .map_err(|e| Error::External(format!("openai json error: {e}")))?;
-
+        let v: serde_json::Value = resp.json().await.map_err(|e| {
+            if e.is_timeout() {
+                TranscriptionError::Timeout
+            } else {
+                api_err(format!("json error: {e}"))
+            }
+        })?;
         let text = v
             .get("text")
             .and_then(|t| t.as_str())
             .unwrap_or("")
             .to_string();
-
         if text.trim().is_empty() {
-            return Err(Error::External(
-                "openai transcription returned empty text".to_string(),
-            ));
+            return Err(api_err("transcription returned empty text".to_string()));
         }
-
         Ok(text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// Minimal HTTP/1.1 server: answers each connection with the next scripted
+    /// `(status, body)` and records the raw requests.
+    fn serve(responses: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let Ok((stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                let mut content_length = 0usize;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = v.trim().parse().unwrap_or(0);
+                    }
+                    head.push_str(&line);
+                }
+                let mut req_body = vec![0u8; content_length];
+                let _ = reader.read_exact(&mut req_body);
+                seen.lock()
+                    .unwrap()
+                    .push(format!("{head}\r\n{}", String::from_utf8_lossy(&req_body)));
+
+                let resp = format!(
+                    "HTTP/1.1 {status} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let mut stream = reader.into_inner();
+                let _ = stream.write_all(resp.as_bytes());
+            }
+        });
+        (format!("http://{addr}/v1"), requests)
+    }
+
+    fn audio_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("ctb-openai-{}-{name}", std::process::id()));
+        std::fs::write(&path, b"fake audio").unwrap();
+        path
+    }
+
+    fn client(base_url: String) -> OpenAiClient {
+        OpenAiClient::with_config(
+            "sk-test",
+            TranscriptionConfig {
+                model: "whisper-1".to_string(),
+                base_url,
+                timeout: Duration::from_secs(5),
+                language_hint: Some("it".to_string()),
+            },
+        )
+    }
+
+    #[test]
+    fn mime_type_follows_extension() {
+        assert_eq!(audio_mime_type(Path::new("a.oga")), "audio/ogg");
+        assert_eq!(audio_mime_type(Path::new("a.MP3")), "audio/mpeg");
+        assert_eq!(audio_mime_type(Path::new("a.m4a")), "audio/mp4");
+        assert_eq!(
+            audio_mime_type(Path::new("noext")),
+            "application/octet-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_configured_form_fields() {
+        let (base, requests) = serve(vec![(200, r#"{"text":"ciao"}"#)]);
+        let path = audio_file("fields.m4a");
+        let text = client(base)
+            .transcribe_file(&path, Some("names: Ada"))
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(text, "ciao");
+
+        let reqs = requests.lock().unwrap();
+        let req = &reqs[0];
+        assert!(req.starts_with("POST /v1/audio/transcriptions "));
+        assert!(req.contains("authorization: Bearer sk-test"));
+        assert!(req.contains("whisper-1"));
+        assert!(req.contains("name=\"language\"\r\n\r\nit"));
+        assert!(req.contains("name=\"prompt\"\r\n\r\nnames: Ada"));
+        assert!(req.contains("Content-Type: audio/mp4"));
+    }
+
+    #[tokio::test]
+    async fn retries_once_on_server_error() {
+        let (base, requests) = serve(vec![
+            (503, r#"{"error":"busy"}"#),
+            (200, r#"{"text":"ok"}"#),
+        ]);
+        let path = audio_file("retry.ogg");
+        let text = client(base).transcribe_file(&path, None).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(text, "ok");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn too_long_audio_is_a_distinct_error_and_not_retried() {
+        let (base, requests) = serve(vec![
+            (
+                400,
+                r#"{"error":{"message":"Audio duration 1500s is longer than the maximum"}}"#,
+            ),
+            (200, r#"{"text":"unused"}"#),
+        ]);
+        let path = audio_file("long.ogg");
+        let err = client(base).transcribe_file(&path, None).await.unwrap_err();
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(err, TranscriptionError::AudioTooLong(_)), "{err}");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (base, requests) = serve(vec![(401, r#"{"error":"bad key"}"#)]);
+        let path = audio_file("auth.ogg");
+        let err = client(base).transcribe_file(&path, None).await.unwrap_err();
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(
+            err,
+            TranscriptionError::Api {
+                status: Some(401),
+                ..
+            }
+        ));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
+};
+
+use teloxide::{prelude::*, types::Audio};
+
+use ctb_core::{formatting::escape_html, security::RateAction};
+use ctb_openai::{OpenAiClient, TranscriptionConfig, TranscriptionError};
+
+use crate::router::AppState;
+
+use super::download::download_to_path;
+use super::prompt::{run_prompt, PromptContext, PromptOptions};
+use super::{
+    chat_target, check_rate_limit, group::prompt_caption, send_text, transcription_prompt,
+    username_of,
+};
+
+static AUDIO_COUNTER: AtomicUsize = AtomicUsize::new(1);
+
+/// Bot API `getFile` refuses anything larger than this.
+const MAX_AUDIO_SIZE: u64 = 20 * 1024 * 1024; // 20MB
+/// Longest input the transcription models accept.
+const MAX_AUDIO_DURATION_SECS: u32 = 25 * 60;
+
+/// Extension for the downloaded file, so the upload gets the right mime type.
+fn audio_extension(audio: &Audio) -> String {
+    let from_name = audio
+        .file_name
+        .as_deref()
+        .and_then(|n| Path::new(n).extension())
+        .and_then(|e| e.to_str())
+        .filter(|e| !e.is_empty() && e.len() <= 5)
+        .map(str::to_lowercase);
+    if let Some(ext) = from_name {
+        return ext;
+    }
+    let ext = match audio.mime_type.as_ref().map(|m| m.subtype().as_str()) {
+        Some("ogg") => "ogg",
+        Some("mp4") | Some("x-m4a") | Some("m4a") => "m4a",
+        Some("wav") | Some("x-wav") => "wav",
+        Some("flac") => "flac",
+        Some("webm") => "webm",
+        _ => "mp3",
+    };
+    ext.to_string()
+}
+
+fn format_duration(secs: u32) -> String {
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+fn build_audio_prompt(
+    file_name: Option<&str>,
+    performer: Option<&str>,
+    title: Option<&str>,
+    duration_secs: u32,
+    transcript: &str,
+    caption: Option<&str>,
+) -> String {
+    let mut header = format!("[Audio file: {}", file_name.unwrap_or("audio"));
+    match (title, performer) {
+        (Some(t), Some(p)) => header.push_str(&format!(" — \"{t}\" by {p}")),
+        (Some(t), None) => header.push_str(&format!(" — \"{t}\"")),
+        (None, Some(p)) => header.push_str(&format!(" — by {p}")),
+        (None, None) => {}
+    }
+    header.push_str(&format!(", {}]", format_duration(duration_secs)));
+
+    let mut out = format!("{header}\n\n[Transcript: {}]", transcript.trim());
+    match caption {
+        Some(c) if !c.trim().is_empty() => out.push_str(&format!("\n\n{c}")),
+        _ => out.push_str("\n\nPlease summarize this audio."),
+    }
+    out
+}
+
+async fn download_audio(bot: &Bot, state: &AppState, audio: &Audio) -> anyhow::Result<PathBuf> {
+    let ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let n = AUDIO_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let path = state
+        .cfg
+        .temp_dir
+        .join(format!("audio_{ts}_{n}.{}", audio_extension(audio)));
+
+    download_to_path(bot, &audio.file, &path, MAX_AUDIO_SIZE).await?;
+    Ok(path)
+}
+
+pub async fn handle_audio(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
+    let Some(user) = msg.from() else {
+        return Ok(());
+    };
+    let Some(audio) = msg.audio() else {
+        return Ok(());
+    };
+
+    let user_id = user.id.0 as i64;
+    let username = username_of(user);
+    let chat_id = msg.chat.id.0;
+    let target = chat_target(&msg);
+    let theme = state.cfg.status_theme;
+
+    let Some(key) = state
+        .cfg
+        .openai_api_key
+        .as_ref()
+        .filter(|_| state.cfg.transcription_available)
+    else {
+        let _ = send_text(
+            &state,
+            target,
+            format!("{}Audio files are transcribed before being sent to Claude, but transcription is not configured. Set OPENAI_API_KEY in .env to enable it.", theme.deco("🎵")),
+        )
+        .await;
+        return Ok(());
+    };
+
+    // Rate limit early.
+    if !check_rate_limit(&state, target, user_id, &username, RateAction::Media).await {
+        return Ok(());
+    }
+
+    if u64::from(audio.file.size) > MAX_AUDIO_SIZE {
+        let _ = send_text(
+            &state,
+            target,
+            format!(
+                "{} Audio file too large. Maximum size is 20MB.",
+                theme.failed
+            ),
+        )
+        .await;
+        return Ok(());
+    }
+    if audio.duration > MAX_AUDIO_DURATION_SECS {
+        let _ = send_text(
+            &state,
+            target,
+            format!(
+                "{} Audio too long ({}). Maximum length is {}.",
+                theme.failed,
+                format_duration(audio.duration),
+                format_duration(MAX_AUDIO_DURATION_SECS)
+            ),
+        )
+        .await;
+        return Ok(());
+    }
+
+    let status = send_text(
+        &state,
+        target,
+        format!("{}Transcribing audio...", theme.deco("🎵")),
+    )
+    .await
+    .ok();
+
+    let audio_path = match download_audio(&bot, &state, audio).await {
+        Ok(p) => p,
+        Err(e) => {
+            let _ = send_text(
+                &state,
+                target,
+                format!(
+                    "{} Failed to download audio: {}",
+                    theme.failed,
+                    e.to_string().chars().take(200).collect::<String>()
+                ),
+            )
+            .await;
+            return Ok(());
+        }
+    };
+
+    let client =
+        OpenAiClient::with_config(key.clone(), TranscriptionConfig::from_config(&state.cfg));
+    let transcript = client
+        .transcribe_file(
+            &audio_path,
+            Some(&transcription_prompt(&state, target.chat_id.0)),
+        )
+        .await;
+    let _ = tokio::fs::remove_file(&audio_path).await;
+    let transcript = match transcript {
+        Ok(t) => t,
+        Err(e) => {
+            let msg = match e {
+                TranscriptionError::AudioTooLong(_) => {
+                    format!("{} Audio file is too long to transcribe.", theme.failed)
+                }
+                e => format!(
+                    "{} Transcription failed: {}",
+                    theme.failed,
+                    e.to_string().chars().take(400).collect::<String>()
+                ),
+            };
+            if let Some(st) = &status {
+                let _ = state.messenger.edit_html(*st, &escape_html(&msg)).await;
+            } else {
+                let _ = send_text(&state, target, msg).await;
+            }
+            return Ok(());
+        }
+    };
+
+    if let Some(st) = &status {
+        let preview = if transcript.len() > 300 {
+            format!("{}...", transcript.chars().take(300).collect::<String>())
+        } else {
+            transcript.clone()
+        };
+        let preview = escape_html(&format!("{}\"{preview}\"", theme.deco("🎵")));
+        let _ = state.messenger.edit_html(*st, &preview).await;
+    }
+
+    let prompt = build_audio_prompt(
+        audio.file_name.as_deref(),
+        audio.performer.as_deref(),
+        audio.title.as_deref(),
+        audio.duration,
+        &transcript,
+        prompt_caption(&msg, &state).as_deref(),
+    );
+    let _ = run_prompt(
+        PromptContext {
+            bot,
+            state,
+            chat_id,
+            thread_id: target.thread_id,
+            user_id,
+            username,
+        },
+        "AUDIO",
+        prompt,
+        PromptOptions {
+            record_last_message: false,
+            rate_action: None,
+            plan_only: false,
+        },
+    )
+    .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audio_prompt_includes_metadata_and_caption() {
+        let prompt = build_audio_prompt(
+            Some("episode.mp3"),
+            Some("Some Podcast"),
+            Some("Ep. 12"),
+            754,
+            " hello there ",
+            Some("What are the key points?"),
+        );
+        assert_eq!(
+            prompt,
+            "[Audio file: episode.mp3 — \"Ep. 12\" by Some Podcast, 12:34]\n\n[Transcript: hello there]\n\nWhat are the key points?"
+        );
+
+        let bare = build_audio_prompt(None, None, None, 5, "hi", None);
+        assert_eq!(
+            bare,
+            "[Audio file: audio, 0:05]\n\n[Transcript: hi]\n\nPlease summarize this audio."
+        );
+    }
+}
@@ -1,37 +1,74 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
-use teloxide::{prelude::*, types::ChatAction};
+use teloxide::prelude::*;
 
 use ctb_core::{
-    domain::{ChatId, UserId},
-    errors::Error,
-    messaging::port::MessagingPort,
-    utils::AuditEvent,
+    ask_user::{archive_request, load_request, locate_request_file, record_answer, AnswerOutcome},
+    domain::{ChatId, ChatTarget, MessageId, MessageRef, UserId},
+    errors::{CancelReason, Error},
+    formatting::escape_html,
+    log_line,
+    messaging::{
+        callback_data::CallbackRejection,
+        port::MessagingPort,
+        types::{truncate_label, OptionsKeyboard},
+    },
+    plan_mode::PLAN_CALLBACK_PREFIX,
+    security::{role_of, Role},
+    security_events::RunUser,
+    utils::{iso_timestamp_utc, AuditEvent},
 };
 
+use crate::handlers::{
+    chat_target, edited,
+    idle::{handle_idle_callback, IDLE_CALLBACK_PREFIX},
+    plan::handle_plan_callback,
+    prompt::{audit_outcome, error_reply, error_texts},
+    send_html_text,
+    sessions::{handle_sessions_callback, SESSIONS_CALLBACK_PREFIX},
+    settings::{handle_settings_callback, SETTINGS_CALLBACK_PREFIX},
+    undo::{handle_undo_callback, UNDO_CALLBACK_PREFIX},
+    username_of,
+};
 use crate::router::AppState;
 
-#[derive(serde::Deserialize)]
-struct AskUserRequestFile {
-    chat_id: Option<serde_json::Value>,
-    options: Option<Vec<String>>,
+/// Callback alerts are capped at 200 characters.
+const ALERT_MAX_CHARS: usize = 200;
+const PREVIEW_HINT: &str = "\n\nTap again to choose this option.";
+
+fn preview_alert(full_text: &str) -> String {
+    let budget = ALERT_MAX_CHARS - PREVIEW_HINT.chars().count();
+    let (mut text, _) = truncate_label(full_text, budget);
+    text.push_str(PREVIEW_HINT);
+    text
 }
 
-fn parse_chat_id(v: &serde_json::Value) -> Option<i64> {
-    if let Some(n) = v.as_i64() {
-        return Some(n);
+fn message_ref(msg: &Message) -> MessageRef {
+    MessageRef {
+        chat_id: ChatId(msg.chat.id.0),
+        message_id: MessageId(msg.id.0),
     }
-    v.as_str().and_then(|s| s.parse::<i64>().ok())
 }
 
-fn is_cancel_error(err: &ctb_core::Error) -> bool {
-    match err {
-        Error::External(s) => {
-            let lower = s.to_lowercase();
-            lower.contains("cancel") || lower.contains("abort")
-        }
-        _ => false,
+/// Refuse a button tap with a short notice and record it in the audit log.
+async fn reject_callback(
+    bot: &Bot,
+    state: &AppState,
+    q: &CallbackQuery,
+    data: &str,
+    reason: &str,
+    notice: &str,
+) {
+    log_line!("[CALLBACK] Rejected callback from {}: {reason}", q.from.id);
+    let username = q.from.username.as_deref().unwrap_or("unknown");
+    let event = AuditEvent::callback_rejected(q.from.id.0 as i64, username, data, reason);
+    if let Err(e) = state.audit.write(event) {
+        log_line!("[AUDIT] Failed to write callback_rejected event: {e}");
     }
+    let _ = bot
+        .answer_callback_query(q.id.clone())
+        .text(notice.to_string())
+        .await;
 }
 
 pub async fn handle_callback(
@@ -41,32 +78,61 @@ pub async fn handle_callback(
 ) -> ResponseResult<()> {
     let cb_id = q.id.clone();
     let user = q.from.clone();
-    let chat_id = q.message.as_ref().map(|m| m.chat.id);
+    let target = q.message.as_ref().map(chat_target);
     let data = q.data.clone().unwrap_or_default();
 
     // Always answer callback query eventually.
-    if chat_id.is_none() || data.is_empty() {
+    if target.is_none() || data.is_empty() {
         let _ = bot.answer_callback_query(cb_id).await;
         return Ok(());
     }
 
-    let chat_id = chat_id.unwrap();
+    let target = target.unwrap();
     let user_id = user.id.0 as i64;
-    let username = user
-        .username
-        .clone()
-        .unwrap_or_else(|| "unknown".to_string());
+    let username = username_of(&user);
 
     // Auth check.
     if !ctb_core::security::is_authorized(Some(UserId(user_id)), &state.cfg.telegram_allowed_users)
     {
-        let _ = bot
-            .answer_callback_query(cb_id)
-            .text("Unauthorized".to_string())
-            .await;
+        let notice = state.messages(target).unauthorized();
+        reject_callback(&bot, &state, &q, &data, "unauthorized user", notice).await;
         return Ok(());
     }
 
+    // Only data this bot signed for this chat is acted on.
+    let data = match state.callback_signer.open(target.chat_id, &data) {
+        Ok(opened) => opened.to_string(),
+        Err(rejection) => {
+            let notice = match rejection {
+                CallbackRejection::Unsigned | CallbackRejection::UnknownVersion(_) => {
+                    "This button has expired"
+                }
+                CallbackRejection::Malformed | CallbackRejection::BadSignature => "Invalid button",
+            };
+            reject_callback(&bot, &state, &q, &data, &rejection.to_string(), notice).await;
+            return Ok(());
+        }
+    };
+
+    if let Some(message_id) = data.strip_prefix("rerun:") {
+        return edited::handle_rerun_callback(bot, &q, state, target, message_id).await;
+    }
+    if let Some(action) = data.strip_prefix(SETTINGS_CALLBACK_PREFIX) {
+        return handle_settings_callback(bot, &q, state, target, user_id, action).await;
+    }
+    if let Some(action) = data.strip_prefix(IDLE_CALLBACK_PREFIX) {
+        return handle_idle_callback(bot, &q, state, target, action).await;
+    }
+    if let Some(action) = data.strip_prefix(PLAN_CALLBACK_PREFIX) {
+        return handle_plan_callback(bot, &q, state, target, action).await;
+    }
+    if let Some(action) = data.strip_prefix(SESSIONS_CALLBACK_PREFIX) {
+        return handle_sessions_callback(bot, &q, state, target, action).await;
+    }
+    if let Some(action) = data.strip_prefix(UNDO_CALLBACK_PREFIX) {
+        return handle_undo_callback(bot, &q, state, target, action).await;
+    }
+
     // Parse callback data: askuser:{request_id}:{option_index}
     if !data.starts_with("askuser:") {
         let _ = bot.answer_callback_query(cb_id).await;
@@ -82,6 +148,43 @@ pub async fn handle_callback(
         return Ok(());
     }
     let request_id = parts[1];
+    let Some(request_file) = locate_request_file(&state.cfg.temp_dir, request_id) else {
+        let _ = bot
+            .answer_callback_query(cb_id)
+            .text("Request expired or invalid".to_string())
+            .await;
+        return Ok(());
+    };
+
+    // Page navigation (`askuser:{id}:p{page}`): re-render the keyboard in place.
+    if let Some(page) = parts[2]
+        .strip_prefix('p')
+        .and_then(|p| p.parse::<usize>().ok())
+    {
+        let request = load_request(&request_file, target).filter(|r| !r.answered);
+        if let (Some(request), Some(msg)) = (request, &q.message) {
+            let keyboard = OptionsKeyboard::new(request_id, &request.options)
+                .max_label_len(state.cfg.button_label_max_length)
+                .page(page)
+                .theme(state.cfg.status_theme)
+                .build();
+            let html = format!(
+                "{} {}",
+                state.cfg.status_theme.question,
+                escape_html(&request.question)
+            );
+            if let Err(e) = state
+                .messenger
+                .edit_inline_keyboard(message_ref(msg), &html, Some(keyboard))
+                .await
+            {
+                log_line!("[ASK_USER] Failed to change keyboard page: {e}");
+            }
+        }
+        let _ = bot.answer_callback_query(cb_id).await;
+        return Ok(());
+    }
+
     let option_index: usize = match parts[2].parse::<usize>() {
         Ok(v) => v,
         Err(_) => {
@@ -93,47 +196,63 @@ pub async fn handle_callback(
         }
     };
 
-    // Load request file
-    let request_file = format!("/tmp/ask-user-{request_id}.json");
-    let request: AskUserRequestFile = match std::fs::read_to_string(&request_file)
-        .ok()
-        .and_then(|txt| serde_json::from_str(&txt).ok())
-    {
-        Some(v) => v,
-        None => {
+    let outcome = record_answer(
+        &request_file,
+        target,
+        option_index,
+        state.cfg.button_label_max_length,
+        &iso_timestamp_utc(),
+    )
+    .unwrap_or_else(|e| {
+        log_line!("[ASK_USER] Failed to record answer for {request_id}: {e}");
+        AnswerOutcome::Invalid("Request expired or invalid")
+    });
+    let (question, selected, sync) = match outcome {
+        AnswerOutcome::Accepted {
+            question,
+            selected,
+            sync,
+        } => (question, selected, sync),
+        AnswerOutcome::Preview { full_text } => {
+            let _ = state
+                .messenger
+                .answer_callback_query(&cb_id, Some(&preview_alert(&full_text)), true)
+                .await;
+            return Ok(());
+        }
+        AnswerOutcome::AlreadyAnswered { .. } => {
             let _ = bot
                 .answer_callback_query(cb_id)
-                .text("Request expired or invalid".to_string())
+                .text("Already answered".to_string())
                 .await;
             return Ok(());
         }
-    };
-
-    if let Some(chat_val) = request.chat_id.as_ref().and_then(parse_chat_id) {
-        if chat_val != chat_id.0 {
+        AnswerOutcome::Invalid(reason) => {
             let _ = bot
                 .answer_callback_query(cb_id)
-                .text("Request expired or invalid".to_string())
+                .text(reason.to_string())
                 .await;
             return Ok(());
         }
-    }
-
-    let options = request.options.unwrap_or_default();
-    if option_index >= options.len() {
-        let _ = bot
-            .answer_callback_query(cb_id)
-            .text("Invalid option".to_string())
-            .await;
-        return Ok(());
-    }
-    let selected = options[option_index].clone();
+    };
 
-    // Update the keyboard message to show the selection.
+    // Replace the keyboard with the choice so the buttons can't be tapped again.
     if let Some(msg) = &q.message {
-        let _ = bot
-            .edit_message_text(msg.chat.id, msg.id, format!("✓ {selected}"))
-            .await;
+        let theme = state.cfg.status_theme;
+        let html = format!(
+            "{} {}\n{} You chose: {}",
+            theme.question,
+            escape_html(&question),
+            theme.done,
+            escape_html(&selected)
+        );
+        if let Err(e) = state
+            .messenger
+            .edit_inline_keyboard(message_ref(msg), &html, None)
+            .await
+        {
+            log_line!("[ASK_USER] Failed to update keyboard message: {e}");
+        }
     }
 
     // Answer callback.
@@ -147,80 +266,140 @@ pub async fn handle_callback(
         .text(format!("Selected: {preview}"))
         .await;
 
-    // Delete request file (best-effort).
-    let _ = std::fs::remove_file(&request_file);
+    // Sync mode: the MCP server picks the answer up from the file inside the running turn
+    // (and archives it).
+    if sync {
+        return Ok(());
+    }
+    archive_answered(&request_file);
+    resume_with_answer(state, target, user_id, username, selected).await
+}
+
+/// A numbered reply to an `ask_user` list (sent where inline keyboards are unsupported); `false`
+/// if the request was answered meanwhile and the text should be handled as a prompt.
+pub(crate) async fn answer_from_text(
+    state: Arc<AppState>,
+    target: ChatTarget,
+    user_id: i64,
+    username: String,
+    request_file: &Path,
+    option_index: usize,
+) -> ResponseResult<bool> {
+    // Labels are never truncated in a list, so there is no preview step.
+    let outcome = record_answer(
+        request_file,
+        target,
+        option_index,
+        usize::MAX,
+        &iso_timestamp_utc(),
+    )
+    .unwrap_or_else(|e| {
+        log_line!("[ASK_USER] Failed to record text answer: {e}");
+        AnswerOutcome::Invalid("Request expired or invalid")
+    });
+    let AnswerOutcome::Accepted { selected, sync, .. } = outcome else {
+        return Ok(false);
+    };
+    let _ = state
+        .messenger
+        .send_html(
+            target,
+            &format!(
+                "{} You chose: {}",
+                state.cfg.status_theme.done,
+                escape_html(&selected)
+            ),
+        )
+        .await;
+    if sync {
+        return Ok(true);
+    }
+    archive_answered(request_file);
+    resume_with_answer(state, target, user_id, username, selected).await?;
+    Ok(true)
+}
+
+/// The answer is about to be consumed; later taps still find the file in `processed/`.
+fn archive_answered(request_file: &Path) {
+    if let Err(e) = archive_request(request_file) {
+        log_line!(
+            "[ASK_USER] Failed to archive {}: {e}",
+            request_file.display()
+        );
+    }
+}
 
+/// Continue the suspended run with the user's choice.
+async fn resume_with_answer(
+    state: Arc<AppState>,
+    target: ChatTarget,
+    user_id: i64,
+    username: String,
+    selected: String,
+) -> ResponseResult<()> {
     // Interrupt any running query: button responses should be immediate.
-    if state.session.is_running().await {
-        let _ = state.session.stop().await;
+    let session = state.sessions.for_target(target).await;
+    if session.is_running().await {
+        let _ = session.stop(target, CancelReason::UserStop).await;
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-        state.session.clear_stop_requested().await;
-    }
-
-    // Typing loop (best-effort).
-    let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
-    let bot_for_typing = bot.clone();
-    let chat_for_typing = chat_id;
-    let typing_task = tokio::spawn(async move {
-        let mut tick = tokio::time::interval(std::time::Duration::from_secs(3));
-        loop {
-            tokio::select! {
-              _ = tick.tick() => {
-                let _ = bot_for_typing.send_chat_action(chat_for_typing, ChatAction::Typing).await;
-              }
-              _ = &mut stop_rx => break,
-            }
-        }
-    });
+        session.clear_stop_requested(target).await;
+    }
 
     let messenger: Arc<dyn MessagingPort> = state.messenger.clone();
 
-    let result = state
-        .session
-        .send_message_to_chat(ChatId(chat_id.0), &selected, messenger)
+    let started = std::time::Instant::now();
+    let suspended = state.suspended_streams.take(target);
+    let requester = RunUser {
+        user_id,
+        username: username.clone(),
+        role: role_of(UserId(user_id), &state.cfg).unwrap_or(Role::ReadOnly),
+    };
+    let mut result = session
+        .resume_message_to_chat(target, &selected, messenger, suspended, requester)
         .await;
+    // The answer may lead to another question.
+    if let Some(suspended) = result.as_mut().ok().and_then(|out| out.suspended.take()) {
+        state.suspended_streams.stash(suspended);
+    }
 
     // Audit log (best-effort).
+    let outcome = audit_outcome(&result);
     let audit_res = match &result {
-        Ok(out) => state.audit.write(AuditEvent::message(
-            user_id,
-            &username,
-            "CALLBACK",
-            &selected,
-            Some(&out.text),
-        )),
-        Err(e) => state.audit.write(AuditEvent::error(
-            user_id,
-            &username,
-            &format!("{e}"),
-            Some("callback"),
-        )),
+        Ok(out) => state.audit.write(
+            AuditEvent::message(user_id, &username, "CALLBACK", &selected, Some(&out.text))
+                .with_turn(
+                    out.session.as_ref().map(|s| s.id.as_str()),
+                    started.elapsed(),
+                    out.usage.as_ref(),
+                    outcome,
+                ),
+        ),
+        Err(e) => {
+            let session_id = session.stats().await.session.map(|s| s.id);
+            state.audit.write(
+                AuditEvent::error(
+                    user_id,
+                    &username,
+                    &error_texts(&state, e).1,
+                    Some("callback"),
+                )
+                .with_turn(session_id.as_deref(), started.elapsed(), None, outcome),
+            )
+        }
     };
     if let Err(e) = audit_res {
-        eprintln!("[AUDIT] Failed to write callback audit event: {e}");
+        log_line!("[AUDIT] Failed to write callback audit event: {e}");
     }
 
     if let Err(err) = result {
-        if is_cancel_error(&err) {
-            let was_interrupt = state.session.consume_interrupt_flag().await;
-            if !was_interrupt {
-                let _ = bot.send_message(chat_id, "🛑 Query stopped.").await;
+        if let Error::Cancelled(reason) = err {
+            if let Some(notice) = state.messages(target).cancel_notice(reason) {
+                let _ = send_html_text(&state, target, notice).await;
             }
         } else {
-            let msg_txt = format!("{err}");
-            let truncated = if msg_txt.len() > 200 {
-                format!("{}...", msg_txt.chars().take(200).collect::<String>())
-            } else {
-                msg_txt
-            };
-            let _ = bot
-                .send_message(chat_id, format!("❌ Error: {truncated}"))
-                .await;
+            let _ = send_html_text(&state, target, error_reply(&state, target, &err)).await;
         }
     }
 
-    let _ = stop_tx.send(());
-    let _ = typing_task.await;
-
     Ok(())
 }
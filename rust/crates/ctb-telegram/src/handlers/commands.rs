@@ -4,13 +4,40 @@ use chrono::{DateTime, Utc};
 use teloxide::prelude::*;
 
 use ctb_core::{
-    formatting::escape_html,
-    usage::{AllUsage, ClaudeUsage, CodexUsage, GeminiUsage},
+    broadcast::{broadcast, broadcast_recipients, BROADCAST_SPACING},
+    config::Config,
+    domain::{ChatId, ChatTarget, UserId},
+    errors::CancelReason,
+    formatting::{escape_html, format_idle, format_turn_duration},
+    git::{GitDiff, WorkTree},
+    health::{claude_cli_version, run_health_checks},
+    i18n::Messages,
+    log_line,
+    mcp_config::{load_mcp_servers, probe_stdio_server, McpServerConfig, MCP_PROBE_TIMEOUT},
+    messaging::chat_action::upload_document,
+    model::types::TokenUsage,
+    model::types::KNOWN_MODEL_ALIASES,
+    prompt_profiles::{list_profiles, load_profile, profiles_dir},
+    restart::now_ms,
+    security::{is_authorized_for, role_of, Capability, RateAction},
+    session::{parse_thinking_budget, prepare_mcp_config_for_chat, thinking_label},
+    state_export::{BotState, STATE_EXPORT_FILE_NAME},
+    theme::StatusTheme,
+    usage::{AllUsage, ClaudeUsage, CodexUsage, GeminiUsage, ProviderStatus},
+    usage_ledger::{Periods, UsageTotals, UserUsage},
+    utils::AuditEvent,
+    vocab::VOCAB_MAX_TERMS,
 };
 
 use crate::router::AppState;
 
-use super::prompt::{run_text_prompt, PromptContext};
+use super::logs::handle_logs_command;
+use super::prompt::{run_prompt, run_text_prompt, PromptContext, PromptOptions};
+use super::remind::handle_remind_command;
+use super::sessions::{handle_sessions_command, handle_title_command};
+use super::settings::handle_settings_command;
+use super::undo::handle_undo_command;
+use super::{chat_target, send_html_text, username_of};
 
 fn parse_command(text: &str) -> (String, String) {
     // Telegram may send `/cmd@botname arg1 ...`
@@ -28,6 +55,19 @@ fn parse_command(text: &str) -> (String, String) {
     (cmd, rest)
 }
 
+/// HTML label for the thinking mode (`/think` override, else keyword detection).
+fn thinking_mode_label(msgs: &Messages, cfg: &Config, override_tokens: Option<u32>) -> String {
+    msgs.thinking_mode(
+        override_tokens.map(thinking_label).as_deref(),
+        &thinking_label(cfg.default_thinking_tokens),
+    )
+}
+
+/// HTML label for the active model (`/model` override, else `CLAUDE_MODEL`, else CLI default).
+fn model_label(msgs: &Messages, cfg: &Config, selected: Option<&str>) -> String {
+    msgs.model_label(selected, cfg.claude_model.as_deref())
+}
+
 fn format_duration(seconds: i64) -> String {
     let seconds = seconds.max(0);
     let hours = seconds / 3600;
@@ -42,7 +82,7 @@ fn format_duration(seconds: i64) -> String {
     format!("{secs}s")
 }
 
-fn format_time_remaining(reset_time: Option<&str>) -> String {
+fn format_time_remaining(msgs: &Messages, reset_time: Option<&str>) -> String {
     let Some(reset_time) = reset_time else {
         return "".to_string();
     };
@@ -57,7 +97,7 @@ fn format_time_remaining(reset_time: Option<&str>) -> String {
     let now = Utc::now();
     let diff = reset.signed_duration_since(now);
     if diff.num_seconds() <= 0 {
-        return "now".to_string();
+        return msgs.now().to_string();
     }
 
     let diff_sec = diff.num_seconds();
@@ -74,7 +114,7 @@ fn format_time_remaining(reset_time: Option<&str>) -> String {
     format!("{mins}m")
 }
 
-fn format_time_remaining_unix_seconds(reset_at: u64) -> String {
+fn format_time_remaining_unix_seconds(msgs: &Messages, reset_at: u64) -> String {
     if reset_at == 0 {
         return "".to_string();
     }
@@ -86,7 +126,7 @@ fn format_time_remaining_unix_seconds(reset_at: u64) -> String {
     let now = Utc::now();
     let diff = reset.signed_duration_since(now);
     if diff.num_seconds() <= 0 {
-        return "now".to_string();
+        return msgs.now().to_string();
     }
 
     let diff_sec = diff.num_seconds();
@@ -103,13 +143,155 @@ fn format_time_remaining_unix_seconds(reset_at: u64) -> String {
     format!("{mins}m")
 }
 
-async fn send_html_split(state: &AppState, chat_id: i64, html: &str) {
-    let limit = state.cfg.telegram_safe_limit.max(200);
-    for chunk in split_html_chunks(html, limit) {
+/// The last query's token counts, for `/status` and `/stats`.
+fn last_usage_lines(msgs: &Messages, u: &TokenUsage) -> Vec<String> {
+    let mut lines = vec![
+        format!("   {}", msgs.input_tokens(u.input_tokens)),
+        format!("   {}", msgs.output_tokens(u.output_tokens)),
+    ];
+    if u.cache_read_input_tokens > 0 {
+        lines.push(format!(
+            "   {}",
+            msgs.cache_read_tokens(u.cache_read_input_tokens)
+        ));
+    }
+    lines
+}
+
+fn audit_git(state: &AppState, user_id: i64, username: &str, command: &str, outcome: &str) {
+    let event = AuditEvent::message(user_id, username, "GIT", command, Some(outcome));
+    if let Err(e) = state.audit.write(event) {
+        log_line!("[AUDIT] Failed to write git event: {e}");
+    }
+}
+
+/// `/diff` output: status and stat inline, the patch inline when short, else as a `.patch` file.
+async fn send_diff(state: &AppState, target: ChatTarget, dir: &std::path::Path, diff: &GitDiff) {
+    let msgs = state.messages(target);
+    let dir = dir.display().to_string();
+    if diff.is_empty() {
         let _ = state
             .messenger
-            .send_html(ctb_core::domain::ChatId(chat_id), &chunk)
+            .send_html(target, &msgs.diff_clean(&dir))
             .await;
+        return;
+    }
+
+    let mut summary = format!(
+        "{}\n<pre>{}</pre>",
+        msgs.diff_title(&dir),
+        escape_html(diff.status.trim_end())
+    );
+    if !diff.stat.trim().is_empty() {
+        summary.push_str(&format!(
+            "\n<pre>{}</pre>",
+            escape_html(diff.stat.trim_end())
+        ));
+    }
+    send_html_split(state, target, &summary).await;
+    if diff.patch.trim().is_empty() {
+        return;
+    }
+
+    // Beyond a few messages a file is easier to read (and to apply).
+    let inline_max = state.cfg.telegram_safe_limit.max(200) * 3;
+    if diff.patch.len() > inline_max && state.messenger.capabilities().supports_documents {
+        let path = state
+            .cfg
+            .temp_dir
+            .join(format!("changes-{}.patch", now_ms()));
+        let caption = if diff.truncated {
+            msgs.diff_caption_truncated()
+        } else {
+            msgs.diff_caption()
+        };
+        let written = write_temp_file(&state.cfg.temp_dir, &path, &diff.patch).await;
+        let sent = match written {
+            Ok(()) => upload_document(state.messenger.as_ref(), target, &path, Some(caption)).await,
+            Err(e) => Err(e.into()),
+        };
+        let _ = tokio::fs::remove_file(&path).await;
+        match sent {
+            Ok(_) => return,
+            Err(e) => log_line!("[GIT] Failed to send patch as file: {e}"),
+        }
+    }
+
+    let mut patch: String = diff.patch.chars().take(inline_max).collect();
+    if patch.len() < diff.patch.len() {
+        patch.push('\n');
+        patch.push_str(msgs.truncated());
+    }
+    send_html_split(
+        state,
+        target,
+        &format!("<pre>{}</pre>", escape_html(&patch)),
+    )
+    .await;
+}
+
+/// Write `contents` to `path` under `temp_dir`, creating the directory first.
+async fn write_temp_file(
+    temp_dir: &std::path::Path,
+    path: &std::path::Path,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(temp_dir).await?;
+    tokio::fs::write(path, contents).await
+}
+
+/// Send every chat's persistent state as a JSON attachment (restored with `ctb import-state`) to
+/// the owner's private chat, never to the chat the command came from.
+async fn send_state_export(state: &AppState, target: ChatTarget, owner_id: i64) {
+    let msgs = state.messages(target);
+    let owner = ChatTarget::from(ChatId(owner_id));
+    let store = state.sessions.default_session().store();
+    let bot_state = BotState {
+        sessions: store.as_ref(),
+        chat_settings: &state.chat_settings,
+        grants: &state.grants,
+        vocabulary: &state.vocabulary,
+    };
+    let path = state.cfg.temp_dir.join(STATE_EXPORT_FILE_NAME);
+    let written = match bot_state.export().and_then(|doc| doc.to_json(&state.cfg)) {
+        Ok(json) => write_temp_file(&state.cfg.temp_dir, &path, json)
+            .await
+            .map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    let sent = match written {
+        Ok(()) => {
+            upload_document(
+                state.messenger.as_ref(),
+                owner,
+                &path,
+                Some(state.messages(owner).state_export_caption()),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    let _ = tokio::fs::remove_file(&path).await;
+    let reply = match sent {
+        Ok(_) if target == owner => return,
+        Ok(_) => msgs.state_export_sent().to_string(),
+        Err(e) => {
+            log_line!("[STATE] Export failed: {e}");
+            msgs.state_export_failed(&e.to_string())
+        }
+    };
+    let _ = state.messenger.send_html(target, &reply).await;
+}
+
+/// Owner-only commands (`/perf`, `/logs`, `/broadcast`, `/grant`, `/restart`, ...); see `TELEGRAM_USER_ROLES`.
+pub(super) fn is_owner(state: &AppState, user_id: i64) -> bool {
+    is_authorized_for(Some(UserId(user_id)), Capability::Admin, &state.cfg)
+}
+
+pub(super) async fn send_html_split(state: &AppState, target: ChatTarget, html: &str) {
+    let limit = state.cfg.telegram_safe_limit.max(200);
+    for chunk in split_html_chunks(html, limit) {
+        let _ = state.messenger.send_html(target, &chunk).await;
     }
 }
 
@@ -406,118 +588,192 @@ fn split_utf8_prefix(s: &str, max_bytes: usize) -> (&str, &str) {
     (&s[..idx], &s[idx..])
 }
 
-fn format_claude_usage(usage: &ClaudeUsage) -> Vec<String> {
+/// ` (resets in 3h 12m)`, or nothing without a reset time.
+fn resets_in(msgs: &Messages, remaining: String) -> String {
+    if remaining.is_empty() {
+        String::new()
+    } else {
+        msgs.provider_resets_in(&remaining)
+    }
+}
+
+fn format_claude_usage(msgs: &Messages, usage: &ClaudeUsage) -> Vec<String> {
     let mut lines = vec!["<b>Claude Code:</b>".to_string()];
 
-    if let Some(w) = &usage.five_hour {
-        let reset = format_time_remaining(w.resets_at.as_deref());
-        lines.push(format!(
-            "   5h: {}%{}",
-            w.utilization.round(),
-            if reset.is_empty() {
-                "".to_string()
-            } else {
-                format!(" (resets in {reset})")
-            }
-        ));
-    }
-    if let Some(w) = &usage.seven_day {
-        let reset = format_time_remaining(w.resets_at.as_deref());
-        lines.push(format!(
-            "   7d: {}%{}",
-            w.utilization.round(),
-            if reset.is_empty() {
-                "".to_string()
-            } else {
-                format!(" (resets in {reset})")
-            }
-        ));
-    }
-    if let Some(w) = &usage.seven_day_sonnet {
-        let reset = format_time_remaining(w.resets_at.as_deref());
-        lines.push(format!(
-            "   7d Sonnet: {}%{}",
-            w.utilization.round(),
-            if reset.is_empty() {
-                "".to_string()
-            } else {
-                format!(" (resets in {reset})")
-            }
-        ));
+    for (label, window) in [
+        ("5h", &usage.five_hour),
+        ("7d", &usage.seven_day),
+        ("7d Sonnet", &usage.seven_day_sonnet),
+    ] {
+        if let Some(w) = window {
+            let reset = format_time_remaining(msgs, w.resets_at.as_deref());
+            lines.push(format!(
+                "   {label}: {}%{}",
+                w.utilization.round(),
+                resets_in(msgs, reset)
+            ));
+        }
     }
 
     lines
 }
 
-fn format_codex_usage(usage: &CodexUsage) -> Vec<String> {
+fn format_codex_usage(msgs: &Messages, usage: &CodexUsage) -> Vec<String> {
     let mut lines = vec![format!(
         "<b>OpenAI Codex</b> ({}):",
         escape_html(&usage.plan_type)
     )];
 
-    if let Some(w) = &usage.primary {
-        let reset = format_time_remaining_unix_seconds(w.reset_at);
-        lines.push(format!(
-            "   5h: {}%{}",
-            w.used_percent.round(),
-            if reset.is_empty() {
-                "".to_string()
-            } else {
-                format!(" (resets in {reset})")
-            }
-        ));
-    }
-    if let Some(w) = &usage.secondary {
-        let reset = format_time_remaining_unix_seconds(w.reset_at);
-        lines.push(format!(
-            "   7d: {}%{}",
-            w.used_percent.round(),
-            if reset.is_empty() {
-                "".to_string()
-            } else {
-                format!(" (resets in {reset})")
-            }
-        ));
+    for (label, window) in [("5h", &usage.primary), ("7d", &usage.secondary)] {
+        if let Some(w) = window {
+            let reset = format_time_remaining_unix_seconds(msgs, w.reset_at);
+            lines.push(format!(
+                "   {label}: {}%{}",
+                w.used_percent.round(),
+                resets_in(msgs, reset)
+            ));
+        }
     }
 
     lines
 }
 
-fn format_gemini_usage(usage: &GeminiUsage) -> Vec<String> {
+fn format_gemini_usage(msgs: &Messages, usage: &GeminiUsage) -> Vec<String> {
     let mut lines = vec![format!("<b>Gemini</b> ({}):", escape_html(&usage.model))];
 
     if let Some(pct) = usage.used_percent {
-        let reset = format_time_remaining(usage.reset_at.as_deref());
+        let reset = format_time_remaining(msgs, usage.reset_at.as_deref());
         lines.push(format!(
-            "   Usage: {pct}%{}",
-            if reset.is_empty() {
-                "".to_string()
-            } else {
-                format!(" (resets in {reset})")
-            }
+            "   {}{}",
+            msgs.provider_usage_percent(pct),
+            resets_in(msgs, reset)
         ));
     }
 
     lines
 }
 
-fn format_provider_usage(all: &AllUsage) -> Vec<String> {
-    let mut lines = vec!["\n🌐 <b>Provider Usage</b>".to_string()];
+fn format_provider_usage(msgs: &Messages, all: &AllUsage) -> Vec<String> {
+    let mut lines = vec![msgs.provider_usage_title().to_string()];
     if let Some(c) = &all.claude {
-        lines.extend(format_claude_usage(c));
+        lines.extend(format_claude_usage(msgs, c));
     }
     if let Some(c) = &all.codex {
-        lines.extend(format_codex_usage(c));
+        lines.extend(format_codex_usage(msgs, c));
     }
     if let Some(g) = &all.gemini {
-        lines.extend(format_gemini_usage(g));
+        lines.extend(format_gemini_usage(msgs, g));
     }
-    if all.claude.is_none() && all.codex.is_none() && all.gemini.is_none() {
-        lines.push("   <i>No providers authenticated</i>".to_string());
+    // Providers that have credentials but produced no numbers this time.
+    for (name, status) in [
+        ("Claude Code", all.status.claude),
+        ("OpenAI Codex", all.status.codex),
+        ("Gemini", all.status.gemini),
+    ] {
+        if !matches!(status, ProviderStatus::Ok | ProviderStatus::NotConfigured) {
+            lines.push(format!("<b>{name}</b>: <i>{}</i>", status.label()));
+        }
+    }
+    if lines.len() == 1 {
+        lines.push(format!("   {}", msgs.no_providers()));
+    } else {
+        lines.push(format!(
+            "   {}",
+            msgs.provider_usage_fetched(&format_turn_duration(all.age(now_ms())))
+        ));
     }
     lines
 }
 
+fn format_totals(msgs: &Messages, t: &UsageTotals) -> String {
+    msgs.limits_totals(t.tokens, t.cost_usd, t.turns)
+}
+
+/// `/limits`: today's and this week's ledger totals per user, plus the daily cap.
+fn limits_html(
+    msgs: &Messages,
+    theme: &StatusTheme,
+    usage: &[UserUsage],
+    daily_cap: Option<u64>,
+) -> String {
+    let mut lines = vec![msgs.limits_title().to_string()];
+    match daily_cap {
+        Some(cap) => lines.push(msgs.limits_cap(cap)),
+        None => lines.push(msgs.limits_no_cap().to_string()),
+    }
+    if usage.is_empty() {
+        lines.push(msgs.limits_empty().to_string());
+        return lines.join("\n");
+    }
+    for u in usage {
+        lines.push(format!(
+            "\n{}<b>{}</b> (<code>{}</code>)",
+            theme.deco("👤"),
+            escape_html(&u.username),
+            u.user_id
+        ));
+        let percent = daily_cap.map(|cap| u.today.tokens as f64 * 100.0 / cap as f64);
+        lines.push(format!(
+            "   {}",
+            msgs.limits_today(&format_totals(msgs, &u.today), percent)
+        ));
+        lines.push(format!(
+            "   {}",
+            msgs.limits_week(&format_totals(msgs, &u.week))
+        ));
+    }
+    lines.join("\n")
+}
+
+/// `/mcp`: servers from the effective (interpolated) config, optionally health-probed.
+async fn mcp_status_html(msgs: &Messages, cfg: &Config, target: ChatTarget, probe: bool) -> String {
+    let servers = match prepare_mcp_config_for_chat(cfg, target) {
+        Ok(Some(path)) => load_mcp_servers(&path),
+        Ok(None) => return msgs.mcp_none().to_string(),
+        Err(e) => Err(e),
+    };
+    let servers = match servers {
+        Ok(s) => s,
+        Err(e) => return msgs.mcp_load_failed(&e.to_string()),
+    };
+
+    let mut names: Vec<&String> = servers.keys().collect();
+    names.sort();
+    let mut lines = vec![msgs.mcp_title(names.len())];
+    for name in names {
+        let server = &servers[name];
+        lines.push(format!(
+            "{} <b>{}</b> {} <code>{}</code>",
+            cfg.status_theme.bullet,
+            escape_html(name),
+            cfg.status_theme.dash,
+            escape_html(&server.transport())
+        ));
+        if let Some(warning) = server.command_warning() {
+            lines.push(format!("   {}", msgs.warning(&warning)));
+        }
+        if !probe {
+            continue;
+        }
+        match server {
+            McpServerConfig::Stdio { command, args, env } => {
+                match probe_stdio_server(command, args, env, MCP_PROBE_TIMEOUT).await {
+                    Ok(tools) if tools.is_empty() => {
+                        lines.push(format!("   {}", msgs.mcp_ok_no_tools()))
+                    }
+                    Ok(tools) => lines.push(format!("   {}", msgs.succeeded(&tools.join(", ")))),
+                    Err(e) => lines.push(format!("   {}", msgs.failed(&e.to_string()))),
+                }
+            }
+            McpServerConfig::Http { .. } => lines.push(format!("   {}", msgs.mcp_not_probed())),
+        }
+    }
+    if !probe {
+        lines.push(msgs.mcp_probe_hint().to_string());
+    }
+    lines.join("\n")
+}
+
 pub async fn handle_command(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
     let Some(user) = msg.from() else {
         return Ok(());
@@ -527,78 +783,54 @@ pub async fn handle_command(bot: Bot, msg: Message, state: Arc<AppState>) -> Res
     };
 
     let user_id = user.id.0 as i64;
-    let username = user
-        .username
-        .clone()
-        .unwrap_or_else(|| "unknown".to_string());
-    let chat_id = msg.chat.id.0;
+    let username = username_of(user);
+    let target = chat_target(&msg);
+    let session = state.sessions.for_target(target).await;
 
     let (cmd, arg) = parse_command(text);
+    let msgs = state.messages(target);
+
+    // Any command ends a `/sessions` rename wait; that is all `/cancel` does.
+    if state.pending_titles.cancel(target, user_id) && cmd == "cancel" {
+        send_html_split(&state, target, msgs.sessions_rename_cancelled()).await;
+        return Ok(());
+    }
 
     match cmd.as_str() {
         "start" | "help" => {
-            let status = if state.session.is_active().await {
-                "Active session"
-            } else {
-                "No active session"
-            };
-            let work_dir = escape_html(&state.cfg.claude_working_dir.display().to_string());
-
-            let body = format!(
-                "🤖 <b>Claude Telegram Bot (Rust)</b>\n\n\
-Status: {status}\n\
-Working directory: <code>{work_dir}</code>\n\n\
-<b>📋 Commands:</b>\n\
-/start - Show this help message\n\
-/new - Start fresh session\n\
-/stop - Stop current query (silent)\n\
-/status - Show current session status\n\
-/stats - Show token usage & cost stats\n\
-/resume - Resume last saved session\n\
-/retry - Retry last message\n\
-/cron [reload] - Scheduled jobs status/reload\n\
-/restart - Restart the bot process\n\n\
-<b>💡 Tips:</b>\n\
-• Prefix with <code>!</code> to interrupt current query\n\
-• Use \"think\" keyword for extended reasoning\n\
-• Use \"ultrathink\" for deep analysis\n\
-• Send photos, voice messages, or documents\n\
-• Multiple photos = album (auto-grouped)"
+            let body = msgs.help(
+                session.is_active().await,
+                &state.cfg.claude_working_dir.display().to_string(),
             );
-
-            send_html_split(&state, chat_id, &body).await;
+            send_html_split(&state, target, &body).await;
             Ok(())
         }
 
         "new" => {
-            if state.session.is_running().await {
-                let _ = state.session.stop().await;
+            if session.is_running().await {
+                let _ = session.stop(target, CancelReason::UserStop).await;
                 tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                state.session.clear_stop_requested().await;
+                session.clear_stop_requested(target).await;
             }
-            let _ = state.session.kill().await;
-            send_html_split(
-                &state,
-                chat_id,
-                "🆕 Session cleared. Next message starts fresh.",
-            )
-            .await;
+            let _ = session.kill().await;
+            send_html_split(&state, target, msgs.session_cleared()).await;
             Ok(())
         }
 
         "stop" => {
-            if state.session.is_running().await {
-                let _ = state.session.stop().await;
+            if session.is_running().await {
+                let _ = session.stop(target, CancelReason::UserStop).await;
                 tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                state.session.clear_stop_requested().await;
+                session.clear_stop_requested(target).await;
             }
             // Silent by design.
             Ok(())
         }
 
         "status" => {
-            let st = state.session.stats().await;
-            let mut lines: Vec<String> = vec!["📊 <b>Bot Status</b>\n".to_string()];
+            let st = session.stats().await;
+            let t = state.cfg.status_theme;
+            let mut lines: Vec<String> = vec![format!("{}{}", t.deco("📊"), msgs.status_title())];
 
             if let Some(sref) = st.session.as_ref() {
                 let short = if sref.id.len() > 8 {
@@ -606,122 +838,561 @@ Working directory: <code>{work_dir}</code>\n\n\
                 } else {
                     &sref.id
                 };
-                lines.push(format!("✅ Session: Active ({short}...)"));
+                lines.push(format!("{} {}", t.done, msgs.status_session_active(short)));
                 if let Some(start) = st.session_start_time.as_deref() {
                     if let Ok(dt) = DateTime::parse_from_rfc3339(start) {
                         let dur = (Utc::now() - dt.with_timezone(&Utc)).num_seconds();
                         lines.push(format!(
-                            "   └─ Duration: {} | {} queries",
-                            format_duration(dur),
-                            st.total_queries
+                            "   {} {}",
+                            t.branch,
+                            msgs.status_session_duration(&format_duration(dur), st.total_queries)
                         ));
                     }
                 }
+                if let Some(idle) = st.idle_for {
+                    lines.push(format!(
+                        "   {} {}",
+                        t.branch,
+                        msgs.status_idle_for(&format_idle(idle))
+                    ));
+                }
             } else {
-                lines.push("⚪ Session: None".to_string());
+                lines.push(format!("{} {}", t.idle, msgs.status_session_none()));
             }
 
             if st.is_running {
-                lines.push("🔄 Query: Running".to_string());
+                lines.push(format!("{} {}", t.running, msgs.status_query_running()));
             } else {
-                lines.push("⚪ Query: Idle".to_string());
+                lines.push(format!("{} {}", t.idle, msgs.status_query_idle()));
+            }
+
+            lines.push(format!(
+                "{}{}",
+                t.deco("🧠"),
+                msgs.status_model(&model_label(msgs, &state.cfg, st.model.as_deref()))
+            ));
+            lines.push(format!(
+                "{}{}",
+                t.deco("💭"),
+                msgs.status_thinking(&thinking_mode_label(msgs, &state.cfg, st.thinking_tokens))
+            ));
+            let chat_cfg = state.chat_settings.effective(&state.cfg, target.chat_id.0);
+            lines.push(format!(
+                "{}{}",
+                t.deco("🧰"),
+                msgs.status_tools(&msgs.tool_policy(
+                    chat_cfg.claude_allowed_tools.as_deref(),
+                    chat_cfg.claude_disallowed_tools.as_deref()
+                ))
+            ));
+            if let Some(version) = claude_cli_version() {
+                lines.push(format!(
+                    "{}{}",
+                    t.deco("🤖"),
+                    msgs.status_cli_version(version)
+                ));
             }
 
             if let Some(u) = st.last_usage.as_ref() {
-                lines.push("\n📈 Last query usage:".to_string());
-                lines.push(format!("   Input: {} tokens", u.input_tokens));
-                lines.push(format!("   Output: {} tokens", u.output_tokens));
-                if u.cache_read_input_tokens > 0 {
-                    lines.push(format!("   Cache read: {}", u.cache_read_input_tokens));
-                }
+                lines.push(format!("\n{}{}", t.deco("📈"), msgs.status_last_usage()));
+                lines.extend(last_usage_lines(msgs, u));
             }
 
             lines.push(format!(
-                "\n📁 Working dir: <code>{}</code>",
-                escape_html(&state.cfg.claude_working_dir.display().to_string())
+                "\n{}{}",
+                t.deco("📁"),
+                msgs.status_working_dir(&state.cfg.claude_working_dir.display().to_string())
             ));
+            if let Some(role) = role_of(UserId(user_id), &state.cfg) {
+                lines.push(format!(
+                    "{}{}",
+                    t.deco("👤"),
+                    msgs.status_access(role.as_str())
+                ));
+            }
+            if is_owner(&state, user_id) {
+                let blocks = state
+                    .sessions
+                    .default_session()
+                    .security_monitor()
+                    .map(|m| m.counts())
+                    .unwrap_or_default();
+                if !blocks.is_empty() {
+                    lines.push(format!("{}{}", t.deco("🛡"), msgs.status_safety_blocks()));
+                    for b in blocks {
+                        lines.push(format!(
+                            "   {} @{} ({}): {}",
+                            t.branch,
+                            escape_html(&b.username),
+                            b.user_id,
+                            b.blocks
+                        ));
+                    }
+                }
+            }
+            let grants = state.grants.active(target.chat_id.0);
+            if !grants.is_empty() {
+                lines.push(format!("{}{}", t.deco("🔓"), msgs.status_granted_dirs()));
+                for g in grants {
+                    let expiry = match g.expires_at_ms {
+                        Some(t) => msgs.status_grant_expires(&format_duration(
+                            (t.saturating_sub(now_ms()) / 1000) as i64,
+                        )),
+                        None => String::new(),
+                    };
+                    lines.push(format!(
+                        "   {} <code>{}</code>{expiry}",
+                        t.branch,
+                        escape_html(&g.path.display().to_string())
+                    ));
+                }
+            }
+
+            send_html_split(&state, target, &lines.join("\n")).await;
+            Ok(())
+        }
+
+        "model" => {
+            let arg = arg.trim();
+            if arg.is_empty() {
+                let current = session.model().await;
+                let body = msgs.model_show(
+                    &model_label(msgs, &state.cfg, current.as_deref()),
+                    KNOWN_MODEL_ALIASES,
+                );
+                send_html_split(&state, target, &body).await;
+                return Ok(());
+            }
+
+            let selection = if arg.eq_ignore_ascii_case("default") {
+                None
+            } else {
+                Some(arg.to_string())
+            };
+            let reply = match session.set_model(selection.clone()).await {
+                Ok(()) => msgs.model_set(&model_label(msgs, &state.cfg, selection.as_deref())),
+                Err(e) => msgs.failed(&e.to_string()),
+            };
+            send_html_split(&state, target, &reply).await;
+            Ok(())
+        }
+
+        "think" => {
+            let arg = arg.trim();
+            if arg.is_empty() {
+                let current = session.thinking_tokens().await;
+                let body = msgs.think_show(&thinking_mode_label(msgs, &state.cfg, current));
+                send_html_split(&state, target, &body).await;
+                return Ok(());
+            }
+
+            let selection = if arg.eq_ignore_ascii_case("default") {
+                Ok(None)
+            } else {
+                parse_thinking_budget(arg).map(Some)
+            };
+            let result = match selection {
+                Ok(tokens) => session
+                    .set_thinking_tokens(tokens)
+                    .await
+                    .map(|()| thinking_mode_label(msgs, &state.cfg, tokens)),
+                Err(e) => Err(e),
+            };
+            let reply = match result {
+                Ok(label) => msgs.think_set(&label),
+                Err(e) => msgs.failed(&e.to_string()),
+            };
+            send_html_split(&state, target, &reply).await;
+            Ok(())
+        }
+
+        "prompt" => {
+            let (sub, name) = arg
+                .trim()
+                .split_once(char::is_whitespace)
+                .map_or((arg.trim(), ""), |(s, n)| (s, n.trim()));
+            let reply = match sub.to_ascii_lowercase().as_str() {
+                "use" if !name.is_empty() => {
+                    match session
+                        .set_prompt_profile(target, Some(name.to_string()))
+                        .await
+                    {
+                        Ok(()) => msgs.prompt_set(name),
+                        Err(e) => msgs.failed(&e.to_string()),
+                    }
+                }
+                "clear" => match session.set_prompt_profile(target, None).await {
+                    Ok(()) => msgs.prompt_cleared().to_string(),
+                    Err(e) => msgs.failed(&e.to_string()),
+                },
+                "" | "show" => {
+                    let mut body = match session.prompt_profile(target) {
+                        Some(name) => {
+                            let preview =
+                                match load_profile(&state.cfg, &session.path_policy(target), &name)
+                                {
+                                    Ok(text) => {
+                                        let mut preview: String = text.chars().take(500).collect();
+                                        if preview.len() < text.len() {
+                                            preview.push('…');
+                                        }
+                                        format!("<pre>{}</pre>", escape_html(&preview))
+                                    }
+                                    Err(e) => msgs.warning(&e.to_string()),
+                                };
+                            msgs.prompt_current(&name, &preview)
+                        }
+                        None => msgs.prompt_none().to_string(),
+                    };
+                    body.push_str(&msgs.prompt_available(
+                        &list_profiles(&state.cfg),
+                        &profiles_dir(&state.cfg).display().to_string(),
+                    ));
+                    body
+                }
+                _ => msgs.prompt_usage().to_string(),
+            };
+            send_html_split(&state, target, &reply).await;
+            Ok(())
+        }
+
+        "settings" => handle_settings_command(&state, target, user_id, &arg).await,
+
+        "undo" => handle_undo_command(&state, target, user_id).await,
 
-            send_html_split(&state, chat_id, &lines.join("\n")).await;
+        "sessions" => handle_sessions_command(&state, target).await,
+
+        "title" => handle_title_command(&state, target, &arg).await,
+
+        "vocab" => {
+            let (sub, terms) = arg
+                .trim()
+                .split_once(char::is_whitespace)
+                .map_or((arg.trim(), ""), |(s, t)| (s, t.trim()));
+            let chat_id = target.chat_id.0;
+            let reply = match sub.to_ascii_lowercase().as_str() {
+                "add" if !terms.is_empty() => match state.vocabulary.add(chat_id, terms) {
+                    Ok(added) if added.is_empty() => msgs.vocab_already_known().to_string(),
+                    Ok(added) => msgs.vocab_added(&added.join(", ")),
+                    Err(e) => msgs.failed(&e.to_string()),
+                },
+                "remove" if !terms.is_empty() => match state.vocabulary.remove(chat_id, terms) {
+                    Ok(true) => msgs.vocab_removed(terms),
+                    Ok(false) => msgs.vocab_not_found(terms),
+                    Err(e) => msgs.failed(&e.to_string()),
+                },
+                "" | "list" => {
+                    let list = state.vocabulary.list(chat_id);
+                    if list.is_empty() {
+                        msgs.vocab_empty().to_string()
+                    } else {
+                        msgs.vocab_list(&list, VOCAB_MAX_TERMS)
+                    }
+                }
+                _ => msgs.vocab_usage().to_string(),
+            };
+            send_html_split(&state, target, &reply).await;
             Ok(())
         }
 
         "resume" => {
-            if state.session.is_active().await {
-                send_html_split(
+            if session.is_active().await {
+                send_html_split(&state, target, msgs.session_already_active()).await;
+                return Ok(());
+            }
+            let reply = match session.resume_last(msgs).await {
+                Ok((true, msg)) => msgs.succeeded(&msg),
+                Ok((false, msg)) => msgs.failed(&msg),
+                Err(e) => msgs.failed(&e.to_string()),
+            };
+            send_html_split(&state, target, &reply).await;
+            Ok(())
+        }
+
+        "cron" => {
+            let (sub, name) = arg
+                .trim()
+                .split_once(char::is_whitespace)
+                .map_or((arg.trim(), ""), |(s, n)| (s, n.trim()));
+            let admin = sub.eq_ignore_ascii_case("test") || sub.eq_ignore_ascii_case("reload");
+            if admin && !is_owner(&state, user_id) {
+                let _ = send_html_text(
                     &state,
-                    chat_id,
-                    "Session already active. Use /new to start fresh first.",
+                    target,
+                    msgs.owner_only(&format!("cron {}", sub.to_ascii_lowercase())),
                 )
                 .await;
                 return Ok(());
             }
-            match state.session.resume_last().await {
-                Ok((true, msg)) => {
-                    send_html_split(&state, chat_id, &format!("✅ {}", escape_html(&msg))).await
-                }
-                Ok((false, msg)) => {
-                    send_html_split(&state, chat_id, &format!("❌ {}", escape_html(&msg))).await
+            if sub.eq_ignore_ascii_case("test") {
+                let reply = if name.is_empty() {
+                    msgs.cron_test_usage().to_string()
+                } else {
+                    match state.scheduler.test_job(name, target).await {
+                        Ok(name) => msgs.cron_test_started(&name),
+                        Err(e) => msgs.failed(&e.to_string()),
+                    }
+                };
+                send_html_split(&state, target, &reply).await;
+                return Ok(());
+            }
+            if arg.trim().eq_ignore_ascii_case("reload") {
+                let mut reply = match state.scheduler.reload().await {
+                    Ok(0) => msgs.cron_none_found().to_string(),
+                    Ok(count) => msgs.cron_reloaded(count),
+                    Err(e) => msgs.failed(&e.to_string()),
+                };
+                let errors = state.scheduler.config_errors().await;
+                if !errors.is_empty() {
+                    reply.push_str("\n\n");
+                    reply.push_str(&msgs.cron_skipped(&errors.join("\n")));
                 }
-                Err(e) => {
-                    send_html_split(
-                        &state,
-                        chat_id,
-                        &format!("❌ {}", escape_html(&format!("{e}"))),
-                    )
-                    .await
+                send_html_split(&state, target, &reply).await;
+                return Ok(());
+            }
+
+            let status = state.scheduler.status_html(msgs).await;
+            send_html_split(&state, target, &format!("{status}{}", msgs.cron_hint())).await;
+            Ok(())
+        }
+
+        "remind" => {
+            handle_remind_command(&state, target, user_id, &arg).await;
+            Ok(())
+        }
+
+        "mcp" => {
+            let probe = arg.trim().eq_ignore_ascii_case("probe");
+            let body = mcp_status_html(msgs, &state.cfg, target, probe).await;
+            send_html_split(&state, target, &body).await;
+            Ok(())
+        }
+
+        "health" => {
+            let report = run_health_checks(&state.cfg).await;
+            send_html_split(
+                &state,
+                target,
+                &state.cfg.status_theme.render(&report.to_html()),
+            )
+            .await;
+            Ok(())
+        }
+
+        "perf" => {
+            if !is_owner(&state, user_id) {
+                let _ = send_html_text(&state, target, msgs.owner_only(&cmd)).await;
+                return Ok(());
+            }
+            send_html_split(
+                &state,
+                target,
+                &state.cfg.status_theme.render(&state.metrics.to_html()),
+            )
+            .await;
+            Ok(())
+        }
+
+        "logs" => {
+            if !is_owner(&state, user_id) {
+                let _ = send_html_text(&state, target, msgs.owner_only(&cmd)).await;
+                return Ok(());
+            }
+            handle_logs_command(&state, target, &arg).await;
+            Ok(())
+        }
+
+        "broadcast" => {
+            if !is_owner(&state, user_id) {
+                let _ = send_html_text(&state, target, msgs.owner_only(&cmd)).await;
+                return Ok(());
+            }
+            // `--all` also sends the message to the owner's own chat.
+            let (include_sender, message) = match arg.trim().strip_prefix("--all") {
+                Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+                    (true, rest.trim())
                 }
+                _ => (false, arg.trim()),
             };
+            if message.is_empty() {
+                let _ = send_html_text(&state, target, msgs.broadcast_usage()).await;
+                return Ok(());
+            }
+            let recipients = broadcast_recipients(&state.cfg, user_id, include_sender);
+            let report = broadcast(
+                state.messenger.as_ref(),
+                state.cfg.status_theme,
+                &recipients,
+                message,
+                BROADCAST_SPACING,
+            )
+            .await;
+            let summary = report.summary(state.cfg.status_theme);
+            let event = AuditEvent::broadcast(user_id, &username, message, &recipients, &summary);
+            if let Err(e) = state.audit.write(event) {
+                log_line!("[AUDIT] Failed to write broadcast event: {e}");
+            }
+            let _ = state.messenger.send_html(target, &summary).await;
             Ok(())
         }
 
-        "cron" => {
-            if arg.trim().eq_ignore_ascii_case("reload") {
-                match state.scheduler.reload().await {
-                    Ok(0) => {
-                        send_html_split(&state, chat_id, "⚠️ No schedules found in cron.yaml").await
-                    }
-                    Ok(count) => {
-                        send_html_split(
-                            &state,
-                            chat_id,
-                            &format!(
-                                "🔄 Reloaded {} scheduled job{}",
-                                count,
-                                if count == 1 { "" } else { "s" }
-                            ),
-                        )
-                        .await
-                    }
-                    Err(e) => {
-                        send_html_split(
-                            &state,
-                            chat_id,
-                            &format!("❌ {}", escape_html(&format!("{e}"))),
+        "export" => {
+            let Some((file_name, markdown)) = session.export_transcript().await else {
+                let _ = send_html_text(&state, target, msgs.nothing_to_export()).await;
+                return Ok(());
+            };
+
+            if state.messenger.capabilities().supports_documents {
+                let path = state.cfg.temp_dir.join(&file_name);
+                let written = write_temp_file(&state.cfg.temp_dir, &path, &markdown).await;
+                let sent = match written {
+                    Ok(()) => {
+                        upload_document(
+                            state.messenger.as_ref(),
+                            target,
+                            &path,
+                            Some(msgs.transcript_caption()),
                         )
                         .await
                     }
+                    Err(e) => Err(e.into()),
+                };
+                let _ = tokio::fs::remove_file(&path).await;
+                match sent {
+                    Ok(_) => return Ok(()),
+                    Err(e) => log_line!("[EXPORT] Failed to send transcript as file: {e}"),
+                }
+            }
+
+            send_html_split(
+                &state,
+                target,
+                &format!("<pre>{}</pre>", escape_html(&markdown)),
+            )
+            .await;
+            Ok(())
+        }
+
+        "export_state" => {
+            if !is_owner(&state, user_id) {
+                let _ = send_html_text(&state, target, msgs.owner_only(&cmd)).await;
+                return Ok(());
+            }
+            send_state_export(&state, target, user_id).await;
+            Ok(())
+        }
+
+        "diff" => {
+            let tree = WorkTree::from_config(&state.cfg, session.path_policy(target));
+            let path = (!arg.is_empty()).then_some(arg.as_str());
+            match tree.diff(path).await {
+                Ok(diff) => {
+                    let summary = if diff.is_empty() {
+                        "clean".to_string()
+                    } else {
+                        format!("{} changed", diff.status.lines().count())
+                    };
+                    audit_git(&state, user_id, &username, text, &summary);
+                    send_diff(&state, target, tree.dir(), &diff).await;
                 }
+                Err(e) => {
+                    audit_git(&state, user_id, &username, text, &e.to_string());
+                    let _ = state
+                        .messenger
+                        .send_html(target, &msgs.failed(&e.to_string()))
+                        .await;
+                }
+            }
+            Ok(())
+        }
+
+        "commit" => {
+            if !is_authorized_for(Some(UserId(user_id)), Capability::EditFiles, &state.cfg) {
+                let _ = send_html_text(&state, target, msgs.commit_needs_full_access()).await;
+                return Ok(());
+            }
+            if arg.is_empty() {
+                let _ = send_html_text(&state, target, msgs.commit_usage()).await;
                 return Ok(());
             }
+            let tree = WorkTree::from_config(&state.cfg, session.path_policy(target));
+            let (outcome, reply) = match tree.commit(&arg).await {
+                Ok(Some(hash)) => (format!("committed {hash}"), msgs.committed(&hash, &arg)),
+                Ok(None) => (
+                    "nothing to commit".to_string(),
+                    msgs.nothing_to_commit().to_string(),
+                ),
+                Err(e) => (e.to_string(), msgs.failed(&e.to_string())),
+            };
+            audit_git(&state, user_id, &username, text, &outcome);
+            let _ = state.messenger.send_html(target, &reply).await;
+            Ok(())
+        }
 
-            let status = state.scheduler.status_html().await;
-            let note = "\n\n<i>cron.yaml is auto-monitored for changes.\nYou can also use /cron reload to force reload.</i>";
-            send_html_split(&state, chat_id, &format!("{status}{note}")).await;
+        "grant" | "revoke" => {
+            if !is_owner(&state, user_id) {
+                let _ = send_html_text(&state, target, msgs.owner_only(&cmd)).await;
+                return Ok(());
+            }
+            let dir = arg.trim();
+            if dir.is_empty() {
+                let _ = send_html_text(&state, target, msgs.grant_usage(&cmd)).await;
+                return Ok(());
+            }
+
+            let reply = if cmd == "grant" {
+                match state.grants.grant(target.chat_id.0, dir) {
+                    Ok(g) => {
+                        let ttl = state
+                            .cfg
+                            .grant_ttl
+                            .map(|ttl| format_duration(ttl.as_secs() as i64));
+                        msgs.granted(&g.path.display().to_string(), ttl.as_deref())
+                    }
+                    Err(e) => msgs.failed(&e.to_string()),
+                }
+            } else {
+                match state.grants.revoke(target.chat_id.0, dir) {
+                    Ok(true) => msgs.revoked(dir),
+                    Ok(false) => msgs.no_grant(dir),
+                    Err(e) => msgs.failed(&e.to_string()),
+                }
+            };
+            let _ = state.messenger.send_html(target, &reply).await;
+            Ok(())
+        }
+
+        "limits" => {
+            let body = match state.ledger.summary(Periods::now()) {
+                Ok(usage) => limits_html(
+                    msgs,
+                    state.cfg.status_theme,
+                    &usage,
+                    state.cfg.daily_token_cap,
+                ),
+                Err(e) => msgs.limits_failed(&e.to_string()),
+            };
+            send_html_split(&state, target, &body).await;
             Ok(())
         }
 
         "stats" => {
-            let st = state.session.stats().await;
-            let mut lines: Vec<String> = vec!["📊 <b>Session Statistics</b>\n".to_string()];
+            let st = session.stats().await;
+            let active_model = st.model.as_deref().or(state.cfg.claude_model.as_deref());
+            let pricing = state.cfg.pricing.resolve(active_model);
+            let mut lines: Vec<String> = vec![msgs.stats_title().to_string()];
 
             if let Some(start) = st.session_start_time.as_deref() {
                 if let Ok(dt) = DateTime::parse_from_rfc3339(start) {
                     let dur = (Utc::now() - dt.with_timezone(&Utc)).num_seconds();
-                    lines.push(format!("⏱️ Session duration: {}", format_duration(dur)));
-                    lines.push(format!("🔢 Total queries: {}", st.total_queries));
+                    lines.push(msgs.stats_duration(&format_duration(dur)));
+                    lines.push(msgs.stats_queries(st.total_queries));
+                    if st.compactions > 0 {
+                        lines.push(msgs.stats_compactions(st.compactions));
+                    }
                 }
             } else {
-                lines.push("⚪ No active session".to_string());
+                lines.push(msgs.stats_no_session().to_string());
             }
 
             if st.total_queries > 0 {
@@ -730,77 +1401,76 @@ Working directory: <code>{work_dir}</code>\n\n\
                 let total_cache = st.total_cache_read_tokens + st.total_cache_create_tokens;
                 let total_tokens = total_in + total_out;
 
-                lines.push("\n🧠 <b>Token Usage</b>".to_string());
-                lines.push(format!("   Input: {total_in} tokens"));
-                lines.push(format!("   Output: {total_out} tokens"));
-                if total_cache > 0 {
-                    lines.push(format!("   Cache: {total_cache} tokens"));
-                    lines.push(format!("     └─ Read: {}", st.total_cache_read_tokens));
-                    lines.push(format!("     └─ Create: {}", st.total_cache_create_tokens));
-                }
-                lines.push(format!("   <b>Total: {total_tokens} tokens</b>"));
-
-                let cost_in = (total_in as f64 / 1_000_000.0) * 3.0;
-                let cost_out = (total_out as f64 / 1_000_000.0) * 15.0;
-                let cost_cache_read = (st.total_cache_read_tokens as f64 / 1_000_000.0) * 0.3;
-                let cost_cache_write = (st.total_cache_create_tokens as f64 / 1_000_000.0) * 3.75;
-                let total_cost = cost_in + cost_out + cost_cache_read + cost_cache_write;
-
-                lines.push("\n💰 <b>Estimated Cost</b>".to_string());
-                lines.push(format!("   Input: ${cost_in:.4}"));
-                lines.push(format!("   Output: ${cost_out:.4}"));
+                lines.push(msgs.stats_tokens_title().to_string());
+                lines.push(format!("   {}", msgs.input_tokens(total_in)));
+                lines.push(format!("   {}", msgs.output_tokens(total_out)));
                 if total_cache > 0 {
+                    lines.push(format!("   {}", msgs.stats_cache_tokens(total_cache)));
+                    lines.push(format!(
+                        "     {}",
+                        msgs.stats_cache_read(st.total_cache_read_tokens)
+                    ));
                     lines.push(format!(
-                        "   Cache: ${:.4}",
-                        cost_cache_read + cost_cache_write
+                        "     {}",
+                        msgs.stats_cache_create(st.total_cache_create_tokens)
                     ));
                 }
-                lines.push(format!("   <b>Total: ${total_cost:.4}</b>"));
+                lines.push(format!("   {}", msgs.stats_total_tokens(total_tokens)));
+
+                let cost = pricing.pricing.cost(&TokenUsage {
+                    input_tokens: total_in,
+                    output_tokens: total_out,
+                    cache_read_input_tokens: st.total_cache_read_tokens,
+                    cache_creation_input_tokens: st.total_cache_create_tokens,
+                });
+                let total_cost = cost.total();
+
+                lines.push(msgs.stats_cost_title().to_string());
+                lines.push(format!("   {}", msgs.stats_cost_input(cost.input)));
+                lines.push(format!("   {}", msgs.stats_cost_output(cost.output)));
+                if total_cache > 0 {
+                    lines.push(format!("   {}", msgs.stats_cost_cache(cost.cache())));
+                }
+                lines.push(format!("   {}", msgs.stats_cost_total(total_cost)));
 
                 if st.total_queries > 1 {
                     let avg_in = total_in / st.total_queries;
                     let avg_out = total_out / st.total_queries;
                     let avg_cost = total_cost / st.total_queries as f64;
-                    lines.push("\n📈 <b>Per Query Average</b>".to_string());
-                    lines.push(format!("   Input: {avg_in} tokens"));
-                    lines.push(format!("   Output: {avg_out} tokens"));
-                    lines.push(format!("   Cost: ${avg_cost:.4}"));
+                    lines.push(msgs.stats_average_title().to_string());
+                    lines.push(format!("   {}", msgs.input_tokens(avg_in)));
+                    lines.push(format!("   {}", msgs.output_tokens(avg_out)));
+                    lines.push(format!("   {}", msgs.stats_average_cost(avg_cost)));
                 }
             } else {
-                lines.push("\n📭 No queries in this session yet".to_string());
+                lines.push(msgs.stats_no_queries().to_string());
             }
 
             if let Some(u) = st.last_usage.as_ref() {
-                lines.push("\n🔍 <b>Last Query</b>".to_string());
-                lines.push(format!("   Input: {} tokens", u.input_tokens));
-                lines.push(format!("   Output: {} tokens", u.output_tokens));
-                if u.cache_read_input_tokens > 0 {
-                    lines.push(format!("   Cache read: {}", u.cache_read_input_tokens));
-                }
+                lines.push(msgs.stats_last_query().to_string());
+                lines.extend(last_usage_lines(msgs, u));
             }
 
             let all = state.usage.fetch_all(None).await;
-            lines.extend(format_provider_usage(&all));
-            lines.push("\n<i>Pricing: Claude Sonnet 4 rates</i>".to_string());
+            lines.extend(format_provider_usage(msgs, &all));
+            lines.push(msgs.stats_pricing(
+                &pricing.key,
+                &model_label(msgs, &state.cfg, st.model.as_deref()),
+            ));
 
-            send_html_split(&state, chat_id, &lines.join("\n")).await;
+            send_html_split(&state, target, &lines.join("\n")).await;
             Ok(())
         }
 
         "retry" => {
-            let last = state.session.last_message().await;
+            let last = session.last_message().await;
             let Some(last) = last else {
-                send_html_split(&state, chat_id, "❌ No message to retry.").await;
+                send_html_split(&state, target, msgs.nothing_to_retry()).await;
                 return Ok(());
             };
 
-            if state.session.is_running().await {
-                send_html_split(
-                    &state,
-                    chat_id,
-                    "⏳ A query is already running. Use /stop first.",
-                )
-                .await;
+            if session.is_running().await {
+                send_html_split(&state, target, msgs.query_already_running()).await;
                 return Ok(());
             }
 
@@ -809,46 +1479,116 @@ Working directory: <code>{work_dir}</code>\n\n\
             } else {
                 last.clone()
             };
-            let _ = bot
-                .send_message(msg.chat.id, format!("🔄 Retrying: \"{preview}\""))
-                .await;
+            let _ = send_html_text(&state, target, msgs.retrying(&preview)).await;
 
-            run_text_prompt(
+            run_prompt(
                 PromptContext {
                     bot: bot.clone(),
                     state: state.clone(),
-                    chat_id,
+                    chat_id: target.chat_id.0,
+                    thread_id: target.thread_id,
                     user_id,
                     username,
                 },
                 "RETRY",
                 last,
+                PromptOptions {
+                    record_last_message: true,
+                    rate_action: Some(RateAction::Retry),
+                    plan_only: false,
+                },
+            )
+            .await
+        }
+
+        "t" => {
+            let (name, rest) = arg
+                .trim()
+                .split_once(char::is_whitespace)
+                .map_or((arg.trim(), ""), |(n, r)| (n, r.trim()));
+            if name.is_empty() || name.eq_ignore_ascii_case("list") {
+                let path = state.templates.path().display().to_string();
+                let body = match state.templates.templates() {
+                    Ok(list) if list.is_empty() => msgs.templates_none(&path),
+                    Ok(list) => {
+                        let mut lines = vec![msgs.templates_title(list.len())];
+                        lines.extend(list.iter().map(|t| {
+                            format!(
+                                "{} <code>{}</code> {} {}",
+                                state.cfg.status_theme.bullet,
+                                escape_html(&t.name),
+                                state.cfg.status_theme.dash,
+                                escape_html(&t.preview(60))
+                            )
+                        }));
+                        lines.push(msgs.templates_hint(&path));
+                        lines.join("\n")
+                    }
+                    Err(e) => msgs.failed(&e.to_string()),
+                };
+                send_html_split(&state, target, &body).await;
+                return Ok(());
+            }
+
+            let prompt = match state.templates.get(name) {
+                Ok(Some(template)) => template.render(rest),
+                Ok(None) => {
+                    send_html_split(&state, target, &msgs.template_missing(name)).await;
+                    return Ok(());
+                }
+                Err(e) => Err(e),
+            };
+            let prompt = match prompt {
+                Ok(p) => p,
+                Err(e) => {
+                    send_html_split(&state, target, &msgs.failed(&e.to_string())).await;
+                    return Ok(());
+                }
+            };
+
+            run_text_prompt(
+                PromptContext {
+                    bot: bot.clone(),
+                    state: state.clone(),
+                    chat_id: target.chat_id.0,
+                    thread_id: target.thread_id,
+                    user_id,
+                    username,
+                },
+                "TEMPLATE",
+                prompt,
             )
             .await
         }
 
         "restart" => {
-            let sent = bot
-                .send_message(msg.chat.id, "🔄 Restarting bot...")
-                .await?;
-            // Keep TS-compatible fields: chat_id/message_id/timestamp(ms).
-            let payload = serde_json::json!({
-              "chat_id": chat_id,
-              "message_id": sent.id.0,
-              "timestamp": (std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis() as u64),
-            });
-            let _ = std::fs::write(
-                &state.cfg.restart_file,
-                serde_json::to_string(&payload).unwrap_or_default(),
-            );
+            if !is_owner(&state, user_id) {
+                let _ = send_html_text(&state, target, msgs.owner_only(&cmd)).await;
+                return Ok(());
+            }
+            let restarting = match send_html_text(&state, target, msgs.restarting()).await {
+                Ok(sent) => sent,
+                Err(e) => {
+                    log_line!("Failed to send restart notice: {e}");
+                    return Ok(());
+                }
+            };
+            if let Err(e) =
+                ctb_core::restart::write_restart_marker(session.store().as_ref(), restarting)
+            {
+                log_line!("Failed to write restart marker: {e}");
+            }
+            // Queued cron jobs would otherwise be lost with the process.
+            if let Err(e) = state.scheduler.persist_pending_jobs().await {
+                log_line!("[CRON] Failed to persist queued jobs: {e}");
+            }
 
             tokio::time::sleep(std::time::Duration::from_millis(500)).await;
             std::process::exit(0);
         }
 
         _ => {
-            let msg = format!("Unknown command: /{}", escape_html(&cmd));
-            send_html_split(&state, chat_id, &msg).await;
+            send_html_split(&state, target, &msgs.unknown_command(&cmd)).await;
             Ok(())
         }
     }
@@ -3,25 +3,34 @@ use std::sync::{
     Arc,
 };
 
-use teloxide::{net::Download, prelude::*};
+use teloxide::prelude::*;
 
 use ctb_core::{
     archive_security::{safe_extract_archive, ExtractLimits},
+    log_line,
+    messaging::{chat_action::with_chat_action, types::ChatAction},
+    office::OfficeKind,
+    security::RateAction,
     utils::AuditEvent,
 };
 
 use crate::router::AppState;
 
 use super::{
-    media_group::{BoxFuture, MediaGroupBuffer, MediaGroupConfig},
+    chat_target, check_rate_limit,
+    download::download_to_path,
+    group::prompt_caption,
+    media_group::MediaItem,
     prompt::{run_prompt, PromptContext, PromptOptions},
+    reply::{build_forward_context, with_forward_context},
+    send_html_text, send_text, username_of,
 };
 
 static DOC_COUNTER: AtomicUsize = AtomicUsize::new(1);
-static DOC_BUFFER: std::sync::OnceLock<Arc<MediaGroupBuffer>> = std::sync::OnceLock::new();
 
 const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024; // 10MB
 const MAX_ARCHIVE_CONTENT: usize = 50_000;
+const MAX_DOCUMENT_CHARS: usize = 100_000;
 
 fn text_extensions() -> &'static [&'static str] {
     &[
@@ -81,55 +90,11 @@ fn uniquify_filename(name: &str, ts: u128, n: usize) -> String {
     format!("{base}_{ts}_{n}")
 }
 
-fn doc_buffer() -> &'static Arc<MediaGroupBuffer> {
-    DOC_BUFFER.get_or_init(|| {
-        let cfg = MediaGroupConfig {
-            emoji: "📄",
-            item_label_plural: "documents",
-        };
-
-        let process = std::sync::Arc::new(
-            |ctx: PromptContext, items: Vec<String>, caption: Option<String>| {
-                let fut: BoxFuture = Box::pin(async move {
-                    let docs = extract_documents(&items).await;
-                    if docs.is_empty() {
-                        let _ = ctx
-                            .bot
-                            .send_message(
-                                teloxide::types::ChatId(ctx.chat_id),
-                                "❌ Failed to extract any documents.",
-                            )
-                            .await;
-                        return;
-                    }
-
-                    let prompt = build_documents_prompt(&docs, caption.as_deref());
-                    let _ = run_prompt(
-                        ctx,
-                        "DOCUMENT",
-                        prompt,
-                        PromptOptions {
-                            record_last_message: false,
-                            skip_rate_limit: true,
-                        },
-                    )
-                    .await;
-                });
-                fut
-            },
-        );
-
-        MediaGroupBuffer::new(cfg, process)
-    })
-}
-
 async fn download_document(
     bot: &Bot,
     state: &AppState,
     doc: &teloxide::types::Document,
 ) -> anyhow::Result<String> {
-    let file = bot.get_file(doc.file.id.clone()).await?;
-
     let ts = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
@@ -142,8 +107,7 @@ async fn download_document(
         .unwrap_or_else(|| format!("doc_{ts}_{n}"));
 
     let path = state.cfg.temp_dir.join(file_name);
-    let mut dst = tokio::fs::File::create(&path).await?;
-    bot.download_file(&file.path, &mut dst).await?;
+    download_to_path(bot, &doc.file, &path, MAX_FILE_SIZE).await?;
     Ok(path.to_string_lossy().to_string())
 }
 
@@ -171,10 +135,19 @@ async fn extract_text_file(path: &str) -> Option<String> {
         .await
         .ok()?
         .ok()?;
-    Some(raw.chars().take(100_000).collect::<String>())
+    Some(raw.chars().take(MAX_DOCUMENT_CHARS).collect::<String>())
+}
+
+async fn extract_office(kind: OfficeKind, path: &str) -> String {
+    let path = std::path::PathBuf::from(path);
+    match tokio::task::spawn_blocking(move || kind.extract(&path)).await {
+        Ok(Ok(text)) => text.chars().take(MAX_DOCUMENT_CHARS).collect(),
+        Ok(Err(e)) => format!("[Document parsing failed: {e}]"),
+        Err(_) => "[Document parsing failed]".to_string(),
+    }
 }
 
-async fn extract_documents(paths: &[String]) -> Vec<(String, String)> {
+pub(super) async fn extract_documents(paths: &[String]) -> Vec<(String, String)> {
     let mut out = Vec::new();
     for p in paths {
         let name = p.rsplit('/').next().unwrap_or("document").to_string();
@@ -183,6 +156,11 @@ async fn extract_documents(paths: &[String]) -> Vec<(String, String)> {
             out.push((name, text));
             continue;
         }
+        if let Some(kind) = OfficeKind::detect(&name, None) {
+            let text = extract_office(kind, p).await;
+            out.push((name, text));
+            continue;
+        }
         if let Some(txt) = extract_text_file(p).await {
             out.push((name, txt));
         }
@@ -190,7 +168,7 @@ async fn extract_documents(paths: &[String]) -> Vec<(String, String)> {
     out
 }
 
-fn build_documents_prompt(docs: &[(String, String)], caption: Option<&str>) -> String {
+pub(super) fn build_documents_prompt(docs: &[(String, String)], caption: Option<&str>) -> String {
     if docs.len() == 1 {
         let (name, content) = &docs[0];
         return match caption {
@@ -282,21 +260,20 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
     };
 
     let user_id = user.id.0 as i64;
-    let username = user
-        .username
-        .clone()
-        .unwrap_or_else(|| "unknown".to_string());
+    let username = username_of(user);
     let chat_id = msg.chat.id.0;
+    let target = chat_target(&msg);
+    let theme = state.cfg.status_theme;
 
     // File size gate.
     let size = doc.file.size as u64;
     if size > MAX_FILE_SIZE {
-        let _ = bot
-            .send_message(
-                teloxide::types::ChatId(chat_id),
-                "❌ File too large. Maximum size is 10MB.",
-            )
-            .await;
+        let _ = send_text(
+            &state,
+            target,
+            format!("{} File too large. Maximum size is 10MB.", theme.failed),
+        )
+        .await;
         return Ok(());
     }
 
@@ -308,54 +285,47 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
     let mime = mime.as_deref();
 
     let media_group_id = msg.media_group_id().map(|s| s.to_string());
-    let caption = msg.caption().map(|s| s.to_string());
+    let caption = prompt_caption(&msg, &state);
 
     // Archive files: process immediately (no media group support).
     if is_archive(&file_name) {
         // Rate limit.
-        let mut rl = state.rate_limiter.lock().await;
-        let (ok, retry_after) = rl.check(ctb_core::domain::UserId(user_id));
-        if !ok {
-            let retry = retry_after.unwrap_or_default().as_secs_f64();
-            if let Err(e) = state
-                .audit
-                .write(AuditEvent::rate_limit(user_id, &username, retry))
-            {
-                eprintln!("[AUDIT] Failed to write rate_limit event: {e}");
-            }
-            let _ = bot
-                .send_message(
-                    teloxide::types::ChatId(chat_id),
-                    format!("⏳ Rate limited. Please wait {:.1} seconds.", retry),
-                )
-                .await;
+        if !check_rate_limit(&state, target, user_id, &username, RateAction::Archive).await {
             return Ok(());
         }
 
-        let status = bot
-            .send_message(
-                teloxide::types::ChatId(chat_id),
-                format!(
-                    "📦 Extracting <b>{}</b>...",
-                    ctb_core::formatting::escape_html(&file_name)
-                ),
-            )
-            .parse_mode(teloxide::types::ParseMode::Html)
-            .await
-            .ok();
+        let status = send_html_text(
+            &state,
+            target,
+            format!(
+                "{}Extracting <b>{}</b>...",
+                theme.deco("📦"),
+                ctb_core::formatting::escape_html(&file_name)
+            ),
+        )
+        .await
+        .ok();
 
-        let archive_path = match download_document(&bot, &state, doc).await {
+        let archive_path = match with_chat_action(
+            state.messenger.as_ref(),
+            target,
+            ChatAction::UploadDocument,
+            download_document(&bot, &state, doc),
+        )
+        .await
+        {
             Ok(p) => p,
             Err(e) => {
-                let _ = bot
-                    .send_message(
-                        teloxide::types::ChatId(chat_id),
-                        format!(
-                            "❌ Failed to download archive: {}",
-                            e.to_string().chars().take(100).collect::<String>()
-                        ),
-                    )
-                    .await;
+                let _ = send_text(
+                    &state,
+                    target,
+                    format!(
+                        "{} Failed to download archive: {}",
+                        theme.failed,
+                        e.to_string().chars().take(100).collect::<String>()
+                    ),
+                )
+                .await;
                 return Ok(());
             }
         };
@@ -368,7 +338,7 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
                 .as_millis()
         ));
 
-        let res = tokio::task::spawn_blocking({
+        let extraction = tokio::task::spawn_blocking({
             let archive_path = std::path::PathBuf::from(&archive_path);
             let file_name = file_name.clone();
             let extract_dir = extract_dir.clone();
@@ -380,7 +350,13 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
                     ExtractLimits::default(),
                 )
             }
-        })
+        });
+        let res = with_chat_action(
+            state.messenger.as_ref(),
+            target,
+            ChatAction::UploadDocument,
+            extraction,
+        )
         .await;
 
         match res {
@@ -388,18 +364,13 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
                 let (tree, contents) = extract_archive_content(&extract_dir).await;
 
                 if let Some(st) = &status {
-                    let _ = bot
-                        .edit_message_text(
-                            st.chat.id,
-                            st.id,
-                            format!(
-                                "📦 Extracted <b>{}</b>: {} files",
-                                ctb_core::formatting::escape_html(&file_name),
-                                report.extracted_files.len()
-                            ),
-                        )
-                        .parse_mode(teloxide::types::ParseMode::Html)
-                        .await;
+                    let html = format!(
+                        "{}Extracted <b>{}</b>: {} files",
+                        theme.deco("📦"),
+                        ctb_core::formatting::escape_html(&file_name),
+                        report.extracted_files.len()
+                    );
+                    let _ = state.messenger.edit_html(*st, &html).await;
                 }
 
                 let tree_str = if tree.is_empty() {
@@ -429,11 +400,14 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
           )
                 };
 
+                let prompt = with_forward_context(&msg, prompt);
+
                 let _ = run_prompt(
                     PromptContext {
                         bot: bot.clone(),
                         state: state.clone(),
                         chat_id,
+                        thread_id: target.thread_id,
                         user_id,
                         username: username.clone(),
                     },
@@ -441,7 +415,8 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
                     prompt,
                     PromptOptions {
                         record_last_message: false,
-                        skip_rate_limit: true,
+                        rate_action: None,
+                        plan_only: false,
                     },
                 )
                 .await;
@@ -449,63 +424,72 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
                 let _ = std::fs::remove_dir_all(&extract_dir);
             }
             Ok(Err(e)) => {
-                let _ = bot
-                    .send_message(
-                        teloxide::types::ChatId(chat_id),
-                        format!("❌ Failed to extract archive: {}", e),
-                    )
-                    .await;
+                let _ = send_text(
+                    &state,
+                    target,
+                    format!("{} Failed to extract archive: {}", theme.failed, e),
+                )
+                .await;
             }
             Err(_) => {
-                let _ = bot
-                    .send_message(
-                        teloxide::types::ChatId(chat_id),
-                        "❌ Failed to extract archive.",
-                    )
-                    .await;
+                let _ = send_text(
+                    &state,
+                    target,
+                    format!("{} Failed to extract archive.", theme.failed),
+                )
+                .await;
             }
         }
 
         if let Some(st) = status {
-            let _ = bot.delete_message(st.chat.id, st.id).await;
+            let _ = state.messenger.delete_message(st).await;
         }
 
         if let Err(e) = state.audit.write(AuditEvent::message(
             user_id, &username, "ARCHIVE", &file_name, None,
         )) {
-            eprintln!("[AUDIT] Failed to write message event: {e}");
+            log_line!("[AUDIT] Failed to write message event: {e}");
         }
 
         return Ok(());
     }
 
     // Validate supported types.
-    if !is_pdf(&file_name, mime) && !is_text_file(&file_name, mime) {
-        let _ = bot
-            .send_message(
-                teloxide::types::ChatId(chat_id),
-                format!(
-          "❌ Unsupported file type.\n\nSupported: PDF, archives (.zip,.tar,.tar.gz,.tgz), {}",
+    let office = OfficeKind::detect(&file_name, mime);
+    if !is_pdf(&file_name, mime) && office.is_none() && !is_text_file(&file_name, mime) {
+        let _ = send_text(
+            &state,
+            target,
+            format!(
+          "{} Unsupported file type.\n\nSupported: PDF, Word (.docx), Excel (.xlsx), archives (.zip,.tar,.tar.gz,.tgz), {}", theme.failed,
           text_extensions().join(", ")
         ),
-            )
-            .await;
+        )
+        .await;
         return Ok(());
     }
 
     // Download document.
-    let doc_path = match download_document(&bot, &state, doc).await {
+    let doc_path = match with_chat_action(
+        state.messenger.as_ref(),
+        target,
+        ChatAction::UploadDocument,
+        download_document(&bot, &state, doc),
+    )
+    .await
+    {
         Ok(p) => p,
         Err(e) => {
-            let _ = bot
-                .send_message(
-                    teloxide::types::ChatId(chat_id),
-                    format!(
-                        "❌ Failed to download document: {}",
-                        e.to_string().chars().take(100).collect::<String>()
-                    ),
-                )
-                .await;
+            let _ = send_text(
+                &state,
+                target,
+                format!(
+                    "{} Failed to download document: {}",
+                    theme.failed,
+                    e.to_string().chars().take(100).collect::<String>()
+                ),
+            )
+            .await;
             return Ok(());
         }
     };
@@ -513,39 +497,35 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
     // Single document: process immediately.
     if media_group_id.is_none() {
         // Rate limit.
-        {
-            let mut rl = state.rate_limiter.lock().await;
-            let (ok, retry_after) = rl.check(ctb_core::domain::UserId(user_id));
-            if !ok {
-                let retry = retry_after.unwrap_or_default().as_secs_f64();
-                if let Err(e) = state
-                    .audit
-                    .write(AuditEvent::rate_limit(user_id, &username, retry))
-                {
-                    eprintln!("[AUDIT] Failed to write rate_limit event: {e}");
-                }
-                let _ = bot
-                    .send_message(
-                        teloxide::types::ChatId(chat_id),
-                        format!("⏳ Rate limited. Please wait {:.1} seconds.", retry),
-                    )
-                    .await;
-                return Ok(());
-            }
+        if !check_rate_limit(&state, target, user_id, &username, RateAction::Media).await {
+            return Ok(());
         }
 
-        let content = if is_pdf(&file_name, mime) {
-            extract_pdf(&doc_path).await
-        } else {
-            extract_text_file(&doc_path).await.unwrap_or_default()
+        let extraction = async {
+            if is_pdf(&file_name, mime) {
+                extract_pdf(&doc_path).await
+            } else if let Some(kind) = office {
+                extract_office(kind, &doc_path).await
+            } else {
+                extract_text_file(&doc_path).await.unwrap_or_default()
+            }
         };
+        let content = with_chat_action(
+            state.messenger.as_ref(),
+            target,
+            ChatAction::UploadDocument,
+            extraction,
+        )
+        .await;
 
         let prompt = build_documents_prompt(&[(file_name.clone(), content)], caption.as_deref());
+        let prompt = with_forward_context(&msg, prompt);
         let _ = run_prompt(
             PromptContext {
                 bot,
                 state,
                 chat_id,
+                thread_id: target.thread_id,
                 user_id,
                 username,
             },
@@ -553,7 +533,8 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
             prompt,
             PromptOptions {
                 record_last_message: false,
-                skip_rate_limit: true,
+                rate_action: None,
+                plan_only: false,
             },
         )
         .await;
@@ -563,16 +544,24 @@ pub async fn handle_document(bot: Bot, msg: Message, state: Arc<AppState>) -> Re
 
     // Media group: buffer and process after timeout.
     if let Some(group_id) = media_group_id {
-        let timeout = state.cfg.media_group_timeout;
         let ctx = PromptContext {
             bot,
             state: state.clone(),
             chat_id,
+            thread_id: target.thread_id,
             user_id,
             username,
         };
-        let _ = doc_buffer()
-            .add_to_group(ctx, group_id, doc_path, caption, timeout)
+        let _ = state
+            .media_groups
+            .add_to_group(
+                ctx,
+                group_id,
+                msg.id.0,
+                MediaItem::Document(doc_path),
+                caption,
+                build_forward_context(&msg),
+            )
             .await;
     }
 
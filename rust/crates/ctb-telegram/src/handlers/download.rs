@@ -0,0 +1,349 @@
+//! Downloading Telegram files into `TEMP_DIR`, shared by the media handlers.
+//!
+//! Bytes go to `<name>.part` and are renamed into place only after the whole file arrived and was
+//! fsynced, so a dropped connection never leaves a truncated file for pdftotext, ffmpeg or a
+//! later directory scan to pick up. The declared size and free disk space are checked up front,
+//! the byte cap is enforced again while streaming, and a network failure is retried once from
+//! scratch.
+
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ctb_core::log_line;
+use teloxide::{net::Download, prelude::*, types::FileMeta};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
+
+/// Largest file the Bot API lets bots download.
+pub(crate) const TELEGRAM_DOWNLOAD_LIMIT: u64 = 20 * 1024 * 1024;
+
+/// Free space to leave in `TEMP_DIR` on top of the file itself.
+const FREE_SPACE_MARGIN: u64 = 16 * 1024 * 1024;
+
+/// Retries after a network failure (each starts over from byte 0).
+const NETWORK_RETRIES: usize = 1;
+
+/// Why one download attempt failed.
+#[derive(Debug)]
+enum AttemptError {
+    /// The connection failed; worth another try.
+    Network(io::Error),
+    /// More than the cap arrived.
+    TooLarge(u64),
+    /// Writing or syncing the `.part` file failed.
+    Write(io::Error),
+}
+
+impl std::fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Network(e) => write!(f, "download failed: {e}"),
+            Self::TooLarge(max) => write!(f, "file is larger than {}MB", max / (1024 * 1024)),
+            Self::Write(e) => write!(f, "failed to save file: {e}"),
+        }
+    }
+}
+
+/// Download `file` to `dest`, refusing anything over `max_bytes`.
+pub(crate) async fn download_to_path(
+    bot: &Bot,
+    file: &FileMeta,
+    dest: &Path,
+    max_bytes: u64,
+) -> anyhow::Result<()> {
+    let declared = u64::from(file.size);
+    if declared > max_bytes {
+        anyhow::bail!("{}", AttemptError::TooLarge(max_bytes));
+    }
+    if let Some(dir) = dest.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+        check_free_space(dir, declared.max(1))?;
+    }
+
+    let tg_file = bot.get_file(file.id.clone()).await?;
+    let written = fetch_to_path(
+        dest,
+        max_bytes,
+        || {
+            bot.download_file_stream(&tg_file.path)
+                .map(|chunk| chunk.map_err(io::Error::other))
+        },
+        tokio::fs::File::create,
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    if declared > 0 && written != declared {
+        log_line!(
+            "[DOWNLOAD] {}: got {written} bytes, Telegram declared {declared}",
+            dest.display()
+        );
+    }
+    Ok(())
+}
+
+/// `dest` with `.part` appended to its file name.
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+/// Stream `open_stream()` into a `.part` file made by `create`, then sync and rename it to
+/// `dest`. The `.part` file is removed on every failure; network failures are retried.
+async fn fetch_to_path<S, B, W, F, Fut>(
+    dest: &Path,
+    max_bytes: u64,
+    mut open_stream: impl FnMut() -> S,
+    create: F,
+) -> Result<u64, AttemptError>
+where
+    S: Stream<Item = io::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+    W: AsyncWrite + Unpin,
+    F: Fn(PathBuf) -> Fut,
+    Fut: Future<Output = io::Result<W>>,
+{
+    let part = part_path(dest);
+    let mut attempt = 0;
+    loop {
+        let result = async {
+            let mut out = create(part.clone()).await.map_err(AttemptError::Write)?;
+            let written = write_capped(open_stream(), &mut out, max_bytes).await?;
+            drop(out);
+            sync_and_rename(&part, dest)
+                .await
+                .map_err(AttemptError::Write)?;
+            Ok(written)
+        }
+        .await;
+
+        match result {
+            Ok(written) => return Ok(written),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&part).await;
+                if matches!(e, AttemptError::Network(_)) && attempt < NETWORK_RETRIES {
+                    attempt += 1;
+                    log_line!("[DOWNLOAD] {e}; retrying {}", dest.display());
+                    continue;
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Copy `chunks` into `out`, failing as soon as more than `max_bytes` arrived.
+async fn write_capped<S, B, W>(
+    mut chunks: S,
+    out: &mut W,
+    max_bytes: u64,
+) -> Result<u64, AttemptError>
+where
+    S: Stream<Item = io::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+    W: AsyncWrite + Unpin,
+{
+    let mut written: u64 = 0;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(AttemptError::Network)?;
+        let chunk = chunk.as_ref();
+        written += chunk.len() as u64;
+        if written > max_bytes {
+            return Err(AttemptError::TooLarge(max_bytes));
+        }
+        out.write_all(chunk).await.map_err(AttemptError::Write)?;
+    }
+    out.flush().await.map_err(AttemptError::Write)?;
+    Ok(written)
+}
+
+async fn sync_and_rename(part: &Path, dest: &Path) -> io::Result<()> {
+    tokio::fs::File::open(part).await?.sync_all().await?;
+    tokio::fs::rename(part, dest).await
+}
+
+/// Fail when `dir` has no room for `needed` bytes plus a margin. Unknown free space passes.
+fn check_free_space(dir: &Path, needed: u64) -> anyhow::Result<()> {
+    match available_space(dir) {
+        Some(free) if free < needed.saturating_add(FREE_SPACE_MARGIN) => anyhow::bail!(
+            "not enough disk space in {} ({}MB free)",
+            dir.display(),
+            free / (1024 * 1024)
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn available_space(dir: &Path) -> Option<u64> {
+    fs2::available_space(dir).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+
+    /// Accepts `fail_after` bytes, then fails every write.
+    struct FailingWriter {
+        inner: tokio::fs::File,
+        fail_after: usize,
+    }
+
+    impl AsyncWrite for FailingWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if self.fail_after == 0 {
+                return Poll::Ready(Err(io::Error::other("disk full")));
+            }
+            let n = buf.len().min(self.fail_after);
+            let this = &mut *self;
+            let res = Pin::new(&mut this.inner).poll_write(cx, &buf[..n]);
+            if let Poll::Ready(Ok(done)) = res {
+                this.fail_after -= done;
+            }
+            res
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ctb-download-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn chunks(
+        items: Vec<io::Result<&'static [u8]>>,
+    ) -> impl Stream<Item = io::Result<&'static [u8]>> + Unpin {
+        tokio_stream::iter(items)
+    }
+
+    fn files_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn complete_download_is_renamed_into_place() {
+        let dir = test_dir("ok");
+        let dest = dir.join("report.pdf");
+        let written = fetch_to_path(
+            &dest,
+            100,
+            || chunks(vec![Ok(b"%PDF-"), Ok(b"1.7")]),
+            tokio::fs::File::create,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 8);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"%PDF-1.7");
+        assert_eq!(files_in(&dir), ["report.pdf"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn failing_writer_leaves_no_part_file() {
+        let dir = test_dir("disk");
+        let dest = dir.join("photo.jpg");
+        let err = fetch_to_path(
+            &dest,
+            100,
+            || chunks(vec![Ok(b"abcd"), Ok(b"efgh")]),
+            |p| async move {
+                Ok(FailingWriter {
+                    inner: tokio::fs::File::create(p).await?,
+                    fail_after: 5,
+                })
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AttemptError::Write(_)), "{err}");
+        assert!(files_in(&dir).is_empty(), "{:?}", files_in(&dir));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn overrunning_the_cap_aborts_and_deletes() {
+        let dir = test_dir("cap");
+        let dest = dir.join("big.bin");
+        let err = fetch_to_path(
+            &dest,
+            6,
+            || chunks(vec![Ok(b"1234"), Ok(b"5678")]),
+            tokio::fs::File::create,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AttemptError::TooLarge(6)), "{err}");
+        assert!(files_in(&dir).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn network_failure_is_retried_once_from_scratch() {
+        let dir = test_dir("retry");
+        let dest = dir.join("voice.ogg");
+        let attempts = AtomicUsize::new(0);
+        let written = fetch_to_path(
+            &dest,
+            100,
+            || {
+                let broken = || Err(io::Error::other("connection reset"));
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => chunks(vec![Ok(b"Ogg"), broken()]),
+                    _ => chunks(vec![Ok(b"OggS"), Ok(b"data")]),
+                }
+            },
+            tokio::fs::File::create,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 8);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"OggSdata");
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // A second failure in a row gives up and cleans up.
+        let dest = dir.join("voice2.ogg");
+        let err = fetch_to_path(
+            &dest,
+            100,
+            || chunks(vec![Ok(b"Ogg"), Err(io::Error::other("timeout"))]),
+            tokio::fs::File::create,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AttemptError::Network(_)), "{err}");
+        assert_eq!(files_in(&dir), ["voice.ogg"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn part_name_keeps_the_extension_visible() {
+        assert_eq!(
+            part_path(Path::new("/tmp/doc_1_2.pdf")),
+            PathBuf::from("/tmp/doc_1_2.pdf.part")
+        );
+        assert!(check_free_space(&std::env::temp_dir(), 1).is_ok());
+        assert!(check_free_space(&std::env::temp_dir(), u64::MAX).is_err());
+    }
+}
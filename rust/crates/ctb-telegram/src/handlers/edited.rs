@@ -0,0 +1,153 @@
+//! Edited text messages.
+//!
+//! Telegram delivers edits as separate updates. Only edits of the chat's latest prompt matter:
+//! while that prompt is still running it is stopped and re-run with the new text; once it has
+//! finished we ask before re-running, since the edit may just be a cosmetic fix.
+
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use ctb_core::{
+    domain::{ChatTarget, MessageId, UserId},
+    log_line,
+    messaging::types::{InlineButton, InlineKeyboard},
+    security::is_authorized,
+    utils::strip_interrupt_prefix,
+    CancelReason,
+};
+
+use crate::handlers::prompt::{run_text_prompt, PromptContext};
+use crate::handlers::{
+    chat_target, entity_text, group::strip_bot_mention, reply::with_reply_context, username_of,
+};
+use crate::router::AppState;
+
+pub async fn handle_edited_message(
+    bot: Bot,
+    msg: Message,
+    state: Arc<AppState>,
+) -> ResponseResult<()> {
+    let Some(user) = msg.from() else {
+        return Ok(());
+    };
+    let user_id = user.id.0 as i64;
+    if !is_authorized(Some(UserId(user_id)), &state.cfg.telegram_allowed_users) {
+        return Ok(());
+    }
+    let Some(text) = msg.text() else {
+        return Ok(());
+    };
+    if text.starts_with('/') {
+        return Ok(());
+    }
+    let text = strip_bot_mention(
+        &entity_text(text, msg.entities()),
+        state.bot_username.as_deref(),
+    );
+    let (_, text) = strip_interrupt_prefix(&text);
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    let text = with_reply_context(
+        &msg,
+        state.bot_username.as_deref(),
+        state.cfg.reply_context_max_chars,
+        text,
+    );
+
+    let target = chat_target(&msg);
+    let message_id = MessageId(msg.id.0);
+    let session = state.sessions.for_target(target).await;
+    if !session
+        .update_last_prompt(target, message_id, text.clone())
+        .await
+    {
+        return Ok(());
+    }
+
+    let ctx = PromptContext {
+        bot,
+        state: state.clone(),
+        chat_id: target.chat_id.0,
+        thread_id: target.thread_id,
+        user_id,
+        username: username_of(user),
+    };
+
+    if session.is_running_for(target).await {
+        // Same semantics as a `!` interrupt: no "Query stopped" notice for the old run.
+        let _ = session.stop(target, CancelReason::Interrupt).await;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        session.clear_stop_requested(target).await;
+
+        let _guard = state.chat_locks.lock_chat(target).await;
+        return run_text_prompt(ctx, "EDIT", text).await;
+    }
+
+    let keyboard = InlineKeyboard::new(vec![InlineButton {
+        label: format!("{}Re-run", state.cfg.status_theme.deco("🔁")),
+        callback_data: format!("rerun:{}", message_id.0),
+    }]);
+    if let Err(e) = state
+        .messenger
+        .send_inline_keyboard(
+            target,
+            &format!(
+                "{}Re-run with edited text?",
+                state.cfg.status_theme.deco("✏️")
+            ),
+            keyboard,
+        )
+        .await
+    {
+        log_line!("[EDIT] Failed to offer re-run: {e}");
+    }
+    Ok(())
+}
+
+/// `rerun:{message_id}` button from `handle_edited_message`.
+pub(crate) async fn handle_rerun_callback(
+    bot: Bot,
+    q: &CallbackQuery,
+    state: Arc<AppState>,
+    target: ChatTarget,
+    message_id: &str,
+) -> ResponseResult<()> {
+    let session = state.sessions.for_target(target).await;
+    let prompt = match (message_id.parse::<i32>(), session.last_prompt(target).await) {
+        (Ok(id), Some((latest, text))) if latest == MessageId(id) => text,
+        _ => {
+            let _ = bot
+                .answer_callback_query(q.id.clone())
+                .text("A newer prompt was sent since this edit".to_string())
+                .await;
+            return Ok(());
+        }
+    };
+
+    let _ = bot.answer_callback_query(q.id.clone()).await;
+    if let Some(msg) = &q.message {
+        let _ = bot
+            .edit_message_text(
+                msg.chat.id,
+                msg.id,
+                format!(
+                    "{}Re-running edited prompt",
+                    state.cfg.status_theme.deco("🔁")
+                ),
+            )
+            .await;
+    }
+
+    let ctx = PromptContext {
+        bot,
+        state: state.clone(),
+        chat_id: target.chat_id.0,
+        thread_id: target.thread_id,
+        user_id: q.from.id.0 as i64,
+        username: username_of(&q.from),
+    };
+    let _guard = state.chat_locks.lock_chat(target).await;
+    run_text_prompt(ctx, "EDIT", prompt).await
+}
@@ -0,0 +1,227 @@
+//! Group chat gating (`GROUP_MODE`).
+//!
+//! In `mention` mode the bot only answers group and supergroup messages that address it: an
+//! `@botname` mention, a reply to one of its own messages, or a `/command` that is not addressed
+//! to another bot. Private chats are never gated.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use teloxide::types::{Message, MessageEntityKind};
+
+use ctb_core::{config::GroupMode, utils::strip_interrupt_prefix};
+
+use crate::handlers::entity_text;
+use crate::router::AppState;
+
+/// Album ids admitted by a mention on one item; later items of the same album pass too.
+static ADMITTED_ALBUMS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+const ADMITTED_ALBUMS_MAX: usize = 64;
+
+fn is_group_chat(msg: &Message) -> bool {
+    msg.chat.is_group() || msg.chat.is_supergroup()
+}
+
+/// Whether `msg` should be handled at all under the configured group mode.
+pub(crate) fn should_handle(msg: &Message, state: &AppState) -> bool {
+    if !is_group_chat(msg) {
+        return true;
+    }
+    match state.cfg.group_mode {
+        GroupMode::All => true,
+        GroupMode::Off => false,
+        GroupMode::Mention => {
+            let username = state.bot_username.as_deref();
+            let album = msg.media_group_id();
+            if album.is_some_and(album_admitted) {
+                return true;
+            }
+            let addressed = is_addressed(msg, username);
+            if addressed {
+                if let Some(album) = album {
+                    admit_album(album);
+                }
+            }
+            addressed
+        }
+    }
+}
+
+fn is_addressed(msg: &Message, username: Option<&str>) -> bool {
+    let text = msg.text().or(msg.caption()).unwrap_or_default();
+    if text.starts_with('/') {
+        return command_is_for_bot(text, username);
+    }
+    let Some(username) = username else {
+        return false;
+    };
+    mentions_bot(msg, username) || replies_to_bot(msg, username)
+}
+
+fn mentions_bot(msg: &Message, username: &str) -> bool {
+    let entities = msg
+        .parse_entities()
+        .or_else(|| msg.parse_caption_entities())
+        .unwrap_or_default();
+    entities.iter().any(|e| match e.kind() {
+        MessageEntityKind::Mention => e
+            .text()
+            .strip_prefix('@')
+            .is_some_and(|name| name.eq_ignore_ascii_case(username)),
+        MessageEntityKind::TextMention { user } => user.is_bot && same_username(user, username),
+        _ => false,
+    })
+}
+
+fn replies_to_bot(msg: &Message, username: &str) -> bool {
+    msg.reply_to_message()
+        .and_then(Message::from)
+        .is_some_and(|u| u.is_bot && same_username(u, username))
+}
+
+pub(crate) fn same_username(user: &teloxide::types::User, username: &str) -> bool {
+    user.username
+        .as_deref()
+        .is_some_and(|n| n.eq_ignore_ascii_case(username))
+}
+
+fn album_admitted(album: &str) -> bool {
+    let albums = ADMITTED_ALBUMS.lock().unwrap_or_else(|e| e.into_inner());
+    albums.iter().any(|a| a == album)
+}
+
+fn admit_album(album: &str) {
+    let mut albums = ADMITTED_ALBUMS.lock().unwrap_or_else(|e| e.into_inner());
+    if albums.len() >= ADMITTED_ALBUMS_MAX {
+        albums.pop_front();
+    }
+    albums.push_back(album.to_string());
+}
+
+/// `/cmd` and `/cmd@us` are ours; `/cmd@other_bot` is not. Unknown username accepts any suffix.
+pub(crate) fn command_is_for_bot(text: &str, username: Option<&str>) -> bool {
+    let command = text.split_whitespace().next().unwrap_or_default();
+    match (command.split_once('@'), username) {
+        (Some((_, addressee)), Some(username)) => addressee.eq_ignore_ascii_case(username),
+        _ => true,
+    }
+}
+
+/// Remove `@username` mentions (and a directly following `,` or `:`) from a prompt.
+pub(crate) fn strip_bot_mention(text: &str, username: Option<&str>) -> String {
+    let Some(username) = username.filter(|u| !u.is_empty()) else {
+        return text.to_string();
+    };
+    let needle = format!("@{}", username.to_ascii_lowercase());
+    // Usernames are ASCII, so lowercasing keeps byte offsets aligned with `text`.
+    let lower = text.to_ascii_lowercase();
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    while let Some(found) = lower[pos..].find(&needle) {
+        let start = pos + found;
+        let end = start + needle.len();
+        let standalone = !text[..start].chars().next_back().is_some_and(is_word)
+            && !text[end..].chars().next().is_some_and(is_word);
+        if !standalone {
+            out.push_str(&text[pos..end]);
+            pos = end;
+            continue;
+        }
+        out.push_str(&text[pos..start]);
+        let rest = &text[end..];
+        let rest = rest.strip_prefix([',', ':']).unwrap_or(rest);
+        let trimmed = rest.trim_start_matches([' ', '\t']);
+        // Keep one separating space between the words around a mid-sentence mention.
+        if !out.is_empty() && !out.ends_with(char::is_whitespace) && !trimmed.is_empty() {
+            out.push(' ');
+        }
+        pos = text.len() - trimmed.len();
+    }
+    out.push_str(&text[pos..]);
+    out.trim().to_string()
+}
+
+/// Message caption with its entities as markdown and any bot mention or `!` interrupt prefix
+/// removed; `None` if nothing else remains.
+pub(crate) fn prompt_caption(msg: &Message, state: &AppState) -> Option<String> {
+    caption_text(msg, state.bot_username.as_deref())
+}
+
+fn caption_text(msg: &Message, bot_username: Option<&str>) -> Option<String> {
+    msg.caption()
+        .map(|c| entity_text(c, msg.caption_entities()))
+        .map(|c| strip_interrupt_prefix(&strip_bot_mention(&c, bot_username)).1)
+        .filter(|c| !c.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_mentions_anywhere() {
+        let bot = Some("MyBot");
+        assert_eq!(strip_bot_mention("@mybot what's up?", bot), "what's up?");
+        assert_eq!(
+            strip_bot_mention("@MyBot: fix the tests", bot),
+            "fix the tests"
+        );
+        assert_eq!(
+            strip_bot_mention("hey @mybot, look at this", bot),
+            "hey look at this"
+        );
+        assert_eq!(
+            strip_bot_mention("summarise this @MYBOT", bot),
+            "summarise this"
+        );
+        assert_eq!(
+            strip_bot_mention("line one\n@mybot line two", bot),
+            "line one\nline two"
+        );
+        assert_eq!(strip_bot_mention("@mybot", bot), "");
+    }
+
+    #[test]
+    fn keeps_other_handles_and_addresses() {
+        let bot = Some("mybot");
+        assert_eq!(strip_bot_mention("ask @mybot_dev", bot), "ask @mybot_dev");
+        assert_eq!(
+            strip_bot_mention("mail me@mybot.dev", bot),
+            "mail me@mybot.dev"
+        );
+        assert_eq!(strip_bot_mention("ping @mybot", None), "ping @mybot");
+    }
+
+    #[test]
+    fn captions_lose_mention_and_interrupt_prefix() {
+        let photo = |caption: &str| -> Message {
+            serde_json::from_value(serde_json::json!({
+                "message_id": 1,
+                "date": 1_700_000_000,
+                "chat": {"id": 42, "type": "private", "first_name": "Ann"},
+                "photo": [{"file_id": "f", "file_unique_id": "u", "width": 1, "height": 1}],
+                "caption": caption
+            }))
+            .unwrap()
+        };
+        let bot = Some("mybot");
+        assert_eq!(
+            caption_text(&photo("@mybot !stop and look at this instead"), bot).as_deref(),
+            Some("stop and look at this instead")
+        );
+        assert_eq!(
+            caption_text(&photo("look! now"), bot).as_deref(),
+            Some("look! now")
+        );
+        assert_eq!(caption_text(&photo("!"), bot), None);
+    }
+
+    #[test]
+    fn commands_addressed_to_other_bots_are_ignored() {
+        assert!(command_is_for_bot("/status", Some("mybot")));
+        assert!(command_is_for_bot("/status@MyBot now", Some("mybot")));
+        assert!(!command_is_for_bot("/status@other_bot", Some("mybot")));
+        assert!(command_is_for_bot("/status@other_bot", None));
+    }
+}
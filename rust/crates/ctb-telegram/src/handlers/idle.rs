@@ -0,0 +1,179 @@
+//! Idle session expiry (`SESSION_IDLE_EXPIRY`).
+//!
+//! In `ask` mode the first prompt after the idle timeout is held back and the chat gets
+//! "continue" / "start fresh" buttons (`idle:continue`, `idle:fresh`); the tap runs the held
+//! prompt against the chosen session.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use teloxide::prelude::*;
+
+use ctb_core::{
+    config::IdleExpiry,
+    domain::ChatTarget,
+    formatting::format_idle,
+    log_line,
+    messaging::types::{InlineButton, InlineKeyboard},
+    session::ClaudeSession,
+    theme::StatusTheme,
+};
+
+use crate::handlers::prompt::{run_prompt, PromptContext, PromptOptions};
+use crate::handlers::username_of;
+use crate::router::AppState;
+
+pub(crate) const IDLE_CALLBACK_PREFIX: &str = "idle:";
+
+/// A prompt waiting for the "continue" / "start fresh" choice.
+struct HeldPrompt {
+    message_type: String,
+    text: String,
+    opts: PromptOptions,
+}
+
+/// Prompts held back by `ask` mode, one per chat (a newer prompt replaces the older one).
+#[derive(Default)]
+pub struct IdlePrompts {
+    inner: Mutex<HashMap<ChatTarget, HeldPrompt>>,
+}
+
+impl IdlePrompts {
+    fn hold(&self, target: ChatTarget, prompt: HeldPrompt) {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        map.insert(target, prompt);
+    }
+
+    fn take(&self, target: ChatTarget) -> Option<HeldPrompt> {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        map.remove(&target)
+    }
+}
+
+/// Apply the idle policy before a prompt runs. Returns `true` when the prompt was held back
+/// for the user's choice and must not run now.
+pub(crate) async fn check_idle(
+    state: &AppState,
+    session: &ClaudeSession,
+    target: ChatTarget,
+    message_type: &str,
+    text: &str,
+    opts: PromptOptions,
+) -> bool {
+    let Some(idle) = session.expired_idle().await else {
+        return false;
+    };
+    let theme = state.cfg.status_theme;
+    match state.cfg.session_idle_expiry {
+        IdleExpiry::Off => false,
+        IdleExpiry::New => {
+            let _ = session.kill().await;
+            let notice = format!(
+                "🕑 Previous session was idle for {} — started a new one. /resume brings it back.",
+                format_idle(idle)
+            );
+            let _ = state
+                .messenger
+                .send_html(target, &theme.render(&notice))
+                .await;
+            false
+        }
+        IdleExpiry::Ask => {
+            state.idle_prompts.hold(
+                target,
+                HeldPrompt {
+                    message_type: message_type.to_string(),
+                    text: text.to_string(),
+                    opts,
+                },
+            );
+            if let Err(e) = state
+                .messenger
+                .send_inline_keyboard(target, &ask_text(theme, idle), ask_keyboard(theme))
+                .await
+            {
+                log_line!("[IDLE] Failed to send idle prompt: {e}");
+            }
+            true
+        }
+    }
+}
+
+fn ask_text(theme: &StatusTheme, idle: Duration) -> String {
+    format!(
+        "{} The previous session was last used {} ago. Continue it or start fresh?",
+        theme.clock,
+        format_idle(idle)
+    )
+}
+
+fn ask_keyboard(theme: &StatusTheme) -> InlineKeyboard {
+    InlineKeyboard::new(vec![
+        InlineButton {
+            label: theme.render("▶️ Continue").into_owned(),
+            callback_data: format!("{IDLE_CALLBACK_PREFIX}continue"),
+        },
+        InlineButton {
+            label: theme.render("🆕 Start fresh").into_owned(),
+            callback_data: format!("{IDLE_CALLBACK_PREFIX}fresh"),
+        },
+    ])
+}
+
+/// `idle:` buttons from [`check_idle`].
+pub(crate) async fn handle_idle_callback(
+    bot: Bot,
+    q: &CallbackQuery,
+    state: Arc<AppState>,
+    target: ChatTarget,
+    action: &str,
+) -> ResponseResult<()> {
+    let Some(held) = state.idle_prompts.take(target) else {
+        let _ = bot
+            .answer_callback_query(q.id.clone())
+            .text("This prompt is no longer pending".to_string())
+            .await;
+        return Ok(());
+    };
+
+    let _guard = state.chat_locks.lock_chat(target).await;
+    let session = state.sessions.for_target(target).await;
+    let status = match action {
+        "continue" => {
+            session.touch_activity().await;
+            "▶️ Continuing the previous session"
+        }
+        "fresh" => {
+            let _ = session.kill().await;
+            "🆕 Started a new session. /resume brings the previous one back."
+        }
+        _ => {
+            state.idle_prompts.hold(target, held);
+            let _ = bot
+                .answer_callback_query(q.id.clone())
+                .text("Invalid callback data".to_string())
+                .await;
+            return Ok(());
+        }
+    };
+    let _ = bot.answer_callback_query(q.id.clone()).await;
+    if let Some(msg) = &q.message {
+        let status = state.cfg.status_theme.render(status);
+        let _ = bot.edit_message_text(msg.chat.id, msg.id, status).await;
+    }
+
+    let ctx = PromptContext {
+        bot,
+        state: state.clone(),
+        chat_id: target.chat_id.0,
+        thread_id: target.thread_id,
+        user_id: q.from.id.0 as i64,
+        username: username_of(&q.from),
+    };
+    let opts = PromptOptions {
+        rate_action: None,
+        ..held.opts
+    };
+    run_prompt(ctx, &held.message_type, held.text, opts).await
+}
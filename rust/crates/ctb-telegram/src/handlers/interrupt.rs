@@ -0,0 +1,92 @@
+//! `!` interrupts: a message whose text or caption starts with `!` stops the chat's running query
+//! and runs next instead of waiting for the chat lock.
+
+use teloxide::types::Message;
+use tokio::sync::OwnedMutexGuard;
+
+use ctb_core::{domain::ChatTarget, CancelReason};
+
+use crate::handlers::group::strip_bot_mention;
+use crate::router::AppState;
+
+/// Whether the message text, or a media caption, starts with `!` (after any bot mention).
+pub(crate) fn is_interrupt(msg: &Message, bot_username: Option<&str>) -> bool {
+    msg.text()
+        .or_else(|| msg.caption())
+        .is_some_and(|t| strip_bot_mention(t, bot_username).starts_with('!'))
+}
+
+/// Stop the query running for `target`'s session, without the "Query stopped" notice, so an
+/// interrupting message can run right away.
+pub(crate) async fn stop_running(state: &AppState, target: ChatTarget) {
+    let session = state.sessions.for_target(target).await;
+    if session.is_running().await {
+        let _ = session.stop(target, CancelReason::Interrupt).await;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        session.clear_stop_requested(target).await;
+    }
+}
+
+/// Take `target`'s turn: an interrupt stops the running query and skips the queue (`None`);
+/// anything else waits for the chat lock.
+pub(crate) async fn begin_turn(
+    state: &AppState,
+    target: ChatTarget,
+    interrupt: bool,
+) -> Option<OwnedMutexGuard<()>> {
+    if interrupt {
+        stop_running(state, target).await;
+        return None;
+    }
+    Some(state.chat_locks.lock_chat(target).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(body: serde_json::Value) -> Message {
+        let mut m = json!({
+            "message_id": 1,
+            "date": 1_700_000_000,
+            "chat": {"id": 42, "type": "private", "first_name": "Ann"},
+        });
+        m.as_object_mut()
+            .unwrap()
+            .extend(body.as_object().unwrap().clone());
+        serde_json::from_value(m).unwrap()
+    }
+
+    #[test]
+    fn text_and_captions_interrupt_with_a_leading_bang() {
+        let bot = Some("ctb_bot");
+        let photo = json!([{"file_id": "f", "file_unique_id": "u", "width": 1, "height": 1}]);
+        let voice = json!({
+            "file_id": "v",
+            "file_unique_id": "u",
+            "file_size": 10,
+            "duration": 3,
+            "mime_type": "audio/ogg"
+        });
+
+        for interrupting in [
+            json!({"text": "!stop"}),
+            json!({"text": "@ctb_bot !stop"}),
+            json!({"photo": photo, "caption": "!stop and look at this instead"}),
+            json!({"voice": voice, "caption": "! urgent"}),
+        ] {
+            assert!(
+                is_interrupt(&message(interrupting.clone()), bot),
+                "{interrupting}"
+            );
+        }
+        for queued in [
+            json!({"text": "hi!"}),
+            json!({"photo": photo, "caption": "look! now"}),
+            json!({"voice": voice}),
+        ] {
+            assert!(!is_interrupt(&message(queued.clone()), bot), "{queued}");
+        }
+    }
+}
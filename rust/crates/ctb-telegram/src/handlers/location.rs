@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use ctb_core::location::{contact_prompt, location_prompt};
+use ctb_core::{log_line, security::RateAction};
+
+use crate::router::AppState;
+
+use super::prompt::{run_prompt, PromptContext, PromptOptions};
+use super::{chat_target, username_of};
+
+fn prompt_context(bot: Bot, msg: &Message, state: Arc<AppState>) -> Option<PromptContext> {
+    let user = msg.from()?;
+    let target = chat_target(msg);
+    Some(PromptContext {
+        bot,
+        state,
+        chat_id: target.chat_id.0,
+        thread_id: target.thread_id,
+        user_id: user.id.0 as i64,
+        username: username_of(user),
+    })
+}
+
+const OPTIONS: PromptOptions = PromptOptions {
+    record_last_message: true,
+    rate_action: Some(RateAction::Text),
+    plan_only: false,
+};
+
+pub async fn handle_location(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
+    let Some(location) = msg.location() else {
+        return Ok(());
+    };
+    let Some(ctx) = prompt_context(bot, &msg, state.clone()) else {
+        return Ok(());
+    };
+
+    // Geocoding is best-effort: coordinates alone are still useful context.
+    let place = match state
+        .geocoder
+        .reverse(location.latitude, location.longitude)
+        .await
+    {
+        Ok(place) => place,
+        Err(e) => {
+            log_line!("[LOCATION] Reverse geocoding failed: {e}");
+            None
+        }
+    };
+    let prompt = location_prompt(
+        location.latitude,
+        location.longitude,
+        location.horizontal_accuracy,
+        place.as_deref(),
+    );
+    run_prompt(ctx, "LOCATION", prompt, OPTIONS).await
+}
+
+pub async fn handle_contact(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
+    let Some(contact) = msg.contact() else {
+        return Ok(());
+    };
+    let Some(ctx) = prompt_context(bot, &msg, state) else {
+        return Ok(());
+    };
+
+    let prompt = contact_prompt(
+        &contact.first_name,
+        contact.last_name.as_deref(),
+        &contact.phone_number,
+        contact.user_id.map(|id| id.0),
+    );
+    run_prompt(ctx, "CONTACT", prompt, OPTIONS).await
+}
@@ -0,0 +1,164 @@
+//! `/logs`: tail of the process log for remote debugging (owner only).
+//!
+//! `/logs [n] [level]` dumps the newest lines; `/logs follow` forwards new warn/error lines for
+//! a minute, batched, until `/logs stop`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ctb_core::{
+    domain::ChatTarget,
+    formatting::escape_html,
+    logging::{self, LogLevel, LogLine},
+    security::scrub_secrets,
+};
+
+use super::commands::send_html_split;
+use crate::router::AppState;
+
+const DEFAULT_TAIL_LINES: usize = 50;
+const FOLLOW_FOR: Duration = Duration::from_secs(60);
+const FOLLOW_BATCH_EVERY: Duration = Duration::from_secs(5);
+
+/// Running `/logs follow` tasks, one per chat.
+#[derive(Default)]
+pub struct LogFollows {
+    inner: Mutex<HashMap<ChatTarget, tokio::task::AbortHandle>>,
+}
+
+impl LogFollows {
+    /// Track `task` for `target`, cancelling an earlier follow there.
+    fn start(&self, target: ChatTarget, task: tokio::task::AbortHandle) {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(old) = map.insert(target, task) {
+            old.abort();
+        }
+    }
+
+    /// Cancel the follow in `target`; `false` if none was running.
+    fn stop(&self, target: ChatTarget) -> bool {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        map.remove(&target).is_some_and(|task| {
+            let running = !task.is_finished();
+            task.abort();
+            running
+        })
+    }
+}
+
+/// `<pre>` block of `lines` with secrets redacted.
+fn lines_html(state: &AppState, lines: &[LogLine]) -> String {
+    let text: Vec<String> = lines.iter().map(LogLine::display).collect();
+    let text = scrub_secrets(&text.join("\n"), &state.cfg);
+    format!("<pre>{}</pre>", escape_html(&text))
+}
+
+/// `[n] [level]` in either order.
+fn parse_tail_args(arg: &str) -> Option<(usize, LogLevel)> {
+    let mut n = DEFAULT_TAIL_LINES;
+    let mut level = LogLevel::Info;
+    for word in arg.split_whitespace() {
+        if let Ok(count) = word.parse::<usize>() {
+            n = count.max(1);
+        } else {
+            level = LogLevel::parse(word)?;
+        }
+    }
+    Some((n, level))
+}
+
+pub(crate) async fn handle_logs_command(state: &Arc<AppState>, target: ChatTarget, arg: &str) {
+    let theme = state.cfg.status_theme;
+    let Some(buffer) = logging::buffer() else {
+        let html = theme.render("📭 Log capture is off (LOG_BUFFER_LINES=0).");
+        send_html_split(state, target, &html).await;
+        return;
+    };
+
+    match arg.trim().to_ascii_lowercase().as_str() {
+        "follow" => {
+            let task = tokio::spawn(follow(state.clone(), target)).abort_handle();
+            state.log_follows.start(target, task);
+            let html = format!(
+                "{} Following warn/error lines for {}s. /logs stop ends it.",
+                theme.viewing,
+                FOLLOW_FOR.as_secs()
+            );
+            send_html_split(state, target, &html).await;
+        }
+        "stop" => {
+            let html = if state.log_follows.stop(target) {
+                "⏹ Stopped following logs."
+            } else {
+                "Not following logs."
+            };
+            send_html_split(state, target, &theme.render(html)).await;
+        }
+        arg => {
+            let Some((n, level)) = parse_tail_args(arg) else {
+                send_html_split(state, target, state.messages(target).logs_usage()).await;
+                return;
+            };
+            let lines = buffer.tail(n, level);
+            if lines.is_empty() {
+                send_html_split(state, target, &theme.render("📭 No matching log lines.")).await;
+                return;
+            }
+            let html = format!(
+                "{}<b>Last {} log lines</b> ({}+, buffer {})\n{}",
+                theme.deco("📜"),
+                lines.len(),
+                level.as_str(),
+                buffer.capacity(),
+                lines_html(state, &lines)
+            );
+            send_html_split(state, target, &html).await;
+        }
+    }
+}
+
+/// Send new warn/error lines every few seconds until [`FOLLOW_FOR`] has passed.
+async fn follow(state: Arc<AppState>, target: ChatTarget) {
+    let Some(buffer) = logging::buffer() else {
+        return;
+    };
+    let mut cursor = buffer.next_seq();
+    let deadline = tokio::time::Instant::now() + FOLLOW_FOR;
+    let mut tick = tokio::time::interval(FOLLOW_BATCH_EVERY);
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let (lines, next) = buffer.since(cursor, LogLevel::Warn);
+        cursor = next;
+        if !lines.is_empty() {
+            send_html_split(&state, target, &lines_html(&state, &lines)).await;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            break;
+        }
+    }
+    let html = state.cfg.status_theme.render("⏹ /logs follow ended.");
+    send_html_split(&state, target, &html).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tail_args_take_count_and_level_in_any_order() {
+        assert_eq!(
+            parse_tail_args(""),
+            Some((DEFAULT_TAIL_LINES, LogLevel::Info))
+        );
+        assert_eq!(parse_tail_args("20"), Some((20, LogLevel::Info)));
+        assert_eq!(parse_tail_args("error 5"), Some((5, LogLevel::Error)));
+        assert_eq!(
+            parse_tail_args("warn"),
+            Some((DEFAULT_TAIL_LINES, LogLevel::Warn))
+        );
+        assert_eq!(parse_tail_args("0"), Some((1, LogLevel::Info)));
+        assert_eq!(parse_tail_args("verbose"), None);
+    }
+}
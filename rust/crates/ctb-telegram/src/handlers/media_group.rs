@@ -1,181 +1,526 @@
-use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+//! Album buffering: photos and documents sharing a `media_group_id` become one prompt.
+//!
+//! Telegram delivers each album item as its own message. Items are collected per group id until
+//! no new item has arrived for `MEDIA_GROUP_TIMEOUT`, then the whole group is sent as a single
+//! prompt, so a mixed album of screenshots and PDFs is one turn rather than two competing ones.
+//!
+//! The album caption rides on only one item, and not necessarily the first one delivered, so
+//! captions are gathered from every item and an album still missing one waits an extra
+//! [`CAPTION_GRACE`] before flushing.
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
 
 use teloxide::prelude::*;
 use tokio_util::sync::CancellationToken;
 
-use ctb_core::{domain::ChatId, utils::AuditEvent};
+use ctb_core::{
+    domain::ChatTarget,
+    messaging::{chat_action::with_chat_action, types::ChatAction},
+    security::RateAction,
+};
 
 use crate::router::AppState;
 
-use super::prompt::PromptContext;
+use super::{
+    check_rate_limit,
+    document::{build_documents_prompt, extract_documents},
+    photo::{build_photo_prompt, with_photo_ocr},
+    prompt::{run_prompt, PromptContext, PromptOptions},
+    send_text,
+};
 
-pub struct MediaGroupConfig {
-    pub emoji: &'static str,
-    pub item_label_plural: &'static str,
+/// One downloaded album item.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MediaItem {
+    Photo(String),
+    Document(String),
 }
 
-pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
-type ProcessFn = Arc<dyn Fn(PromptContext, Vec<String>, Option<String>) -> BoxFuture + Send + Sync>;
+impl MediaItem {
+    fn emoji(&self) -> &'static str {
+        match self {
+            MediaItem::Photo(_) => "📷",
+            MediaItem::Document(_) => "📄",
+        }
+    }
+}
 
-struct PendingGroup {
-    items: Vec<String>,
+/// Extra wait before flushing a multi-item album that has not delivered its caption yet.
+const CAPTION_GRACE: Duration = Duration::from_millis(500);
+
+/// Items and caption gathered so far for one album.
+#[derive(Debug, Default)]
+struct Album {
+    items: Vec<MediaItem>,
+    message_ids: HashSet<i32>,
     caption: Option<String>,
+    /// Provenance header of the first forwarded item.
+    forward: Option<String>,
+}
+
+impl Album {
+    /// Records one delivered album message; returns `false` when `message_id` was already seen
+    /// (a redelivered update), leaving the album untouched.
+    fn push(&mut self, message_id: i32, item: MediaItem, caption: Option<String>) -> bool {
+        if !self.message_ids.insert(message_id) {
+            return false;
+        }
+        self.items.push(item);
+        if let Some(c) = caption.filter(|c| !c.trim().is_empty()) {
+            let longer = self
+                .caption
+                .as_ref()
+                .is_none_or(|cur| c.chars().count() > cur.chars().count());
+            if longer {
+                self.caption = Some(c);
+            }
+        }
+        true
+    }
+
+    /// How long to wait for the next item before flushing.
+    fn flush_delay(&self, timeout: Duration) -> Duration {
+        if self.caption.is_none() && self.items.len() > 1 {
+            timeout + CAPTION_GRACE
+        } else {
+            timeout
+        }
+    }
+}
+
+struct PendingGroup {
+    album: Album,
     user_id: i64,
     username: String,
-    chat_id: i64,
+    target: ChatTarget,
     status_msg: ctb_core::domain::MessageRef,
     cancel: CancellationToken,
 }
 
+/// Pending albums keyed by `media_group_id`; owned by [`AppState`].
+#[derive(Default)]
 pub struct MediaGroupBuffer {
-    cfg: MediaGroupConfig,
-    process: ProcessFn,
     pending: tokio::sync::Mutex<HashMap<String, PendingGroup>>,
 }
 
 impl MediaGroupBuffer {
-    pub fn new(cfg: MediaGroupConfig, process: ProcessFn) -> Arc<Self> {
-        Arc::new(Self {
-            cfg,
-            process,
-            pending: tokio::sync::Mutex::new(HashMap::new()),
-        })
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
     }
 
+    /// Add one album item; `forward` is its provenance header if it was forwarded.
     pub async fn add_to_group(
         self: &Arc<Self>,
         ctx: PromptContext,
         media_group_id: String,
-        item_path: String,
+        message_id: i32,
+        item: MediaItem,
         caption: Option<String>,
-        timeout: Duration,
+        forward: Option<String>,
     ) -> bool {
+        let target = ctx.target();
+        let timeout = ctx.state.cfg.media_group_timeout;
         let PromptContext {
             bot,
             state,
-            chat_id,
             user_id,
             username,
+            ..
         } = ctx;
 
         let mut map = self.pending.lock().await;
         if !map.contains_key(&media_group_id) {
             // Rate limit on first item only (parity with TS).
-            {
-                let mut rl = state.rate_limiter.lock().await;
-                let (ok, retry_after) = rl.check(ctb_core::domain::UserId(user_id));
-                if !ok {
-                    let retry = retry_after.unwrap_or_default().as_secs_f64();
-                    if let Err(e) = state
-                        .audit
-                        .write(AuditEvent::rate_limit(user_id, &username, retry))
-                    {
-                        eprintln!("[AUDIT] Failed to write rate_limit event: {e}");
-                    }
-                    let _ = bot
-                        .send_message(
-                            teloxide::types::ChatId(chat_id),
-                            format!("⏳ Rate limited. Please wait {:.1} seconds.", retry),
-                        )
-                        .await;
-                    return false;
-                }
+            if !check_rate_limit(&state, target, user_id, &username, RateAction::Media).await {
+                return false;
             }
 
             let status = format!(
-                "{} Receiving {}...",
-                self.cfg.emoji, self.cfg.item_label_plural
+                "{}Receiving album...",
+                state.cfg.status_theme.deco(item.emoji())
             );
-            let status_msg = match state.messenger.send_html(ChatId(chat_id), &status).await {
+            let status_msg = match state.messenger.send_html(target, &status).await {
                 Ok(m) => m,
                 Err(_) => ctb_core::domain::MessageRef {
-                    chat_id: ChatId(chat_id),
+                    chat_id: target.chat_id,
                     message_id: ctb_core::domain::MessageId(0),
                 },
             };
 
+            let mut album = Album::default();
+            album.push(message_id, item, caption);
+            album.forward = forward;
+            let delay = album.flush_delay(timeout);
             let cancel = CancellationToken::new();
             map.insert(
                 media_group_id.clone(),
                 PendingGroup {
-                    items: vec![item_path],
-                    caption,
+                    album,
                     user_id,
                     username,
-                    chat_id,
+                    target,
                     status_msg,
                     cancel: cancel.clone(),
                 },
             );
 
             drop(map);
-            self.spawn_timer(bot, state, media_group_id, cancel, timeout);
+            self.spawn_timer(media_group_id, cancel, delay, move |group| {
+                process_group(bot, state, group)
+            });
             return true;
         }
 
-        // Existing group: push and reset timeout.
+        // Existing group: push and reset timeout (a redelivered item changes nothing).
         let group = map.get_mut(&media_group_id).expect("group exists");
-        group.items.push(item_path);
-        if group.caption.is_none() && caption.is_some() {
-            group.caption = caption;
+        if !group.album.push(message_id, item, caption) {
+            return true;
         }
+        if group.album.forward.is_none() {
+            group.album.forward = forward;
+        }
+        let delay = group.album.flush_delay(timeout);
 
         group.cancel.cancel();
         let cancel = CancellationToken::new();
         group.cancel = cancel.clone();
         drop(map);
-        self.spawn_timer(bot, state, media_group_id, cancel, timeout);
+        self.spawn_timer(media_group_id, cancel, delay, move |group| {
+            process_group(bot, state, group)
+        });
         true
     }
 
-    fn spawn_timer(
+    /// Flushes the group through `flush` after `delay`, unless `cancel` fires first.
+    fn spawn_timer<F, Fut>(
         self: &Arc<Self>,
-        bot: Bot,
-        state: Arc<AppState>,
         media_group_id: String,
         cancel: CancellationToken,
-        timeout: Duration,
-    ) {
+        delay: Duration,
+        flush: F,
+    ) where
+        F: FnOnce(PendingGroup) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
         let buffer = Arc::clone(self);
         tokio::spawn(async move {
             tokio::select! {
               _ = cancel.cancelled() => {}
-              _ = tokio::time::sleep(timeout) => {
-                buffer.process_group(bot, state, &media_group_id).await;
+              _ = tokio::time::sleep(delay) => {
+                let group = buffer.pending.lock().await.remove(&media_group_id);
+                if let Some(group) = group {
+                    flush(group).await;
+                }
               }
             }
         });
     }
+}
+
+async fn process_group(bot: Bot, state: Arc<AppState>, group: PendingGroup) {
+    let (photos, documents) = split_items(group.album.items);
+    let status = format!(
+        "{}Processing {}...",
+        state
+            .cfg
+            .status_theme
+            .deco(if documents.is_empty() { "📷" } else { "📄" }),
+        describe_counts(photos.len(), documents.len())
+    );
+    let _ = state.messenger.edit_html(group.status_msg, &status).await;
+
+    // Sequentialize per chat (parity with text handler lock).
+    let _guard = state.chat_locks.lock_chat(group.target).await;
+
+    let ctx = PromptContext {
+        bot,
+        state: state.clone(),
+        chat_id: group.target.chat_id.0,
+        thread_id: group.target.thread_id,
+        user_id: group.user_id,
+        username: group.username,
+    };
+    process_album(
+        ctx,
+        photos,
+        documents,
+        group.album.caption,
+        group.album.forward,
+    )
+    .await;
+
+    let _ = state.messenger.delete_message(group.status_msg).await;
+}
+
+fn split_items(items: Vec<MediaItem>) -> (Vec<String>, Vec<String>) {
+    let mut photos = Vec::new();
+    let mut documents = Vec::new();
+    for item in items {
+        match item {
+            MediaItem::Photo(p) => photos.push(p),
+            MediaItem::Document(p) => documents.push(p),
+        }
+    }
+    (photos, documents)
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{n} {}", if n == 1 { one } else { many })
+}
 
-    async fn process_group(self: &Arc<Self>, bot: Bot, state: Arc<AppState>, media_group_id: &str) {
-        let group = {
-            let mut map = self.pending.lock().await;
-            map.remove(media_group_id)
-        };
+/// "3 photos", "2 documents", "1 photo and 2 documents".
+fn describe_counts(photos: usize, documents: usize) -> String {
+    match (photos, documents) {
+        (p, 0) => plural(p, "photo", "photos"),
+        (0, d) => plural(d, "document", "documents"),
+        (p, d) => format!(
+            "{} and {}",
+            plural(p, "photo", "photos"),
+            plural(d, "document", "documents")
+        ),
+    }
+}
 
-        let Some(group) = group else {
-            return;
-        };
+async fn process_album(
+    ctx: PromptContext,
+    photos: Vec<String>,
+    documents: Vec<String>,
+    caption: Option<String>,
+    forward: Option<String>,
+) {
+    let messenger = ctx.state.messenger.clone();
+    let docs = with_chat_action(
+        messenger.as_ref(),
+        ctx.target(),
+        ChatAction::UploadDocument,
+        extract_documents(&documents),
+    )
+    .await;
+    if photos.is_empty() && docs.is_empty() {
+        let _ = send_text(
+            &ctx.state,
+            ctx.target(),
+            format!(
+                "{} Failed to extract any documents.",
+                ctx.state.cfg.status_theme.failed
+            ),
+        )
+        .await;
+        return;
+    }
+
+    let (label, prompt) = match (photos.is_empty(), docs.is_empty()) {
+        (false, true) => ("PHOTO", build_photo_prompt(&photos, caption.as_deref())),
+        (true, false) => (
+            "DOCUMENT",
+            build_documents_prompt(&docs, caption.as_deref()),
+        ),
+        _ => (
+            "MEDIA_GROUP",
+            build_mixed_prompt(&photos, &docs, caption.as_deref()),
+        ),
+    };
+    let prompt = match forward {
+        Some(header) => format!("{header}\n{prompt}"),
+        None => prompt,
+    };
+    let prompt = if photos.is_empty() {
+        prompt
+    } else {
+        with_chat_action(
+            messenger.as_ref(),
+            ctx.target(),
+            ChatAction::UploadPhoto,
+            with_photo_ocr(&ctx.state, ctx.target(), &photos, prompt),
+        )
+        .await
+    };
+    let _ = run_prompt(
+        ctx,
+        label,
+        prompt,
+        PromptOptions {
+            record_last_message: false,
+            rate_action: None,
+            plan_only: false,
+        },
+    )
+    .await;
+}
 
-        let count = group.items.len();
-        let status = format!(
-            "{} Processing {} {}...",
-            self.cfg.emoji, count, self.cfg.item_label_plural
+/// Album with both images (listed by path for the model to Read) and extracted documents.
+fn build_mixed_prompt(
+    photos: &[String],
+    docs: &[(String, String)],
+    caption: Option<&str>,
+) -> String {
+    let images = photos
+        .iter()
+        .enumerate()
+        .map(|(i, p)| format!("{}. {}", i + 1, p))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let documents = docs
+        .iter()
+        .enumerate()
+        .map(|(i, (name, content))| format!("--- Document {}: {name} ---\n{content}", i + 1))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let body = format!("[Photos:\n{images}]\n\n{documents}");
+
+    match caption {
+        Some(c) if !c.trim().is_empty() => format!("{body}\n\n---\n\n{c}"),
+        _ => format!(
+            "Please analyze these {}:\n\n{body}",
+            describe_counts(photos.len(), docs.len())
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_album_lists_images_and_documents() {
+        let photos = vec!["/tmp/a.jpg".to_string(), "/tmp/b.jpg".to_string()];
+        let docs = vec![("spec.pdf".to_string(), "Section 1".to_string())];
+
+        assert_eq!(
+            build_mixed_prompt(&photos, &docs, Some("compare these")),
+            "[Photos:\n1. /tmp/a.jpg\n2. /tmp/b.jpg]\n\n--- Document 1: spec.pdf ---\nSection 1\n\n---\n\ncompare these"
         );
-        let _ = state.messenger.edit_html(group.status_msg, &status).await;
+        assert!(build_mixed_prompt(&photos, &docs, None)
+            .starts_with("Please analyze these 2 photos and 1 document:\n\n[Photos:\n"));
+    }
 
-        // Sequentialize per chat (parity with text handler lock).
-        let _guard = state.chat_locks.lock_chat(group.chat_id).await;
+    #[test]
+    fn items_keep_arrival_order_per_kind() {
+        let items = vec![
+            MediaItem::Document("/tmp/x.pdf".into()),
+            MediaItem::Photo("/tmp/1.jpg".into()),
+            MediaItem::Photo("/tmp/2.jpg".into()),
+        ];
+        let (photos, documents) = split_items(items);
+        assert_eq!(photos, ["/tmp/1.jpg", "/tmp/2.jpg"]);
+        assert_eq!(documents, ["/tmp/x.pdf"]);
+        assert_eq!(describe_counts(1, 0), "1 photo");
+        assert_eq!(describe_counts(0, 3), "3 documents");
+    }
 
-        let ctx = PromptContext {
-            bot,
-            state: state.clone(),
-            chat_id: group.chat_id,
-            user_id: group.user_id,
-            username: group.username,
-        };
-        (self.process)(ctx, group.items, group.caption).await;
-
-        let _ = state.messenger.delete_message(group.status_msg).await;
+    #[test]
+    fn caption_from_any_item_prefers_longest() {
+        let mut album = Album::default();
+        assert!(album.push(1, MediaItem::Photo("/tmp/1.jpg".into()), None));
+        assert!(album.push(2, MediaItem::Photo("/tmp/2.jpg".into()), Some("  ".into())));
+        assert_eq!(album.caption, None);
+        assert!(album.push(
+            3,
+            MediaItem::Photo("/tmp/3.jpg".into()),
+            Some("short".into())
+        ));
+        assert!(album.push(
+            4,
+            MediaItem::Photo("/tmp/4.jpg".into()),
+            Some("a longer caption".into())
+        ));
+        assert!(album.push(
+            5,
+            MediaItem::Photo("/tmp/5.jpg".into()),
+            Some("tiny".into())
+        ));
+        assert_eq!(album.caption.as_deref(), Some("a longer caption"));
+        assert_eq!(album.items.len(), 5);
+    }
+
+    #[test]
+    fn redelivered_item_is_ignored() {
+        let mut album = Album::default();
+        assert!(album.push(7, MediaItem::Photo("/tmp/a.jpg".into()), None));
+        assert!(!album.push(
+            7,
+            MediaItem::Photo("/tmp/a-again.jpg".into()),
+            Some("late".into())
+        ));
+        assert_eq!(album.items, [MediaItem::Photo("/tmp/a.jpg".into())]);
+        assert_eq!(album.caption, None);
+    }
+
+    #[test]
+    fn uncaptioned_album_waits_for_grace() {
+        let timeout = Duration::from_millis(1000);
+        let mut album = Album::default();
+        album.push(1, MediaItem::Photo("/tmp/1.jpg".into()), None);
+        assert_eq!(album.flush_delay(timeout), timeout);
+        album.push(2, MediaItem::Photo("/tmp/2.jpg".into()), None);
+        assert_eq!(album.flush_delay(timeout), timeout + CAPTION_GRACE);
+        album.push(
+            3,
+            MediaItem::Photo("/tmp/3.jpg".into()),
+            Some("caption".into()),
+        );
+        assert_eq!(album.flush_delay(timeout), timeout);
+    }
+
+    fn pending(album: Album) -> PendingGroup {
+        let chat_id = ctb_core::domain::ChatId(1);
+        PendingGroup {
+            album,
+            user_id: 1,
+            username: "alice".into(),
+            target: chat_id.into(),
+            status_msg: ctb_core::domain::MessageRef {
+                chat_id,
+                message_id: ctb_core::domain::MessageId(0),
+            },
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn timer_flushes_group_once_after_delay() {
+        let buffer = MediaGroupBuffer::new();
+        let mut album = Album::default();
+        album.push(1, MediaItem::Photo("/tmp/1.jpg".into()), Some("hi".into()));
+        buffer
+            .pending
+            .lock()
+            .await
+            .insert("g".into(), pending(album));
+
+        // A superseded timer must not flush.
+        let stale = CancellationToken::new();
+        buffer.spawn_timer(
+            "g".into(),
+            stale.clone(),
+            Duration::from_millis(10),
+            |_| async {
+                panic!("cancelled timer flushed");
+            },
+        );
+        stale.cancel();
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        buffer.spawn_timer(
+            "g".into(),
+            CancellationToken::new(),
+            Duration::from_millis(20),
+            move |group| async move {
+                let _ = tx.send(group.album.caption);
+            },
+        );
+        assert!(buffer.pending.lock().await.contains_key("g"));
+
+        let caption = tokio::time::timeout(Duration::from_secs(5), rx)
+            .await
+            .expect("group flushed")
+            .expect("flush callback ran");
+        assert_eq!(caption.as_deref(), Some("hi"));
+        assert!(buffer.pending.lock().await.is_empty());
     }
 }
@@ -12,22 +12,150 @@ use std::sync::Arc;
 
 use teloxide::{
     prelude::*,
-    types::{CallbackQuery, Message},
+    types::{CallbackQuery, Message, MessageEntity, MessageEntityKind, MessageKind, User},
 };
 
-use ctb_core::domain::UserId;
-use ctb_core::security::is_authorized;
+use ctb_core::ask_user::{awaits_sync_text_answer, find_text_answer};
+use ctb_core::domain::{ChatId, ChatTarget, MessageRef, UserId};
+use ctb_core::entities::{telegram_text_to_prompt, TextEntity, TextEntityKind};
+use ctb_core::formatting::escape_html;
+use ctb_core::log_line;
+use ctb_core::security::{is_authorized, RateAction};
+use ctb_core::utils::AuditEvent;
 
 use crate::router::AppState;
+mod audio;
 mod callback;
 mod commands;
 mod document;
+mod download;
+mod edited;
+mod group;
+mod idle;
+mod interrupt;
+mod location;
+mod logs;
 mod media_group;
 mod photo;
+mod plan;
 mod prompt;
+mod remind;
+mod reply;
+mod sessions;
+mod settings;
 mod text;
+mod text_burst;
+mod undo;
+mod video;
 mod voice;
 
+pub use idle::IdlePrompts;
+pub use logs::LogFollows;
+pub use media_group::MediaGroupBuffer;
+pub use sessions::PendingTitles;
+pub use text_burst::TextBurstBuffer;
+
+/// Chat + forum topic a message belongs to.
+///
+/// Only real topic messages carry a thread: in non-forum groups `message_thread_id` also marks
+/// reply threads, which should keep sharing the chat's session.
+pub(crate) fn chat_target(msg: &Message) -> ChatTarget {
+    let is_topic = matches!(&msg.kind, MessageKind::Common(c) if c.is_topic_message);
+    let thread_id = if is_topic { msg.thread_id } else { None };
+    ChatTarget::new(ChatId(msg.chat.id.0), thread_id)
+}
+
+/// Send plain `text` to `target` (inside its forum topic) through the app's messenger, so it is
+/// throttled and delivery-audited like every other bot message.
+pub(crate) async fn send_text(
+    state: &AppState,
+    target: ChatTarget,
+    text: impl AsRef<str>,
+) -> ctb_core::Result<MessageRef> {
+    send_html_text(state, target, escape_html(text.as_ref())).await
+}
+
+/// [`send_text`] for catalog strings, which are HTML.
+pub(crate) async fn send_html_text(
+    state: &AppState,
+    target: ChatTarget,
+    html: impl AsRef<str>,
+) -> ctb_core::Result<MessageRef> {
+    state.messenger.send_html(target, html.as_ref()).await
+}
+
+/// The name rate-limit and audit events record for `user`.
+pub(crate) fn username_of(user: &User) -> String {
+    user.username
+        .clone()
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Charge `action` to the user's rate limit. Over the limit, the refusal is audited and the user
+/// told when to retry; returns `false`.
+pub(crate) async fn check_rate_limit(
+    state: &AppState,
+    target: ChatTarget,
+    user_id: i64,
+    username: &str,
+    action: RateAction,
+) -> bool {
+    let reply = {
+        let mut rl = state.rate_limiter.lock().await;
+        match rl.check_weighted(UserId(user_id), action.weight()) {
+            (true, _) => return true,
+            (false, retry_after) => {
+                let retry_after = retry_after.unwrap_or_default();
+                let retry = retry_after.as_secs_f64();
+                if let Err(e) = state
+                    .audit
+                    .write(AuditEvent::rate_limit(user_id, username, retry))
+                {
+                    log_line!("[AUDIT] Failed to write rate_limit event: {e}");
+                }
+                rl.limited_message(state.messages(target), UserId(user_id), retry_after)
+            }
+        }
+    };
+    let _ = send_html_text(state, target, reply).await;
+    false
+}
+
+/// `text` with its entities rebuilt as markdown, so URLs behind link labels and code spans
+/// reach the prompt.
+pub(crate) fn entity_text(text: &str, entities: Option<&[MessageEntity]>) -> String {
+    let entities: Vec<TextEntity> = entities
+        .unwrap_or_default()
+        .iter()
+        .map(|e| {
+            let kind = match &e.kind {
+                MessageEntityKind::TextLink { url } => TextEntityKind::TextLink {
+                    url: url.to_string(),
+                },
+                MessageEntityKind::Code => TextEntityKind::Code,
+                MessageEntityKind::Pre { language } => TextEntityKind::Pre {
+                    language: language.clone(),
+                },
+                MessageEntityKind::Bold => TextEntityKind::Bold,
+                MessageEntityKind::Italic => TextEntityKind::Italic,
+                MessageEntityKind::Strikethrough => TextEntityKind::Strikethrough,
+                MessageEntityKind::BotCommand => TextEntityKind::BotCommand,
+                _ => TextEntityKind::Other,
+            };
+            TextEntity::new(kind, e.offset, e.length)
+        })
+        .collect();
+    telegram_text_to_prompt(text, &entities)
+}
+
+/// Transcription prompt for `chat_id`: the configured prompt plus the chat's `/vocab` terms.
+pub(crate) fn transcription_prompt(state: &AppState, chat_id: i64) -> String {
+    ctb_core::vocab::transcription_prompt(
+        &state.cfg.transcription_prompt,
+        &state.vocabulary.list(chat_id),
+    )
+}
+
 pub async fn handle_callback(
     bot: Bot,
     q: CallbackQuery,
@@ -36,37 +164,81 @@ pub async fn handle_callback(
     callback::handle_callback(bot, q, state).await
 }
 
+pub async fn handle_edited_message(
+    bot: Bot,
+    msg: Message,
+    state: Arc<AppState>,
+) -> ResponseResult<()> {
+    edited::handle_edited_message(bot, msg, state).await
+}
+
 pub async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
-    let chat_id = msg.chat.id.0;
+    let target = chat_target(&msg);
     let user_id = msg.from().map(|u| u.id.0);
 
+    // Groups: stay silent unless addressed (also avoids "Unauthorized" replies to bystanders).
+    if !group::should_handle(&msg, &state) {
+        return Ok(());
+    }
+
     if !is_authorized(
         user_id.map(|id| UserId(id as i64)),
         &state.cfg.telegram_allowed_users,
     ) {
-        let _ = bot
-            .send_message(
-                msg.chat.id,
-                "Unauthorized. Contact the bot owner for access.",
-            )
-            .await;
+        let _ = send_html_text(&state, target, state.messages(target).unauthorized()).await;
         return Ok(());
     }
 
+    // A number typed in reply to a sync `ask_user` list: the prompt that asked is still running
+    // and holds the chat's queue, so the answer is recorded right away (the dispatcher routes
+    // these past the queue too).
+    if let Some(text) = msg.text() {
+        if awaits_sync_text_answer(target.chat_id.0) {
+            if let Some((request_file, option_index)) =
+                find_text_answer(&state.cfg.temp_dir, target, text)
+            {
+                let user = msg.from();
+                let answered = callback::answer_from_text(
+                    state.clone(),
+                    target,
+                    user.map_or(0, |u| u.id.0 as i64),
+                    user.map_or_else(|| "unknown".to_string(), username_of),
+                    &request_file,
+                    option_index,
+                )
+                .await?;
+                if answered {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
     if let Some(text) = msg.text() {
         if text.starts_with('/') {
+            text_burst::flush_pending(&bot, &state, &msg, false).await;
             return commands::handle_command(bot, msg, state).await;
         }
     }
 
-    if msg.text().is_some() {
-        // Interrupt (`!`) bypasses queue.
-        if msg.text().unwrap_or("").starts_with('!') {
-            return text::handle_text(bot, msg, state).await;
+    // Interrupt (`!` before the text or caption) stops the running query and bypasses the
+    // per-chat queue (per topic in forums) that sequentializes everything else.
+    let interrupt = interrupt::is_interrupt(&msg, state.bot_username.as_deref());
+
+    if state.cfg.text_coalesce {
+        if msg.text().is_some() && !interrupt {
+            text_burst::buffer_text(bot, msg, state).await;
+            return Ok(());
+        }
+        if let Some(mut burst) = text_burst::flush_pending(&bot, &state, &msg, interrupt).await {
+            burst.push(msg);
+            let _guard = interrupt::begin_turn(&state, target, true).await;
+            return text::handle_text_burst(bot, burst, state).await;
         }
+    }
 
-        // Sequentialize normal text messages per chat.
-        let _guard = state.chat_locks.lock_chat(chat_id).await;
+    if msg.text().is_some() {
+        let _guard = interrupt::begin_turn(&state, target, interrupt).await;
         return text::handle_text(bot, msg, state).await;
     }
 
@@ -74,34 +246,86 @@ pub async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Res
     if msg.photo().is_some() {
         // Only lock for single photos; media groups are buffered and processed later.
         if msg.media_group_id().is_none() {
-            let _guard = state.chat_locks.lock_chat(chat_id).await;
+            let _guard = interrupt::begin_turn(&state, target, interrupt).await;
             return photo::handle_photo(bot, msg, state).await;
         }
+        if interrupt {
+            interrupt::stop_running(&state, target).await;
+        }
         return photo::handle_photo(bot, msg, state).await;
     }
 
     // Documents (agi-cnf.16).
     if msg.document().is_some() {
         if msg.media_group_id().is_none() {
-            let _guard = state.chat_locks.lock_chat(chat_id).await;
+            let _guard = interrupt::begin_turn(&state, target, interrupt).await;
             return document::handle_document(bot, msg, state).await;
         }
+        if interrupt {
+            interrupt::stop_running(&state, target).await;
+        }
         return document::handle_document(bot, msg, state).await;
     }
 
-    // Voice (agi-cnf.14).
+    // Voice (agi-cnf.14): queued before transcription, so voice prompts keep their order.
     if msg.voice().is_some() {
-        let _guard = state.chat_locks.lock_chat(chat_id).await;
+        let _guard = interrupt::begin_turn(&state, target, interrupt).await;
         return voice::handle_voice(bot, msg, state).await;
     }
 
+    // Audio files (forwarded podcasts, music) go through the same transcription path.
+    if msg.audio().is_some() {
+        let _guard = interrupt::begin_turn(&state, target, interrupt).await;
+        return audio::handle_audio(bot, msg, state).await;
+    }
+
+    // Videos and round video notes are sampled into frames.
+    if msg.video().is_some() || msg.video_note().is_some() {
+        let _guard = interrupt::begin_turn(&state, target, interrupt).await;
+        return video::handle_video(bot, msg, state).await;
+    }
+
+    if msg.location().is_some() {
+        let _guard = state.chat_locks.lock_chat(target).await;
+        return location::handle_location(bot, msg, state).await;
+    }
+
+    if msg.contact().is_some() {
+        let _guard = state.chat_locks.lock_chat(target).await;
+        return location::handle_contact(bot, msg, state).await;
+    }
+
     // Other message types (voice/document) implemented in agi-cnf.14-16.
-    let _ = bot
-        .send_message(
-            msg.chat.id,
-            "Rust port: message handling not implemented yet.",
-        )
-        .await;
+    let _ = send_text(
+        &state,
+        target,
+        "Rust port: message handling not implemented yet.",
+    )
+    .await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entity_text_reads_telegram_entities() {
+        // Offsets as Telegram sends them: UTF-16 units, so the emoji counts twice.
+        let entities: Vec<MessageEntity> = serde_json::from_str(
+            r#"[
+                {"type":"bold","offset":0,"length":2},
+                {"type":"text_link","offset":8,"length":4,"url":"https://example.com/spec"},
+                {"type":"code","offset":17,"length":5},
+                {"type":"mention","offset":23,"length":6}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            entity_text("🔥 read spec and cargo @alice", Some(&entities)),
+            "**🔥** read [spec](https://example.com/spec) and `cargo` @alice"
+        );
+        assert_eq!(entity_text("plain", None), "plain");
+    }
+}
@@ -3,51 +3,30 @@ use std::sync::{
     Arc,
 };
 
-use teloxide::{net::Download, prelude::*};
+use teloxide::prelude::*;
 
-use ctb_core::utils::AuditEvent;
+use ctb_core::{
+    domain::{ChatTarget, MessageRef},
+    messaging::{chat_action::with_chat_action, types::ChatAction},
+    ocr::{append_ocr, needs_ocr},
+    security::RateAction,
+};
 
 use crate::router::AppState;
 
 use super::{
-    media_group::{BoxFuture, MediaGroupBuffer, MediaGroupConfig},
+    chat_target, check_rate_limit,
+    download::{download_to_path, TELEGRAM_DOWNLOAD_LIMIT},
+    group::prompt_caption,
+    media_group::MediaItem,
     prompt::{run_prompt, PromptContext, PromptOptions},
+    reply::{build_forward_context, with_forward_context},
+    send_text, username_of,
 };
 
 static PHOTO_COUNTER: AtomicUsize = AtomicUsize::new(1);
-static PHOTO_BUFFER: std::sync::OnceLock<Arc<MediaGroupBuffer>> = std::sync::OnceLock::new();
-
-fn photo_buffer() -> &'static Arc<MediaGroupBuffer> {
-    PHOTO_BUFFER.get_or_init(|| {
-        let cfg = MediaGroupConfig {
-            emoji: "📷",
-            item_label_plural: "photos",
-        };
-
-        let process = std::sync::Arc::new(
-            |ctx: PromptContext, items: Vec<String>, caption: Option<String>| {
-                let fut: BoxFuture = Box::pin(async move {
-                    let prompt = build_photo_prompt(&items, caption.as_deref());
-                    let _ = run_prompt(
-                        ctx,
-                        "PHOTO",
-                        prompt,
-                        PromptOptions {
-                            record_last_message: false,
-                            skip_rate_limit: true,
-                        },
-                    )
-                    .await;
-                });
-                fut
-            },
-        );
-
-        MediaGroupBuffer::new(cfg, process)
-    })
-}
 
-fn build_photo_prompt(photo_paths: &[String], caption: Option<&str>) -> String {
+pub(super) fn build_photo_prompt(photo_paths: &[String], caption: Option<&str>) -> String {
     if photo_paths.len() == 1 {
         let p = &photo_paths[0];
         return match caption {
@@ -69,6 +48,21 @@ fn build_photo_prompt(photo_paths: &[String], caption: Option<&str>) -> String {
     }
 }
 
+/// `prompt` with the photos' OCR text appended when `target`'s model can't read images, or
+/// `PHOTO_OCR_FORCE` is set.
+pub(super) async fn with_photo_ocr(
+    state: &AppState,
+    target: ChatTarget,
+    photo_paths: &[String],
+    prompt: String,
+) -> String {
+    let capabilities = state.sessions.for_target(target).await.model_capabilities();
+    if !needs_ocr(state.cfg.photo_ocr_force, capabilities) {
+        return prompt;
+    }
+    append_ocr(prompt, photo_paths, state.ocr.as_ref()).await
+}
+
 async fn download_photo(
     bot: &Bot,
     state: &AppState,
@@ -77,8 +71,6 @@ async fn download_photo(
     let best = photos
         .last()
         .ok_or_else(|| anyhow::anyhow!("no photo sizes"))?;
-    let file = bot.get_file(best.file.id.clone()).await?;
-
     let ts = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
@@ -86,8 +78,7 @@ async fn download_photo(
     let n = PHOTO_COUNTER.fetch_add(1, Ordering::SeqCst);
     let path = state.cfg.temp_dir.join(format!("photo_{ts}_{n}.jpg"));
 
-    let mut dst = tokio::fs::File::create(&path).await?;
-    bot.download_file(&file.path, &mut dst).await?;
+    download_to_path(bot, &best.file, &path, TELEGRAM_DOWNLOAD_LIMIT).await?;
 
     Ok(path.to_string_lossy().to_string())
 }
@@ -101,66 +92,71 @@ pub async fn handle_photo(bot: Bot, msg: Message, state: Arc<AppState>) -> Respo
     };
 
     let user_id = user.id.0 as i64;
-    let username = user
-        .username
-        .clone()
-        .unwrap_or_else(|| "unknown".to_string());
+    let username = username_of(user);
     let chat_id = msg.chat.id.0;
+    let target = chat_target(&msg);
+    let theme = state.cfg.status_theme;
 
     let media_group_id = msg.media_group_id().map(|s| s.to_string());
-    let caption = msg.caption().map(|s| s.to_string());
+    let caption = prompt_caption(&msg, &state);
 
     // For single photos, rate limit early and show status immediately (parity with TS).
-    let mut status_msg: Option<Message> = None;
+    let mut status_msg: Option<MessageRef> = None;
     if media_group_id.is_none() {
-        let mut rl = state.rate_limiter.lock().await;
-        let (ok, retry_after) = rl.check(ctb_core::domain::UserId(user_id));
-        if !ok {
-            let retry = retry_after.unwrap_or_default().as_secs_f64();
-            if let Err(e) = state
-                .audit
-                .write(AuditEvent::rate_limit(user_id, &username, retry))
-            {
-                eprintln!("[AUDIT] Failed to write rate_limit event: {e}");
-            }
-            let _ = bot
-                .send_message(
-                    teloxide::types::ChatId(chat_id),
-                    format!("⏳ Rate limited. Please wait {:.1} seconds.", retry),
-                )
-                .await;
+        if !check_rate_limit(&state, target, user_id, &username, RateAction::Media).await {
             return Ok(());
         }
-        status_msg = bot
-            .send_message(teloxide::types::ChatId(chat_id), "📷 Processing image...")
-            .await
-            .ok();
+        status_msg = send_text(
+            &state,
+            target,
+            format!("{}Processing image...", theme.deco("📷")),
+        )
+        .await
+        .ok();
     }
 
-    let photo_path = match download_photo(&bot, &state, photos).await {
+    let photo_path = match with_chat_action(
+        state.messenger.as_ref(),
+        target,
+        ChatAction::UploadPhoto,
+        download_photo(&bot, &state, photos),
+    )
+    .await
+    {
         Ok(p) => p,
         Err(e) => {
-            let _ = bot
-                .send_message(
-                    teloxide::types::ChatId(chat_id),
-                    format!(
-                        "❌ Failed to download photo: {}",
-                        e.to_string().chars().take(100).collect::<String>()
-                    ),
-                )
-                .await;
+            let _ = send_text(
+                &state,
+                target,
+                format!(
+                    "{} Failed to download photo: {}",
+                    theme.failed,
+                    e.to_string().chars().take(100).collect::<String>()
+                ),
+            )
+            .await;
             return Ok(());
         }
     };
 
     // Single photo: process immediately.
     if media_group_id.is_none() {
-        let prompt = build_photo_prompt(std::slice::from_ref(&photo_path), caption.as_deref());
+        let photo_paths = std::slice::from_ref(&photo_path);
+        let prompt =
+            with_forward_context(&msg, build_photo_prompt(photo_paths, caption.as_deref()));
+        let prompt = with_chat_action(
+            state.messenger.as_ref(),
+            target,
+            ChatAction::UploadPhoto,
+            with_photo_ocr(&state, target, photo_paths, prompt),
+        )
+        .await;
         let _ = run_prompt(
             PromptContext {
                 bot: bot.clone(),
                 state: state.clone(),
                 chat_id,
+                thread_id: target.thread_id,
                 user_id,
                 username: username.clone(),
             },
@@ -168,13 +164,14 @@ pub async fn handle_photo(bot: Bot, msg: Message, state: Arc<AppState>) -> Respo
             prompt,
             PromptOptions {
                 record_last_message: false,
-                skip_rate_limit: true,
+                rate_action: None,
+                plan_only: false,
             },
         )
         .await;
 
         if let Some(st) = status_msg {
-            let _ = bot.delete_message(st.chat.id, st.id).await;
+            let _ = state.messenger.delete_message(st).await;
         }
 
         return Ok(());
@@ -182,16 +179,24 @@ pub async fn handle_photo(bot: Bot, msg: Message, state: Arc<AppState>) -> Respo
 
     // Media group: buffer and process after timeout.
     if let Some(group_id) = media_group_id {
-        let timeout = state.cfg.media_group_timeout;
         let ctx = PromptContext {
             bot,
             state: state.clone(),
             chat_id,
+            thread_id: target.thread_id,
             user_id,
             username,
         };
-        let _ = photo_buffer()
-            .add_to_group(ctx, group_id, photo_path, caption, timeout)
+        let _ = state
+            .media_groups
+            .add_to_group(
+                ctx,
+                group_id,
+                msg.id.0,
+                MediaItem::Photo(photo_path),
+                caption,
+                build_forward_context(&msg),
+            )
             .await;
     }
 
@@ -0,0 +1,80 @@
+//! "▶️ Execute plan" / "✖️ Discard" buttons (`plan:execute`, `plan:discard`) sent at the end of
+//! a plan-only turn (`PLAN_MODE_PREFIX`). Executing resumes the plan's session with normal
+//! permissions and counts against the rate limit; discarding just closes the review. Only the
+//! user who asked for the plan, or an owner, can press them.
+
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use ctb_core::{domain::ChatTarget, plan_mode::EXECUTE_PLAN_PROMPT, security::RateAction};
+
+use crate::handlers::prompt::{run_prompt, PromptContext, PromptOptions};
+use crate::handlers::username_of;
+use crate::router::AppState;
+
+/// `plan:` buttons from the end of a plan-only turn.
+pub(crate) async fn handle_plan_callback(
+    bot: Bot,
+    q: &CallbackQuery,
+    state: Arc<AppState>,
+    target: ChatTarget,
+    action: &str,
+) -> ResponseResult<()> {
+    if !matches!(action, "execute" | "discard") {
+        let _ = bot
+            .answer_callback_query(q.id.clone())
+            .text("Invalid callback data".to_string())
+            .await;
+        return Ok(());
+    }
+
+    let _guard = state.chat_locks.lock_chat(target).await;
+    let session = state.sessions.for_target(target).await;
+    let user_id = q.from.id.0 as i64;
+    if !session.may_review_plan(target, user_id).await {
+        let _ = bot
+            .answer_callback_query(q.id.clone())
+            .text("Only whoever asked for this plan, or the owner, can review it".to_string())
+            .await;
+        return Ok(());
+    }
+    if !session.take_pending_plan(target).await {
+        let _ = bot
+            .answer_callback_query(q.id.clone())
+            .text("This plan is no longer pending".to_string())
+            .await;
+        if let Some(msg) = &q.message {
+            let _ = bot.edit_message_reply_markup(msg.chat.id, msg.id).await;
+        }
+        return Ok(());
+    }
+
+    let status = if action == "execute" {
+        state.cfg.status_theme.render("▶️ Executing the plan")
+    } else {
+        state.cfg.status_theme.render("✖️ Plan discarded")
+    };
+    let _ = bot.answer_callback_query(q.id.clone()).await;
+    if let Some(msg) = &q.message {
+        let _ = bot.edit_message_text(msg.chat.id, msg.id, status).await;
+    }
+    if action == "discard" {
+        return Ok(());
+    }
+
+    let ctx = PromptContext {
+        bot,
+        state: state.clone(),
+        chat_id: target.chat_id.0,
+        thread_id: target.thread_id,
+        user_id,
+        username: username_of(&q.from),
+    };
+    let opts = PromptOptions {
+        record_last_message: false,
+        rate_action: Some(RateAction::Text),
+        plan_only: false,
+    };
+    run_prompt(ctx, "PLAN_EXECUTE", EXECUTE_PLAN_PROMPT.to_string(), opts).await
+}
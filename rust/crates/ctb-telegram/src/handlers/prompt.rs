@@ -1,18 +1,28 @@
+use std::path::Path;
 use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
 
-use teloxide::{prelude::*, types::ChatAction};
+use teloxide::prelude::*;
 
 use ctb_core::{
-    domain::{ChatId, MessageId, MessageRef, UserId},
-    errors::Error,
+    domain::{ChatId, ChatTarget, MessageId, MessageRef, UserId},
+    errors::{CancelReason, Error},
     formatting::convert_markdown_to_html,
+    health::claude_cli_version,
+    log_line,
     messaging::port::MessagingPort,
-    messaging::types::{ChatAction as PortChatAction, InlineKeyboard, MessagingCapabilities},
-    utils::{add_timestamp, AuditEvent},
+    messaging::types::{ChatAction, InlineKeyboard, MessagingCapabilities},
+    model::types::is_connection_error,
+    restart::now_ms,
+    security::{audit_error_text, role_of, scrub_secrets, RateAction, Role},
+    security_events::RunUser,
+    session::{ClaudeSession, TurnOutput},
+    usage_ledger::{LedgerRecord, Periods},
+    utils::{add_timestamp, AuditEvent, AuditOutcome},
     Result,
 };
 
+use crate::handlers::{check_rate_limit, idle::check_idle, send_html_text};
 use crate::router::AppState;
 
 #[derive(Clone)]
@@ -20,30 +30,37 @@ pub struct PromptContext {
     pub bot: Bot,
     pub state: Arc<AppState>,
     pub chat_id: i64,
+    /// Forum topic the prompt came from (see `handlers::chat_target`).
+    pub thread_id: Option<i32>,
     pub user_id: i64,
     pub username: String,
 }
 
+impl PromptContext {
+    pub fn target(&self) -> ChatTarget {
+        ChatTarget::new(ChatId(self.chat_id), self.thread_id)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct PromptOptions {
     pub record_last_message: bool,
-    pub skip_rate_limit: bool,
-}
-
-fn is_claude_crash(err: &ctb_core::Error) -> bool {
-    match err {
-        Error::External(s) => s.contains("exited with status") || s.contains("exited with code"),
-        _ => false,
-    }
+    /// Charged to the user's rate limit before the run; `None` when the caller already did.
+    pub rate_action: Option<RateAction>,
+    /// Plan-only turn ending with "Execute plan" / "Discard" buttons (`PLAN_MODE_PREFIX`).
+    pub plan_only: bool,
 }
 
-fn is_cancel_error(err: &ctb_core::Error) -> bool {
-    match err {
-        Error::External(s) => {
-            let lower = s.to_lowercase();
-            lower.contains("cancel") || lower.contains("abort")
+/// How a turn ended, for its audit event.
+pub(crate) fn audit_outcome(result: &Result<TurnOutput>) -> AuditOutcome {
+    match result {
+        Ok(out) if out.budget_exceeded => AuditOutcome::Cancelled,
+        Ok(_) => AuditOutcome::Ok,
+        Err(Error::Security(_) | Error::Cancelled(CancelReason::SafetyBlock)) => {
+            AuditOutcome::Blocked
         }
-        _ => false,
+        Err(Error::Cancelled(_)) => AuditOutcome::Cancelled,
+        Err(_) => AuditOutcome::Error,
     }
 }
 
@@ -53,160 +70,189 @@ pub async fn run_prompt(
     text: String,
     opts: PromptOptions,
 ) -> ResponseResult<()> {
+    let target = ctx.target();
     let PromptContext {
-        bot,
         state,
         chat_id,
         user_id,
         username,
+        ..
     } = ctx;
 
     if text.trim().is_empty() {
         return Ok(());
     }
 
-    if !opts.skip_rate_limit {
+    if let Some(action) = opts.rate_action {
         // Rate limit before heavy work.
-        let mut rl = state.rate_limiter.lock().await;
-        let (ok, retry_after) = rl.check(UserId(user_id));
-        if !ok {
-            let retry = retry_after.unwrap_or_default().as_secs_f64();
-            if let Err(e) = state
-                .audit
-                .write(AuditEvent::rate_limit(user_id, &username, retry))
-            {
-                eprintln!("[AUDIT] Failed to write rate_limit event: {e}");
-            }
-            let _ = bot
-                .send_message(
-                    teloxide::types::ChatId(chat_id),
-                    format!("⏳ Rate limited. Please wait {:.1} seconds.", retry),
-                )
-                .await;
+        if !check_rate_limit(&state, target, user_id, &username, action).await {
             return Ok(());
         }
     }
 
+    if let Some(cap) = state.cfg.daily_token_cap {
+        match state.ledger.tokens_today(user_id, Periods::now()) {
+            Ok(used) if used >= cap => {
+                let reply = state.messages(target).daily_limit_reached(used, cap);
+                let _ = send_html_text(&state, target, reply).await;
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(e) => log_line!("[LEDGER] Failed to read usage ledger: {e}"),
+        }
+    }
+
+    let session = state.sessions.for_target(target).await;
+    if check_idle(&state, &session, target, message_type, &text, opts).await {
+        return Ok(());
+    }
     if opts.record_last_message {
-        state.session.set_last_message(text.clone()).await;
+        session.set_last_message(text.clone()).await;
     }
     let prompt = add_timestamp(&text);
-
-    // Typing loop (best-effort).
-    let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
-    let bot_for_typing = bot.clone();
-    let chat_for_typing = teloxide::types::ChatId(chat_id);
-    let typing_task = tokio::spawn(async move {
-        let mut tick = tokio::time::interval(std::time::Duration::from_secs(3));
-        loop {
-            tokio::select! {
-              _ = tick.tick() => {
-                let _ = bot_for_typing.send_chat_action(chat_for_typing, ChatAction::Typing).await;
-              }
-              _ = &mut stop_rx => break,
-            }
-        }
-    });
+    let requester = RunUser {
+        user_id,
+        username: username.clone(),
+        role: role_of(UserId(user_id), &state.cfg).unwrap_or(Role::ReadOnly),
+    };
 
     let messenger: Arc<dyn MessagingPort> = state.messenger.clone();
+    let previous_session = session.stats().await.session.map(|s| s.id);
 
     const MAX_RETRIES: usize = 1;
     for attempt in 0..=MAX_RETRIES {
-        let result = state
-            .session
-            .send_message_to_chat(ChatId(chat_id), &prompt, messenger.clone())
-            .await;
-
+        let started = std::time::Instant::now();
+        let result = if opts.plan_only {
+            session
+                .plan_message_to_chat_as(target, &prompt, messenger.clone(), requester.clone())
+                .await
+        } else {
+            session
+                .send_message_to_chat_as(target, &prompt, messenger.clone(), requester.clone())
+                .await
+        };
+
+        let outcome = audit_outcome(&result);
         match result {
-            Ok(out) => {
-                if let Err(e) = state.audit.write(AuditEvent::message(
-                    user_id,
-                    &username,
-                    message_type,
-                    &text,
-                    Some(&out.text),
-                )) {
-                    eprintln!("[AUDIT] Failed to write message event: {e}");
+            Ok(mut out) => {
+                match out.suspended.take() {
+                    Some(suspended) => state.suspended_streams.stash(suspended),
+                    None => state.suspended_streams.discard(target),
+                }
+                let event =
+                    AuditEvent::message(user_id, &username, message_type, &text, Some(&out.text))
+                        .with_turn(
+                            out.session.as_ref().map(|s| s.id.as_str()),
+                            started.elapsed(),
+                            out.usage.as_ref(),
+                            outcome,
+                        );
+                if let Err(e) = state.audit.write(event) {
+                    log_line!("[AUDIT] Failed to write message event: {e}");
+                }
+                if let Some(id) = out.session.as_ref().map(|s| s.id.as_str()) {
+                    if previous_session.as_deref() != Some(id) {
+                        let event =
+                            AuditEvent::session_start(user_id, &username, id, claude_cli_version());
+                        if let Err(e) = state.audit.write(event) {
+                            log_line!("[AUDIT] Failed to write session_start event: {e}");
+                        }
+                    }
+                }
+                if let Some(usage) = out.usage.as_ref() {
+                    let pricing = session.pricing().await;
+                    let record =
+                        LedgerRecord::new(now_ms(), user_id, &username, chat_id, usage, &pricing);
+                    if let Err(e) = state.ledger.append(&record) {
+                        log_line!("[LEDGER] Failed to record usage: {e}");
+                    }
                 }
                 if !out.waiting_for_user {
                     let _ = state.scheduler.process_queued_jobs().await;
                 }
 
                 // Context-limit warning + auto-save (parity with TS).
-                if state.session.needs_save().await {
+                if session.needs_save().await {
                     if let Err(e) = handle_context_limit_autosave(
                         state.clone(),
-                        ChatId(chat_id),
+                        session.clone(),
+                        target,
                         user_id,
                         &username,
                         messenger.clone(),
                     )
                     .await
                     {
-                        eprintln!("[AUTO_SAVE] failed: {e}");
-                        let sanitized = sanitize_error(&state, &e.to_string());
+                        log_line!("[AUTO_SAVE] failed: {e}");
+                        let sanitized = scrub_secrets(&e.to_string(), &state.cfg);
                         let truncated = sanitized.chars().take(300).collect::<String>();
                         let msg = format!(
-                            "🚨 **CRITICAL: Auto-Save Failed**\n\nError: `{}`\n\n⚠️ **YOUR WORK IS NOT SAVED**\n\nDo NOT restart. Try manual: /oh-my-claude:save",
+                            "{0} **CRITICAL: Auto-Save Failed**\n\nError: `{1}`\n\n{0} **YOUR WORK IS NOT SAVED**\n\nDo NOT restart. Try manual: /oh-my-claude:save",
+                            state.cfg.status_theme.warning,
                             truncated
                         );
                         let _ = messenger
-                            .send_html(ChatId(chat_id), &convert_markdown_to_html(&msg))
+                            .send_html(target, &convert_markdown_to_html(&msg))
                             .await;
                     }
                 }
                 break;
             }
             Err(err) => {
-                if is_claude_crash(&err) && attempt < MAX_RETRIES {
-                    let _ = state.session.kill().await;
-                    let _ = bot
-                        .send_message(
-                            teloxide::types::ChatId(chat_id),
-                            "⚠️ Claude crashed, retrying...",
-                        )
-                        .await;
+                if matches!(err, Error::ModelCrashed(_)) && attempt < MAX_RETRIES {
+                    let _ = session.kill().await;
+                    let msgs = state.messages(target);
+                    let _ = send_html_text(&state, target, msgs.model_crashed_retrying()).await;
                     continue;
                 }
+                if let Error::Provider(detail) = &err {
+                    if state.cfg.provider_error_retry
+                        && attempt < MAX_RETRIES
+                        && is_connection_error(detail)
+                    {
+                        log_line!("[PROMPT] Provider connection error, retrying: {detail}");
+                        let msgs = state.messages(target);
+                        let _ =
+                            send_html_text(&state, target, msgs.provider_error_retrying()).await;
+                        continue;
+                    }
+                }
 
-                if is_cancel_error(&err) {
-                    let was_interrupt = state.session.consume_interrupt_flag().await;
-                    if !was_interrupt {
-                        let _ = bot
-                            .send_message(teloxide::types::ChatId(chat_id), "🛑 Query stopped.")
-                            .await;
+                let session_id = session.stats().await.session.map(|s| s.id);
+                let turn = |event: AuditEvent| {
+                    event.with_turn(session_id.as_deref(), started.elapsed(), None, outcome)
+                };
+
+                if let Error::Cancelled(reason) = err {
+                    if let Err(e) = state.audit.write(turn(AuditEvent::error(
+                        user_id,
+                        &username,
+                        &format!("cancelled: {reason}"),
+                        Some(message_type),
+                    ))) {
+                        log_line!("[AUDIT] Failed to write error event: {e}");
+                    }
+                    if let Some(notice) = state.messages(target).cancel_notice(reason) {
+                        let _ = send_html_text(&state, target, notice).await;
                     }
                     break;
                 }
 
-                let msg_txt = format!("{err}");
-                let truncated = if msg_txt.len() > 200 {
-                    format!("{}...", msg_txt.chars().take(200).collect::<String>())
-                } else {
-                    msg_txt
-                };
-                let _ = bot
-                    .send_message(
-                        teloxide::types::ChatId(chat_id),
-                        format!("❌ Error: {truncated}"),
-                    )
-                    .await;
-                if let Err(e) = state.audit.write(AuditEvent::error(
+                let (_, audited) = error_texts(&state, &err);
+                let _ = send_html_text(&state, target, error_reply(&state, target, &err)).await;
+                if let Err(e) = state.audit.write(turn(AuditEvent::error(
                     user_id,
                     &username,
-                    &truncated,
+                    &audited,
                     Some(message_type),
-                )) {
-                    eprintln!("[AUDIT] Failed to write error event: {e}");
+                ))) {
+                    log_line!("[AUDIT] Failed to write error event: {e}");
                 }
                 break;
             }
         }
     }
 
-    let _ = stop_tx.send(());
-    let _ = typing_task.await;
-
     Ok(())
 }
 
@@ -221,7 +267,8 @@ pub async fn run_text_prompt(
         text,
         PromptOptions {
             record_last_message: true,
-            skip_rate_limit: false,
+            rate_action: Some(RateAction::Text),
+            plan_only: false,
         },
     )
     .await
@@ -229,22 +276,25 @@ pub async fn run_text_prompt(
 
 async fn handle_context_limit_autosave(
     state: Arc<AppState>,
-    chat_id: ChatId,
+    session: Arc<ClaudeSession>,
+    target: ChatTarget,
     user_id: i64,
     username: &str,
     messenger: Arc<dyn MessagingPort>,
 ) -> anyhow::Result<()> {
+    let theme = state.cfg.status_theme;
     // If a save id is already present, don't spam /save again.
     let save_id_file = state.cfg.claude_working_dir.join(".last-save-id");
     if let Ok(existing) = std::fs::read_to_string(&save_id_file) {
         let id = existing.trim();
         if crate::router::is_valid_save_id(id) {
             let msg = format!(
-                "✅ **Context Already Saved**\n\nSave ID: `{}`\n\nPlease run: `make up` to restart with restored context.",
+                "{} **Context Already Saved**\n\nSave ID: `{}`\n\nPlease run: `make up` to restart with restored context.",
+                theme.done,
                 id
             );
             let _ = messenger
-                .send_html(chat_id, &convert_markdown_to_html(&msg))
+                .send_html(target, &convert_markdown_to_html(&msg))
                 .await;
             return Ok(());
         }
@@ -252,33 +302,33 @@ async fn handle_context_limit_autosave(
         let _ = std::fs::remove_file(&save_id_file);
     }
 
-    let current = state.session.current_context_tokens().await;
+    let current = session.current_context_tokens().await;
     let percentage = ((current as f64 / 200_000f64) * 100.0).min(999.9);
     let warn = format!(
-        "⚠️ **Context Limit Approaching**\n\nCurrent: {} / 200,000 tokens ({:.1}%)\n\nInitiating automatic save...",
+        "{} **Context Limit Approaching**\n\nCurrent: {} / 200,000 tokens ({:.1}%)\n\nInitiating automatic save...",
+        theme.warning,
         current,
         percentage
     );
     let _ = messenger
-        .send_html(chat_id, &convert_markdown_to_html(&warn))
+        .send_html(target, &convert_markdown_to_html(&warn))
         .await;
 
     let silent: Arc<dyn MessagingPort> = Arc::new(SuppressedMessenger::new(messenger.clone()));
     let save_prompt = "Context limit reached. Execute: Skill tool with skill='oh-my-claude:save'";
 
-    let out = state
-        .session
-        .send_message_to_chat(chat_id, save_prompt, silent)
+    let out = session
+        .send_message_to_chat(target, save_prompt, silent)
         .await?;
 
     let Some(save_id) = parse_save_id(&out.text) else {
         let snippet = out.text.chars().take(200).collect::<String>();
         let msg = format!(
-            "⚠️ Save completed but couldn't parse save ID.\n\nResponse: `{}`",
-            snippet
+            "{} Save completed but couldn't parse save ID.\n\nResponse: `{}`",
+            theme.warning, snippet
         );
         let _ = messenger
-            .send_html(chat_id, &convert_markdown_to_html(&msg))
+            .send_html(target, &convert_markdown_to_html(&msg))
             .await;
         return Ok(());
     };
@@ -301,11 +351,12 @@ async fn handle_context_limit_autosave(
     }
 
     let ok = format!(
-        "✅ **Context Saved**\n\nSave ID: `{}`\n\nPlease run: `make up` to restart with restored context.",
+        "{} **Context Saved**\n\nSave ID: `{}`\n\nPlease run: `make up` to restart with restored context.",
+        theme.done,
         save_id
     );
     let _ = messenger
-        .send_html(chat_id, &convert_markdown_to_html(&ok))
+        .send_html(target, &convert_markdown_to_html(&ok))
         .await;
 
     if let Err(e) = state.audit.write(AuditEvent::message(
@@ -315,7 +366,7 @@ async fn handle_context_limit_autosave(
         save_prompt,
         Some(&format!("save_id={save_id}")),
     )) {
-        eprintln!("[AUDIT] Failed to write auto_save event: {e}");
+        log_line!("[AUDIT] Failed to write auto_save event: {e}");
     }
 
     Ok(())
@@ -334,15 +385,35 @@ fn parse_save_id(text: &str) -> Option<String> {
     None
 }
 
-fn sanitize_error(state: &AppState, s: &str) -> String {
-    let mut out = s.to_string();
-    if let Ok(home) = std::env::var("HOME") {
-        out = out.replace(&home, "~");
+/// A failed turn's error as shown in chat (secrets scrubbed) and as written to the audit log,
+/// both truncated.
+pub(crate) fn error_texts(state: &AppState, err: &Error) -> (String, String) {
+    let raw = err.to_string();
+    (
+        truncate_error(&scrub_secrets(&raw, &state.cfg)),
+        truncate_error(&audit_error_text(&raw, &state.cfg)),
+    )
+}
+
+/// The chat reply to a failed turn: provider failures get their own message with a `/retry`
+/// hint, other errors the generic one.
+pub(crate) fn error_reply(state: &AppState, target: ChatTarget, err: &Error) -> String {
+    let msgs = state.messages(target);
+    match err {
+        Error::Provider(detail) => {
+            msgs.provider_error(&truncate_error(&scrub_secrets(detail, &state.cfg)))
+        }
+        _ => msgs.error(&error_texts(state, err).0),
     }
-    if let Some(wd) = state.cfg.claude_working_dir.to_str() {
-        out = out.replace(wd, "~");
+}
+
+fn truncate_error(s: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    if s.chars().count() > MAX_CHARS {
+        format!("{}...", s.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        s.to_string()
     }
-    out
 }
 
 // === MessagingPort decorator used for auto-save (no streaming spam) ===
@@ -375,8 +446,8 @@ impl MessagingPort for SuppressedMessenger {
         self.real.capabilities()
     }
 
-    async fn send_html(&self, chat_id: ChatId, _html: &str) -> Result<MessageRef> {
-        Ok(self.alloc(chat_id))
+    async fn send_html(&self, target: ChatTarget, _html: &str) -> Result<MessageRef> {
+        Ok(self.alloc(target.chat_id))
     }
 
     async fn edit_html(&self, _msg: MessageRef, _html: &str) -> Result<()> {
@@ -387,7 +458,16 @@ impl MessagingPort for SuppressedMessenger {
         Ok(())
     }
 
-    async fn send_chat_action(&self, _chat_id: ChatId, _action: PortChatAction) -> Result<()> {
+    async fn edit_inline_keyboard(
+        &self,
+        _msg: MessageRef,
+        _html: &str,
+        _keyboard: Option<InlineKeyboard>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_chat_action(&self, _target: ChatTarget, _action: ChatAction) -> Result<()> {
         Ok(())
     }
 
@@ -397,17 +477,31 @@ impl MessagingPort for SuppressedMessenger {
 
     async fn send_inline_keyboard(
         &self,
-        chat_id: ChatId,
+        target: ChatTarget,
         text: &str,
         keyboard: InlineKeyboard,
     ) -> Result<MessageRef> {
+        self.real.send_inline_keyboard(target, text, keyboard).await
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()> {
         self.real
-            .send_inline_keyboard(chat_id, text, keyboard)
+            .answer_callback_query(callback_id, text, show_alert)
             .await
     }
 
-    async fn answer_callback_query(&self, callback_id: &str, text: Option<&str>) -> Result<()> {
-        self.real.answer_callback_query(callback_id, text).await
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        _path: &Path,
+        _caption: Option<&str>,
+    ) -> Result<MessageRef> {
+        Ok(self.alloc(target.chat_id))
     }
 }
 
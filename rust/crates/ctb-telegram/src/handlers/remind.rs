@@ -0,0 +1,84 @@
+//! `/remind`: one-shot reminders kept by the scheduler.
+//!
+//! `/remind 40m check the deploy` posts the text back in 40 minutes; an `ask:` prefix runs it as a
+//! prompt instead. `/remind list` and `/remind cancel <id>` manage this chat's reminders.
+
+use std::sync::Arc;
+
+use chrono::Local;
+use ctb_core::{
+    domain::{ChatTarget, UserId},
+    formatting::escape_html,
+    i18n::Messages,
+    scheduler::{parse_reminder_time, OneShotJob},
+    security::{role_of, Role},
+    theme::StatusTheme,
+};
+
+use super::commands::send_html_split;
+use crate::router::AppState;
+
+fn list_html(msgs: &Messages, theme: &StatusTheme, jobs: &[OneShotJob]) -> String {
+    if jobs.is_empty() {
+        return format!("{}{}", theme.deco("📭"), msgs.reminders_none());
+    }
+    let now = Local::now();
+    let mut lines = vec![format!(
+        "{} {}",
+        theme.clock,
+        msgs.reminders_title(jobs.len())
+    )];
+    lines.extend(jobs.iter().map(|j| j.summary_html(theme, now)));
+    lines.join("\n")
+}
+
+pub(crate) async fn handle_remind_command(
+    state: &Arc<AppState>,
+    target: ChatTarget,
+    user_id: i64,
+    arg: &str,
+) {
+    let msgs = state.messages(target);
+    let usage = msgs.remind_usage();
+    let theme = state.cfg.status_theme;
+    let arg = arg.trim();
+    let (first, rest) = arg
+        .split_once(char::is_whitespace)
+        .map_or((arg, ""), |(f, r)| (f, r.trim()));
+
+    let html = match first.to_ascii_lowercase().as_str() {
+        "" => format!(
+            "{usage}\n\n{}",
+            list_html(msgs, theme, &state.scheduler.reminders_for(target).await)
+        ),
+        "list" => list_html(msgs, theme, &state.scheduler.reminders_for(target).await),
+        "cancel" => match rest.trim_start_matches('#').parse::<u32>() {
+            Err(_) => msgs.remind_cancel_usage().to_string(),
+            Ok(id) => match state.scheduler.cancel_reminder(target, id).await {
+                Ok(true) => format!("{}{}", theme.deco("🗑"), msgs.reminder_cancelled(id)),
+                Ok(false) => format!("{} {}", theme.failed, msgs.reminder_not_found(id)),
+                Err(e) => format!("{} {}", theme.failed, escape_html(&e.to_string())),
+            },
+        },
+        spec => match parse_reminder_time(spec, Local::now()) {
+            Err(e) => format!("{} {}\n\n{usage}", theme.failed, escape_html(&e)),
+            Ok(_) if rest.is_empty() => usage.to_string(),
+            Ok(at) => {
+                let role = role_of(UserId(user_id), &state.cfg).unwrap_or(Role::ReadOnly);
+                match state.scheduler.add_reminder(target, at, rest, role).await {
+                    Ok(job) => format!(
+                        "{} {}",
+                        theme.clock,
+                        msgs.reminder_set(
+                            job.id,
+                            &job.fire_at_local().format("%Y-%m-%d %H:%M").to_string(),
+                            job.ask
+                        )
+                    ),
+                    Err(e) => format!("{} {}", theme.failed, escape_html(&e.to_string())),
+                }
+            }
+        },
+    };
+    send_html_split(state, target, &html).await;
+}
@@ -0,0 +1,435 @@
+//! `/sessions` and `/title`: browse the chat's saved sessions (see [`ctb_core::session_history`])
+//! and resume, rename or delete them.
+//!
+//! Each row's buttons carry `sessions:{action}:{index}:{id prefix}`. The index points into the
+//! list as it was shown; the session id prefix is checked against the entry at that index, so a
+//! button from an outdated list is refused instead of acting on whichever session moved there.
+//! Rename waits up to [`RENAME_WAIT`] for the tapping user's next text message in the chat; any
+//! command, `/cancel` included, ends the wait.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use teloxide::prelude::*;
+
+use ctb_core::{
+    domain::{ChatId, ChatTarget, MessageId, MessageRef},
+    formatting::{escape_html, format_idle},
+    i18n::Messages,
+    log_line,
+    messaging::types::{InlineButton, InlineKeyboard},
+    session::ClaudeSession,
+    session_history::{SavedSession, SessionHistory},
+    theme::StatusTheme,
+};
+
+use crate::router::AppState;
+
+pub(crate) const SESSIONS_CALLBACK_PREFIX: &str = "sessions:";
+
+/// Characters of the session id carried in a button to confirm the index still points at it.
+const ID_PREFIX_CHARS: usize = 8;
+
+/// How long a Rename tap waits for the title.
+const RENAME_WAIT: Duration = Duration::from_secs(120);
+
+/// What a `/sessions` button does to the session in its row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Resume,
+    Rename,
+    Delete,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Resume => "resume",
+            Action::Rename => "rename",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SessionAction {
+    action: Action,
+    index: usize,
+    id_prefix: String,
+}
+
+fn parse_action(data: &str) -> Option<SessionAction> {
+    let mut parts = data.splitn(3, ':');
+    let action = match parts.next()? {
+        "resume" => Action::Resume,
+        "rename" => Action::Rename,
+        "delete" => Action::Delete,
+        _ => return None,
+    };
+    let index = parts.next()?.parse().ok()?;
+    let id_prefix = parts.next().filter(|p| !p.is_empty())?.to_string();
+    Some(SessionAction {
+        action,
+        index,
+        id_prefix,
+    })
+}
+
+fn callback_data(action: Action, index: usize, entry: &SavedSession) -> String {
+    let prefix: String = entry.session_id.chars().take(ID_PREFIX_CHARS).collect();
+    format!(
+        "{SESSIONS_CALLBACK_PREFIX}{}:{index}:{prefix}",
+        action.as_str()
+    )
+}
+
+/// The entry a button was made for, if the list hasn't changed under it.
+fn entry_for<'a>(history: &'a SessionHistory, action: &SessionAction) -> Option<&'a SavedSession> {
+    history
+        .entries()
+        .get(action.index)
+        .filter(|e| e.session_id.starts_with(&action.id_prefix))
+}
+
+/// Plain text: the session's title, else a name made from its id.
+fn display_title(msgs: &Messages, entry: &SavedSession) -> String {
+    entry.title.clone().unwrap_or_else(|| {
+        let short: String = entry.session_id.chars().take(ID_PREFIX_CHARS).collect();
+        msgs.sessions_untitled(&short)
+    })
+}
+
+fn age(msgs: &Messages, timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .and_then(|t| (Utc::now() - t.with_timezone(&Utc)).to_std().ok())
+        .map_or_else(|| "?".to_string(), |d| msgs.ago(&format_idle(d)))
+}
+
+fn sessions_view(
+    msgs: &Messages,
+    theme: &StatusTheme,
+    history: &SessionHistory,
+    current: Option<&str>,
+) -> (String, InlineKeyboard) {
+    let mut lines = vec![msgs.sessions_title(history.entries().len())];
+    let mut rows = Vec::new();
+    for (i, entry) in history.entries().iter().enumerate() {
+        let marker = if current == Some(entry.session_id.as_str()) {
+            msgs.sessions_current()
+        } else {
+            ""
+        };
+        lines.push(format!(
+            "{}. <b>{}</b>{marker}\n    {} {} <code>{}</code>",
+            i + 1,
+            escape_html(&display_title(msgs, entry)),
+            age(msgs, &entry.last_used_at),
+            theme.separator,
+            escape_html(&entry.working_dir)
+        ));
+        rows.push(vec![
+            InlineButton {
+                label: msgs.sessions_resume_button(i + 1),
+                callback_data: callback_data(Action::Resume, i, entry),
+            },
+            InlineButton {
+                label: msgs.sessions_rename_button().to_string(),
+                callback_data: callback_data(Action::Rename, i, entry),
+            },
+            InlineButton {
+                label: msgs.sessions_delete_button().to_string(),
+                callback_data: callback_data(Action::Delete, i, entry),
+            },
+        ]);
+    }
+    (lines.join("\n"), InlineKeyboard::from_rows(rows))
+}
+
+async fn current_session_id(session: &ClaudeSession) -> Option<String> {
+    session.stats().await.session.map(|s| s.id)
+}
+
+pub(crate) async fn handle_sessions_command(
+    state: &AppState,
+    target: ChatTarget,
+) -> ResponseResult<()> {
+    let msgs = state.messages(target);
+    let session = state.sessions.for_target(target).await;
+    let history = match session.history(target) {
+        Ok(history) if history.is_empty() => {
+            let _ = state
+                .messenger
+                .send_html(target, msgs.sessions_none())
+                .await;
+            return Ok(());
+        }
+        Ok(history) => history,
+        Err(e) => {
+            let _ = state
+                .messenger
+                .send_html(target, &msgs.failed(&e.to_string()))
+                .await;
+            return Ok(());
+        }
+    };
+    let current = current_session_id(&session).await;
+    let (html, keyboard) =
+        sessions_view(msgs, state.cfg.status_theme, &history, current.as_deref());
+    if let Err(e) = state
+        .messenger
+        .send_inline_keyboard(target, &html, keyboard)
+        .await
+    {
+        log_line!("[SESSIONS] Failed to send the session list: {e}");
+    }
+    Ok(())
+}
+
+/// `/title [text]`: rename the current session, or show its title.
+pub(crate) async fn handle_title_command(
+    state: &AppState,
+    target: ChatTarget,
+    arg: &str,
+) -> ResponseResult<()> {
+    let msgs = state.messages(target);
+    let session = state.sessions.for_target(target).await;
+    let reply = if arg.trim().is_empty() {
+        match session.title(target).await {
+            Some(title) => msgs.session_title(&title),
+            None => msgs.title_usage().to_string(),
+        }
+    } else {
+        match session.set_current_title(target, arg).await {
+            Ok(title) => msgs.session_renamed(&title),
+            Err(e) => msgs.failed(&e.to_string()),
+        }
+    };
+    let _ = state.messenger.send_html(target, &reply).await;
+    Ok(())
+}
+
+/// A rename waiting for its title.
+struct PendingTitle {
+    user_id: i64,
+    session_id: String,
+    since: Instant,
+}
+
+/// Chats where a `/sessions` rename is waiting for the next text message (one per chat; a newer
+/// rename replaces the older one).
+#[derive(Default)]
+pub struct PendingTitles {
+    inner: Mutex<HashMap<ChatTarget, PendingTitle>>,
+}
+
+impl PendingTitles {
+    fn wait(&self, target: ChatTarget, user_id: i64, session_id: String) {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        map.insert(
+            target,
+            PendingTitle {
+                user_id,
+                session_id,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// The session `user_id` is renaming in `target`, ending the wait. `None` once it expired.
+    fn take(&self, target: ChatTarget, user_id: i64) -> Option<String> {
+        self.take_at(target, user_id, Instant::now())
+    }
+
+    fn take_at(&self, target: ChatTarget, user_id: i64, now: Instant) -> Option<String> {
+        let mut map = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if map.get(&target)?.user_id != user_id {
+            return None;
+        }
+        map.remove(&target)
+            .filter(|pending| now.duration_since(pending.since) < RENAME_WAIT)
+            .map(|pending| pending.session_id)
+    }
+
+    /// Ends `user_id`'s wait in `target`; `true` if one was still running.
+    pub(crate) fn cancel(&self, target: ChatTarget, user_id: i64) -> bool {
+        self.take(target, user_id).is_some()
+    }
+}
+
+/// Use `text` as the title when `user_id` tapped Rename in `target`. Returns `true` when the
+/// message was consumed and must not run as a prompt.
+pub(crate) async fn take_pending_title(
+    state: &AppState,
+    target: ChatTarget,
+    user_id: i64,
+    text: &str,
+) -> bool {
+    let Some(session_id) = state.pending_titles.take(target, user_id) else {
+        return false;
+    };
+    let msgs = state.messages(target);
+    let session = state.sessions.for_target(target).await;
+    let reply = match session.set_title(target, &session_id, text) {
+        Ok(title) => msgs.session_renamed(&title),
+        Err(e) => msgs.failed(&e.to_string()),
+    };
+    let _ = state.messenger.send_html(target, &reply).await;
+    true
+}
+
+/// `sessions:` buttons from [`handle_sessions_command`].
+pub(crate) async fn handle_sessions_callback(
+    bot: Bot,
+    q: &CallbackQuery,
+    state: Arc<AppState>,
+    target: ChatTarget,
+    data: &str,
+) -> ResponseResult<()> {
+    let msgs = state.messages(target);
+    let notice = |text: &str| {
+        bot.answer_callback_query(q.id.clone())
+            .text(text.to_string())
+    };
+    let Some(action) = parse_action(data) else {
+        let _ = notice(msgs.invalid_callback()).await;
+        return Ok(());
+    };
+    let session = state.sessions.for_target(target).await;
+    let history = match session.history(target) {
+        Ok(history) => history,
+        Err(e) => {
+            let _ = notice(&msgs.failed(&e.to_string())).await;
+            return Ok(());
+        }
+    };
+    let Some(entry) = entry_for(&history, &action).cloned() else {
+        let _ = notice(msgs.sessions_outdated()).await;
+        return Ok(());
+    };
+    let msg = q.message.as_ref().map(|m| MessageRef {
+        chat_id: ChatId(m.chat.id.0),
+        message_id: MessageId(m.id.0),
+    });
+    let user_id = q.from.id.0 as i64;
+
+    match action.action {
+        Action::Resume => {
+            if session.is_running().await {
+                let _ = notice(msgs.sessions_busy()).await;
+                return Ok(());
+            }
+            let report = match session
+                .resume_session(target, &entry.session_id, msgs)
+                .await
+            {
+                Ok((true, text)) => msgs.succeeded(&text),
+                Ok((false, text)) | Err(ctb_core::Error::Config(text)) => msgs.failed(&text),
+                Err(e) => msgs.failed(&e.to_string()),
+            };
+            let _ = bot.answer_callback_query(q.id.clone()).await;
+            if let Some(msg) = msg {
+                if let Err(e) = state
+                    .messenger
+                    .edit_inline_keyboard(msg, &report, None)
+                    .await
+                {
+                    log_line!("[SESSIONS] Failed to report the resume: {e}");
+                }
+            }
+        }
+        Action::Rename => {
+            state
+                .pending_titles
+                .wait(target, user_id, entry.session_id.clone());
+            let _ = bot.answer_callback_query(q.id.clone()).await;
+            let prompt = msgs.sessions_rename_prompt(&display_title(msgs, &entry));
+            let _ = state.messenger.send_html(target, &prompt).await;
+        }
+        Action::Delete => {
+            if let Err(e) = session.delete_saved_session(target, &entry.session_id) {
+                let _ = notice(&msgs.failed(&e.to_string())).await;
+                return Ok(());
+            }
+            let _ = notice(&msgs.sessions_deleted(&display_title(msgs, &entry))).await;
+            let Some(msg) = msg else {
+                return Ok(());
+            };
+            let history = session.history(target).unwrap_or_default();
+            let edited = if history.is_empty() {
+                state
+                    .messenger
+                    .edit_inline_keyboard(msg, msgs.sessions_none_left(), None)
+                    .await
+            } else {
+                let current = current_session_id(&session).await;
+                let (html, keyboard) =
+                    sessions_view(msgs, state.cfg.status_theme, &history, current.as_deref());
+                state
+                    .messenger
+                    .edit_inline_keyboard(msg, &html, Some(keyboard))
+                    .await
+            };
+            if let Err(e) = edited {
+                log_line!("[SESSIONS] Failed to refresh the session list: {e}");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ctb_core::{i18n::EN, theme::UNICODE};
+
+    #[test]
+    fn buttons_only_act_on_the_session_they_were_made_for() {
+        let mut history = SessionHistory::default();
+        history.touch("aaaaaaaa-1111", "/w", "t1");
+        history.touch("bbbbbbbb-2222", "/w", "t2");
+        let (_, keyboard) = sessions_view(&EN, &UNICODE, &history, None);
+        let data: Vec<_> = keyboard
+            .buttons()
+            .map(|b| b.callback_data.as_str())
+            .collect();
+        assert_eq!(data[0], "sessions:resume:0:bbbbbbbb");
+        assert_eq!(data[5], "sessions:delete:1:aaaaaaaa");
+
+        let delete_a = parse_action(&data[5][SESSIONS_CALLBACK_PREFIX.len()..]).unwrap();
+        assert_eq!(
+            entry_for(&history, &delete_a).map(|e| e.session_id.as_str()),
+            Some("aaaaaaaa-1111")
+        );
+        // Once "b" is gone, index 1 no longer exists and index 0 names another session.
+        history.remove("bbbbbbbb-2222");
+        assert!(entry_for(&history, &delete_a).is_none());
+        let resume_b = parse_action("resume:0:bbbbbbbb").unwrap();
+        assert!(entry_for(&history, &resume_b).is_none());
+
+        assert_eq!(parse_action("resume:0:"), None);
+        assert_eq!(parse_action("open:0:aaaaaaaa"), None);
+        assert_eq!(parse_action("rename:x:aaaaaaaa"), None);
+    }
+
+    #[test]
+    fn rename_waits_expire_and_belong_to_the_tapping_user() {
+        let target: ChatTarget = ChatId(1).into();
+        let pending = PendingTitles::default();
+
+        pending.wait(target, 7, "s1".to_string());
+        assert_eq!(pending.take(target, 8), None);
+        assert_eq!(pending.take(target, 7).as_deref(), Some("s1"));
+        assert_eq!(pending.take(target, 7), None);
+
+        pending.wait(target, 7, "s1".to_string());
+        let late = Instant::now() + RENAME_WAIT;
+        assert_eq!(pending.take_at(target, 7, late), None);
+
+        pending.wait(target, 7, "s1".to_string());
+        assert!(pending.cancel(target, 7));
+        assert!(!pending.cancel(target, 7));
+    }
+}
@@ -0,0 +1,217 @@
+//! `/settings`: show this chat's effective display settings and language with toggle buttons.
+//!
+//! Callback data is `settings:toggle:{key}` and `settings:reset`; the settings message is edited
+//! in place after every change.
+
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use ctb_core::{
+    chat_settings::{ChatSetting, STREAMING_THROTTLE_MAX_MS, STREAMING_THROTTLE_MIN_MS},
+    domain::{ChatId, ChatTarget, MessageId, MessageRef},
+    formatting::escape_html,
+    i18n::Locale,
+    log_line,
+    messaging::types::{InlineButton, InlineKeyboard},
+    model::types::parse_tool_list,
+};
+
+use super::commands::is_owner;
+use crate::router::AppState;
+
+pub(crate) const SETTINGS_CALLBACK_PREFIX: &str = "settings:";
+
+/// Settings message and keyboard for `chat_id`.
+fn settings_view(state: &AppState, chat_id: i64) -> (String, InlineKeyboard) {
+    let overrides = state.chat_settings.overrides(chat_id);
+    let effective = state.chat_settings.effective(&state.cfg, chat_id);
+    let msgs = effective.messages();
+    let theme = state.cfg.status_theme;
+
+    let mut lines = vec![format!("{}\n", msgs.settings_title())];
+    for setting in ChatSetting::ALL {
+        let origin = if overrides.is_overridden(setting) {
+            msgs.settings_overridden()
+        } else {
+            ""
+        };
+        lines.push(format!(
+            "{} {}: <b>{}</b>{origin}",
+            theme.bullet,
+            msgs.setting_label(setting),
+            escape_html(&effective.display(setting))
+        ));
+    }
+    lines.push(format!(
+        "\n<i>{}\n{}\n{}</i>",
+        msgs.settings_throttle_hint(STREAMING_THROTTLE_MIN_MS, STREAMING_THROTTLE_MAX_MS),
+        msgs.settings_language_hint(),
+        msgs.settings_tools_hint()
+    ));
+
+    let mut buttons: Vec<InlineButton> = ChatSetting::ALL
+        .into_iter()
+        .filter(|s| s.is_toggle())
+        .map(|setting| {
+            let mark = if effective.flag(setting) == Some(true) {
+                theme.done
+            } else {
+                theme.idle
+            };
+            InlineButton {
+                label: format!("{mark} {}", msgs.setting_label(setting)),
+                callback_data: format!("{SETTINGS_CALLBACK_PREFIX}toggle:{}", setting.key()),
+            }
+        })
+        .collect();
+    if !overrides.is_empty() {
+        buttons.push(InlineButton {
+            label: msgs.settings_reset_button().to_string(),
+            callback_data: format!("{SETTINGS_CALLBACK_PREFIX}reset"),
+        });
+    }
+    (lines.join("\n"), InlineKeyboard::new(buttons))
+}
+
+/// `/settings`. The tool lists are a security boundary, so only the owner may change them (or
+/// drop them with `reset`).
+pub(crate) async fn handle_settings_command(
+    state: &AppState,
+    target: ChatTarget,
+    user_id: i64,
+    arg: &str,
+) -> ResponseResult<()> {
+    let owner = is_owner(state, user_id);
+    let chat_id = target.chat_id.0;
+    let msgs = state.messages(target);
+    let failed = |e: &str| format!("{} {}", state.cfg.status_theme.failed, escape_html(e));
+    let (sub, value) = arg
+        .trim()
+        .split_once(char::is_whitespace)
+        .map_or((arg.trim(), ""), |(s, v)| (s, v.trim()));
+
+    let error = match sub.to_ascii_lowercase().as_str() {
+        "" => None,
+        "throttle" => {
+            let ms = match value.to_ascii_lowercase().as_str() {
+                "default" => Ok(None),
+                v => v
+                    .trim_end_matches("ms")
+                    .trim()
+                    .parse::<u64>()
+                    .map(Some)
+                    .map_err(|_| msgs.settings_throttle_usage().to_string()),
+            };
+            ms.and_then(|ms| {
+                state
+                    .chat_settings
+                    .set_streaming_throttle(chat_id, ms)
+                    .map_err(|e| failed(&e.to_string()))
+            })
+            .err()
+        }
+        "language" | "locale" => {
+            let locale = match value.to_ascii_lowercase().as_str() {
+                "default" => Ok(None),
+                v => Locale::parse(v)
+                    .map(Some)
+                    .ok_or_else(|| msgs.settings_language_usage()),
+            };
+            locale
+                .and_then(|locale| {
+                    state
+                        .chat_settings
+                        .set_locale(chat_id, locale)
+                        .map_err(|e| failed(&e.to_string()))
+                })
+                .err()
+        }
+        key @ ("allowed_tools" | "disallowed_tools") if !owner => {
+            Some(msgs.owner_only(&format!("settings {key}")))
+        }
+        key @ ("allowed_tools" | "disallowed_tools") => {
+            let setting = ChatSetting::from_key(key).expect("tool list setting");
+            let tools = match value.to_ascii_lowercase().as_str() {
+                "" => Err(msgs.settings_tools_usage(setting)),
+                "default" => Ok(None),
+                "none" => Ok(Some(Vec::new())),
+                _ => parse_tool_list(value).map(Some).map_err(|e| failed(&e)),
+            };
+            tools
+                .and_then(|tools| {
+                    state
+                        .chat_settings
+                        .set_tools(chat_id, setting, tools)
+                        .map_err(|e| failed(&e.to_string()))
+                })
+                .err()
+        }
+        "reset" => state
+            .chat_settings
+            .reset(chat_id, !owner)
+            .map_err(|e| failed(&e.to_string()))
+            .err(),
+        _ => Some(msgs.settings_usage().to_string()),
+    };
+    if let Some(error) = error {
+        let _ = state.messenger.send_html(target, &error).await;
+        return Ok(());
+    }
+
+    let (html, keyboard) = settings_view(state, chat_id);
+    if let Err(e) = state
+        .messenger
+        .send_inline_keyboard(target, &html, keyboard)
+        .await
+    {
+        log_line!("[SETTINGS] Failed to send settings: {e}");
+    }
+    Ok(())
+}
+
+/// `settings:` buttons from [`handle_settings_command`].
+pub(crate) async fn handle_settings_callback(
+    bot: Bot,
+    q: &CallbackQuery,
+    state: Arc<AppState>,
+    target: ChatTarget,
+    user_id: i64,
+    action: &str,
+) -> ResponseResult<()> {
+    let chat_id = target.chat_id.0;
+    let msgs = state.messages(target);
+    let result = match action.strip_prefix("toggle:").map(ChatSetting::from_key) {
+        Some(Some(setting)) => state
+            .chat_settings
+            .toggle(&state.cfg, chat_id, setting)
+            .map(|on| format!("{}: {}", msgs.setting_label(setting), msgs.on_off(on))),
+        Some(None) => Ok(msgs.settings_unknown().to_string()),
+        None if action == "reset" => state
+            .chat_settings
+            .reset(chat_id, !is_owner(&state, user_id))
+            .map(|_| msgs.settings_reset_done().to_string()),
+        None => Ok(msgs.invalid_callback().to_string()),
+    };
+    let notice = result.unwrap_or_else(|e| {
+        log_line!("[SETTINGS] Failed to save settings: {e}");
+        msgs.settings_save_failed().to_string()
+    });
+    let _ = bot.answer_callback_query(q.id.clone()).text(notice).await;
+
+    if let Some(msg) = &q.message {
+        let (html, keyboard) = settings_view(&state, chat_id);
+        let msg = MessageRef {
+            chat_id: ChatId(msg.chat.id.0),
+            message_id: MessageId(msg.id.0),
+        };
+        if let Err(e) = state
+            .messenger
+            .edit_inline_keyboard(msg, &html, Some(keyboard))
+            .await
+        {
+            log_line!("[SETTINGS] Failed to refresh settings: {e}");
+        }
+    }
+    Ok(())
+}
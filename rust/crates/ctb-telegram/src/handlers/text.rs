@@ -2,50 +2,161 @@ use std::sync::Arc;
 
 use teloxide::prelude::*;
 
-use ctb_core::utils::strip_interrupt_prefix;
+use ctb_core::{
+    ask_user::find_text_answer, domain::MessageId, plan_mode::strip_plan_prefix,
+    security::RateAction, utils::strip_interrupt_prefix,
+};
 
-use crate::handlers::prompt::{run_text_prompt, PromptContext};
+use crate::handlers::prompt::{run_prompt, run_text_prompt, PromptContext, PromptOptions};
+use crate::handlers::{
+    callback, chat_target, entity_text,
+    group::strip_bot_mention,
+    reply::{with_forward_context, with_reply_context},
+    sessions::take_pending_title,
+    username_of,
+};
 use crate::router::AppState;
 
 pub async fn handle_text(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
-    let Some(user) = msg.from() else {
+    let Some(text) = message_text(&msg, state.bot_username.as_deref()) else {
+        return Ok(());
+    };
+    let message_id = MessageId(msg.id.0);
+    handle_text_prompt(bot, &msg, message_id, text, state).await
+}
+
+/// A burst of quick consecutive messages (see [`super::text_burst`]) as one prompt: the texts
+/// joined by newlines. The first message supplies the sender and reply context; the last one
+/// is recorded as the prompt, and may be the `!` interrupt the burst was merged into.
+pub(crate) async fn handle_text_burst(
+    bot: Bot,
+    messages: Vec<Message>,
+    state: Arc<AppState>,
+) -> ResponseResult<()> {
+    let (Some(first), Some(last)) = (messages.first(), messages.last()) else {
         return Ok(());
     };
-    let Some(mut text) = msg.text().map(|s| s.to_string()) else {
+    let text = messages
+        .iter()
+        .filter_map(|m| message_text(m, state.bot_username.as_deref()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    handle_text_prompt(bot, first, MessageId(last.id.0), text, state).await
+}
+
+/// Message text as a prompt: entities rendered, bot mention and `!` interrupt stripped (the
+/// running query was already stopped, see `interrupt::begin_turn`), and a forwarded message's
+/// origin in front.
+fn message_text(msg: &Message, bot_username: Option<&str>) -> Option<String> {
+    msg.text().map(|s| {
+        let text = strip_bot_mention(&entity_text(s, msg.entities()), bot_username);
+        with_forward_context(msg, strip_interrupt_prefix(&text).1)
+    })
+}
+
+async fn handle_text_prompt(
+    bot: Bot,
+    msg: &Message,
+    message_id: MessageId,
+    mut text: String,
+    state: Arc<AppState>,
+) -> ResponseResult<()> {
+    let Some(user) = msg.from() else {
         return Ok(());
     };
 
     let user_id = user.id.0 as i64;
-    let username = user
-        .username
-        .clone()
-        .unwrap_or_else(|| "unknown".to_string());
+    let username = username_of(user);
     let chat_id = msg.chat.id.0;
+    let target = chat_target(msg);
+
+    let session = state.sessions.for_target(target).await;
+
+    // The title for a `/sessions` rename.
+    if take_pending_title(&state, target, user_id, &text).await {
+        return Ok(());
+    }
 
-    // Interrupt prefix handling (`!`): stop current run, then proceed with stripped text.
-    let (is_interrupt, stripped) = strip_interrupt_prefix(&text);
-    text = stripped;
-    if is_interrupt && state.session.is_running().await {
-        state.session.mark_interrupt().await;
-        let _ = state.session.stop().await;
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-        state.session.clear_stop_requested().await;
+    // Plan prefix (`?` by default): plan first, execute only after approval.
+    let plan = strip_plan_prefix(&text, &state.cfg.plan_mode_prefix);
+    let is_plan = plan.is_some();
+    if let Some(rest) = plan {
+        text = rest;
+    }
+    if is_plan && text.trim().is_empty() {
+        let msgs = state.messages(target);
+        let notice = msgs.plan_empty(&state.cfg.plan_mode_prefix);
+        let _ = state.messenger.send_html(target, &notice).await;
+        return Ok(());
     }
 
     if text.trim().is_empty() {
         return Ok(());
     }
 
-    run_text_prompt(
-        PromptContext {
-            bot,
-            state,
-            chat_id,
+    // A numbered reply to an `ask_user` list (messengers without inline keyboards).
+    if let Some((request_file, option_index)) = find_text_answer(&state.cfg.temp_dir, target, &text)
+    {
+        let answered = callback::answer_from_text(
+            state.clone(),
+            target,
             user_id,
-            username,
-        },
-        "TEXT",
+            username.clone(),
+            &request_file,
+            option_index,
+        )
+        .await?;
+        if answered {
+            return Ok(());
+        }
+    }
+    let text = with_reply_context(
+        msg,
+        state.bot_username.as_deref(),
+        state.cfg.reply_context_max_chars,
         text,
-    )
-    .await
+    );
+    session
+        .record_prompt(target, message_id, text.clone())
+        .await;
+
+    let ctx = PromptContext {
+        bot,
+        state,
+        chat_id,
+        thread_id: target.thread_id,
+        user_id,
+        username,
+    };
+    if is_plan {
+        let opts = PromptOptions {
+            record_last_message: true,
+            rate_action: Some(RateAction::Text),
+            plan_only: true,
+        };
+        return run_prompt(ctx, "PLAN", text, opts).await;
+    }
+    run_text_prompt(ctx, "TEXT", text).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn forwarded_interrupts_lose_the_bang_under_the_header() {
+        let msg: Message = serde_json::from_value(json!({
+            "message_id": 1,
+            "date": 1_714_600_000,
+            "chat": {"id": 42, "type": "private", "first_name": "Ann"},
+            "forward_sender_name": "Dee",
+            "forward_date": 1_714_521_600,
+            "text": "!is this true?"
+        }))
+        .unwrap();
+        let text = message_text(&msg, None).unwrap();
+        assert!(text.starts_with("[Forwarded from 'Dee'"), "{text}");
+        assert!(text.ends_with("]\nis this true?"), "{text}");
+    }
 }
@@ -0,0 +1,271 @@
+//! Text bursts (`TEXT_COALESCE`): plain text messages a user sends in quick succession become
+//! one prompt.
+//!
+//! Typing a thought as several short messages would otherwise start a turn per message. Like
+//! albums (see [`super::media_group`]), messages are collected per chat until none has arrived
+//! for `TEXT_COALESCE_MS`, then the burst is sent as a single prompt with the texts joined by
+//! newlines. Only one sender's burst is held per chat: a message from someone else flushes the
+//! pending burst right away.
+//!
+//! `/` commands and `!` interrupts are never buffered. Any other message that isn't buffered
+//! runs the chat's pending burst first, so prompts and commands keep their arrival order. A text
+//! interrupt from the burst's sender takes the burst along instead: it supersedes whatever the
+//! chat was doing, not what the user typed just before it.
+
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use teloxide::prelude::*;
+use tokio_util::sync::CancellationToken;
+
+use ctb_core::domain::ChatTarget;
+use ctb_core::log_line;
+
+use crate::router::AppState;
+
+use super::{chat_target, interrupt, text};
+
+struct PendingBurst {
+    user_id: i64,
+    messages: Vec<Message>,
+    cancel: CancellationToken,
+}
+
+/// Pending bursts keyed by chat (and forum topic); owned by [`AppState`].
+#[derive(Default)]
+pub struct TextBurstBuffer {
+    pending: tokio::sync::Mutex<HashMap<ChatTarget, PendingBurst>>,
+}
+
+impl TextBurstBuffer {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Add `msg` to the chat's burst and (re)start its `window` timer; `flush` receives each
+    /// finished burst in arrival order. A redelivered message changes nothing.
+    pub async fn push<F, Fut>(
+        self: &Arc<Self>,
+        target: ChatTarget,
+        user_id: i64,
+        msg: Message,
+        window: Duration,
+        flush: F,
+    ) where
+        F: Fn(Vec<Message>) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let cancel = CancellationToken::new();
+        let mut map = self.pending.lock().await;
+        let mut displaced = None;
+        match map.get_mut(&target) {
+            Some(burst) if burst.user_id == user_id => {
+                if burst.messages.iter().any(|m| m.id == msg.id) {
+                    return;
+                }
+                burst.cancel.cancel();
+                burst.cancel = cancel.clone();
+                burst.messages.push(msg);
+            }
+            _ => {
+                let burst = PendingBurst {
+                    user_id,
+                    messages: vec![msg],
+                    cancel: cancel.clone(),
+                };
+                if let Some(other) = map.insert(target, burst) {
+                    other.cancel.cancel();
+                    displaced = Some(other.messages);
+                }
+            }
+        }
+        drop(map);
+
+        if let Some(messages) = displaced {
+            tokio::spawn(flush.clone()(messages));
+        }
+        let buffer = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::select! {
+              _ = cancel.cancelled() => {}
+              _ = tokio::time::sleep(window) => {
+                let mut map = buffer.pending.lock().await;
+                // A message that arrived after the sleep ended restarted the timer.
+                if cancel.is_cancelled() {
+                    return;
+                }
+                let burst = map.remove(&target);
+                drop(map);
+                if let Some(burst) = burst {
+                    flush(burst.messages).await;
+                }
+              }
+            }
+        });
+    }
+
+    /// Take the chat's pending burst, stopping its timer.
+    pub async fn take(&self, target: ChatTarget) -> Option<Vec<Message>> {
+        let burst = self.pending.lock().await.remove(&target)?;
+        burst.cancel.cancel();
+        Some(burst.messages)
+    }
+}
+
+/// Buffer a text message of `msg`'s sender.
+pub(super) async fn buffer_text(bot: Bot, msg: Message, state: Arc<AppState>) {
+    let Some(user_id) = msg.from().map(|u| u.id.0 as i64) else {
+        return;
+    };
+    let target = chat_target(&msg);
+    let window = state.cfg.text_coalesce_window;
+    let buffer = Arc::clone(&state.text_bursts);
+    let flush = move |messages| run_burst(bot.clone(), Arc::clone(&state), messages);
+    buffer.push(target, user_id, msg, window, flush).await;
+}
+
+/// Settle the pending burst of `msg`'s chat before `msg`, which isn't buffered. A text interrupt
+/// from the burst's sender gets the burst back, to run with it as one prompt; otherwise the
+/// burst runs to completion first (after stopping the running query for an interrupt).
+pub(super) async fn flush_pending(
+    bot: &Bot,
+    state: &Arc<AppState>,
+    msg: &Message,
+    interrupt: bool,
+) -> Option<Vec<Message>> {
+    let target = chat_target(msg);
+    let messages = state.text_bursts.take(target).await?;
+    if interrupt {
+        let sender = |m: &Message| m.from().map(|u| u.id);
+        if msg.text().is_some() && messages.first().map(sender) == Some(sender(msg)) {
+            return Some(messages);
+        }
+        interrupt::stop_running(state, target).await;
+    }
+    run_burst(bot.clone(), Arc::clone(state), messages).await;
+    None
+}
+
+async fn run_burst(bot: Bot, state: Arc<AppState>, messages: Vec<Message>) {
+    let Some(first) = messages.first() else {
+        return;
+    };
+    let target = chat_target(first);
+    let _guard = state.chat_locks.lock_chat(target).await;
+    if let Err(e) = text::handle_text_burst(bot, messages, state.clone()).await {
+        log_line!("[TEXT] Burst failed: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ctb_core::domain::ChatId;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    const WINDOW: Duration = Duration::from_millis(200);
+
+    fn message(id: i32, chat_id: i64, user_id: u64) -> Message {
+        serde_json::from_value(json!({
+            "message_id": id,
+            "date": 1_700_000_000,
+            "chat": {"id": chat_id, "type": "private", "first_name": "Ann"},
+            "from": {"id": user_id, "is_bot": false, "first_name": "Ann"},
+            "text": format!("part {id}"),
+        }))
+        .unwrap()
+    }
+
+    fn target(chat_id: i64) -> ChatTarget {
+        ChatTarget::new(ChatId(chat_id), None)
+    }
+
+    type Flushed = mpsc::UnboundedReceiver<Vec<i32>>;
+
+    fn recorder() -> (
+        impl Fn(Vec<Message>) -> std::future::Ready<()> + Clone + Send + 'static,
+        Flushed,
+    ) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let flush = move |messages: Vec<Message>| {
+            let _ = tx.send(messages.iter().map(|m| m.id.0).collect());
+            std::future::ready(())
+        };
+        (flush, rx)
+    }
+
+    #[tokio::test]
+    async fn burst_flushes_once_after_a_quiet_window_in_arrival_order() {
+        let buffer = TextBurstBuffer::new();
+        let (flush, mut flushed) = recorder();
+
+        buffer
+            .push(target(1), 7, message(10, 1, 7), WINDOW, flush.clone())
+            .await;
+        tokio::time::sleep(WINDOW / 2).await;
+        buffer
+            .push(target(1), 7, message(11, 1, 7), WINDOW, flush.clone())
+            .await;
+        buffer
+            .push(target(1), 7, message(11, 1, 7), WINDOW, flush.clone())
+            .await;
+        buffer
+            .push(target(1), 7, message(12, 1, 7), WINDOW, flush)
+            .await;
+
+        // The later messages restarted the timer: nothing yet when the first window would end.
+        tokio::time::sleep(WINDOW * 3 / 4).await;
+        assert!(flushed.try_recv().is_err());
+
+        let burst = tokio::time::timeout(WINDOW * 5, flushed.recv()).await;
+        assert_eq!(burst.unwrap(), Some(vec![10, 11, 12]));
+        tokio::time::sleep(WINDOW * 2).await;
+        assert!(flushed.try_recv().is_err(), "flushed exactly once");
+    }
+
+    #[tokio::test]
+    async fn another_sender_flushes_the_pending_burst_first() {
+        let buffer = TextBurstBuffer::new();
+        let (flush, mut flushed) = recorder();
+
+        buffer
+            .push(target(1), 7, message(1, 1, 7), WINDOW, flush.clone())
+            .await;
+        buffer
+            .push(target(2), 7, message(2, 2, 7), WINDOW, flush.clone())
+            .await;
+        buffer
+            .push(target(1), 8, message(3, 1, 8), WINDOW, flush)
+            .await;
+
+        let first = tokio::time::timeout(WINDOW / 2, flushed.recv()).await;
+        assert_eq!(first.unwrap(), Some(vec![1]), "displaced without waiting");
+
+        let mut rest = Vec::new();
+        for _ in 0..2 {
+            let burst = tokio::time::timeout(WINDOW * 5, flushed.recv()).await;
+            rest.push(burst.unwrap().unwrap());
+        }
+        rest.sort();
+        assert_eq!(rest, [vec![2], vec![3]], "other chats keep their own burst");
+    }
+
+    #[tokio::test]
+    async fn taken_burst_is_not_flushed_by_its_timer() {
+        let buffer = TextBurstBuffer::new();
+        let (flush, mut flushed) = recorder();
+
+        buffer
+            .push(target(1), 7, message(1, 1, 7), WINDOW, flush.clone())
+            .await;
+        buffer
+            .push(target(1), 7, message(2, 1, 7), WINDOW, flush)
+            .await;
+        let taken = buffer.take(target(1)).await.unwrap();
+        assert_eq!(taken.iter().map(|m| m.id.0).collect::<Vec<_>>(), [1, 2]);
+        assert!(buffer.take(target(1)).await.is_none());
+
+        tokio::time::sleep(WINDOW * 2).await;
+        assert!(flushed.try_recv().is_err());
+    }
+}
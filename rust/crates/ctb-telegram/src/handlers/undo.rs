@@ -0,0 +1,241 @@
+//! `/undo`: list the files the chat's last file-writing turn changed, with buttons to restore
+//! all of them (`undo:{turn}:all`) or a single one (`undo:{turn}:{index}`) from the turn's
+//! snapshots (see [`ctb_core::undo`]).
+
+use std::{path::Path, sync::Arc};
+
+use teloxide::prelude::*;
+
+use ctb_core::{
+    domain::{ChatId, ChatTarget, MessageId, MessageRef, UserId},
+    formatting::escape_html,
+    log_line,
+    messaging::types::{truncate_label, InlineButton, InlineKeyboard},
+    security::{is_authorized_for, Capability},
+    undo::{RestoreOutcome, Snapshot, UndoTurn},
+};
+
+use crate::router::AppState;
+
+pub(crate) const UNDO_CALLBACK_PREFIX: &str = "undo:";
+
+/// Which files of a turn a button restores.
+#[derive(Debug, PartialEq, Eq)]
+struct UndoAction {
+    turn: u64,
+    index: Option<usize>,
+}
+
+fn parse_action(action: &str) -> Option<UndoAction> {
+    let (turn, which) = action.split_once(':')?;
+    let turn = turn.parse().ok()?;
+    let index = match which {
+        "all" => None,
+        n => Some(n.parse().ok()?),
+    };
+    Some(UndoAction { turn, index })
+}
+
+/// `path` relative to the working directory when it is inside it.
+fn display_path(state: &AppState, path: &Path) -> String {
+    path.strip_prefix(&state.cfg.claude_working_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+fn undo_view(state: &AppState, turn: &UndoTurn) -> (String, InlineKeyboard) {
+    let theme = state.cfg.status_theme;
+    let mut lines = vec![format!(
+        "{}<b>The last turn changed {} file(s)</b>\n",
+        theme.deco("↩️"),
+        turn.files.len()
+    )];
+    let mut buttons = Vec::new();
+    for (i, file) in turn.files.iter().enumerate() {
+        let shown = display_path(state, &file.path);
+        let note = match &file.snapshot {
+            Snapshot::Saved(_) => String::new(),
+            Snapshot::Created => " (new, undo deletes it)".to_string(),
+            Snapshot::Unavailable(reason) => {
+                format!(" (can't restore: {})", escape_html(reason))
+            }
+        };
+        lines.push(format!(
+            "{}. <code>{}</code>{note}",
+            i + 1,
+            escape_html(&shown)
+        ));
+        if !matches!(file.snapshot, Snapshot::Unavailable(_)) {
+            let name = file
+                .path
+                .file_name()
+                .map_or(shown.clone(), |n| n.to_string_lossy().to_string());
+            let (name, _) = truncate_label(&name, state.cfg.button_label_max_length);
+            buttons.push(InlineButton {
+                label: format!("{}{}. {name}", theme.deco("↩️"), i + 1),
+                callback_data: format!("{UNDO_CALLBACK_PREFIX}{}:{i}", turn.id),
+            });
+        }
+    }
+    if buttons.len() > 1 {
+        buttons.insert(
+            0,
+            InlineButton {
+                label: format!("{}Restore all", theme.deco("↩️")),
+                callback_data: format!("{UNDO_CALLBACK_PREFIX}{}:all", turn.id),
+            },
+        );
+    }
+    lines.push(
+        "\n<i>Copies are taken as each Write/Edit is announced; changes made another way (e.g. by \
+Bash) are not listed.</i>"
+            .to_string(),
+    );
+    (lines.join("\n"), InlineKeyboard::new(buttons))
+}
+
+pub(crate) async fn handle_undo_command(
+    state: &AppState,
+    target: ChatTarget,
+    user_id: i64,
+) -> ResponseResult<()> {
+    let reply = if !is_authorized_for(Some(UserId(user_id)), Capability::EditFiles, &state.cfg) {
+        format!(
+            "{} /undo needs full access.",
+            state.cfg.status_theme.blocked
+        )
+    } else {
+        let turn = state
+            .sessions
+            .for_target(target)
+            .await
+            .undo_journal()
+            .and_then(|journal| journal.last_turn(target));
+        match turn {
+            Some(turn) => {
+                let (html, keyboard) = undo_view(state, &turn);
+                if let Err(e) = state
+                    .messenger
+                    .send_inline_keyboard(target, &html, keyboard)
+                    .await
+                {
+                    log_line!("[UNDO] Failed to send the undo list: {e}");
+                }
+                return Ok(());
+            }
+            None => "Nothing to undo: no recent turn in this chat changed files.".to_string(),
+        }
+    };
+    let _ = state.messenger.send_html(target, &reply).await;
+    Ok(())
+}
+
+/// `undo:` buttons from [`handle_undo_command`].
+pub(crate) async fn handle_undo_callback(
+    bot: Bot,
+    q: &CallbackQuery,
+    state: Arc<AppState>,
+    target: ChatTarget,
+    action: &str,
+) -> ResponseResult<()> {
+    let notice = |text: &str| {
+        bot.answer_callback_query(q.id.clone())
+            .text(text.to_string())
+    };
+    let Some(action) = parse_action(action) else {
+        let _ = notice("Invalid callback data").await;
+        return Ok(());
+    };
+    let user_id = q.from.id.0 as i64;
+    if !is_authorized_for(Some(UserId(user_id)), Capability::EditFiles, &state.cfg) {
+        let _ = notice(&format!(
+            "{} Undo needs full access",
+            state.cfg.status_theme.blocked
+        ))
+        .await;
+        return Ok(());
+    }
+    let session = state.sessions.for_target(target).await;
+    if session.is_running().await {
+        let _ = notice("Wait for the current query to finish").await;
+        return Ok(());
+    }
+    let Some(journal) = session.undo_journal() else {
+        let _ = notice("Undo is disabled").await;
+        return Ok(());
+    };
+
+    let paths = session.path_policy(target);
+    let theme = state.cfg.status_theme;
+    let report = match journal.restore(target, action.turn, action.index, &paths) {
+        Ok(restored) => restored
+            .iter()
+            .map(|(path, outcome)| {
+                let shown = escape_html(&display_path(&state, path));
+                match outcome {
+                    RestoreOutcome::Restored => {
+                        format!("{} Restored <code>{shown}</code>", theme.done)
+                    }
+                    RestoreOutcome::Removed => {
+                        format!("{}Removed <code>{shown}</code>", theme.deco("🗑"))
+                    }
+                    RestoreOutcome::Skipped(reason) => {
+                        format!(
+                            "{} Skipped <code>{shown}</code>: {}",
+                            theme.warning,
+                            escape_html(reason)
+                        )
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(e) => format!("{} {}", theme.failed, escape_html(&e.to_string())),
+    };
+    log_line!(
+        "[UNDO] User {user_id} restored turn {}: {report}",
+        action.turn
+    );
+    let _ = bot.answer_callback_query(q.id.clone()).await;
+    if let Some(msg) = &q.message {
+        let msg = MessageRef {
+            chat_id: ChatId(msg.chat.id.0),
+            message_id: MessageId(msg.id.0),
+        };
+        if let Err(e) = state
+            .messenger
+            .edit_inline_keyboard(msg, &report, None)
+            .await
+        {
+            log_line!("[UNDO] Failed to report the restore: {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_name_a_turn_and_one_or_all_files() {
+        assert_eq!(
+            parse_action("12:all"),
+            Some(UndoAction {
+                turn: 12,
+                index: None
+            })
+        );
+        assert_eq!(
+            parse_action("12:3"),
+            Some(UndoAction {
+                turn: 12,
+                index: Some(3)
+            })
+        );
+        assert_eq!(parse_action("12"), None);
+        assert_eq!(parse_action("x:all"), None);
+        assert_eq!(parse_action("12:-1"), None);
+    }
+}
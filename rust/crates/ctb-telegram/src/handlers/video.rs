@@ -0,0 +1,329 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
+};
+
+use teloxide::{prelude::*, types::FileMeta};
+
+use ctb_core::{log_line, security::RateAction};
+use ctb_openai::{OpenAiClient, TranscriptionConfig};
+
+use crate::router::AppState;
+
+use super::download::download_to_path;
+use super::prompt::{run_prompt, PromptContext, PromptOptions};
+use super::{
+    chat_target, check_rate_limit, group::prompt_caption, send_text, transcription_prompt,
+    username_of,
+};
+
+static VIDEO_COUNTER: AtomicUsize = AtomicUsize::new(1);
+
+/// Bot API `getFile` refuses anything larger than this.
+const MAX_VIDEO_SIZE: u64 = 20 * 1024 * 1024; // 20MB
+const FRAME_COUNT: usize = 4;
+const FFMPEG_MISSING: &str =
+    "Video processing needs ffmpeg. Install it (brew install ffmpeg / apt install ffmpeg) and try again.";
+
+/// Video or round video note, normalized.
+struct VideoInput<'a> {
+    file: &'a FileMeta,
+    duration_secs: u32,
+    label: &'static str,
+}
+
+fn video_input(msg: &Message) -> Option<VideoInput<'_>> {
+    if let Some(v) = msg.video() {
+        return Some(VideoInput {
+            file: &v.file,
+            duration_secs: v.duration,
+            label: "Video",
+        });
+    }
+    msg.video_note().map(|v| VideoInput {
+        file: &v.file,
+        duration_secs: v.duration,
+        label: "Video note",
+    })
+}
+
+/// Evenly spaced sample points (segment midpoints), so the first/last frames avoid black
+/// fade-in/out frames.
+fn frame_timestamps(duration_secs: u32, count: usize) -> Vec<f64> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let duration = f64::from(duration_secs.max(1));
+    (0..count)
+        .map(|i| duration * (i as f64 + 0.5) / count as f64)
+        .collect()
+}
+
+fn build_video_prompt(
+    label: &str,
+    duration_secs: u32,
+    frames: &[(PathBuf, f64)],
+    transcript: Option<&str>,
+    caption: Option<&str>,
+) -> String {
+    let list = frames
+        .iter()
+        .enumerate()
+        .map(|(i, (p, at))| format!("{}. {} (at {at:.1}s)", i + 1, p.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut out = format!(
+        "[{label}: {duration_secs}s, {} frames extracted:\n{list}]",
+        frames.len()
+    );
+    if let Some(t) = transcript.map(str::trim).filter(|t| !t.is_empty()) {
+        out.push_str(&format!("\n\n[Audio transcript: {t}]"));
+    }
+    match caption {
+        Some(c) if !c.trim().is_empty() => out.push_str(&format!("\n\n{c}")),
+        _ => out.push_str("\n\nPlease analyze this video using the frames above."),
+    }
+    out
+}
+
+enum FfmpegError {
+    Missing,
+    Failed(String),
+}
+
+async fn run_ffmpeg(args: &[&std::ffi::OsStr]) -> Result<(), FfmpegError> {
+    use tokio::process::Command;
+
+    let out = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => FfmpegError::Missing,
+            _ => FfmpegError::Failed(e.to_string()),
+        })?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(FfmpegError::Failed(
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        ))
+    }
+}
+
+async fn extract_frames(
+    video: &Path,
+    dir: &Path,
+    stem: &str,
+    duration_secs: u32,
+) -> Result<Vec<(PathBuf, f64)>, FfmpegError> {
+    let mut frames = Vec::new();
+    for (i, at) in frame_timestamps(duration_secs, FRAME_COUNT)
+        .into_iter()
+        .enumerate()
+    {
+        let out = dir.join(format!("{stem}_frame{}.jpg", i + 1));
+        let at_arg = format!("{at:.2}");
+        run_ffmpeg(&[
+            "-ss".as_ref(),
+            at_arg.as_ref(),
+            "-i".as_ref(),
+            video.as_os_str(),
+            "-frames:v".as_ref(),
+            "1".as_ref(),
+            "-q:v".as_ref(),
+            "2".as_ref(),
+            out.as_os_str(),
+        ])
+        .await?;
+        frames.push((out, at));
+    }
+    Ok(frames)
+}
+
+/// Transcribe the audio track (best-effort; silent videos simply have no transcript).
+async fn transcribe_audio(state: &AppState, chat_id: i64, video: &Path) -> Option<String> {
+    let key = state.cfg.openai_api_key.as_ref()?;
+    let audio = video.with_extension("wav");
+    let extracted = run_ffmpeg(&[
+        "-i".as_ref(),
+        video.as_os_str(),
+        "-vn".as_ref(),
+        "-ac".as_ref(),
+        "1".as_ref(),
+        "-ar".as_ref(),
+        "16000".as_ref(),
+        audio.as_os_str(),
+    ])
+    .await;
+    if extracted.is_err() {
+        return None;
+    }
+
+    let transcript =
+        OpenAiClient::with_config(key.clone(), TranscriptionConfig::from_config(&state.cfg))
+            .transcribe_file(&audio, Some(&transcription_prompt(state, chat_id)))
+            .await;
+    let _ = tokio::fs::remove_file(&audio).await;
+    match transcript {
+        Ok(t) => Some(t),
+        Err(e) => {
+            log_line!("[VIDEO] Transcription failed: {e}");
+            None
+        }
+    }
+}
+
+async fn download_video(bot: &Bot, file: &FileMeta, path: &Path) -> anyhow::Result<()> {
+    download_to_path(bot, file, path, MAX_VIDEO_SIZE).await
+}
+
+pub async fn handle_video(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
+    let Some(user) = msg.from() else {
+        return Ok(());
+    };
+    let Some(video) = video_input(&msg) else {
+        return Ok(());
+    };
+
+    let user_id = user.id.0 as i64;
+    let username = username_of(user);
+    let chat_id = msg.chat.id.0;
+    let target = chat_target(&msg);
+    let theme = state.cfg.status_theme;
+
+    // Rate limit early.
+    if !check_rate_limit(&state, target, user_id, &username, RateAction::Media).await {
+        return Ok(());
+    }
+
+    if u64::from(video.file.size) > MAX_VIDEO_SIZE {
+        let _ = send_text(
+            &state,
+            target,
+            format!("{} Video too large. Maximum size is 20MB.", theme.failed),
+        )
+        .await;
+        return Ok(());
+    }
+
+    let status = send_text(
+        &state,
+        target,
+        format!("{}Processing video...", theme.deco("🎬")),
+    )
+    .await
+    .ok();
+
+    let ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let n = VIDEO_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let stem = format!("video_{ts}_{n}");
+    let video_path = state.cfg.temp_dir.join(format!("{stem}.mp4"));
+
+    if let Err(e) = download_video(&bot, video.file, &video_path).await {
+        let _ = send_text(
+            &state,
+            target,
+            format!(
+                "{} Failed to download video: {}",
+                theme.failed,
+                e.to_string().chars().take(200).collect::<String>()
+            ),
+        )
+        .await;
+        return Ok(());
+    }
+
+    let frames =
+        match extract_frames(&video_path, &state.cfg.temp_dir, &stem, video.duration_secs).await {
+            Ok(f) => f,
+            Err(e) => {
+                let msg = match e {
+                    FfmpegError::Missing => format!("{} {FFMPEG_MISSING}", theme.failed),
+                    FfmpegError::Failed(err) => format!(
+                        "{} Failed to extract video frames: {}",
+                        theme.failed,
+                        err.chars().take(200).collect::<String>()
+                    ),
+                };
+                let _ = send_text(&state, target, msg).await;
+                let _ = tokio::fs::remove_file(&video_path).await;
+                return Ok(());
+            }
+        };
+
+    let transcript = if state.cfg.transcription_available {
+        transcribe_audio(&state, target.chat_id.0, &video_path).await
+    } else {
+        None
+    };
+    let _ = tokio::fs::remove_file(&video_path).await;
+
+    if let Some(st) = status {
+        let _ = state.messenger.delete_message(st).await;
+    }
+
+    let prompt = build_video_prompt(
+        video.label,
+        video.duration_secs,
+        &frames,
+        transcript.as_deref(),
+        prompt_caption(&msg, &state).as_deref(),
+    );
+    let _ = run_prompt(
+        PromptContext {
+            bot,
+            state,
+            chat_id,
+            thread_id: target.thread_id,
+            user_id,
+            username,
+        },
+        "VIDEO",
+        prompt,
+        PromptOptions {
+            record_last_message: false,
+            rate_action: None,
+            plan_only: false,
+        },
+    )
+    .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_sampled_at_segment_midpoints() {
+        assert_eq!(frame_timestamps(8, 4), vec![1.0, 3.0, 5.0, 7.0]);
+        // Zero-length notes still yield a usable sample point.
+        assert_eq!(frame_timestamps(0, 1), vec![0.5]);
+        assert!(frame_timestamps(10, 0).is_empty());
+    }
+
+    #[test]
+    fn video_prompt_lists_frames_and_transcript() {
+        let frames = vec![
+            (PathBuf::from("/tmp/v_frame1.jpg"), 1.0),
+            (PathBuf::from("/tmp/v_frame2.jpg"), 3.0),
+        ];
+        let prompt =
+            build_video_prompt("Video", 4, &frames, Some(" hello "), Some("What is this?"));
+        assert_eq!(
+            prompt,
+            "[Video: 4s, 2 frames extracted:\n1. /tmp/v_frame1.jpg (at 1.0s)\n2. /tmp/v_frame2.jpg (at 3.0s)]\n\n[Audio transcript: hello]\n\nWhat is this?"
+        );
+
+        let no_caption = build_video_prompt("Video note", 4, &frames, None, None);
+        assert!(no_caption.ends_with("Please analyze this video using the frames above."));
+        assert!(!no_caption.contains("Audio transcript"));
+    }
+}
@@ -4,14 +4,17 @@ use std::{
     sync::Arc,
 };
 
-use teloxide::{net::Download, prelude::*};
+use teloxide::prelude::*;
 
-use ctb_core::utils::AuditEvent;
-use ctb_openai::OpenAiClient;
+use ctb_core::{formatting::escape_html, security::RateAction, utils::strip_interrupt_prefix};
+use ctb_openai::{OpenAiClient, TranscriptionConfig, TranscriptionError};
 
 use crate::router::AppState;
 
+use super::download::{download_to_path, TELEGRAM_DOWNLOAD_LIMIT};
 use super::prompt::{run_prompt, PromptContext, PromptOptions};
+use super::reply::with_forward_context;
+use super::{chat_target, check_rate_limit, send_text, transcription_prompt, username_of};
 
 static VOICE_COUNTER: AtomicUsize = AtomicUsize::new(1);
 
@@ -20,8 +23,6 @@ async fn download_voice(
     state: &AppState,
     voice: &teloxide::types::Voice,
 ) -> anyhow::Result<PathBuf> {
-    let file = bot.get_file(voice.file.id.clone()).await?;
-
     let ts = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
@@ -29,8 +30,7 @@ async fn download_voice(
     let n = VOICE_COUNTER.fetch_add(1, Ordering::SeqCst);
     let path = state.cfg.temp_dir.join(format!("voice_{ts}_{n}.ogg"));
 
-    let mut dst = tokio::fs::File::create(&path).await?;
-    bot.download_file(&file.path, &mut dst).await?;
+    download_to_path(bot, &voice.file, &path, TELEGRAM_DOWNLOAD_LIMIT).await?;
     Ok(path)
 }
 
@@ -43,93 +43,86 @@ pub async fn handle_voice(bot: Bot, msg: Message, state: Arc<AppState>) -> Respo
     };
 
     let user_id = user.id.0 as i64;
-    let username = user
-        .username
-        .clone()
-        .unwrap_or_else(|| "unknown".to_string());
+    let username = username_of(user);
     let chat_id = msg.chat.id.0;
+    let target = chat_target(&msg);
+    let theme = state.cfg.status_theme;
 
     if !state.cfg.transcription_available {
-        let _ = bot
-            .send_message(
-                teloxide::types::ChatId(chat_id),
-                "Voice transcription is not configured. Set OPENAI_API_KEY in .env",
-            )
-            .await;
+        let _ = send_text(
+            &state,
+            target,
+            "Voice transcription is not configured. Set OPENAI_API_KEY in .env",
+        )
+        .await;
         return Ok(());
     }
 
     // Rate limit early.
-    {
-        let mut rl = state.rate_limiter.lock().await;
-        let (ok, retry_after) = rl.check(ctb_core::domain::UserId(user_id));
-        if !ok {
-            let retry = retry_after.unwrap_or_default().as_secs_f64();
-            if let Err(e) = state
-                .audit
-                .write(AuditEvent::rate_limit(user_id, &username, retry))
-            {
-                eprintln!("[AUDIT] Failed to write rate_limit event: {e}");
-            }
-            let _ = bot
-                .send_message(
-                    teloxide::types::ChatId(chat_id),
-                    format!("⏳ Rate limited. Please wait {:.1} seconds.", retry),
-                )
-                .await;
-            return Ok(());
-        }
+    if !check_rate_limit(&state, target, user_id, &username, RateAction::Media).await {
+        return Ok(());
     }
 
-    let status = bot
-        .send_message(teloxide::types::ChatId(chat_id), "🎤 Transcribing...")
-        .await
-        .ok();
+    let status = send_text(
+        &state,
+        target,
+        format!("{}Transcribing...", theme.deco("🎤")),
+    )
+    .await
+    .ok();
 
     let voice_path = match download_voice(&bot, &state, voice).await {
         Ok(p) => p,
         Err(e) => {
-            let _ = bot
-                .send_message(
-                    teloxide::types::ChatId(chat_id),
-                    format!(
-                        "❌ Failed to download voice: {}",
-                        e.to_string().chars().take(200).collect::<String>()
-                    ),
-                )
-                .await;
+            let _ = send_text(
+                &state,
+                target,
+                format!(
+                    "{} Failed to download voice: {}",
+                    theme.failed,
+                    e.to_string().chars().take(200).collect::<String>()
+                ),
+            )
+            .await;
             return Ok(());
         }
     };
 
     let Some(k) = state.cfg.openai_api_key.as_ref() else {
-        let _ = bot
-            .send_message(
-                teloxide::types::ChatId(chat_id),
-                "Voice transcription is not configured. Set OPENAI_API_KEY in .env",
-            )
-            .await;
+        let _ = send_text(
+            &state,
+            target,
+            "Voice transcription is not configured. Set OPENAI_API_KEY in .env",
+        )
+        .await;
         let _ = tokio::fs::remove_file(&voice_path).await;
         return Ok(());
     };
 
-    let client = OpenAiClient::new(k.clone());
+    let client = OpenAiClient::with_config(k.clone(), TranscriptionConfig::from_config(&state.cfg));
     let transcript = match client
-        .transcribe_file(&voice_path, Some(&state.cfg.transcription_prompt))
+        .transcribe_file(
+            &voice_path,
+            Some(&transcription_prompt(&state, target.chat_id.0)),
+        )
         .await
     {
         Ok(t) => t,
         Err(e) => {
-            let msg = format!(
-                "❌ Transcription failed: {}",
-                e.to_string().chars().take(400).collect::<String>()
-            );
+            let msg = match e {
+                TranscriptionError::AudioTooLong(_) => {
+                    format!("{} Voice message is too long to transcribe. Try splitting it into shorter messages.", theme.failed)
+                }
+                e => format!(
+                    "{} Transcription failed: {}",
+                    theme.failed,
+                    e.to_string().chars().take(400).collect::<String>()
+                ),
+            };
             if let Some(st) = &status {
-                let _ = bot.edit_message_text(st.chat.id, st.id, msg).await;
+                let _ = state.messenger.edit_html(*st, &escape_html(&msg)).await;
             } else {
-                let _ = bot
-                    .send_message(teloxide::types::ChatId(chat_id), msg)
-                    .await;
+                let _ = send_text(&state, target, msg).await;
             }
             let _ = tokio::fs::remove_file(&voice_path).await;
             return Ok(());
@@ -143,16 +136,20 @@ pub async fn handle_voice(bot: Bot, msg: Message, state: Arc<AppState>) -> Respo
         } else {
             transcript.clone()
         };
-        let _ = bot
-            .edit_message_text(st.chat.id, st.id, format!("🎤 \"{preview}\""))
-            .await;
+        let preview = escape_html(&format!("{}\"{preview}\"", theme.deco("🎤")));
+        let _ = state.messenger.edit_html(*st, &preview).await;
     }
 
+    // The turn is already ours (a `!` caption interrupted on arrival); a spoken `!` is dropped
+    // before the forward header goes on.
+    let transcript = with_forward_context(&msg, strip_interrupt_prefix(&transcript).1);
+
     let _ = run_prompt(
         PromptContext {
             bot,
             state,
             chat_id,
+            thread_id: target.thread_id,
             user_id,
             username,
         },
@@ -160,7 +157,8 @@ pub async fn handle_voice(bot: Bot, msg: Message, state: Arc<AppState>) -> Respo
         transcript,
         PromptOptions {
             record_last_message: false,
-            skip_rate_limit: true,
+            rate_action: None,
+            plan_only: false,
         },
     )
     .await;
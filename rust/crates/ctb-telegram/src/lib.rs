@@ -2,22 +2,26 @@
 //!
 //! This crate implements the `ctb-core` MessagingPort over Telegram Bot API.
 
+use std::path::Path;
+
 use async_trait::async_trait;
 
 use teloxide::{
     prelude::*,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode},
 };
 
 use tokio::time::sleep;
 
 pub mod handlers;
 pub mod router;
+pub mod webhook;
 
 use ctb_core::{
-    domain::{ChatId, MessageId, MessageRef},
+    domain::{ChatId, ChatTarget, MessageId, MessageRef},
     errors::Error,
     messaging::{
+        callback_data::CallbackSigner,
         port::MessagingPort,
         types::{ChatAction, InlineKeyboard, MessagingCapabilities},
     },
@@ -27,11 +31,14 @@ use ctb_core::{
 #[derive(Clone)]
 pub struct TelegramMessenger {
     bot: Bot,
+    /// Seals every button's callback data (see `ctb_core::messaging::callback_data`).
+    signer: CallbackSigner,
 }
 
 impl TelegramMessenger {
     pub fn new(bot: Bot) -> Self {
-        Self { bot }
+        let signer = CallbackSigner::from_bot_token(bot.token());
+        Self { bot, signer }
     }
 
     pub fn bot(&self) -> Bot {
@@ -46,8 +53,49 @@ impl TelegramMessenger {
         teloxide::types::MessageId(message_id.0)
     }
 
+    fn tg_chat_action(action: ChatAction) -> teloxide::types::ChatAction {
+        match action {
+            ChatAction::Typing => teloxide::types::ChatAction::Typing,
+            ChatAction::UploadPhoto => teloxide::types::ChatAction::UploadPhoto,
+            ChatAction::UploadDocument => teloxide::types::ChatAction::UploadDocument,
+        }
+    }
+
+    fn markup(&self, chat_id: ChatId, keyboard: InlineKeyboard) -> Result<InlineKeyboardMarkup> {
+        let rows = keyboard
+            .rows
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|b| {
+                        let data = self.signer.seal(chat_id, &b.callback_data)?;
+                        Ok(InlineKeyboardButton::callback(b.label, data))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(InlineKeyboardMarkup::new(rows))
+    }
+
     fn map_err(e: teloxide::RequestError) -> Error {
-        Error::External(format!("telegram error: {e}"))
+        use teloxide::{ApiError, RequestError};
+        match &e {
+            RequestError::Api(
+                ApiError::MessageToEditNotFound
+                | ApiError::MessageToDeleteNotFound
+                | ApiError::MessageIdInvalid,
+            ) => Error::MessageGone(format!("telegram error: {e}")),
+            RequestError::Api(ApiError::BotBlocked) => {
+                Error::Unreachable("blocked by user".to_string())
+            }
+            RequestError::Api(ApiError::CantInitiateConversation | ApiError::ChatNotFound) => {
+                Error::Unreachable("never started the bot".to_string())
+            }
+            RequestError::Api(ApiError::UserDeactivated) => {
+                Error::Unreachable("account deleted".to_string())
+            }
+            _ => Error::External(format!("telegram error: {e}")),
+        }
     }
 
     async fn with_retry<T, Fut>(&self, mut op: impl FnMut() -> Fut) -> Result<T>
@@ -82,21 +130,27 @@ impl MessagingPort for TelegramMessenger {
             supports_reactions: true,
             supports_chat_actions: true,
             supports_inline_keyboards: true,
+            supports_documents: true,
             max_message_len: 4096,
         }
     }
 
-    async fn send_html(&self, chat_id: ChatId, html: &str) -> Result<MessageRef> {
+    async fn send_html(&self, target: ChatTarget, html: &str) -> Result<MessageRef> {
         let msg = self
             .with_retry(|| {
-                self.bot
-                    .send_message(Self::tg_chat(chat_id), html.to_string())
-                    .parse_mode(ParseMode::Html)
+                let req = self
+                    .bot
+                    .send_message(Self::tg_chat(target.chat_id), html.to_string())
+                    .parse_mode(ParseMode::Html);
+                match target.thread_id {
+                    Some(thread_id) => req.message_thread_id(thread_id),
+                    None => req,
+                }
             })
             .await?;
 
         Ok(MessageRef {
-            chat_id,
+            chat_id: target.chat_id,
             message_id: MessageId(msg.id.0),
         })
     }
@@ -124,14 +178,18 @@ impl MessagingPort for TelegramMessenger {
         Ok(())
     }
 
-    async fn send_chat_action(&self, chat_id: ChatId, action: ChatAction) -> Result<()> {
-        let tg_action = match action {
-            ChatAction::Typing => teloxide::types::ChatAction::Typing,
-            ChatAction::UploadPhoto => teloxide::types::ChatAction::UploadPhoto,
-            ChatAction::UploadDocument => teloxide::types::ChatAction::UploadDocument,
-        };
-        self.with_retry(|| self.bot.send_chat_action(Self::tg_chat(chat_id), tg_action))
-            .await?;
+    async fn send_chat_action(&self, target: ChatTarget, action: ChatAction) -> Result<()> {
+        let tg_action = Self::tg_chat_action(action);
+        self.with_retry(|| {
+            let req = self
+                .bot
+                .send_chat_action(Self::tg_chat(target.chat_id), tg_action);
+            match target.thread_id {
+                Some(thread_id) => req.message_thread_id(thread_id),
+                None => req,
+            }
+        })
+        .await?;
         Ok(())
     }
 
@@ -142,35 +200,68 @@ impl MessagingPort for TelegramMessenger {
 
     async fn send_inline_keyboard(
         &self,
-        chat_id: ChatId,
+        target: ChatTarget,
         text: &str,
         keyboard: InlineKeyboard,
     ) -> Result<MessageRef> {
-        let rows: Vec<Vec<InlineKeyboardButton>> = keyboard
-            .buttons
-            .into_iter()
-            .map(|b| vec![InlineKeyboardButton::callback(b.label, b.callback_data)])
-            .collect();
-        let markup = InlineKeyboardMarkup::new(rows);
+        let markup = self.markup(target.chat_id, keyboard)?;
 
         let msg = self
             .with_retry(|| {
-                self.bot
-                    .send_message(Self::tg_chat(chat_id), text.to_string())
+                let req = self
+                    .bot
+                    .send_message(Self::tg_chat(target.chat_id), text.to_string())
                     .parse_mode(ParseMode::Html)
-                    .reply_markup(markup.clone())
+                    .reply_markup(markup.clone());
+                match target.thread_id {
+                    Some(thread_id) => req.message_thread_id(thread_id),
+                    None => req,
+                }
             })
             .await?;
 
         Ok(MessageRef {
-            chat_id,
+            chat_id: target.chat_id,
             message_id: MessageId(msg.id.0),
         })
     }
 
-    async fn answer_callback_query(&self, callback_id: &str, text: Option<&str>) -> Result<()> {
+    async fn edit_inline_keyboard(
+        &self,
+        msg: MessageRef,
+        html: &str,
+        keyboard: Option<InlineKeyboard>,
+    ) -> Result<()> {
+        // An empty markup strips the buttons.
+        let markup = match keyboard {
+            Some(keyboard) => self.markup(msg.chat_id, keyboard)?,
+            None => InlineKeyboardMarkup::default(),
+        };
+        self.with_retry(|| {
+            self.bot
+                .edit_message_text(
+                    Self::tg_chat(msg.chat_id),
+                    Self::tg_msg_id(msg.message_id),
+                    html.to_string(),
+                )
+                .parse_mode(ParseMode::Html)
+                .reply_markup(markup.clone())
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: &str,
+        text: Option<&str>,
+        show_alert: bool,
+    ) -> Result<()> {
         self.with_retry(|| {
-            let mut req = self.bot.answer_callback_query(callback_id.to_string());
+            let mut req = self
+                .bot
+                .answer_callback_query(callback_id.to_string())
+                .show_alert(show_alert);
             if let Some(t) = text {
                 req = req.text(t.to_string());
             }
@@ -179,4 +270,58 @@ impl MessagingPort for TelegramMessenger {
         .await?;
         Ok(())
     }
+
+    async fn send_document(
+        &self,
+        target: ChatTarget,
+        path: &Path,
+        caption: Option<&str>,
+    ) -> Result<MessageRef> {
+        let msg = self
+            .with_retry(|| {
+                let mut req = self
+                    .bot
+                    .send_document(Self::tg_chat(target.chat_id), InputFile::file(path));
+                if let Some(c) = caption {
+                    req = req.caption(c.to_string());
+                }
+                match target.thread_id {
+                    Some(thread_id) => req.message_thread_id(thread_id),
+                    None => req,
+                }
+            })
+            .await?;
+
+        Ok(MessageRef {
+            chat_id: target.chat_id,
+            message_id: MessageId(msg.id.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_chat_action_maps_to_its_own_telegram_action() {
+        // A new variant fails to compile here; giving it the next index fails the test until
+        // it is also listed in `ChatAction::ALL`.
+        let position = |action| match action {
+            ChatAction::Typing => 0,
+            ChatAction::UploadPhoto => 1,
+            ChatAction::UploadDocument => 2,
+        };
+        let positions: Vec<usize> = ChatAction::ALL.into_iter().map(position).collect();
+        assert_eq!(positions, (0..=2).collect::<Vec<_>>());
+
+        let mapped: Vec<_> = ChatAction::ALL
+            .into_iter()
+            .map(TelegramMessenger::tg_chat_action)
+            .collect();
+        for (i, a) in mapped.iter().enumerate() {
+            assert!(!mapped[i + 1..].contains(a), "{a:?} mapped twice");
+        }
+        assert_eq!(mapped[0], teloxide::types::ChatAction::Typing);
+    }
 }
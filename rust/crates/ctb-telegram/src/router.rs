@@ -5,45 +5,106 @@ use std::{
     time::Duration,
 };
 
-use teloxide::{dispatching::Dispatcher, dptree, prelude::*};
+use teloxide::{dispatching::Dispatcher, dptree, error_handlers::LoggingErrorHandler, prelude::*};
 
 use tokio::sync::{Mutex, OwnedMutexGuard};
 
 use ctb_core::messaging::throttled::{ThrottleConfig, ThrottledMessenger};
 use ctb_core::{
-    config::Config, messaging::port::MessagingPort, scheduler::CronScheduler,
-    security::RateLimiter, session::ClaudeSession, usage::UsageService, utils::AuditLogger,
+    ask_user::{awaits_sync_text_answer, parse_option_number, sweep_request_files, ASK_USER_TTL},
+    chat_settings::ChatSettings,
+    config::Config,
+    grants::DirectoryGrants,
+    health::run_health_checks,
+    i18n::Messages,
+    location::{CoordinatesOnly, ReverseGeocoder},
+    messaging::{callback_data::CallbackSigner, port::MessagingPort},
+    metrics::ProcessMetrics,
+    ocr::{ImageTextExtractor, Tesseract},
+    pipeline::SuspendedStreams,
+    processes::{format_orphan_notice, recover_orphans, ProcessTracker},
+    prompt_templates::TemplateStore,
+    restart::{self, RESTART_STALE_AFTER},
+    scheduler::CronScheduler,
+    security::{owner_user_id, role_of, scrub_secrets, RateLimiter, Role},
+    session::{ClaudeSession, SessionRegistry},
+    shutdown::{self, Shutdown, SHUTDOWN_GRACE},
+    theme::StatusTheme,
+    usage::{monitor::run_usage_monitor, prefetch::run_usage_prefetch, UsageService},
+    usage_ledger::UsageLedger,
+    utils::AuditLogger,
+    vocab::Vocabulary,
 };
 use ctb_core::{
-    domain::ChatId,
+    domain::{ChatId, ChatTarget},
     formatting::{convert_markdown_to_html, escape_html},
 };
+use ctb_core::{log_info, log_line, messaging::audited::AuditedMessenger};
 
 use crate::handlers;
+use crate::webhook::{self, WebhookOptions};
 use crate::TelegramMessenger;
 
 #[derive(Clone)]
 pub struct AppState {
     pub cfg: Arc<Config>,
-    pub session: Arc<ClaudeSession>,
+    pub sessions: Arc<SessionRegistry>,
     pub messenger: Arc<dyn MessagingPort>,
     pub scheduler: Arc<CronScheduler>,
     pub usage: Arc<UsageService>,
     pub rate_limiter: Arc<Mutex<RateLimiter>>,
     pub chat_locks: Arc<ChatLocks>,
+    /// Albums being collected (photos and documents share one buffer per `media_group_id`).
+    pub media_groups: Arc<handlers::MediaGroupBuffer>,
+    /// Text messages sent in quick succession, waiting to become one prompt (`TEXT_COALESCE`).
+    pub text_bursts: Arc<handlers::TextBurstBuffer>,
     pub audit: Arc<AuditLogger>,
+    pub ledger: Arc<UsageLedger>,
+    pub geocoder: Arc<dyn ReverseGeocoder>,
+    /// OCR for photo prompts the model can't read itself.
+    pub ocr: Arc<dyn ImageTextExtractor>,
+    /// Process-wide pipeline timings behind `/perf`.
+    pub metrics: Arc<ProcessMetrics>,
+    /// Per-chat `/grant` directories (shared with the sessions).
+    pub grants: Arc<DirectoryGrants>,
+    /// Per-chat `/settings` overrides (shared with the sessions).
+    pub chat_settings: Arc<ChatSettings>,
+    /// Streamed messages of turns waiting for an `ask_user` answer.
+    pub suspended_streams: Arc<SuspendedStreams>,
+    /// Opens the signed callback data of inline buttons (sealed by `TelegramMessenger`).
+    pub callback_signer: CallbackSigner,
+    /// Prompts held back until the user picks "continue" or "start fresh" after idle expiry.
+    pub idle_prompts: Arc<handlers::IdlePrompts>,
+    /// Running `/logs follow` tasks.
+    pub log_follows: Arc<handlers::LogFollows>,
+    /// Chats whose next text message renames a session (`/sessions` rename button).
+    pub pending_titles: Arc<handlers::PendingTitles>,
+    /// Per-chat `/vocab` terms for transcriptions.
+    pub vocabulary: Arc<Vocabulary>,
+    /// `/t` prompt templates from `templates.yaml`.
+    pub templates: Arc<TemplateStore>,
+    /// Bot `@username` (without `@`) for group mention gating; `None` if `get_me` failed.
+    pub bot_username: Option<String>,
 }
 
+impl AppState {
+    /// The catalog `target`'s chat is answered in (`BOT_LOCALE` or its `/settings language`).
+    pub fn messages(&self, target: ChatTarget) -> &'static Messages {
+        self.chat_settings.messages(&self.cfg, target.chat_id.0)
+    }
+}
+
+/// Per-conversation locks; forum topics are locked independently of each other.
 #[derive(Default)]
 pub struct ChatLocks {
-    inner: Mutex<HashMap<i64, Arc<Mutex<()>>>>,
+    inner: Mutex<HashMap<ChatTarget, Arc<Mutex<()>>>>,
 }
 
 impl ChatLocks {
-    pub async fn lock_chat(&self, chat_id: i64) -> OwnedMutexGuard<()> {
+    pub async fn lock_chat(&self, target: ChatTarget) -> OwnedMutexGuard<()> {
         let lock = {
             let mut map = self.inner.lock().await;
-            map.entry(chat_id)
+            map.entry(target)
                 .or_insert_with(|| Arc::new(Mutex::new(())))
                 .clone()
         };
@@ -51,88 +112,254 @@ impl ChatLocks {
     }
 }
 
-pub async fn run_polling(cfg: Arc<Config>, session: Arc<ClaudeSession>) -> anyhow::Result<()> {
+/// Receive updates by long polling `getUpdates` (the default).
+pub async fn run_polling(
+    cfg: Arc<Config>,
+    session: Arc<ClaudeSession>,
+    shutdown: Shutdown,
+    metrics: Arc<ProcessMetrics>,
+    grants: Arc<DirectoryGrants>,
+    chat_settings: Arc<ChatSettings>,
+) -> anyhow::Result<()> {
     let bot = Bot::new(cfg.telegram_bot_token.clone());
+    let mut dispatcher =
+        build_dispatcher(bot, cfg, session, shutdown, metrics, grants, chat_settings).await;
+    dispatcher.dispatch().await;
+    Ok(())
+}
 
-    // Basic startup info.
-    if let Ok(me) = bot.get_me().await {
-        println!("ctb (Rust) started: @{}", me.username());
+/// Receive updates on a webhook (`TELEGRAM_WEBHOOK_URL`); the webhook is deleted again on
+/// shutdown so a later polling run works.
+pub async fn run_webhook(
+    cfg: Arc<Config>,
+    session: Arc<ClaudeSession>,
+    shutdown: Shutdown,
+    metrics: Arc<ProcessMetrics>,
+    grants: Arc<DirectoryGrants>,
+    chat_settings: Arc<ChatSettings>,
+    options: WebhookOptions,
+) -> anyhow::Result<()> {
+    let bot = Bot::new(cfg.telegram_bot_token.clone());
+    // Updates arriving before the dispatcher runs wait in the listener's queue.
+    let listener = webhook::start(&bot, &options).await?;
+    let mut dispatcher = build_dispatcher(
+        bot.clone(),
+        cfg,
+        session,
+        shutdown,
+        metrics,
+        grants,
+        chat_settings,
+    )
+    .await;
+    dispatcher
+        .dispatch_with_listener(
+            listener,
+            LoggingErrorHandler::with_custom_text("Webhook listener error"),
+        )
+        .await;
+    match bot.delete_webhook().await {
+        Ok(_) => log_info!("[WEBHOOK] Webhook deleted"),
+        Err(e) => log_line!("[WEBHOOK] Failed to delete webhook: {e}"),
+    }
+    Ok(())
+}
+
+/// Dispatcher queue of an update. Updates of a chat are handled in order, but answers get a
+/// queue of their own: a sync `ask_user` tap, or a number typed in reply to a sync question shown
+/// as a list, must reach the bot while the prompt that asked is still running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct UpdateKey {
+    chat_id: teloxide::types::ChatId,
+    answer: bool,
+}
+
+fn update_key(update: &Update) -> Option<UpdateKey> {
+    let chat_id = update.chat()?.id;
+    let answer = match &update.kind {
+        teloxide::types::UpdateKind::CallbackQuery(_) => true,
+        teloxide::types::UpdateKind::Message(msg) => msg
+            .text()
+            .and_then(parse_option_number)
+            .is_some_and(|_| awaits_sync_text_answer(chat_id.0)),
+        _ => false,
+    };
+    Some(UpdateKey { chat_id, answer })
+}
+
+/// `RATE_LIMIT_*` token bucket; owners are exempt with `RATE_LIMIT_OWNER_BYPASS`.
+fn rate_limiter(cfg: &Config) -> RateLimiter {
+    let limiter = RateLimiter::new(
+        cfg.rate_limit_enabled,
+        cfg.rate_limit_requests,
+        cfg.rate_limit_window,
+    )
+    .with_burst(cfg.rate_limit_burst);
+    if !cfg.rate_limit_owner_bypass {
+        return limiter;
     }
-    println!("Working directory: {}", cfg.claude_working_dir.display());
-    println!("Allowed users: {}", cfg.telegram_allowed_users.len());
+    limiter.with_exempt(
+        cfg.telegram_allowed_users
+            .iter()
+            .map(|&u| ctb_core::domain::UserId(u))
+            .filter(|&u| role_of(u, cfg) == Some(Role::Owner)),
+    )
+}
+
+/// Everything both update sources share: startup work, [`AppState`], the handler tree and the
+/// shutdown drain.
+async fn build_dispatcher(
+    bot: Bot,
+    cfg: Arc<Config>,
+    session: Arc<ClaudeSession>,
+    shutdown: Shutdown,
+    metrics: Arc<ProcessMetrics>,
+    grants: Arc<DirectoryGrants>,
+    chat_settings: Arc<ChatSettings>,
+) -> Dispatcher<Bot, teloxide::RequestError, UpdateKey> {
+    // Basic startup info.
+    let bot_username = match bot.get_me().await {
+        Ok(me) => {
+            log_info!("ctb (Rust) started: @{}", me.username());
+            me.user.username.clone()
+        }
+        Err(e) => {
+            log_line!("Failed to fetch bot info: {e} (group mentions will not be recognised)");
+            None
+        }
+    };
+    log_info!("Working directory: {}", cfg.claude_working_dir.display());
+    log_info!("Allowed users: {}", cfg.telegram_allowed_users.len());
 
     // Auto-resume previous session if available (parity with TS).
-    let resumed = match session.resume_last().await {
+    let resumed = match session.resume_last(cfg.bot_locale.messages()).await {
         Ok((true, msg)) => {
-            println!("Auto-resumed: {msg}");
+            log_info!("Auto-resumed: {msg}");
             true
         }
         Ok((false, _)) => {
-            println!("No previous session to resume");
+            log_info!("No previous session to resume");
             false
         }
         Err(e) => {
-            eprintln!("Failed to resume previous session: {e}");
+            log_line!("Failed to resume previous session: {e}");
             false
         }
     };
 
+    // Wrap the raw Telegram messenger with a throttling decorator to reduce 429s for streaming-heavy
+    // workloads. We still keep a 429 RetryAfter retry at the Telegram adapter layer.
+    let audit = Arc::new(AuditLogger::new(
+        cfg.audit_log_path.clone(),
+        cfg.audit_log_json,
+    ));
+    let mut raw_messenger: Arc<dyn MessagingPort> = Arc::new(TelegramMessenger::new(bot.clone()));
+    if cfg.audit_delivery {
+        // Inside the throttle, so each recorded event is a real API call.
+        raw_messenger = Arc::new(AuditedMessenger::new(raw_messenger, audit.clone()));
+    }
+    let messenger: Arc<dyn MessagingPort> = Arc::new(ThrottledMessenger::new(
+        raw_messenger,
+        ThrottleConfig {
+            group_budget: cfg.group_messages_per_minute,
+            ..ThrottleConfig::default()
+        },
+    ));
+
     // If we were restarted via a command, update the "restarting..." message.
     // Data format matches TS: { chat_id, message_id, timestamp }.
-    if cfg.restart_file.exists() {
-        #[derive(serde::Deserialize)]
-        struct RestartData {
-            chat_id: i64,
-            message_id: i32,
-            timestamp: u64,
+    let now_ms = restart::now_ms();
+    if let Some(data) =
+        restart::take_restart_marker(session.store().as_ref(), now_ms, RESTART_STALE_AFTER)
+    {
+        if let Err(e) =
+            restart::confirm_restart(messenger.as_ref(), cfg.status_theme, &data, now_ms).await
+        {
+            log_line!("Failed to confirm restart: {e}");
         }
+    }
 
-        let cleanup = || {
-            let _ = std::fs::remove_file(&cfg.restart_file);
-        };
+    // ask_user requests from before the restart can no longer be resumed.
+    let swept = sweep_request_files(&cfg.temp_dir, ASK_USER_TTL);
+    if swept > 0 {
+        log_line!("[ASK_USER] Removed {swept} stale request file(s)");
+    }
 
-        match std::fs::read_to_string(&cfg.restart_file)
-            .ok()
-            .and_then(|txt| serde_json::from_str::<RestartData>(&txt).ok())
+    // Claude CLI processes a crashed predecessor never reaped; stopped before anything new runs.
+    let orphans = recover_orphans(
+        Arc::new(ProcessTracker::from_config(&cfg)),
+        cfg.cli_kill_grace,
+    )
+    .await;
+    if let Some(owner) = owner_user_id(&cfg).filter(|_| !orphans.is_empty()) {
+        let notice = cfg
+            .status_theme
+            .render(&format_orphan_notice(&orphans))
+            .into_owned();
+        if let Err(e) = messenger
+            .send_html(ChatTarget::from(ChatId(owner)), &notice)
+            .await
         {
-            Some(data) => {
-                let now_ms = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis() as u64;
-                let age = now_ms.saturating_sub(data.timestamp);
-                if age < 30_000 {
-                    let _ = bot
-                        .edit_message_text(
-                            teloxide::types::ChatId(data.chat_id),
-                            teloxide::types::MessageId(data.message_id),
-                            "✅ Bot restarted",
-                        )
-                        .await;
-                }
-                cleanup();
-            }
-            None => cleanup(),
+            log_line!("[PROCESS] Failed to notify owner: {e}");
         }
     }
 
-    // Wrap the raw Telegram messenger with a throttling decorator to reduce 429s for streaming-heavy
-    // workloads. We still keep a 429 RetryAfter retry at the Telegram adapter layer.
-    let raw_messenger: Arc<dyn MessagingPort> = Arc::new(TelegramMessenger::new(bot.clone()));
-    let messenger: Arc<dyn MessagingPort> = Arc::new(ThrottledMessenger::new(
-        raw_messenger,
-        ThrottleConfig::default(),
-    ));
     let scheduler = Arc::new(CronScheduler::new(
         cfg.clone(),
         session.clone(),
         messenger.clone(),
     ));
     if let Err(e) = scheduler.start().await {
-        eprintln!("[CRON] Failed to start scheduler: {e}");
+        log_line!("[CRON] Failed to start scheduler: {e}");
+    }
+    if let Err(e) = scheduler.restore_pending_jobs().await {
+        log_line!("[CRON] Failed to restore queued jobs: {e}");
+    }
+    if let Err(e) = scheduler.restore_reminders().await {
+        log_line!("[CRON] Failed to restore reminders: {e}");
     }
     scheduler.ensure_watcher().await;
     let usage = Arc::new(UsageService::new());
+    // With USAGE_PREFETCH, warm the provider caches in the background so the first /stats is fast.
+    tokio::spawn(run_usage_prefetch(
+        usage.clone(),
+        cfg.usage_prefetch,
+        shutdown.clone(),
+    ));
+
+    // Warn the owner before provider rate-limit windows run out.
+    if let (Some(interval), Some(&owner)) = (
+        cfg.usage_monitor_interval,
+        cfg.telegram_allowed_users.first(),
+    ) {
+        tokio::spawn(run_usage_monitor(
+            usage.clone(),
+            messenger.clone(),
+            ChatTarget::from(ChatId(owner)),
+            cfg.status_theme,
+            interval,
+            shutdown.clone(),
+        ));
+    }
+
+    // Boot diagnostics: log everything, tell the owner only about problems.
+    if let Some(&owner) = cfg.telegram_allowed_users.first() {
+        let cfg = cfg.clone();
+        let messenger = messenger.clone();
+        tokio::spawn(async move {
+            let report = run_health_checks(&cfg).await;
+            for line in report.to_text().lines() {
+                log_info!("[HEALTH] {line}");
+            }
+            if let Some(html) = report.problems_html() {
+                let target = ChatTarget::from(ChatId(owner));
+                let html = cfg.status_theme.render(&html);
+                if let Err(e) = messenger.send_html(target, &html).await {
+                    log_line!("[HEALTH] Failed to send startup diagnostics: {e}");
+                }
+            }
+        });
+    }
 
     // Send startup notification (best-effort) to the first allowed user (parity with TS).
     if !cfg.telegram_allowed_users.is_empty() {
@@ -142,40 +369,83 @@ pub async fn run_polling(cfg: Arc<Config>, session: Arc<ClaudeSession>) -> anyho
         tokio::spawn(async move {
             tokio::time::sleep(Duration::from_secs(2)).await;
             if let Err(e) = send_startup_notification(cfg, session, messenger, resumed).await {
-                eprintln!("Startup notification failed: {e}");
+                log_line!("Startup notification failed: {e}");
             }
         });
     }
 
+    let templates = Arc::new(TemplateStore::from_config(&cfg));
+    match templates.templates() {
+        Ok(list) if !list.is_empty() => {
+            log_info!("[TEMPLATES] Loaded {} prompt template(s)", list.len())
+        }
+        Ok(_) => {}
+        Err(e) => log_line!("[TEMPLATES] {e}"),
+    }
+
     let state = Arc::new(AppState {
         cfg: cfg.clone(),
-        session,
+        sessions: Arc::new(SessionRegistry::new(session)),
         messenger,
         scheduler,
         usage,
-        rate_limiter: Arc::new(Mutex::new(RateLimiter::new(
-            cfg.rate_limit_enabled,
-            cfg.rate_limit_requests,
-            cfg.rate_limit_window,
-        ))),
+        rate_limiter: Arc::new(Mutex::new(rate_limiter(&cfg))),
         chat_locks: Arc::new(ChatLocks::default()),
-        audit: Arc::new(AuditLogger::new(
-            cfg.audit_log_path.clone(),
-            cfg.audit_log_json,
-        )),
+        media_groups: handlers::MediaGroupBuffer::new(),
+        text_bursts: handlers::TextBurstBuffer::new(),
+        audit,
+        ledger: Arc::new(UsageLedger::new(cfg.usage_ledger_path.clone())),
+        geocoder: Arc::new(CoordinatesOnly),
+        ocr: Arc::new(Tesseract::default()),
+        metrics,
+        grants,
+        chat_settings,
+        suspended_streams: Arc::new(SuspendedStreams::new(ASK_USER_TTL)),
+        callback_signer: CallbackSigner::from_bot_token(bot.token()),
+        idle_prompts: Arc::new(handlers::IdlePrompts::default()),
+        log_follows: Arc::new(handlers::LogFollows::default()),
+        pending_titles: Arc::new(handlers::PendingTitles::default()),
+        vocabulary: Arc::new(Vocabulary::from_config(&cfg)),
+        templates,
+        bot_username,
     });
 
     let handler = dptree::entry()
         .branch(Update::filter_callback_query().endpoint(handlers::handle_callback))
-        .branch(Update::filter_message().endpoint(handlers::handle_message));
-
-    Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![state])
-        .build()
-        .dispatch()
+        .branch(Update::filter_message().endpoint(handlers::handle_message))
+        .branch(Update::filter_edited_message().endpoint(handlers::handle_edited_message));
+
+    let dispatcher = Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![state.clone()])
+        .distribution_function(update_key)
+        .build();
+
+    // On SIGINT/SIGTERM: abort in-flight queries (bounded), stop cron, then stop receiving.
+    let dispatcher_token = dispatcher.shutdown_token();
+    tokio::spawn(async move {
+        shutdown.triggered().await;
+        log_info!("[SHUTDOWN] Draining in-flight queries");
+        let drained = shutdown::drain(
+            &shutdown,
+            &state.sessions,
+            Some(&state.scheduler),
+            SHUTDOWN_GRACE,
+        )
         .await;
+        log_info!("[SHUTDOWN] Drain finished (clean: {drained})");
+        loop {
+            match dispatcher_token.shutdown() {
+                Ok(done) => {
+                    done.await;
+                    break;
+                }
+                // Dispatcher not running yet; retry shortly.
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    });
 
-    Ok(())
+    dispatcher
 }
 
 async fn send_startup_notification(
@@ -187,20 +457,29 @@ async fn send_startup_notification(
     let Some(&user_id) = cfg.telegram_allowed_users.first() else {
         return Ok(());
     };
-    let chat_id = ChatId(user_id);
+    let target = ChatTarget::from(ChatId(user_id));
 
     // PRIORITY 1: .last-save-id auto-load mechanism.
     let save_id_file = cfg.claude_working_dir.join(".last-save-id");
     if save_id_file.exists() {
-        match try_auto_load(session.clone(), messenger.clone(), chat_id, &save_id_file).await {
+        match try_auto_load(
+            session.clone(),
+            messenger.clone(),
+            cfg.status_theme,
+            target,
+            &save_id_file,
+        )
+        .await
+        {
             Ok(true) => return Ok(()), // Successfully restored; skip normal startup message.
             Ok(false) => {}            // Not restored; fall through.
             Err(e) => {
                 let msg = format!(
-                    "🚨 <b>Auto-load Failed</b>\n\nError: <code>{}</code>\n\n⚠️ Starting fresh session. Check logs for recovery.",
-                    escape_html(&sanitize_error(&cfg, &e.to_string()))
+                    "{0} <b>Auto-load Failed</b>\n\nError: <code>{1}</code>\n\n{0} Starting fresh session. Check logs for recovery.",
+                    cfg.status_theme.warning,
+                    escape_html(&scrub_secrets(&e.to_string(), &cfg))
                 );
-                let _ = messenger.send_html(chat_id, &msg).await;
+                let _ = messenger.send_html(target, &msg).await;
             }
         }
     }
@@ -229,7 +508,11 @@ async fn send_startup_notification(
         ));
     }
     let _ = messenger
-        .send_html(chat_id, &convert_markdown_to_html(&header_md))
+        .send_html(
+            target,
+            &cfg.status_theme
+                .render(&convert_markdown_to_html(&header_md)),
+        )
         .await;
 
     let prompt = if resumed {
@@ -239,7 +522,7 @@ async fn send_startup_notification(
     };
 
     let _ = session
-        .send_message_to_chat(chat_id, &prompt, messenger.clone())
+        .send_message_to_chat(target, &prompt, messenger.clone())
         .await;
 
     Ok(())
@@ -248,7 +531,8 @@ async fn send_startup_notification(
 async fn try_auto_load(
     session: Arc<ClaudeSession>,
     messenger: Arc<dyn MessagingPort>,
-    chat_id: ChatId,
+    theme: &StatusTheme,
+    target: ChatTarget,
     save_id_file: &Path,
 ) -> anyhow::Result<bool> {
     let save_id = std::fs::read_to_string(save_id_file)
@@ -262,14 +546,15 @@ async fn try_auto_load(
     }
 
     let notice = format!(
-        "🔄 <b>Auto-restoring context</b>\n\nSave ID: <code>{}</code>\n\nExecuting /load...",
+        "{} <b>Auto-restoring context</b>\n\nSave ID: <code>{}</code>\n\nExecuting /load...",
+        theme.running,
         escape_html(&save_id)
     );
-    let _ = messenger.send_html(chat_id, &notice).await;
+    let _ = messenger.send_html(target, &notice).await;
 
     let load_prompt = format!("Skill tool with skill='oh-my-claude:load' and args='{save_id}'");
     let out = session
-        .send_message_to_chat(chat_id, &load_prompt, messenger.clone())
+        .send_message_to_chat(target, &load_prompt, messenger.clone())
         .await
         .map_err(|e| anyhow::anyhow!("load failed: {e}"))?;
 
@@ -283,10 +568,11 @@ async fn try_auto_load(
     let _ = std::fs::remove_file(save_id_file);
 
     let ok_msg = format!(
-        "✅ <b>Context Restored</b>\n\nResumed from save: <code>{}</code>",
+        "{} <b>Context Restored</b>\n\nResumed from save: <code>{}</code>",
+        theme.done,
         escape_html(&save_id)
     );
-    let _ = messenger.send_html(chat_id, &ok_msg).await;
+    let _ = messenger.send_html(target, &ok_msg).await;
 
     Ok(true)
 }
@@ -324,17 +610,6 @@ fn latest_restart_context(dir: &Path) -> Option<(String, String)> {
     Some((name, content))
 }
 
-fn sanitize_error(cfg: &Config, s: &str) -> String {
-    let mut out = s.to_string();
-    if let Ok(home) = std::env::var("HOME") {
-        out = out.replace(&home, "~");
-    }
-    if let Some(wd) = cfg.claude_working_dir.to_str() {
-        out = out.replace(wd, "~");
-    }
-    out
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,217 @@
+//! Webhook mode: Telegram POSTs updates to `TELEGRAM_WEBHOOK_URL` instead of the bot
+//! long-polling `getUpdates`.
+//!
+//! The listener is teloxide's webhook router, served over plain HTTP on
+//! `TELEGRAM_WEBHOOK_LISTEN`; TLS is left to the reverse proxy in front of it. Only the public
+//! URL's path is served, and requests must carry the configured secret in
+//! `X-Telegram-Bot-Api-Secret-Token`.
+
+use std::{convert::Infallible, net::SocketAddr};
+
+use anyhow::{anyhow, bail};
+use hyper::Server;
+use teloxide::{
+    prelude::*,
+    update_listeners::{webhooks, UpdateListener},
+};
+use url::Url;
+
+use ctb_core::config::Config;
+use ctb_core::{log_info, log_line};
+
+/// Ports Telegram delivers webhooks to.
+const WEBHOOK_PORTS: [u16; 4] = [443, 80, 88, 8443];
+
+/// Validated `TELEGRAM_WEBHOOK_*` settings.
+#[derive(Clone, Debug)]
+pub struct WebhookOptions {
+    pub url: Url,
+    pub listen: SocketAddr,
+    pub secret: String,
+}
+
+impl WebhookOptions {
+    /// Options from the config; `None` when no webhook URL is set (long polling).
+    pub fn from_config(cfg: &Config) -> anyhow::Result<Option<Self>> {
+        let Some(url) = &cfg.telegram_webhook_url else {
+            return Ok(None);
+        };
+        let Some(secret) = &cfg.telegram_webhook_secret else {
+            bail!("TELEGRAM_WEBHOOK_SECRET is required when TELEGRAM_WEBHOOK_URL is set");
+        };
+        Self::new(url, cfg.telegram_webhook_listen, secret).map(Some)
+    }
+
+    pub fn new(public_url: &str, listen: SocketAddr, secret: &str) -> anyhow::Result<Self> {
+        let url = Url::parse(public_url.trim())
+            .map_err(|e| anyhow!("TELEGRAM_WEBHOOK_URL is not a valid URL: {e}"))?;
+        if url.scheme() != "https" {
+            bail!("TELEGRAM_WEBHOOK_URL must use https:// (Telegram only delivers to HTTPS)");
+        }
+        if url.host_str().is_none_or(str::is_empty) {
+            bail!("TELEGRAM_WEBHOOK_URL has no host");
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            bail!("TELEGRAM_WEBHOOK_URL must not have a query or fragment");
+        }
+        let port = url.port_or_known_default().unwrap_or(443);
+        if !WEBHOOK_PORTS.contains(&port) {
+            bail!("TELEGRAM_WEBHOOK_URL port must be one of 443, 80, 88, 8443 (got {port})");
+        }
+        if !is_valid_secret(secret) {
+            bail!("TELEGRAM_WEBHOOK_SECRET must be 1-256 characters of A-Z, a-z, 0-9, _ and -");
+        }
+        Ok(Self {
+            url,
+            listen,
+            secret: secret.to_string(),
+        })
+    }
+
+    /// Path the listener serves; the proxy must forward it unchanged.
+    pub fn path(&self) -> &str {
+        self.url.path()
+    }
+
+    fn listener_options(&self) -> webhooks::Options {
+        webhooks::Options::new(self.listen, self.url.clone()).secret_token(self.secret.clone())
+    }
+}
+
+/// Telegram's rule for `secret_token`.
+fn is_valid_secret(secret: &str) -> bool {
+    (1..=256).contains(&secret.len())
+        && secret
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Bind the listener, then register the webhook with Telegram. The returned listener feeds the
+/// dispatcher; stopping it shuts the server down. The caller deletes the webhook afterwards.
+pub async fn start(
+    bot: &Bot,
+    opts: &WebhookOptions,
+) -> anyhow::Result<impl UpdateListener<Err = Infallible>> {
+    // Bind first, so a taken port fails startup before the webhook is registered.
+    let server = Server::try_bind(&opts.listen)
+        .map_err(|e| anyhow!("cannot listen on {}: {e}", opts.listen))?;
+    let (mut listener, stopped, router) = webhooks::axum_no_setup(opts.listener_options());
+    bot.set_webhook(opts.url.clone())
+        .secret_token(opts.secret.clone())
+        .await?;
+    log_info!(
+        "[WEBHOOK] Listening on {} for {}",
+        opts.listen,
+        opts.url.as_str()
+    );
+
+    let stop_token = listener.stop_token();
+    tokio::spawn(async move {
+        let served = server
+            .serve(router.into_make_service())
+            .with_graceful_shutdown(stopped)
+            .await;
+        if let Err(e) = served {
+            log_line!("[WEBHOOK] Server failed: {e}");
+            stop_token.stop();
+        }
+    });
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 8080))
+    }
+
+    #[test]
+    fn validates_public_url_and_secret() {
+        let ok =
+            WebhookOptions::new("https://bot.example.com/tg/hook", listen(), "s3cret_-").unwrap();
+        assert_eq!(ok.path(), "/tg/hook");
+        assert_eq!(
+            WebhookOptions::new("https://bot.example.com:8443", listen(), "x")
+                .unwrap()
+                .path(),
+            "/"
+        );
+
+        for (url, secret, why) in [
+            ("http://bot.example.com/hook", "x", "https"),
+            ("bot.example.com/hook", "x", "valid URL"),
+            ("https://bot.example.com:9000/hook", "x", "port"),
+            ("https://bot.example.com/hook?a=1", "x", "query"),
+            ("https://bot.example.com/hook", "not secret!", "SECRET"),
+            ("https://bot.example.com/hook", "", "SECRET"),
+        ] {
+            let err = WebhookOptions::new(url, listen(), secret).unwrap_err();
+            assert!(err.to_string().contains(why), "{url}: {err}");
+        }
+    }
+
+    /// Raw HTTP/1.1 request to `addr`; returns the status code.
+    async fn post(addr: SocketAddr, path: &str, secret: Option<&str>) -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let body = serde_json::json!({
+            "update_id": 7,
+            "message": {
+                "message_id": 1,
+                "date": 0,
+                "chat": {"id": 42, "type": "private", "first_name": "A"},
+                "from": {"id": 42, "is_bot": false, "first_name": "A"},
+                "text": "hi"
+            }
+        })
+        .to_string();
+        let secret = secret
+            .map(|s| format!("X-Telegram-Bot-Api-Secret-Token: {s}\r\n"))
+            .unwrap_or_default();
+        let request = format!(
+            "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             {secret}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response[9..12].parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_the_secret_path_reaches_the_dispatcher() {
+        use teloxide::update_listeners::AsUpdateStream;
+        use tokio_stream::StreamExt;
+
+        let opts = WebhookOptions::new(
+            "https://bot.example.com/tg/hook",
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            "s3cret",
+        )
+        .unwrap();
+        let (mut listener, stopped, router) = webhooks::axum_no_setup(opts.listener_options());
+        let server = Server::try_bind(&opts.listen)
+            .unwrap()
+            .serve(router.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server.with_graceful_shutdown(stopped));
+
+        assert_eq!(post(addr, "/other", Some("s3cret")).await, 404);
+        assert_eq!(post(addr, "/tg/hook", None).await, 401);
+        assert_eq!(post(addr, "/tg/hook", Some("guess")).await, 401);
+        assert_eq!(post(addr, "/tg/hook", Some("s3cret")).await, 200);
+
+        let stop_token = listener.stop_token();
+        let updates = listener.as_stream();
+        tokio::pin!(updates);
+        let Some(Ok(update)) = updates.next().await else {
+            panic!("update was not forwarded");
+        };
+        assert_eq!(update.id, 7);
+        stop_token.stop();
+    }
+}
@@ -1,35 +1,210 @@
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use ctb_claude_cli::ClaudeCliClient;
+use ctb_telegram::webhook::WebhookOptions;
 
 use ctb_core::{
+    chat_settings::ChatSettings,
     config::Config,
-    model::types::{ClaudeCliConfig, PermissionMode},
+    grants::DirectoryGrants,
+    health::{run_health_checks, verify_claude_cli, Severity},
+    log_info, log_line,
+    metrics::ProcessMetrics,
+    model::{
+        client::ModelClient,
+        fallback::FallbackModelClient,
+        types::{ClaudeCliConfig, PermissionMode},
+    },
+    processes::ProcessTracker,
+    security_events::SecurityMonitor,
     session::ClaudeSession,
+    shutdown::Shutdown,
+    state_export::{BotState, ImportSummary, StateExport},
+    undo::UndoJournal,
+    vocab::Vocabulary,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), ctb_core::Error> {
-    ctb_core::logging::init("ctb")?;
-
     let cfg = Arc::new(Config::load()?);
+    ctb_core::logging::init("ctb", cfg.log_buffer_lines)?;
+    ctb_core::storage::migrate_legacy_files(&cfg);
+
+    // `ctb doctor`: print the self-check and exit non-zero on errors.
+    if std::env::args().nth(1).as_deref() == Some("doctor") {
+        let report = run_health_checks(&cfg).await;
+        println!("{}", report.to_text());
+        std::process::exit(i32::from(report.worst() == Severity::Error));
+    }
+    // `ctb import-state <file>`: restore an `/export_state` document. Run it while the bot is
+    // stopped, or the running bot overwrites the restored files.
+    if std::env::args().nth(1).as_deref() == Some("import-state") {
+        let Some(file) = std::env::args().nth(2) else {
+            return Err(ctb_core::Error::Config(
+                "usage: ctb import-state <file>".to_string(),
+            ));
+        };
+        let summary = import_state(&cfg, Path::new(&file))?;
+        println!("Imported {summary}");
+        return Ok(());
+    }
     if let Some(dir) = &cfg.claude_config_dir {
         std::env::set_var("CLAUDE_CONFIG_DIR", dir);
     }
+    verify_claude_cli(&cfg).await?;
 
-    let model = Arc::new(ClaudeCliClient::new(ClaudeCliConfig {
-        claude_path: cfg.claude_cli_path.clone(),
-        model: None,
-        permission_mode: PermissionMode::BypassPermissions,
-        dangerously_skip_permissions: true,
-        include_partial_messages: true,
-    }));
+    let model = build_model_client(&cfg);
 
-    let session = Arc::new(ClaudeSession::new(cfg.clone(), model));
+    let shutdown = Shutdown::new();
+    tokio::spawn(trigger_on_signal(shutdown.clone()));
 
-    ctb_telegram::router::run_polling(cfg, session)
-        .await
-        .map_err(|e| ctb_core::Error::External(format!("telegram bot failed: {e}")))?;
+    let metrics = ProcessMetrics::new();
+    let grants = Arc::new(DirectoryGrants::from_config(&cfg));
+    let chat_settings = Arc::new(ChatSettings::from_config(&cfg));
+    let store = ctb_core::storage::open(&cfg)?;
+    let mut session = ClaudeSession::new(cfg.clone(), model)
+        .with_store(store)
+        .with_shutdown(shutdown.clone())
+        .with_metrics(metrics.clone())
+        .with_grants(grants.clone())
+        .with_chat_settings(chat_settings.clone())
+        .with_security_monitor(Arc::new(SecurityMonitor::from_config(&cfg)));
+    if cfg.undo_keep_turns > 0 {
+        session = session.with_undo_journal(UndoJournal::from_config(&cfg));
+    }
+    let session = Arc::new(session);
+
+    let webhook =
+        WebhookOptions::from_config(&cfg).map_err(|e| ctb_core::Error::Config(e.to_string()))?;
+    match webhook {
+        Some(options) => {
+            ctb_telegram::router::run_webhook(
+                cfg,
+                session,
+                shutdown,
+                metrics,
+                grants,
+                chat_settings,
+                options,
+            )
+            .await
+        }
+        None => {
+            ctb_telegram::router::run_polling(
+                cfg,
+                session,
+                shutdown,
+                metrics,
+                grants,
+                chat_settings,
+            )
+            .await
+        }
+    }
+    .map_err(|e| ctb_core::Error::External(format!("telegram bot failed: {e}")))?;
 
     Ok(())
 }
+
+fn import_state(cfg: &Config, file: &Path) -> Result<ImportSummary, ctb_core::Error> {
+    let doc = StateExport::parse(&std::fs::read_to_string(file)?)?;
+    let store = ctb_core::storage::open(cfg)?;
+    BotState {
+        sessions: store.as_ref(),
+        chat_settings: &ChatSettings::from_config(cfg),
+        grants: &DirectoryGrants::from_config(cfg),
+        vocabulary: &Vocabulary::from_config(cfg),
+    }
+    .import(doc)
+}
+
+fn claude_cli(
+    cfg: &Config,
+    claude_path: PathBuf,
+    model: Option<String>,
+    processes: Arc<ProcessTracker>,
+) -> Arc<dyn ModelClient> {
+    Arc::new(
+        ClaudeCliClient::new(ClaudeCliConfig {
+            claude_path,
+            model,
+            permission_mode: PermissionMode::BypassPermissions,
+            dangerously_skip_permissions: true,
+            include_partial_messages: true,
+            kill_grace: cfg.cli_kill_grace,
+            allowed_tools: cfg.claude_allowed_tools.clone(),
+            disallowed_tools: cfg.claude_disallowed_tools.clone(),
+            stderr_notices: cfg.claude_stderr_notices.clone(),
+        })
+        .with_process_tracker(processes),
+    )
+}
+
+/// The Claude CLI, behind a failover to `CLAUDE_FALLBACK_*` when configured.
+fn build_model_client(cfg: &Config) -> Arc<dyn ModelClient> {
+    // Shared by both providers: one file, one writer lock.
+    let processes = Arc::new(ProcessTracker::from_config(cfg));
+    let primary = claude_cli(
+        cfg,
+        cfg.claude_cli_path.clone(),
+        cfg.claude_model.clone(),
+        processes.clone(),
+    );
+    if cfg.claude_fallback_cli_path.is_none() && cfg.claude_fallback_model.is_none() {
+        return primary;
+    }
+
+    let path = cfg
+        .claude_fallback_cli_path
+        .clone()
+        .unwrap_or_else(|| cfg.claude_cli_path.clone());
+    let model = cfg
+        .claude_fallback_model
+        .clone()
+        .or_else(|| cfg.claude_model.clone());
+    let name = match &model {
+        Some(m) => format!("Claude CLI ({m})"),
+        None => format!("Claude CLI ({})", path.display()),
+    };
+    log_info!("[MODEL] Fallback provider: {name}");
+    let fallback = claude_cli(cfg, path, model, processes);
+    Arc::new(
+        FallbackModelClient::new("Claude CLI", primary).with_fallback(
+            name,
+            fallback,
+            cfg.claude_fallback_model.clone(),
+        ),
+    )
+}
+
+/// Flip `shutdown` on the first SIGINT/SIGTERM.
+async fn trigger_on_signal(shutdown: Shutdown) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(s) => s,
+            Err(e) => {
+                log_line!("[SHUTDOWN] Failed to install SIGTERM handler: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+                log_info!("[SHUTDOWN] Received SIGINT");
+                shutdown.trigger();
+                return;
+            }
+        };
+        tokio::select! {
+            _ = sigterm.recv() => log_info!("[SHUTDOWN] Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => log_info!("[SHUTDOWN] Received SIGINT"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        log_info!("[SHUTDOWN] Received SIGINT");
+    }
+    shutdown.trigger();
+}